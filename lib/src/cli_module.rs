@@ -0,0 +1,452 @@
+//! Quiche `Cli` module — the argument parser behind `@cli`.
+//!
+//! ```python
+//! @cli
+//! def main(path: Str, verbose: bool = False, count: i32 = 1):
+//!     """Count the lines of a file.
+//!
+//!     Args:
+//!         path: the file to read
+//!         count: how many times to read it
+//!     """
+//! ```
+//!
+//! The compiler turns `main`'s signature into a spec for [`Cli::parse`] and
+//! fetches each argument with [`Cli::get`] and friends. Parameters without
+//! a default are positional arguments (`<path>`), ones with a default are
+//! options (`--count 3` or `--count=3`, underscores spelled as dashes), a
+//! `bool` option is a flag (`--verbose`, or `--no-verbose` when it defaults
+//! to `True`), keyword-only parameters without a default are required
+//! options, and `*args` takes the remaining positional arguments. `-h` or
+//! `--help` prints the generated help; bad arguments print an error and the
+//! usage line and exit with status 2, as Python's argparse does.
+
+use crate::{List, Str};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The arguments [`Cli::parse`] read, for the getters.
+static PARSED: OnceLock<Parsed> = OnceLock::new();
+
+/// How an `@cli` parameter is given on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    /// `<name>`, required, in order
+    Positional,
+    /// The remaining positional arguments
+    Variadic,
+    /// `--name <value>`, required unless it has a default
+    Option,
+    /// `--name` / `--no-name`
+    Flag,
+}
+
+/// One line of the spec: `kind\tname\ttype\tdefault\thelp`, where `kind` is
+/// `arg`, `args`, `opt` or `flag` and `default` is the default's source
+/// text, empty for none. The lines after the first blank one describe the
+/// program.
+#[derive(Clone, Debug)]
+struct Param {
+    kind: Kind,
+    name: String,
+    ty: String,
+    default: Option<String>,
+    help: String,
+}
+
+impl Param {
+    /// `--max-depth` for `max_depth`.
+    fn flag(&self) -> String {
+        format!("--{}", self.name.replace('_', "-"))
+    }
+
+    /// How the usage line and help show the parameter.
+    fn usage(&self) -> String {
+        match self.kind {
+            Kind::Positional => format!("<{}>", self.name),
+            Kind::Variadic => format!("[{}...]", self.name),
+            Kind::Option => format!("{} <{}>", self.flag(), self.ty),
+            Kind::Flag if self.default.as_deref() == Some("True") => {
+                format!("--no-{}", &self.flag()[2..])
+            }
+            Kind::Flag => self.flag(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Parsed {
+    program: String,
+    params: Vec<Param>,
+    /// The values given for each parameter, by name
+    values: Vec<(String, Vec<String>)>,
+}
+
+impl Parsed {
+    fn values(&self, name: &str) -> &[String] {
+        self.values
+            .iter()
+            .find(|(given, _)| given == name)
+            .map_or(&[], |(_, values)| values)
+    }
+
+    /// `value` converted to the type of `name`, or exit with a usage error.
+    fn convert<T: FromStr>(&self, name: &str, value: &str) -> T {
+        value.parse().unwrap_or_else(|_| {
+            let param = self.params.iter().find(|param| param.name == name);
+            let (shown, ty) = match param {
+                Some(param) if param.kind == Kind::Positional || param.kind == Kind::Variadic => {
+                    (format!("<{name}>"), param.ty.as_str())
+                }
+                Some(param) => (param.flag(), param.ty.as_str()),
+                None => (name.to_string(), "value"),
+            };
+            self.fail(&format!("argument {shown}: invalid {ty} value '{value}'"))
+        })
+    }
+
+    fn fail(&self, message: &str) -> ! {
+        eprintln!("{}", usage_line(&self.program, &self.params));
+        eprintln!("{}: error: {message}", self.program);
+        std::process::exit(2);
+    }
+}
+
+/// Static module behind `@cli`, used as `Cli.parse(spec)` in generated code.
+pub struct Cli;
+
+impl Cli {
+    /// Read the process's arguments against `spec` (see [`Param`]). Prints
+    /// the help and exits for `--help`, and exits with status 2 on bad
+    /// arguments.
+    pub fn parse(spec: Str) {
+        let mut args = std::env::args();
+        let program = args
+            .next()
+            .as_deref()
+            .map(std::path::Path::new)
+            .and_then(|path| path.file_stem())
+            .map_or_else(
+                || "main".to_string(),
+                |stem| stem.to_string_lossy().into_owned(),
+            );
+        let args: Vec<String> = args.collect();
+        let (params, about) = parse_spec(&spec);
+        match read_args(&params, &args) {
+            Ok(values) => {
+                let _ = PARSED.set(Parsed {
+                    program,
+                    params,
+                    values,
+                });
+            }
+            Err(None) => {
+                print!("{}", help(&program, &params, &about));
+                std::process::exit(0);
+            }
+            Err(Some(message)) => Parsed {
+                program,
+                params,
+                values: vec![],
+            }
+            .fail(&message),
+        }
+    }
+
+    /// The value of the required argument `name`.
+    pub fn get<T: FromStr>(name: Str) -> T {
+        let parsed = Self::parsed();
+        match parsed.values(&name).last() {
+            Some(value) => parsed.convert(&name, value),
+            None => parsed.fail(&format!("missing argument '{}'", &*name)),
+        }
+    }
+
+    /// The value of `name`, or `default` when it wasn't given.
+    pub fn get_or<T: FromStr>(name: Str, default: T) -> T {
+        Self::get_optional(name).unwrap_or(default)
+    }
+
+    /// The value of `name`, if it was given.
+    pub fn get_optional<T: FromStr>(name: Str) -> Option<T> {
+        let parsed = Self::parsed();
+        let value = parsed.values(&name).last()?;
+        Some(parsed.convert(&name, value))
+    }
+
+    /// Every value given for the variadic `name`.
+    pub fn get_all<T: FromStr>(name: Str) -> List<T> {
+        let parsed = Self::parsed();
+        List(
+            parsed
+                .values(&name)
+                .iter()
+                .map(|value| parsed.convert(&name, value))
+                .collect(),
+        )
+    }
+
+    fn parsed() -> &'static Parsed {
+        PARSED
+            .get()
+            .unwrap_or_else(|| panic!("Cli: arguments read before Cli.parse"))
+    }
+}
+
+/// The parameters of `spec`, and the description after them.
+fn parse_spec(spec: &str) -> (Vec<Param>, String) {
+    let (params, about) = spec.split_once("\n\n").unwrap_or((spec, ""));
+    let params = params
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let kind = match fields.next()? {
+                "arg" => Kind::Positional,
+                "args" => Kind::Variadic,
+                "flag" => Kind::Flag,
+                _ => Kind::Option,
+            };
+            let name = fields.next()?.to_string();
+            let ty = fields.next().unwrap_or("Str").to_string();
+            let default = fields
+                .next()
+                .filter(|default| !default.is_empty())
+                .map(str::to_string);
+            let help = fields.next().unwrap_or("").to_string();
+            Some(Param {
+                kind,
+                name,
+                ty,
+                default,
+                help,
+            })
+        })
+        .collect();
+    (params, about.trim().to_string())
+}
+
+/// The values `args` give each parameter: `Err(None)` for `--help`, and
+/// `Err(Some(message))` for arguments that don't fit `params`.
+fn read_args(
+    params: &[Param],
+    args: &[String],
+) -> Result<Vec<(String, Vec<String>)>, Option<String>> {
+    let mut values: Vec<(String, Vec<String>)> = Vec::new();
+    let mut give = |name: &str, value: String| match values.iter_mut().find(|(n, _)| n == name) {
+        Some((_, given)) => given.push(value),
+        None => values.push((name.to_string(), vec![value])),
+    };
+    let mut positionals = params.iter().filter(|param| param.kind == Kind::Positional);
+    let variadic = params.iter().find(|param| param.kind == Kind::Variadic);
+    let mut args = args.iter();
+    let mut options_done = false;
+    while let Some(arg) = args.next() {
+        if !options_done && (arg == "-h" || arg == "--help") {
+            return Err(None);
+        }
+        if !options_done && arg == "--" {
+            options_done = true;
+            continue;
+        }
+        if options_done || !arg.starts_with('-') || arg == "-" || arg.parse::<f64>().is_ok() {
+            match positionals.next().or(variadic) {
+                Some(param) => give(&param.name, arg.clone()),
+                None => return Err(Some(format!("unexpected argument '{arg}'"))),
+            }
+            continue;
+        }
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let named = |param: &&Param| matches!(param.kind, Kind::Option | Kind::Flag);
+        if let Some(param) = params
+            .iter()
+            .filter(named)
+            .find(|param| param.flag() == flag)
+        {
+            let value = match (param.kind, inline) {
+                (Kind::Flag, None) => "true".to_string(),
+                (Kind::Flag, Some(_)) => {
+                    return Err(Some(format!("argument {flag} takes no value")));
+                }
+                (_, Some(value)) => value,
+                (_, None) => args
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| Some(format!("argument {flag} expects a {} value", param.ty)))?,
+            };
+            give(&param.name, value);
+        } else {
+            // No let chains: scripts compile quiche-lib as edition 2021
+            let negated = params
+                .iter()
+                .filter(|param| param.kind == Kind::Flag && inline.is_none())
+                .find(|param| format!("--no-{}", &param.flag()[2..]) == flag);
+            let Some(param) = negated else {
+                return Err(Some(format!("unrecognized argument '{flag}'")));
+            };
+            give(&param.name, "false".to_string());
+        }
+    }
+    for param in params {
+        let given = values.iter().any(|(name, _)| *name == param.name);
+        if !given
+            && param.default.is_none()
+            && matches!(param.kind, Kind::Positional | Kind::Option)
+        {
+            return Err(Some(format!("missing argument {}", param.usage())));
+        }
+    }
+    Ok(values)
+}
+
+fn usage_line(program: &str, params: &[Param]) -> String {
+    let mut usage = format!("usage: {program} [-h]");
+    let required = |param: &Param| param.default.is_none() && param.kind != Kind::Flag;
+    for param in params
+        .iter()
+        .filter(|param| matches!(param.kind, Kind::Option | Kind::Flag))
+    {
+        if required(param) {
+            usage.push_str(&format!(" {}", param.usage()));
+        } else {
+            usage.push_str(&format!(" [{}]", param.usage()));
+        }
+    }
+    for param in params
+        .iter()
+        .filter(|param| matches!(param.kind, Kind::Positional | Kind::Variadic))
+    {
+        usage.push_str(&format!(" {}", param.usage()));
+    }
+    usage
+}
+
+/// The `--help` text: usage, description, then each parameter with its
+/// help and default.
+fn help(program: &str, params: &[Param], about: &str) -> String {
+    let mut text = usage_line(program, params);
+    text.push('\n');
+    if !about.is_empty() {
+        text.push_str(&format!("\n{about}\n"));
+    }
+    let help_param = Param {
+        kind: Kind::Flag,
+        name: "help".to_string(),
+        ty: "bool".to_string(),
+        default: None,
+        help: "show this help and exit".to_string(),
+    };
+    let positional: Vec<&Param> = params
+        .iter()
+        .filter(|param| matches!(param.kind, Kind::Positional | Kind::Variadic))
+        .collect();
+    let named: Vec<&Param> = params
+        .iter()
+        .filter(|param| matches!(param.kind, Kind::Option | Kind::Flag))
+        .chain([&help_param])
+        .collect();
+    let width = positional
+        .iter()
+        .chain(&named)
+        .map(|param| param.usage().len())
+        .max()
+        .unwrap_or(0)
+        .max("-h, --help".len());
+    for (title, group) in [("arguments", positional), ("options", named)] {
+        if group.is_empty() {
+            continue;
+        }
+        text.push_str(&format!("\n{title}:\n"));
+        for param in group {
+            let shown = if std::ptr::eq(param, &help_param) {
+                "-h, --help".to_string()
+            } else {
+                param.usage()
+            };
+            let mut line = format!("  {shown:<width$}  {}", param.help);
+            match &param.default {
+                Some(default) if param.kind == Kind::Option => {
+                    line.push_str(&format!(" (default: {default})"));
+                }
+                _ => {}
+            }
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = "arg\tpath\tStr\t\tthe file to read\nflag\tverbose\tbool\tFalse\t\nopt\tmax_depth\ti32\t1\thow deep to go\nargs\trest\tStr\t\t\n\nWalk a tree.";
+
+    fn read(args: &[&str]) -> Result<Vec<(String, Vec<String>)>, Option<String>> {
+        let (params, _) = parse_spec(SPEC);
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        read_args(&params, &args)
+    }
+
+    #[test]
+    fn arguments_fill_parameters_by_kind() {
+        let values = read(&["src", "--max-depth", "3", "--verbose", "a", "--", "--b"]).unwrap();
+        assert_eq!(
+            values,
+            vec![
+                ("path".to_string(), vec!["src".to_string()]),
+                ("max_depth".to_string(), vec!["3".to_string()]),
+                ("verbose".to_string(), vec!["true".to_string()]),
+                ("rest".to_string(), vec!["a".to_string(), "--b".to_string()]),
+            ]
+        );
+        let values = read(&["--max-depth=-2", "-5", "--no-verbose"]).unwrap();
+        assert_eq!(values[0], ("max_depth".to_string(), vec!["-2".to_string()]));
+        assert_eq!(values[1], ("path".to_string(), vec!["-5".to_string()]));
+        assert_eq!(
+            values[2],
+            ("verbose".to_string(), vec!["false".to_string()])
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        assert_eq!(read(&[]), Err(Some("missing argument <path>".to_string())));
+        assert_eq!(
+            read(&["src", "--depth", "2"]),
+            Err(Some("unrecognized argument '--depth'".to_string()))
+        );
+        assert_eq!(
+            read(&["src", "--max-depth"]),
+            Err(Some("argument --max-depth expects a i32 value".to_string()))
+        );
+        assert_eq!(
+            read(&["src", "--verbose=yes"]),
+            Err(Some("argument --verbose takes no value".to_string()))
+        );
+        assert_eq!(read(&["src", "--help"]), Err(None));
+    }
+
+    #[test]
+    fn help_lists_parameters_with_defaults() {
+        let (params, about) = parse_spec(SPEC);
+        assert_eq!(
+            help("walk", &params, &about),
+            "usage: walk [-h] [--verbose] [--max-depth <i32>] <path> [rest...]\n\
+             \n\
+             Walk a tree.\n\
+             \n\
+             arguments:\n\
+             \x20 <path>             the file to read\n\
+             \x20 [rest...]\n\
+             \n\
+             options:\n\
+             \x20 --verbose\n\
+             \x20 --max-depth <i32>  how deep to go (default: 1)\n\
+             \x20 -h, --help         show this help and exit\n"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Quiche `CLib` module — ctypes-style loading of C shared libraries.
+//!
+//! Opens a shared library at runtime and calls its symbols by name, for C
+//! code that isn't known at compile time. Libraries known up front are
+//! better declared with `@extern("C")`, which type-checks each call.
+
+use crate::{List, Str};
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+
+/// Raw C pointer, as passed to and returned from C functions.
+pub type Ptr = *mut c_void;
+
+#[cfg(unix)]
+#[cfg_attr(target_os = "linux", link(name = "dl"))]
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *mut c_char;
+}
+
+#[cfg(unix)]
+const RTLD_NOW: c_int = 2;
+
+/// A loaded C shared library, used as `CLib.load("libm.so.6")` in Quiche.
+///
+/// The library stays loaded until the value is dropped.
+pub struct CLib {
+    handle: Ptr,
+    path: Str,
+}
+
+impl CLib {
+    /// Load a shared library by file name or path.
+    ///
+    /// Panics if the library cannot be loaded.
+    pub fn load(path: Str) -> CLib {
+        Self::try_load(path).unwrap_or_else(|e| panic!("CLib.load failed for {e}"))
+    }
+
+    /// [`CLib::load`], failing with `'path': reason` instead of panicking.
+    #[cfg(unix)]
+    pub(crate) fn try_load(path: Str) -> Result<CLib, String> {
+        let c_path = Self::c_string(&path);
+        let handle = unsafe { dlopen(c_path.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            return Err(format!("'{}': {}", &*path, last_dl_error()));
+        }
+        Ok(CLib { handle, path })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn try_load(path: Str) -> Result<CLib, String> {
+        Err(format!(
+            "'{}': dynamic loading is only supported on Unix",
+            &*path
+        ))
+    }
+
+    /// Whether the library exports `name`.
+    pub fn has(&self, name: Str) -> bool {
+        !self.lookup(&name).is_null()
+    }
+
+    /// Address of the symbol `name`. Panics if the library doesn't export it.
+    pub fn symbol(&self, name: Str) -> Ptr {
+        let ptr = self.lookup(&name);
+        if ptr.is_null() {
+            panic!(
+                "CLib.symbol failed: '{}' not found in '{}'",
+                &*name, &*self.path
+            );
+        }
+        ptr
+    }
+
+    /// Call `name` as a C function taking and returning integers
+    /// (`long` / pointer-sized arguments), with up to six arguments.
+    pub fn call_i64(&self, name: Str, args: List<i64>) -> i64 {
+        let f = self.symbol(name.clone());
+        unsafe {
+            match args.as_slice() {
+                [] => std::mem::transmute::<Ptr, extern "C" fn() -> i64>(f)(),
+                [a] => std::mem::transmute::<Ptr, extern "C" fn(i64) -> i64>(f)(*a),
+                [a, b] => std::mem::transmute::<Ptr, extern "C" fn(i64, i64) -> i64>(f)(*a, *b),
+                [a, b, c] => {
+                    std::mem::transmute::<Ptr, extern "C" fn(i64, i64, i64) -> i64>(f)(*a, *b, *c)
+                }
+                [a, b, c, d] => {
+                    std::mem::transmute::<Ptr, extern "C" fn(i64, i64, i64, i64) -> i64>(f)(
+                        *a, *b, *c, *d,
+                    )
+                }
+                [a, b, c, d, e] => std::mem::transmute::<
+                    Ptr,
+                    extern "C" fn(i64, i64, i64, i64, i64) -> i64,
+                >(f)(*a, *b, *c, *d, *e),
+                [a, b, c, d, e, g] => std::mem::transmute::<
+                    Ptr,
+                    extern "C" fn(i64, i64, i64, i64, i64, i64) -> i64,
+                >(f)(*a, *b, *c, *d, *e, *g),
+                _ => panic!(
+                    "CLib.call_i64 failed for '{}': at most 6 arguments are supported, got {}",
+                    &*name,
+                    args.len()
+                ),
+            }
+        }
+    }
+
+    /// Call `name` as a C function taking and returning `double`s, with up
+    /// to four arguments.
+    pub fn call_f64(&self, name: Str, args: List<f64>) -> f64 {
+        let f = self.symbol(name.clone());
+        unsafe {
+            match args.as_slice() {
+                [] => std::mem::transmute::<Ptr, extern "C" fn() -> f64>(f)(),
+                [a] => std::mem::transmute::<Ptr, extern "C" fn(f64) -> f64>(f)(*a),
+                [a, b] => std::mem::transmute::<Ptr, extern "C" fn(f64, f64) -> f64>(f)(*a, *b),
+                [a, b, c] => {
+                    std::mem::transmute::<Ptr, extern "C" fn(f64, f64, f64) -> f64>(f)(*a, *b, *c)
+                }
+                [a, b, c, d] => {
+                    std::mem::transmute::<Ptr, extern "C" fn(f64, f64, f64, f64) -> f64>(f)(
+                        *a, *b, *c, *d,
+                    )
+                }
+                _ => panic!(
+                    "CLib.call_f64 failed for '{}': at most 4 arguments are supported, got {}",
+                    &*name,
+                    args.len()
+                ),
+            }
+        }
+    }
+
+    /// Call `name` as a C function taking one string and returning an
+    /// integer, e.g. `strlen` or `atoi`.
+    pub fn call_str_i64(&self, name: Str, arg: Str) -> i64 {
+        let f = self.symbol(name);
+        let arg = Self::c_string(&arg);
+        unsafe { std::mem::transmute::<Ptr, extern "C" fn(*const c_char) -> i64>(f)(arg.as_ptr()) }
+    }
+
+    /// Copy a NUL-terminated C string into a `Str`. A null pointer reads as
+    /// the empty string; invalid UTF-8 is replaced.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to a NUL-terminated string that stays
+    /// valid for the duration of the call.
+    pub unsafe fn from_c_str(ptr: *const c_char) -> Str {
+        if ptr.is_null() {
+            return Str::from("");
+        }
+        let s = unsafe { CStr::from_ptr(ptr) };
+        Str::from(s.to_string_lossy().into_owned())
+    }
+
+    /// Convert a `Str` to an owned C string for passing to C.
+    ///
+    /// Panics if the string contains an interior NUL byte.
+    pub fn c_string(s: &Str) -> CString {
+        CString::new(s.as_bytes())
+            .unwrap_or_else(|_| panic!("cannot pass '{}' to C: contains a NUL byte", &**s))
+    }
+
+    /// Hand a `Str` to C as a newly allocated C string. The caller owns it
+    /// and must release it with [`CLib::free_c_str`].
+    pub fn into_c_str(s: Str) -> *mut c_char {
+        Self::c_string(&s).into_raw()
+    }
+
+    /// Free a string made by [`CLib::into_c_str`]. Null is ignored.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or come from `into_c_str`, and not be used again.
+    pub unsafe fn free_c_str(ptr: *mut c_char) {
+        if !ptr.is_null() {
+            drop(unsafe { CString::from_raw(ptr) });
+        }
+    }
+
+    /// The null pointer, for optional pointer arguments.
+    pub fn null() -> Ptr {
+        std::ptr::null_mut()
+    }
+
+    #[cfg(unix)]
+    fn lookup(&self, name: &Str) -> Ptr {
+        let c_name = Self::c_string(name);
+        unsafe { dlsym(self.handle, c_name.as_ptr()) }
+    }
+
+    #[cfg(not(unix))]
+    fn lookup(&self, _name: &Str) -> Ptr {
+        std::ptr::null_mut()
+    }
+}
+
+impl Drop for CLib {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if !self.handle.is_null() {
+            unsafe {
+                dlclose(self.handle);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn last_dl_error() -> String {
+    let err = unsafe { dlerror() };
+    if err.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(err) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn s(v: &str) -> Str {
+        Str::from(v)
+    }
+
+    #[test]
+    fn test_clib_calls_libm_and_libc() {
+        let libm = CLib::load(s("libm.so.6"));
+        assert!(libm.has(s("cos")));
+        assert!(!libm.has(s("no_such_symbol")));
+        assert_eq!(libm.call_f64(s("cos"), List::from(vec![0.0])), 1.0);
+        assert_eq!(libm.call_f64(s("pow"), List::from(vec![2.0, 10.0])), 1024.0);
+
+        let libc = CLib::load(s("libc.so.6"));
+        assert_eq!(libc.call_i64(s("labs"), List::from(vec![-42])), 42);
+        assert_eq!(libc.call_str_i64(s("strlen"), s("quiche")), 6);
+    }
+
+    #[test]
+    fn test_clib_string_marshalling() {
+        let c = CLib::c_string(&s("héllo"));
+        assert_eq!(&*unsafe { CLib::from_c_str(c.as_ptr()) }, "héllo");
+        assert_eq!(&*unsafe { CLib::from_c_str(std::ptr::null()) }, "");
+        let owned = CLib::into_c_str(s("out"));
+        assert_eq!(&*unsafe { CLib::from_c_str(owned) }, "out");
+        unsafe { CLib::free_c_str(owned) };
+    }
+
+    #[test]
+    #[should_panic(expected = "CLib.load failed")]
+    fn test_clib_load_missing_library_panics() {
+        CLib::load(s("libdoes-not-exist.so"));
+    }
+}
@@ -0,0 +1,96 @@
+//! `ContextManager` — what a `with` block enters and exits.
+//!
+//! `with open(path) as f:` compiles to a plain Rust block holding
+//! `let f = With::enter(open(path));`. The [`With`] guard calls the manager's
+//! [`ContextManager::enter`] at once and its [`ContextManager::exit`] when
+//! dropped, so the resource is released where the block ends — also when it
+//! is left by `return`, `break`, a raise or a panic. Inside the block the
+//! guard derefs to the manager, so `f.read()` calls the file's own method.
+
+use std::ops::{Deref, DerefMut};
+
+/// A resource a `with` block sets up and releases. Both steps default to
+/// nothing, so a type only writes the one it needs.
+pub trait ContextManager {
+    /// Called when the `with` block is entered.
+    fn enter(&mut self) {}
+
+    /// Called when the `with` block is left, however it is left.
+    fn exit(&mut self) {}
+}
+
+/// A manager entered by a `with` block, exited when dropped.
+pub struct With<T: ContextManager> {
+    manager: T,
+}
+
+impl<T: ContextManager> With<T> {
+    /// Enter `manager`.
+    pub fn enter(mut manager: T) -> With<T> {
+        manager.enter();
+        With { manager }
+    }
+}
+
+impl<T: ContextManager> Drop for With<T> {
+    fn drop(&mut self) {
+        self.manager.exit();
+    }
+}
+
+impl<T: ContextManager> Deref for With<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.manager
+    }
+}
+
+impl<T: ContextManager> DerefMut for With<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.manager
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Logged(&'static str, Rc<RefCell<Vec<String>>>);
+
+    impl ContextManager for Logged {
+        fn enter(&mut self) {
+            self.1.borrow_mut().push(format!("enter {}", self.0));
+        }
+
+        fn exit(&mut self) {
+            self.1.borrow_mut().push(format!("exit {}", self.0));
+        }
+    }
+
+    fn run(log: &Rc<RefCell<Vec<String>>>, leave_early: bool) -> i64 {
+        let _a = With::enter(Logged("a", log.clone()));
+        let b = With::enter(Logged("b", log.clone()));
+        if leave_early {
+            return 0;
+        }
+        b.1.borrow_mut().push("body".to_string());
+        1
+    }
+
+    #[test]
+    fn managers_exit_in_reverse_order_however_the_block_ends() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        assert_eq!(run(&log, false), 1);
+        assert_eq!(
+            *log.borrow(),
+            ["enter a", "enter b", "body", "exit b", "exit a"]
+        );
+
+        log.borrow_mut().clear();
+        assert_eq!(run(&log, true), 0);
+        assert_eq!(*log.borrow(), ["enter a", "enter b", "exit b", "exit a"]);
+    }
+}
@@ -1,20 +1,23 @@
+use crate::index_map::{self, IndexMap};
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter, Result};
 use std::hash::Hash;
 use std::ops::{Deref, DerefMut};
 /// Quiche's dictionary type — a key-value store.
 ///
-/// Wraps `HashMap<K, V>` with chainable builder methods.
-/// Derefs to `HashMap<K, V>` so all standard map methods are available.
+/// Wraps an insertion-ordered [`IndexMap<K, V>`] with chainable builder
+/// methods, so iterating and printing a `Dict` follow the order its keys
+/// were first inserted, as in Python. Derefs to the map so all standard map
+/// methods (`get`, `insert`, `entry`, `keys`, ...) are available.
 #[derive(Clone, Debug)]
-pub struct Dict<K, V>(pub HashMap<K, V>);
+pub struct Dict<K, V>(pub IndexMap<K, V>);
 
 impl<K, V> Deref for Dict<K, V>
 where
     K: Eq + Hash,
     V: PartialEq,
 {
-    type Target = HashMap<K, V>;
+    type Target = IndexMap<K, V>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -25,7 +28,7 @@ where
     K: Eq + Hash,
     V: PartialEq,
 {
-    fn deref_mut(&mut self) -> &mut HashMap<K, V> {
+    fn deref_mut(&mut self) -> &mut IndexMap<K, V> {
         &mut self.0
     }
 }
@@ -50,13 +53,52 @@ where
     }
 }
 
+impl<K: Eq + Hash, V: Eq> Eq for Dict<K, V> {}
+
+impl<K, V> PartialEq<HashMap<K, V>> for Dict<K, V>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+{
+    fn eq(&self, other: &HashMap<K, V>) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<K, V> PartialEq<Dict<K, V>> for HashMap<K, V>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Dict<K, V>) -> bool {
+        other == self
+    }
+}
+
+impl<K, V> From<IndexMap<K, V>> for Dict<K, V> {
+    fn from(m: IndexMap<K, V>) -> Self {
+        Dict(m)
+    }
+}
+
+/// Entries keep the `HashMap`'s own iteration order.
 impl<K, V> From<HashMap<K, V>> for Dict<K, V>
 where
     K: Eq + Hash,
     V: PartialEq,
 {
     fn from(m: HashMap<K, V>) -> Self {
-        Dict(m)
+        m.into_iter().collect()
+    }
+}
+
+/// A dict literal: `Dict::from([("a", 1), ("b", 2)])`.
+impl<K, V, const N: usize> From<[(K, V); N]> for Dict<K, V>
+where
+    K: Eq + Hash,
+{
+    fn from(entries: [(K, V); N]) -> Self {
+        Dict(IndexMap::from(entries))
     }
 }
 
@@ -65,12 +107,20 @@ where
     K: Eq + Hash,
 {
     type Item = (K, V);
-    type IntoIter = std::collections::hash_map::IntoIter<K, V>;
+    type IntoIter = index_map::IntoIter<K, V>;
     fn into_iter(self) -> Self::IntoIter {
         self.0.into_iter()
     }
 }
 
+impl<'a, K, V> IntoIterator for &'a Dict<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = index_map::Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
 impl<K, V> FromIterator<(K, V)> for Dict<K, V>
 where
     K: Eq + Hash,
@@ -86,7 +136,7 @@ where
     V: PartialEq,
 {
     pub fn new() -> Self {
-        Dict(HashMap::new())
+        Dict(IndexMap::new())
     }
 
     pub fn set(mut self, key: K, value: V) -> Self {
@@ -128,8 +178,8 @@ where
 
 use crate::QuicheType;
 impl<K: Eq + Hash, V: PartialEq> QuicheType for Dict<K, V> {
-    type Inner = HashMap<K, V>;
-    fn inner(self) -> HashMap<K, V> {
+    type Inner = IndexMap<K, V>;
+    fn inner(self) -> IndexMap<K, V> {
         self.0
     }
 }
@@ -137,6 +187,7 @@ impl<K: Eq + Hash, V: PartialEq> QuicheType for Dict<K, V> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::List;
 
     #[test]
     fn dict_new_empty() {
@@ -175,6 +226,34 @@ mod tests {
         assert_eq!(d.get_value(&"x"), Some(&10));
     }
 
+    #[test]
+    fn dict_keeps_insertion_order() {
+        let d = Dict::from([("zebra", 1), ("apple", 2), ("mango", 3)]).set("apple", 4);
+        assert_eq!(
+            d.keys().copied().collect::<Vec<_>>(),
+            ["zebra", "apple", "mango"]
+        );
+        assert_eq!(d.to_string(), "{\"zebra\": 1, \"apple\": 4, \"mango\": 3}");
+        let d = d.remove_key(&"zebra").set("zebra", 5);
+        assert_eq!(
+            d.into_iter().collect::<Vec<_>>(),
+            [("apple", 4), ("mango", 3), ("zebra", 5)]
+        );
+    }
+
+    #[test]
+    fn dict_equality_ignores_order() {
+        let d = Dict::from([("a", List(vec![1])), ("b", List(vec![2, 3]))]);
+        assert_eq!(
+            d,
+            Dict::from([("b", List(vec![2, 3])), ("a", List(vec![1]))])
+        );
+        assert_ne!(d, Dict::from([("a", List(vec![1]))]));
+        let m: HashMap<_, _> = d.clone().into_iter().collect();
+        assert_eq!(d, m);
+        assert_eq!(m, d);
+    }
+
     #[test]
     fn dict_deref_methods() {
         let d = Dict::new().set(1, "one").set(2, "two");
@@ -0,0 +1,223 @@
+//! Encoding of Quiche values for the embedding engine.
+//!
+//! A program compiled by `quiche::Engine` prints its result with
+//! [`EngineValue::encode`], as Quiche literal syntax the engine parses back
+//! into a `quiche::Value`.
+
+use crate::{Dict, IndexMap, List, Str};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// A value that can be handed back to a Rust host through `quiche::Engine`.
+pub trait EngineValue {
+    /// Append this value to `out` as a Quiche literal.
+    fn encode_into(&self, out: &mut String);
+
+    /// This value as a Quiche literal: `42`, `1.5`, `"hi"`, `[1, 2]`,
+    /// `{"a": 1}`, `True`, `None`.
+    fn encode(&self) -> String {
+        let mut out = String::new();
+        self.encode_into(&mut out);
+        out
+    }
+}
+
+macro_rules! encode_int {
+    ($($ty:ty),*) => {$(
+        impl EngineValue for $ty {
+            fn encode_into(&self, out: &mut String) {
+                let _ = write!(out, "{self}");
+            }
+        }
+    )*};
+}
+
+encode_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! encode_float {
+    ($($ty:ty),*) => {$(
+        impl EngineValue for $ty {
+            fn encode_into(&self, out: &mut String) {
+                if self.is_nan() {
+                    out.push_str("nan");
+                } else if self.is_infinite() {
+                    out.push_str(if *self > 0.0 { "inf" } else { "-inf" });
+                } else {
+                    // `{:?}` always keeps a `.0` or exponent, so floats stay floats.
+                    let _ = write!(out, "{self:?}");
+                }
+            }
+        }
+    )*};
+}
+
+encode_float!(f32, f64);
+
+impl EngineValue for bool {
+    fn encode_into(&self, out: &mut String) {
+        out.push_str(if *self { "True" } else { "False" });
+    }
+}
+
+impl EngineValue for () {
+    fn encode_into(&self, out: &mut String) {
+        out.push_str("None");
+    }
+}
+
+impl EngineValue for str {
+    fn encode_into(&self, out: &mut String) {
+        out.push('"');
+        for ch in self.chars() {
+            match ch {
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                '\r' => out.push_str("\\r"),
+                '\0' => out.push_str("\\0"),
+                '\\' => out.push_str("\\\\"),
+                '"' => out.push_str("\\\""),
+                ch => out.push(ch),
+            }
+        }
+        out.push('"');
+    }
+}
+
+impl EngineValue for String {
+    fn encode_into(&self, out: &mut String) {
+        self.as_str().encode_into(out);
+    }
+}
+
+impl EngineValue for Str {
+    fn encode_into(&self, out: &mut String) {
+        (**self).encode_into(out);
+    }
+}
+
+impl<T: EngineValue + ?Sized> EngineValue for &T {
+    fn encode_into(&self, out: &mut String) {
+        (**self).encode_into(out);
+    }
+}
+
+impl<T: EngineValue> EngineValue for Option<T> {
+    fn encode_into(&self, out: &mut String) {
+        match self {
+            Some(value) => value.encode_into(out),
+            None => out.push_str("None"),
+        }
+    }
+}
+
+impl<T: EngineValue> EngineValue for [T] {
+    fn encode_into(&self, out: &mut String) {
+        out.push('[');
+        for (i, item) in self.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            item.encode_into(out);
+        }
+        out.push(']');
+    }
+}
+
+impl<T: EngineValue> EngineValue for Vec<T> {
+    fn encode_into(&self, out: &mut String) {
+        self.as_slice().encode_into(out);
+    }
+}
+
+impl<T: EngineValue> EngineValue for List<T> {
+    fn encode_into(&self, out: &mut String) {
+        self.as_slice().encode_into(out);
+    }
+}
+
+impl<K: EngineValue, V: EngineValue, S> EngineValue for HashMap<K, V, S> {
+    fn encode_into(&self, out: &mut String) {
+        // Sorted by encoded key, so the output doesn't depend on hash order.
+        let mut entries: Vec<(String, String)> =
+            self.iter().map(|(k, v)| (k.encode(), v.encode())).collect();
+        entries.sort();
+        out.push('{');
+        for (i, (key, value)) in entries.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            let _ = write!(out, "{key}: {value}");
+        }
+        out.push('}');
+    }
+}
+
+impl<K: EngineValue, V: EngineValue> EngineValue for IndexMap<K, V> {
+    fn encode_into(&self, out: &mut String) {
+        out.push('{');
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            key.encode_into(out);
+            out.push_str(": ");
+            value.encode_into(out);
+        }
+        out.push('}');
+    }
+}
+
+impl<K: EngineValue, V: EngineValue> EngineValue for Dict<K, V> {
+    fn encode_into(&self, out: &mut String) {
+        self.0.encode_into(out);
+    }
+}
+
+macro_rules! encode_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: EngineValue),+> EngineValue for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_into(&self, out: &mut String) {
+                let ($($name,)+) = self;
+                let items: Vec<String> = vec![$($name.encode()),+];
+                out.push('[');
+                out.push_str(&items.join(", "));
+                out.push(']');
+            }
+        }
+    };
+}
+
+encode_tuple!(A);
+encode_tuple!(A, B);
+encode_tuple!(A, B, C);
+encode_tuple!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_scalars() {
+        assert_eq!(42i64.encode(), "42");
+        assert_eq!((-3i32).encode(), "-3");
+        assert_eq!(2.0f64.encode(), "2.0");
+        assert_eq!(f64::NEG_INFINITY.encode(), "-inf");
+        assert_eq!(true.encode(), "True");
+        assert_eq!(().encode(), "None");
+        assert_eq!(Str::from("a\"b\n").encode(), "\"a\\\"b\\n\"");
+    }
+
+    #[test]
+    fn test_encode_collections() {
+        let list = List::from(vec![Some(1), None]);
+        assert_eq!(list.encode(), "[1, None]");
+        let mut map = HashMap::new();
+        map.insert("b".to_string(), vec![2.5]);
+        map.insert("a".to_string(), vec![]);
+        assert_eq!(map.encode(), "{\"a\": [], \"b\": [2.5]}");
+        let dict = Dict::from([("b".to_string(), vec![2.5]), ("a".to_string(), vec![])]);
+        assert_eq!(dict.encode(), "{\"b\": [2.5], \"a\": []}");
+        assert_eq!((1, "x").encode(), "[1, \"x\"]");
+    }
+}
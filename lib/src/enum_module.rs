@@ -78,7 +78,7 @@ impl Enum {
     /// Join a list of strings with a separator.
     pub fn join(list: List<crate::Str>, sep: crate::Str) -> crate::Str {
         let parts: Vec<&str> = list.iter().map(|s| &**s).collect();
-        crate::Str(std::sync::Arc::from(parts.join(&*sep).as_str()))
+        crate::Str::from(parts.join(&*sep).as_str())
     }
 }
 
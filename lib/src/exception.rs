@@ -0,0 +1,229 @@
+//! Exceptions: what `raise` throws and `try` / `except` catches.
+//!
+//! Quiche lowers exceptions to `Result`s rather than unwinding. `raise v`
+//! wraps `v` — a built-in error such as [`ValueError`] or any user type —
+//! in an [`Exception`] and returns it as the `Err` of the enclosing function
+//! (which returns `Result[T, Exception]`) or `try` body; elsewhere it ends the
+//! program through [`Exception::raise`]. A `try` body runs inside
+//! [`Exception::attempt`], and each `except T` clause tests the caught
+//! exception with [`Exception::is`] and binds it with [`Exception::get`]:
+//!
+//! ```text
+//! try:                               __try_1 = Exception::attempt(|| { ...; Ok(()) });
+//!     n = parse(text)?               if __try_1.is_err() {
+//! except ValueError as e:     →          let __error_1 = __try_1.unwrap_err();
+//!     print(e)                           if __error_1.is::<ValueError>() {
+//!                                            let e = __error_1.get::<ValueError>()...;
+//! ```
+//!
+//! `?` converts any `std::error::Error` into an `Exception`, so Rust errors
+//! such as `ParseIntError` are caught by their own type too.
+
+use std::any::{Any, type_name};
+use std::fmt::{self, Debug, Display};
+
+use crate::Str;
+
+/// A raised value, with the message it is shown with.
+pub struct Exception {
+    value: Box<dyn Any + Send>,
+    name: &'static str,
+    message: String,
+}
+
+impl Exception {
+    /// Wrap `value`, shown as `message` (see [`Raised`]).
+    pub fn new<E: Any + Send>(value: E, message: impl Into<String>) -> Exception {
+        let name = type_name::<E>();
+        let name = name.split('<').next().unwrap_or(name);
+        Exception {
+            value: Box::new(value),
+            name: name.rsplit("::").next().unwrap_or(name),
+            message: message.into(),
+        }
+    }
+
+    /// Run a `try` body, returning the exception it raised, if any.
+    pub fn attempt(body: impl FnOnce() -> Result<(), Exception>) -> Result<(), Exception> {
+        body()
+    }
+
+    /// Whether the raised value is an `E`; every exception is an
+    /// `Exception`.
+    pub fn is<E: Any>(&self) -> bool {
+        self.get::<E>().is_some()
+    }
+
+    /// The raised value as an `E`, or the exception itself for `Exception`.
+    pub fn get<E: Any>(&self) -> Option<&E> {
+        (self as &dyn Any)
+            .downcast_ref::<E>()
+            .or_else(|| self.value.downcast_ref::<E>())
+    }
+
+    /// The raised value's type name, e.g. `ValueError`.
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// End the program with this exception, for a `raise` no `try` or
+    /// `Result`-returning function can take.
+    pub fn raise(self) -> ! {
+        panic!("{self}")
+    }
+
+    /// End the program with `outcome`'s exception, if it has one.
+    pub fn check(outcome: Result<(), Exception>) {
+        if let Err(exception) = outcome {
+            exception.raise();
+        }
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // A `Debug` message (`NotFound { path: "x" }`) already names its type
+        if self.message.starts_with(self.name) {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.name, self.message)
+        }
+    }
+}
+
+impl Debug for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// `?` on a Rust error inside a `try` body or a function returning
+/// `Result[T, Exception]`.
+impl<E: std::error::Error + Send + 'static> From<E> for Exception {
+    fn from(error: E) -> Exception {
+        let message = error.to_string();
+        Exception::new(error, message)
+    }
+}
+
+/// A value being raised, for picking its message: the prelude's
+/// `quiche_raise!` calls `(&&Raised(&value)).exception_message()`, which
+/// finds [`RaiseDisplay`] before [`RaiseDebug`] (as [`crate::Show`] does for
+/// f-strings), so a type without `Display` is shown with `Debug`.
+pub struct Raised<'a, T: ?Sized>(pub &'a T);
+
+/// Preferred: the message is the value's `Display` output.
+pub trait RaiseDisplay {
+    fn exception_message(&self) -> String;
+}
+
+impl<T: Display + ?Sized> RaiseDisplay for &Raised<'_, T> {
+    fn exception_message(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Fallback: the message is the value's `Debug` output.
+pub trait RaiseDebug {
+    fn exception_message(&self) -> String;
+}
+
+impl<T: Debug + ?Sized> RaiseDebug for &&Raised<'_, T> {
+    fn exception_message(&self) -> String {
+        crate::repr(self.0).to_string()
+    }
+}
+
+macro_rules! builtin_exceptions {
+    ($($(#[$doc:meta])* $name:ident),* $(,)?) => {$(
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct $name(pub Str);
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    )*};
+}
+
+builtin_exceptions! {
+    /// An argument of the right type but an unusable value.
+    ValueError,
+    /// A value of the wrong type.
+    TypeError,
+    /// A missing dictionary key.
+    KeyError,
+    /// A sequence index out of range.
+    IndexError,
+    /// Division or modulo by zero.
+    ZeroDivisionError,
+    /// An error that fits no other class.
+    RuntimeError,
+    /// A method a type has yet to implement.
+    NotImplementedError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct NotFound {
+        path: Str,
+    }
+
+    fn parse(text: &str) -> Result<i64, Exception> {
+        if text.is_empty() {
+            let error = ValueError(Str::from("empty input"));
+            let message = (&&Raised(&error)).exception_message();
+            return Err(Exception::new(error, message));
+        }
+        Ok(text.parse::<i64>()?)
+    }
+
+    #[test]
+    fn exceptions_carry_their_type_and_message() {
+        let empty = parse("").unwrap_err();
+        assert!(empty.is::<ValueError>());
+        assert!(empty.is::<Exception>());
+        assert!(!empty.is::<KeyError>());
+        assert_eq!(empty.get::<ValueError>().unwrap().0, "empty input");
+        assert_eq!(empty.to_string(), "ValueError: empty input");
+
+        let bad = parse("x").unwrap_err();
+        assert!(bad.is::<std::num::ParseIntError>());
+        assert_eq!(
+            bad.to_string(),
+            "ParseIntError: invalid digit found in string"
+        );
+
+        let missing = NotFound {
+            path: Str::from("a.txt"),
+        };
+        let message = (&&Raised(&missing)).exception_message();
+        let exception = Exception::new(missing, message);
+        assert_eq!(exception.to_string(), "NotFound { path: \"a.txt\" }");
+    }
+
+    #[test]
+    fn attempt_returns_the_raised_exception() {
+        let mut reached = false;
+        let outcome = Exception::attempt(|| {
+            parse("")?;
+            reached = true;
+            Ok(())
+        });
+        assert!(!reached);
+        assert!(outcome.unwrap_err().is::<ValueError>());
+        Exception::check(Exception::attempt(|| Ok(())));
+    }
+
+    #[test]
+    #[should_panic(expected = "KeyError: name")]
+    fn uncaught_exceptions_end_the_program() {
+        Exception::check(Err(Exception::new(KeyError(Str::from("name")), "name")));
+    }
+}
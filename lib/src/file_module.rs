@@ -1,9 +1,12 @@
 //! Quiche `File` module — Elixir-style file operations.
 //!
-//! All functions use `Str` for paths and content.
+//! All functions use `Str` for paths and content. `File.open`,
+//! `File.create` and `File.append` (and the `open(path)` builtin) return a
+//! [`FileHandle`], a [`ContextManager`] closed when its `with` block ends.
 
-use crate::{List, Str};
-use std::sync::Arc;
+use std::io::{Read, Write};
+
+use crate::{ContextManager, List, Str};
 
 /// Static module for file operations, used as `File.read(path)` in Quiche.
 pub struct File;
@@ -15,7 +18,7 @@ impl File {
     pub fn read(path: Str) -> Str {
         let content = std::fs::read_to_string(&*path)
             .unwrap_or_else(|e| panic!("File.read failed for '{}': {}", &*path, e));
-        Str(Arc::from(content.as_str()))
+        Str::from(content.as_str())
     }
 
     /// Write content to a file, creating it if it doesn't exist.
@@ -35,7 +38,7 @@ impl File {
             .filter_map(|entry| {
                 entry
                     .ok()
-                    .and_then(|e| e.file_name().to_str().map(|s| Str(Arc::from(s))))
+                    .and_then(|e| e.file_name().to_str().map(Str::from))
             })
             .collect();
         entries.sort();
@@ -90,6 +93,106 @@ impl File {
         // Update mtime by opening and closing
         let _ = std::fs::OpenOptions::new().write(true).open(&*path);
     }
+
+    /// Open a file for reading.
+    ///
+    /// Panics if the file cannot be opened.
+    pub fn open(path: Str) -> FileHandle {
+        FileHandle::new("File.open", path, std::fs::OpenOptions::new().read(true))
+    }
+
+    /// Create (or truncate) a file for writing.
+    ///
+    /// Panics if the file cannot be created.
+    pub fn create(path: Str) -> FileHandle {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        FileHandle::new("File.create", path, &options)
+    }
+
+    /// Open a file for appending, creating it if it doesn't exist.
+    ///
+    /// Panics if the file cannot be opened.
+    pub fn append(path: Str) -> FileHandle {
+        let mut options = std::fs::OpenOptions::new();
+        options.append(true).create(true);
+        FileHandle::new("File.append", path, &options)
+    }
+}
+
+/// `open(path)`: open a file for reading, as `File.open` does.
+pub fn open(path: Str) -> FileHandle {
+    File::open(path)
+}
+
+/// An open file, closed by `close()` or at the end of its `with` block.
+pub struct FileHandle {
+    path: Str,
+    file: Option<std::fs::File>,
+}
+
+impl FileHandle {
+    fn new(operation: &str, path: Str, options: &std::fs::OpenOptions) -> FileHandle {
+        let file = options
+            .open(&*path)
+            .unwrap_or_else(|e| panic!("{operation} failed for '{}': {}", &*path, e));
+        FileHandle {
+            path,
+            file: Some(file),
+        }
+    }
+
+    fn file(&mut self, operation: &str) -> &mut std::fs::File {
+        match &mut self.file {
+            Some(file) => file,
+            None => panic!("{operation} on closed file '{}'", &*self.path),
+        }
+    }
+
+    /// Read the rest of the file as a `Str`.
+    ///
+    /// Panics on failure or if the file is closed.
+    pub fn read(&mut self) -> Str {
+        let mut content = String::new();
+        self.file("read")
+            .read_to_string(&mut content)
+            .unwrap_or_else(|e| panic!("read failed for '{}': {}", &*self.path, e));
+        Str::from(content.as_str())
+    }
+
+    /// Read the rest of the file as lines, without their line endings.
+    ///
+    /// Panics on failure or if the file is closed.
+    pub fn lines(&mut self) -> List<Str> {
+        List(self.read().lines().map(Str::from).collect())
+    }
+
+    /// Write `content` to the file.
+    ///
+    /// Panics on failure or if the file is closed.
+    pub fn write(&mut self, content: Str) {
+        self.file("write")
+            .write_all(content.as_bytes())
+            .unwrap_or_else(|e| panic!("write failed for '{}': {}", &*self.path, e));
+    }
+
+    /// Flush and close the file; closing it again does nothing.
+    pub fn close(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            let _ = file.flush();
+        }
+    }
+
+    /// Whether the file has been closed.
+    pub fn closed(&self) -> bool {
+        self.file.is_none()
+    }
+}
+
+impl ContextManager for FileHandle {
+    fn exit(&mut self) {
+        self.close();
+    }
 }
 
 #[cfg(test)]
@@ -123,6 +226,36 @@ mod tests {
         let _ = std::fs::remove_dir(&*dir);
     }
 
+    #[test]
+    fn file_handle_closes_with_its_with_block() {
+        let tmp = str("/tmp/quiche_test_file_handle.txt");
+        {
+            let mut out = crate::With::enter(File::create(tmp.clone()));
+            out.write(str("one\n"));
+            out.write(str("two\n"));
+        }
+        let mut appended = File::append(tmp.clone());
+        appended.write(str("three\n"));
+        appended.close();
+        assert!(appended.closed());
+
+        let mut handle = crate::With::enter(open(tmp.clone()));
+        assert_eq!(handle.lines().len(), 3);
+        drop(handle);
+        File::rm(tmp);
+    }
+
+    #[test]
+    #[should_panic(expected = "read on closed file")]
+    fn file_handle_rejects_reads_once_closed() {
+        let tmp = str("/tmp/quiche_test_file_closed.txt");
+        File::write(tmp.clone(), str("x"));
+        let mut handle = File::open(tmp.clone());
+        handle.close();
+        File::rm(tmp);
+        handle.read();
+    }
+
     #[test]
     fn file_exists() {
         assert!(File::exists(str("Cargo.toml")));
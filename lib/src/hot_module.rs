@@ -0,0 +1,286 @@
+//! `Hot` — swapping a running script's functions for recompiled ones.
+//!
+//! `quiche app.q --hot` compiles each top-level function `f` (other than
+//! `main`) as `__quiche_hot_f`, behind a wrapper `f` that calls whatever its
+//! [`HotSlot`] holds: its own body until a reload. The generated
+//! `quiche_hot_register` lists these functions with their signatures and a
+//! hash of their source, and `main` starts with [`Hot::install`], which
+//! watches the control file named by [`HOT_ENV`] from a background thread.
+//!
+//! When the source changes, `quiche` rebuilds the whole script as a shared
+//! library and writes its path to the control file. The watcher loads it,
+//! calls its `quiche_hot_register`, and points the slot of each function
+//! whose hash changed at the new code. Calls already running finish on the
+//! old code; the next call runs the new.
+//!
+//! Only function bodies reload. A changed signature, or a change to the
+//! script's types or constants, is reported and needs a restart, since
+//! values made by the running code wouldn't match the new code's. The new
+//! code has its own copies of globals, and loaded libraries stay loaded
+//! for the life of the process.
+
+use crate::{CLib, Str};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::time::Duration;
+
+/// Environment variable naming the control file `quiche --hot` writes each
+/// rebuilt library's path to.
+pub const HOT_ENV: &str = "QUICHE_HOT";
+
+/// How often the watcher reads the control file.
+const POLL: Duration = Duration::from_millis(100);
+
+/// A script's `quiche_hot_register`: passes each hot-reloadable function to
+/// the callback and returns a hash of the script's other items.
+pub type HotRegister = fn(&mut dyn FnMut(HotFunction)) -> u64;
+
+/// What a hot-reloadable function's wrapper calls: the function's own body
+/// until a reload points it at new code.
+pub struct HotSlot {
+    code: AtomicPtr<()>,
+}
+
+impl Default for HotSlot {
+    fn default() -> Self {
+        HotSlot::new()
+    }
+}
+
+impl HotSlot {
+    /// A slot calling the function's own body.
+    pub const fn new() -> HotSlot {
+        HotSlot {
+            code: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    /// The function to call: the reloaded code, else `own`.
+    ///
+    /// `F` is the function's `fn` pointer type, which a reload only stores
+    /// code of (see [`Hot`]).
+    pub fn get<F: Copy>(&self, own: F) -> F {
+        let code = self.code.load(Ordering::Acquire);
+        if code.is_null() {
+            own
+        } else {
+            unsafe { std::mem::transmute_copy(&code) }
+        }
+    }
+
+    fn set(&self, code: usize) {
+        self.code.store(code as *mut (), Ordering::Release);
+    }
+}
+
+/// A hot-reloadable function, as a script's `quiche_hot_register` lists it.
+pub struct HotFunction {
+    name: &'static str,
+    signature: &'static str,
+    hash: u64,
+    slot: &'static HotSlot,
+    /// The body's address
+    code: usize,
+}
+
+impl HotFunction {
+    /// The function `name`, of type `signature` (`fn(i64) -> i64`), whose
+    /// source hashes to `hash`, called through `slot`, with body `code`.
+    pub fn new<F: Copy>(
+        name: &'static str,
+        signature: &'static str,
+        hash: u64,
+        slot: &'static HotSlot,
+        code: F,
+    ) -> HotFunction {
+        assert_eq!(
+            std::mem::size_of::<F>(),
+            std::mem::size_of::<usize>(),
+            "hot function '{name}' must be given as a fn pointer"
+        );
+        HotFunction {
+            name,
+            signature,
+            hash,
+            slot,
+            code: unsafe { std::mem::transmute_copy(&code) },
+        }
+    }
+}
+
+/// Static module behind `--hot` reloading.
+pub struct Hot;
+
+impl Hot {
+    /// Reload the script's functions whenever the control file named by
+    /// [`HOT_ENV`] names a new library. Does nothing when it is unset, so a
+    /// `--hot` build also runs on its own.
+    pub fn install(register: HotRegister) {
+        let Some(control) = std::env::var_os(HOT_ENV) else {
+            return;
+        };
+        let mut script = Script::new(register);
+        std::thread::spawn(move || {
+            let mut loaded = String::new();
+            loop {
+                std::thread::sleep(POLL);
+                let Ok(path) = std::fs::read_to_string(&control) else {
+                    continue;
+                };
+                if path != loaded {
+                    eprintln!("{}", script.reload(path.trim()));
+                    loaded = path;
+                }
+            }
+        });
+    }
+}
+
+/// The running script's functions, as the last reload left them.
+struct Script {
+    layout: u64,
+    functions: HashMap<&'static str, HotFunction>,
+}
+
+impl Script {
+    fn new(register: HotRegister) -> Script {
+        let mut functions = HashMap::new();
+        let layout = register(&mut |function: HotFunction| {
+            functions.insert(function.name, function);
+        });
+        Script { layout, functions }
+    }
+
+    /// Load the library at `path` and swap in its changed functions,
+    /// describing what happened.
+    fn reload(&mut self, path: &str) -> String {
+        let library = match CLib::try_load(Str::from(path)) {
+            Ok(library) => library,
+            Err(e) => return format!("hot reload failed for {e}"),
+        };
+        let symbol = Str::from("quiche_hot_register");
+        if !library.has(symbol.clone()) {
+            return format!("hot reload failed: '{path}' has no quiche_hot_register");
+        }
+        let register =
+            unsafe { std::mem::transmute::<crate::Ptr, HotRegister>(library.symbol(symbol)) };
+        // The new code is called from now on, so its library is never closed
+        std::mem::forget(library);
+        let mut incoming = Vec::new();
+        let layout = register(&mut |function| incoming.push(function));
+        self.apply(layout, incoming)
+    }
+
+    /// Point the slots of the functions changed in `incoming` at their new
+    /// code, unless the script's other items changed (`layout`).
+    fn apply(&mut self, layout: u64, incoming: Vec<HotFunction>) -> String {
+        if layout != self.layout {
+            return "hot reload skipped: types or constants changed; restart to pick them up"
+                .to_string();
+        }
+        let mut reloaded = Vec::new();
+        let mut restart = Vec::new();
+        for function in incoming {
+            // A function new to the script is only called by new code, which
+            // calls its own copy
+            let Some(current) = self.functions.get_mut(function.name) else {
+                continue;
+            };
+            if current.signature != function.signature {
+                restart.push(function.name);
+            } else if current.hash != function.hash {
+                current.slot.set(function.code);
+                current.hash = function.hash;
+                reloaded.push(function.name);
+            }
+        }
+        let mut report = if reloaded.is_empty() {
+            "hot reload: no function changed".to_string()
+        } else {
+            format!("hot reload: reloaded {}", reloaded.join(", "))
+        };
+        if !restart.is_empty() {
+            report.push_str(&format!(
+                "\nhot reload: signature of {} changed; restart to pick it up",
+                restart.join(", ")
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SLOT: HotSlot = HotSlot::new();
+    static NEW_SLOT: HotSlot = HotSlot::new();
+
+    fn double(x: i64) -> i64 {
+        x * 2
+    }
+
+    fn triple(x: i64) -> i64 {
+        x * 3
+    }
+
+    fn scale(x: i64) -> i64 {
+        SLOT.get(double as fn(i64) -> i64)(x)
+    }
+
+    fn register(register: &mut dyn FnMut(HotFunction)) -> u64 {
+        register(HotFunction::new(
+            "scale",
+            "fn(i64) -> i64",
+            1,
+            &SLOT,
+            double as fn(i64) -> i64,
+        ));
+        7
+    }
+
+    fn rebuilt(signature: &'static str, hash: u64) -> Vec<HotFunction> {
+        vec![
+            HotFunction::new(
+                "scale",
+                signature,
+                hash,
+                &NEW_SLOT,
+                triple as fn(i64) -> i64,
+            ),
+            HotFunction::new("helper", "fn()", 1, &NEW_SLOT, triple as fn(i64) -> i64),
+        ]
+    }
+
+    #[test]
+    fn changed_functions_are_swapped_in() {
+        let mut script = Script::new(register);
+        assert_eq!(scale(2), 4);
+        assert_eq!(
+            script.apply(7, rebuilt("fn(i64) -> i64", 1)),
+            "hot reload: no function changed"
+        );
+        assert_eq!(scale(2), 4);
+
+        assert_eq!(
+            script.apply(8, rebuilt("fn(i64) -> i64", 2)),
+            "hot reload skipped: types or constants changed; restart to pick them up"
+        );
+        assert_eq!(
+            script.apply(7, rebuilt("fn(i64, i64) -> i64", 2)),
+            "hot reload: no function changed\nhot reload: signature of scale changed; restart to pick it up"
+        );
+        assert_eq!(scale(2), 4);
+
+        assert_eq!(
+            script.apply(7, rebuilt("fn(i64) -> i64", 2)),
+            "hot reload: reloaded scale"
+        );
+        assert_eq!(scale(2), 6);
+        let failed = script.reload("libdoes-not-exist.so");
+        assert!(
+            failed.starts_with("hot reload failed for 'libdoes-not-exist.so': "),
+            "{failed}"
+        );
+    }
+}
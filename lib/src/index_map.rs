@@ -0,0 +1,562 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt::{Debug, Formatter, Result};
+use std::hash::{BuildHasher, Hash};
+use std::ops::Index;
+
+/// Marks an unused slot in the lookup table.
+const EMPTY: usize = usize::MAX;
+
+#[derive(Clone)]
+struct Bucket<K, V> {
+    hash: u64,
+    key: K,
+    value: V,
+}
+
+/// A hash map that iterates in insertion order, like a Python 3.7+ `dict`.
+///
+/// Entries live in a `Vec` in the order they were first inserted; an
+/// open-addressed table of positions into it answers lookups. Re-inserting
+/// an existing key replaces its value and keeps its place. Removing a key
+/// shifts the entries after it down, so it costs O(n) rather than O(1).
+#[derive(Clone)]
+pub struct IndexMap<K, V> {
+    entries: Vec<Bucket<K, V>>,
+    table: Vec<usize>,
+    hasher: RandomState,
+}
+
+impl<K, V> Default for IndexMap<K, V> {
+    fn default() -> Self {
+        IndexMap::new()
+    }
+}
+
+impl<K, V> IndexMap<K, V> {
+    pub fn new() -> Self {
+        IndexMap {
+            entries: Vec::new(),
+            table: Vec::new(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.table.fill(EMPTY);
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter(self.entries.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut(self.entries.iter_mut())
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.entries.iter())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.entries.iter())
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut(self.entries.iter_mut())
+    }
+
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys(self.entries.into_iter())
+    }
+
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues(self.entries.into_iter())
+    }
+
+    /// The entry at position `index` in insertion order.
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        self.entries.get(index).map(|b| (&b.key, &b.value))
+    }
+
+    /// Keep only the entries for which `keep` returns true, in order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        self.entries.retain_mut(|b| keep(&b.key, &mut b.value));
+        self.rebuild(self.table.len());
+    }
+
+    /// Refill a table of `capacity` slots from the entries.
+    fn rebuild(&mut self, capacity: usize) {
+        self.table = vec![EMPTY; capacity];
+        for index in 0..self.entries.len() {
+            let slot = self.free_slot(self.entries[index].hash);
+            self.table[slot] = index;
+        }
+    }
+
+    /// The first unused slot on `hash`'s probe sequence.
+    fn free_slot(&self, hash: u64) -> usize {
+        let mask = self.table.len() - 1;
+        let mut slot = hash as usize & mask;
+        while self.table[slot] != EMPTY {
+            slot = (slot + 1) & mask;
+        }
+        slot
+    }
+
+    /// Append an entry whose key is known to be absent; returns its position.
+    fn push(&mut self, hash: u64, key: K, value: V) -> usize {
+        // Keep the table at most three quarters full so probes stay short.
+        if (self.entries.len() + 1) * 4 > self.table.len() * 3 {
+            self.rebuild((self.table.len() * 2).max(8));
+        }
+        let index = self.entries.len();
+        self.entries.push(Bucket { hash, key, value });
+        let slot = self.free_slot(hash);
+        self.table[slot] = index;
+        index
+    }
+
+    /// Remove the entry at `index`, found in table slot `slot`.
+    fn remove_at(&mut self, slot: usize, index: usize) -> (K, V) {
+        // Backward-shift deletion: pull later probes into the hole so
+        // lookups never stop early at it.
+        let mask = self.table.len() - 1;
+        let mut hole = slot;
+        let mut next = (hole + 1) & mask;
+        while self.table[next] != EMPTY {
+            let home = self.entries[self.table[next]].hash as usize & mask;
+            if next.wrapping_sub(home) & mask >= next.wrapping_sub(hole) & mask {
+                self.table[hole] = self.table[next];
+                hole = next;
+            }
+            next = (next + 1) & mask;
+        }
+        self.table[hole] = EMPTY;
+
+        let bucket = self.entries.remove(index);
+        for position in &mut self.table {
+            if *position != EMPTY && *position > index {
+                *position -= 1;
+            }
+        }
+        (bucket.key, bucket.value)
+    }
+}
+
+impl<K: Hash + Eq, V> IndexMap<K, V> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut map = IndexMap::new();
+        map.entries.reserve(capacity);
+        map
+    }
+
+    fn hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    /// The table slot and entry position holding `key`.
+    fn find<Q>(&self, hash: u64, key: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        if self.table.is_empty() {
+            return None;
+        }
+        let mask = self.table.len() - 1;
+        let mut slot = hash as usize & mask;
+        loop {
+            let index = self.table[slot];
+            if index == EMPTY {
+                return None;
+            }
+            let bucket = &self.entries[index];
+            if bucket.hash == hash && bucket.key.borrow() == key {
+                return Some((slot, index));
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    fn position<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(self.hash(key), key).map(|(_, index)| index)
+    }
+
+    /// Insert `value` under `key`, returning the value it replaces. A
+    /// replaced key keeps its original position.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hash(&key);
+        match self.find(hash, &key) {
+            Some((_, index)) => Some(std::mem::replace(&mut self.entries[index].value, value)),
+            None => {
+                self.push(hash, key, value);
+                None
+            }
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(key).map(|index| &self.entries[index].value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(key)
+            .map(|index| &mut self.entries[index].value)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(key).and_then(|index| self.get_index(index))
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(key).is_some()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (slot, index) = self.find(self.hash(key), key)?;
+        Some(self.remove_at(slot, index))
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let hash = self.hash(&key);
+        match self.find(hash, &key) {
+            Some((slot, index)) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                slot,
+                index,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                map: self,
+                hash,
+                key,
+            }),
+        }
+    }
+}
+
+/// A view into one key of an [`IndexMap`], from [`IndexMap::entry`].
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+pub struct OccupiedEntry<'a, K, V> {
+    map: &'a mut IndexMap<K, V>,
+    slot: usize,
+    index: usize,
+}
+
+pub struct VacantEntry<'a, K, V> {
+    map: &'a mut IndexMap<K, V>,
+    hash: u64,
+    key: K,
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.map.entries[self.index].key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.map.entries[self.index].value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.entries[self.index].value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.entries[self.index].value
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.map.remove_at(self.slot, self.index).1
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        let index = self.map.push(self.hash, self.key, value);
+        &mut self.map.entries[index].value
+    }
+}
+
+impl<K, V, Q> Index<&Q> for IndexMap<K, V>
+where
+    K: Hash + Eq + Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+{
+    type Output = V;
+    fn index(&self, key: &Q) -> &V {
+        match self.get(key) {
+            Some(value) => value,
+            None => panic!("key not found"),
+        }
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for IndexMap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Equal when both hold the same keys and values, in any order (as in Python).
+impl<K: Hash + Eq, V: PartialEq> PartialEq for IndexMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Hash + Eq, V: Eq> Eq for IndexMap<K, V> {}
+
+impl<K: Hash + Eq, V> Extend<(K, V)> for IndexMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for IndexMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = IndexMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Hash + Eq, V, const N: usize> From<[(K, V); N]> for IndexMap<K, V> {
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K, V> IntoIterator for IndexMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter(self.entries.into_iter())
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a IndexMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut IndexMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+macro_rules! iterator {
+    ($(#[$doc:meta])* $name:ident<$($lt:lifetime)?>, $inner:ty, |$b:ident| -> $item:ty { $map:expr }) => {
+        $(#[$doc])*
+        pub struct $name<$($lt,)? K, V>($inner);
+
+        impl<$($lt,)? K, V> Iterator for $name<$($lt,)? K, V> {
+            type Item = $item;
+            fn next(&mut self) -> Option<$item> {
+                self.0.next().map(|$b| $map)
+            }
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.0.size_hint()
+            }
+        }
+
+        impl<$($lt,)? K, V> DoubleEndedIterator for $name<$($lt,)? K, V> {
+            fn next_back(&mut self) -> Option<$item> {
+                self.0.next_back().map(|$b| $map)
+            }
+        }
+
+        impl<$($lt,)? K, V> ExactSizeIterator for $name<$($lt,)? K, V> {}
+    };
+}
+
+iterator!(
+    /// Entries of an [`IndexMap`] by reference, in insertion order.
+    Iter<'a>, std::slice::Iter<'a, Bucket<K, V>>, |b| -> (&'a K, &'a V) { (&b.key, &b.value) }
+);
+iterator!(
+    /// Entries of an [`IndexMap`] with mutable values, in insertion order.
+    IterMut<'a>, std::slice::IterMut<'a, Bucket<K, V>>, |b| -> (&'a K, &'a mut V) { (&b.key, &mut b.value) }
+);
+iterator!(
+    /// Keys of an [`IndexMap`], in insertion order.
+    Keys<'a>, std::slice::Iter<'a, Bucket<K, V>>, |b| -> &'a K { &b.key }
+);
+iterator!(
+    /// Values of an [`IndexMap`], in insertion order.
+    Values<'a>, std::slice::Iter<'a, Bucket<K, V>>, |b| -> &'a V { &b.value }
+);
+iterator!(
+    /// Mutable values of an [`IndexMap`], in insertion order.
+    ValuesMut<'a>, std::slice::IterMut<'a, Bucket<K, V>>, |b| -> &'a mut V { &mut b.value }
+);
+iterator!(
+    /// Owned entries of an [`IndexMap`], in insertion order.
+    IntoIter<>, std::vec::IntoIter<Bucket<K, V>>, |b| -> (K, V) { (b.key, b.value) }
+);
+iterator!(
+    /// Owned keys of an [`IndexMap`], in insertion order.
+    IntoKeys<>, std::vec::IntoIter<Bucket<K, V>>, |b| -> K { b.key }
+);
+iterator!(
+    /// Owned values of an [`IndexMap`], in insertion order.
+    IntoValues<>, std::vec::IntoIter<Bucket<K, V>>, |b| -> V { b.value }
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_map_iterates_in_insertion_order() {
+        let mut map = IndexMap::new();
+        for key in ["zeta", "alpha", "mid", "beta"] {
+            map.insert(key, key.len());
+        }
+        map.insert("alpha", 0);
+        assert_eq!(
+            map.keys().copied().collect::<Vec<_>>(),
+            ["zeta", "alpha", "mid", "beta"]
+        );
+        assert_eq!(map["alpha"], 0);
+        assert_eq!(
+            format!("{map:?}"),
+            "{\"zeta\": 4, \"alpha\": 0, \"mid\": 3, \"beta\": 4}"
+        );
+    }
+
+    #[test]
+    fn index_map_remove_keeps_order_and_lookups() {
+        let mut map: IndexMap<i64, i64> = (0..100).map(|i| (i, i * i)).collect();
+        for i in (0..100).step_by(3) {
+            assert_eq!(map.remove(&i), Some(i * i));
+        }
+        assert_eq!(map.remove(&0), None);
+        let keys: Vec<i64> = map.keys().copied().collect();
+        let expected: Vec<i64> = (0..100).filter(|i| i % 3 != 0).collect();
+        assert_eq!(keys, expected);
+        for i in 0..100 {
+            assert_eq!(map.get(&i).copied(), (i % 3 != 0).then_some(i * i));
+        }
+        map.insert(0, -1);
+        assert_eq!(map.iter().next_back(), Some((&0, &-1)));
+    }
+
+    #[test]
+    fn index_map_entry_api() {
+        let mut counts: IndexMap<char, usize> = IndexMap::new();
+        for c in "banana".chars() {
+            *counts.entry(c).or_insert(0) += 1;
+        }
+        assert_eq!(
+            counts.into_iter().collect::<Vec<_>>(),
+            [('b', 1), ('a', 3), ('n', 2)]
+        );
+    }
+
+    #[test]
+    fn index_map_eq_ignores_order() {
+        let a = IndexMap::from([(1, "one"), (2, "two")]);
+        let b = IndexMap::from([(2, "two"), (1, "one")]);
+        assert_eq!(a, b);
+        assert_ne!(a, IndexMap::from([(1, "one")]));
+    }
+}
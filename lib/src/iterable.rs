@@ -0,0 +1,91 @@
+//! `QuicheIterable` — what a Quiche `for` loop can iterate over.
+//!
+//! The compiler lowers `for x in items` to a loop over
+//! `QuicheIterable::quiche_iter(items)`, so every collection the stdlib
+//! modules can hand back iterates the same way, owned or borrowed:
+//! Quiche's `List`, `Dict` and `Str`, the std collections, `BTreeMap` and
+//! `HashSet` iterators, string `chars()` / `lines()`, integer ranges, and
+//! `Option` / `Result` (zero or one item, as in Rust).
+//!
+//! Anything implementing `IntoIterator` is iterable, so an iterator kept in
+//! a variable (`it = xs.iter().map(f)`) or a class marked `@impl(Iterator)`
+//! loops as it would in Rust. Iterating a `Str` yields its characters, as in
+//! Python; a Rust `String` or `&str` has no `IntoIterator` of its own, so
+//! loop over its `.chars()`.
+
+/// A value a `for` loop can iterate over.
+pub trait QuicheIterable {
+    type Item;
+    type IntoIter: Iterator<Item = Self::Item>;
+
+    /// The iterator a `for` loop over `self` walks.
+    fn quiche_iter(self) -> Self::IntoIter;
+}
+
+impl<T: IntoIterator> QuicheIterable for T {
+    type Item = T::Item;
+    type IntoIter = T::IntoIter;
+    fn quiche_iter(self) -> Self::IntoIter {
+        self.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{List, Str};
+    use std::collections::{BTreeMap, HashSet};
+
+    fn collect<I: QuicheIterable>(items: I) -> Vec<I::Item> {
+        items.quiche_iter().collect()
+    }
+
+    #[test]
+    fn test_iterates_std_collections_and_strings() {
+        let tree = BTreeMap::from([(2, "b"), (1, "a")]);
+        assert_eq!(collect(tree.keys()), [&1, &2]);
+        assert_eq!(collect(&tree), [(&1, &"a"), (&2, &"b")]);
+        assert_eq!(collect(tree.into_values()), ["a", "b"]);
+
+        let mut seen = collect(HashSet::from([3, 4]));
+        seen.sort();
+        assert_eq!(seen, [3, 4]);
+
+        assert_eq!(collect(Str::from("hi")), ['h', 'i']);
+        assert_eq!(collect(&Str::from("ok")), ['o', 'k']);
+        assert_eq!(collect("a\nb".lines()), ["a", "b"]);
+        assert_eq!(collect(Some(5)), [5]);
+        assert_eq!(collect(Err::<i32, &str>("no")), Vec::<i32>::new());
+        assert_eq!(collect(&List::from(vec![1, 2])), [&1, &2]);
+        assert_eq!(collect(1i64..=3), [1, 2, 3]);
+    }
+
+    /// A class marked `@impl(Iterator)`.
+    struct Countdown {
+        n: i64,
+    }
+
+    impl Iterator for Countdown {
+        type Item = i64;
+        fn next(&mut self) -> Option<i64> {
+            self.n -= 1;
+            (self.n >= 0).then_some(self.n + 1)
+        }
+    }
+
+    #[test]
+    fn test_iterates_iterators_held_in_variables() {
+        let xs = List::from(vec![1, 2, 3]);
+        let it = xs.iter().map(|x| x * 10);
+        let mut seen = Vec::new();
+        for x in QuicheIterable::quiche_iter(it) {
+            seen.push(x);
+        }
+        assert_eq!(seen, [10, 20, 30]);
+
+        let countdown = Countdown { n: 3 };
+        assert_eq!(collect(countdown), [3, 2, 1]);
+        let pairs = xs.iter().zip(xs.iter().rev()).enumerate().peekable();
+        assert_eq!(collect(pairs).len(), 3);
+    }
+}
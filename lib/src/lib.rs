@@ -1,27 +1,96 @@
 //! Quiche standard library — primitive newtypes for the Quiche language.
 //!
 //! Provides `Str`, `List<T>`, and `Dict<K,V>` as ergonomic wrappers
-//! around Rust's standard types with chainable APIs.
+//! around Rust's standard types with chainable APIs, `IndexMap<K, V>`, the
+//! insertion-ordered map behind `Dict`, and `Pool<T>`, a free-list object
+//! pool for hot allocate/free patterns.
 //!
-//! Also provides Elixir-style modules: `File`, `Path`, `System`, `Enum`.
+//! Also provides Elixir-style modules: `File`, `Path`, `System`, `Os`,
+//! `Enum`, `CLib` (runtime loading of C shared libraries), `Cli` (the
+//! argument parser behind `@cli`), `Memo` (the cache behind `@memoize`),
+//! plus `Qtest`, the runtime behind `quiche test` harnesses,
+//! `Value`, a dynamically typed value for exchanging data with Python,
+//! `QuicheIterable`, what `for` loops iterate through, `Show`, how f-strings
+//! pick between `Display` and `Debug`, `QuicheUnpack`, what `a, b = value`
+//! unpacks, the `Overflow*` traits behind `--overflow` integer arithmetic,
+//! `Traceback`, which prints Python-style tracebacks when a script panics,
+//! `Hot`, which swaps in recompiled functions under `quiche --hot`,
+//! `Parallel`, which maps a function over a list on a thread pool,
+//! `Server`, a minimal HTTP server routing paths to handlers, `Signal`,
+//! which runs handlers for process signals such as Ctrl-C,
+//! `Exception`, what `raise` throws and `try` / `except` catches, with
+//! built-in classes such as `ValueError`, and `ContextManager`, what a
+//! `with` block enters and exits.
 //!
 //! All newtypes implement the [`QuicheType`] trait, giving them
 //! `.view()` (borrow inner) and `.inner()` (consume wrapper).
 
+mod cli_module;
+mod clib_module;
+mod context;
 mod dict;
+mod engine_value;
 mod enum_module;
+mod exception;
 mod file_module;
+mod hot_module;
+pub mod index_map;
+mod iterable;
 mod list;
+mod memo;
+mod os_module;
+mod overflow;
+mod parallel_module;
 mod path_module;
+mod pool;
+mod pprint;
+mod qtest_given;
+mod qtest_module;
 mod quiche_type;
+mod server_module;
+mod show;
+mod signal_module;
+#[cfg(feature = "sources")]
+pub mod sources;
 mod str_type;
 mod system_module;
+mod traceback;
+mod unpack;
+mod value;
 
+pub use cli_module::Cli;
+pub use clib_module::{CLib, Ptr};
+pub use context::{ContextManager, With};
 pub use dict::Dict;
+pub use engine_value::EngineValue;
 pub use enum_module::Enum;
-pub use file_module::File;
+pub use exception::{
+    Exception, IndexError, KeyError, NotImplementedError, RaiseDebug, RaiseDisplay, Raised,
+    RuntimeError, TypeError, ValueError, ZeroDivisionError,
+};
+pub use file_module::{File, FileHandle, open};
+pub use hot_module::{HOT_ENV, Hot, HotFunction, HotRegister, HotSlot};
+pub use index_map::IndexMap;
+pub use iterable::QuicheIterable;
 pub use list::List;
+pub use memo::Memo;
+pub use os_module::Os;
+pub use overflow::{OverflowAdd, OverflowMul, OverflowNeg, OverflowSub};
+pub use parallel_module::Parallel;
 pub use path_module::Path;
+pub use pool::{Pool, PoolRef};
+pub use pprint::{pformat, pprint, repr};
+pub use qtest_given::{Arbitrary, Gen, PROPERTY_CASES_ENV, PROPERTY_SEED_ENV};
+pub use qtest_module::{
+    COVERAGE_DIR_ENV, Qtest, TEST_FORMAT_ENV, TEST_REPORT_DIR_ENV, TestOutcome, TestResult,
+    TestSummary, UPDATE_SNAPSHOTS_ENV,
+};
 pub use quiche_type::QuicheType;
+pub use server_module::{Request, Response, Server};
+pub use show::{AsDebug, Show, ShowDebug, ShowDisplay};
+pub use signal_module::Signal;
 pub use str_type::{Str, str};
 pub use system_module::System;
+pub use traceback::Traceback;
+pub use unpack::QuicheUnpack;
+pub use value::{FromValue, Value};
@@ -29,12 +29,59 @@ impl<T: Debug> Display for List<T> {
     }
 }
 
-impl<T: PartialEq> PartialEq for List<T> {
-    fn eq(&self, other: &Self) -> bool {
+/// Element-wise equality, also against the `Vec`s, arrays and slices a
+/// list is compared with in tests (`assert xs == [1, 2, 3]`).
+impl<T: PartialEq<U>, U> PartialEq<List<U>> for List<T> {
+    fn eq(&self, other: &List<U>) -> bool {
         self.0 == other.0
     }
 }
 
+impl<T: Eq> Eq for List<T> {}
+
+impl<T: PartialEq<U>, U> PartialEq<Vec<U>> for List<T> {
+    fn eq(&self, other: &Vec<U>) -> bool {
+        self.0 == *other
+    }
+}
+
+impl<T: PartialEq<U>, U> PartialEq<List<U>> for Vec<T> {
+    fn eq(&self, other: &List<U>) -> bool {
+        *self == other.0
+    }
+}
+
+impl<T: PartialEq<U>, U, const N: usize> PartialEq<[U; N]> for List<T> {
+    fn eq(&self, other: &[U; N]) -> bool {
+        self.0 == *other
+    }
+}
+
+impl<T: PartialEq<U>, U> PartialEq<[U]> for List<T> {
+    fn eq(&self, other: &[U]) -> bool {
+        self.0 == *other
+    }
+}
+
+impl<T: PartialEq<U>, U> PartialEq<&[U]> for List<T> {
+    fn eq(&self, other: &&[U]) -> bool {
+        self.0 == *other
+    }
+}
+
+/// Lexicographic, as in Python: `[1, 2] < [1, 3]` and `[1] < [1, 0]`.
+impl<T: PartialOrd> PartialOrd for List<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<T: Ord> Ord for List<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
 impl<T> From<Vec<T>> for List<T> {
     fn from(v: Vec<T>) -> Self {
         List(v)
@@ -49,6 +96,14 @@ impl<T> IntoIterator for List<T> {
     }
 }
 
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
 impl<T> FromIterator<T> for List<T> {
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
         List(iter.into_iter().collect())
@@ -120,6 +175,7 @@ impl<T> QuicheType for List<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Str;
 
     #[test]
     fn list_new_empty() {
@@ -187,6 +243,20 @@ mod tests {
         assert_eq!(l.0, vec![1, 2, 3]);
     }
 
+    #[test]
+    fn list_compares_with_vecs_and_arrays() {
+        let l = List(vec![1, 2, 3]);
+        assert_eq!(l, [1, 2, 3]);
+        assert_eq!(l, vec![1, 2, 3]);
+        assert_eq!(vec![1, 2, 3], l);
+        assert_eq!(l, List(vec![1, 2, 3]));
+        assert_ne!(l, [1, 2]);
+        assert!(List(vec![1, 2]) < List(vec![1, 3]));
+        assert!(List(vec![1]) < List(vec![1, 0]));
+        let nested = List(vec![List(vec![Str::from("a")])]);
+        assert_eq!(nested, [List(vec![Str::from("a")])]);
+    }
+
     #[test]
     fn list_display() {
         let l = List(vec![1, 2, 3]);
@@ -0,0 +1,84 @@
+//! `Memo` — the result cache behind `@memoize`.
+//!
+//! A `@memoize`d function compiles to a wrapper that calls
+//! `Memo.cached("fib", n, || __memoized_fib(n))`: each function gets its own
+//! per-thread cache keyed by its arguments, so recursive calls go through the
+//! cache too.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+thread_local! {
+    static CACHES: RefCell<HashMap<&'static str, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Static module for memoized calls.
+pub struct Memo;
+
+impl Memo {
+    /// The cached result of function `name` for `key`, computing and storing
+    /// it with `compute` on a miss.
+    pub fn cached<K, V>(name: &'static str, key: K, compute: impl FnOnce() -> V) -> V
+    where
+        K: Hash + Eq + 'static,
+        V: Clone + 'static,
+    {
+        let hit = CACHES.with(|caches| {
+            caches
+                .borrow()
+                .get(name)
+                .and_then(|cache| cache.downcast_ref::<HashMap<K, V>>())
+                .and_then(|cache| cache.get(&key).cloned())
+        });
+        if let Some(value) = hit {
+            return value;
+        }
+        // No borrow is held while computing: recursive calls hit the cache.
+        let value = compute();
+        CACHES.with(|caches| {
+            let mut caches = caches.borrow_mut();
+            let cache = caches
+                .entry(name)
+                .or_insert_with(|| Box::new(HashMap::<K, V>::new()));
+            if let Some(cache) = cache.downcast_mut::<HashMap<K, V>>() {
+                cache.insert(key, value.clone());
+            }
+        });
+        value
+    }
+
+    /// Drop every cached result of `name`.
+    pub fn clear(name: &str) {
+        CACHES.with(|caches| caches.borrow_mut().remove(name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static CALLS: Cell<u32> = const { Cell::new(0) };
+    }
+
+    fn fib(n: u64) -> u64 {
+        Memo::cached("fib", n, || {
+            CALLS.with(|c| c.set(c.get() + 1));
+            if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+        })
+    }
+
+    #[test]
+    fn test_cached_computes_each_key_once() {
+        assert_eq!(fib(50), 12586269025);
+        assert_eq!(CALLS.with(Cell::get), 51);
+        assert_eq!(fib(50), 12586269025);
+        assert_eq!(CALLS.with(Cell::get), 51);
+        Memo::clear("fib");
+        fib(1);
+        assert_eq!(CALLS.with(Cell::get), 52);
+    }
+}
@@ -0,0 +1,50 @@
+//! Quiche `Os` module — the platform a program is running on.
+//!
+//! Runtime counterparts of `@cfg(target_os=...)`, for behavior that differs
+//! by platform but compiles everywhere.
+
+use crate::Str;
+
+/// Static module for platform checks, used as `Os.name()` in Quiche.
+pub struct Os;
+
+impl Os {
+    /// The operating system: `"linux"`, `"macos"`, `"windows"`, ...
+    /// (Rust's `target_os`).
+    pub fn name() -> Str {
+        Str::from(std::env::consts::OS)
+    }
+
+    /// The OS family: `"unix"` or `"windows"` (empty on targets with
+    /// neither, such as wasm).
+    pub fn family() -> Str {
+        Str::from(std::env::consts::FAMILY)
+    }
+
+    /// The CPU architecture: `"x86_64"`, `"aarch64"`, ...
+    pub fn arch() -> Str {
+        Str::from(std::env::consts::ARCH)
+    }
+
+    /// Whether the program is running on Windows.
+    pub fn is_windows() -> bool {
+        cfg!(windows)
+    }
+
+    /// Whether the program is running on a Unix (Linux, macOS, the BSDs, ...).
+    pub fn is_unix() -> bool {
+        cfg!(unix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn os_matches_target() {
+        assert_eq!(&*Os::name(), std::env::consts::OS);
+        assert_eq!(Os::is_unix(), &*Os::family() == "unix");
+        assert!(!Os::arch().is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+//! Integer arithmetic under `--overflow=checked|wrapping|saturating`.
+//!
+//! With an overflow mode, the compiler lowers `a + b` to
+//! `OverflowAdd::checked(a, b)` (or `wrapping` / `saturating`), and likewise
+//! `-`, `*` and negation, wherever an operand is known to be an integer.
+//! Floats and strings keep their usual operators, since they cannot
+//! overflow.
+
+/// `+` under an overflow mode.
+pub trait OverflowAdd: Sized {
+    /// Panic with an `OverflowError` instead of overflowing.
+    fn checked(self, rhs: Self) -> Self;
+    /// Wrap around at the type's bounds.
+    fn wrapping(self, rhs: Self) -> Self;
+    /// Clamp to the type's bounds.
+    fn saturating(self, rhs: Self) -> Self;
+}
+
+/// `-` under an overflow mode.
+pub trait OverflowSub: Sized {
+    fn checked(self, rhs: Self) -> Self;
+    fn wrapping(self, rhs: Self) -> Self;
+    fn saturating(self, rhs: Self) -> Self;
+}
+
+/// `*` under an overflow mode.
+pub trait OverflowMul: Sized {
+    fn checked(self, rhs: Self) -> Self;
+    fn wrapping(self, rhs: Self) -> Self;
+    fn saturating(self, rhs: Self) -> Self;
+}
+
+/// Unary `-` under an overflow mode.
+pub trait OverflowNeg: Sized {
+    fn checked(self) -> Self;
+    fn wrapping(self) -> Self;
+    fn saturating(self) -> Self;
+}
+
+fn overflow<T: std::fmt::Display>(lhs: T, op: &str, rhs: T, ty: &str) -> ! {
+    panic!("OverflowError: {lhs} {op} {rhs} does not fit in {ty}")
+}
+
+macro_rules! int_overflow {
+    ($trait:ident, $op:literal, $checked:ident, $wrapping:ident, $saturating:ident, INTS) => {
+        int_overflow! {
+            $trait, $op, $checked, $wrapping, $saturating,
+            i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize
+        }
+    };
+    ($trait:ident, $op:literal, $checked:ident, $wrapping:ident, $saturating:ident, $($t:ty),*) => {$(
+        impl $trait for $t {
+            fn checked(self, rhs: Self) -> Self {
+                self.$checked(rhs)
+                    .unwrap_or_else(|| overflow(self, $op, rhs, stringify!($t)))
+            }
+            fn wrapping(self, rhs: Self) -> Self {
+                self.$wrapping(rhs)
+            }
+            fn saturating(self, rhs: Self) -> Self {
+                self.$saturating(rhs)
+            }
+        }
+    )*};
+}
+
+macro_rules! plain_overflow {
+    ($trait:ident, $op:path, $($t:ty),*) => {$(
+        impl $trait for $t {
+            fn checked(self, rhs: Self) -> Self {
+                $op(self, rhs)
+            }
+            fn wrapping(self, rhs: Self) -> Self {
+                $op(self, rhs)
+            }
+            fn saturating(self, rhs: Self) -> Self {
+                $op(self, rhs)
+            }
+        }
+    )*};
+}
+
+int_overflow! { OverflowAdd, "+", checked_add, wrapping_add, saturating_add, INTS }
+int_overflow! { OverflowSub, "-", checked_sub, wrapping_sub, saturating_sub, INTS }
+int_overflow! { OverflowMul, "*", checked_mul, wrapping_mul, saturating_mul, INTS }
+macro_rules! neg_overflow {
+    ($($t:ty),*) => {$(
+        impl OverflowNeg for $t {
+            fn checked(self) -> Self {
+                self.checked_neg()
+                    .unwrap_or_else(|| panic!("OverflowError: -({self}) does not fit in {}", stringify!($t)))
+            }
+            fn wrapping(self) -> Self {
+                self.wrapping_neg()
+            }
+            fn saturating(self) -> Self {
+                self.saturating_neg()
+            }
+        }
+    )*};
+}
+
+neg_overflow!(i8, i16, i32, i64, i128, isize);
+plain_overflow!(OverflowAdd, std::ops::Add::add, f32, f64, crate::Str);
+plain_overflow!(OverflowSub, std::ops::Sub::sub, f32, f64);
+plain_overflow!(OverflowMul, std::ops::Mul::mul, f32, f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modes_differ_only_on_overflow() {
+        assert_eq!(OverflowAdd::checked(2i64, 3), 5);
+        assert_eq!(OverflowAdd::wrapping(i64::MAX, 1), i64::MIN);
+        assert_eq!(OverflowAdd::saturating(i64::MAX, 1), i64::MAX);
+        assert_eq!(OverflowSub::saturating(0u8, 1), 0);
+        assert_eq!(OverflowMul::wrapping(200u8, 2), 144);
+        assert_eq!(OverflowMul::saturating(1.5f64, 2.0), 3.0);
+        assert_eq!(OverflowNeg::saturating(i8::MIN), i8::MAX);
+        assert_eq!(OverflowNeg::wrapping(i8::MIN), i8::MIN);
+        assert_eq!(OverflowNeg::checked(5i64), -5);
+    }
+
+    #[test]
+    #[should_panic(expected = "OverflowError: 9223372036854775807 + 1 does not fit in i64")]
+    fn test_checked_overflow_panics() {
+        OverflowAdd::checked(i64::MAX, 1);
+    }
+}
@@ -0,0 +1,114 @@
+//! Quiche `Parallel` module — data-parallel helpers over a thread pool.
+//!
+//! `Parallel.map(f, items)` and `Parallel.for_each(f, items)` run `f` on
+//! every item using one worker per available core. Workers pull the next
+//! unclaimed item, so uneven work spreads out, and all of them finish before
+//! the call returns: nothing `f` borrows outlives it. Results keep the order
+//! of `items`.
+//!
+//! The compiler checks that `f` only captures values that can be shared
+//! between threads; Rust's `Send` and `Sync` bounds here back that up.
+
+use crate::List;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Static module for parallel collection operations, used as
+/// `Parallel.map(f, items)` in Quiche.
+pub struct Parallel;
+
+impl Parallel {
+    /// Transform each element on the worker threads, keeping their order.
+    pub fn map<T: Send, U: Send>(f: impl Fn(T) -> U + Sync, items: List<T>) -> List<U> {
+        let slots: Vec<Mutex<Option<T>>> = items
+            .0
+            .into_iter()
+            .map(|item| Mutex::new(Some(item)))
+            .collect();
+        let next = AtomicUsize::new(0);
+        let done: Vec<Vec<(usize, U)>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..Self::workers(slots.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(slot) = slots.get(i) else {
+                                return done;
+                            };
+                            let item = slot
+                                .lock()
+                                .unwrap_or_else(|e| e.into_inner())
+                                .take()
+                                .expect("each item is claimed once");
+                            done.push((i, f(item)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+        let mut results: Vec<Option<U>> = (0..slots.len()).map(|_| None).collect();
+        for (i, result) in done.into_iter().flatten() {
+            results[i] = Some(result);
+        }
+        List(results.into_iter().flatten().collect())
+    }
+
+    /// Call `f` on each element on the worker threads.
+    pub fn for_each<T: Send>(f: impl Fn(T) + Sync, items: List<T>) {
+        Self::map(f, items);
+    }
+
+    /// Threads to run `count` items on: one per core, at most one per item.
+    fn workers(count: usize) -> usize {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        cores.min(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn map_keeps_order() {
+        let squares = Parallel::map(|x: i64| x * x, List((1..=100).collect()));
+        assert_eq!(squares.0, (1..=100).map(|x| x * x).collect::<Vec<i64>>());
+        let empty: List<i64> = Parallel::map(|x: i64| x, List(vec![]));
+        assert!(empty.0.is_empty());
+    }
+
+    #[test]
+    fn for_each_visits_every_item_borrowing_from_the_caller() {
+        let seen = Mutex::new(HashSet::new());
+        Parallel::for_each(
+            |x: i64| {
+                seen.lock().unwrap_or_else(|e| e.into_inner()).insert(x);
+            },
+            List((0..50).collect()),
+        );
+        assert_eq!(seen.into_inner().unwrap_or_default().len(), 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "bad item 3")]
+    fn a_panicking_item_panics_the_caller() {
+        Parallel::for_each(
+            |x: i64| {
+                if x == 3 {
+                    panic!("bad item {x}");
+                }
+            },
+            List((0..8).collect()),
+        );
+    }
+}
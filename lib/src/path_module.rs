@@ -4,7 +4,6 @@
 //! (except `expand` which resolves to an absolute path).
 
 use crate::{List, Str};
-use std::sync::Arc;
 
 /// Static module for path operations, used as `Path.join(a, b)` in Quiche.
 #[allow(non_camel_case_types)]
@@ -14,14 +13,14 @@ impl Path {
     /// Join two path segments.
     pub fn join(base: Str, child: Str) -> Str {
         let joined = std::path::Path::new(&*base).join(&*child);
-        Str(Arc::from(joined.to_string_lossy().as_ref()))
+        Str::from(joined.to_string_lossy().into_owned())
     }
 
     /// Return the last component of the path (filename).
     pub fn basename(path: Str) -> Str {
         let p = std::path::Path::new(&*path);
         match p.file_name() {
-            Some(name) => Str(Arc::from(name.to_string_lossy().as_ref())),
+            Some(name) => Str::from(name.to_string_lossy().into_owned()),
             None => path,
         }
     }
@@ -30,8 +29,8 @@ impl Path {
     pub fn dirname(path: Str) -> Str {
         let p = std::path::Path::new(&*path);
         match p.parent() {
-            Some(parent) => Str(Arc::from(parent.to_string_lossy().as_ref())),
-            None => Str(Arc::from(".")),
+            Some(parent) => Str::from(parent.to_string_lossy().into_owned()),
+            None => Str::from("."),
         }
     }
 
@@ -41,9 +40,9 @@ impl Path {
         match p.extension() {
             Some(ext) => {
                 let s = format!(".{}", ext.to_string_lossy());
-                Str(Arc::from(s.as_str()))
+                Str::from(s.as_str())
             }
-            None => Str(Arc::from("")),
+            None => Str::from(""),
         }
     }
 
@@ -54,7 +53,7 @@ impl Path {
             Some(stem) => {
                 let parent = p.parent().unwrap_or(std::path::Path::new(""));
                 let result = parent.join(stem);
-                Str(Arc::from(result.to_string_lossy().as_ref()))
+                Str::from(result.to_string_lossy().into_owned())
             }
             None => path,
         }
@@ -64,13 +63,13 @@ impl Path {
     pub fn expand(path: Str) -> Str {
         let p = std::path::Path::new(&*path);
         match std::fs::canonicalize(p) {
-            Ok(abs) => Str(Arc::from(abs.to_string_lossy().as_ref())),
+            Ok(abs) => Str::from(abs.to_string_lossy().into_owned()),
             Err(_) => {
                 // If file doesn't exist, do best-effort with current dir
                 match std::env::current_dir() {
                     Ok(cwd) => {
                         let joined = cwd.join(p);
-                        Str(Arc::from(joined.to_string_lossy().as_ref()))
+                        Str::from(joined.to_string_lossy().into_owned())
                     }
                     Err(_) => path,
                 }
@@ -82,7 +81,7 @@ impl Path {
     pub fn split(path: Str) -> List<Str> {
         let components: Vec<Str> = std::path::Path::new(&*path)
             .components()
-            .map(|c| Str(Arc::from(c.as_os_str().to_string_lossy().as_ref())))
+            .map(|c| Str::from(c.as_os_str().to_string_lossy().into_owned()))
             .collect();
         List(components)
     }
@@ -105,7 +104,7 @@ impl Path {
                 let name = entry.file_name().to_string_lossy().to_string();
                 if Self::glob_match(&pat, &name) {
                     let full = entry.path().to_string_lossy().to_string();
-                    results.push(Str(Arc::from(full.as_str())));
+                    results.push(Str::from(full.as_str()));
                 }
             }
         }
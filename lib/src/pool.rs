@@ -0,0 +1,205 @@
+/// Handle to a value stored in a [`Pool`].
+///
+/// Carries the slot's generation, so a handle kept after its value was
+/// freed (and the slot reused) is rejected instead of aliasing the new value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PoolRef {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Debug)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Quiche's object pool — fixed-type storage with free-list reuse.
+///
+/// Freed slots are recycled by later allocations, so hot allocate/free
+/// loops (tokens, AST nodes, particles) stop hitting the general allocator
+/// once the pool has warmed up.
+#[derive(Clone, Debug)]
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Pool::new()
+    }
+}
+
+impl<T> Pool<T> {
+    pub fn new() -> Self {
+        Pool {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Pool {
+            slots: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Store `value`, reusing a freed slot when one is available.
+    pub fn alloc(&mut self, value: T) -> PoolRef {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return PoolRef {
+                index,
+                generation: slot.generation,
+            };
+        }
+        let index = u32::try_from(self.slots.len()).unwrap_or_else(|_| panic!("Pool is full"));
+        self.slots.push(Slot {
+            generation: 0,
+            value: Some(value),
+        });
+        PoolRef {
+            index,
+            generation: 0,
+        }
+    }
+
+    /// Remove and return the value behind `handle`. Returns `None` for a
+    /// stale or already-freed handle.
+    pub fn free(&mut self, handle: PoolRef) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, handle: PoolRef) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?
+            .value
+            .as_ref()
+    }
+
+    pub fn get_mut(&mut self, handle: PoolRef) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?
+            .value
+            .as_mut()
+    }
+
+    /// Whether `handle` still refers to a live value.
+    pub fn contains(&self, handle: PoolRef) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Number of live values.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of slots allocated so far, live or free.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Free every value. Outstanding handles become stale.
+    pub fn clear(&mut self) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.value.take().is_some() {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+            }
+        }
+        self.len = 0;
+    }
+
+    /// Iterate over live values with their handles.
+    pub fn iter(&self) -> impl Iterator<Item = (PoolRef, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value.as_ref().map(|value| {
+                (
+                    PoolRef {
+                        index: index as u32,
+                        generation: slot.generation,
+                    },
+                    value,
+                )
+            })
+        })
+    }
+}
+
+impl<T: Clone> Pool<T> {
+    /// Copy of the value behind `handle`, for callers that cannot hold a
+    /// borrow of the pool.
+    pub fn get_cloned(&self, handle: PoolRef) -> Option<T> {
+        self.get(handle).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_alloc_get_free() {
+        let mut pool = Pool::new();
+        let a = pool.alloc("a");
+        let b = pool.alloc("b");
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.get(a), Some(&"a"));
+        assert_eq!(pool.free(a), Some("a"));
+        assert_eq!(pool.free(a), None);
+        assert_eq!(pool.get(b), Some(&"b"));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_pool_reuses_slots_and_rejects_stale_handles() {
+        let mut pool = Pool::new();
+        let old = pool.alloc(1);
+        pool.free(old);
+        let new = pool.alloc(2);
+        assert_eq!(pool.capacity(), 1);
+        assert_eq!(pool.get(old), None);
+        assert_eq!(pool.get(new), Some(&2));
+        if let Some(v) = pool.get_mut(new) {
+            *v += 1;
+        }
+        assert_eq!(pool.get_cloned(new), Some(3));
+    }
+
+    #[test]
+    fn test_pool_clear_invalidates_handles() {
+        let mut pool = Pool::with_capacity(4);
+        let handles: Vec<PoolRef> = (0..4).map(|i| pool.alloc(i)).collect();
+        pool.free(handles[1]);
+        assert_eq!(
+            pool.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            vec![0, 2, 3]
+        );
+        pool.clear();
+        assert!(pool.is_empty());
+        assert!(handles.iter().all(|h| !pool.contains(*h)));
+        pool.alloc(9);
+        assert_eq!(pool.capacity(), 4);
+    }
+}
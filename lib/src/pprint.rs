@@ -0,0 +1,293 @@
+//! `pprint(value)` and the `f"{expr=}"` debug form — Python-style views of a
+//! value's `Debug` output.
+//!
+//! Both start from `{:?}` and unwrap Quiche's newtypes, so a `List[Str]`
+//! shows as `["a", "b"]` rather than `List([Str("a"), Str("b")])`.
+//! [`pformat`] also breaks every bracket that doesn't fit in [`WIDTH`]
+//! columns into one item per line, indented like Rust's `{:#?}`:
+//!
+//! ```text
+//! {
+//!     "points": [Point { x: 1, y: 2 }, Point { x: 3, y: 4 }],
+//!     "names": ["a long name", "another long name", "and one more"],
+//! }
+//! ```
+
+use crate::Str;
+use std::fmt::Debug;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Column `pformat` keeps lines within, where it can.
+pub const WIDTH: usize = 80;
+
+/// Newtypes shown as their inner value.
+const TRANSPARENT: [&str; 3] = ["List", "Dict", "Str"];
+
+/// Print `value` laid out by [`pformat`].
+pub fn pprint<T: Debug + ?Sized>(value: &T) {
+    println!("{}", pformat(value));
+}
+
+/// `value`'s `Debug` output, newtypes unwrapped, with brackets that don't fit
+/// in [`WIDTH`] columns broken one item per line.
+pub fn pformat<T: Debug + ?Sized>(value: &T) -> Str {
+    let mut out = String::new();
+    layout(&parse(value), 0, &mut out);
+    Str::from(out)
+}
+
+/// `value`'s `Debug` output on one line, newtypes unwrapped — what
+/// `f"{expr=}"` shows after `expr=`.
+pub fn repr<T: Debug + ?Sized>(value: &T) -> Str {
+    let mut out = String::new();
+    flat(&parse(value), &mut out);
+    Str::from(out)
+}
+
+/// A piece of `Debug` output: plain text, or a bracketed, comma-separated
+/// group.
+enum Part {
+    Text(String),
+    Group {
+        open: char,
+        close: char,
+        /// `Point { x: 1 }` pads its braces; `{"a": 1}` doesn't.
+        padded: bool,
+        /// A one-element tuple's `(1,)`.
+        trailing_comma: bool,
+        items: Vec<Vec<Part>>,
+    },
+}
+
+fn parse<T: Debug + ?Sized>(value: &T) -> Vec<Part> {
+    let text = format!("{value:?}");
+    let mut chars = text.chars().peekable();
+    let (mut items, _) = parse_items(&mut chars, None);
+    unwrap_newtypes(items.pop().unwrap_or_default())
+}
+
+/// Items up to `close` (or the end), and whether the first was padded.
+fn parse_items(chars: &mut Peekable<Chars<'_>>, close: Option<char>) -> (Vec<Vec<Part>>, bool) {
+    let padded = chars.peek() == Some(&' ');
+    let mut items = Vec::new();
+    let mut item = Vec::new();
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => {
+                push_text(&mut item, &mut text, false);
+                let close = match c {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                };
+                let (mut group_items, padded) = parse_items(chars, Some(close));
+                let trailing_comma =
+                    group_items.len() > 1 && group_items.last().is_some_and(|last| last.is_empty());
+                if trailing_comma || group_items.iter().all(|i| i.is_empty()) {
+                    group_items.pop();
+                }
+                item.push(Part::Group {
+                    open: c,
+                    close,
+                    padded,
+                    trailing_comma,
+                    items: group_items,
+                });
+            }
+            ',' => {
+                push_text(&mut item, &mut text, true);
+                items.push(std::mem::take(&mut item));
+            }
+            c if Some(c) == close => break,
+            '"' | '\'' => {
+                text.push(c);
+                while let Some(next) = chars.next() {
+                    text.push(next);
+                    if next == '\\' {
+                        text.extend(chars.next());
+                    } else if next == c {
+                        break;
+                    }
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+    push_text(&mut item, &mut text, true);
+    items.push(item);
+    (items, padded)
+}
+
+/// Add `text` to `item`, trimmed at the item's start and `end` (the spaces
+/// around items are the layout's to choose).
+fn push_text(item: &mut Vec<Part>, text: &mut String, end: bool) {
+    let mut trimmed = text.as_str();
+    if item.is_empty() {
+        trimmed = trimmed.trim_start();
+    }
+    if end {
+        trimmed = trimmed.trim_end();
+    }
+    if !trimmed.is_empty() {
+        item.push(Part::Text(trimmed.to_string()));
+    }
+    text.clear();
+}
+
+/// Replace `List(x)`, `Dict(x)` and `Str(x)` with `x`, at any depth.
+fn unwrap_newtypes(parts: Vec<Part>) -> Vec<Part> {
+    let mut out: Vec<Part> = Vec::new();
+    for part in parts {
+        let Part::Group {
+            open,
+            close,
+            padded,
+            trailing_comma,
+            items,
+        } = part
+        else {
+            out.push(part);
+            continue;
+        };
+        let mut items: Vec<Vec<Part>> = items.into_iter().map(unwrap_newtypes).collect();
+        if let (Some(Part::Text(text)), '(', 1) = (out.last_mut(), open, items.len()) {
+            let name = TRANSPARENT.iter().find(|name| {
+                text.strip_suffix(**name).is_some_and(|before| {
+                    !before.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                })
+            });
+            if let Some(name) = name {
+                text.truncate(text.len() - name.len());
+                let text = text.trim_end().to_string();
+                out.pop();
+                if !text.is_empty() {
+                    out.push(Part::Text(text + " "));
+                }
+                out.extend(items.pop().unwrap_or_default());
+                continue;
+            }
+        }
+        out.push(Part::Group {
+            open,
+            close,
+            padded,
+            trailing_comma,
+            items,
+        });
+    }
+    out
+}
+
+fn flat(parts: &[Part], out: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Group {
+                open,
+                close,
+                padded,
+                trailing_comma,
+                items,
+            } => {
+                out.push(*open);
+                let pad = *padded && !items.is_empty();
+                if pad {
+                    out.push(' ');
+                }
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    flat(item, out);
+                }
+                if *trailing_comma {
+                    out.push(',');
+                }
+                if pad {
+                    out.push(' ');
+                }
+                out.push(*close);
+            }
+        }
+    }
+}
+
+/// Write `parts` starting at the end of `out`, on a line indented by
+/// `indent`.
+fn layout(parts: &[Part], indent: usize, out: &mut String) {
+    for part in parts {
+        let line_start = out.rfind('\n').map_or(0, |at| at + 1);
+        let column = out[line_start..].chars().count();
+        let mut single = String::new();
+        flat(std::slice::from_ref(part), &mut single);
+        let Part::Group {
+            open, close, items, ..
+        } = part
+        else {
+            out.push_str(&single);
+            continue;
+        };
+        if items.is_empty() || column + single.chars().count() <= WIDTH {
+            out.push_str(&single);
+            continue;
+        }
+        out.push(*open);
+        out.push('\n');
+        for item in items {
+            out.push_str(&" ".repeat(indent + 4));
+            layout(item, indent + 4, out);
+            out.push_str(",\n");
+        }
+        out.push_str(&" ".repeat(indent));
+        out.push(*close);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dict, List};
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Point {
+        x: i64,
+        label: Str,
+    }
+
+    #[test]
+    fn repr_unwraps_newtypes() {
+        let nested = List(vec![List(vec![Str::from("a")]), List(vec![])]);
+        assert_eq!(repr(&nested), "[[\"a\"], []]");
+        let point = Point {
+            x: 1,
+            label: Str::from("origin"),
+        };
+        assert_eq!(repr(&point), "Point { x: 1, label: \"origin\" }");
+        assert_eq!(repr(&(Str::from("(,)"), 'x')), "(\"(,)\", 'x')");
+        assert_eq!(repr(&(1,)), "(1,)");
+        assert_eq!(repr(&Some(MyList(1))), "Some(MyList(1))");
+    }
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct MyList(i64);
+
+    #[test]
+    fn pformat_breaks_what_does_not_fit() {
+        assert_eq!(pformat(&List(vec![1, 2, 3])), "[1, 2, 3]");
+        let long: Vec<Str> = (0..4)
+            .map(|i| Str::from(format!("a fairly long string number {i}")))
+            .collect();
+        let dict = Dict::from([
+            (Str::from("short"), List(vec![Str::from("x")])),
+            (Str::from("long"), List(long)),
+        ]);
+        assert_eq!(
+            pformat(&dict),
+            "{\n    \"short\": [\"x\"],\n    \"long\": [\n        \"a fairly long string number 0\",\n        \"a fairly long string number 1\",\n        \"a fairly long string number 2\",\n        \"a fairly long string number 3\",\n    ],\n}"
+        );
+    }
+}
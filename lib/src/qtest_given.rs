@@ -0,0 +1,422 @@
+//! Property-based testing for `Qtest` — the runtime behind `@given(...)`.
+//!
+//! ```text
+//! @given(int, Str)
+//! def test_roundtrip(n: int, s: Str):
+//!     ...
+//! ```
+//!
+//! The harness runs the test body through [`Qtest::given`] with the
+//! decorator's types as a tuple. Each case draws a fresh value from a seeded
+//! generator, with sizes growing over the run. On failure the input is
+//! shrunk towards a minimal counterexample, which is reported together with
+//! the seed so the run can be replayed with `QUICHE_PROPERTY_SEED`.
+
+use super::qtest_module::catch_panic;
+use crate::{List, Qtest, Str};
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable fixing the generator seed, to replay a failure.
+pub const PROPERTY_SEED_ENV: &str = "QUICHE_PROPERTY_SEED";
+
+/// Environment variable overriding the number of cases per property.
+pub const PROPERTY_CASES_ENV: &str = "QUICHE_PROPERTY_CASES";
+
+const DEFAULT_CASES: u64 = 100;
+const MAX_SIZE: u64 = 100;
+/// Upper bound on accepted shrink steps, in case a shrinker cycles.
+const MAX_SHRINKS: usize = 1000;
+
+/// Deterministic xorshift64* generator handed to [`Arbitrary::generate`].
+#[derive(Clone, Debug)]
+pub struct Gen {
+    state: u64,
+    size: u64,
+}
+
+impl Gen {
+    pub fn new(seed: u64, size: u64) -> Self {
+        Gen {
+            // xorshift has a fixed point at zero
+            state: seed.max(1),
+            size,
+        }
+    }
+
+    /// Current size hint: collection lengths and number magnitudes scale
+    /// with it.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform value in `0..=bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        match bound.checked_add(1) {
+            Some(n) => self.next_u64() % n,
+            None => self.next_u64(),
+        }
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+}
+
+/// Types `@given` can generate and shrink.
+pub trait Arbitrary: Clone + Debug + Sized {
+    fn generate(g: &mut Gen) -> Self;
+
+    /// Simpler candidate values, most aggressive first. Empty when the value
+    /// is already minimal.
+    fn shrink(&self) -> Vec<Self> {
+        Vec::new()
+    }
+}
+
+macro_rules! arbitrary_int {
+    ($($t:ty),*) => {$(
+        impl Arbitrary for $t {
+            fn generate(g: &mut Gen) -> Self {
+                // Mostly small values scaled by size, occasionally extremes.
+                match g.below(19) {
+                    0 => <$t>::MIN,
+                    1 => <$t>::MAX,
+                    _ => {
+                        let magnitude = g.below(g.size()) as i128;
+                        let value = if g.bool() { -magnitude } else { magnitude };
+                        value.clamp(<$t>::MIN as i128, <$t>::MAX as i128) as $t
+                    }
+                }
+            }
+
+            fn shrink(&self) -> Vec<Self> {
+                let x = *self;
+                if x == 0 {
+                    return Vec::new();
+                }
+                let mut out = vec![0 as $t];
+                let half = x / 2;
+                if half != 0 {
+                    out.push(half);
+                }
+                #[allow(unused_comparisons)]
+                let step = if x < 0 { x + 1 } else { x - 1 };
+                if !out.contains(&step) {
+                    out.push(step);
+                }
+                out
+            }
+        }
+    )*};
+}
+
+arbitrary_int!(i8, i16, i32, i64, u8, u16, u32, u64, usize, isize);
+
+impl Arbitrary for f64 {
+    fn generate(g: &mut Gen) -> Self {
+        let whole = g.below(g.size()) as f64;
+        let frac = (g.next_u64() % 1000) as f64 / 1000.0;
+        let value = whole + frac;
+        if g.bool() { -value } else { value }
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        let x = *self;
+        if x == 0.0 || !x.is_finite() {
+            return Vec::new();
+        }
+        let mut out = vec![0.0];
+        if x.trunc() != x && x.trunc() != 0.0 {
+            out.push(x.trunc());
+        }
+        out
+    }
+}
+
+impl Arbitrary for bool {
+    fn generate(g: &mut Gen) -> Self {
+        g.bool()
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        if *self { vec![false] } else { Vec::new() }
+    }
+}
+
+impl Arbitrary for char {
+    fn generate(g: &mut Gen) -> Self {
+        // Printable ASCII most of the time, some non-ASCII for coverage.
+        if g.below(9) == 0 {
+            ['é', 'ß', 'λ', '中', '🙂'][g.below(4) as usize]
+        } else {
+            char::from(b' ' + g.below(94) as u8)
+        }
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        if *self == 'a' { Vec::new() } else { vec!['a'] }
+    }
+}
+
+impl Arbitrary for String {
+    fn generate(g: &mut Gen) -> Self {
+        let len = g.below(g.size().min(32));
+        (0..len).map(|_| char::generate(g)).collect()
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        let chars: Vec<char> = self.chars().collect();
+        shrink_vec(&chars)
+            .into_iter()
+            .map(|cs| cs.into_iter().collect())
+            .collect()
+    }
+}
+
+impl Arbitrary for Str {
+    fn generate(g: &mut Gen) -> Self {
+        Str::from(String::generate(g))
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        self.to_string()
+            .shrink()
+            .into_iter()
+            .map(Str::from)
+            .collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Vec<T> {
+    fn generate(g: &mut Gen) -> Self {
+        let len = g.below(g.size().min(32));
+        (0..len).map(|_| T::generate(g)).collect()
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        shrink_vec(self)
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for List<T> {
+    fn generate(g: &mut Gen) -> Self {
+        List::from(Vec::generate(g))
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        self.0.shrink().into_iter().map(List::from).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Option<T> {
+    fn generate(g: &mut Gen) -> Self {
+        if g.below(4) == 0 {
+            None
+        } else {
+            Some(T::generate(g))
+        }
+    }
+
+    fn shrink(&self) -> Vec<Self> {
+        match self {
+            None => Vec::new(),
+            Some(x) => std::iter::once(None)
+                .chain(x.shrink().into_iter().map(Some))
+                .collect(),
+        }
+    }
+}
+
+/// Empty, then halves, then single removals, then element-wise shrinks.
+fn shrink_vec<T: Arbitrary>(items: &[T]) -> Vec<Vec<T>> {
+    if items.is_empty() {
+        return Vec::new();
+    }
+    let mut out = vec![Vec::new()];
+    let half = items.len() / 2;
+    if half > 0 {
+        out.push(items[..half].to_vec());
+        out.push(items[half..].to_vec());
+    }
+    for i in 0..items.len() {
+        let mut removed = items.to_vec();
+        removed.remove(i);
+        out.push(removed);
+    }
+    for (i, item) in items.iter().enumerate() {
+        for smaller in item.shrink() {
+            let mut replaced = items.to_vec();
+            replaced[i] = smaller;
+            out.push(replaced);
+        }
+    }
+    out
+}
+
+macro_rules! arbitrary_tuple {
+    ($(($($t:ident $i:tt),+)),*) => {$(
+        impl<$($t: Arbitrary),+> Arbitrary for ($($t,)+) {
+            fn generate(g: &mut Gen) -> Self {
+                ($($t::generate(g),)+)
+            }
+
+            fn shrink(&self) -> Vec<Self> {
+                let mut out = Vec::new();
+                $(
+                    for smaller in self.$i.shrink() {
+                        let mut next = self.clone();
+                        next.$i = smaller;
+                        out.push(next);
+                    }
+                )+
+                out
+            }
+        }
+    )*};
+}
+
+arbitrary_tuple!(
+    (A 0),
+    (A 0, B 1),
+    (A 0, B 1, C 2),
+    (A 0, B 1, C 2, D 3),
+    (A 0, B 1, C 2, D 3, E 4),
+    (A 0, B 1, C 2, D 3, E 4, F 5)
+);
+
+impl Qtest {
+    /// Check `property` against generated inputs. The harness passes the
+    /// `@given(...)` types as a tuple `T`.
+    ///
+    /// Panics with the shrunk counterexample and the seed on failure.
+    pub fn given<T: Arbitrary>(property: impl Fn(T)) {
+        let seed = std::env::var(PROPERTY_SEED_ENV)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or(1)
+            });
+        let cases = std::env::var(PROPERTY_CASES_ENV)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_CASES);
+        if let Err(message) = check(&property, seed, cases) {
+            panic!("{message}");
+        }
+    }
+}
+
+/// Run `cases` generated inputs through `property`, shrinking the first
+/// failure. Returns the failure report.
+fn check<T: Arbitrary>(property: &impl Fn(T), seed: u64, cases: u64) -> Result<(), String> {
+    let mut g = Gen::new(seed, 0);
+    for case in 0..cases {
+        g.size = 1 + case * MAX_SIZE / cases;
+        let input = T::generate(&mut g);
+        let Err(message) = catch_panic(|| property(input.clone())) else {
+            continue;
+        };
+        let (input, message, shrinks) = shrink_failure(property, input, message);
+        return Err(format!(
+            "property falsified after {} case(s), {} shrink(s)\n      input: {:?}\n      error: {}\n      seed: {} (set {}={} to replay)",
+            case + 1,
+            shrinks,
+            input,
+            message,
+            seed,
+            PROPERTY_SEED_ENV,
+            seed
+        ));
+    }
+    Ok(())
+}
+
+/// Greedily replace `input` with its first still-failing shrink candidate
+/// until none fails.
+fn shrink_failure<T: Arbitrary>(
+    property: &impl Fn(T),
+    mut input: T,
+    mut message: String,
+) -> (T, String, usize) {
+    let mut shrinks = 0;
+    'outer: while shrinks < MAX_SHRINKS {
+        for candidate in input.shrink() {
+            if let Err(m) = catch_panic(|| property(candidate.clone())) {
+                input = candidate;
+                message = m;
+                shrinks += 1;
+                continue 'outer;
+            }
+        }
+        break;
+    }
+    (input, message, shrinks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passing_property_runs_all_cases() {
+        let count = std::cell::Cell::new(0);
+        let result = check(
+            &|(a, b): (i64, i64)| {
+                count.set(count.get() + 1);
+                assert_eq!(a.wrapping_add(b), b.wrapping_add(a));
+            },
+            7,
+            50,
+        );
+        assert!(result.is_ok());
+        assert_eq!(count.get(), 50);
+    }
+
+    #[test]
+    fn failing_int_shrinks_to_boundary() {
+        let err = check(&|(n,): (i64,)| assert!(n < 10), 42, 200).unwrap_err();
+        assert!(err.contains("input: (10,)"), "{err}");
+        assert!(err.contains("seed: 42"), "{err}");
+    }
+
+    #[test]
+    fn failing_vec_shrinks_to_single_element() {
+        let err = check(
+            &|(v,): (Vec<i64>,)| assert!(v.iter().all(|x| *x != 3 && *x < 5)),
+            3,
+            500,
+        )
+        .unwrap_err();
+        assert!(
+            err.contains("input: ([5],)") || err.contains("input: ([3],)"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn failing_str_shrinks_to_minimal() {
+        let err = check(&|(s,): (Str,)| assert!(s.chars().count() < 2), 9, 200).unwrap_err();
+        assert!(err.contains("input: (Str(\"aa\"),)"), "{err}");
+    }
+
+    #[test]
+    fn generation_is_deterministic_per_seed() {
+        let mut a = Gen::new(5, 50);
+        let mut b = Gen::new(5, 50);
+        let xs: Vec<(i64, Str)> = (0..10).map(|_| Arbitrary::generate(&mut a)).collect();
+        let ys: Vec<(i64, Str)> = (0..10).map(|_| Arbitrary::generate(&mut b)).collect();
+        assert_eq!(xs, ys);
+    }
+}
@@ -0,0 +1,932 @@
+//! Quiche `Qtest` module — runtime support for generated test harnesses.
+//!
+//! `quiche <file.q> --test` replaces the user's `main` with a harness that
+//! calls `Qtest.run(name, || ...)` for every discovered test and finishes
+//! with `Qtest.finish()`. Results are collected into a [`TestSummary`].
+//!
+//! Snapshot assertions (`assert_snapshot(name, value)` in Quiche) store
+//! expected output under `__snapshots__/<test file>/<name>.snap` next to the
+//! test file. Set `QUICHE_UPDATE_SNAPSHOTS=1` (or pass `--update-snapshots`
+//! to `quiche test`) to rewrite them.
+//!
+//! `Qtest.mock` / `Qtest.patch_env` changes are scoped to the running test:
+//! `Qtest.run` drops registered mocks and restores patched environment
+//! variables once the test body returns or panics.
+//!
+//! With `QUICHE_TEST_FORMAT=junit|tap` (set by `quiche test --format`),
+//! `Qtest.finish` also writes the file's results as a JUnit `<testsuite>` or
+//! TAP fragment to `$QUICHE_TEST_REPORT_DIR/<stem>.xml|.tap`.
+//!
+//! Under `--coverage`, the compiler probes every statement with `Qtest.hit`
+//! and `Qtest.finish` writes an lcov record for the test file to
+//! `$QUICHE_COVERAGE_DIR/<stem>.info` (default `target/qcov`).
+
+use crate::Str;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+
+/// Environment variable that switches `assert_snapshot` into update mode.
+pub const UPDATE_SNAPSHOTS_ENV: &str = "QUICHE_UPDATE_SNAPSHOTS";
+
+/// Environment variable selecting a machine-readable report: `junit` or `tap`.
+pub const TEST_FORMAT_ENV: &str = "QUICHE_TEST_FORMAT";
+
+/// Environment variable naming the directory for per-file test reports.
+pub const TEST_REPORT_DIR_ENV: &str = "QUICHE_TEST_REPORT_DIR";
+
+/// Environment variable naming the directory for per-file lcov records.
+pub const COVERAGE_DIR_ENV: &str = "QUICHE_COVERAGE_DIR";
+
+/// Hit counts per instrumented source line. Shared across threads so probes
+/// in spawned work are still counted.
+static COVERAGE: Mutex<Option<BTreeMap<i64, u64>>> = Mutex::new(None);
+
+/// Outcome of a single test function.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestOutcome {
+    Passed,
+    /// The test panicked; carries the panic message.
+    Failed(String),
+    /// `@skip` / `@skipif` — not run; carries the reason.
+    Skipped(String),
+    /// `@xfail` test that failed as expected; carries the reason.
+    XFailed(String),
+    /// `@xfail` test that unexpectedly passed; carries the reason.
+    XPassed(String),
+    /// `setup_module` / `teardown_module` or a fixture panicked; carries the
+    /// message.
+    Error(String),
+}
+
+impl TestOutcome {
+    /// Summary section and message/reason for non-passing outcomes.
+    fn detail(&self) -> Option<(&'static str, &str)> {
+        match self {
+            TestOutcome::Passed => None,
+            TestOutcome::Failed(message) => Some(("failures", message)),
+            TestOutcome::Skipped(reason) => Some(("skipped", reason)),
+            TestOutcome::XFailed(reason) => Some(("xfailed", reason)),
+            TestOutcome::XPassed(reason) => Some(("xpassed", reason)),
+            TestOutcome::Error(message) => Some(("errors", message)),
+        }
+    }
+}
+
+/// Result of a single test function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    pub duration: Duration,
+}
+
+/// All results recorded by `Qtest.run` in the current test binary.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestSummary {
+    pub results: Vec<TestResult>,
+}
+
+impl TestSummary {
+    pub fn passed(&self) -> usize {
+        self.count(|o| matches!(o, TestOutcome::Passed))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, TestOutcome::Failed(_)))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|o| matches!(o, TestOutcome::Skipped(_)))
+    }
+
+    pub fn xfailed(&self) -> usize {
+        self.count(|o| matches!(o, TestOutcome::XFailed(_)))
+    }
+
+    pub fn xpassed(&self) -> usize {
+        self.count(|o| matches!(o, TestOutcome::XPassed(_)))
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(|o| matches!(o, TestOutcome::Error(_)))
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0 && self.errors() == 0
+    }
+
+    fn count(&self, f: impl Fn(&TestOutcome) -> bool) -> usize {
+        self.results.iter().filter(|r| f(&r.outcome)).count()
+    }
+
+    /// Render as a JUnit `<testsuite>` element named after the test file.
+    pub fn to_junit(&self, suite: &str) -> String {
+        let classname = Path::new(suite)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let total: Duration = self.results.iter().map(|r| r.duration).sum();
+        let mut out = format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(suite),
+            self.results.len(),
+            self.failed(),
+            self.errors(),
+            self.skipped() + self.xfailed(),
+            total.as_secs_f64()
+        );
+        for result in &self.results {
+            let open = format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                xml_escape(&result.name),
+                xml_escape(&classname),
+                result.duration.as_secs_f64()
+            );
+            match &result.outcome {
+                TestOutcome::Passed | TestOutcome::XPassed(_) => {
+                    out.push_str(&format!("{open}/>\n"))
+                }
+                TestOutcome::Failed(message) => out.push_str(&format!(
+                    "{open}>\n      <failure message=\"{0}\">{0}</failure>\n    </testcase>\n",
+                    xml_escape(message)
+                )),
+                TestOutcome::Error(message) => out.push_str(&format!(
+                    "{open}>\n      <error message=\"{0}\">{0}</error>\n    </testcase>\n",
+                    xml_escape(message)
+                )),
+                TestOutcome::Skipped(reason) => out.push_str(&format!(
+                    "{open}>\n      <skipped message=\"{}\"/>\n    </testcase>\n",
+                    xml_escape(reason)
+                )),
+                TestOutcome::XFailed(reason) => out.push_str(&format!(
+                    "{open}>\n      <skipped message=\"xfail: {}\"/>\n    </testcase>\n",
+                    xml_escape(reason)
+                )),
+            }
+        }
+        out.push_str("  </testsuite>\n");
+        out
+    }
+
+    /// Render as a TAP (version 13) stream.
+    pub fn to_tap(&self) -> String {
+        let mut out = format!("TAP version 13\n1..{}\n", self.results.len());
+        for (i, result) in self.results.iter().enumerate() {
+            let (n, name) = (i + 1, &result.name);
+            match &result.outcome {
+                TestOutcome::Passed => out.push_str(&format!("ok {n} - {name}\n")),
+                TestOutcome::Skipped(reason) => {
+                    out.push_str(&format!("ok {n} - {name} # SKIP {reason}\n"))
+                }
+                TestOutcome::XFailed(reason) => {
+                    out.push_str(&format!("not ok {n} - {name} # TODO {reason}\n"))
+                }
+                TestOutcome::XPassed(reason) => {
+                    out.push_str(&format!("ok {n} - {name} # TODO {reason}\n"))
+                }
+                TestOutcome::Failed(message) | TestOutcome::Error(message) => {
+                    out.push_str(&format!("not ok {n} - {name}\n"));
+                    out.push_str("  ---\n");
+                    out.push_str(&format!("  message: {:?}\n", message));
+                    out.push_str("  ...\n");
+                }
+            }
+        }
+        out
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+thread_local! {
+    static SUMMARY: RefCell<TestSummary> = RefCell::new(TestSummary::default());
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+    static SOURCE_FILE: RefCell<Option<String>> = const { RefCell::new(None) };
+    static SETUP_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+    static FIXTURE_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+    static MOCKS: RefCell<HashMap<String, Box<dyn Any>>> = RefCell::new(HashMap::new());
+    /// Patched env vars with their previous values, in patch order.
+    static ENV_PATCHES: RefCell<Vec<(String, Option<String>)>> = const { RefCell::new(Vec::new()) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Install a panic hook that records panics raised inside `Qtest.run`
+/// instead of printing them, and defers to the previous hook otherwise.
+fn install_panic_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CAPTURING.with(|c| c.get()) {
+                let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+                    s.to_string()
+                } else if let Some(s) = info.payload().downcast_ref::<String>() {
+                    s.clone()
+                } else {
+                    "test panicked".to_string()
+                };
+                let message = match info.location() {
+                    Some(loc) => format!("{message} ({}:{})", loc.file(), loc.line()),
+                    None => message,
+                };
+                LAST_PANIC.with(|p| *p.borrow_mut() = Some(message));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+/// Static module for test execution, used as `Qtest.run(...)` by generated harnesses.
+pub struct Qtest;
+
+impl Qtest {
+    /// Run one test body, catching panics, and record the outcome.
+    ///
+    /// Returns `true` if the test passed.
+    pub fn run(name: Str, f: impl FnOnce()) -> bool {
+        let (result, duration) = execute(f);
+        let outcome = match (result, take_fixture_error()) {
+            (_, Some(error)) => TestOutcome::Error(error),
+            (Ok(()), None) => TestOutcome::Passed,
+            (Err(message), None) => TestOutcome::Failed(message),
+        };
+        record(&name, outcome, duration)
+    }
+
+    /// Record a test as skipped without running it (`@skip` / `@skipif`).
+    pub fn skip(name: Str, reason: Str) {
+        record(
+            &name,
+            TestOutcome::Skipped(reason.to_string()),
+            Duration::ZERO,
+        );
+    }
+
+    /// Run an `@xfail` test: failing is expected. An unexpected pass is
+    /// reported as XPASS, and counts as a failure when `strict` is set.
+    ///
+    /// Returns `true` unless the test strictly xpassed.
+    pub fn run_xfail(name: Str, reason: Str, strict: bool, f: impl FnOnce()) -> bool {
+        let (result, duration) = execute(f);
+        let outcome = match (result, take_fixture_error()) {
+            (_, Some(error)) => TestOutcome::Error(error),
+            (Err(_), None) => TestOutcome::XFailed(reason.to_string()),
+            (Ok(()), None) if strict => TestOutcome::Failed(format!("XPASS(strict): {}", &*reason)),
+            (Ok(()), None) => TestOutcome::XPassed(reason.to_string()),
+        };
+        record(&name, outcome, duration)
+    }
+
+    /// Run the module's `setup_module()` hook. A panic is recorded as an
+    /// error against the whole module, and every test is then reported via
+    /// [`Qtest::setup_failed`] instead of being run.
+    ///
+    /// Returns `true` if setup succeeded.
+    pub fn setup_module(f: impl FnOnce()) -> bool {
+        let (result, duration) = execute(f);
+        match result {
+            Ok(()) => true,
+            Err(message) => {
+                SETUP_ERROR.with(|e| *e.borrow_mut() = Some(message.clone()));
+                record("setup_module", TestOutcome::Error(message), duration);
+                false
+            }
+        }
+    }
+
+    /// Record a test as errored because `setup_module()` failed.
+    pub fn setup_failed(name: Str) {
+        let message = SETUP_ERROR
+            .with(|e| e.borrow().clone())
+            .unwrap_or_else(|| "setup failed".to_string());
+        record(
+            &name,
+            TestOutcome::Error(format!("setup_module failed: {message}")),
+            Duration::ZERO,
+        );
+    }
+
+    /// Run the module's `teardown_module()` hook; a panic is recorded as an
+    /// error against the whole module.
+    pub fn teardown_module(f: impl FnOnce()) {
+        if let (Err(message), duration) = execute(f) {
+            record("teardown_module", TestOutcome::Error(message), duration);
+        }
+    }
+
+    /// Build the function-scoped fixture `name` inside a running test. A
+    /// panic ends the test, which is reported as an error rather than a
+    /// failure.
+    pub fn fixture<T>(name: Str, f: impl FnOnce() -> T) -> T {
+        let mut value = None;
+        let result = catch_panic(|| value = Some(f()));
+        match (value, result) {
+            (Some(value), _) => value,
+            (None, result) => {
+                let message = result.err().unwrap_or_default();
+                let error = format!("fixture '{}' failed: {message}", &*name);
+                FIXTURE_ERROR.with(|e| *e.borrow_mut() = Some(error));
+                panic::resume_unwind(Box::new(()))
+            }
+        }
+    }
+
+    /// Build the module-scoped fixture `name` before the first test. If it
+    /// panics, every test in `tests` (comma-separated) is reported as an
+    /// error and the harness finishes without running them.
+    pub fn module_fixture<T>(name: Str, tests: Str, f: impl FnOnce() -> T) -> T {
+        let mut value = None;
+        let (result, duration) = execute(|| value = Some(f()));
+        if let Some(value) = value {
+            return value;
+        }
+        let message = result.err().unwrap_or_default();
+        let error = format!("fixture '{}' failed: {message}", &*name);
+        for test in tests.split(',').filter(|test| !test.is_empty()) {
+            record(test, TestOutcome::Error(error.clone()), duration);
+        }
+        Self::finish();
+        std::process::exit(1)
+    }
+
+    /// Run a test body whose fixtures still need tearing down, holding on to
+    /// its panic; [`Qtest::resume`] re-raises it once teardown has run.
+    pub fn attempt(f: impl FnOnce()) -> std::thread::Result<()> {
+        panic::catch_unwind(AssertUnwindSafe(f))
+    }
+
+    /// Re-raise the panic held by [`Qtest::attempt`], if there was one.
+    pub fn resume(outcome: std::thread::Result<()>) {
+        if let Err(payload) = outcome {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    /// Record the `.q` file under test; snapshots are stored relative to it.
+    pub fn set_source(path: Str) {
+        SOURCE_FILE.with(|s| *s.borrow_mut() = Some(path.to_string()));
+    }
+
+    /// Compare `value` against the stored snapshot `name`, writing it if it
+    /// does not exist yet or if update mode is on. Panics with a line diff
+    /// on mismatch.
+    pub fn assert_snapshot(name: Str, value: impl Display) {
+        let path = snapshot_path(&name);
+        let update = std::env::var(UPDATE_SNAPSHOTS_ENV).is_ok_and(|v| !v.is_empty() && v != "0");
+        if let Err(message) = check_snapshot(&path, &value.to_string(), update) {
+            panic!("{message}");
+        }
+    }
+
+    /// Register the instrumented lines of the file under test (comma
+    /// separated), so lines that never run are reported with zero hits.
+    pub fn track_lines(lines: Str) {
+        let mut coverage = COVERAGE.lock().unwrap_or_else(|e| e.into_inner());
+        let map = coverage.get_or_insert_with(BTreeMap::new);
+        for line in lines
+            .split(',')
+            .filter_map(|l| l.trim().parse::<i64>().ok())
+        {
+            map.entry(line).or_insert(0);
+        }
+    }
+
+    /// Coverage probe: count one execution of `line`.
+    pub fn hit(line: i64) {
+        let mut coverage = COVERAGE.lock().unwrap_or_else(|e| e.into_inner());
+        *coverage
+            .get_or_insert_with(BTreeMap::new)
+            .entry(line)
+            .or_insert(0) += 1;
+    }
+
+    /// Replace the module function `name` with `replacement` for the rest of
+    /// the current test. Emitted by the compiler for `mock(f, replacement)`.
+    pub fn mock<F: Copy + 'static>(name: &str, replacement: F) {
+        MOCKS.with(|m| {
+            m.borrow_mut()
+                .insert(name.to_string(), Box::new(replacement))
+        });
+    }
+
+    /// The active replacement for `name`, if one of matching type is mocked.
+    pub fn mocked<F: Copy + 'static>(name: &str) -> Option<F> {
+        MOCKS.with(|m| m.borrow().get(name)?.downcast_ref::<F>().copied())
+    }
+
+    /// Set an environment variable for the rest of the current test.
+    pub fn patch_env(key: Str, value: Str) {
+        let previous = std::env::var(&*key).ok();
+        ENV_PATCHES.with(|p| p.borrow_mut().push((key.to_string(), previous)));
+        // SAFETY: harnesses run their tests one at a time on the main
+        // thread, so no other thread reads the environment while a test
+        // patches it (a test that starts threads must patch before it does).
+        unsafe {
+            std::env::set_var(&*key, &*value);
+        }
+    }
+
+    /// Snapshot of the results recorded so far.
+    pub fn summary() -> TestSummary {
+        SUMMARY.with(|s| s.borrow().clone())
+    }
+
+    /// Print the failure details and summary line, then exit non-zero if
+    /// any test failed.
+    pub fn finish() {
+        let summary = Self::summary();
+        for title in ["errors", "failures", "skipped", "xfailed", "xpassed"] {
+            let entries: Vec<(&str, &str)> = summary
+                .results
+                .iter()
+                .filter_map(|r| match r.outcome.detail() {
+                    Some((section, text)) if section == title => Some((r.name.as_str(), text)),
+                    _ => None,
+                })
+                .collect();
+            if entries.is_empty() {
+                continue;
+            }
+            println!("\n{title}:");
+            for (name, text) in entries {
+                if text.is_empty() {
+                    println!("    {name}");
+                } else {
+                    println!("    {name}: {text}");
+                }
+            }
+        }
+
+        write_coverage();
+        write_report(&summary);
+
+        println!(
+            "\ntest result: {}. {} passed; {} failed; {} errors; {} skipped; {} xfailed; {} xpassed",
+            if summary.is_success() { "ok" } else { "FAILED" },
+            summary.passed(),
+            summary.failed(),
+            summary.errors(),
+            summary.skipped(),
+            summary.xfailed(),
+            summary.xpassed()
+        );
+
+        if !summary.is_success() {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Run a test body under the capturing panic hook, then drop its mocks and
+/// env patches. Returns the panic message on failure.
+fn execute(f: impl FnOnce()) -> (Result<(), String>, Duration) {
+    let start = Instant::now();
+    let result = catch_panic(f);
+    let duration = start.elapsed();
+    reset_test_state();
+    (result, duration)
+}
+
+/// Run `f` under the capturing panic hook and return its panic message, if
+/// any. Nests: `Qtest.given` uses it for each case inside a running test.
+pub(crate) fn catch_panic(f: impl FnOnce()) -> Result<(), String> {
+    install_panic_hook();
+    let outer = CAPTURING.with(|c| c.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CAPTURING.with(|c| c.set(outer));
+    result.map_err(|_| {
+        LAST_PANIC
+            .with(|p| p.borrow_mut().take())
+            .unwrap_or_else(|| "test panicked".to_string())
+    })
+}
+
+/// The error left by a failed [`Qtest::fixture`] in the test just run.
+fn take_fixture_error() -> Option<String> {
+    FIXTURE_ERROR.with(|e| e.borrow_mut().take())
+}
+
+/// Print the per-test status line and append the result to the summary.
+/// Returns `false` only for failures and errors.
+fn record(name: &str, outcome: TestOutcome, duration: Duration) -> bool {
+    let status = match &outcome {
+        TestOutcome::Passed => "ok".to_string(),
+        TestOutcome::Failed(_) => "FAILED".to_string(),
+        TestOutcome::Error(_) => "ERROR".to_string(),
+        TestOutcome::Skipped(reason) => with_reason("skipped", reason),
+        TestOutcome::XFailed(reason) => with_reason("xfail", reason),
+        TestOutcome::XPassed(reason) => with_reason("XPASS", reason),
+    };
+    println!("test {name} ... {status}");
+    let ok = !matches!(outcome, TestOutcome::Failed(_) | TestOutcome::Error(_));
+    SUMMARY.with(|s| {
+        s.borrow_mut().results.push(TestResult {
+            name: name.to_string(),
+            outcome,
+            duration,
+        })
+    });
+    ok
+}
+
+fn with_reason(label: &str, reason: &str) -> String {
+    if reason.is_empty() {
+        label.to_string()
+    } else {
+        format!("{label} ({reason})")
+    }
+}
+
+/// Drop the current test's mocks and undo its env patches, newest first.
+fn reset_test_state() {
+    MOCKS.with(|m| m.borrow_mut().clear());
+    let patches = ENV_PATCHES.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    for (key, previous) in patches.into_iter().rev() {
+        // SAFETY: as in `Qtest::patch_env` — this runs on the harness
+        // thread between tests, when no test threads are left reading it.
+        unsafe {
+            match previous {
+                Some(value) => std::env::set_var(&key, value),
+                None => std::env::remove_var(&key),
+            }
+        }
+    }
+}
+
+/// Write this file's results in the format requested by `QUICHE_TEST_FORMAT`.
+fn write_report(summary: &TestSummary) {
+    let Ok(format) = std::env::var(TEST_FORMAT_ENV) else {
+        return;
+    };
+    let source = SOURCE_FILE
+        .with(|s| s.borrow().clone())
+        .unwrap_or_else(|| "unknown.q".to_string());
+    let (text, ext) = match format.as_str() {
+        "junit" => (summary.to_junit(&source), "xml"),
+        "tap" => (summary.to_tap(), "tap"),
+        other => {
+            eprintln!("warning: unknown {TEST_FORMAT_ENV} '{other}' (expected junit or tap)");
+            return;
+        }
+    };
+    let dir = std::env::var(TEST_REPORT_DIR_ENV).unwrap_or_else(|_| "target/qtest".to_string());
+    let stem = Path::new(&source)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = Path::new(&dir).join(format!("{stem}.{ext}"));
+    let result = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, text));
+    if let Err(e) = result {
+        eprintln!(
+            "warning: cannot write test report to {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Render an lcov record for `source` from per-line hit counts.
+fn lcov_record(source: &str, hits: &BTreeMap<i64, u64>) -> String {
+    let mut out = format!("TN:\nSF:{source}\n");
+    for (line, count) in hits {
+        out.push_str(&format!("DA:{line},{count}\n"));
+    }
+    let found = hits.len();
+    let hit = hits.values().filter(|c| **c > 0).count();
+    out.push_str(&format!("LF:{found}\nLH:{hit}\nend_of_record\n"));
+    out
+}
+
+/// Write the lcov record for the file under test and print a one-line
+/// summary. No-op unless coverage probes were registered.
+fn write_coverage() {
+    let coverage = COVERAGE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(hits) = coverage.as_ref().filter(|h| !h.is_empty()) else {
+        return;
+    };
+    let source = SOURCE_FILE
+        .with(|s| s.borrow().clone())
+        .unwrap_or_else(|| "unknown.q".to_string());
+    let dir = std::env::var(COVERAGE_DIR_ENV).unwrap_or_else(|_| "target/qcov".to_string());
+    let stem = Path::new(&source)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = Path::new(&dir).join(format!("{stem}.info"));
+
+    let result = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&path, lcov_record(&source, hits)));
+    if let Err(e) = result {
+        eprintln!(
+            "warning: cannot write coverage to {}: {}",
+            path.display(),
+            e
+        );
+    }
+
+    let hit = hits.values().filter(|c| **c > 0).count();
+    println!(
+        "coverage: {} {}/{} lines ({:.1}%)",
+        source,
+        hit,
+        hits.len(),
+        100.0 * hit as f64 / hits.len() as f64
+    );
+}
+
+/// `<dir>/__snapshots__/<stem>/<name>.snap` for source file `<dir>/<stem>.q`,
+/// or `tests/__snapshots__/<name>.snap` when no source was recorded.
+fn snapshot_path(name: &str) -> PathBuf {
+    let file = format!("{name}.snap");
+    match SOURCE_FILE.with(|s| s.borrow().clone()) {
+        Some(source) => {
+            let source = Path::new(&source);
+            let dir = source.parent().unwrap_or_else(|| Path::new(""));
+            let stem = source
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            dir.join("__snapshots__").join(stem).join(file)
+        }
+        None => Path::new("tests").join("__snapshots__").join(file),
+    }
+}
+
+fn check_snapshot(path: &Path, actual: &str, update: bool) -> Result<(), String> {
+    let expected = std::fs::read_to_string(path).ok();
+    if !update && expected.as_deref() == Some(actual) {
+        return Ok(());
+    }
+    if update || expected.is_none() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("cannot create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(path, actual)
+            .map_err(|e| format!("cannot write snapshot {}: {}", path.display(), e))?;
+        if expected.as_deref() != Some(actual) {
+            eprintln!("snapshot written: {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let expected = expected.unwrap_or_default();
+    let mut diff = String::new();
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    for i in 0..old.len().max(new.len()) {
+        match (old.get(i), new.get(i)) {
+            (Some(a), Some(b)) if a == b => diff.push_str(&format!("  {a}\n")),
+            (a, b) => {
+                if let Some(a) = a {
+                    diff.push_str(&format!("- {a}\n"));
+                }
+                if let Some(b) = b {
+                    diff.push_str(&format!("+ {b}\n"));
+                }
+            }
+        }
+    }
+    Err(format!(
+        "snapshot mismatch: {}\n{}(rerun with --update-snapshots to accept)",
+        path.display(),
+        diff
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::str;
+
+    #[test]
+    fn qtest_run_records_pass() {
+        assert!(Qtest::run(str("test_ok"), || assert_eq!(1 + 1, 2)));
+        let summary = Qtest::summary();
+        assert_eq!(summary.passed(), 1);
+        assert!(summary.is_success());
+    }
+
+    #[test]
+    fn qtest_run_records_failure_message() {
+        assert!(!Qtest::run(str("test_bad"), || panic!("boom")));
+        let summary = Qtest::summary();
+        assert_eq!(summary.failed(), 1);
+        match &summary.results[0].outcome {
+            TestOutcome::Failed(message) => assert!(message.starts_with("boom")),
+            other => panic!("Expected failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn qtest_failed_fixture_is_a_test_error() {
+        assert!(!Qtest::run(str("test_db"), || {
+            let _: i64 = Qtest::fixture(str("db"), || panic!("no database"));
+            unreachable!("the test body runs after its fixtures");
+        }));
+        let summary = Qtest::summary();
+        assert_eq!(summary.errors(), 1);
+        match &summary.results[0].outcome {
+            TestOutcome::Error(message) => {
+                assert!(
+                    message.starts_with("fixture 'db' failed: no database"),
+                    "{message}"
+                )
+            }
+            other => panic!("Expected error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn qtest_attempt_runs_teardown_before_failing() {
+        let torn_down = Cell::new(false);
+        assert!(!Qtest::run(str("test_torn"), || {
+            let outcome = Qtest::attempt(|| panic!("boom"));
+            torn_down.set(true);
+            Qtest::resume(outcome);
+        }));
+        assert!(torn_down.get());
+        match &Qtest::summary().results[0].outcome {
+            TestOutcome::Failed(message) => assert!(message.starts_with("boom")),
+            other => panic!("Expected failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn snapshot_written_then_compared() {
+        let dir = std::env::temp_dir().join(format!("qtest-snap-{}", std::process::id()));
+        let path = dir.join("out.snap");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(check_snapshot(&path, "a\nb\n", false).is_ok());
+        assert!(check_snapshot(&path, "a\nb\n", false).is_ok());
+        let err = check_snapshot(&path, "a\nc\n", false).unwrap_err();
+        assert!(err.contains("- b") && err.contains("+ c"), "{err}");
+
+        assert!(check_snapshot(&path, "a\nc\n", true).is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nc\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn snapshot_path_relative_to_source() {
+        Qtest::set_source(str("tests/test_fmt.q"));
+        assert_eq!(
+            snapshot_path("header"),
+            Path::new("tests/__snapshots__/test_fmt/header.snap")
+        );
+    }
+
+    #[test]
+    fn lcov_record_counts_hit_lines() {
+        let hits = BTreeMap::from([(3, 2), (4, 0), (7, 1)]);
+        assert_eq!(
+            lcov_record("tests/test_x.q", &hits),
+            "TN:\nSF:tests/test_x.q\nDA:3,2\nDA:4,0\nDA:7,1\nLF:3\nLH:2\nend_of_record\n"
+        );
+    }
+
+    fn fake_greeting(_name: Str) -> Str {
+        str("mocked")
+    }
+
+    #[test]
+    fn mock_is_scoped_to_one_test() {
+        Qtest::run(str("test_mocked"), || {
+            Qtest::mock("greeting", fake_greeting as fn(_) -> _);
+            let mocked = Qtest::mocked::<fn(Str) -> Str>("greeting").unwrap();
+            assert_eq!(&*mocked(str("x")), "mocked");
+        });
+        assert!(Qtest::mocked::<fn(Str) -> Str>("greeting").is_none());
+        assert!(Qtest::summary().is_success());
+    }
+
+    #[test]
+    fn patch_env_restored_after_test() {
+        // `set_var` races with every other test thread reading the
+        // environment, so the check runs alone in a child test process.
+        let child = "QTEST_PATCH_ENV_CHILD";
+        if std::env::var_os(child).is_none() {
+            let name = module_path!().split_once("::").unwrap().1;
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", &format!("{name}::patch_env_restored_after_test")])
+                .env(child, "1")
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{stdout}");
+            assert!(stdout.contains("1 passed"), "{stdout}");
+            return;
+        }
+        let key = "QTEST_PATCH_ENV_CHECK";
+        Qtest::run(str("test_env"), || {
+            Qtest::patch_env(str(key), str("on"));
+            assert_eq!(std::env::var(key).as_deref(), Ok("on"));
+        });
+        assert!(std::env::var(key).is_err());
+        assert!(Qtest::summary().is_success());
+    }
+
+    fn sample_summary() -> TestSummary {
+        TestSummary {
+            results: vec![
+                TestResult {
+                    name: "test_ok".into(),
+                    outcome: TestOutcome::Passed,
+                    duration: Duration::from_millis(2),
+                },
+                TestResult {
+                    name: "test_bad".into(),
+                    outcome: TestOutcome::Failed("1 < 2 \"oops\"".into()),
+                    duration: Duration::ZERO,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn summary_to_junit() {
+        let xml = sample_summary().to_junit("tests/test_x.q");
+        assert!(xml.starts_with(
+            "  <testsuite name=\"tests/test_x.q\" tests=\"2\" failures=\"1\" errors=\"0\" skipped=\"0\" time=\"0.002\">"
+        ));
+        assert!(xml.contains("<testcase name=\"test_ok\" classname=\"test_x\" time=\"0.002\"/>"));
+        assert!(xml.contains("<failure message=\"1 &lt; 2 &quot;oops&quot;\">"));
+    }
+
+    #[test]
+    fn summary_to_tap() {
+        let tap = sample_summary().to_tap();
+        assert!(tap.starts_with("TAP version 13\n1..2\nok 1 - test_ok\nnot ok 2 - test_bad\n"));
+    }
+
+    #[test]
+    fn skip_and_xfail_outcomes() {
+        Qtest::skip(str("test_skipped"), str("not on CI"));
+        assert!(Qtest::run_xfail(
+            str("test_xfail"),
+            str("bug #1"),
+            false,
+            || { panic!("known bug") }
+        ));
+        assert!(Qtest::run_xfail(str("test_xpass"), str(""), false, || {}));
+        assert!(!Qtest::run_xfail(str("test_strict"), str(""), true, || {}));
+
+        let summary = Qtest::summary();
+        assert_eq!(
+            summary.results[0].outcome,
+            TestOutcome::Skipped("not on CI".into())
+        );
+        assert_eq!(
+            (summary.skipped(), summary.xfailed(), summary.xpassed()),
+            (1, 1, 1)
+        );
+        assert_eq!(summary.failed(), 1);
+
+        let tap = summary.to_tap();
+        assert!(tap.contains("ok 1 - test_skipped # SKIP not on CI\n"));
+        assert!(tap.contains("not ok 2 - test_xfail # TODO bug #1\n"));
+    }
+
+    #[test]
+    fn setup_module_failure_errors_every_test() {
+        assert!(!Qtest::setup_module(|| panic!("no database")));
+        Qtest::setup_failed(str("test_query"));
+        Qtest::teardown_module(|| panic!("still no database"));
+
+        let summary = Qtest::summary();
+        assert_eq!(summary.errors(), 3);
+        assert!(!summary.is_success());
+        match &summary.results[1].outcome {
+            TestOutcome::Error(message) => {
+                assert!(message.starts_with("setup_module failed: no database"))
+            }
+            other => panic!("Expected error, got {:?}", other),
+        }
+        assert!(
+            summary
+                .to_junit("tests/test_db.q")
+                .contains("<error message=\"no database")
+        );
+    }
+
+    #[test]
+    fn setup_module_success_runs_tests() {
+        assert!(Qtest::setup_module(|| {}));
+        Qtest::teardown_module(|| {});
+        assert!(Qtest::summary().results.is_empty());
+    }
+}
@@ -35,13 +35,13 @@ pub trait QuicheType: Deref {
 ///
 /// Usage:
 /// ```text
-/// impl_quiche_type!(Str, Arc<str>);
+/// impl_quiche_type!(Meters, f64);
 /// impl_quiche_type!(List<T>, Vec<T>);
-/// impl_quiche_type!(Dict<K: Eq + Hash, V: PartialEq>, HashMap<K, V>);
+/// impl_quiche_type!(Dict<K: Eq + Hash, V: PartialEq>, IndexMap<K, V>);
 /// ```
 #[macro_export]
 macro_rules! impl_quiche_type {
-    // Non-generic: impl_quiche_type!(Str, Arc<str>)
+    // Non-generic: impl_quiche_type!(Meters, f64)
     ($ty:ty, $inner:ty) => {
         impl $crate::QuicheType for $ty {
             type Inner = $inner;
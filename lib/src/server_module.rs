@@ -0,0 +1,384 @@
+//! Quiche `Server` module — a minimal HTTP/1.1 server for web handlers.
+//!
+//! ```python
+//! def hello(req: Request) -> Str:
+//!     return f"hello, {req.query["name"]}"
+//!
+//! def main():
+//!     Server.route("/hello", hello)
+//!     Server.route("/health", lambda req: Response.new(204, ""))
+//!     Server.run(port=8080)
+//! ```
+//!
+//! Routes match a request's path exactly; anything else gets a 404.
+//! Connections are served by a fixed pool of worker threads, one request per
+//! connection, so handlers must be safe to call from several threads at once
+//! (the compiler checks what a handler lambda captures). A client that stalls
+//! for longer than the I/O timeout is dropped. A handler that panics answers
+//! 500 and leaves the server running.
+//!
+//! Built on `std::net` alone, like the rest of quiche-lib, so scripts built
+//! with bare `rustc` can serve too.
+
+use crate::{Dict, Str};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, sync_channel};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+
+type Handler = Arc<dyn Fn(Request) -> Response + Send + Sync>;
+
+/// The routes registered with [`Server::route`], by path.
+static ROUTES: RwLock<Vec<(Str, Handler)>> = RwLock::new(Vec::new());
+
+/// Largest request body read, in bytes.
+const MAX_BODY: usize = 16 << 20;
+
+/// Threads answering connections; further connections wait for one.
+const WORKERS: usize = 32;
+
+/// How long a connection may stall on a read or write before it is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An HTTP request, as a handler receives it.
+#[derive(Clone, Debug)]
+pub struct Request {
+    /// `GET`, `POST`, ...
+    pub method: Str,
+    /// The path without its query string, e.g. `/users`
+    pub path: Str,
+    /// Decoded query string parameters
+    pub query: Dict<Str, Str>,
+    /// Header values, by lowercase name
+    pub headers: Dict<Str, Str>,
+    pub body: Str,
+}
+
+/// An HTTP response. Handlers may return a `Str` instead, sent as a 200
+/// `text/plain` response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: i64,
+    pub content_type: Str,
+    pub body: Str,
+}
+
+impl Response {
+    /// A `text/plain` response with status `status`.
+    pub fn new(status: i64, body: Str) -> Response {
+        Response {
+            status,
+            content_type: Str::from("text/plain; charset=utf-8"),
+            body,
+        }
+    }
+
+    /// A 200 response of JSON text.
+    pub fn json(body: Str) -> Response {
+        Response {
+            status: 200,
+            content_type: Str::from("application/json"),
+            body,
+        }
+    }
+}
+
+impl From<Str> for Response {
+    fn from(body: Str) -> Response {
+        Response::new(200, body)
+    }
+}
+
+impl From<&str> for Response {
+    fn from(body: &str) -> Response {
+        Response::new(200, Str::from(body))
+    }
+}
+
+/// Static module for serving HTTP, used as `Server.route(...)` in Quiche.
+pub struct Server;
+
+impl Server {
+    /// Answer requests for `path` with `handler`, replacing any handler
+    /// already registered for it.
+    pub fn route<R: Into<Response>>(
+        path: Str,
+        handler: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        let handler: Handler = Arc::new(move |request| handler(request).into());
+        let mut routes = ROUTES.write().unwrap_or_else(|e| e.into_inner());
+        routes.retain(|(route, _)| *route != path);
+        routes.push((path, handler));
+    }
+
+    /// Serve the registered routes on `port` of every interface, forever.
+    ///
+    /// Panics if `port` isn't a port number or can't be bound.
+    pub fn run(port: i64) {
+        let listener = listen(port).unwrap_or_else(|e| panic!("Server.run failed: {e}"));
+        eprintln!("Serving on http://localhost:{port}");
+        Self::serve(listener);
+    }
+
+    /// Serve each connection to `listener` on one of [`WORKERS`] threads.
+    fn serve(listener: TcpListener) {
+        let (sender, receiver) = sync_channel::<TcpStream>(WORKERS);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..WORKERS {
+            let receiver = Arc::clone(&receiver);
+            std::thread::spawn(move || Self::work(&receiver));
+        }
+        for stream in listener.incoming().flatten() {
+            if sender.send(stream).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Answer connections from `receiver` until the server stops.
+    fn work(receiver: &Mutex<Receiver<TcpStream>>) {
+        loop {
+            let next = receiver
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .recv();
+            let Ok(stream) = next else {
+                return;
+            };
+            Self::answer(stream);
+        }
+    }
+
+    /// Read one request from `stream` and write its response.
+    fn answer(mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(IO_TIMEOUT));
+        let _ = stream.set_write_timeout(Some(IO_TIMEOUT));
+        let response = match read_request(&mut stream) {
+            Some(request) => Self::dispatch(request),
+            None => Response::new(400, Str::from("bad request")),
+        };
+        let _ = write_response(&mut stream, &response);
+    }
+
+    /// The response of the handler routed for `request.path`.
+    fn dispatch(request: Request) -> Response {
+        let handler = ROUTES
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|(route, _)| *route == request.path)
+            .map(|(_, handler)| Arc::clone(handler));
+        let Some(handler) = handler else {
+            return Response::new(404, Str::from("not found"));
+        };
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handler(request)))
+            .unwrap_or_else(|_| Response::new(500, Str::from("internal server error")))
+    }
+}
+
+/// A listener on `port` of every interface.
+fn listen(port: i64) -> Result<TcpListener, String> {
+    let port = u16::try_from(port).map_err(|_| format!("{port} is not a port number"))?;
+    TcpListener::bind(("0.0.0.0", port))
+        .map_err(|e| format!("could not listen on port {port}: {e}"))
+}
+
+/// The request at the start of `stream`, or `None` if it isn't HTTP.
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: Str::from(method),
+        path: Str::from(percent_decode(path)),
+        query: query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (Str::from(query_decode(key)), Str::from(query_decode(value)))
+            })
+            .collect(),
+        headers: Dict::new(),
+        body: Str::from(""),
+    };
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        request.headers.insert(
+            Str::from(name.trim().to_ascii_lowercase()),
+            Str::from(value.trim()),
+        );
+    }
+    let length = request
+        .headers
+        .get(&Str::from("content-length"))
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return None;
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    request.body = Str::from(String::from_utf8_lossy(&body).into_owned());
+    Some(request)
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+/// The reason phrase sent with `status`.
+fn reason(status: i64) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+/// `text` with `+` and `%XX` escapes decoded, as in a URL query.
+fn query_decode(text: &str) -> String {
+    percent_decode(&text.replace('+', " "))
+}
+
+/// `text` with `%XX` escapes decoded, as in a URL path.
+fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'%' => match rest
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(decoded) => {
+                    bytes.push(decoded);
+                    rest = &rest[2..];
+                }
+                None => bytes.push(b'%'),
+            },
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(listener_port: u16, request: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", listener_port)).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn routes_answer_requests() {
+        Server::route(Str::from("/test/hello"), |req: Request| {
+            Str::from(format!(
+                "hello, {} via {}",
+                req.query[&Str::from("name")],
+                req.method
+            ))
+        });
+        Server::route(Str::from("/test/echo"), |req: Request| {
+            Response::json(req.body)
+        });
+        Server::route(Str::from("/test/fail"), |_req: Request| -> Str {
+            panic!("handler failed")
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || Server::serve(listener));
+
+        let hello = get(port, "GET /test/hello?name=a%20b+c HTTP/1.1\r\n\r\n");
+        assert!(hello.starts_with("HTTP/1.1 200 OK\r\n"), "{hello}");
+        assert!(hello.ends_with("\r\n\r\nhello, a b c via GET"), "{hello}");
+
+        let echo = get(
+            port,
+            "POST /test/echo HTTP/1.1\r\nContent-Length: 7\r\n\r\n{\"a\":1}",
+        );
+        assert!(
+            echo.contains("Content-Type: application/json\r\n"),
+            "{echo}"
+        );
+        assert!(echo.ends_with("\r\n\r\n{\"a\":1}"), "{echo}");
+
+        assert!(get(port, "GET /test/missing HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(get(port, "GET /test/fail HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 500"));
+        assert!(get(port, "nonsense\r\n\r\n").starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn query_escapes_are_decoded() {
+        assert_eq!(query_decode("a+b%2Fc%zz%4"), "a b/c%zz%4");
+        assert_eq!(query_decode("1%2B1"), "1+1");
+    }
+
+    #[test]
+    fn path_keeps_plus_signs() {
+        assert_eq!(percent_decode("/c++/a%20b"), "/c++/a b");
+    }
+
+    #[test]
+    fn out_of_range_ports_are_rejected() {
+        assert_eq!(listen(65616).unwrap_err(), "65616 is not a port number");
+        assert!(listen(-1).is_err());
+    }
+
+    #[test]
+    fn queued_connections_are_answered() {
+        Server::route(Str::from("/test/ping"), |_req: Request| "pong");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || Server::serve(listener));
+
+        // More connections than workers queue up rather than spawning
+        // threads, and each one that closes frees its worker.
+        let idle: Vec<TcpStream> = (0..WORKERS * 2)
+            .map(|_| TcpStream::connect(("127.0.0.1", port)).unwrap())
+            .collect();
+        drop(idle);
+        let ping = get(port, "GET /test/ping HTTP/1.1\r\n\r\n");
+        assert!(ping.ends_with("\r\n\r\npong"), "{ping}");
+    }
+}
@@ -0,0 +1,102 @@
+//! How f-strings format a value: `Display` when the type has it, `Debug`
+//! otherwise.
+//!
+//! The compiler wraps every f-string placeholder in the prelude's
+//! `quiche_show!(value)`, which expands to `(&Show(&value)).quiche_show()`.
+//! Method lookup tries [`ShowDisplay`]
+//! (implemented on `Show`) before [`ShowDebug`] (implemented on `&Show`, one
+//! auto-reference further), so a type without `Display` (or a `to_string`
+//! method) falls back to its `Debug` output instead of failing to compile.
+//! The first fallback for each type prints a warning.
+
+use std::any::type_name;
+use std::collections::HashSet;
+use std::fmt::{self, Debug, Display};
+use std::sync::{Mutex, OnceLock};
+
+/// A value being interpolated into an f-string.
+pub struct Show<'a, T: ?Sized>(pub &'a T);
+
+/// Preferred: format with `Display`.
+pub trait ShowDisplay {
+    type Shown: Display;
+    fn quiche_show(&self) -> Self::Shown;
+}
+
+impl<'a, T: Display + ?Sized> ShowDisplay for Show<'a, T> {
+    type Shown = &'a T;
+    fn quiche_show(&self) -> &'a T {
+        self.0
+    }
+}
+
+/// Fallback: format with `Debug`.
+pub trait ShowDebug {
+    type Shown: Display;
+    fn quiche_show(&self) -> Self::Shown;
+}
+
+impl<'a, T: Debug + ?Sized> ShowDebug for &Show<'a, T> {
+    type Shown = AsDebug<'a, T>;
+    fn quiche_show(&self) -> AsDebug<'a, T> {
+        warn_once(type_name::<T>());
+        AsDebug(self.0)
+    }
+}
+
+/// Displays a value with its `Debug` output, padded to the spec's width.
+pub struct AsDebug<'a, T: ?Sized>(&'a T);
+
+impl<T: Debug + ?Sized> Display for AsDebug<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.width().is_some() || f.precision().is_some() {
+            f.pad(&format!("{:?}", self.0))
+        } else {
+            write!(f, "{:?}", self.0)
+        }
+    }
+}
+
+fn warn_once(ty: &'static str) {
+    static WARNED: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let Ok(mut warned) = WARNED.get_or_init(Default::default).lock() else {
+        return;
+    };
+    if warned.insert(ty) {
+        eprintln!(
+            "warning: {ty} has no Display (or to_string method); f-strings show it with Debug"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Origin;
+
+    #[test]
+    fn test_prefers_display_and_falls_back_to_debug() {
+        let name = crate::Str::from("Ada");
+        let shown = format!(
+            "{} {} {}",
+            Show(&name).quiche_show(),
+            (&Show(&Origin)).quiche_show(),
+            Show(&(2 * 3)).quiche_show()
+        );
+        assert_eq!(shown, "Ada Origin 6");
+    }
+
+    #[test]
+    fn test_format_specs_pad_both() {
+        let name = crate::Str::from("Ada");
+        let shown = format!(
+            "[{:>5}] [{:<8}] [{:.2}]",
+            Show(&name).quiche_show(),
+            (&Show(&Origin)).quiche_show(),
+            Show(&2.5f64).quiche_show()
+        );
+        assert_eq!(shown, "[  Ada] [Origin  ] [2.50]");
+    }
+}
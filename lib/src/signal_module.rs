@@ -0,0 +1,231 @@
+//! Quiche `Signal` module — process-wide signal handling.
+//!
+//! ```python
+//! def main():
+//!     Signal.on("SIGINT", lambda: print("finishing the current batch"))
+//!     while not Signal.shutdown_requested():
+//!         process_next_batch()
+//!     save_progress()
+//! ```
+//!
+//! `Signal.on(name, handler)` replaces the signal's default action (for
+//! `SIGINT`, dying mid-write) with a call to `handler`. Handlers don't run
+//! inside the signal handler itself, where almost nothing is safe to do, but
+//! on a dispatcher thread that picks delivered signals up within 50ms;
+//! the compiler checks that a handler lambda only captures what can be
+//! shared with that thread.
+//!
+//! `SIGINT` and `SIGTERM` also raise the cooperative shutdown flag, read with
+//! `Signal.shutdown_requested()`. Asking for it the first time starts
+//! catching both, so a loop can wind down on Ctrl-C with no handler at all.
+//! A second `SIGINT` or `SIGTERM` arriving while the flag is up takes the
+//! default action, so a program stuck in its cleanup can still be stopped.
+//!
+//! Signals are Unix-only: on other platforms handlers never run and the flag
+//! is only raised by `Signal.request_shutdown()`.
+
+use crate::Str;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Once, RwLock};
+use std::time::Duration;
+
+type Handler = Arc<dyn Fn() + Send + Sync>;
+
+/// The handlers registered with [`Signal::on`], by signal number.
+static HANDLERS: RwLock<Vec<(i32, Handler)>> = RwLock::new(Vec::new());
+
+/// Signals delivered but not yet dispatched, one bit per signal number.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+static DISPATCHER: Once = Once::new();
+
+/// How often the dispatcher thread looks for delivered signals.
+const POLL: Duration = Duration::from_millis(50);
+
+/// The signals [`Signal::on`] accepts, by name.
+#[cfg(target_os = "macos")]
+const SIGNALS: &[(&str, i32)] = &[
+    ("SIGHUP", 1),
+    ("SIGINT", 2),
+    ("SIGQUIT", 3),
+    ("SIGTERM", 15),
+    ("SIGUSR1", 30),
+    ("SIGUSR2", 31),
+];
+
+#[cfg(not(target_os = "macos"))]
+const SIGNALS: &[(&str, i32)] = &[
+    ("SIGHUP", 1),
+    ("SIGINT", 2),
+    ("SIGQUIT", 3),
+    ("SIGUSR1", 10),
+    ("SIGUSR2", 12),
+    ("SIGTERM", 15),
+];
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn signal(signum: std::ffi::c_int, handler: usize) -> usize;
+    fn raise(signum: std::ffi::c_int) -> std::ffi::c_int;
+}
+
+#[cfg(unix)]
+const SIG_DFL: usize = 0;
+
+/// Static module for signal handling, used as `Signal.on("SIGINT", f)` in
+/// Quiche.
+pub struct Signal;
+
+impl Signal {
+    /// Call `handler` each time the signal `name` (`"SIGINT"`, or just
+    /// `"INT"`) arrives, after any handlers registered for it before.
+    ///
+    /// Panics if `name` isn't a signal Quiche handles.
+    pub fn on(name: Str, handler: impl Fn() + Send + Sync + 'static) {
+        let signum = number(&name).unwrap_or_else(|| {
+            let known: Vec<&str> = SIGNALS.iter().map(|(name, _)| *name).collect();
+            panic!(
+                "Signal.on failed: unknown signal '{}' (expected one of {})",
+                &*name,
+                known.join(", ")
+            )
+        });
+        HANDLERS
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((signum, Arc::new(handler)));
+        DISPATCHER.call_once(|| {
+            std::thread::spawn(|| {
+                loop {
+                    std::thread::sleep(POLL);
+                    dispatch(PENDING.swap(0, Ordering::AcqRel));
+                }
+            });
+        });
+        catch(signum);
+    }
+
+    /// Whether `SIGINT` or `SIGTERM` has arrived (or
+    /// [`Signal::request_shutdown`] was called), for long-running loops to
+    /// check between units of work.
+    pub fn shutdown_requested() -> bool {
+        static CATCH: Once = Once::new();
+        CATCH.call_once(|| {
+            catch(SIGINT);
+            catch(SIGTERM);
+        });
+        SHUTDOWN.load(Ordering::Acquire)
+    }
+
+    /// Raise the shutdown flag from the program itself.
+    pub fn request_shutdown() {
+        SHUTDOWN.store(true, Ordering::Release);
+    }
+}
+
+/// The number of the signal `name`, with or without its `SIG` prefix.
+fn number(name: &str) -> Option<i32> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(known, _)| known[3..] == *name)
+        .map(|(_, signum)| *signum)
+}
+
+/// Run the handlers of each signal in the `pending` bits, in the order they
+/// were registered. A handler that panics doesn't stop the others.
+fn dispatch(pending: u64) {
+    if pending == 0 {
+        return;
+    }
+    let handlers: Vec<(i32, Handler)> = HANDLERS.read().unwrap_or_else(|e| e.into_inner()).clone();
+    for (signum, handler) in handlers {
+        if pending & (1 << signum) != 0 {
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handler()));
+        }
+    }
+}
+
+/// Route `signum` to [`on_signal`] instead of its default action.
+#[cfg(unix)]
+fn catch(signum: i32) {
+    unsafe {
+        signal(signum, on_signal as extern "C" fn(std::ffi::c_int) as usize);
+    }
+}
+
+#[cfg(not(unix))]
+fn catch(_signum: i32) {}
+
+/// The process's handler for every caught signal: only atomics, which are
+/// safe to touch from a signal handler.
+#[cfg(unix)]
+extern "C" fn on_signal(signum: std::ffi::c_int) {
+    if (signum == SIGINT || signum == SIGTERM) && SHUTDOWN.swap(true, Ordering::AcqRel) {
+        unsafe {
+            signal(signum, SIG_DFL);
+            raise(signum);
+        }
+        return;
+    }
+    PENDING.fetch_or(1 << signum, Ordering::AcqRel);
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn signal_names_resolve_with_or_without_prefix() {
+        assert_eq!(number("SIGINT"), Some(2));
+        assert_eq!(number("int"), Some(2));
+        assert_eq!(number("SIGTERM"), Some(15));
+        assert_eq!(number("SIGKILL"), None);
+        assert_eq!(number("SIG"), None);
+    }
+
+    #[test]
+    fn handlers_run_on_the_dispatcher_thread() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        Signal::on(Str::from("SIGUSR1"), || {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+        });
+        Signal::on(Str::from("USR1"), || panic!("a failing handler"));
+        Signal::on(Str::from("USR1"), || {
+            CALLS.fetch_add(10, Ordering::SeqCst);
+        });
+        let signum = number("SIGUSR1").unwrap_or_default();
+        unsafe {
+            raise(signum);
+        }
+        for _ in 0..100 {
+            if CALLS.load(Ordering::SeqCst) == 11 {
+                break;
+            }
+            std::thread::sleep(POLL);
+        }
+        assert_eq!(CALLS.load(Ordering::SeqCst), 11);
+    }
+
+    #[test]
+    fn sigterm_requests_shutdown() {
+        assert!(!Signal::shutdown_requested());
+        unsafe {
+            raise(SIGTERM);
+        }
+        assert!(Signal::shutdown_requested());
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown signal 'SIGKILL'")]
+    fn uncatchable_signals_are_rejected() {
+        Signal::on(Str::from("SIGKILL"), || {});
+    }
+}
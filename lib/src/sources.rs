@@ -0,0 +1,48 @@
+//! quiche-lib's own package files, embedded for the compiler.
+//!
+//! Scripts are built with bare `rustc` rather than Cargo, so the `quiche`
+//! CLI writes these sources out and includes them as a module (see
+//! `quiche::vendor`). Only built with the `sources` feature, which the
+//! compiler enables; the sources are read from this package, so they are
+//! always the release it depends on.
+
+/// The version of this package, which `quiche init` projects depend on.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The package's files, by path relative to its root, sorted.
+pub const FILES: &[(&str, &str)] = &[
+    ("Cargo.toml", include_str!("../Cargo.toml")),
+    ("qtest.q", include_str!("../qtest.q")),
+    ("src/cli_module.rs", include_str!("cli_module.rs")),
+    ("src/clib_module.rs", include_str!("clib_module.rs")),
+    ("src/context.rs", include_str!("context.rs")),
+    ("src/dict.rs", include_str!("dict.rs")),
+    ("src/engine_value.rs", include_str!("engine_value.rs")),
+    ("src/enum_module.rs", include_str!("enum_module.rs")),
+    ("src/exception.rs", include_str!("exception.rs")),
+    ("src/file_module.rs", include_str!("file_module.rs")),
+    ("src/hot_module.rs", include_str!("hot_module.rs")),
+    ("src/index_map.rs", include_str!("index_map.rs")),
+    ("src/iterable.rs", include_str!("iterable.rs")),
+    ("src/lib.rs", include_str!("lib.rs")),
+    ("src/list.rs", include_str!("list.rs")),
+    ("src/memo.rs", include_str!("memo.rs")),
+    ("src/os_module.rs", include_str!("os_module.rs")),
+    ("src/overflow.rs", include_str!("overflow.rs")),
+    ("src/parallel_module.rs", include_str!("parallel_module.rs")),
+    ("src/path_module.rs", include_str!("path_module.rs")),
+    ("src/pool.rs", include_str!("pool.rs")),
+    ("src/pprint.rs", include_str!("pprint.rs")),
+    ("src/qtest_given.rs", include_str!("qtest_given.rs")),
+    ("src/qtest_module.rs", include_str!("qtest_module.rs")),
+    ("src/quiche_type.rs", include_str!("quiche_type.rs")),
+    ("src/server_module.rs", include_str!("server_module.rs")),
+    ("src/show.rs", include_str!("show.rs")),
+    ("src/signal_module.rs", include_str!("signal_module.rs")),
+    ("src/sources.rs", include_str!("sources.rs")),
+    ("src/str_type.rs", include_str!("str_type.rs")),
+    ("src/system_module.rs", include_str!("system_module.rs")),
+    ("src/traceback.rs", include_str!("traceback.rs")),
+    ("src/unpack.rs", include_str!("unpack.rs")),
+    ("src/value.rs", include_str!("value.rs")),
+];
@@ -1,32 +1,78 @@
 use std::cmp::PartialEq;
+use std::collections::HashSet;
 use std::fmt::{Debug, Display, Formatter, Result};
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::ops::{Add, Deref};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 
-/// Quiche's string type — an immutable, reference-counted string.
+/// Strings up to this many bytes are stored inline, without an allocation.
+const INLINE_CAP: usize = 22;
+
+/// Quiche's string type — an immutable string that is cheap to clone.
+///
+/// Short strings (up to 22 bytes) are stored inline, so creating and cloning
+/// them neither allocates nor touches a reference count; longer ones are a
+/// shared `Arc<str>`. Either way a `Str` is 24 bytes. Derefs to `&str` so
+/// all standard string methods are available.
 ///
-/// Wraps `Arc<str>` for cheap cloning and zero-copy sharing.
-/// Derefs to `&str` so all standard string methods are available.
-#[derive(Clone, Debug, Eq, Hash)]
-pub struct Str(pub Arc<str>);
+/// Breaking change in quiche-lib 0.2.0: the field is private (it was
+/// `Str(pub Arc<str>)`). Use [`Str::new`] in place of `Str(arc)` and
+/// [`Str::as_arc`] in place of `.0`.
+#[derive(Clone)]
+pub struct Str(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Inline { len: u8, bytes: [u8; INLINE_CAP] },
+    Shared(Arc<str>),
+}
+
+/// Every string made with [`Str::interned`].
+static INTERNED: OnceLock<Mutex<HashSet<Arc<str>>>> = OnceLock::new();
 
 impl Deref for Str {
     type Target = str;
     fn deref(&self) -> &str {
-        &self.0
+        match &self.0 {
+            // SAFETY: inline bytes are always copied whole from a `&str`
+            // (see `From<&str>`), so `bytes[..len]` is valid UTF-8.
+            Repr::Inline { len, bytes } => unsafe {
+                std::str::from_utf8_unchecked(&bytes[..*len as usize])
+            },
+            Repr::Shared(shared) => shared,
+        }
+    }
+}
+
+impl AsRef<str> for Str {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl Debug for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "Str({:?})", &**self)
     }
 }
 
 impl Display for Str {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "{}", &*self.0)
+        f.pad(self)
     }
 }
 
 impl PartialEq for Str {
     fn eq(&self, other: &Self) -> bool {
-        *self.0 == *other.0
+        **self == **other
+    }
+}
+
+impl Eq for Str {}
+
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
     }
 }
 
@@ -38,105 +84,209 @@ impl PartialOrd for Str {
 
 impl Ord for Str {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        (*self.0).cmp(&*other.0)
+        (**self).cmp(&**other)
     }
 }
 
 impl PartialEq<&str> for Str {
     fn eq(&self, other: &&str) -> bool {
-        &*self.0 == *other
+        &**self == *other
+    }
+}
+
+impl PartialEq<str> for Str {
+    fn eq(&self, other: &str) -> bool {
+        &**self == other
+    }
+}
+
+impl PartialEq<String> for Str {
+    fn eq(&self, other: &String) -> bool {
+        **self == **other
+    }
+}
+
+impl PartialEq<Str> for &str {
+    fn eq(&self, other: &Str) -> bool {
+        *self == &**other
+    }
+}
+
+impl PartialEq<Str> for String {
+    fn eq(&self, other: &Str) -> bool {
+        **self == **other
     }
 }
 
 impl From<&str> for Str {
     fn from(s: &str) -> Self {
-        Str(Arc::from(s))
+        if s.len() > INLINE_CAP {
+            return Str(Repr::Shared(Arc::from(s)));
+        }
+        let mut bytes = [0; INLINE_CAP];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Str(Repr::Inline {
+            len: s.len() as u8,
+            bytes,
+        })
     }
 }
 
 impl From<String> for Str {
     fn from(s: String) -> Self {
-        Str(Arc::from(s.as_str()))
+        Str::from(s.as_str())
+    }
+}
+
+/// Parsing never fails, so `Str` can be read like a number, e.g. by `@cli`.
+impl std::str::FromStr for Str {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(Str::from(s))
+    }
+}
+
+/// Iterating a `Str` yields its characters, as in Python.
+impl IntoIterator for Str {
+    type Item = char;
+    type IntoIter = std::vec::IntoIter<char>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.chars().collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Str {
+    type Item = char;
+    type IntoIter = std::str::Chars<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.chars()
+    }
+}
+
+impl From<Arc<str>> for Str {
+    fn from(s: Arc<str>) -> Self {
+        if s.len() > INLINE_CAP {
+            Str(Repr::Shared(s))
+        } else {
+            Str::from(&*s)
+        }
     }
 }
 
 impl Add for Str {
     type Output = Str;
     fn add(self, other: Str) -> Str {
-        let mut s = self.0.to_string();
-        s.push_str(&other.0);
-        Str(Arc::from(s.as_str()))
+        self + &*other
     }
 }
 
 impl Add<&str> for Str {
     type Output = Str;
     fn add(self, other: &str) -> Str {
-        let mut s = self.0.to_string();
+        let mut s = String::with_capacity(self.len() + other.len());
+        s.push_str(&self);
         s.push_str(other);
-        Str(Arc::from(s.as_str()))
+        Str::from(s)
     }
 }
 
 impl Str {
+    /// Wrap a shared string; the replacement for the old `Str(arc)`.
+    pub fn new(s: Arc<str>) -> Str {
+        Str::from(s)
+    }
+
+    /// The text as an `Arc<str>`; the replacement for the old `.0`.
+    /// Short strings are stored inline, so for them this allocates.
+    pub fn as_arc(&self) -> Arc<str> {
+        match &self.0 {
+            Repr::Inline { .. } => Arc::from(&**self),
+            Repr::Shared(shared) => shared.clone(),
+        }
+    }
+
+    /// The one shared `Str` for `s`: every call with equal text returns the
+    /// same allocation, so hot literals and repeated keys are stored once.
+    /// Interned strings live until the program exits.
+    pub fn interned(s: &str) -> Str {
+        if s.len() <= INLINE_CAP {
+            return Str::from(s);
+        }
+        let mut table = INTERNED
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(shared) = table.get(s) {
+            return Str(Repr::Shared(shared.clone()));
+        }
+        let shared: Arc<str> = Arc::from(s);
+        table.insert(shared.clone());
+        Str(Repr::Shared(shared))
+    }
+
     /// Return the length in bytes.
     pub fn len(&self) -> usize {
-        self.0.len()
+        (**self).len()
     }
 
     /// Check if the string is empty.
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        (**self).is_empty()
     }
 
     /// Check if the string ends with the given suffix.
     pub fn ends_with(&self, pat: Str) -> bool {
-        (*self.0).ends_with(&*pat)
+        (**self).ends_with(&*pat)
     }
 
     /// Check if the string starts_with the given prefix.
     pub fn starts_with(&self, pat: Str) -> bool {
-        (*self.0).starts_with(&*pat)
+        (**self).starts_with(&*pat)
     }
 
     /// Check if the string contains the given substring.
     pub fn contains(&self, pat: Str) -> bool {
-        (*self.0).contains(&*pat)
+        (**self).contains(&*pat)
     }
 
     /// Strip the suffix from the string, returning the string without it.
     pub fn trim_end_matches(&self, pat: Str) -> Str {
-        Str(Arc::from((*self.0).trim_end_matches(&*pat)))
+        Str::from((**self).trim_end_matches(&*pat))
     }
 
     /// Strip leading/trailing whitespace.
     pub fn trim(&self) -> Str {
-        Str(Arc::from((*self.0).trim()))
+        Str::from((**self).trim())
     }
 
     /// Convert to uppercase.
     pub fn to_uppercase(&self) -> Str {
-        Str(Arc::from((*self.0).to_uppercase().as_str()))
+        Str::from((**self).to_uppercase())
     }
 
     /// Convert to lowercase.
     pub fn to_lowercase(&self) -> Str {
-        Str(Arc::from((*self.0).to_lowercase().as_str()))
+        Str::from((**self).to_lowercase())
     }
 
     /// Replace all occurrences of a pattern.
     pub fn replace(&self, from: Str, to: Str) -> Str {
-        Str(Arc::from((*self.0).replace(&*from, &*to).as_str()))
+        Str::from(str::replace(self, &*from, &to))
     }
 }
 
 /// Construct a `Str` from any `Display` value.
 pub fn str<T: std::fmt::Display>(x: T) -> Str {
-    Str(Arc::from(x.to_string().as_str()))
+    Str::from(x.to_string())
 }
 
-use crate::impl_quiche_type;
-impl_quiche_type!(Str, Arc<str>);
+impl crate::QuicheType for Str {
+    type Inner = Arc<str>;
+    fn inner(self) -> Arc<str> {
+        self.as_arc()
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -149,6 +299,16 @@ mod tests {
         assert_eq!(s.len(), 5);
     }
 
+    #[test]
+    fn str_compares_with_std_strings() {
+        let s = str("quiche");
+        assert_eq!(s, "quiche");
+        assert_eq!("quiche", s);
+        assert_eq!(s, String::from("quiche"));
+        assert_eq!(String::from("quiche"), s);
+        assert!(s == *"quiche");
+    }
+
     #[test]
     fn str_display() {
         let s = str("world");
@@ -157,10 +317,37 @@ mod tests {
 
     #[test]
     fn str_clone_shares() {
-        let a = str("shared");
+        let a = str("a string too long to store inline");
         let b = a.clone();
         assert_eq!(a, b);
-        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a.as_ptr(), b.as_ptr());
+    }
+
+    #[test]
+    fn str_short_strings_are_inline() {
+        assert_eq!(std::mem::size_of::<Str>(), 24);
+        let a = str("inline");
+        assert!(matches!(a.0, Repr::Inline { len: 6, .. }));
+        assert_eq!(a.clone() + "!", "inline!");
+        assert_eq!(format!("{:?}", a), "Str(\"inline\")");
+    }
+
+    #[test]
+    fn str_interned_shares_one_allocation() {
+        let text = String::from("a hot literal used all over the program");
+        let a = Str::interned(&text);
+        let b = Str::interned(&text.clone());
+        assert_eq!(a.as_ptr(), b.as_ptr());
+        assert_eq!(Str::interned("short"), "short");
+    }
+
+    #[test]
+    fn str_new_and_as_arc_round_trip() {
+        let long: Arc<str> = Arc::from("a string well past the inline capacity");
+        let s = Str::new(long.clone());
+        assert!(Arc::ptr_eq(&s.as_arc(), &long));
+        let short = Str::new(Arc::from("short"));
+        assert_eq!(&*short.as_arc(), "short");
     }
 
     #[test]
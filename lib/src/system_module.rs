@@ -3,7 +3,6 @@
 //! Process spawning, environment variables, and program control.
 
 use crate::{List, Str};
-use std::sync::Arc;
 
 /// Static module for system operations, used as `System.cmd(...)` in Quiche.
 pub struct System;
@@ -30,7 +29,7 @@ impl System {
             combined.push_str(&stderr);
         }
         let code = output.status.code().unwrap_or(-1) as i64;
-        (Str(Arc::from(combined.as_str())), code)
+        (Str::from(combined.as_str()), code)
     }
 
     /// Execute a command and return only the exit code.
@@ -47,17 +46,15 @@ impl System {
 
     /// Return command-line arguments passed to the program.
     pub fn argv() -> List<Str> {
-        let args: Vec<Str> = std::env::args()
-            .map(|a| Str(Arc::from(a.as_str())))
-            .collect();
+        let args: Vec<Str> = std::env::args().map(|a| Str::from(a.as_str())).collect();
         List(args)
     }
 
     /// Get an environment variable. Returns empty string if not set.
     pub fn get_env(key: Str) -> Str {
         match std::env::var(&*key) {
-            Ok(val) => Str(Arc::from(val.as_str())),
-            Err(_) => Str(Arc::from("")),
+            Ok(val) => Str::from(val.as_str()),
+            Err(_) => Str::from(""),
         }
     }
 
@@ -71,7 +68,7 @@ impl System {
     /// Return the current working directory.
     pub fn cwd() -> Str {
         let dir = std::env::current_dir().unwrap_or_else(|e| panic!("System.cwd failed: {}", e));
-        Str(Arc::from(dir.to_string_lossy().as_ref()))
+        Str::from(dir.to_string_lossy().into_owned())
     }
 
     /// Halt the program with an exit code.
@@ -86,16 +83,16 @@ impl System {
             for dir in path_var.split(':') {
                 let candidate = std::path::Path::new(dir).join(&*name);
                 if candidate.exists() {
-                    return Str(Arc::from(candidate.to_string_lossy().as_ref()));
+                    return Str::from(candidate.to_string_lossy().into_owned());
                 }
             }
         }
-        Str(Arc::from(""))
+        Str::from("")
     }
 
     /// Return the OS process ID as a string.
     pub fn pid() -> Str {
-        Str(Arc::from(std::process::id().to_string().as_str()))
+        Str::from(std::process::id().to_string().as_str())
     }
 }
 
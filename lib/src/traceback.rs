@@ -0,0 +1,117 @@
+//! Python-style tracebacks for panics in compiled scripts.
+//!
+//! `quiche run` starts `main` with [`Traceback::install`], passing a source
+//! map of every function in the script: its Rust path and the `.q` line of
+//! its `def`. When the script panics, the hook walks the backtrace, keeps
+//! the frames that belong to the script, and prints them outermost first:
+//!
+//! ```text
+//! Traceback (most recent call last):
+//!   File "app.q", line 9, in main
+//!   File "app.q", line 2, in divide
+//! RuntimeError: attempt to divide by zero
+//! ```
+//!
+//! Setting `RUST_BACKTRACE` keeps Rust's own panic output instead.
+
+use std::backtrace::Backtrace;
+use std::panic;
+use std::sync::Once;
+
+static HOOK: Once = Once::new();
+
+/// Static module behind script tracebacks.
+pub struct Traceback;
+
+impl Traceback {
+    /// Replace the panic hook with one printing a traceback of `file`.
+    ///
+    /// `frames` holds `(rust_path, name, line)` for each function: its path
+    /// in the generated crate (`divide`, `Point::area`), the name to show
+    /// (`divide`, `Point.area`) and the line of its `def`.
+    pub fn install(file: &'static str, frames: &'static [(&'static str, &'static str, u32)]) {
+        if std::env::var_os("RUST_BACKTRACE").is_some() {
+            return;
+        }
+        HOOK.call_once(|| {
+            panic::set_hook(Box::new(move |info| {
+                let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+                    s.to_string()
+                } else if let Some(s) = info.payload().downcast_ref::<String>() {
+                    s.clone()
+                } else {
+                    "panic".to_string()
+                };
+                let backtrace = Backtrace::force_capture().to_string();
+                eprint!("{}", render(file, frames, &backtrace, &message));
+            }));
+        });
+    }
+}
+
+/// The traceback for a panic with `message`, given the text of a captured
+/// backtrace.
+fn render(file: &str, frames: &[(&str, &str, u32)], backtrace: &str, message: &str) -> String {
+    let mut out = String::from("Traceback (most recent call last):\n");
+    for symbol in script_symbols(backtrace).into_iter().rev() {
+        if let Some((_, name, line)) = frames.iter().find(|(path, _, _)| *path == symbol) {
+            out.push_str(&format!("  File \"{file}\", line {line}, in {name}\n"));
+        }
+    }
+    // Messages such as `OverflowError: ...` already name their error.
+    let named = message
+        .split_once(": ")
+        .is_some_and(|(kind, _)| kind.ends_with("Error") && !kind.contains(' '));
+    if named {
+        out.push_str(&format!("{message}\n"));
+    } else {
+        out.push_str(&format!("RuntimeError: {message}\n"));
+    }
+    out
+}
+
+/// Symbols of the backtrace's frames, innermost first, with the crate name
+/// and symbol hash removed: `app::Point::area::h1a2b` becomes `Point::area`.
+fn script_symbols(backtrace: &str) -> Vec<&str> {
+    backtrace
+        .lines()
+        .filter_map(|line| {
+            let (index, symbol) = line.trim_start().split_once(": ")?;
+            if !index.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            let symbol = match symbol.rsplit_once("::h") {
+                Some((rest, hash)) if hash.chars().all(|c| c.is_ascii_hexdigit()) => rest,
+                _ => symbol,
+            };
+            symbol.split_once("::").map(|(_, path)| path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_lists_script_frames_outermost_first() {
+        let backtrace = "   0: std::panicking::begin_panic\n\
+                         \x20  1: app::Point::area::h0123abcd\n\
+                         \x20            at ./app.rs:10:5\n\
+                         \x20  2: app::quiche_lib::list::List<T>::pop\n\
+                         \x20  3: app::main\n\
+                         \x20  4: core::ops::function::FnOnce::call_once\n";
+        let frames = [("main", "main", 7), ("Point::area", "Point.area", 2)];
+        assert_eq!(
+            render("app.q", &frames, backtrace, "attempt to divide by zero"),
+            "Traceback (most recent call last):\n  \
+             File \"app.q\", line 7, in main\n  \
+             File \"app.q\", line 2, in Point.area\n\
+             RuntimeError: attempt to divide by zero\n"
+        );
+        assert!(
+            render("app.q", &frames, "", "OverflowError: 1 + 1 does not fit")
+                .ends_with("\nOverflowError: 1 + 1 does not fit\n")
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! `QuicheUnpack` — what the right-hand side of `a, b = value` can be.
+//!
+//! The compiler lowers tuple unpacking of anything but a tuple literal to
+//! `let (a, b) = QuicheUnpack::unpack(value)`. Tuples unpack as themselves
+//! (borrowed tuples into borrowed elements, as Rust's binding modes would),
+//! and named tuples (`type Point = (x: i64, y: i64)`) get an impl
+//! unpacking their fields in declaration order.
+
+/// A value that unpacks into a tuple of its parts.
+pub trait QuicheUnpack {
+    type Tuple;
+
+    /// The tuple `a, b = self` binds from.
+    fn unpack(self) -> Self::Tuple;
+}
+
+macro_rules! unpack_tuples {
+    ($(($($t:ident),+)),* $(,)?) => {$(
+        impl<$($t),+> QuicheUnpack for ($($t,)+) {
+            type Tuple = Self;
+            fn unpack(self) -> Self {
+                self
+            }
+        }
+
+        impl<'a, $($t),+> QuicheUnpack for &'a ($($t,)+) {
+            type Tuple = ($(&'a $t,)+);
+            #[allow(non_snake_case)]
+            fn unpack(self) -> Self::Tuple {
+                let ($($t,)+) = self;
+                ($($t,)+)
+            }
+        }
+    )*};
+}
+
+unpack_tuples! {
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F),
+    (A, B, C, D, E, F, G),
+    (A, B, C, D, E, F, G, H),
+    (A, B, C, D, E, F, G, H, I),
+    (A, B, C, D, E, F, G, H, I, J),
+    (A, B, C, D, E, F, G, H, I, J, K),
+    (A, B, C, D, E, F, G, H, I, J, K, L),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    impl QuicheUnpack for Point {
+        type Tuple = (i64, i64);
+        fn unpack(self) -> (i64, i64) {
+            (self.x, self.y)
+        }
+    }
+
+    #[test]
+    fn test_unpacks_tuples_and_named_tuples() {
+        let (a, b) = QuicheUnpack::unpack((1, "one"));
+        assert_eq!((a, b), (1, "one"));
+
+        let pair = (String::from("k"), 2);
+        let (k, v) = QuicheUnpack::unpack(&pair);
+        assert_eq!((k.as_str(), *v), ("k", 2));
+
+        let (x, y) = QuicheUnpack::unpack(Point { x: 3, y: 4 });
+        assert_eq!((x, y), (3, 4));
+    }
+}
@@ -0,0 +1,517 @@
+//! Quiche `Value` — a dynamically typed value.
+//!
+//! Backs Quiche's `Any` type, whose operations are checked at runtime, and
+//! is used where Quiche code exchanges data with a dynamically typed
+//! runtime, such as Python packages called through `@py_import`. Typed
+//! Quiche values convert in with `Value::from` and back out with
+//! [`FromValue`].
+
+use crate::{Dict, List, Str};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::hash::Hash;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+/// A dynamically typed value: the shapes shared by Quiche and Python.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Str),
+    List(List<Value>),
+    /// Key/value pairs in insertion order.
+    Dict(List<(Value, Value)>),
+}
+
+impl Value {
+    /// Name of this value's kind, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::None => "None",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::List(_) => "list",
+            Value::Dict(_) => "dict",
+        }
+    }
+
+    /// Python truthiness: `None`, `False`, zero and empty collections are
+    /// false.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::None => false,
+            Value::Bool(b) => *b,
+            Value::Int(i) => *i != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            Value::Dict(entries) => !entries.is_empty(),
+        }
+    }
+
+    /// Length of a string, list or dict. Panics for other values.
+    pub fn len(&self) -> i64 {
+        match self {
+            Value::Str(s) => s.chars().count() as i64,
+            Value::List(items) => items.len() as i64,
+            Value::Dict(entries) => entries.len() as i64,
+            other => panic!("object of type '{}' has no len()", other.kind()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `value[key]`: a list element (negative indices count from the end),
+    /// a dict entry, or a character of a string. Panics if missing.
+    pub fn get(&self, key: impl Into<Value>) -> Value {
+        let key = key.into();
+        match (self, &key) {
+            (Value::List(items), Value::Int(i)) => items
+                .get(wrap_index(*i, items.len()))
+                .cloned()
+                .unwrap_or_else(|| panic!("list index {i} out of range")),
+            (Value::Str(s), Value::Int(i)) => {
+                let chars: Vec<char> = s.chars().collect();
+                chars
+                    .get(wrap_index(*i, chars.len()))
+                    .map(|c| Value::from(c.to_string()))
+                    .unwrap_or_else(|| panic!("string index {i} out of range"))
+            }
+            (Value::Dict(entries), key) => entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+                .unwrap_or_else(|| panic!("key {key} not found")),
+            (value, key) => panic!(
+                "'{}' object is not subscriptable by {}",
+                value.kind(),
+                key.kind()
+            ),
+        }
+    }
+
+    /// `value.name` on a dynamic object: the dict entry keyed by `name`.
+    pub fn attr(&self, name: &str) -> Value {
+        match self {
+            Value::Dict(_) => self.get(name),
+            other => panic!("'{}' object has no attribute '{name}'", other.kind()),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            Value::Bool(b) => Some(*b as i64 as f64),
+            _ => None,
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::Bool(b) => Some(*b as i64),
+            _ => None,
+        }
+    }
+}
+
+fn wrap_index(i: i64, len: usize) -> usize {
+    if i < 0 {
+        (len as i64 + i) as usize
+    } else {
+        i as usize
+    }
+}
+
+fn unsupported(op: &str, a: &Value, b: &Value) -> ! {
+    panic!(
+        "unsupported operand types for {op}: '{}' and '{}'",
+        a.kind(),
+        b.kind()
+    )
+}
+
+/// Apply a numeric operator: integer arithmetic when both sides are ints
+/// (or bools), float arithmetic when either is a float.
+fn numeric(
+    op: &str,
+    a: Value,
+    b: Value,
+    int: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Value {
+    if let (Some(x), Some(y)) = (a.as_i64(), b.as_i64()) {
+        return Value::Int(int(x, y).unwrap_or_else(|| panic!("integer overflow in {op}")));
+    }
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) => Value::Float(float(x, y)),
+        _ => unsupported(op, &a, &b),
+    }
+}
+
+impl Add for Value {
+    type Output = Value;
+
+    fn add(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Str(a), Value::Str(b)) => Value::Str(a + b),
+            (Value::List(a), Value::List(b)) => Value::List(a.0.into_iter().chain(b.0).collect()),
+            (a, b) => numeric("+", a, b, i64::checked_add, |x, y| x + y),
+        }
+    }
+}
+
+impl Sub for Value {
+    type Output = Value;
+
+    fn sub(self, other: Value) -> Value {
+        numeric("-", self, other, i64::checked_sub, |x, y| x - y)
+    }
+}
+
+impl Mul for Value {
+    type Output = Value;
+
+    fn mul(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => {
+                Value::from(s.repeat(n.max(0) as usize))
+            }
+            (a, b) => numeric("*", a, b, i64::checked_mul, |x, y| x * y),
+        }
+    }
+}
+
+impl Div for Value {
+    type Output = Value;
+
+    /// True division, as in Python: always a float.
+    fn div(self, other: Value) -> Value {
+        match (self.as_f64(), other.as_f64()) {
+            (Some(_), Some(0.0)) => panic!("division by zero"),
+            (Some(x), Some(y)) => Value::Float(x / y),
+            _ => unsupported("/", &self, &other),
+        }
+    }
+}
+
+impl Rem for Value {
+    type Output = Value;
+
+    fn rem(self, other: Value) -> Value {
+        if other.as_f64() == Some(0.0) {
+            panic!("modulo by zero");
+        }
+        numeric("%", self, other, i64::checked_rem_euclid, f64::rem_euclid)
+    }
+}
+
+impl Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        match self {
+            Value::Int(i) => Value::Int(-i),
+            Value::Float(f) => Value::Float(-f),
+            other => panic!("bad operand type for unary -: '{}'", other.kind()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    /// Numbers compare with numbers, strings with strings, lists
+    /// element-wise; anything else is unordered.
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+            (Value::List(a), Value::List(b)) => a.0.partial_cmp(&b.0),
+            (a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        }
+    }
+}
+
+impl Display for Value {
+    /// Like Python's `str()`: strings print bare at the top level and
+    /// quoted inside collections.
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fn repr(value: &Value, f: &mut Formatter<'_>) -> FmtResult {
+            match value {
+                Value::Str(s) => write!(f, "{:?}", &**s),
+                other => write!(f, "{other}"),
+            }
+        }
+        match self {
+            Value::None => write!(f, "None"),
+            Value::Bool(b) => write!(f, "{}", if *b { "True" } else { "False" }),
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Float(x) => write!(f, "{x:?}"),
+            Value::Str(s) => write!(f, "{s}"),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    repr(item, f)?;
+                }
+                write!(f, "]")
+            }
+            Value::Dict(entries) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    repr(k, f)?;
+                    write!(f, ": ")?;
+                    repr(v, f)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+macro_rules! value_from_int {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for Value {
+            fn from(v: $ty) -> Value {
+                Value::Int(v as i64)
+            }
+        }
+
+        impl FromValue for $ty {
+            fn from_value(value: Value) -> $ty {
+                match value {
+                    Value::Int(i) => <$ty>::try_from(i).unwrap_or_else(|_| {
+                        panic!("expected {}, got {i} (out of range)", stringify!($ty))
+                    }),
+                    Value::Bool(b) => b as $ty,
+                    other => mismatch(stringify!($ty), &other),
+                }
+            }
+        }
+    )*};
+}
+
+value_from_int!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Value {
+        Value::Float(v)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Value {
+        Value::Float(v as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Value {
+        Value::Bool(v)
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Value {
+        Value::None
+    }
+}
+
+impl From<Str> for Value {
+    fn from(v: Str) -> Value {
+        Value::Str(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Value {
+        Value::Str(Str::from(v))
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Value {
+        Value::Str(Str::from(v))
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map_or(Value::None, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Value {
+        Value::List(v.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<List<T>> for Value {
+    fn from(v: List<T>) -> Value {
+        v.0.into()
+    }
+}
+
+impl<K: Into<Value>, V: Into<Value>> From<Dict<K, V>> for Value {
+    fn from(v: Dict<K, V>) -> Value {
+        Value::Dict(v.0.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+/// Conversion out of a [`Value`] into a typed Quiche value.
+///
+/// Panics when the value has the wrong shape, like the other runtime
+/// conversions in quiche-lib.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Self;
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Value {
+        value
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> f64 {
+        match value {
+            Value::Float(f) => f,
+            Value::Int(i) => i as f64,
+            other => mismatch("float", &other),
+        }
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: Value) -> f32 {
+        f64::from_value(value) as f32
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> bool {
+        match value {
+            Value::Bool(b) => b,
+            other => mismatch("bool", &other),
+        }
+    }
+}
+
+impl FromValue for () {
+    fn from_value(_: Value) {}
+}
+
+impl FromValue for Str {
+    fn from_value(value: Value) -> Str {
+        match value {
+            Value::Str(s) => s,
+            other => mismatch("str", &other),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> String {
+        Str::from_value(value).to_string()
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Option<T> {
+        match value {
+            Value::None => None,
+            other => Some(T::from_value(other)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Vec<T> {
+        match value {
+            Value::List(items) => items.into_iter().map(T::from_value).collect(),
+            other => mismatch("list", &other),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for List<T> {
+    fn from_value(value: Value) -> List<T> {
+        List(Vec::from_value(value))
+    }
+}
+
+impl<K: FromValue + Eq + Hash, V: FromValue> FromValue for Dict<K, V> {
+    fn from_value(value: Value) -> Dict<K, V> {
+        match value {
+            Value::Dict(entries) => entries
+                .into_iter()
+                .map(|(k, v)| (K::from_value(k), V::from_value(v)))
+                .collect(),
+            other => mismatch("dict", &other),
+        }
+    }
+}
+
+fn mismatch(expected: &str, got: &Value) -> ! {
+    panic!("expected {expected}, got {}", got.kind())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_round_trips_quiche_types() {
+        let list = List::from(vec![1.5, 2.0]);
+        assert_eq!(List::<f64>::from_value(Value::from(list.clone())), list);
+        assert_eq!(i64::from_value(Value::from(7u8)), 7);
+        assert_eq!(f64::from_value(Value::Int(3)), 3.0);
+        assert_eq!(Option::<i64>::from_value(Value::None), None);
+        let dict = Dict::from([(Str::from("a"), 1i64)]);
+        assert_eq!(
+            Dict::<Str, i64>::from_value(Value::from(dict.clone())),
+            dict
+        );
+    }
+
+    #[test]
+    fn test_value_operators_follow_python() {
+        assert_eq!(Value::from(2) + Value::from(3), Value::Int(5));
+        assert_eq!(Value::from(2) + Value::from(0.5), Value::Float(2.5));
+        assert_eq!(Value::from(7) / Value::from(2), Value::Float(3.5));
+        assert_eq!(Value::from(-7) % Value::from(3), Value::Int(2));
+        assert_eq!(Value::from("ab") * Value::from(2), Value::from("abab"));
+        assert_eq!(Value::from("a") + Value::from("b"), Value::from("ab"));
+        assert!(Value::from(1) < Value::from(1.5));
+        assert!(!Value::from(Vec::<i64>::new()).is_truthy());
+
+        let list = Value::from(vec![Value::from("x"), Value::from(1)]);
+        assert_eq!(list.get(-1), Value::Int(1));
+        assert_eq!(list.to_string(), "[\"x\", 1]");
+        let dict = Dict::from([(Str::from("name"), Str::from("quiche"))]);
+        assert_eq!(Value::from(dict).attr("name"), Value::from("quiche"));
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported operand types for -: 'str' and 'int'")]
+    fn test_value_operator_type_error_panics() {
+        let _ = Value::from("a") - Value::from(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected str, got int")]
+    fn test_value_shape_mismatch_panics() {
+        Str::from_value(Value::Int(1));
+    }
+}
@@ -0,0 +1,186 @@
+//! `quiche!` — write Quiche items inline in Rust source.
+//!
+//! ```ignore
+//! use quiche_macros::quiche;
+//!
+//! quiche! {
+//!     def helper(x: i64) -> i64:
+//!         return x * 2
+//! }
+//!
+//! fn main() {
+//!     assert_eq!(helper(21), 42);
+//! }
+//! ```
+//!
+//! The block is compiled by the Quiche compiler while the Rust crate builds.
+//! Its items land in a hidden module that is glob-imported into the
+//! surrounding one, so they are callable as if written there, and Quiche code
+//! can in turn see the surrounding items. The crate needs `quiche-lib` as a
+//! dependency.
+//!
+//! Rust tokenizes the block before Quiche sees it, so it cannot contain `#`
+//! comments, f-strings, or single-quoted strings. Quiche errors are reported
+//! at the offending token.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Source name the compiler reports locations against.
+const SOURCE_NAME: &str = "quiche!";
+
+#[proc_macro]
+pub fn quiche(input: TokenStream) -> TokenStream {
+    let source = Source::from_tokens(input);
+
+    if let Err(e) = quiche::parser::parse(&source.text) {
+        return compile_error(&e.message, source.span_at(e.line, e.column));
+    }
+    let rust = match quiche::compile_file(&source.text, SOURCE_NAME, &quiche::default_options()) {
+        Ok(rust) => rust,
+        Err(message) => {
+            let span = quiche::diagnostics::error_location(&message, SOURCE_NAME)
+                .map(|(line, column)| source.span_at(line, column))
+                .unwrap_or_else(Span::call_site);
+            return compile_error(&message, span);
+        }
+    };
+
+    let mut hasher = DefaultHasher::new();
+    source.text.hash(&mut hasher);
+    let module = format!("__quiche_{:016x}", hasher.finish());
+    let expanded = format!(
+        "#[allow(dead_code, unused_imports, non_snake_case)]\n\
+         mod {module} {{\n{rust}\nuse super::*;\n}}\n\
+         #[allow(unused_imports)]\nuse {module}::*;\n"
+    );
+    expanded.parse().unwrap_or_else(|e| {
+        compile_error(
+            &format!("quiche! generated invalid Rust: {e}"),
+            Span::call_site(),
+        )
+    })
+}
+
+/// Quiche source rebuilt from the macro's tokens, with the span of every
+/// token so compiler errors can point back into the Rust file.
+struct Source {
+    text: String,
+    /// `(line, column, span)` of each token in `text`, in order, 1-based.
+    spans: Vec<(usize, usize, Span)>,
+}
+
+impl Source {
+    /// Lay the tokens out at their original lines and columns — Quiche is
+    /// indentation-sensitive — shifted left so the least-indented line
+    /// starts at column 1.
+    fn from_tokens(input: TokenStream) -> Source {
+        let mut atoms = Vec::new();
+        flatten(input, &mut atoms);
+
+        let mut line_starts = Vec::new();
+        let mut last_line = 0;
+        for (_, span) in &atoms {
+            if span.line() != last_line {
+                line_starts.push(span.column());
+                last_line = span.line();
+            }
+        }
+        let base = line_starts.iter().copied().min().unwrap_or(1);
+
+        let mut source = Source {
+            text: String::new(),
+            spans: Vec::new(),
+        };
+        let (mut src_line, mut src_col) = (0, 0);
+        let (mut out_line, mut out_col) = (1, 1);
+        for (i, (text, span)) in atoms.iter().enumerate() {
+            if i == 0 {
+                src_line = span.line();
+            } else if span.line() > src_line {
+                for _ in src_line..span.line() {
+                    source.text.push('\n');
+                }
+                out_line += span.line() - src_line;
+                out_col = 1;
+                src_line = span.line();
+                let indent = span.column().saturating_sub(base);
+                source.text.push_str(&" ".repeat(indent));
+                out_col += indent;
+            } else {
+                let gap = span.column().saturating_sub(src_col);
+                source.text.push_str(&" ".repeat(gap));
+                out_col += gap;
+            }
+            source.spans.push((out_line, out_col, *span));
+            source.text.push_str(text);
+            out_col += text.chars().count();
+
+            let end = span.end();
+            if end.line() > src_line {
+                out_line += end.line() - src_line;
+                out_col = text.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+            }
+            src_line = end.line();
+            src_col = end.column();
+        }
+        source.text.push('\n');
+        source
+    }
+
+    /// Span of the token at or just before `line:column` of the rebuilt text.
+    fn span_at(&self, line: usize, column: usize) -> Span {
+        self.spans
+            .iter()
+            .take_while(|(l, c, _)| (*l, *c) <= (line, column))
+            .last()
+            .map_or_else(Span::call_site, |(_, _, span)| *span)
+    }
+}
+
+/// Flatten a token stream into `(text, span)` atoms, with each group's
+/// delimiters as atoms of their own.
+fn flatten(input: TokenStream, atoms: &mut Vec<(String, Span)>) {
+    for tree in input {
+        match tree {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::None => ("", ""),
+                };
+                if !open.is_empty() {
+                    atoms.push((open.to_string(), group.span_open()));
+                }
+                flatten(group.stream(), atoms);
+                if !close.is_empty() {
+                    atoms.push((close.to_string(), group.span_close()));
+                }
+            }
+            TokenTree::Ident(ident) => atoms.push((ident.to_string(), ident.span())),
+            TokenTree::Punct(punct) => atoms.push((punct.as_char().to_string(), punct.span())),
+            TokenTree::Literal(lit) => atoms.push((lit.to_string(), lit.span())),
+        }
+    }
+}
+
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut args = Group::new(Delimiter::Parenthesis, TokenTree::from(message).into());
+    args.set_span(span);
+    let mut semi = Punct::new(';', Spacing::Alone);
+    semi.set_span(span);
+    [
+        TokenTree::from(Ident::new("compile_error", span)),
+        bang.into(),
+        args.into(),
+        semi.into(),
+    ]
+    .into_iter()
+    .collect()
+}
@@ -0,0 +1,25 @@
+//! `quiche!` items are usable from the surrounding Rust module.
+
+use quiche_macros::quiche;
+
+quiche! {
+    def double(x: i64) -> i64:
+        return x * 2
+
+    def clamp(x: i64, lo: i64, hi: i64) -> i64:
+        if x < lo:
+            return lo
+        if x > hi:
+            return hi
+        return x
+}
+
+quiche! { def triple(x: i64) -> i64: return x * 3 }
+
+#[test]
+fn test_inline_functions_are_callable() {
+    assert_eq!(double(21), 42);
+    assert_eq!(clamp(15, 0, 10), 10);
+    assert_eq!(clamp(-1, 0, 10), 0);
+    assert_eq!(triple(double(1)), 6);
+}
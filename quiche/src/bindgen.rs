@@ -0,0 +1,574 @@
+//! `quiche bindgen` — Quiche interface stubs (`.qi`) from rustdoc JSON.
+//!
+//! Reads the JSON emitted by `rustdoc --output-format json` and writes one
+//! `@extern` declaration per public free function, so calls into the crate
+//! are checked like any other `@extern`:
+//!
+//! ```text
+//! @extern(path="serde_json::from_str")
+//! def from_str[T](s: Str) -> Result[T, Error]:
+//!     pass
+//! ```
+//!
+//! Public types are listed as comments with their method sets. Functions
+//! whose signatures use types Quiche cannot spell (`impl Trait`, `dyn`,
+//! function pointers, ...) are listed as skipped.
+//!
+//! A module picks up `<name>.qi` from its own directory with `import <name>`.
+
+use std::collections::HashMap;
+
+/// Render the `.qi` interface for the crate described by `rustdoc_json`.
+pub fn generate_interface(rustdoc_json: &str) -> Result<String, String> {
+    let doc = json::parse(rustdoc_json)?;
+    let index: HashMap<String, &json::Value> = doc
+        .get("index")
+        .and_then(json::Value::as_object)
+        .ok_or("rustdoc JSON has no 'index'")?
+        .iter()
+        .map(|(k, v)| (k.clone(), v))
+        .collect();
+    let root = doc
+        .get("root")
+        .and_then(json::Value::as_id)
+        .ok_or("rustdoc JSON has no 'root'")?;
+    let root_item = index.get(&root).ok_or("root module missing from index")?;
+    let crate_name = root_item
+        .get("name")
+        .and_then(json::Value::as_str)
+        .ok_or("root module has no name")?;
+
+    let mut out = Interface::default();
+    visit_module(&index, root_item, &[crate_name.to_string()], &mut out);
+
+    let mut text = format!(
+        "# Generated by `quiche bindgen` from rustdoc JSON for crate `{crate_name}`.\n\
+         # Calls are checked against these signatures; edit types if needed.\n"
+    );
+    if !out.types.is_empty() {
+        text.push('\n');
+        for line in &out.types {
+            text.push_str(&format!("# {line}\n"));
+        }
+    }
+    for decl in &out.functions {
+        text.push('\n');
+        text.push_str(decl);
+    }
+    if !out.skipped.is_empty() {
+        text.push('\n');
+        for name in &out.skipped {
+            text.push_str(&format!("# skipped {name}: unsupported signature\n"));
+        }
+    }
+    Ok(text)
+}
+
+#[derive(Default)]
+struct Interface {
+    functions: Vec<String>,
+    types: Vec<String>,
+    skipped: Vec<String>,
+}
+
+fn visit_module(
+    index: &HashMap<String, &json::Value>,
+    module: &json::Value,
+    path: &[String],
+    out: &mut Interface,
+) {
+    let children = module
+        .get("inner")
+        .and_then(|i| i.get("module"))
+        .and_then(|m| m.get("items"))
+        .and_then(json::Value::as_array)
+        .unwrap_or(&[]);
+    for child in children {
+        let Some(item) = child.as_id().and_then(|id| index.get(&id)) else {
+            continue;
+        };
+        if item.get("visibility").and_then(json::Value::as_str) != Some("public") {
+            continue;
+        }
+        let (Some(name), Some(inner)) = (
+            item.get("name").and_then(json::Value::as_str),
+            item.get("inner"),
+        ) else {
+            continue;
+        };
+        if let Some(function) = inner.get("function") {
+            let full = format!("{}::{name}", path.join("::"));
+            match extern_decl(name, &full, function) {
+                Some(decl) => out.functions.push(decl),
+                None => out.skipped.push(full),
+            }
+        } else if inner.get("module").is_some() {
+            let mut sub = path.to_vec();
+            sub.push(name.to_string());
+            visit_module(index, item, &sub, out);
+        } else if let Some(kind) = ["struct", "enum"]
+            .into_iter()
+            .find(|k| inner.get(k).is_some())
+        {
+            let methods = method_set(index, &inner[kind]);
+            out.types.push(if methods.is_empty() {
+                format!("type {name} ({kind})")
+            } else {
+                format!("type {name} ({kind}): {}", methods.join(", "))
+            });
+        }
+    }
+}
+
+/// Public inherent method signatures of a struct/enum, e.g. `len(self) -> usize`.
+fn method_set(index: &HashMap<String, &json::Value>, def: &json::Value) -> Vec<String> {
+    let impls = def
+        .get("impls")
+        .and_then(json::Value::as_array)
+        .unwrap_or(&[]);
+    let mut methods = Vec::new();
+    for impl_id in impls {
+        let Some(block) = impl_id
+            .as_id()
+            .and_then(|id| index.get(&id))
+            .and_then(|item| item.get("inner"))
+            .and_then(|inner| inner.get("impl"))
+        else {
+            continue;
+        };
+        // Trait impls are reachable through the trait, not as inherent methods
+        if !matches!(block.get("trait"), None | Some(json::Value::Null)) {
+            continue;
+        }
+        for id in block
+            .get("items")
+            .and_then(json::Value::as_array)
+            .unwrap_or(&[])
+        {
+            let Some(item) = id.as_id().and_then(|id| index.get(&id)) else {
+                continue;
+            };
+            if item.get("visibility").and_then(json::Value::as_str) != Some("public") {
+                continue;
+            }
+            let (Some(name), Some(function)) = (
+                item.get("name").and_then(json::Value::as_str),
+                item.get("inner").and_then(|i| i.get("function")),
+            ) else {
+                continue;
+            };
+            if let Some((params, ret)) = signature(function) {
+                methods.push(format!("{name}({}){ret}", params.join(", ")));
+            }
+        }
+    }
+    methods
+}
+
+fn extern_decl(name: &str, path: &str, function: &json::Value) -> Option<String> {
+    let (params, ret) = signature(function)?;
+    if params.first().is_some_and(|p| p == "self") {
+        return None;
+    }
+    let generics: Vec<&str> = function
+        .get("generics")
+        .and_then(|g| g.get("params"))
+        .and_then(json::Value::as_array)
+        .unwrap_or(&[])
+        .iter()
+        .filter(|p| p.get("kind").and_then(|k| k.get("type")).is_some())
+        .filter_map(|p| p.get("name").and_then(json::Value::as_str))
+        // `impl Trait` arguments show up as synthetic generics
+        .filter(|n| !n.starts_with("impl "))
+        .collect();
+    let generics = if generics.is_empty() {
+        String::new()
+    } else {
+        format!("[{}]", generics.join(", "))
+    };
+    Some(format!(
+        "@extern(path=\"{path}\")\ndef {name}{generics}({}){ret}:\n    pass\n",
+        params.join(", ")
+    ))
+}
+
+/// Quiche parameter list and ` -> Ret` suffix, or `None` if any type is
+/// not expressible in Quiche.
+fn signature(function: &json::Value) -> Option<(Vec<String>, String)> {
+    // `sig` in current rustdoc, `decl` in older format versions
+    let sig = function.get("sig").or_else(|| function.get("decl"))?;
+    let mut params = Vec::new();
+    for input in sig.get("inputs")?.as_array()? {
+        let pair = input.as_array()?;
+        let name = pair.first()?.as_str()?;
+        if name == "self" {
+            params.push("self".to_string());
+            continue;
+        }
+        params.push(format!("{name}: {}", quiche_type(pair.get(1)?)?));
+    }
+    let ret = match sig.get("output") {
+        None | Some(json::Value::Null) => String::new(),
+        Some(ty) => match quiche_type(ty)?.as_str() {
+            "()" => String::new(),
+            ty => format!(" -> {ty}"),
+        },
+    };
+    Some((params, ret))
+}
+
+/// Spell a rustdoc type in Quiche syntax.
+fn quiche_type(ty: &json::Value) -> Option<String> {
+    if let Some(name) = ty.get("primitive").and_then(json::Value::as_str) {
+        return Some(if name == "str" { "Str" } else { name }.to_string());
+    }
+    if let Some(name) = ty.get("generic").and_then(json::Value::as_str) {
+        return Some(name.to_string());
+    }
+    if let Some(inner) = ty.get("borrowed_ref").and_then(|r| r.get("type")) {
+        return quiche_type(inner);
+    }
+    if let Some(inner) = ty.get("slice") {
+        return Some(format!("Vec[{}]", quiche_type(inner)?));
+    }
+    if let Some(items) = ty.get("tuple").and_then(json::Value::as_array) {
+        if items.is_empty() {
+            return Some("()".to_string());
+        }
+        let items: Option<Vec<String>> = items.iter().map(quiche_type).collect();
+        return Some(format!("Tuple[{}]", items?.join(", ")));
+    }
+    let path = ty.get("resolved_path")?;
+    let name = path
+        .get("path")
+        .or_else(|| path.get("name"))
+        .and_then(json::Value::as_str)?;
+    let name = name.rsplit("::").next().unwrap_or(name);
+    let name = if name == "String" { "Str" } else { name };
+    let args = path
+        .get("args")
+        .and_then(|a| a.get("angle_bracketed"))
+        .and_then(|a| a.get("args"))
+        .and_then(json::Value::as_array)
+        .unwrap_or(&[]);
+    let args: Vec<String> = args
+        .iter()
+        .filter_map(|a| a.get("type"))
+        .map(quiche_type)
+        .collect::<Option<_>>()?;
+    Some(if args.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name}[{}]", args.join(", "))
+    })
+}
+
+/// Just enough JSON to read rustdoc output.
+mod json {
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Value {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    impl Value {
+        pub fn get(&self, key: &str) -> Option<&Value> {
+            match self {
+                Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+                _ => None,
+            }
+        }
+
+        pub fn as_str(&self) -> Option<&str> {
+            match self {
+                Value::String(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        pub fn as_array(&self) -> Option<&[Value]> {
+            match self {
+                Value::Array(items) => Some(items),
+                _ => None,
+            }
+        }
+
+        pub fn as_object(&self) -> Option<&[(String, Value)]> {
+            match self {
+                Value::Object(fields) => Some(fields),
+                _ => None,
+            }
+        }
+
+        /// Item ids are strings in older format versions, integers in newer.
+        pub fn as_id(&self) -> Option<String> {
+            match self {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(format!("{n}")),
+                _ => None,
+            }
+        }
+    }
+
+    impl std::ops::Index<&str> for Value {
+        type Output = Value;
+        fn index(&self, key: &str) -> &Value {
+            self.get(key).unwrap_or(&Value::Null)
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut p = Parser {
+            bytes: text.as_bytes(),
+            pos: 0,
+        };
+        let value = p.value()?;
+        p.skip_ws();
+        if p.pos != p.bytes.len() {
+            return Err(p.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    struct Parser<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl Parser<'_> {
+        fn error(&self, message: &str) -> String {
+            format!("invalid JSON at byte {}: {message}", self.pos)
+        }
+
+        fn skip_ws(&mut self) {
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(u8::is_ascii_whitespace)
+            {
+                self.pos += 1;
+            }
+        }
+
+        fn eat(&mut self, byte: u8) -> bool {
+            self.skip_ws();
+            if self.bytes.get(self.pos) == Some(&byte) {
+                self.pos += 1;
+                true
+            } else {
+                false
+            }
+        }
+
+        fn expect(&mut self, byte: u8) -> Result<(), String> {
+            if self.eat(byte) {
+                Ok(())
+            } else {
+                Err(self.error(&format!("expected '{}'", byte as char)))
+            }
+        }
+
+        fn keyword(&mut self, word: &str, value: Value) -> Result<Value, String> {
+            if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+                self.pos += word.len();
+                Ok(value)
+            } else {
+                Err(self.error("unexpected token"))
+            }
+        }
+
+        fn value(&mut self) -> Result<Value, String> {
+            self.skip_ws();
+            match self.bytes.get(self.pos) {
+                Some(b'{') => {
+                    self.pos += 1;
+                    let mut fields = Vec::new();
+                    if self.eat(b'}') {
+                        return Ok(Value::Object(fields));
+                    }
+                    loop {
+                        self.skip_ws();
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        fields.push((key, self.value()?));
+                        if !self.eat(b',') {
+                            self.expect(b'}')?;
+                            return Ok(Value::Object(fields));
+                        }
+                    }
+                }
+                Some(b'[') => {
+                    self.pos += 1;
+                    let mut items = Vec::new();
+                    if self.eat(b']') {
+                        return Ok(Value::Array(items));
+                    }
+                    loop {
+                        items.push(self.value()?);
+                        if !self.eat(b',') {
+                            self.expect(b']')?;
+                            return Ok(Value::Array(items));
+                        }
+                    }
+                }
+                Some(b'"') => Ok(Value::String(self.string()?)),
+                Some(b't') => self.keyword("true", Value::Bool(true)),
+                Some(b'f') => self.keyword("false", Value::Bool(false)),
+                Some(b'n') => self.keyword("null", Value::Null),
+                Some(_) => self.number(),
+                None => Err(self.error("unexpected end of input")),
+            }
+        }
+
+        fn number(&mut self) -> Result<Value, String> {
+            let start = self.pos;
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+            {
+                self.pos += 1;
+            }
+            std::str::from_utf8(&self.bytes[start..self.pos])
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .map(Value::Number)
+                .ok_or_else(|| self.error("invalid number"))
+        }
+
+        fn string(&mut self) -> Result<String, String> {
+            if self.bytes.get(self.pos) != Some(&b'"') {
+                return Err(self.error("expected string"));
+            }
+            self.pos += 1;
+            let mut out = Vec::new();
+            loop {
+                match self.bytes.get(self.pos) {
+                    None => return Err(self.error("unterminated string")),
+                    Some(b'"') => {
+                        self.pos += 1;
+                        return String::from_utf8(out).map_err(|_| self.error("invalid UTF-8"));
+                    }
+                    Some(b'\\') => {
+                        let escaped = self.bytes.get(self.pos + 1).copied();
+                        self.pos += 2;
+                        let ch = match escaped {
+                            Some(b'n') => '\n',
+                            Some(b't') => '\t',
+                            Some(b'r') => '\r',
+                            Some(b'b') => '\u{8}',
+                            Some(b'f') => '\u{c}',
+                            Some(b'u') => {
+                                let code = self.hex4()?;
+                                // Surrogate pairs encode astral-plane characters
+                                let code = if (0xd800..0xdc00).contains(&code)
+                                    && self.bytes[self.pos..].starts_with(b"\\u")
+                                {
+                                    self.pos += 2;
+                                    let low = self.hex4()?;
+                                    0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00))
+                                } else {
+                                    code
+                                };
+                                char::from_u32(code).unwrap_or('\u{fffd}')
+                            }
+                            Some(other) => other as char,
+                            None => return Err(self.error("unterminated escape")),
+                        };
+                        let mut buf = [0; 4];
+                        out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                    }
+                    Some(&b) => {
+                        out.push(b);
+                        self.pos += 1;
+                    }
+                }
+            }
+        }
+
+        fn hex4(&mut self) -> Result<u32, String> {
+            let digits = self
+                .bytes
+                .get(self.pos..self.pos + 4)
+                .and_then(|d| std::str::from_utf8(d).ok())
+                .and_then(|d| u32::from_str_radix(d, 16).ok())
+                .ok_or_else(|| self.error("invalid \\u escape"))?;
+            self.pos += 4;
+            Ok(digits)
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = r#"{
+        "root": 0,
+        "format_version": 39,
+        "index": {
+            "0": {"name": "mini", "visibility": "public",
+                  "inner": {"module": {"items": [1, 2, 3, 5, 6]}}},
+            "1": {"name": "from_str", "visibility": "public",
+                  "inner": {"function": {
+                      "generics": {"params": [{"name": "T", "kind": {"type": {}}}]},
+                      "sig": {"inputs": [["s", {"borrowed_ref": {"type": {"primitive": "str"}}}]],
+                              "output": {"resolved_path": {"path": "Result", "args": {"angle_bracketed": {"args": [
+                                  {"type": {"generic": "T"}},
+                                  {"type": {"resolved_path": {"path": "Error", "args": null}}}]}}}}}}}},
+            "2": {"name": "hidden", "visibility": "crate",
+                  "inner": {"function": {"sig": {"inputs": [], "output": null}}}},
+            "3": {"name": "Value", "visibility": "public",
+                  "inner": {"struct": {"impls": [4]}}},
+            "4": {"name": null, "visibility": "default",
+                  "inner": {"impl": {"trait": null, "items": [7]}}},
+            "5": {"name": "apply", "visibility": "public",
+                  "inner": {"function": {"sig": {"inputs": [["f", {"dyn_trait": {}}]], "output": null}}}},
+            "6": {"name": "util", "visibility": "public",
+                  "inner": {"module": {"items": [8]}}},
+            "7": {"name": "is_null", "visibility": "public",
+                  "inner": {"function": {"sig": {"inputs": [["self", {"borrowed_ref": {"type": {"generic": "Self"}}}]],
+                                                 "output": {"primitive": "bool"}}}}},
+            "8": {"name": "join", "visibility": "public",
+                  "inner": {"function": {"sig": {"inputs": [["parts", {"borrowed_ref": {"type": {"slice": {"resolved_path": {"path": "String"}}}}}]],
+                                                 "output": {"resolved_path": {"path": "std::string::String"}}}}}}
+        }
+    }"#;
+
+    #[test]
+    fn test_interface_from_rustdoc_json() {
+        let qi = generate_interface(DOC).unwrap();
+        assert!(qi.contains(
+            "@extern(path=\"mini::from_str\")\ndef from_str[T](s: Str) -> Result[T, Error]:\n    pass\n"
+        ));
+        assert!(qi.contains(
+            "@extern(path=\"mini::util::join\")\ndef join(parts: Vec[Str]) -> Str:\n    pass\n"
+        ));
+        assert!(qi.contains("# type Value (struct): is_null(self) -> bool\n"));
+        assert!(qi.contains("# skipped mini::apply: unsupported signature\n"));
+        assert!(!qi.contains("hidden"));
+    }
+
+    #[test]
+    fn test_generated_interface_parses() {
+        let qi = generate_interface(DOC).unwrap();
+        let parsed = crate::parser::parse_decorated(&qi).unwrap();
+        assert_eq!(parsed.extern_crates, vec!["mini"]);
+    }
+
+    #[test]
+    fn test_json_strings_and_errors() {
+        let value = json::parse(r#"{"a": ["x\"y", "é😀", -1.5e2, true, null]}"#).unwrap();
+        let items = value["a"].as_array().unwrap();
+        assert_eq!(items[0].as_str(), Some("x\"y"));
+        assert_eq!(items[1].as_str(), Some("é😀"));
+        assert_eq!(items[2], json::Value::Number(-150.0));
+        assert!(json::parse("{\"a\": }").is_err());
+        assert!(json::parse("[1] 2").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Build-script support: compile a tree of `.q` files from `build.rs`.
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     let out_dir = std::env::var("OUT_DIR").unwrap();
+//!     if let Err(e) = quiche::compile_dir("src".as_ref(), out_dir.as_ref()) {
+//!         panic!("{e}");
+//!     }
+//! }
+//!
+//! // src/lib.rs
+//! include!(concat!(env!("OUT_DIR"), "/quiche_modules.rs"));
+//! ```
+//!
+//! [`crate::CompilePipeline`] does the same with passes of the build
+//! script's own run over each module.
+
+use crate::diagnostics::Report;
+use crate::pipeline::CompilePipeline;
+use crate::qmeta::ModuleMeta;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the generated file declaring one Rust module per `.q` file.
+pub const MODULES_FILE: &str = "quiche_modules.rs";
+
+/// Compile every `.q` file under `src_dir` into `out_dir`, mirroring the
+/// directory layout (`src/net/http.q` → `out/net/http.rs`), and write
+/// [`MODULES_FILE`] declaring them as nested modules (`net::http`).
+///
+/// Each module's symbol tables are written first (`out/net/http.qmeta`, see
+/// [`crate::qmeta`]) and loaded when compiling the modules that import it,
+/// so keyword arguments, positional construction and class attributes work
+/// across modules.
+///
+/// Prints `cargo::rerun-if-changed` for each source and interface file, so
+/// it is meant to be called from a build script. All files are compiled even
+/// after a failure; the error is one [`Report`] of every file's diagnostics.
+/// Returns the generated `.rs` paths.
+pub fn compile_dir(src_dir: &Path, out_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    compile_dir_with(&CompilePipeline::new(), src_dir, out_dir)
+}
+
+/// [`compile_dir`], compiling each file with `pipeline`.
+pub(crate) fn compile_dir_with(
+    pipeline: &CompilePipeline,
+    src_dir: &Path,
+    out_dir: &Path,
+) -> Result<Vec<PathBuf>, String> {
+    println!("cargo::rerun-if-changed={}", src_dir.display());
+
+    let sources = find_sources(src_dir, "q")?;
+    for interface in find_sources(src_dir, "qi")? {
+        println!("cargo::rerun-if-changed={}", interface.display());
+    }
+
+    // Symbol tables first, so every module can load its imports'. A file
+    // that fails to parse is reported once, by the compile below.
+    for relative in &sources {
+        let path = src_dir.join(relative);
+        let filename = path.display().to_string();
+        let Ok(meta) = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| crate::module_meta(&source, &filename))
+        else {
+            continue;
+        };
+        let meta_path = out_dir.join(relative).with_extension("qmeta");
+        if let Some(parent) = meta_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create {}: {e}", parent.display()))?;
+        }
+        fs::write(&meta_path, meta.render())
+            .map_err(|e| format!("failed to write {}: {e}", meta_path.display()))?;
+    }
+
+    let mut outputs = Vec::new();
+    let mut report = Report::new();
+    for relative in &sources {
+        let path = src_dir.join(relative);
+        println!("cargo::rerun-if-changed={}", path.display());
+        let filename = path.display().to_string();
+        report.file_compiled();
+        let compiled = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read {filename}: {e}"))
+            .and_then(|source| {
+                let imports = imported_meta(&source, &sources, out_dir)?;
+                pipeline.compile_file_with_imports(&source, &filename, &imports)
+            });
+        match compiled {
+            Ok(rust_code) => {
+                let out_path = out_dir.join(relative).with_extension("rs");
+                if let Some(parent) = out_path.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| format!("failed to create {}: {e}", parent.display()))?;
+                }
+                fs::write(&out_path, rust_code)
+                    .map_err(|e| format!("failed to write {}: {e}", out_path.display()))?;
+                outputs.push(out_path);
+            }
+            Err(e) => report.add_error(&filename, &e),
+        }
+    }
+    if !report.is_empty() {
+        return Err(report.render());
+    }
+
+    fs::create_dir_all(out_dir)
+        .map_err(|e| format!("failed to create {}: {e}", out_dir.display()))?;
+    let modules_path = out_dir.join(MODULES_FILE);
+    fs::write(&modules_path, module_tree(&sources, out_dir))
+        .map_err(|e| format!("failed to write {}: {e}", modules_path.display()))?;
+    Ok(outputs)
+}
+
+/// The `.qmeta` symbol tables of the project modules `source` imports
+/// (`from net.http import get`, or `crate.net.http`), read from `out_dir`.
+fn imported_meta(
+    source: &str,
+    sources: &[PathBuf],
+    out_dir: &Path,
+) -> Result<Vec<ModuleMeta>, String> {
+    let mut imports = Vec::new();
+    for import in crate::parser::parse_imports(source).map_err(|e| format!("{e}"))? {
+        let module = import
+            .module
+            .strip_prefix("crate.")
+            .unwrap_or(&import.module);
+        let relative: PathBuf = module.split('.').collect::<PathBuf>().with_extension("q");
+        if !sources.contains(&relative) {
+            continue;
+        }
+        let meta_path = out_dir.join(&relative).with_extension("qmeta");
+        let Ok(text) = fs::read_to_string(&meta_path) else {
+            continue;
+        };
+        let meta = ModuleMeta::parse(&text).map_err(|e| format!("{}: {e}", meta_path.display()))?;
+        imports.push(meta);
+    }
+    Ok(imports)
+}
+
+/// Files under `dir` with extension `ext`, relative to `dir`, sorted.
+pub fn find_sources(dir: &Path, ext: &str) -> Result<Vec<PathBuf>, String> {
+    let mut found = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let full = dir.join(&relative);
+        let entries =
+            fs::read_dir(&full).map_err(|e| format!("failed to read {}: {e}", full.display()))?;
+        for entry in entries.flatten() {
+            let path = relative.join(entry.file_name());
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                pending.push(path);
+            } else if path.extension().is_some_and(|e| e == ext) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Rust source declaring a module per compiled file, nested by directory.
+fn module_tree(sources: &[PathBuf], out_dir: &Path) -> String {
+    let mut out = String::from("// Generated by quiche::compile_dir -- do not edit.\n");
+    let mut open: Vec<String> = Vec::new();
+    for relative in sources {
+        let dirs: Vec<String> = relative
+            .parent()
+            .map(|p| {
+                p.components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let shared = open.iter().zip(&dirs).take_while(|(a, b)| a == b).count();
+        while open.len() > shared {
+            open.pop();
+            out.push_str(&format!("{}}}\n", "    ".repeat(open.len())));
+        }
+        for dir in &dirs[shared..] {
+            out.push_str(&format!("{}pub mod {dir} {{\n", "    ".repeat(open.len())));
+            open.push(dir.clone());
+        }
+        let stem = relative
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let path = out_dir.join(relative).with_extension("rs");
+        out.push_str(&format!(
+            "{indent}#[path = {path:?}]\n{indent}pub mod {stem};\n",
+            indent = "    ".repeat(open.len()),
+            path = path.display().to_string(),
+        ));
+    }
+    while open.pop().is_some() {
+        out.push_str(&format!("{}}}\n", "    ".repeat(open.len())));
+    }
+    out
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = crate::script::unique_temp_path(name, "");
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_find_sources_recurses_and_sorts() {
+        let dir = scratch_dir("quiche-find-sources");
+        fs::create_dir_all(dir.join("net")).unwrap();
+        for file in ["main.q", "net/http.q", "net/http.qi", "notes.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(
+            find_sources(&dir, "q").unwrap(),
+            vec![PathBuf::from("main.q"), PathBuf::from("net/http.q")]
+        );
+        assert_eq!(
+            find_sources(&dir, "qi").unwrap(),
+            vec![PathBuf::from("net/http.qi")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_module_tree_nests_directories() {
+        let sources = [
+            PathBuf::from("a/b/c.q"),
+            PathBuf::from("a/d.q"),
+            PathBuf::from("main.q"),
+        ];
+        let tree = module_tree(&sources, Path::new("/out"));
+        assert_eq!(
+            tree,
+            "// Generated by quiche::compile_dir -- do not edit.\n\
+             pub mod a {\n\
+             \x20   pub mod b {\n\
+             \x20       #[path = \"/out/a/b/c.rs\"]\n\
+             \x20       pub mod c;\n\
+             \x20   }\n\
+             \x20   #[path = \"/out/a/d.rs\"]\n\
+             \x20   pub mod d;\n\
+             }\n\
+             #[path = \"/out/main.rs\"]\n\
+             pub mod main;\n"
+        );
+    }
+
+    #[test]
+    fn test_imported_meta_reads_project_imports() {
+        let out = scratch_dir("quiche-imported-meta");
+        fs::create_dir_all(out.join("net")).unwrap();
+        fs::write(out.join("net/http.qmeta"), "fields Request url method\n").unwrap();
+        let sources = [PathBuf::from("main.q"), PathBuf::from("net/http.q")];
+        let source = "\"\"\"Docs.\nfrom net.http import Request\n\"\"\"\nfrom crate.net.http import Request\nfrom rust.std.fs import read\n";
+        let imports = imported_meta(source, &sources, &out).unwrap();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].struct_fields["Request"], ["url", "method"]);
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn test_compile_dir_reports_every_failing_file() {
+        let src = scratch_dir("quiche-compile-dir");
+        fs::create_dir_all(src.join("pkg")).unwrap();
+        fs::write(src.join("bad.q"), "def f(:\n").unwrap();
+        fs::write(src.join("pkg/worse.q"), "def g(x\n").unwrap();
+        let err = compile_dir(&src, &src.join("out")).unwrap_err();
+        assert!(
+            err.ends_with("error: 2 diagnostics in 2 of 2 files"),
+            "{err}"
+        );
+        let bad = err.find("bad.q:\nParse error at 1:").unwrap();
+        let worse = err.find("worse.q:\nParse error at ").unwrap();
+        assert!(bad < worse, "{err}");
+        fs::remove_dir_all(&src).unwrap();
+    }
+}
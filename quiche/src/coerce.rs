@@ -0,0 +1,502 @@
+//! Numeric coercion at binary operators and call boundaries.
+//!
+//! Rust never converts between numeric types implicitly, so `scale(x, 2)`
+//! with `def scale(v: f64, by: f64)` fails to compile, and so does `x * 2`
+//! for an `f64` `x`. [`coerce`] inserts the `as` casts Python's rules would
+//! imply, along the widening chain `i8 → i16 → i32 → i64 → f32 → f64`
+//! (unsigned integers widen to wider signed and unsigned ones too):
+//!
+//! - an integer literal next to a float operand, or passed for a float
+//!   parameter or field, becomes that float type;
+//! - the narrower operand of a binary operator is widened to the other's
+//!   type;
+//! - an argument is widened to its parameter's (or field's) type.
+//!
+//! Arguments that only convert the other way — `f64` into an `i32`
+//! parameter, `i64` into `u8` — are cast too, but each one is reported as a
+//! [`Warning`], since the value may not survive. An explicit `x as i32` in
+//! the source says the loss is intended and is not reported.
+//!
+//! Types are known for parameters, annotated or numerically-initialized
+//! locals, casts, and the results of calls to functions in the module;
+//! anything else is left for rustc.
+
+use elevate::ast as e;
+use std::collections::HashMap;
+
+/// A lossy conversion inserted at a call boundary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// The function the call is in.
+    pub function: String,
+    /// Byte offset of that function's definition, when the parser recorded
+    /// one.
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl Warning {
+    /// `file:line: warning: in `f`: message`, located in `source`.
+    pub fn render(&self, filename: &str, source: &str) -> String {
+        let location = match self.offset {
+            Some(offset) => {
+                let line = source[..offset.min(source.len())].matches('\n').count() + 1;
+                format!("{filename}:{line}")
+            }
+            None => filename.to_string(),
+        };
+        format!(
+            "{location}: warning: in `{}`: {}",
+            self.function, self.message
+        )
+    }
+}
+
+/// The numeric type of an expression, as far as the pass can tell.
+#[derive(Debug, Clone, PartialEq)]
+enum Numeric {
+    /// An integer literal, which Rust types by its context.
+    Literal,
+    /// A primitive numeric type such as `i32` or `f64`.
+    Typed(String),
+}
+
+/// Width in bits, signedness and floatness of a primitive numeric type.
+fn shape(ty: &str) -> Option<(u32, bool, bool)> {
+    Some(match ty {
+        "i8" => (8, true, false),
+        "i16" => (16, true, false),
+        "i32" => (32, true, false),
+        "i64" | "isize" => (64, true, false),
+        "i128" => (128, true, false),
+        "u8" => (8, false, false),
+        "u16" => (16, false, false),
+        "u32" => (32, false, false),
+        "u64" | "usize" => (64, false, false),
+        "u128" => (128, false, false),
+        "f32" => (32, true, true),
+        "f64" => (64, true, true),
+        _ => return None,
+    })
+}
+
+fn is_float(ty: &str) -> bool {
+    shape(ty).is_some_and(|(_, _, float)| float)
+}
+
+/// Whether every `from` value converts to `to` in the widening chain.
+fn widens(from: &str, to: &str) -> bool {
+    let (Some((from_bits, from_signed, from_float)), Some((to_bits, to_signed, to_float))) =
+        (shape(from), shape(to))
+    else {
+        return false;
+    };
+    match (from_float, to_float) {
+        (false, true) => true,
+        (true, false) => false,
+        (true, true) => from_bits < to_bits,
+        (false, false) => from_bits < to_bits && (to_signed || !from_signed),
+    }
+}
+
+/// The primitive numeric type `ty` names, if it is one.
+fn numeric_type(ty: &e::Type) -> Option<String> {
+    match ty.path.as_slice() {
+        [name] if ty.args.is_empty() && shape(name).is_some() => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Parameter (or field) types, by function (or type) name; `None` for the
+/// non-numeric ones.
+#[derive(Default)]
+struct Signatures {
+    params: HashMap<String, Vec<(String, Option<String>)>>,
+    returns: HashMap<String, String>,
+    fields: HashMap<String, HashMap<String, String>>,
+}
+
+impl Signatures {
+    fn of(module: &e::Module) -> Signatures {
+        let mut signatures = Signatures::default();
+        let mut add = |name: String, func: &e::FunctionDef| {
+            let params = func
+                .params
+                .iter()
+                .map(|p| (p.name.clone(), numeric_type(&p.ty)))
+                .collect();
+            signatures.params.insert(name.clone(), params);
+            if let Some(ret) = func.return_type.as_ref().and_then(numeric_type) {
+                signatures.returns.insert(name, ret);
+            }
+        };
+        for item in &module.items {
+            match item {
+                e::Item::Function(func) => add(func.name.clone(), func),
+                e::Item::Impl(block) => {
+                    for func in &block.methods {
+                        add(format!("{}::{}", block.target, func.name), func);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for item in &module.items {
+            if let e::Item::Struct(def) = item {
+                let fields = def
+                    .fields
+                    .iter()
+                    .filter_map(|f| Some((f.name.clone(), numeric_type(&f.ty)?)))
+                    .collect();
+                signatures.fields.insert(def.name.clone(), fields);
+            }
+        }
+        signatures
+    }
+}
+
+/// Insert the widening casts in every function of `module`, returning a
+/// warning for each lossy conversion.
+pub fn coerce(module: &mut e::Module) -> Vec<Warning> {
+    let signatures = Signatures::of(module);
+    let mut warnings = Vec::new();
+    for item in &mut module.items {
+        let functions: Vec<&mut e::FunctionDef> = match item {
+            e::Item::Function(func) => vec![func],
+            e::Item::Impl(block) => block.methods.iter_mut().collect(),
+            _ => continue,
+        };
+        for func in functions {
+            let mut pass = Pass {
+                signatures: &signatures,
+                locals: func
+                    .params
+                    .iter()
+                    .filter_map(|p| Some((p.name.clone(), Numeric::Typed(numeric_type(&p.ty)?))))
+                    .collect(),
+                function: func.name.clone(),
+                offset: func.span.map(|span| span.start),
+                warnings: &mut warnings,
+            };
+            pass.block(&mut func.body);
+        }
+    }
+    warnings
+}
+
+struct Pass<'a> {
+    signatures: &'a Signatures,
+    locals: HashMap<String, Numeric>,
+    function: String,
+    offset: Option<usize>,
+    warnings: &'a mut Vec<Warning>,
+}
+
+impl Pass<'_> {
+    fn block(&mut self, block: &mut e::Block) {
+        for stmt in &mut block.statements {
+            match stmt {
+                e::Stmt::Const(def) => {
+                    self.expr(&mut def.value);
+                    let ty = match &def.ty {
+                        Some(ty) => numeric_type(ty).map(Numeric::Typed),
+                        None => self.type_of(&def.value),
+                    };
+                    self.bind(&def.name, ty);
+                }
+                e::Stmt::DestructureConst { value, .. } => self.expr(value),
+                e::Stmt::Assign { target, op, value } => {
+                    self.expr(value);
+                    if let (e::AssignTarget::Path(name), e::AssignOp::Assign) = (target, op) {
+                        let ty = self.type_of(value);
+                        self.bind(name, ty);
+                    }
+                }
+                e::Stmt::Return(Some(value)) | e::Stmt::Expr(value) | e::Stmt::TailExpr(value) => {
+                    self.expr(value)
+                }
+                e::Stmt::If {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    self.expr(condition);
+                    self.block(then_block);
+                    if let Some(block) = else_block {
+                        self.block(block);
+                    }
+                }
+                e::Stmt::While { condition, body } => {
+                    self.expr(condition);
+                    self.block(body);
+                }
+                e::Stmt::For { iter, body, .. } => {
+                    self.expr(iter);
+                    self.block(body);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Record the type of the local `name`, or forget it when unknown.
+    fn bind(&mut self, name: &str, ty: Option<Numeric>) {
+        match ty {
+            Some(ty) => self.locals.insert(name.to_string(), ty),
+            None => self.locals.remove(name),
+        };
+    }
+
+    fn expr(&mut self, expr: &mut e::Expr) {
+        match expr {
+            e::Expr::Binary { op, left, right } => {
+                self.expr(left);
+                self.expr(right);
+                if matches!(op, e::BinaryOp::And | e::BinaryOp::Or) {
+                    return;
+                }
+                match (self.type_of(left), self.type_of(right)) {
+                    (Some(Numeric::Typed(l)), Some(Numeric::Typed(r))) => {
+                        if widens(&l, &r) {
+                            cast(left, &r);
+                        } else if widens(&r, &l) {
+                            cast(right, &l);
+                        }
+                    }
+                    (Some(Numeric::Literal), Some(Numeric::Typed(r))) if is_float(&r) => {
+                        cast(left, &r)
+                    }
+                    (Some(Numeric::Typed(l)), Some(Numeric::Literal)) if is_float(&l) => {
+                        cast(right, &l)
+                    }
+                    _ => {}
+                }
+            }
+            e::Expr::Call { callee, args } => {
+                self.expr(callee);
+                args.iter_mut().for_each(|arg| self.expr(arg));
+                let e::Expr::Path(path) = &**callee else {
+                    return;
+                };
+                let name = path.join("::");
+                if let Some(params) = self.signatures.params.get(&name) {
+                    for (arg, (param, ty)) in args.iter_mut().zip(params) {
+                        if let Some(ty) = ty {
+                            let what = format!("argument `{param}` of `{name}`");
+                            self.convert(arg, ty, &what);
+                        }
+                    }
+                }
+            }
+            e::Expr::StructLiteral { path, fields } => {
+                for field in fields.iter_mut() {
+                    self.expr(&mut field.value);
+                }
+                let name = path.join("::");
+                if let Some(types) = self.signatures.fields.get(&name) {
+                    for field in fields {
+                        if let Some(ty) = types.get(&field.name) {
+                            let what = format!("field `{}` of `{name}`", field.name);
+                            self.convert(&mut field.value, ty, &what);
+                        }
+                    }
+                }
+            }
+            e::Expr::MacroCall { args, .. } | e::Expr::Array(args) | e::Expr::Tuple(args) => {
+                args.iter_mut().for_each(|arg| self.expr(arg));
+            }
+            e::Expr::Field { base, .. } => self.expr(base),
+            e::Expr::Index { base, index } => {
+                self.expr(base);
+                self.expr(index);
+            }
+            e::Expr::Unary { expr, .. } | e::Expr::Cast { expr, .. } => self.expr(expr),
+            e::Expr::Range { start, end, .. } => {
+                for bound in [start, end].into_iter().flatten() {
+                    self.expr(bound);
+                }
+            }
+            e::Expr::Closure { body, .. } => self.block(body),
+            e::Expr::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                for arm in arms {
+                    self.expr(&mut arm.value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Cast `value` to `ty` where it doesn't have that type already,
+    /// warning when the conversion is lossy.
+    fn convert(&mut self, value: &mut e::Expr, ty: &str, what: &str) {
+        match self.type_of(value) {
+            Some(Numeric::Literal) if is_float(ty) => cast(value, ty),
+            Some(Numeric::Typed(from)) if from != ty => {
+                if !widens(&from, ty) {
+                    self.warnings.push(Warning {
+                        function: self.function.clone(),
+                        offset: self.offset,
+                        message: format!(
+                            "{from} value converted to {ty} for {what}, which may lose \
+                             information; write `as {ty}` to make the conversion explicit"
+                        ),
+                    });
+                }
+                cast(value, ty);
+            }
+            _ => {}
+        }
+    }
+
+    fn type_of(&self, expr: &e::Expr) -> Option<Numeric> {
+        match expr {
+            e::Expr::Int(_) => Some(Numeric::Literal),
+            e::Expr::Path(path) if path.len() == 1 => self.locals.get(&path[0]).cloned(),
+            e::Expr::Cast { target_type, .. } => numeric_type(target_type).map(Numeric::Typed),
+            e::Expr::Unary {
+                op: e::UnaryOp::Neg,
+                expr,
+            } => self.type_of(expr),
+            e::Expr::Binary {
+                op:
+                    e::BinaryOp::Add
+                    | e::BinaryOp::Sub
+                    | e::BinaryOp::Mul
+                    | e::BinaryOp::Div
+                    | e::BinaryOp::Rem,
+                left,
+                right,
+            } => match (self.type_of(left)?, self.type_of(right)?) {
+                (Numeric::Typed(ty), _) | (_, Numeric::Typed(ty)) => Some(Numeric::Typed(ty)),
+                _ => Some(Numeric::Literal),
+            },
+            e::Expr::Call { callee, .. } => match &**callee {
+                e::Expr::Path(path) => self
+                    .signatures
+                    .returns
+                    .get(&path.join("::"))
+                    .cloned()
+                    .map(Numeric::Typed),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Replace `expr` with `expr as ty`.
+fn cast(expr: &mut e::Expr, ty: &str) {
+    let inner = std::mem::replace(expr, e::Expr::Tuple(vec![]));
+    *expr = e::Expr::Cast {
+        expr: Box::new(inner),
+        target_type: e::Type {
+            path: vec![ty.to_string()],
+            args: vec![],
+            trait_bounds: vec![],
+        },
+    };
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn function<'a>(module: &'a e::Module, name: &str) -> &'a e::FunctionDef {
+        module
+            .items
+            .iter()
+            .find_map(|item| match item {
+                e::Item::Function(f) if f.name == name => Some(f),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn cast_to(expr: &e::Expr) -> Option<String> {
+        match expr {
+            e::Expr::Cast { target_type, .. } => Some(target_type.path.join("::")),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_widening_at_calls_and_operators() {
+        let source = "def scale(v: f64, by: f64) -> f64:\n    return v * by\n\n\
+                      def main(n: i32, x: f64, big: i64):\n    \
+                      scale(2, x)\n    \
+                      y = x * 2\n    \
+                      z = n + big\n    \
+                      scale(n, big)\n";
+        let mut module = crate::parser::parse(source).unwrap();
+        let warnings = coerce(&mut module);
+        assert!(warnings.is_empty(), "{warnings:?}");
+        let main = function(&module, "main");
+        let statement = |i: usize| match &main.body.statements[i] {
+            e::Stmt::Expr(expr) => expr.clone(),
+            e::Stmt::Const(def) => def.value.clone(),
+            e::Stmt::Assign { value, .. } => value.clone(),
+            other => panic!("unexpected {other:?}"),
+        };
+
+        let e::Expr::Call { args, .. } = statement(0) else {
+            panic!("expected a call");
+        };
+        assert_eq!(cast_to(&args[0]).as_deref(), Some("f64"));
+        assert_eq!(cast_to(&args[1]), None);
+
+        let e::Expr::Binary { right, .. } = statement(1) else {
+            panic!("expected x * 2");
+        };
+        assert_eq!(cast_to(&right).as_deref(), Some("f64"));
+
+        let e::Expr::Binary { left, right, .. } = statement(2) else {
+            panic!("expected n + big");
+        };
+        assert_eq!(cast_to(&left).as_deref(), Some("i64"));
+        assert_eq!(cast_to(&right), None);
+
+        let e::Expr::Call { args, .. } = statement(3) else {
+            panic!("expected a call");
+        };
+        assert_eq!(cast_to(&args[0]).as_deref(), Some("f64"));
+        assert_eq!(cast_to(&args[1]).as_deref(), Some("f64"));
+    }
+
+    #[test]
+    fn test_lossy_conversions_warn() {
+        let source = "type Pixel:\n    level: u8\n\n\
+                      def index(i: i32) -> i32:\n    return i\n\n\
+                      def main(x: f64, wide: i64):\n    \
+                      index(x)\n    \
+                      index(x as i32)\n    \
+                      p = Pixel(level=wide)\n";
+        let mut module = crate::parser::parse(source).unwrap();
+        let warnings = coerce(&mut module);
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert!(
+            warnings[0]
+                .message
+                .starts_with("f64 value converted to i32 for argument `i` of `index`"),
+            "{}",
+            warnings[0].message
+        );
+        assert!(
+            warnings[1]
+                .message
+                .starts_with("i64 value converted to u8 for field `level` of `Pixel`"),
+            "{}",
+            warnings[1].message
+        );
+        let rendered = warnings[0].render("app.q", source);
+        assert!(
+            rendered.starts_with("app.q:7: warning: in `main`: "),
+            "{rendered}"
+        );
+
+        assert!(widens("u32", "i64"));
+        assert!(!widens("i32", "u64"));
+        assert!(!widens("f64", "f32"));
+    }
+}
@@ -0,0 +1,582 @@
+//! `quiche daemon`: a compiler kept running between builds.
+//!
+//! Every `quiche` invocation otherwise starts a process, reads the imported
+//! `.qi` interfaces and compiles from scratch. The daemon listens on a Unix
+//! socket ([`socket_path`]) and answers compile requests from the CLI (when
+//! run with `--daemon` or [`USE_ENV`]) and any editor integration speaking
+//! the same protocol, keeping the compiled output of each file so an
+//! unchanged file is answered without compiling.
+//!
+//! Requests and replies are sequences of frames, `<kind> <length>\n`
+//! followed by `length` bytes, one request per connection:
+//!
+//! ```text
+//! → compile 16\n/src/app/main.q  version 5\n0.7.0  option 16\noverflow=checked
+//!   source 21\ndef main():\n    pass\n
+//! ← version 5\n0.7.0  warning 40\n…  ok 812\n<rust code>
+//!                                 (or: error 57\n<message>)
+//! → ping 0\n       ← ok 42\npid 4242, quiche 0.7.0, 3 cached, 17 served
+//! → stop 0\n       ← ok 0\n
+//! ```
+//!
+//! The source travels with the request, so an editor can compile a buffer
+//! that isn't saved; interfaces are read from disk next to the file's name,
+//! and `include_str` paths resolved against it, so the name must be
+//! absolute ([`Request::new`] makes it so): the daemon's working directory
+//! is not the client's.
+//!
+//! A compile request names the client's version, and a daemon of another
+//! version refuses it, so a daemon left running across an upgrade never
+//! answers for the new compiler; [`compile_at`] then returns `None` and the
+//! CLI compiles in-process.
+//! A reply is cached under the file name, options, source and interface
+//! texts, so an edited import misses the cache like an edited file does. It
+//! always compiles with the default prelude (see [`crate::prelude`]); the CLI
+//! compiles in-process when given another.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{CompileOptions, Overflow};
+
+/// Environment variable naming the socket, instead of
+/// `$QUICHE_HOME/daemon.sock`.
+pub const SOCKET_ENV: &str = "QUICHE_DAEMON_SOCKET";
+
+/// Environment variable that, set to `1`, has scripts and `quiche build`
+/// compile on a running daemon, as `--daemon` does.
+pub const USE_ENV: &str = "QUICHE_DAEMON";
+
+/// The version of quiche a daemon and its clients must share.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Replies kept before the cache is emptied and starts over.
+const CACHE_LIMIT: usize = 256;
+
+/// The socket the daemon listens on and the CLI tries first.
+pub fn socket_path() -> PathBuf {
+    match env::var_os(SOCKET_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => crate::vendor::quiche_home().join("daemon.sock"),
+    }
+}
+
+/// A file to compile, with the options it is compiled under.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Request {
+    /// Absolute, or relative to the daemon's working directory.
+    pub filename: String,
+    /// The client's quiche version, [`VERSION`] for the CLI.
+    pub version: String,
+    pub source: String,
+    /// `overflow=<mode>`, `allow-unsafe`, `fail-on-hot-clone`,
+    /// `move-mut-args` and `no-type-system`; see [`Request::new`].
+    pub options: Vec<String>,
+}
+
+impl Request {
+    /// A request for `filename`, made absolute against the current
+    /// directory.
+    pub fn new(
+        filename: &str,
+        source: &str,
+        options: &CompileOptions,
+        overflow: Overflow,
+        allow_unsafe: bool,
+    ) -> Request {
+        let mut names = vec![format!("overflow={}", overflow_name(overflow))];
+        for (on, name) in [
+            (allow_unsafe, "allow-unsafe"),
+            (options.fail_on_hot_clone, "fail-on-hot-clone"),
+            (options.experiments.move_mut_args, "move-mut-args"),
+            (!options.experiments.type_system, "no-type-system"),
+        ] {
+            if on {
+                names.push(name.to_string());
+            }
+        }
+        let filename = std::path::absolute(filename)
+            .map_or_else(|_| filename.to_string(), |path| path.display().to_string());
+        Request {
+            filename,
+            version: VERSION.to_string(),
+            source: source.to_string(),
+            options: names,
+        }
+    }
+
+    /// The options named in the request, in the form the compiler takes.
+    fn compile_options(&self) -> Result<(CompileOptions, Overflow, bool), String> {
+        let mut options = crate::default_options();
+        let mut overflow = Overflow::Native;
+        let mut allow_unsafe = false;
+        for name in &self.options {
+            match name.as_str() {
+                "allow-unsafe" => allow_unsafe = true,
+                "fail-on-hot-clone" => options.fail_on_hot_clone = true,
+                "move-mut-args" => options.experiments.move_mut_args = true,
+                "no-type-system" => options.experiments.type_system = false,
+                "overflow=native" => overflow = Overflow::Native,
+                other => match other.strip_prefix("overflow=") {
+                    Some(mode) => overflow = Overflow::parse(mode)?,
+                    None => return Err(format!("unknown compile option '{other}'")),
+                },
+            }
+        }
+        Ok((options, overflow, allow_unsafe))
+    }
+}
+
+fn overflow_name(overflow: Overflow) -> &'static str {
+    match overflow {
+        Overflow::Native => "native",
+        Overflow::Checked => "checked",
+        Overflow::Wrapping => "wrapping",
+        Overflow::Saturating => "saturating",
+    }
+}
+
+/// The daemon's answer to a [`Request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// Compiler warnings, rendered as they would be printed.
+    pub warnings: Vec<String>,
+    /// The Rust code, or the compile error.
+    pub result: Result<String, String>,
+}
+
+/// The state kept between requests.
+#[derive(Debug, Default)]
+pub struct Daemon {
+    cache: HashMap<u64, Reply>,
+    served: usize,
+}
+
+impl Daemon {
+    pub fn new() -> Daemon {
+        Daemon::default()
+    }
+
+    /// Compile `request`, or answer it from the cache.
+    pub fn compile(&mut self, request: &Request) -> Reply {
+        self.served += 1;
+        let interfaces = match crate::imported_interfaces(&request.source, &request.filename) {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                return Reply {
+                    warnings: Vec::new(),
+                    result: Err(e),
+                };
+            }
+        };
+        let mut hasher = DefaultHasher::new();
+        (request, &interfaces).hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(reply) = self.cache.get(&key) {
+            return reply.clone();
+        }
+        let reply = compile_request(request);
+        if self.cache.len() >= CACHE_LIMIT {
+            self.cache.clear();
+        }
+        self.cache.insert(key, reply.clone());
+        reply
+    }
+
+    /// `pid <n>, quiche <version>, <n> cached, <n> served`.
+    pub fn status(&self) -> String {
+        format!(
+            "pid {}, quiche {VERSION}, {} cached, {} served",
+            std::process::id(),
+            self.cache.len(),
+            self.served
+        )
+    }
+
+    /// Answer one connection's request frames; `None` for `stop`.
+    fn answer(&mut self, frames: Vec<(String, String)>) -> Option<Vec<(String, String)>> {
+        let mut frames = frames.into_iter();
+        let Some((command, payload)) = frames.next() else {
+            return Some(vec![error("empty request")]);
+        };
+        match command.as_str() {
+            "ping" => Some(vec![("ok".to_string(), self.status())]),
+            "stop" => None,
+            "compile" => {
+                let mut request = Request {
+                    filename: payload,
+                    version: String::new(),
+                    source: String::new(),
+                    options: Vec::new(),
+                };
+                for (kind, payload) in frames {
+                    match kind.as_str() {
+                        "version" => request.version = payload,
+                        "option" => request.options.push(payload),
+                        "source" => request.source = payload,
+                        other => return Some(vec![error(&format!("unknown frame '{other}'"))]),
+                    }
+                }
+                let mut out = vec![("version".to_string(), VERSION.to_string())];
+                if request.version != VERSION {
+                    out.push(error(&format!(
+                        "this daemon runs quiche {VERSION}, not {}",
+                        request.version
+                    )));
+                    return Some(out);
+                }
+                let reply = self.compile(&request);
+                out.extend(
+                    reply
+                        .warnings
+                        .into_iter()
+                        .map(|warning| ("warning".to_string(), warning)),
+                );
+                out.push(match reply.result {
+                    Ok(rust_code) => ("ok".to_string(), rust_code),
+                    Err(e) => ("error".to_string(), e),
+                });
+                Some(out)
+            }
+            other => Some(vec![error(&format!("unknown command '{other}'"))]),
+        }
+    }
+}
+
+fn error(message: &str) -> (String, String) {
+    ("error".to_string(), message.to_string())
+}
+
+/// What `quiche build` does with the file, warnings collected rather than
+/// printed.
+fn compile_request(request: &Request) -> Reply {
+    let (options, overflow, allow_unsafe) = match request.compile_options() {
+        Ok(options) => options,
+        Err(e) => {
+            return Reply {
+                warnings: Vec::new(),
+                result: Err(e),
+            };
+        }
+    };
+    let (result, warnings) = crate::capture_warnings(|| {
+        if !allow_unsafe {
+            crate::reject_unsafe(&request.source, &request.filename)?;
+        }
+        crate::compile_file_with_prelude(
+            &request.source,
+            &request.filename,
+            &options,
+            overflow,
+            &crate::Prelude::default(),
+        )
+    });
+    Reply { warnings, result }
+}
+
+/// Write one frame.
+pub fn write_frame(out: &mut impl Write, kind: &str, payload: &str) -> io::Result<()> {
+    writeln!(out, "{kind} {}", payload.len())?;
+    out.write_all(payload.as_bytes())
+}
+
+/// Read frames until the end of `input`.
+pub fn read_frames(input: impl Read) -> io::Result<Vec<(String, String)>> {
+    let mut input = BufReader::new(input);
+    let mut frames = Vec::new();
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(frames);
+        }
+        let bad = || io::Error::new(io::ErrorKind::InvalidData, "malformed frame header");
+        let (kind, len) = header.trim_end().split_once(' ').ok_or_else(bad)?;
+        let len: usize = len.parse().map_err(|_| bad())?;
+        let mut payload = vec![0; len];
+        input.read_exact(&mut payload)?;
+        let payload = String::from_utf8(payload)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "frame is not UTF-8"))?;
+        frames.push((kind.to_string(), payload));
+    }
+}
+
+/// Compile `request` on the daemon at `socket`; `None` when no daemon is
+/// listening there, or one of another version is, so the caller compiles it
+/// itself.
+pub fn compile_at(socket: &Path, request: &Request) -> Option<Reply> {
+    let mut frames = vec![
+        ("compile", request.filename.as_str()),
+        ("version", request.version.as_str()),
+    ];
+    frames.extend(request.options.iter().map(|name| ("option", name.as_str())));
+    frames.push(("source", request.source.as_str()));
+    let mut reply = Reply {
+        warnings: Vec::new(),
+        result: Err("the daemon closed the connection without replying".to_string()),
+    };
+    let mut frames = exchange(socket, &frames).ok()?.into_iter();
+    // A daemon from before the version frame has none to answer with
+    match frames.next() {
+        Some((kind, version)) if kind == "version" && version == request.version => {}
+        other => {
+            let running = match other {
+                Some((kind, version)) if kind == "version" => format!("quiche {version}"),
+                _ => "an older quiche".to_string(),
+            };
+            crate::warn(format!(
+                "warning: the daemon at {} runs {running}, not {}; compiling in-process (restart it with `quiche daemon stop`)",
+                socket.display(),
+                request.version
+            ));
+            return None;
+        }
+    }
+    for (kind, payload) in frames {
+        match kind.as_str() {
+            "warning" => reply.warnings.push(payload),
+            "ok" => reply.result = Ok(payload),
+            _ => reply.result = Err(payload),
+        }
+    }
+    Some(reply)
+}
+
+/// The status line of the daemon at `socket`.
+pub fn ping(socket: &Path) -> Result<String, String> {
+    let reply = exchange(socket, &[("ping", "")])
+        .map_err(|e| format!("no daemon at {}: {e}", socket.display()))?;
+    match reply.into_iter().next() {
+        Some((kind, status)) if kind == "ok" => Ok(status),
+        Some((_, e)) => Err(e),
+        None => Err("the daemon closed the connection without replying".to_string()),
+    }
+}
+
+/// Ask the daemon at `socket` to exit.
+pub fn stop(socket: &Path) -> Result<(), String> {
+    exchange(socket, &[("stop", "")])
+        .map(|_| ())
+        .map_err(|e| format!("no daemon at {}: {e}", socket.display()))
+}
+
+/// Send `frames` as one request and read the reply.
+#[cfg(unix)]
+fn exchange(socket: &Path, frames: &[(&str, &str)]) -> io::Result<Vec<(String, String)>> {
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket)?;
+    let mut out = io::BufWriter::new(&stream);
+    for (kind, payload) in frames {
+        write_frame(&mut out, kind, payload)?;
+    }
+    out.flush()?;
+    drop(out);
+    stream.shutdown(Shutdown::Write)?;
+    read_frames(&mut stream)
+}
+
+#[cfg(not(unix))]
+fn exchange(_socket: &Path, _frames: &[(&str, &str)]) -> io::Result<Vec<(String, String)>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the daemon needs Unix domain sockets",
+    ))
+}
+
+/// Listen on `socket` and answer requests, one at a time, until a `stop`
+/// request. A socket left behind by a daemon that didn't exit cleanly is
+/// replaced; one a daemon still answers on is an error.
+#[cfg(unix)]
+pub fn serve(socket: &Path) -> Result<(), String> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if UnixStream::connect(socket).is_ok() {
+        return Err(format!(
+            "a daemon is already running at {}",
+            socket.display()
+        ));
+    }
+    if socket.exists() {
+        std::fs::remove_file(socket)
+            .map_err(|e| format!("failed to remove stale {}: {e}", socket.display()))?;
+    }
+    if let Some(parent) = socket.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("failed to create {}: {e}", parent.display()))?;
+    }
+    let listener = UnixListener::bind(socket)
+        .map_err(|e| format!("failed to listen on {}: {e}", socket.display()))?;
+    let mut daemon = Daemon::new();
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        // A client that hangs up mid-request only loses its own reply
+        let Ok(frames) = read_frames(&mut stream) else {
+            continue;
+        };
+        let Some(reply) = daemon.answer(frames) else {
+            let _ = write_frame(&mut stream, "ok", "");
+            break;
+        };
+        let mut out = io::BufWriter::new(&stream);
+        for (kind, payload) in &reply {
+            if write_frame(&mut out, kind, payload).is_err() {
+                break;
+            }
+        }
+        let _ = out.flush();
+    }
+    std::fs::remove_file(socket).map_err(|e| format!("failed to remove {}: {e}", socket.display()))
+}
+
+#[cfg(not(unix))]
+pub fn serve(_socket: &Path) -> Result<(), String> {
+    Err("quiche daemon needs Unix domain sockets".to_string())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let mut out = Vec::new();
+        write_frame(&mut out, "source", "def main():\n    pass\n").unwrap();
+        write_frame(&mut out, "option", "").unwrap();
+        assert!(out.starts_with(b"source 21\ndef main"));
+        let frames = read_frames(out.as_slice()).unwrap();
+        assert_eq!(
+            frames,
+            vec![
+                ("source".to_string(), "def main():\n    pass\n".to_string()),
+                ("option".to_string(), String::new()),
+            ]
+        );
+        assert!(read_frames(&b"source x\n"[..]).is_err());
+        assert!(read_frames(&b"source 10\nshort"[..]).is_err());
+    }
+
+    #[test]
+    fn test_replies_match_the_compiler_and_are_cached() {
+        let options = crate::default_options();
+        let mut request = Request::new(
+            "main.q",
+            "def index(i: i32) -> i32:\n    return i\n\ndef main(x: f64):\n    index(x)\n",
+            &options,
+            Overflow::Checked,
+            false,
+        );
+        assert_eq!(request.options, ["overflow=checked"]);
+        let (parsed, overflow, allow_unsafe) = request.compile_options().unwrap();
+        assert_eq!(overflow, Overflow::Checked);
+        assert!(!allow_unsafe);
+        assert_eq!(
+            parsed.experiments.type_system,
+            options.experiments.type_system
+        );
+
+        let mut daemon = Daemon::new();
+        let reply = daemon.compile(&request);
+        assert_eq!(
+            reply.result,
+            crate::compile_file_with_overflow(
+                &request.source,
+                &request.filename,
+                &options,
+                Overflow::Checked
+            )
+        );
+        assert_eq!(reply.warnings.len(), 1, "{:?}", reply.warnings);
+        let main_q = std::path::absolute("main.q").unwrap();
+        assert_eq!(request.filename, main_q.display().to_string());
+        assert!(
+            reply.warnings[0].starts_with(&format!("{}:4: warning:", main_q.display())),
+            "{:?}",
+            reply.warnings
+        );
+        assert_eq!(daemon.compile(&request), reply);
+        assert!(daemon.status().ends_with(", 1 cached, 2 served"));
+
+        request.source.push_str("    index(1)\n");
+        daemon.compile(&request);
+        assert!(daemon.status().ends_with(", 2 cached, 3 served"));
+
+        request.options.push("overflow=sideways".to_string());
+        let reply = daemon.compile(&request);
+        assert!(reply.result.unwrap_err().contains("unknown overflow mode"));
+    }
+
+    #[test]
+    fn test_daemon_refuses_other_versions() {
+        let frames = |version: &str| {
+            vec![
+                ("compile".to_string(), "/src/main.q".to_string()),
+                ("version".to_string(), version.to_string()),
+                ("source".to_string(), "def main():\n    pass\n".to_string()),
+            ]
+        };
+        let mut daemon = Daemon::new();
+        let reply = daemon.answer(frames("0.0.1")).unwrap();
+        assert_eq!(reply[0], ("version".to_string(), VERSION.to_string()));
+        assert_eq!(reply[1].0, "error");
+        assert!(reply[1].1.contains("not 0.0.1"), "{:?}", reply);
+        assert!(daemon.status().ends_with(", 0 cached, 0 served"));
+
+        let reply = daemon.answer(frames(VERSION)).unwrap();
+        assert_eq!(reply[0], ("version".to_string(), VERSION.to_string()));
+        assert!(daemon.status().ends_with(", 1 served"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_answers_until_stopped() {
+        let socket = crate::script::unique_temp_path("quiche-daemon-test", "sock");
+        assert!(
+            compile_at(
+                &socket,
+                &Request::new(
+                    "a.q",
+                    "",
+                    &crate::default_options(),
+                    Overflow::Native,
+                    false
+                )
+            )
+            .is_none()
+        );
+        let server = {
+            let socket = socket.clone();
+            std::thread::spawn(move || serve(&socket))
+        };
+        while ping(&socket).is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let request = Request::new(
+            "broken.q",
+            "def main(:\n",
+            &crate::default_options(),
+            Overflow::Native,
+            false,
+        );
+        let reply = compile_at(&socket, &request).unwrap();
+        assert_eq!(reply.result, Daemon::new().compile(&request).result);
+        assert!(reply.result.is_err());
+        assert!(ping(&socket).unwrap().ends_with(", 1 cached, 1 served"));
+        let upgraded = Request {
+            version: "99.0.0".to_string(),
+            ..request
+        };
+        assert!(compile_at(&socket, &upgraded).is_none());
+        stop(&socket).unwrap();
+        server.join().unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,186 @@
+//! Aggregated diagnostics for multi-file compiles.
+//!
+//! Each file's compile error is split into individual diagnostics and
+//! located by line and column, so a [`Report`] over a whole project reads as
+//! one list: grouped by file, ordered by position, duplicates removed (a
+//! shared `.qi` interface can produce the same error for every importer),
+//! and closed by a summary line.
+
+use std::collections::BTreeMap;
+
+/// One compiler error in one file.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+    pub file: String,
+    /// 1-based position, or 0:0 when the message carries none.
+    pub line: usize,
+    pub column: usize,
+    /// The diagnostic as rendered by the compiler.
+    pub text: String,
+}
+
+/// Diagnostics collected across the files of one compile.
+#[derive(Debug, Default)]
+pub struct Report {
+    diagnostics: Vec<Diagnostic>,
+    files: usize,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `file` was compiled, whether or not it failed.
+    pub fn file_compiled(&mut self) {
+        self.files += 1;
+    }
+
+    /// Add the diagnostics in `message`, a compile error for `file`.
+    pub fn add_error(&mut self, file: &str, message: &str) {
+        for text in split_diagnostics(message) {
+            let (line, column) = error_location(&text, file).unwrap_or((0, 0));
+            self.diagnostics.push(Diagnostic {
+                file: file.to_string(),
+                line,
+                column,
+                text,
+            });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// The diagnostics, deduplicated and sorted by file and position.
+    pub fn diagnostics(&self) -> Vec<&Diagnostic> {
+        let mut sorted: Vec<&Diagnostic> = self.diagnostics.iter().collect();
+        sorted.sort();
+        sorted.dedup();
+        sorted
+    }
+
+    /// The report as text: a section per file, then a summary line such as
+    /// `error: 3 diagnostics in 2 of 5 files`.
+    pub fn render(&self) -> String {
+        let mut by_file: BTreeMap<&str, Vec<&Diagnostic>> = BTreeMap::new();
+        for diagnostic in self.diagnostics() {
+            by_file
+                .entry(&diagnostic.file)
+                .or_default()
+                .push(diagnostic);
+        }
+        let count: usize = by_file.values().map(Vec::len).sum();
+        let mut out = String::new();
+        for (file, diagnostics) in &by_file {
+            out.push_str(&format!("{file}:\n"));
+            for diagnostic in diagnostics {
+                out.push_str(diagnostic.text.trim_end());
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "error: {count} {} in {} of {} {}",
+            plural(count, "diagnostic"),
+            by_file.len(),
+            self.files.max(by_file.len()),
+            plural(self.files.max(by_file.len()), "file"),
+        ));
+        out
+    }
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        word.to_string()
+    } else {
+        format!("{word}s")
+    }
+}
+
+/// Split a compile error holding several rendered diagnostics at each line
+/// that starts a new one (`error: ...`, `error[E0308]: ...`).
+fn split_diagnostics(message: &str) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    for line in message.lines() {
+        if line.starts_with("error") || parts.is_empty() {
+            parts.push(String::new());
+        }
+        if let Some(part) = parts.last_mut() {
+            part.push_str(line);
+            part.push('\n');
+        }
+    }
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// `line:column` of a compile error for `source_name`: either a rendered
+/// diagnostic pointing at `source_name:line:column`, or a parse error
+/// (`Parse error at line:column: ...`).
+pub fn error_location(message: &str, source_name: &str) -> Option<(usize, usize)> {
+    let rest = match message.find(source_name) {
+        Some(at) => message[at + source_name.len()..].strip_prefix(':')?,
+        None => message
+            .find("Parse error at ")
+            .map(|at| &message[at + "Parse error at ".len()..])?,
+    };
+    let mut parts = rest.splitn(3, ':');
+    let line = parts.next()?.trim().parse().ok()?;
+    let column = parts
+        .next()?
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()
+        .ok()?;
+    Some((line, column))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_location_reads_rendered_and_parse_errors() {
+        assert_eq!(
+            error_location("error: mismatched types\n  --> src/a.q:4:9\n", "src/a.q"),
+            Some((4, 9))
+        );
+        assert_eq!(
+            error_location("Parse error at 2:7: expected ')'", "src/a.q"),
+            Some((2, 7))
+        );
+        assert_eq!(error_location("failed to read src/a.q", "src/a.q"), None);
+    }
+
+    #[test]
+    fn test_report_groups_sorts_and_dedups() {
+        let mut report = Report::new();
+        for _ in 0..3 {
+            report.file_compiled();
+        }
+        report.add_error(
+            "b.q",
+            "error: second\n  --> b.q:9:1\nerror: first\n  --> b.q:2:5\n",
+        );
+        report.add_error("a.q", "Parse error at 1:3: bad");
+        report.add_error("b.q", "error: first\n  --> b.q:2:5\n");
+
+        let order: Vec<(&str, usize)> = report
+            .diagnostics()
+            .iter()
+            .map(|d| (d.file.as_str(), d.line))
+            .collect();
+        assert_eq!(order, vec![("a.q", 1), ("b.q", 2), ("b.q", 9)]);
+        let rendered = report.render();
+        assert!(rendered.starts_with("a.q:\nParse error at 1:3: bad\n\nb.q:\nerror: first"));
+        assert!(
+            rendered.ends_with("error: 3 diagnostics in 2 of 3 files"),
+            "{rendered}"
+        );
+    }
+}
@@ -0,0 +1,518 @@
+//! Embedding API — evaluate Quiche from a Rust host.
+//!
+//! ```ignore
+//! let mut engine = quiche::Engine::new();
+//! engine.eval("def double(x: i64) -> i64:\n    return x * 2\n")?;
+//! assert_eq!(engine.call("double", &[Value::Int(21)])?, Value::Int(42));
+//! ```
+//!
+//! Quiche has no interpreter: each evaluation compiles the session's
+//! definitions plus the snippet to a standalone executable with `rustc`, runs
+//! it, and reads the result back from its output. Definitions (`def`, `type`,
+//! imports, and their decorators) persist for the life of the engine; other
+//! top-level statements run once, in the evaluation that contains them.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::lexer::{self, Keyword, TokenKind};
+use crate::script::{
+    binary_ext, compile_rust_to_binary, inject_quiche_lib_module, unique_temp_path,
+};
+use crate::{CompileOptions, default_options, parser};
+
+/// Prefix of the output line carrying the encoded result, so the snippet's
+/// own `print` output can be told apart from it.
+const RESULT_MARKER: &str = "\u{1e}quiche-value:";
+
+/// A Quiche value crossing the host boundary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    /// Entries in key order.
+    Dict(Vec<(Value, Value)>),
+}
+
+impl Value {
+    /// Parse a value written as a Quiche literal (the format `Display`
+    /// produces).
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let tokens: Vec<TokenKind> = lexer::tokenize(text)
+            .map_err(|e| format!("invalid value '{text}': {e}"))?
+            .into_iter()
+            .map(|t| t.kind)
+            .filter(|k| {
+                !matches!(
+                    k,
+                    TokenKind::Newline | TokenKind::Indent | TokenKind::Dedent | TokenKind::Eof
+                )
+            })
+            .collect();
+        let mut pos = 0;
+        let value =
+            parse_value(&tokens, &mut pos).ok_or_else(|| format!("invalid value '{text}'"))?;
+        if pos != tokens.len() {
+            return Err(format!("invalid value '{text}': trailing input"));
+        }
+        Ok(value)
+    }
+
+    /// Whether a float appears anywhere in the value.
+    fn has_float(&self) -> bool {
+        match self {
+            Value::Float(_) => true,
+            Value::List(items) => items.iter().any(Value::has_float),
+            Value::Dict(entries) => entries
+                .iter()
+                .any(|(key, value)| key.has_float() || value.has_float()),
+            _ => false,
+        }
+    }
+}
+
+fn parse_value(tokens: &[TokenKind], pos: &mut usize) -> Option<Value> {
+    let token = tokens.get(*pos)?;
+    *pos += 1;
+    match token {
+        TokenKind::Int(n) => Some(Value::Int(*n)),
+        TokenKind::Float(f) => Some(Value::Float(*f)),
+        TokenKind::String(s) => Some(Value::Str(s.clone())),
+        TokenKind::Keyword(Keyword::True) => Some(Value::Bool(true)),
+        TokenKind::Keyword(Keyword::False) => Some(Value::Bool(false)),
+        TokenKind::Keyword(Keyword::None) => Some(Value::None),
+        TokenKind::Ident(name) if name == "nan" => Some(Value::Float(f64::NAN)),
+        TokenKind::Ident(name) if name == "inf" => Some(Value::Float(f64::INFINITY)),
+        TokenKind::Minus => match parse_value(tokens, pos)? {
+            Value::Int(n) => Some(Value::Int(-n)),
+            Value::Float(f) => Some(Value::Float(-f)),
+            _ => None,
+        },
+        TokenKind::LBracket => {
+            let mut items = Vec::new();
+            while tokens.get(*pos)? != &TokenKind::RBracket {
+                items.push(parse_value(tokens, pos)?);
+                if tokens.get(*pos)? == &TokenKind::Comma {
+                    *pos += 1;
+                }
+            }
+            *pos += 1;
+            Some(Value::List(items))
+        }
+        TokenKind::LBrace => {
+            let mut entries = Vec::new();
+            while tokens.get(*pos)? != &TokenKind::RBrace {
+                let key = parse_value(tokens, pos)?;
+                if tokens.get(*pos)? != &TokenKind::Colon {
+                    return None;
+                }
+                *pos += 1;
+                entries.push((key, parse_value(tokens, pos)?));
+                if tokens.get(*pos)? == &TokenKind::Comma {
+                    *pos += 1;
+                }
+            }
+            *pos += 1;
+            Some(Value::Dict(entries))
+        }
+        _ => None,
+    }
+}
+
+/// Values render as Quiche literals, so they can be spliced into source.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::None => write!(f, "None"),
+            Value::Bool(true) => write!(f, "True"),
+            Value::Bool(false) => write!(f, "False"),
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Float(x) if x.is_nan() => write!(f, "nan"),
+            Value::Float(x) if x.is_infinite() => {
+                write!(f, "{}", if *x > 0.0 { "inf" } else { "-inf" })
+            }
+            Value::Float(x) => write!(f, "{x:?}"),
+            Value::Str(s) => {
+                write!(f, "\"")?;
+                for ch in s.chars() {
+                    match ch {
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\0' => write!(f, "\\0")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '"' => write!(f, "\\\"")?,
+                        ch => write!(f, "{ch}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Value::Dict(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A Quiche session embedded in a Rust program.
+pub struct Engine {
+    definitions: String,
+    options: CompileOptions,
+    /// quiche-lib's `lib.rs`, or `None` for the vendored copy.
+    lib_src: Option<PathBuf>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    /// An engine using the quiche-lib built into the compiler (see
+    /// [`crate::vendor`]).
+    pub fn new() -> Self {
+        Engine {
+            definitions: String::new(),
+            options: default_options(),
+            lib_src: None,
+        }
+    }
+
+    /// Use the quiche-lib source at `lib_rs` (a `lib.rs` path).
+    pub fn with_lib(mut self, lib_rs: impl Into<PathBuf>) -> Self {
+        self.lib_src = Some(lib_rs.into());
+        self
+    }
+
+    pub fn with_options(mut self, options: CompileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Evaluate a snippet. Its definitions are kept for later calls; if it
+    /// ends in an expression, that expression's value is returned, otherwise
+    /// `Value::None`.
+    pub fn eval(&mut self, src: &str) -> Result<Value, String> {
+        let mut definitions = String::new();
+        let mut statements = Vec::new();
+        for chunk in top_level_chunks(src) {
+            if is_definition(&chunk) {
+                definitions.push_str(&chunk);
+                definitions.push('\n');
+            } else {
+                statements.push(chunk);
+            }
+        }
+        let definitions = format!("{}{definitions}", self.definitions);
+
+        let value = if statements.is_empty() {
+            // Nothing to run, but reject definitions that don't compile
+            // before they join the session.
+            self.compile(&definitions, &[], None)?;
+            Value::None
+        } else {
+            // A trailing block (`if`, `for`, ...) runs as a statement
+            let result = statements.pop_if(|last| !last.contains('\n'));
+            let rust = match result {
+                Some(expr) => self
+                    .compile(&definitions, &statements, Some(&expr))
+                    .or_else(|_| {
+                        statements.push(expr);
+                        self.compile(&definitions, &statements, None)
+                    })?,
+                None => self.compile(&definitions, &statements, None)?,
+            };
+            self.run(&rust)?
+        };
+        self.definitions = definitions;
+        Ok(value)
+    }
+
+    /// Call a function defined in this session with `args`.
+    /// Floats aren't supported yet: the parser lowers float literals to `0`.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+        if args.iter().any(Value::has_float) {
+            return Err(format!(
+                "call to '{name}': float arguments are not supported yet (float literals don't compile)"
+            ));
+        }
+        let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+        self.eval(&format!("{name}({})", args.join(", ")))
+    }
+
+    /// Compile `definitions` with a `main` running `statements`, then
+    /// printing `result` (if any) behind [`RESULT_MARKER`].
+    fn compile(
+        &self,
+        definitions: &str,
+        statements: &[String],
+        result: Option<&str>,
+    ) -> Result<String, String> {
+        let source = snippet_source(definitions, statements, result);
+        let mut module = parser::parse(&source).map_err(|e| format!("{e}"))?;
+        if result.is_some()
+            && let Some(elevate::ast::Item::Function(main)) =
+                module.items.iter_mut().rev().find(
+                    |item| matches!(item, elevate::ast::Item::Function(f) if f.name == "main"),
+                )
+        {
+            main.body
+                .statements
+                .push(elevate::ast::Stmt::RustBlock(format!(
+                    "println!(\"{RESULT_MARKER}{{}}\", EngineValue::encode(&__quiche_result));"
+                )));
+        }
+        crate::compile_module_file(
+            &module,
+            &source,
+            "<engine>",
+            &self.options,
+            &crate::Prelude::default(),
+        )
+    }
+
+    /// Build and run a compiled snippet, forwarding its output and decoding
+    /// its result.
+    fn run(&self, rust_code: &str) -> Result<Value, String> {
+        let stdout = run_snippet(rust_code, self.lib_src.as_deref())?;
+        let mut value = Value::None;
+        for line in stdout.lines() {
+            match line.strip_prefix(RESULT_MARKER) {
+                Some(encoded) => value = Value::parse(encoded)?,
+                None => println!("{line}"),
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// The Quiche source of a snippet: `definitions`, then a `main` running
+/// `statements` and assigning `result` (if any) to `__quiche_result`.
+fn snippet_source(definitions: &str, statements: &[String], result: Option<&str>) -> String {
+    let mut source = format!("{definitions}\ndef main():\n");
+    for statement in statements {
+        for line in statement.lines() {
+            source.push_str(&format!("    {line}\n"));
+        }
+    }
+    if let Some(expr) = result {
+        source.push_str(&format!("    __quiche_result = {expr}\n"));
+    }
+    source.push_str("    pass\n");
+    source
+}
+
+/// Build a compiled snippet against quiche-lib's `lib.rs` at `lib_src` (the
+/// vendored copy if `None`) and run it, returning its standard output.
+pub(crate) fn run_snippet(rust_code: &str, lib_src: Option<&Path>) -> Result<String, String> {
+    let rs_path = unique_temp_path("quiche-engine", "rs");
+    let bin_path = unique_temp_path("quiche-engine", binary_ext());
+    let lib_src = match lib_src {
+        Some(lib_src) => lib_src.to_path_buf(),
+        None => crate::vendor::vendored_lib_dir()?
+            .join("src")
+            .join("lib.rs"),
+    };
+    let rust_code = inject_quiche_lib_module(rust_code, &lib_src);
+    fs::write(&rs_path, rust_code).map_err(|e| format!("failed to write temp file: {e}"))?;
+    let built = compile_rust_to_binary(&rs_path, &bin_path);
+    let _ = fs::remove_file(&rs_path);
+    if let Err(error) = built {
+        let _ = fs::remove_file(&bin_path);
+        return Err(error);
+    }
+    let output = Command::new(&bin_path).output();
+    let _ = fs::remove_file(&bin_path);
+    let output = output.map_err(|e| format!("failed to run snippet: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "snippet failed (status: {})\n{}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Split source into top-level chunks: an unindented line plus the indented
+/// (or blank) lines that follow it. Comments between chunks are dropped.
+pub(crate) fn top_level_chunks(src: &str) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    for line in src.lines() {
+        let starts_chunk = !line.starts_with([' ', '\t']) && !line.trim().is_empty();
+        if starts_chunk && line.starts_with('#') {
+            continue;
+        }
+        match chunks.last_mut() {
+            Some(chunk) if !starts_chunk => {
+                chunk.push('\n');
+                chunk.push_str(line);
+            }
+            _ if starts_chunk => chunks.push(line.to_string()),
+            _ => {}
+        }
+    }
+    for chunk in &mut chunks {
+        chunk.truncate(chunk.trim_end().len());
+    }
+    chunks
+}
+
+pub(crate) fn is_definition(chunk: &str) -> bool {
+    chunk.starts_with('@')
+        || ["def ", "type ", "from ", "import "]
+            .iter()
+            .any(|kw| chunk.starts_with(kw))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_display_round_trips() {
+        let value = Value::Dict(vec![
+            (Value::from("a\"b"), Value::from(vec![1i64, -2])),
+            (
+                Value::from("c"),
+                Value::List(vec![Value::Float(-0.5), Value::None]),
+            ),
+            (Value::from("d"), Value::Bool(true)),
+        ]);
+        let text = value.to_string();
+        assert_eq!(
+            text,
+            "{\"a\\\"b\": [1, -2], \"c\": [-0.5, None], \"d\": True}"
+        );
+        assert_eq!(Value::parse(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_value_parse_rejects_non_literals() {
+        assert!(Value::parse("foo(1)").is_err());
+        assert!(Value::parse("[1, 2").is_err());
+        assert!(Value::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_top_level_chunks_split_definitions_from_statements() {
+        let chunks = top_level_chunks(
+            "# setup\n@inline\ndef f(x: i64) -> i64:\n    return x\n\nx = f(1)\nx + 1\n",
+        );
+        assert_eq!(
+            chunks,
+            vec![
+                "@inline",
+                "def f(x: i64) -> i64:\n    return x",
+                "x = f(1)",
+                "x + 1"
+            ]
+        );
+        let kinds: Vec<bool> = chunks.iter().map(|c| is_definition(c)).collect();
+        assert_eq!(kinds, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn test_snippet_keeps_a_trailing_block() {
+        let statements = vec![
+            "total = 0".to_string(),
+            "for i in range(3):\n    total += i".to_string(),
+        ];
+        let source = snippet_source("", &statements, None);
+        assert_eq!(
+            source,
+            "\ndef main():\n    total = 0\n    for i in range(3):\n        total += i\n    pass\n"
+        );
+    }
+
+    #[test]
+    fn test_eval_runs_a_trailing_block() {
+        let mut engine =
+            Engine::new().with_lib(concat!(env!("CARGO_MANIFEST_DIR"), "/../lib/src/lib.rs"));
+        engine
+            .eval("def check(x: i64) -> bool:\n    return x > 1\n")
+            .unwrap();
+        // The block compiles, and only fails if it runs
+        engine.eval("if check(2):\n    assert 2 == 2\n").unwrap();
+        assert!(engine.eval("if check(2):\n    assert 1 == 2\n").is_err());
+        assert!(
+            engine
+                .eval("for i in range(3):\n    assert check(i)\n")
+                .is_err()
+        );
+        assert_eq!(
+            engine.call("check", &[Value::Int(2)]).unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_call_rejects_float_arguments() {
+        let mut engine = Engine::new();
+        let err = engine
+            .call("scale", &[Value::List(vec![Value::Float(1.5)])])
+            .unwrap_err();
+        assert!(err.contains("float arguments are not supported"), "{err}");
+    }
+}
@@ -0,0 +1,598 @@
+//! `quiche fmt` — reprints `.q` sources in one canonical layout.
+//!
+//! The formatter works on the lexer's tokens rather than the parsed AST,
+//! which has already lowered comprehensions, `with` blocks and the like,
+//! and has no comments. It only lays out what the source already says:
+//!
+//! - blocks are indented four spaces a level, and comments follow the
+//!   block they sit in
+//! - one space around binary operators, `=` and `->`, after `,` and `:`,
+//!   and none inside brackets, before calls and subscripts, or
+//!   around keyword-argument `=` (unless the parameter is annotated, as
+//!   in `n: i64 = 0`)
+//! - at most two blank lines at the top level and one inside blocks, and
+//!   none at the start of a block
+//! - a bracket broken across lines — right after `(`, `[` or `{`, after a
+//!   comma, or before the closing bracket — is laid out one item per line,
+//!   ending in a trailing comma; one on a single line loses its trailing
+//!   comma (but `(x,)` stays a tuple)
+//!
+//! Long lines aren't wrapped, and string literals, docstrings included, are
+//! kept as written. A file that doesn't parse is left alone, and the result
+//! is checked to lex to the same tokens as the original, commas before
+//! closing brackets aside.
+
+use crate::lexer::{Keyword, Token, TokenKind, tokenize};
+
+const INDENT: &str = "    ";
+
+/// `source` in canonical layout, or why it can't be formatted.
+pub fn format_source(source: &str) -> Result<String, String> {
+    crate::parse(source)?;
+    let tokens = tokenize(source).map_err(|e| e.to_string())?;
+    let formatted = Printer::new(source, &tokens).print();
+    let reformatted = tokenize(&formatted).map_err(|e| e.to_string())?;
+    if significant(&tokens) != significant(&reformatted) || crate::parse(&formatted).is_err() {
+        return Err("formatting would change the meaning of the file; please report this".into());
+    }
+    Ok(formatted)
+}
+
+/// The tokens layout can't change: all but comments, the commas directly
+/// before closing brackets, and the line break ending the file.
+fn significant(tokens: &[Token]) -> Vec<&TokenKind> {
+    let mut kinds: Vec<&TokenKind> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(i, token)| match &token.kind {
+            TokenKind::Comment(_) => false,
+            TokenKind::Comma => !tokens.get(i + 1).is_some_and(|next| is_closer(&next.kind)),
+            _ => true,
+        })
+        .map(|(_, token)| &token.kind)
+        .collect();
+    while kinds.last().is_some_and(|kind| {
+        matches!(
+            kind,
+            TokenKind::Newline | TokenKind::Dedent | TokenKind::Eof
+        )
+    }) {
+        kinds.pop();
+    }
+    kinds
+}
+
+fn is_opener(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace
+    )
+}
+
+fn is_closer(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace
+    )
+}
+
+/// Whether `kind` can end an operand, making a following `-` binary and a
+/// following `(` or `[` a call or subscript.
+fn ends_operand(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Ident(_)
+            | TokenKind::Int(_)
+            | TokenKind::Float(_)
+            | TokenKind::String(_)
+            | TokenKind::FString { .. }
+            | TokenKind::Keyword(Keyword::None | Keyword::True | Keyword::False)
+            | TokenKind::RParen
+            | TokenKind::RBracket
+            | TokenKind::RBrace
+            | TokenKind::Question
+            | TokenKind::Ellipsis
+    )
+}
+
+/// Width of the indentation starting `line`, tabs stopping every 8 columns
+/// as in the lexer.
+fn indent_width(line: &str) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width = (width / 8 + 1) * 8,
+            _ => break,
+        }
+    }
+    width
+}
+
+/// What the innermost open bracket is.
+#[derive(Clone, Copy, PartialEq)]
+enum BracketKind {
+    /// `f(...)`, `def f(...)`, `@cfg(...)`
+    Call,
+    /// `(a + b)` or a tuple
+    Group,
+    /// `xs[i]`, `List[i64]`, `def f[T]`
+    Subscript,
+    /// `[...]` and `{...}` literals
+    Literal,
+}
+
+struct Bracket {
+    kind: BracketKind,
+    /// Index of the closing token
+    close: usize,
+    /// Laid out one item per line
+    multiline: bool,
+    /// Indentation level of the line the bracket opens on
+    level: usize,
+    /// Top-level commas in the source
+    commas: usize,
+    /// The last thing printed at the top level is a comma (or the opening
+    /// bracket)
+    after_comma: bool,
+    /// The current item has a `:` annotation, so its `=` is spaced
+    annotated: bool,
+}
+
+impl Bracket {
+    /// A trailing comma may be added: not `(x)`, which it would make a
+    /// tuple, nor subscripts.
+    fn takes_trailing_comma(&self) -> bool {
+        match self.kind {
+            BracketKind::Group => self.commas > 0,
+            BracketKind::Subscript => false,
+            BracketKind::Call | BracketKind::Literal => true,
+        }
+    }
+}
+
+/// What encloses the token being printed.
+enum Context {
+    Bracket(Bracket),
+    /// An indented lambda body inside brackets
+    Block,
+}
+
+/// Where the next line break indents to.
+#[derive(Clone, Copy)]
+enum Level {
+    /// The block depth
+    Block,
+    Fixed(usize),
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    tokens: &'a [Token],
+    /// Index of the bracket closing each opening bracket
+    closes: Vec<Option<usize>>,
+    out: String,
+    depth: usize,
+    /// Source indentation width of each block level, the top level's 0
+    widths: Vec<usize>,
+    contexts: Vec<Context>,
+    /// The last two tokens printed, comments aside
+    prev: Option<usize>,
+    prev2: Option<usize>,
+    /// A line break due before the next token
+    pending: Option<Level>,
+    blanks: usize,
+    /// Indentation level of the current output line
+    line_level: usize,
+    /// Anything printed on the current line yet
+    on_line: bool,
+    /// No space before the next token: it follows a unary operator, a
+    /// decorator's `@` or a closure's opening `|`
+    glue: bool,
+    /// `contexts.len()` where a closure's `|params|` opened
+    closure: Option<usize>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str, tokens: &'a [Token]) -> Printer<'a> {
+        let mut closes = vec![None; tokens.len()];
+        let mut open = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if is_opener(&token.kind) {
+                open.push(i);
+            } else if is_closer(&token.kind)
+                && let Some(at) = open.pop()
+            {
+                closes[at] = Some(i);
+            }
+        }
+        Printer {
+            source,
+            tokens,
+            closes,
+            out: String::new(),
+            depth: 0,
+            widths: vec![0],
+            contexts: Vec::new(),
+            prev: None,
+            prev2: None,
+            pending: None,
+            blanks: 0,
+            line_level: 0,
+            on_line: false,
+            glue: false,
+            closure: None,
+        }
+    }
+
+    fn print(mut self) -> String {
+        self.gap(0, 0, 0, false);
+        for i in 0..self.tokens.len() {
+            match &self.tokens[i].kind {
+                TokenKind::Eof => break,
+                TokenKind::Newline => {
+                    self.pending = Some(Level::Block);
+                    self.on_line = false;
+                    let next = self.next_code(i + 1);
+                    let opens_block = self.tokens[i + 1..next]
+                        .iter()
+                        .any(|t| t.kind == TokenKind::Indent);
+                    self.gap(self.tokens[i].end, i + 1, next, opens_block);
+                }
+                TokenKind::Indent => {
+                    let width = self.line_width(self.tokens[i].start);
+                    self.depth += 1;
+                    self.widths.push(width);
+                    if !self.contexts.is_empty() {
+                        self.contexts.push(Context::Block);
+                    }
+                }
+                TokenKind::Dedent => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.widths.len() > 1 {
+                        self.widths.pop();
+                    }
+                    if matches!(self.contexts.last(), Some(Context::Block)) {
+                        self.contexts.pop();
+                    }
+                }
+                TokenKind::Comment(_) => self.comment(i),
+                _ => self.token(i),
+            }
+        }
+        let mut out = self.out.trim_end().to_string();
+        out.push('\n');
+        out
+    }
+
+    /// Index of the first token from `from` that isn't an indent or dedent.
+    fn next_code(&self, from: usize) -> usize {
+        (from..self.tokens.len())
+            .find(|&i| !matches!(self.tokens[i].kind, TokenKind::Indent | TokenKind::Dedent))
+            .unwrap_or(self.tokens.len() - 1)
+    }
+
+    /// Indentation width of the source line holding byte `at`.
+    fn line_width(&self, at: usize) -> usize {
+        let start = self.source[..at].rfind('\n').map_or(0, |i| i + 1);
+        indent_width(&self.source[start..])
+    }
+
+    fn end_line(&self, i: usize) -> usize {
+        let token = &self.tokens[i];
+        token.line + self.source[token.start..token.end].matches('\n').count()
+    }
+
+    /// Print the comment lines between byte `from` and token `next`, and
+    /// note the blank lines to keep before it. Tokens `first..next` are the
+    /// indents and dedents in between.
+    fn gap(&mut self, from: usize, first: usize, next: usize, opens_block: bool) {
+        // Block levels after the indents and dedents before `next`
+        let mut widths = self.widths.clone();
+        let mut next_depth = self.depth;
+        for token in &self.tokens[first..next] {
+            match token.kind {
+                TokenKind::Indent => {
+                    next_depth += 1;
+                    widths.push(self.line_width(token.start));
+                }
+                TokenKind::Dedent => next_depth = next_depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        let (low, high) = (self.depth.min(next_depth), self.depth.max(next_depth));
+        let end = self.tokens.get(next).map_or(self.source.len(), |t| t.start);
+        let text = &self.source[from.min(end)..end];
+        let lines: Vec<&str> = text.split('\n').collect();
+        let mut blanks = 0;
+        let mut first = true;
+        for (k, line) in lines.iter().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                if k + 1 < lines.len() {
+                    blanks += 1;
+                }
+                continue;
+            }
+            if !trimmed.starts_with('#') {
+                continue;
+            }
+            let width = indent_width(line);
+            let level = widths
+                .iter()
+                .rposition(|&w| w <= width)
+                .unwrap_or(0)
+                .clamp(low, high);
+            let keep = if first && opens_block {
+                0
+            } else {
+                blanks.min(max_blanks(level))
+            };
+            self.break_line(level, keep);
+            self.out.push_str(trimmed);
+            self.pending = Some(Level::Block);
+            blanks = 0;
+            first = false;
+        }
+        self.blanks = if first && opens_block {
+            0
+        } else {
+            blanks.min(max_blanks(next_depth))
+        };
+    }
+
+    /// Start a new line indented to `level`, after `blanks` blank lines.
+    fn break_line(&mut self, level: usize, blanks: usize) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+            for _ in 0..blanks {
+                self.out.push('\n');
+            }
+        }
+        self.out.push_str(&INDENT.repeat(level));
+        self.line_level = level;
+        self.on_line = false;
+        self.pending = None;
+        self.blanks = 0;
+    }
+
+    fn flush_break(&mut self) -> bool {
+        let Some(level) = self.pending else {
+            return false;
+        };
+        let level = match level {
+            Level::Block => self.depth,
+            Level::Fixed(level) => level,
+        };
+        self.break_line(level, self.blanks);
+        true
+    }
+
+    fn bracket(&mut self) -> Option<&mut Bracket> {
+        match self.contexts.last_mut() {
+            Some(Context::Bracket(bracket)) => Some(bracket),
+            _ => None,
+        }
+    }
+
+    /// Print the comment ending a line. (Comment lines come between
+    /// tokens, and the parser takes no comments inside brackets.)
+    fn comment(&mut self, i: usize) {
+        let text = self.source[self.tokens[i].start..self.tokens[i].end].trim_end();
+        if self.on_line {
+            self.out.push_str("  ");
+        } else {
+            self.flush_break();
+        }
+        self.out.push_str(text);
+        self.on_line = true;
+    }
+
+    fn token(&mut self, i: usize) {
+        let kind = &self.tokens[i].kind;
+        // Drop a trailing comma in a bracket kept on one line
+        if *kind == TokenKind::Comma
+            && let Some(Context::Bracket(bracket)) = self.contexts.last()
+            && !bracket.multiline
+            && bracket.close == i + 1
+            && match bracket.kind {
+                BracketKind::Group => bracket.commas > 1,
+                BracketKind::Subscript => false,
+                BracketKind::Call | BracketKind::Literal => true,
+            }
+        {
+            return;
+        }
+        if is_closer(kind)
+            && let Some(Context::Bracket(bracket)) = self.contexts.pop()
+            && bracket.multiline
+        {
+            if !bracket.after_comma && bracket.takes_trailing_comma() {
+                self.out.push(',');
+            }
+            self.pending = Some(Level::Fixed(bracket.level));
+        }
+
+        let broke = self.flush_break();
+        if !broke && self.on_line && self.space_before(i) {
+            self.out.push(' ');
+        }
+        let text = &self.source[self.tokens[i].start..self.tokens[i].end];
+        self.out.push_str(text);
+
+        let unary_position = self
+            .prev
+            .is_none_or(|prev| !ends_operand(&self.tokens[prev].kind))
+            || !self.on_line;
+        let was_on_line = self.on_line;
+        self.on_line = true;
+        self.glue = false;
+        match kind {
+            TokenKind::Minus
+            | TokenKind::Plus
+            | TokenKind::Tilde
+            | TokenKind::Star
+            | TokenKind::DoubleStar
+            | TokenKind::Amp
+                if unary_position =>
+            {
+                self.glue = true;
+            }
+            TokenKind::At if !was_on_line => self.glue = true,
+            TokenKind::Pipe if self.closure == Some(self.contexts.len()) => self.closure = None,
+            TokenKind::Pipe if unary_position => {
+                self.closure = Some(self.contexts.len());
+                self.glue = true;
+            }
+            _ => {}
+        }
+        if let Some(bracket) = self.bracket() {
+            match kind {
+                TokenKind::Comma => {
+                    bracket.annotated = false;
+                    if bracket.multiline && bracket.close != i + 1 {
+                        let level = bracket.level + 1;
+                        self.pending = Some(Level::Fixed(level));
+                    }
+                }
+                TokenKind::Colon => bracket.annotated = true,
+                _ => {}
+            }
+            if let Some(bracket) = self.bracket() {
+                bracket.after_comma = *kind == TokenKind::Comma;
+            }
+        }
+        if is_opener(kind) {
+            self.open(i);
+        }
+        self.prev2 = self.prev;
+        self.prev = Some(i);
+    }
+
+    /// Push the bracket opened by token `i`.
+    fn open(&mut self, i: usize) {
+        let Some(close) = self.closes[i] else {
+            return;
+        };
+        let prev = self.prev.map(|p| &self.tokens[p].kind);
+        let after_operand = prev.is_some_and(ends_operand);
+        let kind = match self.tokens[i].kind {
+            TokenKind::LParen if after_operand => BracketKind::Call,
+            TokenKind::LParen => BracketKind::Group,
+            TokenKind::LBracket if after_operand => BracketKind::Subscript,
+            _ => BracketKind::Literal,
+        };
+        // Top-level commas and line breaks inside
+        let mut commas = 0;
+        let mut breaks = self.tokens[i + 1].line > self.end_line(i) && close > i + 1;
+        let mut nested = 0usize;
+        let mut block = false;
+        for k in i + 1..close {
+            match &self.tokens[k].kind {
+                kind if is_opener(kind) => nested += 1,
+                kind if is_closer(kind) => nested = nested.saturating_sub(1),
+                TokenKind::Indent => block = true,
+                TokenKind::Comma if nested == 0 => {
+                    commas += 1;
+                    breaks |= self.tokens[k + 1].line > self.end_line(k);
+                }
+                _ => {}
+            }
+        }
+        breaks |= close > i + 1 && self.tokens[close].line > self.end_line(close - 1);
+        let multiline = breaks && !block && kind != BracketKind::Subscript;
+        let level = self.line_level;
+        self.contexts.push(Context::Bracket(Bracket {
+            kind,
+            close,
+            multiline,
+            level,
+            commas,
+            after_comma: true,
+            annotated: false,
+        }));
+        if multiline {
+            self.pending = Some(Level::Fixed(level + 1));
+        }
+    }
+
+    /// Whether a space separates token `i` from the one printed before it.
+    fn space_before(&self, i: usize) -> bool {
+        let Some(prev) = self.prev else {
+            return false;
+        };
+        if self.glue {
+            return false;
+        }
+        let kind = &self.tokens[i].kind;
+        let prev_kind = &self.tokens[prev].kind;
+        let bracket = match self.contexts.last() {
+            Some(Context::Bracket(bracket)) => Some(bracket),
+            _ => None,
+        };
+        let in_parens =
+            bracket.is_some_and(|b| matches!(b.kind, BracketKind::Call | BracketKind::Group));
+        let in_subscript = bracket.is_some_and(|b| b.kind == BracketKind::Subscript);
+        let keyword_eq = in_parens && !bracket.is_some_and(|b| b.annotated);
+        if is_opener(prev_kind)
+            || matches!(prev_kind, TokenKind::Dot | TokenKind::DotDot)
+            || (keyword_eq && *prev_kind == TokenKind::Eq)
+        {
+            return false;
+        }
+        // `def f[T: Display]`: type parameters, spaced like annotations
+        let type_params = in_subscript
+            && self.prev2.is_some_and(|p| {
+                matches!(
+                    self.tokens[p].kind,
+                    TokenKind::Keyword(Keyword::Def | Keyword::Type)
+                )
+            });
+        match kind {
+            TokenKind::Comma
+            | TokenKind::Semicolon
+            | TokenKind::Colon
+            | TokenKind::Question
+            | TokenKind::DotDot
+            | TokenKind::RParen
+            | TokenKind::RBracket
+            | TokenKind::RBrace
+            | TokenKind::Dot => return false,
+            TokenKind::LParen | TokenKind::LBracket => return !ends_operand(prev_kind),
+            TokenKind::Pipe if self.closure == Some(self.contexts.len()) => return false,
+            TokenKind::Eq if keyword_eq => return false,
+            _ => {}
+        }
+        !(in_subscript && !type_params && *prev_kind == TokenKind::Colon)
+    }
+}
+
+/// Blank lines kept in a row before a line at block `level`.
+fn max_blanks(level: usize) -> usize {
+    if level == 0 { 2 } else { 1 }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_normalizes_layout() {
+        let source = "# header\n\n\n\n\nimport math\ndef area(w:f64,h :f64=1.0)->f64 :\n\n  # the area\n  return w*h   # done\n\n\n\n\n@cfg( unix )\ndef main( ) :\n  xs=[1,2,3,]\n  print( xs[ 1 ], area(w = 2.0) , -xs[0] )\n  print(sum( [1] , start=[ 0][0]))\n  ys = [\n      1,\n      2\n  ]\n  d = {\"a\" :1,\n    \"b\": 2}\n  t = (1,)\n  f = |x| x+1\n  if not xs :\n      pass\n";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted,
+            "# header\n\n\nimport math\ndef area(w: f64, h: f64 = 1.0) -> f64:\n    # the area\n    return w * h  # done\n\n\n@cfg(unix)\ndef main():\n    xs = [1, 2, 3]\n    print(xs[1], area(w=2.0), -xs[0])\n    print(sum([1], start=[0][0]))\n    ys = [\n        1,\n        2,\n    ]\n    d = {\n        \"a\": 1,\n        \"b\": 2,\n    }\n    t = (1,)\n    f = |x| x + 1\n    if not xs:\n        pass\n"
+        );
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_comments_with_their_block() {
+        let source = "def f():\n    if True:\n        pass\n        # end of if\n    # end of f\n\n# top\ndef g():\n    pass\n";
+        assert_eq!(format_source(source).unwrap(), source);
+        let err = format_source("def f(:\n").unwrap_err();
+        assert!(err.contains("Parse error"), "{err}");
+    }
+}
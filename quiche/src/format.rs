@@ -0,0 +1,376 @@
+//! Python format strings — f-string placeholders and `"...".format(...)`
+//! templates — checked and translated to Rust's `format!` syntax while
+//! parsing.
+//!
+//! A mistake in a placeholder is reported against the Quiche source, rather
+//! than surfacing from rustc as an error in the generated `format!` call:
+//!
+//! ```text
+//! Parse error at 4:19: format spec ',.2f': digit grouping (',') is not supported
+//! ```
+//!
+//! Specs follow Python's `[[fill]align][sign][#][0][width][.precision][type]`
+//! and are rewritten into the equivalent Rust spec (`{x:>8.2f}` becomes
+//! `{:>8.2}`). The parts Rust's formatter has no equivalent for — `=`
+//! alignment, the `' '` sign, digit grouping, and the `g`, `n`, `%` and `c`
+//! types — are rejected, as are placeholders nested inside a spec.
+
+/// A replacement field split into its parts: `name!conversion:spec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field<'a> {
+    /// The expression (f-strings) or field name (`.format()`).
+    pub name: &'a str,
+    /// `r` or `s`, from `!r` / `!s`.
+    pub conversion: Option<char>,
+    /// The Python spec, after the `:`.
+    pub spec: Option<&'a str>,
+}
+
+/// Split a placeholder's text at its top-level `!` conversion and `:` spec.
+/// Brackets and string literals in the expression are skipped, and so are
+/// the `!` of `!=` and the `:` of a slice.
+pub fn split_field(text: &str) -> Result<Field<'_>, String> {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth = depth.saturating_sub(1),
+            (None, '!') if depth == 0 && chars.peek().is_some_and(|&(_, next)| next != '=') => {
+                let rest = &text[i + 1..];
+                let (conversion, spec) = match rest.split_once(':') {
+                    Some((conversion, spec)) => (conversion, Some(spec)),
+                    None => (rest, None),
+                };
+                let conversion = match conversion {
+                    "r" => 'r',
+                    "s" => 's',
+                    other => {
+                        return Err(format!(
+                            "invalid conversion '!{other}' (expected '!r' or '!s')"
+                        ));
+                    }
+                };
+                return Ok(Field {
+                    name: &text[..i],
+                    conversion: Some(conversion),
+                    spec,
+                });
+            }
+            (None, ':') if depth == 0 => {
+                return Ok(Field {
+                    name: &text[..i],
+                    conversion: None,
+                    spec: Some(&text[i + 1..]),
+                });
+            }
+            (None, _) => {}
+        }
+    }
+    Ok(Field {
+        name: text,
+        conversion: None,
+        spec: None,
+    })
+}
+
+/// The Rust spec (without the `:`) for the Python format spec `spec`.
+pub fn rust_spec(spec: &str) -> Result<String, String> {
+    let invalid = |why: &str| format!("format spec '{spec}': {why}");
+    if spec.contains(['{', '}']) {
+        return Err(invalid("nested placeholders in a spec are not supported"));
+    }
+    let chars: Vec<char> = spec.chars().collect();
+    let is_align = |c: Option<&char>| matches!(c, Some('<' | '>' | '^' | '='));
+    let mut i = 0;
+    let mut out = String::new();
+    let align = if is_align(chars.get(1)) {
+        i = 2;
+        Some((Some(chars[0]), chars[1]))
+    } else if is_align(chars.first()) {
+        i = 1;
+        Some((None, chars[0]))
+    } else {
+        None
+    };
+    if let Some((fill, align)) = align {
+        if align == '=' {
+            return Err(invalid("'=' alignment is not supported"));
+        }
+        out.extend(fill);
+        out.push(align);
+    }
+    let sign = match chars.get(i) {
+        Some(&c @ ('+' | '-')) => {
+            i += 1;
+            out.push(c);
+            true
+        }
+        Some(' ') => return Err(invalid("the ' ' sign is not supported; use '+' or '-'")),
+        _ => false,
+    };
+    if chars.get(i) == Some(&'z') {
+        return Err(invalid("the 'z' option is not supported"));
+    }
+    if chars.get(i) == Some(&'#') {
+        i += 1;
+        out.push('#');
+    }
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>()
+    };
+    out.push_str(&digits(&mut i));
+    if let Some(&c @ (',' | '_')) = chars.get(i) {
+        return Err(invalid(&format!("digit grouping ('{c}') is not supported")));
+    }
+    let precision = if chars.get(i) == Some(&'.') {
+        i += 1;
+        let precision = digits(&mut i);
+        if precision.is_empty() {
+            return Err(invalid("expected a precision after '.'"));
+        }
+        Some(precision)
+    } else {
+        None
+    };
+    let kind = match &chars[i..] {
+        [] => None,
+        [kind] => Some(*kind),
+        _ => {
+            return Err(invalid(
+                "unexpected characters after the width and precision",
+            ));
+        }
+    };
+    let no_precision = |kind: char| match precision {
+        Some(_) => Err(invalid(&format!(
+            "precision is not allowed with integer format '{kind}'"
+        ))),
+        None => Ok(()),
+    };
+    let (precision, suffix) = match kind {
+        None => (precision, None),
+        Some('s') if sign => return Err(invalid("a sign is not allowed with string format 's'")),
+        Some('s') => (precision, None),
+        Some('d') => {
+            no_precision('d')?;
+            (None, None)
+        }
+        Some(kind @ ('x' | 'X' | 'o' | 'b')) => {
+            no_precision(kind)?;
+            (None, Some(kind))
+        }
+        // Python's default precision for fixed and exponent notation is 6
+        Some('f' | 'F') => (precision.or(Some("6".into())), None),
+        Some(kind @ ('e' | 'E')) => (precision.or(Some("6".into())), Some(kind)),
+        Some(kind @ ('g' | 'G' | 'n' | '%' | 'c')) => {
+            return Err(invalid(&format!("format type '{kind}' is not supported")));
+        }
+        Some(kind) => return Err(invalid(&format!("unknown format type '{kind}'"))),
+    };
+    if let Some(precision) = precision {
+        out.push('.');
+        out.push_str(&precision);
+    }
+    out.extend(suffix);
+    Ok(out)
+}
+
+/// The reference a `.format()` field makes to its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldName {
+    /// `{}`: the next positional argument.
+    Auto,
+    /// `{0}`.
+    Index(usize),
+    /// `{name}`: a keyword argument.
+    Keyword(String),
+}
+
+/// A piece of a `.format()` template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece {
+    /// Literal text, with `{{` and `}}` kept escaped for Rust.
+    Text(String),
+    /// A replacement field, at byte `offset` of the template.
+    Field {
+        offset: usize,
+        name: FieldName,
+        conversion: Option<char>,
+        /// The Rust spec, empty when there is none.
+        spec: String,
+    },
+}
+
+/// Split a `.format()` template into text and fields, or fail with the
+/// byte offset of the problem and a message.
+pub fn template_pieces(template: &str) -> Result<Vec<Piece>, (usize, String)> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().is_some_and(|&(_, next)| next == '{') => {
+                chars.next();
+                text.push_str("{{");
+            }
+            '}' if chars.peek().is_some_and(|&(_, next)| next == '}') => {
+                chars.next();
+                text.push_str("}}");
+            }
+            '}' => return Err((i, "single '}' in format string (write '}}')".into())),
+            '{' => {
+                let mut depth = 1;
+                let end = chars
+                    .by_ref()
+                    .find(|&(_, c)| {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => {}
+                        }
+                        depth == 0
+                    })
+                    .map(|(end, _)| end)
+                    .ok_or((i, "unclosed '{' in format string".to_string()))?;
+                let located = |message: String| (i, message);
+                let field = split_field(&template[i + 1..end]).map_err(located)?;
+                let name = match field.name.trim() {
+                    "" => FieldName::Auto,
+                    name if name.chars().all(|c| c.is_ascii_digit()) => FieldName::Index(
+                        name.parse()
+                            .map_err(|_| located("index too large".into()))?,
+                    ),
+                    name if name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                        && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+                    {
+                        FieldName::Keyword(name.to_string())
+                    }
+                    name => {
+                        return Err(located(format!(
+                            "invalid field '{{{name}}}': fields are positions or argument names"
+                        )));
+                    }
+                };
+                let spec = match field.spec {
+                    Some(spec) => rust_spec(spec).map_err(located)?,
+                    None => String::new(),
+                };
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+                pieces.push(Piece::Field {
+                    offset: i,
+                    name,
+                    conversion: field.conversion,
+                    spec,
+                });
+            }
+            c => text.push(c),
+        }
+    }
+    pieces.push(Piece::Text(text));
+    pieces.retain(|piece| !matches!(piece, Piece::Text(text) if text.is_empty()));
+    Ok(pieces)
+}
+
+/// `(line, column)` of byte `offset` in a string literal whose contents
+/// start at `start`.
+pub fn locate(text: &str, offset: usize, start: (usize, usize)) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    match before.rfind('\n') {
+        Some(newline) => (
+            start.0 + before.matches('\n').count(),
+            before[newline + 1..].chars().count() + 1,
+        ),
+        None => (start.0, start.1 + before.chars().count()),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_field() {
+        let field = |text| split_field(text).unwrap();
+        assert_eq!(
+            field("price:>8.2f"),
+            Field {
+                name: "price",
+                conversion: None,
+                spec: Some(">8.2f"),
+            }
+        );
+        assert_eq!(field("a != b").spec, None);
+        assert_eq!(field("xs[1:3]").name, "xs[1:3]");
+        assert_eq!(field("d[\"a:b\"]!r").conversion, Some('r'));
+        assert_eq!(field("name!s:^10").spec, Some("^10"));
+        assert!(split_field("x!q").is_err());
+    }
+
+    #[test]
+    fn test_rust_spec() {
+        let ok = |spec| rust_spec(spec).unwrap();
+        assert_eq!(ok(">8.2f"), ">8.2");
+        assert_eq!(ok("*^10"), "*^10");
+        assert_eq!(ok("+08.3e"), "+08.3e");
+        assert_eq!(ok("#x"), "#x");
+        assert_eq!(ok("f"), ".6");
+        assert_eq!(ok("05d"), "05");
+        assert_eq!(ok(".3"), ".3");
+        for (spec, why) in [
+            (",.2f", "digit grouping (',') is not supported"),
+            (".2d", "precision is not allowed with integer format 'd'"),
+            ("=10", "'=' alignment is not supported"),
+            (".1%", "format type '%' is not supported"),
+            ("5q", "unknown format type 'q'"),
+            ("{width}", "nested placeholders in a spec are not supported"),
+            ("10.", "expected a precision after '.'"),
+        ] {
+            assert_eq!(
+                rust_spec(spec).unwrap_err(),
+                format!("format spec '{spec}': {why}")
+            );
+        }
+    }
+
+    #[test]
+    fn test_template_pieces() {
+        let pieces = template_pieces("{{x}} {name:>5} {0!r}").unwrap();
+        assert_eq!(
+            pieces,
+            [
+                Piece::Text("{{x}} ".into()),
+                Piece::Field {
+                    offset: 6,
+                    name: FieldName::Keyword("name".into()),
+                    conversion: None,
+                    spec: ">5".into(),
+                },
+                Piece::Text(" ".into()),
+                Piece::Field {
+                    offset: 16,
+                    name: FieldName::Index(0),
+                    conversion: Some('r'),
+                    spec: String::new(),
+                },
+            ]
+        );
+        assert_eq!(template_pieces("a } b").unwrap_err().0, 2);
+        assert_eq!(template_pieces("{x.y}").unwrap_err().0, 0);
+        assert_eq!(template_pieces("ab {").unwrap_err().0, 3);
+        assert_eq!(locate("ab\ncd {", 6, (4, 10)), (5, 4));
+        assert_eq!(locate("ab {", 3, (4, 10)), (4, 13));
+    }
+}
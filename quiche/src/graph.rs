@@ -0,0 +1,362 @@
+//! `quiche graph` — module and call graphs of a Quiche project.
+//!
+//! [`module_graph`] links each module to the modules it imports (`import a.b`
+//! and `from a.b import x`); [`call_graph`] links each function and method
+//! to the project functions it calls. Modules are named by their path in
+//! the source directory, `src/net/http.q` → `net.http`, as
+//! [`crate::compile_dir`] nests them and other modules import them.
+//! [`Graph::render`] writes either graph as Graphviz DOT or as a Mermaid
+//! flowchart.
+//!
+//! Calls are resolved by name: to a function or `Type.method` defined in the
+//! same module, to a name brought in with `from module import name`, or to a
+//! method of the enclosing type through `self`. Calls through other values
+//! (`items.push(x)`) and into Rust or quiche-lib aren't drawn.
+
+use crate::parser;
+use elevate::ast as e;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// Output format of [`Graph::render`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Graphviz DOT (`quiche graph | dot -Tsvg > graph.svg`).
+    #[default]
+    Dot,
+    /// A Mermaid flowchart, for Markdown that renders Mermaid.
+    Mermaid,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Format, String> {
+        match format {
+            "dot" => Ok(Format::Dot),
+            "mermaid" => Ok(Format::Mermaid),
+            other => Err(format!(
+                "unknown graph format '{other}' (expected dot or mermaid)"
+            )),
+        }
+    }
+}
+
+/// A directed graph, kept sorted so output is stable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Graph {
+    pub nodes: BTreeSet<String>,
+    pub edges: BTreeSet<(String, String)>,
+}
+
+impl Graph {
+    fn add_edge(&mut self, from: &str, to: &str) {
+        self.nodes.insert(from.to_string());
+        self.nodes.insert(to.to_string());
+        self.edges.insert((from.to_string(), to.to_string()));
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Dot => {
+                let mut out = String::from("digraph quiche {\n    rankdir=LR;\n");
+                for node in &self.nodes {
+                    out.push_str(&format!("    {node:?};\n"));
+                }
+                for (from, to) in &self.edges {
+                    out.push_str(&format!("    {from:?} -> {to:?};\n"));
+                }
+                out.push_str("}\n");
+                out
+            }
+            Format::Mermaid => {
+                // Mermaid ids can't contain dots: number the nodes, label
+                // them with their names.
+                let ids: HashMap<&str, usize> = self
+                    .nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, node)| (node.as_str(), i))
+                    .collect();
+                let mut out = String::from("flowchart LR\n");
+                for (i, node) in self.nodes.iter().enumerate() {
+                    out.push_str(&format!("    n{i}[\"{}\"]\n", node.replace('"', "#quot;")));
+                }
+                for (from, to) in &self.edges {
+                    out.push_str(&format!(
+                        "    n{} --> n{}\n",
+                        ids[from.as_str()],
+                        ids[to.as_str()]
+                    ));
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Every `.q` module under `src_dir`, as `(module name, source)` pairs.
+pub fn project_modules(src_dir: &Path) -> Result<Vec<(String, String)>, String> {
+    crate::build::find_sources(src_dir, "q")?
+        .into_iter()
+        .map(|relative| {
+            let path = src_dir.join(&relative);
+            let source = std::fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+            Ok((module_name(&relative), source))
+        })
+        .collect()
+}
+
+/// `net/http.q` → `net.http`.
+fn module_name(relative: &Path) -> String {
+    relative
+        .with_extension("")
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Which modules import which, for `(module name, source)` pairs. Imported
+/// modules outside the project (`rust.std.collections`) are drawn too.
+pub fn module_graph(modules: &[(String, String)]) -> Result<Graph, String> {
+    let mut graph = Graph::default();
+    for (name, source) in modules {
+        graph.nodes.insert(name.clone());
+        let imports = parser::parse_imports(source).map_err(|e| format!("{name}: {e}"))?;
+        for import in imports {
+            graph.add_edge(name, &import.module);
+        }
+    }
+    Ok(graph)
+}
+
+/// Which project functions call which, for `(module name, source)` pairs.
+/// Nodes are `module.function` and `module.Type.method`.
+pub fn call_graph(modules: &[(String, String)]) -> Result<Graph, String> {
+    let mut parsed = Vec::new();
+    for (name, source) in modules {
+        let module = parser::parse_decorated(source).map_err(|e| format!("{name}: {e}"))?;
+        let imports = module.imports.clone();
+        parsed.push((name, imports, module.without_inline_tests()));
+    }
+    let mut defined: HashMap<&str, BTreeSet<String>> = HashMap::new();
+    for (name, _, module) in &parsed {
+        defined.insert(name.as_str(), functions(module).map(|(f, _)| f).collect());
+    }
+
+    let mut graph = Graph::default();
+    for (name, imports, module) in &parsed {
+        let local = &defined[name.as_str()];
+        let mut scope: HashMap<String, String> = local
+            .iter()
+            .map(|f| (f.clone(), format!("{name}.{f}")))
+            .collect();
+        for import in imports {
+            let Some(theirs) = defined.get(import.module.as_str()) else {
+                continue;
+            };
+            for (item, alias) in import
+                .names
+                .iter()
+                .filter(|(item, _)| theirs.contains(item))
+            {
+                scope.insert(alias.clone(), format!("{}.{item}", import.module));
+            }
+        }
+        for (function, body) in functions(module) {
+            let caller = format!("{name}.{function}");
+            graph.nodes.insert(caller.clone());
+            let owner = function.split_once('.').map(|(owner, _)| owner);
+            let mut callees = Vec::new();
+            calls_in_block(body, &mut callees);
+            for callee in callees {
+                let callee = match callee.strip_prefix("self.") {
+                    Some(method) => owner.map(|owner| format!("{owner}.{method}")),
+                    None => Some(callee),
+                };
+                if let Some(target) = callee.and_then(|callee| scope.get(&callee)) {
+                    graph.add_edge(&caller, target);
+                }
+            }
+        }
+    }
+    Ok(graph)
+}
+
+/// The functions of a module with their bodies: top-level functions by
+/// name, methods as `Type.method`. Compiler-generated helpers (`__...`)
+/// are left out.
+fn functions(module: &e::Module) -> impl Iterator<Item = (String, &e::Block)> {
+    module
+        .items
+        .iter()
+        .flat_map(|item| match item {
+            e::Item::Function(func) => vec![(func.name.clone(), &func.body)],
+            e::Item::Impl(block) => block
+                .methods
+                .iter()
+                .map(|func| (format!("{}.{}", block.target, func.name), &func.body))
+                .collect(),
+            _ => Vec::new(),
+        })
+        .filter(|(name, _)| !name.starts_with("__") && !name.contains(".__"))
+}
+
+fn calls_in_block(block: &e::Block, calls: &mut Vec<String>) {
+    for stmt in &block.statements {
+        match stmt {
+            e::Stmt::Const(def) => calls_in_expr(&def.value, calls),
+            e::Stmt::DestructureConst { value, .. } | e::Stmt::Assign { value, .. } => {
+                calls_in_expr(value, calls)
+            }
+            e::Stmt::Return(Some(value)) | e::Stmt::Expr(value) | e::Stmt::TailExpr(value) => {
+                calls_in_expr(value, calls)
+            }
+            e::Stmt::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                calls_in_expr(condition, calls);
+                calls_in_block(then_block, calls);
+                if let Some(block) = else_block {
+                    calls_in_block(block, calls);
+                }
+            }
+            e::Stmt::While { condition, body } => {
+                calls_in_expr(condition, calls);
+                calls_in_block(body, calls);
+            }
+            e::Stmt::For { iter, body, .. } => {
+                calls_in_expr(iter, calls);
+                calls_in_block(body, calls);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collect the names called in `expr`: `f`, `Type.method`, `self.method`.
+fn calls_in_expr(expr: &e::Expr, calls: &mut Vec<String>) {
+    match expr {
+        e::Expr::Call { callee, args } => {
+            match &**callee {
+                e::Expr::Path(path) => calls.push(path.join(".")),
+                e::Expr::Field { base, field } => match &**base {
+                    e::Expr::Path(path) if path == &["self"] => calls.push(format!("self.{field}")),
+                    base => calls_in_expr(base, calls),
+                },
+                callee => calls_in_expr(callee, calls),
+            }
+            args.iter().for_each(|arg| calls_in_expr(arg, calls));
+        }
+        e::Expr::MacroCall { args, .. } | e::Expr::Array(args) | e::Expr::Tuple(args) => {
+            args.iter().for_each(|arg| calls_in_expr(arg, calls));
+        }
+        e::Expr::Field { base, .. } => calls_in_expr(base, calls),
+        e::Expr::Index { base, index } => {
+            calls_in_expr(base, calls);
+            calls_in_expr(index, calls);
+        }
+        e::Expr::Unary { expr, .. } | e::Expr::Cast { expr, .. } => calls_in_expr(expr, calls),
+        e::Expr::Binary { left, right, .. } => {
+            calls_in_expr(left, calls);
+            calls_in_expr(right, calls);
+        }
+        e::Expr::Range { start, end, .. } => {
+            for bound in [start, end].into_iter().flatten() {
+                calls_in_expr(bound, calls);
+            }
+        }
+        e::Expr::StructLiteral { fields, .. } => {
+            for field in fields {
+                calls_in_expr(&field.value, calls);
+            }
+        }
+        e::Expr::Closure { body, .. } => calls_in_block(body, calls),
+        e::Expr::Match { scrutinee, arms } => {
+            calls_in_expr(scrutinee, calls);
+            for arm in arms {
+                calls_in_expr(&arm.value, calls);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn modules() -> Vec<(String, String)> {
+        vec![
+            (
+                "app".to_string(),
+                "from util import clamp as limit\nimport rust.std.fs\n\ntype Counter:\n    n: i64\n\n    def bump(self):\n        self.n = limit(self.n + 1)\n\n    def twice(self):\n        self.bump()\n        self.bump()\n\ndef main():\n    c = Counter(0)\n    c.twice()\n    print(limit(5))\n".to_string(),
+            ),
+            (
+                "util".to_string(),
+                "def clamp(n: i64) -> i64:\n    return helper(n)\n\ndef helper(n: i64) -> i64:\n    return n\n".to_string(),
+            ),
+        ]
+    }
+
+    fn edges(graph: &Graph) -> Vec<String> {
+        graph
+            .edges
+            .iter()
+            .map(|(a, b)| format!("{a} -> {b}"))
+            .collect()
+    }
+
+    #[test]
+    fn test_module_graph() {
+        let graph = module_graph(&modules()).unwrap();
+        assert_eq!(edges(&graph), vec!["app -> rust.std.fs", "app -> util"]);
+        assert!(graph.nodes.contains("util"));
+    }
+
+    #[test]
+    fn test_module_graph_ignores_imports_in_strings() {
+        let source = "\
+def main():
+    \"\"\"Usage:
+import fake
+from fake import thing
+\"\"\"
+    print(\"from other import x\")
+";
+        let graph = module_graph(&[("app".to_string(), source.to_string())]).unwrap();
+        assert!(graph.edges.is_empty(), "{:?}", graph.edges);
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let graph = call_graph(&modules()).unwrap();
+        assert_eq!(
+            edges(&graph),
+            vec![
+                "app.Counter.bump -> util.clamp",
+                "app.Counter.twice -> app.Counter.bump",
+                "app.main -> util.clamp",
+                "util.clamp -> util.helper",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let mut graph = Graph::default();
+        graph.add_edge("a", "b.c");
+        assert_eq!(
+            graph.render(Format::Dot),
+            "digraph quiche {\n    rankdir=LR;\n    \"a\";\n    \"b.c\";\n    \"a\" -> \"b.c\";\n}\n"
+        );
+        assert_eq!(
+            graph.render(Format::Mermaid),
+            "flowchart LR\n    n0[\"a\"]\n    n1[\"b.c\"]\n    n0 --> n1\n"
+        );
+        assert_eq!(module_name(Path::new("net/http.q")), "net.http");
+    }
+}
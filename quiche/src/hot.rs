@@ -0,0 +1,290 @@
+//! Codegen for `quiche app.q --hot`, which swaps changed functions into the
+//! running script.
+//!
+//! [`instrument`] routes each top-level function but `main` through an
+//! indirection table: `f`'s body is renamed `__quiche_hot_f`, and a wrapper
+//! `f` calls what its `static __QUICHE_HOT_<n>: HotSlot` holds. It adds
+//! `quiche_hot_register`, listing every such function with its `fn` type
+//! and a hash of its `.q` source, and starts `main` with quiche-lib's
+//! `Hot::install`. A reload builds the same code as a shared library, whose
+//! `quiche_hot_register` the running script compares against its own (see
+//! quiche-lib's `Hot`).
+//!
+//! Generic functions, functions taking `impl Trait`, and functions defined
+//! more than once (under `@cfg`) are left alone and don't reload.
+
+use elevate::ast as e;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Environment variable naming the control file the running script
+/// watches for rebuilt libraries (quiche-lib's `HOT_ENV`).
+pub const HOT_ENV: &str = "QUICHE_HOT";
+
+/// A function `instrument` routed through the table.
+struct HotFunction {
+    name: String,
+    /// Its `fn` pointer type, e.g. `fn(i64, Str) -> i64`
+    signature: String,
+    hash: u64,
+}
+
+/// `rust_code`, generated from `module`, with its functions reloadable.
+pub fn instrument(rust_code: &str, module: &e::Module, source: &str) -> String {
+    let mut code = rust_code.to_string();
+    let mut functions = Vec::new();
+    let mut wrappers = String::new();
+    let mut defs = Vec::new();
+    for item in &module.items {
+        let e::Item::Function(func) = item else {
+            continue;
+        };
+        let text = func
+            .span
+            .and_then(|span| source.get(span.start..span.end))
+            .unwrap_or("");
+        defs.push(text);
+        if func.name == "main" || !func.type_params.is_empty() {
+            continue;
+        }
+        let slot = format!("__QUICHE_HOT_{}", functions.len());
+        let Some((signature, wrapper)) = route(&mut code, &func.name, &slot) else {
+            continue;
+        };
+        wrappers.push_str(&format!(
+            "\nstatic {slot}: quiche_lib::HotSlot = quiche_lib::HotSlot::new();\n\n{wrapper}"
+        ));
+        functions.push(HotFunction {
+            name: func.name.clone(),
+            signature,
+            hash: hash(text),
+        });
+    }
+
+    // Anything else changing (types, constants, imports) needs a restart
+    let mut rest = source.to_string();
+    for def in defs.iter().filter(|def| !def.is_empty()) {
+        rest = rest.replacen(def, "", 1);
+    }
+    let layout: Vec<&str> = rest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let mut register = String::from(
+        "\n#[no_mangle]\npub fn quiche_hot_register(register: &mut dyn FnMut(quiche_lib::HotFunction)) -> u64 {\n",
+    );
+    for (i, function) in functions.iter().enumerate() {
+        register.push_str(&format!(
+            "    register(quiche_lib::HotFunction::new({:?}, {:?}, {}, &__QUICHE_HOT_{i}, __quiche_hot_{} as {}));\n",
+            function.name, function.signature, function.hash, function.name, function.signature
+        ));
+    }
+    register.push_str(&format!("    {}\n}}\n", hash(&layout.join("\n"))));
+
+    let code = install(&code);
+    format!("{}\n{wrappers}{register}", code.trim_end_matches('\n'))
+}
+
+/// Rename the definition of `name` in `code` to `__quiche_hot_<name>`,
+/// returning its `fn` type and a wrapper `name` calling through `slot`.
+/// `None` (leaving `code` alone) for definitions a `fn` pointer can't
+/// stand in for.
+fn route(code: &mut String, name: &str, slot: &str) -> Option<(String, String)> {
+    let mut found = None;
+    for public in ["pub ", ""] {
+        let def = format!("{public}fn {name}(");
+        for (at, _) in code.match_indices(&def) {
+            if at == 0 || code[..at].ends_with('\n') {
+                if found.is_some() {
+                    return None;
+                }
+                found = Some((at, public));
+            }
+        }
+    }
+    let (at, public) = found?;
+    let open = at + public.len() + "fn ".len() + name.len();
+    let close = open + closing_paren(&code[open..])?;
+    let body = close + code[close..].find('{')?;
+    let returns = code[close + 1..body].trim();
+    if returns.contains(';') || returns.contains("where") {
+        return None;
+    }
+    let returns = returns.strip_prefix("->").map(str::trim).unwrap_or("");
+
+    let mut params = Vec::new();
+    for param in split_top_level(&code[open + 1..close]) {
+        let (pattern, ty) = param.split_once(':')?;
+        let pattern = pattern.trim();
+        let param = pattern.strip_prefix("mut ").unwrap_or(pattern).trim();
+        let ty = ty.trim();
+        if !param.chars().all(|c| c.is_alphanumeric() || c == '_') || ty.contains("impl ") {
+            return None;
+        }
+        params.push((param.to_string(), ty.to_string()));
+    }
+
+    let types: Vec<&str> = params.iter().map(|(_, ty)| ty.as_str()).collect();
+    let signature = if returns.is_empty() {
+        format!("fn({})", types.join(", "))
+    } else {
+        format!("fn({}) -> {returns}", types.join(", "))
+    };
+    let declared: Vec<String> = params
+        .iter()
+        .map(|(param, ty)| format!("{param}: {ty}"))
+        .collect();
+    let args: Vec<&str> = params.iter().map(|(param, _)| param.as_str()).collect();
+    let arrow = if returns.is_empty() {
+        String::new()
+    } else {
+        format!(" -> {returns}")
+    };
+    let wrapper = format!(
+        "{public}fn {name}({}){arrow} {{\n    {slot}.get(__quiche_hot_{name} as {signature})({})\n}}\n",
+        declared.join(", "),
+        args.join(", ")
+    );
+    code.replace_range(at..open, &format!("{public}fn __quiche_hot_{name}"));
+    Some((signature, wrapper))
+}
+
+/// Offset of the `)` closing the `(` that `text` starts with.
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut prev = ' ';
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            // The `>` of `->` in a `fn(...) -> T` parameter type
+            '>' if prev == '-' => {}
+            ')' | ']' | '>' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return (c == ')').then_some(i);
+                }
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    None
+}
+
+/// `params` split at the commas outside brackets.
+fn split_top_level(params: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut prev = ' ';
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            '>' if prev == '-' => {}
+            ')' | ']' | '>' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&params[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    parts.push(&params[start..]);
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// `code` with `main` starting by installing the reloader.
+fn install(code: &str) -> String {
+    let Some(main) = code
+        .match_indices("fn main()")
+        .map(|(at, _)| at)
+        .find(|&at| at == 0 || code[..at].ends_with('\n') || code[..at].ends_with("pub "))
+    else {
+        return code.to_string();
+    };
+    let Some(open) = code[main..].find('{').map(|brace| main + brace + 1) else {
+        return code.to_string();
+    };
+    format!(
+        "{}\n    quiche_lib::Hot::install(quiche_hot_register);{}",
+        &code[..open],
+        &code[open..]
+    )
+}
+
+fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instrument_routes_functions_through_slots() {
+        let source = "def scale(xs: List[i64], by: i64) -> i64:\n    return by\n\ndef apply[T](x: T) -> T:\n    return x\n\ndef main():\n    print(scale([1], 2))\n";
+        let module = crate::parser::parse(source).unwrap();
+        let rust = "pub fn scale(mut xs: List<i64>, by: i64) -> i64 {\n    by\n}\n\npub fn apply<T>(x: T) -> T {\n    x\n}\n\npub fn main() {\n    println!(\"{}\", scale(vec![1].into(), 2));\n}\n";
+        let hot = instrument(rust, &module, source);
+        assert!(
+            hot.starts_with("pub fn __quiche_hot_scale(mut xs: List<i64>, by: i64) -> i64 {\n    by\n}\n\npub fn apply<T>(x: T) -> T {"),
+            "{hot}"
+        );
+        assert!(
+            hot.contains(
+                "pub fn main() {\n    quiche_lib::Hot::install(quiche_hot_register);\n    println!"
+            ),
+            "{hot}"
+        );
+        assert!(hot.contains(
+            "static __QUICHE_HOT_0: quiche_lib::HotSlot = quiche_lib::HotSlot::new();\n\n\
+             pub fn scale(xs: List<i64>, by: i64) -> i64 {\n    \
+             __QUICHE_HOT_0.get(__quiche_hot_scale as fn(List<i64>, i64) -> i64)(xs, by)\n}\n"
+        ));
+        assert!(hot.contains(
+            "register(quiche_lib::HotFunction::new(\"scale\", \"fn(List<i64>, i64) -> i64\", "
+        ));
+        assert!(!hot.contains("__quiche_hot_apply"));
+        assert!(!hot.contains("__quiche_hot_main"));
+
+        // A body change keeps the layout hash; a new type changes it
+        let last_line = |code: &str| code.lines().rev().nth(1).unwrap().to_string();
+        let edited = source.replace("return by", "return by * 2");
+        let module = crate::parser::parse(&edited).unwrap();
+        assert_eq!(
+            last_line(&instrument(rust, &module, &edited)),
+            last_line(&hot)
+        );
+        let typed = format!("type Point:\n    x: i64\n\n{source}");
+        let module = crate::parser::parse(&typed).unwrap();
+        assert_ne!(
+            last_line(&instrument(rust, &module, &typed)),
+            last_line(&hot)
+        );
+    }
+
+    #[test]
+    fn test_route_skips_what_a_fn_pointer_cant_hold() {
+        let mut code = "fn draw(shape: impl Shape) {\n}\n".to_string();
+        assert!(route(&mut code, "draw", "S").is_none());
+        let mut code = "#[cfg(unix)]\nfn sep() {}\n#[cfg(windows)]\nfn sep() {}\n".to_string();
+        assert!(route(&mut code, "sep", "S").is_none());
+        let mut code = "fn on(f: fn(i64) -> i64, (a, b): (i64, i64)) {}\n".to_string();
+        assert!(route(&mut code, "on", "S").is_none());
+        let mut code = "fn on(f: fn(i64) -> i64, n: i64) {}\n".to_string();
+        let (signature, wrapper) = route(&mut code, "on", "S").unwrap();
+        assert_eq!(signature, "fn(fn(i64) -> i64, i64)");
+        assert_eq!(
+            wrapper,
+            "fn on(f: fn(i64) -> i64, n: i64) {\n    S.get(__quiche_hot_on as fn(fn(i64) -> i64, i64))(f, n)\n}\n"
+        );
+        assert_eq!(code, "fn __quiche_hot_on(f: fn(i64) -> i64, n: i64) {}\n");
+    }
+}
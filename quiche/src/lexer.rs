@@ -185,6 +185,7 @@ pub enum TokenKind {
     Semicolon, // ;
     Arrow,     // ->
     Ellipsis,  // ...
+    Question,  // ?
 
     // Special
     Comment(String),
@@ -252,6 +253,7 @@ impl std::fmt::Display for TokenKind {
             TokenKind::Semicolon => write!(f, "';'"),
             TokenKind::Arrow => write!(f, "'->'"),
             TokenKind::Ellipsis => write!(f, "'...'"),
+            TokenKind::Question => write!(f, "'?'"),
             TokenKind::Comment(_) => write!(f, "comment"),
         }
     }
@@ -346,6 +348,13 @@ pub struct Lexer<'a> {
     patterns: LexerPatterns,
     /// Track depth of nested brackets - newlines inside () [] {} are ignored
     bracket_depth: usize,
+    /// Bracket depth of the `(` after `fn`, while its parameters are lexed.
+    lambda_params: Option<usize>,
+    /// Bracket depth at which a `fn(...)` parameter list just closed.
+    lambda_header: Option<usize>,
+    /// Block-bodied lambdas being lexed: the bracket depth to restore and the
+    /// indent stack height to restore it at, once the body dedents.
+    lambda_blocks: Vec<(usize, usize)>,
 }
 
 impl<'a> Lexer<'a> {
@@ -361,6 +370,9 @@ impl<'a> Lexer<'a> {
             at_line_start: true,
             patterns: LexerPatterns::new()?,
             bracket_depth: 0,
+            lambda_params: None,
+            lambda_header: None,
+            lambda_blocks: Vec::new(),
         })
     }
 
@@ -552,6 +564,14 @@ impl<'a> Lexer<'a> {
                     self.indent_stack.pop();
                     self.pending_dedents += 1;
                 }
+                // Leaving a lambda body: back inside its brackets
+                while let Some(&(depth, height)) = self.lambda_blocks.last() {
+                    if self.indent_stack.len() > height {
+                        break;
+                    }
+                    self.lambda_blocks.pop();
+                    self.bracket_depth = depth;
+                }
 
                 // Check for inconsistent indentation
                 let new_current = *self.indent_stack.last().unwrap_or(&0);
@@ -583,6 +603,14 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Whether only whitespace or a comment is left on the current line.
+    fn line_is_blank(&self) -> bool {
+        let rest = self.remaining();
+        let line = &rest[..rest.find(['\n', '\r']).unwrap_or(rest.len())];
+        let code = line.split('#').next().unwrap_or("");
+        code.trim().is_empty()
+    }
+
     /// Try to match a regex pattern at the current position
     fn try_match(&self, re: &Regex) -> Option<&'a str> {
         re.find(self.remaining()).map(|m| m.as_str())
@@ -678,6 +706,21 @@ impl<'a> Lexer<'a> {
                 }
                 TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => {
                     self.bracket_depth = self.bracket_depth.saturating_sub(1);
+                    if self.lambda_params == Some(self.bracket_depth) {
+                        self.lambda_params = None;
+                        self.lambda_header = Some(self.bracket_depth);
+                    }
+                }
+                // `fn(x):` ending a line inside brackets opens an indented
+                // lambda body: lex it as a block until it dedents.
+                TokenKind::Colon
+                    if self.lambda_header.take() == Some(self.bracket_depth)
+                        && self.bracket_depth > 0
+                        && self.line_is_blank() =>
+                {
+                    self.lambda_blocks
+                        .push((self.bracket_depth, self.indent_stack.len()));
+                    self.bracket_depth = 0;
                 }
                 _ => {}
             }
@@ -854,6 +897,7 @@ impl<'a> Lexer<'a> {
             ',' => Some(TokenKind::Comma),
             '.' => Some(TokenKind::Dot),
             ';' => Some(TokenKind::Semicolon),
+            '?' => Some(TokenKind::Question),
             _ => Option::None,
         }
     }
@@ -906,13 +950,52 @@ impl<'a> Lexer<'a> {
         self.advance_by(quote_len);
 
         let mut content = String::new();
+        // Brace depth inside an f-string. A placeholder's expression is kept
+        // verbatim for the parser to lex: it may span lines and hold string
+        // literals, even ones using the f-string's own quotes (PEP 701).
+        let mut depth = 0usize;
 
         // Scan for end of string
         loop {
-            if self.remaining().starts_with(end_pattern) {
+            if depth == 0 && self.remaining().starts_with(end_pattern) {
                 break;
             }
 
+            if is_fstring {
+                match self.peek() {
+                    Some('{') if depth == 0 && self.remaining().starts_with("{{") => {
+                        self.advance_by(2);
+                        content.push_str("{{");
+                        continue;
+                    }
+                    Some('{') => {
+                        depth += 1;
+                        self.advance();
+                        content.push('{');
+                        continue;
+                    }
+                    Some('}') if depth > 0 => {
+                        depth -= 1;
+                        self.advance();
+                        content.push('}');
+                        continue;
+                    }
+                    Some(quote @ ('"' | '\'')) if depth > 0 => {
+                        self.lex_nested_string(quote, &mut content)?;
+                        continue;
+                    }
+                    Some(ch @ ('\n' | '\\')) if depth > 0 => {
+                        self.advance();
+                        content.push(ch);
+                        if ch == '\\' {
+                            content.extend(self.advance());
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
             match self.peek() {
                 Option::None => {
                     return Err(LexError {
@@ -960,7 +1043,7 @@ impl<'a> Lexer<'a> {
         // Reset at_line_start for multi-line strings - crossing newlines inside
         // strings shouldn't affect indentation tracking. We just finished lexing
         // a token that may have spanned multiple lines, so we're not at line start.
-        if is_triple {
+        if is_triple || self.line != line {
             self.at_line_start = false;
         }
 
@@ -989,6 +1072,41 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Copy a string literal inside an f-string placeholder to `content`
+    /// verbatim, quotes and escapes included.
+    fn lex_nested_string(&mut self, quote: char, content: &mut String) -> Result<(), LexError> {
+        let triple: String = std::iter::repeat_n(quote, 3).collect();
+        let end = if self.remaining().starts_with(&triple) {
+            triple
+        } else {
+            quote.to_string()
+        };
+        self.advance_by(end.len());
+        content.push_str(&end);
+        loop {
+            if self.remaining().starts_with(&end) {
+                self.advance_by(end.len());
+                content.push_str(&end);
+                return Ok(());
+            }
+            match self.advance() {
+                Some('\\') => {
+                    content.push('\\');
+                    content.extend(self.advance());
+                }
+                Some(ch) => content.push(ch),
+                Option::None => {
+                    return Err(LexError {
+                        message: "Unterminated string literal in f-string".to_string(),
+                        line: self.line,
+                        column: self.column,
+                        pos: self.pos,
+                    });
+                }
+            }
+        }
+    }
+
     /// Lex a number literal
     fn lex_number(&mut self, start: usize, line: usize, column: usize) -> Result<Token, LexError> {
         // Try hex, octal, binary first
@@ -1086,6 +1204,9 @@ impl<'a> Lexer<'a> {
     ) -> Result<Token, LexError> {
         if let Some(matched) = self.try_match(&self.patterns.ident) {
             self.advance_by(matched.len());
+            if matched == "fn" && self.peek() == Some('(') {
+                self.lambda_params = Some(self.bracket_depth);
+            }
             let kind = if let Some(kw) = Keyword::from_str(matched) {
                 TokenKind::Keyword(kw)
             } else {
@@ -1210,7 +1331,7 @@ mod tests {
 
     #[test]
     fn test_operators() {
-        let tokens = tok_kinds("+ - * ** // -> ==");
+        let tokens = tok_kinds("+ - * ** // -> == ?");
         assert_eq!(
             tokens,
             vec![
@@ -1221,6 +1342,7 @@ mod tests {
                 TokenKind::DoubleSlash,
                 TokenKind::Arrow,
                 TokenKind::EqEq,
+                TokenKind::Question,
                 TokenKind::Eof,
             ]
         );
@@ -3,9 +3,40 @@
 //! Parses `.q` source files and produces `elevate::ast::Module`,
 //! which feeds directly into the Elevate compiler pipeline.
 
+pub mod bindgen;
+pub mod build;
+pub mod coerce;
+pub mod daemon;
+pub mod diagnostics;
+pub mod engine;
+pub mod fmt;
+pub mod format;
+pub mod graph;
+pub mod hot;
 pub mod lexer;
+pub mod memprof;
+pub mod overflow;
 pub mod parser;
-
+pub mod pipeline;
+pub mod prelude;
+pub mod qmeta;
+pub mod qtest;
+pub mod repl;
+pub mod script;
+pub mod traceback;
+pub mod unsupported;
+pub mod vendor;
+
+pub use build::compile_dir;
+pub use engine::{Engine, Value};
+pub use overflow::Overflow;
+pub use pipeline::CompilePipeline;
+pub use prelude::Prelude;
+
+use std::collections::HashSet;
+
+/// The Elevate AST, which [`CompilePipeline`] passes rewrite.
+pub use elevate::ast;
 // Re-export Elevate options so the CLI can use them without depending on elevate directly
 pub use elevate::{CompileOptions, CompilerOutput, ExperimentFlags};
 
@@ -15,7 +46,6 @@ pub fn default_options() -> CompileOptions {
         experiments: ExperimentFlags {
             move_mut_args: false,
             type_system: true,
-            ..Default::default()
         },
         ..Default::default()
     }
@@ -26,10 +56,77 @@ pub fn parse(source: &str) -> Result<elevate::ast::Module, String> {
     parser::parse(source).map_err(|e| format!("{e}"))
 }
 
+/// crates.io crates the source depends on through `@extern(crate=...)` or
+/// `@extern(path=...)` declarations (its own or from imported `.qi`
+/// interfaces), sorted and deduplicated, each with the version its
+/// `@extern(..., version=...)` asks for.
+pub fn extern_crates(
+    source: &str,
+    filename: &str,
+) -> Result<Vec<(String, Option<String>)>, String> {
+    let interfaces = imported_interfaces(source, filename)?;
+    let mut parsed =
+        parser::parse_with_interfaces(source, &interfaces, false).map_err(|e| format!("{e}"))?;
+    Ok(parsed
+        .extern_crates
+        .into_iter()
+        .map(|krate| {
+            let version = parsed.crate_versions.remove(&krate);
+            (krate, version)
+        })
+        .collect())
+}
+
+/// `.qi` interface files (see [`bindgen`]) named by top-level `import <name>`
+/// statements (any `as` alias aside), looked up next to `filename`. Imports
+/// without an interface file are left to the parser.
+fn imported_interfaces(source: &str, filename: &str) -> Result<Vec<(String, String)>, String> {
+    let dir = std::path::Path::new(filename)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let imports = parser::parse_imports(source).map_err(|e| format!("{e}"))?;
+    let mut interfaces = Vec::new();
+    for import in imports.iter().filter(|import| import.names.is_empty()) {
+        let path = dir.join(format!("{}.qi", import.module.replace('.', "/")));
+        if path.is_file() {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+            interfaces.push((path.display().to_string(), text));
+        }
+    }
+    Ok(interfaces)
+}
+
+/// Environment variable that lets [`compile_file`] (and so `build.rs`
+/// builds) compile `unsafe:` blocks, as `--allow-unsafe` does for `quiche`.
+pub const ALLOW_UNSAFE_ENV: &str = "QUICHE_ALLOW_UNSAFE";
+
+/// Fail if the source contains `unsafe:` blocks, listing each one. They only
+/// compile when explicitly allowed (`--allow-unsafe`), so every use of
+/// unsafe Rust in a project is an audited opt-in.
+pub fn reject_unsafe(source: &str, filename: &str) -> Result<(), String> {
+    let interfaces = imported_interfaces(source, filename)?;
+    let parsed =
+        parser::parse_with_interfaces(source, &interfaces, false).map_err(|e| format!("{e}"))?;
+    if parsed.unsafe_blocks.is_empty() {
+        return Ok(());
+    }
+    let blocks: Vec<String> = parsed
+        .unsafe_blocks
+        .iter()
+        .map(|(line, column)| format!("  {filename}:{line}:{column}: unsafe block"))
+        .collect();
+    Err(format!(
+        "`unsafe:` blocks are not allowed; pass --allow-unsafe (or set {ALLOW_UNSAFE_ENV}=1) to compile them\n{}",
+        blocks.join("\n")
+    ))
+}
+
 /// Parse Quiche source, compile through Elevate, and emit Elevate source code.
 /// This produces valid `.ers` syntax from the typed IR — useful for bug reports.
 pub fn emit_elevate(source: &str, options: &CompileOptions) -> Result<String, String> {
     let module = parser::parse(source).map_err(|e| format!("{e}"))?;
+    let module = Prelude::from_env()?.apply(&module);
     let output = elevate::compile_ast_with_options(&module, options).map_err(|e| format!("{e}"))?;
     Ok(elevate::emit_elevate::emit_typed_module(&output.typed))
 }
@@ -42,9 +139,11 @@ pub fn compile(source: &str) -> Result<String, String> {
 /// Parse Quiche source and compile to Rust via Elevate with custom options.
 pub fn compile_with_options(source: &str, options: &CompileOptions) -> Result<String, String> {
     let module = parser::parse(source).map_err(|e| format!("{e}"))?;
+    let module = Prelude::from_env()?.apply(&module);
     let output = elevate::compile_ast_with_options(&module, options).map_err(|e| format!("{e}"))?;
-    Ok(inject_auto_imports(&wrap_collections(
-        &inject_display_impls(&output.rust_code),
+    Ok(wrap_collections(&inject_display_impls(
+        &apply_reprs(&output.rust_code),
+        &module,
     )))
 }
 
@@ -55,10 +154,177 @@ pub fn compile_file(
     filename: &str,
     options: &CompileOptions,
 ) -> Result<String, String> {
-    let module = parser::parse(source).map_err(|e| format!("{e}"))?;
+    compile_file_with_imports(source, filename, options, &[])
+}
+
+/// [`compile_file`] for a module of a project, given the symbol tables of
+/// the project modules it imports (see [`qmeta`]).
+pub fn compile_file_with_imports(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+    imports: &[qmeta::ModuleMeta],
+) -> Result<String, String> {
+    if std::env::var_os(ALLOW_UNSAFE_ENV).is_none() {
+        reject_unsafe(source, filename)?;
+    }
+    compile_parsed_file(
+        source,
+        filename,
+        options,
+        Overflow::from_env()?,
+        &Prelude::from_env()?,
+        imports,
+        &[],
+    )
+}
+
+/// The symbol tables `source` defines, which [`compile_file_with_imports`]
+/// takes for the modules importing it.
+pub fn module_meta(source: &str, filename: &str) -> Result<qmeta::ModuleMeta, String> {
+    let interfaces = imported_interfaces(source, filename)?;
+    parser::parse_with_interfaces(source, &interfaces, false)
+        .map(|parsed| parsed.meta)
+        .map_err(|e| format!("{e}"))
+}
+
+/// [`compile_file`] with integer `+ - *` lowered for `overflow` (see
+/// [`overflow`]) rather than for the mode in `QUICHE_OVERFLOW`.
+pub fn compile_file_with_overflow(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+    overflow: Overflow,
+) -> Result<String, String> {
+    compile_parsed_file(
+        source,
+        filename,
+        options,
+        overflow,
+        &Prelude::from_env()?,
+        &[],
+        &[],
+    )
+}
+
+/// [`compile_file_with_overflow`] with `prelude` ahead of the module (see
+/// [`prelude`]) rather than the one in `QUICHE_PRELUDE`.
+pub fn compile_file_with_prelude(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+    overflow: Overflow,
+    prelude: &Prelude,
+) -> Result<String, String> {
+    compile_parsed_file(source, filename, options, overflow, prelude, &[], &[])
+}
+
+fn compile_parsed_file(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+    overflow: Overflow,
+    prelude: &Prelude,
+    imports: &[qmeta::ModuleMeta],
+    passes: &[(String, pipeline::Pass)],
+) -> Result<String, String> {
+    let interfaces = imported_interfaces(source, filename)?;
+    let parsed = memprof::pass("parse", || {
+        parser::parse_with_imports(source, &interfaces, imports, false)
+    })
+    .map_err(|e| format!("{e}"))?;
+    let decorators = parsed.decorators.clone();
+    let mut module = parsed.without_inline_tests();
+    report_coercions(&mut module, source, filename);
+    memprof::pass("overflow", || overflow::lower(&mut module, overflow));
+    let context = pipeline::PassContext {
+        filename,
+        decorators: &decorators,
+    };
+    memprof::pass("passes", || {
+        pipeline::run_passes(passes, &mut module, &context)
+    })?;
+    compile_module_file(&module, source, filename, options, prelude)
+}
+
+/// Insert the numeric widening casts (see [`coerce`]) and print a warning
+/// for each lossy one.
+fn report_coercions(module: &mut elevate::ast::Module, source: &str, filename: &str) {
+    for warning in memprof::pass("coerce", || coerce::coerce(module)) {
+        warn(warning.render(filename, source));
+    }
+}
+
+thread_local! {
+    /// Warnings held for [`capture_warnings`]'s caller instead of printed.
+    static CAPTURED_WARNINGS: std::cell::RefCell<Option<Vec<String>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Print a compiler warning, or hold it for [`capture_warnings`].
+fn warn(message: String) {
+    CAPTURED_WARNINGS.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(warnings) => warnings.push(message),
+        None => eprintln!("{message}"),
+    });
+}
+
+/// Run `compile`, returning the warnings it would have printed alongside
+/// its result — for [`daemon`], whose stderr no client sees.
+pub(crate) fn capture_warnings<T>(compile: impl FnOnce() -> T) -> (T, Vec<String>) {
+    let outer = CAPTURED_WARNINGS.with(|captured| captured.replace(Some(Vec::new())));
+    let result = compile();
+    let warnings = CAPTURED_WARNINGS.with(|captured| captured.replace(outer));
+    (result, warnings.unwrap_or_default())
+}
+
+/// Compile a .q file in test mode: `main` is replaced by a generated harness
+/// that runs every `test_*` function with its `@fixture` arguments.
+/// With `coverage`, every statement is probed for line coverage.
+pub fn compile_test_file(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+    prelude: &Prelude,
+    coverage: bool,
+) -> Result<String, String> {
+    let interfaces = imported_interfaces(source, filename)?;
+    let mut parsed =
+        parser::parse_with_interfaces(source, &interfaces, coverage).map_err(|e| format!("{e}"))?;
+    qtest::build_harness(&mut parsed, filename)?;
+    report_coercions(&mut parsed.module, source, filename);
+    overflow::lower(&mut parsed.module, Overflow::from_env()?);
+    compile_module_file(&parsed.module, source, filename, options, prelude)
+}
+
+/// Compile `source` so that running it calls the session hook `hook` (see
+/// [`qtest::build_hook_main`]) instead of `main`.
+pub fn compile_hook_file(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+    prelude: &Prelude,
+    hook: &str,
+) -> Result<String, String> {
+    let mut module = parser::parse(source).map_err(|e| format!("{e}"))?;
+    qtest::build_hook_main(&mut module, hook);
+    compile_module_file(&module, source, filename, options, prelude)
+}
+
+fn compile_module_file(
+    module: &elevate::ast::Module,
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+    prelude: &Prelude,
+) -> Result<String, String> {
+    let module = &prelude.apply(module);
     let mut opts = options.clone();
     opts.source_name = Some(filename.to_string());
-    let output = elevate::compile_ast_with_options(&module, &opts).map_err(|e| {
+    let output = memprof::pass("elevate", || {
+        elevate::compile_ast_with_options(module, &opts)
+    })
+    .map_err(|e| {
         // CompileError Display already uses source_map::render_diagnostic,
         // but we need to also supply source_text for line:col resolution
         let mut err = e;
@@ -67,261 +333,643 @@ pub fn compile_file(
         }
         format!("{err}")
     })?;
-    Ok(inject_auto_imports(&wrap_collections(
-        &inject_display_impls(&output.rust_code),
-    )))
+    Ok(memprof::pass("rewrite", || {
+        let rust_code = resolve_include_paths(&output.rust_code, filename);
+        wrap_collections(&inject_display_impls(&apply_reprs(&rust_code), module))
+    }))
 }
 
-/// Post-process generated Rust: auto-generate `impl Display` for structs
-/// that define a `to_string` method. This lets `print(x)` use the custom
-/// format without the user writing any trait boilerplate.
-fn inject_display_impls(rust_code: &str) -> String {
-    let re_struct = regex::Regex::new(r"pub struct (\w+)").unwrap();
-    let re_impl_to_string = regex::Regex::new(r"impl (\w+)\s*\{[^}]*fn to_string\s*\(").unwrap();
+/// Make the relative paths of `include_str("...")` relative to the `.q` file
+/// that names them: the generated Rust is built elsewhere, and `include_str!`
+/// resolves paths against the file it appears in.
+fn resolve_include_paths(rust_code: &str, filename: &str) -> String {
+    const MACRO: &str = "include_str!(\"";
+    let source_dir = std::path::Path::new(filename)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let mut out = String::with_capacity(rust_code.len());
+    let mut rest = rust_code;
+    while let Some(at) = rest.find(MACRO) {
+        let literal_start = at + MACRO.len() - 1;
+        out.push_str(&rest[..literal_start]);
+        rest = &rest[literal_start..];
+        let mut path = String::new();
+        let mut chars = rest.char_indices().skip(1);
+        let mut literal_len = rest.len();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => path.extend(chars.next().map(|(_, escaped)| escaped)),
+                '"' => {
+                    literal_len = i + 1;
+                    break;
+                }
+                c => path.push(c),
+            }
+        }
+        let resolved = source_dir.join(&path);
+        match std::path::absolute(&resolved) {
+            Ok(resolved) if std::path::Path::new(&path).is_relative() => {
+                out.push_str(&format!("{:?}", resolved.to_string_lossy()));
+            }
+            _ => out.push_str(&rest[..literal_len]),
+        }
+        rest = &rest[literal_len..];
+    }
+    out.push_str(rest);
+    out
+}
 
-    // Collect struct names that have a to_string method
-    let struct_names: std::collections::HashSet<String> = re_struct
-        .captures_iter(rust_code)
-        .map(|c| c[1].to_string())
-        .collect();
+/// Post-process generated Rust: apply the layouts, attributes and blocks the
+/// parser asked for with markers, which Elevate's definitions can't express,
+/// then drop the marker comments.
+///
+/// Each pass walks the tokens of the Rust, like the rewrites below: a marker
+/// is a `// quiche:` line comment, never text inside a string literal, and
+/// items and enum bodies are found by their tokens, not by substrings.
+fn apply_reprs(rust_code: &str) -> String {
+    strip_markers(&apply_with_blocks(&apply_unsafe_blocks(
+        &apply_item_attributes(&apply_transparent_newtypes(&apply_enum_discriminants(
+            &apply_derives(rust_code),
+        ))),
+    )))
+}
 
-    let impls_with_to_string: std::collections::HashSet<String> = re_impl_to_string
-        .captures_iter(rust_code)
-        .map(|c| c[1].to_string())
-        .collect();
+/// The `// quiche:<kind> <payload>` marker comments in `rust_code`, with
+/// their offsets.
+fn markers<'a>(rust_code: &'a str, kind: &str) -> Vec<(usize, &'a str)> {
+    let prefix = format!("// quiche:{kind} ");
+    rust_tokens(rust_code)
+        .filter_map(|(at, token)| match token {
+            RustToken::Literal(comment) => comment
+                .strip_prefix(prefix.as_str())
+                .map(|payload| (at, payload.trim_end())),
+            _ => None,
+        })
+        .collect()
+}
 
-    let needs_display: Vec<&String> = struct_names
-        .iter()
-        .filter(|name| impls_with_to_string.contains(*name))
+/// Remove every `// quiche:` marker comment, with its line if the marker is
+/// all there is on it.
+fn strip_markers(rust_code: &str) -> String {
+    let edits = rust_tokens(rust_code)
+        .filter_map(|(at, token)| match token {
+            RustToken::Literal(comment) if comment.starts_with("// quiche:") => {
+                let start = line_start(rust_code, at);
+                let end = at + comment.len();
+                if rust_code[start..at].trim().is_empty() {
+                    let end = if rust_code[end..].starts_with('\n') {
+                        end + 1
+                    } else {
+                        end
+                    };
+                    Some((start..end, String::new()))
+                } else {
+                    Some((at..end, String::new()))
+                }
+            }
+            _ => None,
+        })
         .collect();
+    apply_edits(rust_code, edits)
+}
 
-    if needs_display.is_empty() {
-        return rust_code.to_string();
-    }
-
-    let mut result = rust_code.to_string();
-    for name in &needs_display {
-        result.push_str(&format!(
-            "\nimpl std::fmt::Display for {name} {{\n    \
-             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{\n        \
-             write!(f, \"{{}}\", self.clone().to_string())\n    \
-             }}\n}}\n"
-        ));
+/// Replace each `edits` range of `code` by its text. Ranges must not
+/// overlap; insertions at the same offset keep their order.
+fn apply_edits(code: &str, mut edits: Vec<(std::ops::Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut result = String::with_capacity(code.len());
+    let mut copied = 0;
+    for (range, text) in edits {
+        result.push_str(&code[copied..range.start]);
+        result.push_str(&text);
+        copied = range.end;
     }
+    result.push_str(&code[copied..]);
     result
 }
 
-/// Auto-inject `use std::collections::*` imports when the generated Rust
-/// references collection types that aren't in the prelude.
-fn inject_auto_imports(rust_code: &str) -> String {
-    // HashMap::from(vec![…]) → HashMap::from([…]) already handled by wrap_collections
+/// Offset of the start of the line holding offset `at`.
+fn line_start(code: &str, at: usize) -> usize {
+    code[..at].rfind('\n').map_or(0, |i| i + 1)
+}
 
-    let mut imports = Vec::new();
+/// The tokens of `code` from `at` on, leaving out whitespace and comments.
+fn significant_tokens(code: &str, at: usize) -> impl Iterator<Item = (usize, RustToken<'_>)> {
+    RustTokens { code, at }.filter(|(_, token)| match token {
+        RustToken::Punct(c) => !c.is_whitespace(),
+        RustToken::Literal(text) => !text.starts_with("//") && !text.starts_with("/*"),
+        RustToken::Ident(_) => true,
+    })
+}
 
-    if rust_code.contains("HashMap") {
-        imports.push("HashMap");
-    }
-    if rust_code.contains("HashSet") {
-        imports.push("HashSet");
-    }
-    if rust_code.contains("BTreeMap") {
-        imports.push("BTreeMap");
-    }
-    if rust_code.contains("BTreeSet") {
-        imports.push("BTreeSet");
+/// The first item after offset `from` declared by one of `keywords` (`fn`,
+/// `struct`, `enum`) and named `name`: the offsets of its keyword and of the
+/// end of its name.
+fn find_item(code: &str, from: usize, keywords: &[&str], name: &str) -> Option<(usize, usize)> {
+    let mut previous: Option<(usize, &str)> = None;
+    for (at, token) in significant_tokens(code, from) {
+        if let (Some((keyword_at, keyword)), RustToken::Ident(ident)) = (previous, token)
+            && ident == name
+            && keywords.contains(&keyword)
+        {
+            return Some((keyword_at, at + ident.len()));
+        }
+        previous = match token {
+            RustToken::Ident(ident) => Some((at, ident)),
+            _ => None,
+        };
     }
+    None
+}
 
-    if imports.is_empty() {
-        return rust_code.to_string();
-    }
+/// Post-process generated Rust: turn the `if __unsafe__!() {` the parser
+/// lowers an `unsafe:` block to into `unsafe {`.
+fn apply_unsafe_blocks(rust_code: &str) -> String {
+    replace_marker_blocks(rust_code, "__unsafe__", "unsafe {")
+}
+
+/// Post-process generated Rust: turn the `if __with__!() {` the parser
+/// lowers a `with` block to into a plain block, so its guards drop where it
+/// ends.
+fn apply_with_blocks(rust_code: &str) -> String {
+    replace_marker_blocks(rust_code, "__with__", "{")
+}
 
-    let import_line = format!("use std::collections::{{{}}};\n", imports.join(", "));
+/// Replace each `if <name>!() {` in `rust_code` with `opening`.
+fn replace_marker_blocks(rust_code: &str, name: &str, opening: &str) -> String {
+    let tokens: Vec<(usize, RustToken)> = significant_tokens(rust_code, 0).collect();
+    let pattern = [
+        RustToken::Ident("if"),
+        RustToken::Ident(name),
+        RustToken::Punct('!'),
+        RustToken::Punct('('),
+        RustToken::Punct(')'),
+        RustToken::Punct('{'),
+    ];
+    let edits = tokens
+        .windows(pattern.len())
+        .filter(|window| window.iter().map(|(_, token)| *token).eq(pattern))
+        .map(|window| {
+            (
+                window[0].0..window[pattern.len() - 1].0 + 1,
+                opening.to_string(),
+            )
+        })
+        .collect();
+    apply_edits(rust_code, edits)
+}
 
-    // Insert after the last #![allow(...)] line
-    if let Some(pos) = rust_code.rfind("#![allow(") {
-        if let Some(end) = rust_code[pos..].find('\n') {
-            let insert_at = pos + end + 1;
-            let mut result = String::with_capacity(rust_code.len() + import_line.len());
-            result.push_str(&rust_code[..insert_at]);
-            result.push_str(&import_line);
-            result.push_str(&rust_code[insert_at..]);
-            return result;
+/// Add the traits of `@derive(...)` to their type's `#[derive(...)]` list,
+/// skipping any Elevate already derives. The parser leaves a
+/// `// quiche:derive Point Hash, Default` marker line for each type.
+fn apply_derives(rust_code: &str) -> String {
+    let mut edits = Vec::new();
+    for (_, marker) in markers(rust_code, "derive") {
+        let Some((name, traits)) = marker.split_once(' ') else {
+            continue;
+        };
+        let traits: Vec<&str> = traits.split(',').map(str::trim).collect();
+        let Some((keyword_at, _)) = find_item(rust_code, 0, &["struct", "enum"], name) else {
+            continue;
+        };
+        let header = line_start(rust_code, keyword_at);
+        // The attribute lines right above the header, nearest first
+        let mut existing = None;
+        let mut line_end = header;
+        while line_end > 0 {
+            let start = line_start(rust_code, line_end - 1);
+            let line = rust_code[start..line_end - 1].trim();
+            if !line.starts_with("#[") {
+                break;
+            }
+            if line.starts_with("#[derive(") {
+                existing = Some(start..line_end - 1);
+                break;
+            }
+            line_end = start;
+        }
+        match existing {
+            Some(range) => {
+                let line = &rust_code[range.clone()];
+                let (Some(open), Some(close)) = (line.find('('), line.rfind(')')) else {
+                    continue;
+                };
+                let mut derived: Vec<&str> = line[open + 1..close]
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .collect();
+                for t in &traits {
+                    if !derived.contains(t) {
+                        derived.push(t);
+                    }
+                }
+                let start = range.start + open + 1;
+                edits.push((start..range.start + close, derived.join(", ")));
+            }
+            None => edits.push((
+                header..header,
+                format!("#[derive({})]\n", traits.join(", ")),
+            )),
         }
     }
-
-    // Fallback: prepend
-    format!("{import_line}{rust_code}")
+    apply_edits(rust_code, edits)
 }
 
-/// Fix `HashMap::from(vec![...])` → `HashMap::from([...])`
-/// AND wrap collection constructors in List/Dict newtypes.
-///
-/// Elevate sees `Vec` and `HashMap` during type-checking, but Quiche programs
-/// should work with `List` and `Dict`.  We rewrite at the Rust string level so
-/// Elevate can still resolve `.push()`, `.len()`, etc. against its Vec/HashMap
-/// knowledge, and the final Rust code uses quiche-lib newtypes.
-fn wrap_collections(rust_code: &str) -> String {
-    // Step 1: fix HashMap::from(vec![…]) → HashMap::from([…])
-    let code = rust_code.replace("HashMap::from(vec![", "HashMap::from([");
-
-    // Step 2: wrap vec![…] → List::from(vec![…])
-    //         and  Vec::new() → List::new()
-    let code = wrap_vec_in_list(&code);
-
-    // Step 3: wrap HashMap::from(…) → Dict(HashMap::from(…))
-    //         and  HashMap::new()   → Dict::new()
-    let code = wrap_hashmap_in_dict(&code);
+/// Put the attributes of `@inline`, `@cold`, `@hot` and `@cfg` on their
+/// functions, and those of `@rust_attr` on their functions and types. The
+/// parser leaves a `// quiche:attribute fib #[inline(always)]` marker line
+/// for each, ahead of the item it applies to: a name can be defined more
+/// than once under different `@cfg`s.
+fn apply_item_attributes(rust_code: &str) -> String {
+    let edits = markers(rust_code, "attribute")
+        .into_iter()
+        .filter_map(|(at, marker)| {
+            let (name, attribute) = marker.split_once(' ')?;
+            let (keyword_at, _) = find_item(rust_code, at, &["fn", "struct", "enum"], name)?;
+            let header = line_start(rust_code, keyword_at);
+            Some((header..header, format!("{}\n", attribute.trim())))
+        })
+        .collect();
+    apply_edits(rust_code, edits)
+}
 
-    // Step 4: rewrite type annotations  Vec<T> → List<T>
-    let code = rewrite_vec_type_annotations(&code);
+/// Make newtypes (`type Meters = new f64`) `#[repr(transparent)]`. The
+/// parser leaves a `// quiche:transparent Meters` marker line for each.
+fn apply_transparent_newtypes(rust_code: &str) -> String {
+    let edits = markers(rust_code, "transparent")
+        .into_iter()
+        .filter_map(|(_, name)| {
+            let (keyword_at, _) = find_item(rust_code, 0, &["struct"], name)?;
+            let header = line_start(rust_code, keyword_at);
+            Some((header..header, "#[repr(transparent)]\n".to_string()))
+        })
+        .collect();
+    apply_edits(rust_code, edits)
+}
 
-    // Step 5: rewrite type annotations  HashMap<K,V> → Dict<K,V>
-    rewrite_hashmap_type_annotations(&code)
+/// Post-process generated Rust: give enums declared with discriminants
+/// (`type Status = | Ok = 0 | NotFound = 404`) their values and a
+/// `#[repr(i32)]` (`i64` if a value needs it). The parser leaves a
+/// `// quiche:discriminants Status Ok=0 NotFound=404` marker line for each.
+fn apply_enum_discriminants(rust_code: &str) -> String {
+    let mut edits = Vec::new();
+    for (_, marker) in markers(rust_code, "discriminants") {
+        let mut words = marker.split_whitespace();
+        let Some(name) = words.next() else { continue };
+        let values: Vec<(&str, i64)> = words
+            .filter_map(|w| w.split_once('='))
+            .filter_map(|(v, n)| Some((v, n.parse().ok()?)))
+            .collect();
+        let Some((keyword_at, name_end)) = find_item(rust_code, 0, &["enum"], name) else {
+            continue;
+        };
+        // Each variant is the first name at the body's own depth after its
+        // `{` or `,`, past any attributes
+        let mut tokens = significant_tokens(rust_code, name_end);
+        if !matches!(tokens.next(), Some((_, RustToken::Punct('{')))) {
+            continue;
+        }
+        let (mut depth, mut expecting) = (1usize, true);
+        for (at, token) in tokens {
+            match token {
+                RustToken::Punct('{' | '(' | '[') => depth += 1,
+                RustToken::Punct('}' | ')' | ']') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                RustToken::Punct(',') if depth == 1 => expecting = true,
+                RustToken::Ident(variant) if depth == 1 && expecting => {
+                    expecting = false;
+                    if let Some((_, n)) = values.iter().find(|(v, _)| *v == variant) {
+                        let end = at + variant.len();
+                        edits.push((end..end, format!(" = {n}")));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let repr = if values.iter().all(|(_, n)| i32::try_from(*n).is_ok()) {
+            "i32"
+        } else {
+            "i64"
+        };
+        let header = line_start(rust_code, keyword_at);
+        edits.push((header..header, format!("#[repr({repr})]\n")));
+    }
+    apply_edits(rust_code, edits)
 }
 
-/// Find each `vec![…]` (bracket-aware) and wrap in `List::from(vec![…])`.
-/// Also replaces `Vec::new()` → `List::new()`.
-fn wrap_vec_in_list(code: &str) -> String {
-    // First, simple replacements
-    let code = code.replace("Vec::new()", "List::new()");
+/// Post-process generated Rust: auto-generate `impl Display` for the
+/// structs of `module` that define a `to_string` method. This lets
+/// `print(x)` use the custom format without the user writing any trait
+/// boilerplate.
+///
+/// The module says which structs get one; their generics are read from the
+/// generated declaration, whose bounds are what Elevate emitted.
+fn inject_display_impls(rust_code: &str, module: &elevate::ast::Module) -> String {
+    use elevate::ast::Item;
+    let structs: HashSet<&str> = module
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Struct(s) => Some(s.name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let with_to_string: HashSet<&str> = module
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Impl(block)
+                if block.trait_target.is_none()
+                    && structs.contains(block.target.as_str())
+                    && block.methods.iter().any(|m| m.name == "to_string") =>
+            {
+                Some(block.target.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    if with_to_string.is_empty() {
+        return rust_code.to_string();
+    }
 
-    let mut result = String::with_capacity(code.len() + 128);
-    let bytes = code.as_bytes();
-    let mut i = 0;
-
-    while i < bytes.len() {
-        // Look for `vec![`
-        if i + 4 < bytes.len() && &code[i..i + 4] == "vec!" && bytes.get(i + 4) == Some(&b'[') {
-            // Find the matching `]`
-            let open = i + 4; // position of `[`
-            if let Some(close) = find_matching_bracket(&code, open) {
-                result.push_str("List::from(");
-                result.push_str(&code[i..=close]); // vec![…]
-                result.push(')');
-                i = close + 1;
-            } else {
-                // No matching bracket — emit as-is
-                result.push(bytes[i] as char);
-                i += 1;
+    // Each `struct Name<...>` declaration, with its generics (or none)
+    let mut needs_display: Vec<(&str, &str)> = Vec::new();
+    let mut after_struct = false;
+    for (at, token) in rust_tokens(rust_code) {
+        match token {
+            RustToken::Ident(name) if after_struct && with_to_string.contains(name) => {
+                let rest = &rust_code[at + name.len()..];
+                let generics = if rest.starts_with('<') {
+                    find_matching_angle(rest).map_or("", |close| &rest[..=close])
+                } else {
+                    ""
+                };
+                if !needs_display.iter().any(|(seen, _)| *seen == name) {
+                    needs_display.push((name, generics));
+                }
+                after_struct = false;
             }
-        } else {
-            result.push(bytes[i] as char);
-            i += 1;
+            RustToken::Ident(ident) => after_struct = ident == "struct",
+            RustToken::Punct(c) if c.is_whitespace() => {}
+            _ => after_struct = false,
         }
     }
+
+    let mut result = rust_code.to_string();
+    for (name, generics) in needs_display {
+        // A generic struct's bounds carry over, and its params may not be
+        // `Clone` themselves
+        let (target, clause) = if generics.is_empty() {
+            (name.to_string(), "")
+        } else {
+            let params: Vec<&str> = split_top_level(&generics[1..generics.len() - 1])
+                .into_iter()
+                .map(|p| p.split(':').next().unwrap_or(p).trim())
+                .collect();
+            (
+                format!("{name}<{}>", params.join(", ")),
+                " where Self: Clone",
+            )
+        };
+        result.push_str(&format!(
+            "\nimpl{generics} std::fmt::Display for {target}{clause} {{\n    \
+             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{\n        \
+             write!(f, \"{{}}\", self.clone().to_string())\n    \
+             }}\n}}\n"
+        ));
+    }
     result
 }
 
-/// Wrap `HashMap::from(…)` in `Dict(…)` and replace `HashMap::new()` with `Dict::new()`.
-fn wrap_hashmap_in_dict(code: &str) -> String {
-    // HashMap::new() → Dict::new()
-    let code = code.replace("HashMap::new()", "Dict::new()");
-
-    // HashMap::from(…) / HashMap::from_iter(…) → Dict(HashMap::…(…))
-    let mut result = String::with_capacity(code.len() + 128);
-    let bytes = code.as_bytes();
-    let mut i = 0;
-    let needles = ["HashMap::from_iter(", "HashMap::from("];
-
-    while i < bytes.len() {
-        let mut matched = false;
-        for needle in &needles {
-            if i + needle.len() <= bytes.len() && &code[i..i + needle.len()] == *needle {
-                let open = i + needle.len() - 1; // position of `(`
-                if let Some(close) = find_matching_paren(&code, open) {
-                    result.push_str("Dict(");
-                    result.push_str(&code[i..=close]); // HashMap::from(…)
-                    result.push(')');
-                    i = close + 1;
-                    matched = true;
+/// Index of the `>` closing the `<` that starts `code`.
+fn find_matching_angle(code: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in code.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
                 }
-                break;
             }
+            _ => {}
         }
-        if !matched {
-            result.push(bytes[i] as char);
-            i += 1;
+    }
+    None
+}
+
+/// Split `code` at commas outside `<...>`.
+fn split_top_level(code: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in code.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&code[start..i]);
+                start = i + 1;
+            }
+            _ => {}
         }
     }
-    result
+    parts.push(&code[start..]);
+    parts
 }
 
-/// Rewrite `Vec<…>` → `List<…>` in type positions (bindings and return types).
-fn rewrite_vec_type_annotations(code: &str) -> String {
-    code.replace(": Vec<", ": List<")
-        .replace("-> Vec<", "-> List<")
+/// A token of generated Rust, as the rewrites of Elevate's output see it.
+/// Comments and literals are single tokens, so nothing inside them is ever
+/// rewritten.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RustToken<'a> {
+    Ident(&'a str),
+    /// A comment, or a string, char or byte literal.
+    Literal(&'a str),
+    /// Any other single character, whitespace included.
+    Punct(char),
 }
 
-/// Rewrite `HashMap<…>` → `Dict<…>` in type positions (bindings and return types).
-fn rewrite_hashmap_type_annotations(code: &str) -> String {
-    code.replace(": HashMap<", ": Dict<")
-        .replace("-> HashMap<", "-> Dict<")
+/// The tokens of a Rust source with their byte offsets, from `at` on.
+struct RustTokens<'a> {
+    code: &'a str,
+    at: usize,
 }
 
-/// Find the matching `]` for a `[` at position `open`, respecting nesting.
-fn find_matching_bracket(code: &str, open: usize) -> Option<usize> {
+fn rust_tokens(code: &str) -> RustTokens<'_> {
+    RustTokens { code, at: 0 }
+}
+
+impl<'a> Iterator for RustTokens<'a> {
+    type Item = (usize, RustToken<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.at;
+        let rest = self.code.get(start..).filter(|rest| !rest.is_empty())?;
+        if let Some(len) = literal_len(rest) {
+            self.at += len;
+            return Some((start, RustToken::Literal(&rest[..len])));
+        }
+        let c = rest.chars().next()?;
+        if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .unwrap_or(rest.len());
+            self.at += len;
+            return Some((start, RustToken::Ident(&rest[..len])));
+        }
+        self.at += c.len_utf8();
+        Some((start, RustToken::Punct(c)))
+    }
+}
+
+/// Length of the comment or literal `code` starts with, if it starts with
+/// one. A `'` starting a lifetime isn't one.
+fn literal_len(code: &str) -> Option<usize> {
     let bytes = code.as_bytes();
-    if bytes.get(open) != Some(&b'[') {
-        return None;
+    if code.starts_with("//") {
+        return Some(code.find('\n').unwrap_or(code.len()));
     }
-    let mut depth = 1;
-    let mut i = open + 1;
-    while i < bytes.len() && depth > 0 {
-        match bytes[i] {
-            b'[' => depth += 1,
-            b']' => {
+    if code.starts_with("/*") {
+        let (mut depth, mut i) = (0, 0);
+        while i < bytes.len() {
+            if code[i..].starts_with("/*") {
+                depth += 1;
+                i += 2;
+            } else if code[i..].starts_with("*/") {
                 depth -= 1;
+                i += 2;
                 if depth == 0 {
-                    return Some(i);
+                    break;
                 }
-            }
-            b'"' => {
-                // Skip string literals
+            } else {
                 i += 1;
-                while i < bytes.len() && bytes[i] != b'"' {
-                    if bytes[i] == b'\\' {
-                        i += 1; // skip escaped char
-                    }
-                    i += 1;
-                }
             }
-            _ => {}
         }
-        i += 1;
+        return Some(i.min(code.len()));
+    }
+    if let Some(len) = raw_string_len(code) {
+        return Some(len);
+    }
+    if code.starts_with('"') || code.starts_with("b\"") {
+        let mut i = code.find('"')? + 1;
+        while i < bytes.len() && bytes[i] != b'"' {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+        }
+        return Some((i + 1).min(code.len()));
+    }
+    if let Some(quoted) = code.strip_prefix('\'') {
+        // A char literal ('x', '\n', '\u{..}'), or else a lifetime
+        let close = if let Some(escaped) = quoted.strip_prefix('\\') {
+            escaped.find('\'').map(|at| at + 2)
+        } else {
+            quoted
+                .char_indices()
+                .nth(1)
+                .filter(|&(_, ch)| ch == '\'')
+                .map(|(at, _)| at + 1)
+        };
+        return close.map(|at| at + 1);
     }
     None
 }
 
-/// Find the matching `)` for a `(` at position `open`, respecting nesting.
-fn find_matching_paren(code: &str, open: usize) -> Option<usize> {
-    let bytes = code.as_bytes();
-    if bytes.get(open) != Some(&b'(') {
+/// Length of the raw string literal (`r"..."`, `br#"..."#`) `code` starts
+/// with, if it starts with one.
+fn raw_string_len(code: &str) -> Option<usize> {
+    let start = code.strip_prefix('b').unwrap_or(code).strip_prefix('r')?;
+    let hashes = start.len() - start.trim_start_matches('#').len();
+    let body = start[hashes..].strip_prefix('"')?;
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    let end = body
+        .find(&terminator)
+        .map_or(body.len(), |at| at + terminator.len());
+    Some(code.len() - body.len() + end)
+}
+
+/// Fix `HashMap::from(vec![...])` → `HashMap::from([...])`
+/// AND wrap collection constructors in List/Dict newtypes.
+///
+/// Elevate sees `Vec` and `HashMap` during type-checking, but Quiche programs
+/// should work with `List` and `Dict`.  We rewrite the Rust Elevate emits so
+/// Elevate can still resolve `.push()`, `.len()`, etc. against its Vec/HashMap
+/// knowledge, and the final Rust code uses quiche-lib newtypes. Elevate hands
+/// back only that Rust, so the rewrite walks its tokens: comments, literals
+/// and qualified paths (`std::collections::HashMap::new()`) are left alone.
+///
+/// - `vec![…]` → `List::from(vec![…])`, and `Vec::new()` → `List::new()`
+/// - `HashMap::new()`, `HashMap::from(…)`, `HashMap::from_iter(…)` →
+///   `Dict::…`, since going through a `HashMap` first would lose the order
+///   of a dict literal's entries; `Dict` keeps insertion order
+/// - `: Vec<…>` / `-> Vec<…>` → `List<…>`, and likewise `HashMap<…>` →
+///   `Dict<…>`, in bindings, fields and return types
+fn wrap_collections(rust_code: &str) -> String {
+    let mut result = String::with_capacity(rust_code.len() + 128);
+    let mut copied = 0;
+    let mut tokens = rust_tokens(rust_code);
+    while let Some((at, token)) = tokens.next() {
+        let RustToken::Ident(ident) = token else {
+            continue;
+        };
+        let after = &rust_code[at + ident.len()..];
+        let before = rust_code[..at].trim_end();
+        let bare = !before.ends_with("::");
+        let annotation =
+            bare && (before.ends_with(':') || before.ends_with("->")) && after.starts_with('<');
+        let constructor = ["::new()", "::from_iter(", "::from("]
+            .iter()
+            .any(|name| after.starts_with(name));
+        let (replacement, len) = match ident {
+            "vec" if after.starts_with("![") => {
+                match find_matching_bracket(rust_code, at + "vec!".len()) {
+                    Some(close) => (
+                        format!("List::from({})", &rust_code[at..=close]),
+                        close + 1 - at,
+                    ),
+                    None => continue,
+                }
+            }
+            "Vec" if annotation || (bare && after.starts_with("::new()")) => {
+                ("List".to_string(), ident.len())
+            }
+            "HashMap" if bare && after.starts_with("::from(vec![") => {
+                ("Dict::from([".to_string(), "HashMap::from(vec![".len())
+            }
+            "HashMap" if annotation || (bare && constructor) => ("Dict".to_string(), ident.len()),
+            _ => continue,
+        };
+        result.push_str(&rust_code[copied..at]);
+        result.push_str(&replacement);
+        copied = at + len;
+        tokens.at = copied;
+    }
+    result.push_str(&rust_code[copied..]);
+    result
+}
+
+/// Find the matching `]` for a `[` at position `open`, respecting nesting
+/// and skipping comments and literals.
+fn find_matching_bracket(code: &str, open: usize) -> Option<usize> {
+    if code.as_bytes().get(open) != Some(&b'[') {
         return None;
     }
-    let mut depth = 1;
-    let mut i = open + 1;
-    while i < bytes.len() && depth > 0 {
-        match bytes[i] {
-            b'(' => depth += 1,
-            b')' => {
+    let mut depth = 0usize;
+    for (at, token) in (RustTokens { code, at: open }) {
+        match token {
+            RustToken::Punct('[') => depth += 1,
+            RustToken::Punct(']') => {
                 depth -= 1;
                 if depth == 0 {
-                    return Some(i);
-                }
-            }
-            b'"' => {
-                // Skip string literals
-                i += 1;
-                while i < bytes.len() && bytes[i] != b'"' {
-                    if bytes[i] == b'\\' {
-                        i += 1; // skip escaped char
-                    }
-                    i += 1;
+                    return Some(at);
                 }
             }
             _ => {}
         }
-        i += 1;
     }
     None
 }
@@ -332,28 +980,30 @@ mod tests {
     use crate::parser::parse;
     use elevate::ast::*;
 
-    /// Number of Item::RustBlock items injected by the Quiche primitive type prelude.
-    const PRELUDE_COUNT: usize = 2;
-
-    /// Extract only user-defined items (skip the smart string prelude).
-    fn user_items(module: &Module) -> &[Item] {
-        &module.items[PRELUDE_COUNT..]
-    }
-
     // ─── Functions ───────────────────────────────────────────────────────────
 
     #[test]
     fn test_parse_simple_expression() {
         let module = parse("x + 1").unwrap();
-        assert_eq!(user_items(&module).len(), 0); // top-level expr is skipped as item
+        // A top-level expression runs in the implicit main()
+        match module.items.as_slice() {
+            [Item::Function(main)] => {
+                assert_eq!(main.name, "main");
+                assert!(matches!(
+                    &main.body.statements[..],
+                    [Stmt::Expr(Expr::Binary { .. })]
+                ));
+            }
+            other => panic!("Expected implicit main, got {:?}", other),
+        }
     }
 
     #[test]
     fn test_parse_function_def() {
         let source = "def foo(x: int) -> int:\n    return x + 1\n";
         let module = parse(source).unwrap();
-        assert_eq!(user_items(&module).len(), 1);
-        match &user_items(&module)[0] {
+        assert_eq!(module.items.len(), 1);
+        match &module.items[0] {
             Item::Function(f) => {
                 assert_eq!(f.name, "foo");
                 assert_eq!(f.params.len(), 1);
@@ -364,13 +1014,318 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_performance_decorators() {
+        let source = "@memoize\n@inline\ndef fib(n: u64) -> u64:\n    return n\n";
+        let module = parse(source).unwrap();
+        let items = &module.items;
+        assert!(
+            matches!(&items[0], Item::RustBlock(code) if code == "// quiche:attribute fib #[inline(always)]")
+        );
+        match (&items[1], &items[2]) {
+            (Item::Function(wrapper), Item::Function(inner)) => {
+                assert_eq!(wrapper.name, "fib");
+                assert_eq!(inner.name, "__memoized_fib");
+                assert!(matches!(
+                    &wrapper.body.statements[0],
+                    Stmt::TailExpr(Expr::Call { callee, args })
+                        if matches!(&**callee, Expr::Path(p) if p == &["Memo", "cached"])
+                            && matches!(&args[1], Expr::Path(p) if p == &["n"])
+                ));
+            }
+            other => panic!("Expected wrapper and body functions, got {:?}", other),
+        }
+
+        let generated =
+            "// quiche:attribute fib #[inline(always)]\npub fn fib(n: u64) -> u64 {\n    n\n}\n";
+        assert_eq!(
+            super::apply_item_attributes(generated),
+            "// quiche:attribute fib #[inline(always)]\n#[inline(always)]\npub fn fib(n: u64) -> u64 {\n    n\n}\n"
+        );
+
+        let err = parse("@memoize\ndef f(n: u64):\n    print(n)\n").unwrap_err();
+        assert!(err.message.contains("needs a return type"), "{err}");
+    }
+
+    #[test]
+    fn test_compile_time_builtins() {
+        let source = "def main():\n    version = env(\"CARGO_PKG_VERSION\")\n    profile = env(\"PROFILE\", \"dev\")\n    schema = include_str(\"data/schema.sql\")\n";
+        let module = parse(source).unwrap();
+        let Item::Function(main) = &module.items[0] else {
+            panic!("expected main");
+        };
+        let values: Vec<&Expr> = main
+            .body
+            .statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Assign { value, .. } => Some(value),
+                _ => None,
+            })
+            .collect();
+        let macro_of = |expr: &Expr| match expr {
+            Expr::Call { callee, args } if matches!(&**callee, Expr::Path(p) if p == &["str"]) => {
+                args[0].clone()
+            }
+            other => panic!("expected str(...), got {other:?}"),
+        };
+        assert!(matches!(
+            macro_of(values[0]),
+            Expr::MacroCall { path, args }
+                if path == ["env"] && matches!(&args[..], [Expr::String(v)] if v == "CARGO_PKG_VERSION")
+        ));
+        assert!(matches!(
+            macro_of(values[1]),
+            Expr::Call { callee, args }
+                if matches!(&*callee, Expr::Field { field, .. } if field == "unwrap_or")
+                    && matches!(&args[..], [Expr::String(d)] if d == "dev")
+        ));
+        assert!(matches!(
+            macro_of(values[2]),
+            Expr::MacroCall { path, args }
+                if path == ["include_str"] && matches!(&args[..], [Expr::String(f)] if f == "data/schema.sql")
+        ));
+
+        let resolved = super::resolve_include_paths(
+            "let a = include_str!(\"data/schema.sql\"); let b = include_str!(\"/etc/hosts\");",
+            "/srv/app/main.q",
+        );
+        assert_eq!(
+            resolved,
+            "let a = include_str!(\"/srv/app/data/schema.sql\"); let b = include_str!(\"/etc/hosts\");"
+        );
+
+        let err = parse("def main():\n    name = \"HOME\"\n    home = env(name)\n").unwrap_err();
+        assert!(err.message.contains("string literals"), "{err}");
+    }
+
+    #[test]
+    fn test_cfg_decorator() {
+        let source = "@cfg(target_os=\"windows\")\ndef sep() -> Str:\n    return \"\\\\\"\n\n@cfg(not(any(windows, target_family=\"wasm\")))\ndef sep() -> Str:\n    return \"/\"\n";
+        let module = parse(source).unwrap();
+        let items = &module.items;
+        assert!(matches!(
+            &items[0],
+            Item::RustBlock(code) if code == "// quiche:attribute sep #[cfg(target_os = \"windows\")]"
+        ));
+        assert!(matches!(
+            &items[2],
+            Item::RustBlock(code)
+                if code == "// quiche:attribute sep #[cfg(not(any(windows, target_family = \"wasm\")))]"
+        ));
+
+        let generated = "// quiche:attribute sep #[cfg(windows)]\npub fn sep() {}\n// quiche:attribute sep #[cfg(unix)]\npub fn sep() {}\n";
+        assert_eq!(
+            super::apply_item_attributes(generated),
+            "// quiche:attribute sep #[cfg(windows)]\n#[cfg(windows)]\npub fn sep() {}\n// quiche:attribute sep #[cfg(unix)]\n#[cfg(unix)]\npub fn sep() {}\n"
+        );
+
+        let err = parse("@cfg(windows)\ntype T:\n    x: i64\n").unwrap_err();
+        assert!(err.message.contains("applies to functions"), "{err}");
+    }
+
+    #[test]
+    fn test_dict_literals_keep_entry_order() {
+        let generated = "let m: HashMap<Str, i64> = HashMap::from(vec![(Str::from(\"b\"), 1), (Str::from(\"a\"), 2)]);\nlet e: HashMap<i64, i64> = HashMap::new();\nlet s = std::collections::HashMap::<i64, i64>::new();\n";
+        assert_eq!(
+            super::wrap_collections(generated),
+            "let m: Dict<Str, i64> = Dict::from([(Str::from(\"b\"), 1), (Str::from(\"a\"), 2)]);\nlet e: Dict<i64, i64> = Dict::new();\nlet s = std::collections::HashMap::<i64, i64>::new();\n"
+        );
+    }
+
+    #[test]
+    fn test_collection_rewrites_skip_comments_and_literals() {
+        let generated = "// vec![1] and Vec::new() stay in comments\nfn f(xs: Vec<i64>) -> HashMap<Str, i64> {\n    let s = \"vec![x]: Vec<i64> HashMap::new()\";\n    let c = '[';\n    let ys = vec![\"]\", r\"vec![]\"];\n    let zs = MyVec::new();\n    HashMap::new()\n}\n";
+        assert_eq!(
+            super::wrap_collections(generated),
+            "// vec![1] and Vec::new() stay in comments\nfn f(xs: List<i64>) -> Dict<Str, i64> {\n    let s = \"vec![x]: Vec<i64> HashMap::new()\";\n    let c = '[';\n    let ys = List::from(vec![\"]\", r\"vec![]\"]);\n    let zs = MyVec::new();\n    Dict::new()\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_collection_imports_recorded_while_parsing() {
+        let source = "# a HashSet of names\n\
+                      def f(q: VecDeque[i64]) -> BTreeSet[i64]:\n    \
+                      m: HashMap[i64, i64] = HashMap.new()\n    \
+                      print(\"BinaryHeap\")\n    \
+                      t: std.collections.BTreeMap[i64, i64] = std.collections.BTreeMap.new()\n    \
+                      return BTreeSet.new()\n";
+        let module = parse(source).unwrap();
+        assert!(matches!(
+            &module.items[0],
+            Item::RustBlock(code) if code == "use std::collections::{BTreeSet, HashMap, VecDeque};"
+        ));
+
+        // Imported or declared by the module itself
+        let source = "from std.collections import HashMap\n\n\
+                      type HashSet:\n    items: List[i64]\n\n\
+                      def f(s: HashSet) -> HashMap[i64, i64]:\n    return HashMap.new()\n";
+        let module = parse(source).unwrap();
+        assert!(!module.items.iter().any(
+            |item| matches!(item, Item::RustBlock(code) if code.starts_with("use std::collections"))
+        ));
+    }
+
+    #[test]
+    fn test_rust_attr_decorator() {
+        let source = "@rust_attr('#[serde(rename_all = \"camelCase\")]')\n@derive(Serialize)\ntype User:\n    user_name: Str\n\n@rust_attr(\"must_use\", allow=\"dead_code\")\ndef check() -> bool:\n    return True\n";
+        let module = parse(source).unwrap();
+        let markers: Vec<&str> = module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::RustBlock(code) if code.starts_with("// quiche:attribute") => {
+                    Some(code.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            markers,
+            [
+                "// quiche:attribute User #[serde(rename_all = \"camelCase\")]",
+                "// quiche:attribute check #[must_use]",
+                "// quiche:attribute check #[allow(dead_code)]",
+            ]
+        );
+
+        let generated = "// quiche:attribute User #[serde(rename_all = \"camelCase\")]\n#[derive(Debug, Clone, serde::Serialize)]\npub struct User {\n    pub user_name: Str,\n}\n";
+        assert_eq!(
+            super::apply_item_attributes(generated),
+            "// quiche:attribute User #[serde(rename_all = \"camelCase\")]\n#[derive(Debug, Clone, serde::Serialize)]\n#[serde(rename_all = \"camelCase\")]\npub struct User {\n    pub user_name: Str,\n}\n"
+        );
+
+        let err = parse("@rust_attr(1)\ndef f():\n    pass\n").unwrap_err();
+        assert!(err.message.contains("@rust_attr takes attributes"), "{err}");
+    }
+
+    #[test]
+    fn test_unsafe_block() {
+        let source = "def read(p: i64) -> i64:\n    unsafe:\n        x = rust(\"*(p as *const i64)\")\n    return x\n";
+        let module = parse(source).unwrap();
+        let Item::Function(read) = &module.items[0] else {
+            panic!("expected a function");
+        };
+        assert!(matches!(
+            &read.body.statements[0],
+            Stmt::If { condition: Expr::MacroCall { path, args }, else_block: None, .. }
+                if path == &["__unsafe__"] && args.is_empty()
+        ));
+        assert_eq!(
+            super::apply_unsafe_blocks("    if __unsafe__!() {\n        f();\n    }\n"),
+            "    unsafe {\n        f();\n    }\n"
+        );
+
+        let err = super::reject_unsafe(source, "mem.q").unwrap_err();
+        assert!(err.contains("--allow-unsafe"), "{err}");
+        assert!(err.contains("mem.q:2:5: unsafe block"), "{err}");
+        assert!(super::reject_unsafe("def f():\n    unsafe = 1\n", "f.q").is_ok());
+    }
+
+    #[test]
+    fn test_with_block() {
+        let source = "def copy(src: Str, dst: Str):\n    with open(src) as a, File.create(dst) as b:\n        b.write(a.read())\n    with lock():\n        pass\n";
+        let module = parse(source).unwrap();
+        let Item::Function(copy) = &module.items[0] else {
+            panic!("expected a function");
+        };
+        let Stmt::If {
+            condition: Expr::MacroCall { path, .. },
+            then_block,
+            else_block: None,
+        } = &copy.body.statements[0]
+        else {
+            panic!("expected a with block, got {:?}", copy.body.statements[0]);
+        };
+        assert_eq!(path, &["__with__"]);
+        let entered: Vec<&str> = then_block
+            .statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Assign {
+                    target: AssignTarget::Path(name),
+                    value: Expr::Call { callee, .. },
+                    ..
+                } if matches!(callee.as_ref(), Expr::Path(p) if p == &["With", "enter"]) => {
+                    Some(name.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(entered, ["a", "b"]);
+        assert_eq!(then_block.statements.len(), 3);
+        assert!(matches!(
+            &copy.body.statements[1],
+            Stmt::If { then_block, .. } if matches!(
+                &then_block.statements[0],
+                Stmt::Assign { target: AssignTarget::Path(name), .. } if name == "__with_1"
+            )
+        ));
+        assert_eq!(
+            super::apply_with_blocks("    if __with__!() {\n        f();\n    }\n"),
+            "    {\n        f();\n    }\n"
+        );
+    }
+
+    #[test]
+    fn test_top_level_statements_become_main() {
+        let source = "def greet(name: Str):\n    print(name)\n\nx = 2\ngreet(\"hi\")\nprint(x)\n";
+        let module = parse(source).unwrap();
+        match &module.items[1] {
+            Item::Function(main) => {
+                assert_eq!(main.name, "main");
+                assert_eq!(main.body.statements.len(), 3);
+                assert!(matches!(&main.body.statements[0], Stmt::Assign { .. }));
+            }
+            other => panic!("Expected implicit main, got {:?}", other),
+        }
+
+        let guarded = "def main():\n    print(1)\n\nif __name__ == \"__main__\":\n    main()\n";
+        assert_eq!(parse(guarded).unwrap().items.len(), 1);
+
+        let err = parse("def main():\n    print(1)\n\nprint(2)\n").unwrap_err();
+        assert!(err.message.contains("already defines `def main`"), "{err}");
+    }
+
+    #[test]
+    fn test_top_level_constants_beside_main() {
+        let source = "GREETING = \"hello\"\nMAX_SIZE: i32 = 100\nlimit: Const[i64] = 5\n\ndef main():\n    print(GREETING)\n";
+        let module = parse(source).unwrap();
+        let constants: Vec<_> = module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Const(def) => Some(def),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(constants.len(), 3);
+        assert!(constants.iter().all(|def| def.is_const));
+        assert_eq!(constants[0].name, "GREETING");
+        assert_eq!(constants[0].ty.as_ref().unwrap().path, vec!["Ref"]);
+        assert!(matches!(&constants[0].value, Expr::String(s) if s == "hello"));
+        assert_eq!(constants[1].ty.as_ref().unwrap().path, vec!["i32"]);
+        assert_eq!(constants[2].ty.as_ref().unwrap().path, vec!["i64"]);
+
+        // Other top-level statements still run in the implicit main()
+        let module = parse("TOTAL = 3\nnames = [\"a\"]\nprint(TOTAL)\n").unwrap();
+        assert!(matches!(&module.items[0], Item::Const(def) if def.name == "TOTAL"));
+        match &module.items[1] {
+            Item::Function(main) => assert_eq!(main.body.statements.len(), 2),
+            other => panic!("Expected implicit main, got {:?}", other),
+        }
+
+        let err = parse("limit: Const[i64] = compute()\n").unwrap_err();
+        assert!(err.message.contains("must be a number"), "{err}");
+    }
+
     // ─── Structs ─────────────────────────────────────────────────────────────
 
     #[test]
     fn test_parse_struct() {
         let source = "type Point:\n    x: int\n    y: int\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Struct(s) => {
                 assert_eq!(s.name, "Point");
                 assert_eq!(s.fields.len(), 2);
@@ -385,7 +1340,7 @@ mod tests {
     fn test_struct_with_type_params() {
         let source = "type Point[T]:\n    x: T\n    y: int\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Struct(s) => {
                 assert_eq!(s.name, "Point");
                 assert_eq!(s.fields.len(), 2);
@@ -396,13 +1351,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_struct_with_bounded_type_params() {
+        let source = "type Pair[K: Hashable, V: Display + Clone]:\n    key: K\n    value: V\n\n    def to_string(self) -> Str:\n        return \"pair\"\n";
+        let module = parse(source).unwrap();
+        match &module.items[0] {
+            Item::Struct(s) => {
+                let bounds: Vec<Vec<String>> = s
+                    .type_params
+                    .iter()
+                    .map(|p| p.bounds.iter().map(|b| b.path.join("::")).collect())
+                    .collect();
+                assert_eq!(
+                    bounds,
+                    vec![vec!["std::hash::Hash", "Eq"], vec!["Display", "Clone"]]
+                );
+            }
+            other => panic!("Expected Struct, got {:?}", other),
+        }
+
+        let rust = "pub struct Pair<K: std::hash::Hash + Eq, V: Into<Str>> {\n}\n\
+                    impl<K: std::hash::Hash + Eq, V: Into<Str>> Pair<K, V> {\n    pub fn to_string(self) -> Str {\n    }\n}\n";
+        let with_display = super::inject_display_impls(rust, &module);
+        assert!(
+            with_display.contains(
+                "impl<K: std::hash::Hash + Eq, V: Into<Str>> std::fmt::Display for Pair<K, V> where Self: Clone {"
+            ),
+            "{with_display}"
+        );
+        // Only structs of the module whose impl has a `to_string`
+        let mentioned = "// pub struct Pair<K, V> {}\nfn f() -> &'static str { \"struct Pair\" }\n";
+        assert_eq!(super::inject_display_impls(mentioned, &module), mentioned);
+        let plain = parse("type Pair[K, V]:\n    key: K\n    value: V\n").unwrap();
+        assert_eq!(super::inject_display_impls(rust, &plain), rust);
+    }
+
     // ─── Control Flow ────────────────────────────────────────────────────────
 
     #[test]
     fn test_parse_if() {
         let source = "def test():\n    if x:\n        y = 1\n    else:\n        y = 2\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => match &f.body.statements[0] {
                 Stmt::If {
                     then_block,
@@ -422,7 +1412,7 @@ mod tests {
     fn test_parse_if_elif_else() {
         let source = "def test():\n    if a > 0:\n        x = 1\n    elif b > 0:\n        x = 2\n    elif c > 0:\n        x = 3\n    else:\n        x = 4\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => {
                 match &f.body.statements[0] {
                     Stmt::If {
@@ -463,7 +1453,7 @@ mod tests {
     fn test_parse_for() {
         let source = "def test():\n    for i in range(10):\n        print(i)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => match &f.body.statements[0] {
                 Stmt::For { binding, .. } => match binding {
                     DestructurePattern::Name(n) => assert_eq!(n, "i"),
@@ -480,7 +1470,7 @@ mod tests {
     #[test]
     fn test_parse_from_import() {
         let module = parse("from os import path").unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::RustUse(u) => {
                 // UseTree::Path { segment: "os", next: UseTree::Name("path") }
                 match &u.tree {
@@ -501,7 +1491,7 @@ mod tests {
     fn test_parse_match() {
         let source = "def test():\n    match result:\n        case Ok(v):\n            return v\n        case Err(e):\n            print(e)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => match &f.body.statements[0] {
                 Stmt::Expr(Expr::Match { arms, .. }) => {
                     assert_eq!(arms.len(), 2);
@@ -512,13 +1502,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_named_variant_construction_and_match() {
+        let source = "type Shape = Dot | Rect(width: f64, height: f64)\n\ndef area(s: Shape) -> f64:\n    match s:\n        case Shape.Rect(width=w, height=h):\n            return w * h\n        case Shape.Dot:\n            return 0.0\n\ndef main():\n    r = Shape.Rect(height=4.0, width=3.0)\n";
+        let module = parse(source).unwrap();
+        let items = &module.items;
+        let Item::Function(area) = &items[1] else {
+            panic!("Expected area");
+        };
+        match &area.body.statements[0] {
+            Stmt::Expr(Expr::Match { arms, .. }) => {
+                assert_eq!(arms.len(), 3);
+                assert!(matches!(&arms[0].pattern, Pattern::Binding(name) if name == "__variant"));
+                assert!(matches!(
+                    &arms[0].guard,
+                    Some(Expr::Call { callee, .. }) if matches!(&**callee, Expr::Closure { body, .. }
+                        if matches!(&body.statements[0], Stmt::RustBlock(code)
+                            if code == "matches!(__variant, Shape::Rect { .. })"))
+                ));
+                assert!(matches!(&arms[2].pattern, Pattern::Wildcard));
+            }
+            other => panic!("Expected Match expr, got {:?}", other),
+        }
+        let Item::Function(main) = &items[2] else {
+            panic!("Expected main");
+        };
+        match &main.body.statements[0] {
+            Stmt::Assign {
+                value: Expr::StructLiteral { path, fields },
+                ..
+            } => {
+                assert_eq!(path, &["Shape", "Rect"]);
+                let names: Vec<&str> = fields.iter().map(|f| f.name.as_str()).collect();
+                assert_eq!(names, ["height", "width"]);
+            }
+            other => panic!("Expected struct literal, got {:?}", other),
+        }
+    }
+
     // ─── Multiline Calls ─────────────────────────────────────────────────────
 
     #[test]
     fn test_parse_multiline_function_call() {
         let source = "def test():\n    result = foo(\n        1,\n        2,\n        3\n    )\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => {
                 // Should parse without error — multiline call inside brackets
                 assert!(!f.body.statements.is_empty());
@@ -533,7 +1561,7 @@ mod tests {
     fn test_parse_multiline_docstring_then_if() {
         let source = "def test(self):\n    \"\"\"Multi\n    line\n    doc\"\"\"\n    if x > 0:\n        pass\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => {
                 // Should have docstring + if in body
                 assert!(
@@ -552,7 +1580,7 @@ mod tests {
     fn test_parse_boolean_operators() {
         let source = "def test():\n    return a and b or not c\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => match &f.body.statements[0] {
                 Stmt::Return(Some(Expr::Binary {
                     op: BinaryOp::Or, ..
@@ -569,7 +1597,7 @@ mod tests {
     fn test_parse_fstring() {
         let source = "def test():\n    name = \"World\"\n    greeting = f\"Hello, {name}!\"\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Function(f) => {
                 // Should parse without errors — f-string becomes MacroCall
                 assert!(f.body.statements.len() >= 2);
@@ -578,6 +1606,227 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fstring_placeholders_go_through_quiche_show() {
+        let source = "def test(p: Point):\n    s = f\"at {p}\"\n";
+        let module = parse(source).unwrap();
+        let Item::Function(f) = &module.items[0] else {
+            panic!("Expected Function");
+        };
+        let Stmt::Assign { value, .. } = &f.body.statements[0] else {
+            panic!("Expected Assign, got {:?}", f.body.statements[0]);
+        };
+        let Expr::Call { args, .. } = value else {
+            panic!("Expected str(format!(...)), got {:?}", value);
+        };
+        match &args[0] {
+            Expr::MacroCall { path, args } => {
+                assert_eq!(path, &["format"]);
+                assert!(matches!(
+                    &args[1],
+                    Expr::MacroCall { path, args }
+                        if path == &["quiche_show"] && matches!(args.as_slice(), [Expr::Path(p)] if p == &["p"])
+                ));
+            }
+            other => panic!("Expected format!, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_collection_literal_equality_goes_through_quiche_eq() {
+        let source = "def test(xs: List[i64]):\n    assert xs == [1, 2, 3]\n    assert {\"a\": 1} != xs\n    assert xs.len() == 3\n";
+        let module = parse(source).unwrap();
+        let Item::Function(f) = &module.items[0] else {
+            panic!("Expected Function");
+        };
+        let conditions: Vec<String> = f
+            .body
+            .statements
+            .iter()
+            .map(|stmt| format!("{stmt:?}"))
+            .collect();
+        assert!(
+            conditions[0]
+                .contains("MacroCall { path: [\"quiche_eq\"], args: [Path([\"xs\"]), Array("),
+            "{}",
+            conditions[0]
+        );
+        // The literal moves to the right, and `!=` negates the comparison
+        assert!(
+            conditions[1].contains("Unary { op: Not, expr: MacroCall { path: [\"quiche_eq\"], args: [Path([\"xs\"]), Call"),
+            "{}",
+            conditions[1]
+        );
+        assert!(!conditions[2].contains("quiche_eq"), "{}", conditions[2]);
+    }
+
+    #[test]
+    fn test_question_mark_propagates() {
+        let source = "def size(p: str) -> Result[i64, str]:\n    n = load(p)?.len()\n    save(p)?\n    return Ok(n)\n";
+        let module = parse(source).unwrap();
+        let Item::Function(f) = &module.items[0] else {
+            panic!("Expected Function");
+        };
+        let Stmt::Assign { value, .. } = &f.body.statements[0] else {
+            panic!("Expected Assign, got {:?}", f.body.statements[0]);
+        };
+        let rendered = format!("{value:?}");
+        assert!(
+            rendered.contains("Field { base: MacroCall { path: [\"quiche_try\"], args: [Call { callee: Path([\"load\"])"),
+            "{rendered}"
+        );
+        assert!(matches!(
+            &f.body.statements[1],
+            Stmt::Expr(Expr::MacroCall { path, .. }) if path == &["quiche_try"]
+        ));
+    }
+
+    #[test]
+    fn test_fstring_nested_expressions() {
+        let source = "def test(d: Dict[str, str], xs: List[i64]):\n    s = f\"{d[\"k\"]} {'}' if xs.is_empty() else '{'} {xs.get(0).unwrap_or(1)} {xs[\n        0]} {1, 2}\"\n    return s\n";
+        let module = parse(source).unwrap();
+        let Item::Function(f) = &module.items[0] else {
+            panic!("Expected Function");
+        };
+        let Stmt::Assign { value, .. } = &f.body.statements[0] else {
+            panic!("Expected Assign, got {:?}", f.body.statements[0]);
+        };
+        let Expr::Call { args, .. } = value else {
+            panic!("Expected str(format!(...)), got {:?}", value);
+        };
+        let Expr::MacroCall { args, .. } = &args[0] else {
+            panic!("Expected format!, got {:?}", args[0]);
+        };
+        assert!(matches!(&args[0], Expr::String(s) if s == "{} {} {} {} {}"));
+        let shown: Vec<&Expr> = args[1..]
+            .iter()
+            .map(|arg| match arg {
+                Expr::MacroCall { args, .. } => &args[0],
+                other => panic!("Expected quiche_show!, got {other:?}"),
+            })
+            .collect();
+        assert!(matches!(shown[0], Expr::Index { .. }), "{:?}", shown[0]);
+        assert!(matches!(shown[2], Expr::Call { .. }), "{:?}", shown[2]);
+        assert!(matches!(shown[3], Expr::Index { .. }), "{:?}", shown[3]);
+        assert!(matches!(shown[4], Expr::Tuple(items) if items.len() == 2));
+        // The statement after the f-string still parses at its own indent
+        assert!(matches!(&f.body.statements[1], Stmt::Return(_)));
+
+        let err = parse("def test():\n    s = f\"{1 +}\"\n").unwrap_err();
+        assert!(
+            err.message.contains("in f-string expression `1 +`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_fstring_debug_form_and_pprint() {
+        let source = "def test(xs: List[i64]):\n    s = f\"{xs=} {len(xs) = } {xs == xs}\"\n    pprint(xs)\n";
+        let module = parse(source).unwrap();
+        let Item::Function(f) = &module.items[0] else {
+            panic!("Expected Function");
+        };
+        let Stmt::Assign { value, .. } = &f.body.statements[0] else {
+            panic!("Expected Assign, got {:?}", f.body.statements[0]);
+        };
+        let Expr::Call { args, .. } = value else {
+            panic!("Expected str(format!(...)), got {:?}", value);
+        };
+        let Expr::MacroCall { args, .. } = &args[0] else {
+            panic!("Expected format!, got {:?}", args[0]);
+        };
+        assert!(matches!(&args[0], Expr::String(s) if s == "xs={} len(xs) = {} {}"));
+        let macros: Vec<&str> = args[1..]
+            .iter()
+            .map(|arg| match arg {
+                Expr::MacroCall { path, .. } => path[0].as_str(),
+                _ => "",
+            })
+            .collect();
+        assert_eq!(macros, ["quiche_repr", "quiche_repr", "quiche_show"]);
+        assert!(matches!(
+            &f.body.statements[1],
+            Stmt::Expr(Expr::MacroCall { path, .. }) if path == &["quiche_pprint"]
+        ));
+        assert!(parse("def test():\n    pprint(1, 2)\n").is_err());
+    }
+
+    #[test]
+    fn test_format_specs_and_str_format() {
+        let format_args = |source: &str| {
+            let module = parse(source).unwrap();
+            let Item::Function(f) = &module.items[0] else {
+                panic!("Expected Function");
+            };
+            let Stmt::Assign { value, .. } = &f.body.statements[0] else {
+                panic!("Expected Assign, got {:?}", f.body.statements[0]);
+            };
+            let Expr::Call { args, .. } = value else {
+                panic!("Expected str(format!(...)), got {:?}", value);
+            };
+            let Expr::MacroCall { args, .. } = &args[0] else {
+                panic!("Expected format!, got {:?}", args[0]);
+            };
+            let Expr::String(template) = &args[0] else {
+                panic!("Expected a template, got {:?}", args[0]);
+            };
+            let macros: Vec<String> = args[1..]
+                .iter()
+                .map(|arg| match arg {
+                    Expr::MacroCall { path, .. } => path[0].clone(),
+                    other => panic!("Expected a macro, got {other:?}"),
+                })
+                .collect();
+            (template.clone(), macros)
+        };
+        let (template, macros) = format_args(
+            "def test(p: f64, n: i64):\n    s = f\"{p:>8.2f} {n:#x} {p!r:^9} {p=:.1f}\"\n",
+        );
+        assert_eq!(template, "{:>8.2} {:#x} {:^9} p={:.1}");
+        assert_eq!(
+            macros,
+            ["quiche_show", "quiche_show", "quiche_repr", "quiche_show"]
+        );
+
+        let (template, macros) = format_args(
+            "def test(a: i64, b: Str):\n    s = \"{0} {name!r:>6} {0:05d} {{x}}\".format(a, name=b)\n",
+        );
+        assert_eq!(template, "{0} {1:>6} {0:05} {{x}}");
+        assert_eq!(macros, ["quiche_show", "quiche_repr"]);
+
+        // Errors point into the string, at the offending placeholder
+        for (body, line, column, message) in [
+            ("s = f\"total: {t:,.2f}\"", 2, 18, "digit grouping (',')"),
+            ("s = f\"{t!x}\"", 2, 11, "invalid conversion '!x'"),
+            ("s = f\"a } b\"", 2, 13, "single '}' in f-string"),
+            (
+                "s = \"{} {}\".format(t)",
+                2,
+                13,
+                "needs positional argument 1",
+            ),
+            ("s = \"{who}\".format(t)", 2, 10, "no argument named 'who'"),
+            ("s = \"{} {0}\".format(t)", 2, 13, "cannot mix automatic"),
+            (
+                "s = \"{}\".format(t, t)",
+                2,
+                10,
+                "positional argument 1 is not used",
+            ),
+            (
+                "s = \"{:.2d}\".format(t)",
+                2,
+                10,
+                "precision is not allowed",
+            ),
+        ] {
+            let source = format!("def test(t: i64):\n    {body}\n");
+            let err = parse(&source).unwrap_err();
+            assert!(err.message.contains(message), "{body}: {err}");
+            assert_eq!((err.line, err.column), (line, column), "{body}: {err}");
+        }
+    }
+
     // ─── End-to-End Compile ──────────────────────────────────────────────────
 
     #[test]
@@ -596,6 +1845,22 @@ mod tests {
         assert!(rust.contains("x: i32"));
     }
 
+    #[test]
+    fn test_aliased_import_finds_its_interface() {
+        let dir = crate::script::unique_temp_path("quiche-interfaces", "");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("rand.qi"),
+            "@extern(crate=\"rand\", version=\"0.8\")\ndef random() -> f64:\n    pass\n",
+        )
+        .unwrap();
+        let main = dir.join("main.q").display().to_string();
+        let source = "import rand as r\n\ndef main():\n    x = random()\n";
+        let crates = crate::extern_crates(source, &main).unwrap();
+        assert_eq!(crates, vec![("rand".to_string(), Some("0.8".to_string()))]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     // ─── Struct Instantiation ────────────────────────────────────────────────
 
     fn compile_ok(source: &str) -> String {
@@ -715,6 +1980,43 @@ mod tests {
         );
     }
 
+    // --- Struct update ---
+
+    #[test]
+    fn test_struct_update_spread() {
+        let source = "type Config:\n    host: Str\n    retries: i64\n\ndef main():\n    old = Config(host=\"a\", retries=1)\n    new = Config(**old, retries=5)\n";
+        let module = parse(source).unwrap();
+        let Item::Function(main) = &module.items[1] else {
+            panic!("Expected main");
+        };
+        match &main.body.statements[1] {
+            Stmt::Assign {
+                value: Expr::Call { callee, args },
+                ..
+            } if args.is_empty() => match &**callee {
+                Expr::Closure { body, .. } => {
+                    assert!(matches!(
+                        &body.statements[0],
+                        Stmt::Assign { value: Expr::Path(p), .. } if p == &["old"]
+                    ));
+                    assert!(matches!(
+                        &body.statements[1],
+                        Stmt::Assign { target: AssignTarget::Field { field, .. }, value: Expr::Int(5), .. }
+                            if field == "retries"
+                    ));
+                    assert!(
+                        matches!(&body.statements[2], Stmt::TailExpr(Expr::Path(p)) if p == &["__spread"])
+                    );
+                }
+                other => panic!("Expected closure, got {:?}", other),
+            },
+            other => panic!("Expected struct update, got {:?}", other),
+        }
+
+        let err = parse("type Config:\n    retries: i64\n\ndef main():\n    c = Config(retries=1)\n    d = Config(**c, retry=2)\n").unwrap_err();
+        assert!(err.message.contains("has no field 'retry'"), "{err}");
+    }
+
     // --- Top-level function kwargs ---
 
     #[test]
@@ -741,6 +2043,109 @@ def main():\n    x: i32 = sum3(c=30, a=10, b=20)\n";
         );
     }
 
+    #[test]
+    fn test_default_and_keyword_only_params() {
+        let source = "\
+def greet(name: str, punct: str = \"!\", *, times: i64 = 1) -> str:\n    return name\n\n\
+type Point:\n    x: i64\n    y: i64\n\n    def origin(scale: i64 = 0) -> Point:\n        return Point(scale, scale)\n\n\
+def main():\n    greet(\"Ada\")\n    greet(\"Ada\", \"?\", times=3)\n    greet(times=2, name=\"Ada\")\n    Point.origin()\n";
+        let module = parse(source).unwrap();
+        let Some(Item::Function(main)) = module.items.last() else {
+            panic!("Expected main");
+        };
+        let args = |i: usize| match &main.body.statements[i] {
+            Stmt::Expr(Expr::Call { args, .. }) => args.clone(),
+            other => panic!("Expected call, got {:?}", other),
+        };
+        let is_str = |expr: &Expr, text: &str| {
+            crate::parser::string_literal(expr).is_some_and(|s| s == text)
+        };
+        let first = args(0);
+        assert_eq!(first.len(), 3);
+        assert!(is_str(&first[1], "!") && matches!(first[2], Expr::Int(1)));
+        let second = args(1);
+        assert!(is_str(&second[1], "?") && matches!(second[2], Expr::Int(3)));
+        let third = args(2);
+        assert!(is_str(&third[0], "Ada") && is_str(&third[1], "!"));
+        assert!(matches!(third[2], Expr::Int(2)));
+        assert!(matches!(args(3).as_slice(), [Expr::Int(0)]));
+
+        let errors = [
+            ("greet(\"Ada\", \"?\", 3)", "takes 2 positional argument(s)"),
+            ("greet(punct=\"?\")", "missing argument 'name'"),
+            ("greet(\"Ada\", nme=\"x\")", "has no parameter named 'nme'"),
+            (
+                "greet(\"Ada\", name=\"x\")",
+                "more than one value for 'name'",
+            ),
+        ];
+        let def = "def greet(name: str, punct: str = \"!\", *, times: i64 = 1) -> str:\n    return name\n\n";
+        for (call, expected) in errors {
+            let err = parse(&format!("{def}def main():\n    {call}\n")).unwrap_err();
+            assert!(err.message.contains(expected), "{call}: {err}");
+        }
+        let err = parse("def f(a: i64 = 1, b: i64):\n    pass\n").unwrap_err();
+        assert!(err.message.contains("'b' without a default"), "{err}");
+        let err = parse("def f(a: i64, *):\n    pass\n").unwrap_err();
+        assert!(err.message.contains("must follow a bare '*'"), "{err}");
+        let err = parse("def main():\n    g = fn(x: i64 = 1): x\n").unwrap_err();
+        assert!(err.message.contains("only supported on a `def`"), "{err}");
+    }
+
+    #[test]
+    fn test_variadic_params() {
+        let source = "\
+def log(level: i64, *parts: str, sep: str = \" \", **tags: i64):\n    pass\n\n\
+def main():\n    log(1)\n    log(2, \"a\", \"b\", sep=\",\", line=7)\n";
+        let module = parse(source).unwrap();
+        let Item::Function(log) = &module.items[0] else {
+            panic!("Expected log");
+        };
+        let types: Vec<String> = log
+            .params
+            .iter()
+            .map(|p| crate::parser::quiche_type(&p.ty))
+            .collect();
+        assert_eq!(types, ["i64", "List[Str]", "Str", "Dict[Str, i64]"]);
+        let Some(Item::Function(main)) = module.items.last() else {
+            panic!("Expected main");
+        };
+        let args = |i: usize| match &main.body.statements[i] {
+            Stmt::Expr(Expr::Call { args, .. }) => args.clone(),
+            other => panic!("Expected call, got {:?}", other),
+        };
+        let empty = args(0);
+        assert!(matches!(&empty[1], Expr::Array(parts) if parts.is_empty()));
+        assert!(matches!(&empty[3], Expr::Call { args, .. } if args.is_empty()));
+        let full = args(1);
+        assert!(matches!(&full[1], Expr::Array(parts) if parts.len() == 2));
+        assert_eq!(
+            crate::parser::string_literal(&full[2]).as_deref(),
+            Some(",")
+        );
+        let Expr::Call { args: tags, .. } = &full[3] else {
+            panic!("Expected a dict, got {:?}", full[3]);
+        };
+        assert!(matches!(&tags[0], Expr::Array(pairs) if pairs.len() == 1));
+
+        let errors = [
+            (
+                "def f(**kw, a: i64):\n    pass\n",
+                "'**kw' must be the last parameter",
+            ),
+            ("def f(*, *rest):\n    pass\n", "can't follow a bare '*'"),
+            ("def f(*rest = 1):\n    pass\n", "can't have a default"),
+            (
+                "def f(*rest: i64):\n    pass\n\ndef main():\n    f(x=1)\n",
+                "no parameter named 'x'",
+            ),
+        ];
+        for (source, expected) in errors {
+            let err = parse(source).unwrap_err();
+            assert!(err.message.contains(expected), "{source}: {err}");
+        }
+    }
+
     // --- Multiple structs ---
 
     #[test]
@@ -767,7 +2172,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_struct() {
         let source = "type Point:\n    x: i32\n    y: i32\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Struct(s) => {
                 assert_eq!(s.name, "Point");
                 assert_eq!(s.fields.len(), 2);
@@ -782,7 +2187,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_enum_with_payloads() {
         let source = "type Color = | Red | Green(i32) | Blue(i32, i32)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Color");
                 assert_eq!(e.variants.len(), 3);
@@ -805,7 +2210,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_enum_bare_variants() {
         let source = "type Direction = | North | South | East | West\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Direction");
                 assert_eq!(e.variants.len(), 4);
@@ -821,7 +2226,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_enum_multiline() {
         let source = "type Number =\n    | I64(i64)\n    | F64(f64)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Number");
                 assert_eq!(e.variants.len(), 2);
@@ -842,7 +2247,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_generic_enum() {
         let source = "type MyResult[T, E] = | Ok(T) | Err(E)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "MyResult");
                 assert_eq!(e.type_params.len(), 2);
@@ -888,7 +2293,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_enum_arity_disambiguation() {
         let source = "type Shape = | Point | Point(f64) | Point(f64, f64)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Shape");
                 assert_eq!(e.variants.len(), 3);
@@ -911,7 +2316,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_enum_mixed_unique_and_duplicate_names() {
         let source = "type Geo = | Circle(f64) | Circle(f64, f64) | Square(f64)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.variants.len(), 3);
                 assert_eq!(e.variants[0].name, "Circle__a1"); // disambiguated
@@ -926,7 +2331,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_enum_named_fields() {
         let source = "type Shape = | Point | Rect(width: f64, height: f64)\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Shape");
                 assert_eq!(e.variants.len(), 2);
@@ -950,7 +2355,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_inline_union() {
         let source = "type Number = i64 | f64\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Number");
                 assert_eq!(e.variants.len(), 2);
@@ -971,7 +2376,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_inline_union_three_types() {
         let source = "type Value = i64 | f64 | String\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Value");
                 assert_eq!(e.variants.len(), 3);
@@ -987,7 +2392,7 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
     fn test_type_enum_inline_no_leading_pipe() {
         let source = "type Dir = North | South | East | West\n";
         let module = parse(source).unwrap();
-        match &user_items(&module)[0] {
+        match &module.items[0] {
             Item::Enum(e) => {
                 assert_eq!(e.name, "Dir");
                 assert_eq!(e.variants.len(), 4);
@@ -998,6 +2403,189 @@ def main():\n    p: Point = Point(y=2, x=1)\n    s: Size = Size(height=200, widt
         }
     }
 
+    #[test]
+    fn test_type_enum_discriminants() {
+        let source = "type Status = | Ok = 0 | NotFound = 404 | Error\n";
+        let module = parse(source).unwrap();
+        match &module.items[1] {
+            Item::RustBlock(code) => {
+                assert!(
+                    code.starts_with(
+                        "// quiche:discriminants Status Ok=0 NotFound=404 Error=405\n"
+                    )
+                );
+                assert!(code.contains("404 => Status::NotFound,"), "{code}");
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
+
+        let generated = "#[derive(Debug, Clone, PartialEq)]\npub enum Status {\n    Ok,\n    NotFound,\n    Error,\n}\n// quiche:discriminants Status Ok=0 NotFound=404 Error=405\n";
+        assert_eq!(
+            super::apply_enum_discriminants(generated),
+            "#[derive(Debug, Clone, PartialEq)]\n#[repr(i32)]\npub enum Status {\n    Ok = 0,\n    NotFound = 404,\n    Error = 405,\n}\n// quiche:discriminants Status Ok=0 NotFound=404 Error=405\n"
+        );
+
+        let err = parse("type Bad = | A = 1 | B(i64)\n").unwrap_err();
+        assert!(err.message.contains("cannot carry data"), "{err}");
+    }
+
+    #[test]
+    fn test_markers_are_only_read_from_comments() {
+        let quoted = "pub fn f() -> Str {\n    Str::from(\"// quiche:attribute f #[cold]\")\n}\npub fn g() {\n    let s = \"if __unsafe__!() {\";\n}\n";
+        assert_eq!(super::apply_reprs(quoted), quoted);
+
+        let generated = "// quiche:attribute fib #[inline(always)]\npub fn fib(n: u64) -> u64 {\n    if __unsafe__!() {\n        n\n    }\n}\n#[derive(Debug)]\npub enum Status {\n    #[doc = \"}\"]\n    Ok,\n    NotFound,\n}\n    // quiche:discriminants Status Ok=0 NotFound=404\n";
+        assert_eq!(
+            super::apply_reprs(generated),
+            "#[inline(always)]\npub fn fib(n: u64) -> u64 {\n    unsafe {\n        n\n    }\n}\n#[derive(Debug)]\n#[repr(i32)]\npub enum Status {\n    #[doc = \"}\"]\n    Ok = 0,\n    NotFound = 404,\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_type_newtype() {
+        let module = parse("type Meters = new f64\ntype Name = new Str\n").unwrap();
+        let items = &module.items;
+        match &items[0] {
+            Item::Struct(s) => {
+                assert_eq!(s.name, "Meters");
+                assert_eq!(s.fields[0].name, "value");
+            }
+            other => panic!("Expected Struct, got {:?}", other),
+        }
+        match (&items[1], &items[3]) {
+            (Item::RustBlock(meters), Item::RustBlock(name)) => {
+                assert!(meters.contains("impl std::ops::Add for Meters"), "{meters}");
+                assert!(
+                    meters.contains("impl std::ops::Mul<f64> for Meters"),
+                    "{meters}"
+                );
+                assert!(meters.contains("impl From<Meters> for f64"), "{meters}");
+                assert!(name.contains("impl std::fmt::Display for Name"), "{name}");
+                assert!(!name.contains("std::ops"), "{name}");
+            }
+            other => panic!("Expected RustBlocks, got {:?}", other),
+        }
+        assert_eq!(
+            super::apply_reprs(
+                "#[derive(Debug)]\npub struct Meters {\n    pub value: f64,\n}\n// quiche:transparent Meters\n"
+            ),
+            "#[derive(Debug)]\n#[repr(transparent)]\npub struct Meters {\n    pub value: f64,\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_type_class_attributes() {
+        let source = "type Circle:\n    PI: f64 = 3.14159\n    mut count: i64 = 0\n    r: f64\n\ndef main():\n    Circle.count += 1\n    print(Circle.PI)\n";
+        let module = parse(source).unwrap();
+        let items = &module.items;
+        match &items[0] {
+            Item::Struct(s) => assert_eq!(s.fields.len(), 1),
+            other => panic!("Expected Struct, got {:?}", other),
+        }
+        assert!(matches!(&items[1], Item::Const(c) if c.name == "__CIRCLE_PI" && c.is_const));
+        assert!(matches!(&items[2], Item::Function(f) if f.name == "__circle_count_init"));
+        match &items[4] {
+            Item::RustBlock(code) => {
+                assert!(code.contains("pub const PI: f64 = __CIRCLE_PI;"), "{code}");
+                assert!(code.contains("pub fn count() -> i64"), "{code}");
+                assert!(code.contains("pub fn set_count(value: i64)"), "{code}");
+            }
+            other => panic!("Expected accessors, got {:?}", other),
+        }
+        let Item::Function(main) = &items[5] else {
+            panic!("Expected main");
+        };
+        match &main.body.statements[0] {
+            Stmt::Expr(Expr::Call { callee, args }) => {
+                assert!(matches!(
+                    &**callee,
+                    Expr::Closure { body, .. }
+                        if matches!(&body.statements[0], Stmt::RustBlock(code) if code == "Circle::set_count(value);")
+                ));
+                assert!(matches!(
+                    &args[0],
+                    Expr::Binary {
+                        op: BinaryOp::Add,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("Expected setter call, got {:?}", other),
+        }
+
+        let err = parse("type Circle:\n    PI: f64 = 3.14\n\ndef main():\n    Circle.PI = 3.0\n")
+            .unwrap_err();
+        assert!(err.message.contains("class constant"), "{err}");
+    }
+
+    #[test]
+    fn test_type_named_tuple() {
+        let source =
+            "type Point = (x: i64, y: i64)\n\ndef main():\n    p = Point(1, 2)\n    x, y = p\n";
+        let module = parse(source).unwrap();
+        let items = &module.items;
+        match (&items[0], &items[1]) {
+            (Item::Struct(s), Item::RustBlock(code)) => {
+                assert_eq!(s.name, "Point");
+                assert_eq!(s.fields.len(), 2);
+                assert!(
+                    code.contains(
+                        "write!(f, \"Point(x={}, y={})\", quiche_show!(self.x), quiche_show!(self.y))"
+                    ),
+                    "{code}"
+                );
+                assert!(
+                    code.contains("impl QuicheUnpack for Point {\n    type Tuple = (i64, i64,);"),
+                    "{code}"
+                );
+                assert!(code.contains("impl From<(i64, i64,)> for Point"), "{code}");
+            }
+            other => panic!("Expected Struct and RustBlock, got {:?}", other),
+        }
+        let Item::Function(main) = &items[2] else {
+            panic!("Expected main");
+        };
+        assert!(matches!(
+            &main.body.statements[0],
+            Stmt::Assign { value: Expr::StructLiteral { path, .. }, .. } if path == &["Point"]
+        ));
+        assert!(matches!(
+            &main.body.statements[1],
+            Stmt::DestructureConst { value: Expr::Call { callee, .. }, .. }
+                if matches!(&**callee, Expr::Path(p) if p == &["QuicheUnpack", "unpack"])
+        ));
+    }
+
+    #[test]
+    fn test_type_derive_decorator() {
+        let parsed = crate::parser::parse_decorated(
+            "@derive(Ord, Hash, Serialize)\ntype Point:\n    x: i64\n",
+        )
+        .unwrap();
+        let items = &parsed.module.items;
+        assert!(matches!(
+            &items[1],
+            Item::RustBlock(code)
+                if code == "// quiche:derive Point PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize"
+        ));
+        assert_eq!(parsed.extern_crates, vec!["serde"]);
+
+        let generated = "#[derive(Debug, Clone, PartialEq)]\npub struct Point {\n    pub x: i64,\n}\n// quiche:derive Point PartialEq, Eq, Hash\n";
+        assert!(
+            super::apply_reprs(generated)
+                .starts_with("#[derive(Debug, Clone, PartialEq, Eq, Hash)]\npub struct Point {")
+        );
+        assert!(
+            super::apply_reprs("pub enum Dir {\n    Up,\n}\n// quiche:derive Dir Hash\n")
+                .starts_with("#[derive(Hash)]\npub enum Dir {")
+        );
+
+        let err = parse("@derive(Display)\ntype Point:\n    x: i64\n").unwrap_err();
+        assert!(err.message.contains("derivable traits"), "{err}");
+        let err = parse("@derive(Default)\ntype Dir = | Up | Down\n").unwrap_err();
+        assert!(err.message.contains("not supported"), "{err}");
+    }
+
     #[test]
     fn test_type_compile_union() {
         let source = "type Number = i64 | f64\n";
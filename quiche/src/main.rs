@@ -2,7 +2,14 @@ use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::{self, Command};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::Duration;
+
+use quiche::script::{
+    binary_ext, cdylib_file_name, compile_quiche_lib_rlib, compile_rust_to_binary,
+    compile_rust_to_cdylib, compile_rust_to_small_binary, compile_rust_to_small_cdylib,
+    inject_quiche_lib_module, unique_temp_path,
+};
 
 // ─────────────────────────────────────────────────────────────────────────────
 // Flag Definitions — single source of truth for CLI parsing AND help text
@@ -33,11 +40,43 @@ const DEFAULT_EXPERIMENTS: &[FlagDef] = &[
 const OPTIN_EXPERIMENTS: &[FlagDef] = &[];
 
 /// Non-experiment compiler options.
-const COMPILER_OPTIONS: &[FlagDef] = &[FlagDef {
-    flag: "--fail-on-hot-clone",
-    description: "Error instead of warn on implicit clones",
-    aliases: &[],
-}];
+const COMPILER_OPTIONS: &[FlagDef] = &[
+    FlagDef {
+        flag: "--fail-on-hot-clone",
+        description: "Error instead of warn on implicit clones",
+        aliases: &[],
+    },
+    FlagDef {
+        flag: "--overflow",
+        description: "Integer + - * on overflow: checked, wrapping or saturating",
+        aliases: &[],
+    },
+    FlagDef {
+        flag: "--prelude",
+        description: "Rust put ahead of the module: quiche, none and/or .rs files",
+        aliases: &[],
+    },
+    FlagDef {
+        flag: "--allow-unsafe",
+        description: "Compile `unsafe:` blocks (rejected otherwise)",
+        aliases: &[],
+    },
+    FlagDef {
+        flag: "--memory-profile",
+        description: "Report peak RSS and allocations per compiler pass",
+        aliases: &[],
+    },
+    FlagDef {
+        flag: "--daemon",
+        description: "Compile on a running `quiche daemon` (or set QUICHE_DAEMON=1)",
+        aliases: &[],
+    },
+    FlagDef {
+        flag: "--opt-size",
+        description: "Build a small binary: opt-level=z, LTO, stripped, no tracebacks",
+        aliases: &[],
+    },
+];
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -61,12 +100,58 @@ fn main() {
         run_test(&args[2..]);
         return;
     }
+    if args[1] == "bindgen" {
+        run_bindgen(&args[2..]);
+        return;
+    }
+    if args[1] == "graph" {
+        run_graph(&args[2..]);
+        return;
+    }
+    if args[1] == "fmt" {
+        run_fmt(&args[2..]);
+        return;
+    }
+    if args[1] == "repl" {
+        run_repl(&args[2..]);
+        return;
+    }
+    if args[1] == "completions" {
+        run_completions(&args[2..]);
+        return;
+    }
+    if args[1] == "daemon" {
+        run_daemon(&args[2..]);
+        return;
+    }
 
+    // Arguments after `--` are the script's own
+    let (args, script_args) = match args.iter().position(|a| a == "--") {
+        Some(at) => (args[..at].to_vec(), args[at + 1..].to_vec()),
+        None => (args, Vec::new()),
+    };
+    if args.len() < 2 {
+        print_usage();
+        process::exit(1);
+    }
     let filename = &args[1];
     let emit_rust = has_flag(&args, "--emit-rust");
     let emit_elevate = has_flag(&args, "--emit-elevate");
     let dump_ast = has_flag(&args, "--emit-ast");
+    let test_mode = has_flag(&args, "--test");
+    // `quiche test --coverage` reaches each test file through the env var
+    let coverage = has_flag(&args, "--coverage") || env::var_os("QUICHE_COVERAGE").is_some();
     let lib_path = flag_value(&args, "--lib");
+    let hook = flag_value(&args, "--hook");
+    let opt_size = has_flag(&args, "--opt-size");
+    let hot = has_flag(&args, "--hot");
+    if hot && (test_mode || hook.is_some() || opt_size) {
+        eprintln!("Error: --hot can't be combined with --test, --hook or --opt-size");
+        process::exit(2);
+    }
+    // `quiche test --allow-unsafe` reaches each test file through the env var
+    let allow_unsafe =
+        has_flag(&args, "--allow-unsafe") || env::var_os(quiche::ALLOW_UNSAFE_ENV).is_some();
 
     // Start with defaults (core experiments enabled)
     let mut options = quiche::default_options();
@@ -81,6 +166,12 @@ fn main() {
     if has_flag(&args, "--fail-on-hot-clone") {
         options.fail_on_hot_clone = true;
     }
+    let overflow = overflow_mode(&args);
+    let prelude = prelude_mode(&args);
+    if hot && !prelude.quiche_lib {
+        eprintln!("Error: --hot needs quiche-lib in the prelude");
+        process::exit(2);
+    }
 
     let source = match fs::read_to_string(filename) {
         Ok(s) => s,
@@ -130,13 +221,86 @@ fn main() {
         eprintln!("🔒 fail-on-hot-clone enabled");
     }
 
-    match quiche::compile_file(&source, filename, &options) {
+    let memory_profile = has_flag(&args, "--memory-profile");
+    let on_daemon = if !use_daemon(&args)
+        || memory_profile
+        || hook.is_some()
+        || test_mode
+        || prelude != Default::default()
+    {
+        None
+    } else {
+        compile_on_daemon(&source, filename, &options, overflow, allow_unsafe)
+    };
+    let compiled = match on_daemon {
+        Some(compiled) => compiled,
+        None => {
+            if !allow_unsafe && let Err(e) = quiche::reject_unsafe(&source, filename) {
+                eprintln!("Compile error:\n{}", e);
+                process::exit(1);
+            }
+            if memory_profile {
+                quiche::memprof::enable();
+            }
+            let compiled = if let Some(hook) = &hook {
+                quiche::compile_hook_file(&source, filename, &options, &prelude, hook)
+            } else if test_mode {
+                quiche::compile_test_file(&source, filename, &options, &prelude, coverage)
+            } else {
+                quiche::compile_file_with_prelude(&source, filename, &options, overflow, &prelude)
+            };
+            if memory_profile {
+                eprint!("{}", quiche::memprof::report());
+            }
+            compiled
+        }
+    };
+    match compiled {
         Ok(rust_code) => {
             if emit_rust {
                 print!("{}", rust_code);
             } else {
-                // Default: compile and run
-                run_rust_code(&rust_code, lib_path.as_deref());
+                // Scripts are built with bare rustc, which cannot link crates.io deps
+                let crates = crate_names(quiche::extern_crates(&source, filename));
+                if !crates.is_empty() {
+                    eprintln!(
+                        "Error: '{}' uses crates via @extern or @py_import ({}); build it inside a Cargo project (see `quiche init`) instead of running it as a script",
+                        filename,
+                        crates.join(", ")
+                    );
+                    process::exit(1);
+                }
+                // Default: compile and run, with `.q` tracebacks on panic
+                // (left out of size-optimized builds)
+                let rust_code = match quiche::parse(&source) {
+                    Ok(module) if hot => {
+                        let rust_code = quiche::hot::instrument(&rust_code, &module, &source);
+                        quiche::traceback::inject(&rust_code, &module, &source, filename)
+                    }
+                    Ok(module) if !test_mode && hook.is_none() && !opt_size => {
+                        quiche::traceback::inject(&rust_code, &module, &source, filename)
+                    }
+                    _ => rust_code,
+                };
+                if hot {
+                    run_hot(
+                        filename,
+                        &rust_code,
+                        lib_path.as_deref(),
+                        &script_args,
+                        |source| {
+                            if !allow_unsafe {
+                                quiche::reject_unsafe(source, filename)?;
+                            }
+                            let rust_code = quiche::compile_file_with_prelude(
+                                source, filename, &options, overflow, &prelude,
+                            )?;
+                            let module = quiche::parse(source)?;
+                            Ok(quiche::hot::instrument(&rust_code, &module, source))
+                        },
+                    );
+                }
+                run_rust_code(&rust_code, lib_path.as_deref(), opt_size, &script_args);
             }
         }
         Err(e) => {
@@ -146,7 +310,31 @@ fn main() {
     }
 }
 
-fn run_rust_code(rust_code: &str, lib_path: Option<&str>) {
+/// Whether to try a running `quiche daemon` before compiling in-process:
+/// only when asked, so a daemon left listening is never used unnoticed.
+fn use_daemon(args: &[String]) -> bool {
+    has_flag(args, "--daemon")
+        || env::var_os(quiche::daemon::USE_ENV).is_some_and(|value| value == "1")
+}
+
+/// Compile on a running `quiche daemon`, printing its warnings; `None` when
+/// no daemon is listening, for the caller to compile in-process.
+fn compile_on_daemon(
+    source: &str,
+    filename: &str,
+    options: &quiche::CompileOptions,
+    overflow: quiche::Overflow,
+    allow_unsafe: bool,
+) -> Option<Result<String, String>> {
+    let request = quiche::daemon::Request::new(filename, source, options, overflow, allow_unsafe);
+    let reply = quiche::daemon::compile_at(&quiche::daemon::socket_path(), &request)?;
+    for warning in &reply.warnings {
+        eprintln!("{warning}");
+    }
+    Some(reply.result)
+}
+
+fn run_rust_code(rust_code: &str, lib_path: Option<&str>, opt_size: bool, script_args: &[String]) {
     let rs_path = unique_temp_path("quiche-script-runner", "rs");
     let bin_path = unique_temp_path("quiche-script-runner", binary_ext());
 
@@ -165,21 +353,35 @@ fn run_rust_code(rust_code: &str, lib_path: Option<&str>) {
         }
     };
 
-    let rust_code = inject_quiche_lib_module(rust_code, &quiche_lib_src);
-
-    fs::write(&rs_path, rust_code).unwrap_or_else(|e| {
-        eprintln!("Error: Failed to write temp file: {}", e);
-        process::exit(1);
-    });
-
-    if let Err(error) = compile_rust_to_binary(&rs_path, &bin_path) {
-        let _ = fs::remove_file(&rs_path);
-        let _ = fs::remove_file(&bin_path);
-        eprintln!("{error}");
-        process::exit(1);
+    if opt_size {
+        fs::write(&rs_path, rust_code).unwrap_or_else(|e| {
+            eprintln!("Error: Failed to write temp file: {}", e);
+            process::exit(1);
+        });
+        let built = with_quiche_lib_rlib(&quiche_lib_src, |rlib| {
+            compile_rust_to_small_binary(&rs_path, &bin_path, rlib)
+        });
+        if let Err(error) = built {
+            let _ = fs::remove_file(&rs_path);
+            let _ = fs::remove_file(&bin_path);
+            eprintln!("{error}");
+            process::exit(1);
+        }
+    } else {
+        let rust_code = inject_quiche_lib_module(rust_code, &quiche_lib_src);
+        fs::write(&rs_path, rust_code).unwrap_or_else(|e| {
+            eprintln!("Error: Failed to write temp file: {}", e);
+            process::exit(1);
+        });
+        if let Err(error) = compile_rust_to_binary(&rs_path, &bin_path) {
+            let _ = fs::remove_file(&rs_path);
+            let _ = fs::remove_file(&bin_path);
+            eprintln!("{error}");
+            process::exit(1);
+        }
     }
 
-    let run = Command::new(&bin_path).status();
+    let run = Command::new(&bin_path).args(script_args).status();
     let _ = fs::remove_file(&rs_path);
     let _ = fs::remove_file(&bin_path);
     match run {
@@ -191,43 +393,129 @@ fn run_rust_code(rust_code: &str, lib_path: Option<&str>) {
     }
 }
 
-fn compile_rust_to_binary(rust_path: &Path, output_path: &Path) -> Result<(), String> {
-    let output = Command::new("rustc")
-        .arg("--edition=2021")
-        .arg(rust_path)
-        .arg("-o")
-        .arg(output_path)
-        .output()
-        .map_err(|error| format!("failed to run rustc for {}: {error}", rust_path.display()))?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "rustc failed for {} (status: {})\n{}",
-            rust_path.display(),
-            output.status.code().unwrap_or(-1),
-            String::from_utf8_lossy(&output.stderr).trim()
-        ));
+/// Run a `--hot` build of `filename`, rebuilding it with `recompile` as a
+/// shared library whenever the file changes, for the running program to
+/// swap its changed functions in (see `quiche::hot`). Exits with the
+/// program's status.
+fn run_hot(
+    filename: &str,
+    rust_code: &str,
+    lib_path: Option<&str>,
+    script_args: &[String],
+    recompile: impl Fn(&str) -> Result<String, String>,
+) -> ! {
+    let quiche_lib_src = resolve_quiche_lib_source(lib_path).unwrap_or_else(|error| {
+        eprintln!("Error: {error}");
+        process::exit(1);
+    });
+    let rs_path = unique_temp_path("quiche-script-runner", "rs");
+    let bin_path = unique_temp_path("quiche-script-runner", binary_ext());
+    let control = unique_temp_path("quiche-hot", "path");
+    let built = fs::write(
+        &rs_path,
+        inject_quiche_lib_module(rust_code, &quiche_lib_src),
+    )
+    .map_err(|e| format!("Error: Failed to write temp file: {e}"))
+    .and_then(|()| compile_rust_to_binary(&rs_path, &bin_path));
+    let _ = fs::remove_file(&rs_path);
+    if let Err(error) = built {
+        let _ = fs::remove_file(&bin_path);
+        eprintln!("{error}");
+        process::exit(1);
     }
 
-    Ok(())
+    let mut child = Command::new(&bin_path)
+        .args(script_args)
+        .env(quiche::hot::HOT_ENV, &control)
+        .spawn()
+        .unwrap_or_else(|e| {
+            eprintln!("Error: Failed to run binary: {}", e);
+            process::exit(1);
+        });
+    eprintln!("🔥 watching {filename} for changes");
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified(filename);
+    let mut libraries = Vec::new();
+    let status = loop {
+        match child.try_wait() {
+            Ok(None) => {}
+            done => break done,
+        }
+        thread::sleep(Duration::from_millis(200));
+        let now = modified(filename);
+        if now == last_modified {
+            continue;
+        }
+        last_modified = now;
+        match rebuild_hot_library(filename, &recompile, &quiche_lib_src) {
+            Ok(library) => {
+                // Written aside and renamed, so the program never reads a
+                // partial path
+                let partial = control.with_extension("partial");
+                let swapped = fs::write(&partial, library.to_string_lossy().as_bytes())
+                    .and_then(|()| fs::rename(&partial, &control));
+                if let Err(e) = swapped {
+                    eprintln!("Error: Failed to write {}: {e}", control.display());
+                }
+                libraries.push(library);
+            }
+            Err(error) => eprintln!("{error}"),
+        }
+    };
+
+    let _ = fs::remove_file(&bin_path);
+    let _ = fs::remove_file(&control);
+    for library in &libraries {
+        let _ = fs::remove_file(library);
+    }
+    match status {
+        Ok(Some(status)) => process::exit(status.code().unwrap_or(1)),
+        Ok(None) => process::exit(1),
+        Err(e) => {
+            eprintln!("Error: Failed to run binary: {}", e);
+            process::exit(1);
+        }
+    }
 }
 
-fn unique_temp_path(prefix: &str, ext: &str) -> PathBuf {
-    let nanos = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|duration| duration.as_nanos())
-        .unwrap_or(0);
-    let pid = process::id();
-    let suffix = if ext.is_empty() {
-        format!("{prefix}-{pid}-{nanos}")
-    } else {
-        format!("{prefix}-{pid}-{nanos}.{ext}")
-    };
-    env::temp_dir().join(suffix)
+/// Compile the current source of `filename` to a shared library for
+/// [`run_hot`], returning its path.
+fn rebuild_hot_library(
+    filename: &str,
+    recompile: &impl Fn(&str) -> Result<String, String>,
+    quiche_lib_src: &Path,
+) -> Result<PathBuf, String> {
+    let source = fs::read_to_string(filename)
+        .map_err(|e| format!("Error: Failed to read '{}': {}", filename, e))?;
+    let rust_code = recompile(&source).map_err(|e| format!("Compile error:\n{}", e))?;
+    let rs_path = unique_temp_path("quiche-hot", "rs");
+    let stem = unique_temp_path("quiche-hot", "");
+    let library = stem.with_file_name(cdylib_file_name(
+        &stem.file_name().unwrap_or_default().to_string_lossy(),
+    ));
+    let built = fs::write(
+        &rs_path,
+        inject_quiche_lib_module(&rust_code, quiche_lib_src),
+    )
+    .map_err(|e| format!("Error: Failed to write temp file: {e}"))
+    .and_then(|()| compile_rust_to_cdylib(&rs_path, &library));
+    let _ = fs::remove_file(&rs_path);
+    built.map(|()| library)
 }
 
-fn binary_ext() -> &'static str {
-    if cfg!(windows) { "exe" } else { "" }
+/// Compile quiche-lib to an rlib in a scratch directory for an `--opt-size`
+/// build, and run `build` against it.
+fn with_quiche_lib_rlib(
+    lib_src: &Path,
+    build: impl FnOnce(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let lib_dir = unique_temp_path("quiche-lib", "");
+    let built = fs::create_dir_all(&lib_dir)
+        .map_err(|e| format!("Error: Failed to create temp dir: {e}"))
+        .and_then(|()| compile_quiche_lib_rlib(lib_src, &lib_dir))
+        .and_then(|rlib| build(&rlib));
+    let _ = fs::remove_dir_all(&lib_dir);
+    built
 }
 
 fn has_flag(args: &[String], flag: &str) -> bool {
@@ -240,18 +528,52 @@ fn flag_value(args: &[String], flag: &str) -> Option<String> {
         .map(|w| w[1].clone())
 }
 
-fn resolve_quiche_lib_source(lib_path: Option<&str>) -> Result<PathBuf, String> {
-    let default_lib_dir = if let Some(root) = find_workspace_root() {
-        root.join("lib")
-    } else {
-        env::current_dir()
-            .map_err(|e| format!("failed to resolve current directory: {e}"))?
-            .join("lib")
+/// The value of `--overflow <mode>` or `--overflow=<mode>`.
+fn overflow_flag(args: &[String]) -> Option<String> {
+    flag_value(args, "--overflow").or_else(|| {
+        args.iter()
+            .find_map(|a| a.strip_prefix("--overflow=").map(str::to_string))
+    })
+}
+
+/// The overflow mode from `--overflow`, falling back to `QUICHE_OVERFLOW`;
+/// exits on an unknown mode.
+fn overflow_mode(args: &[String]) -> quiche::Overflow {
+    let mode = match overflow_flag(args) {
+        Some(mode) => quiche::Overflow::parse(&mode),
+        None => quiche::Overflow::from_env(),
     };
+    mode.unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        process::exit(2);
+    })
+}
+
+/// The value of `--prelude <spec>` or `--prelude=<spec>`.
+fn prelude_flag(args: &[String]) -> Option<String> {
+    flag_value(args, "--prelude").or_else(|| {
+        args.iter()
+            .find_map(|a| a.strip_prefix("--prelude=").map(str::to_string))
+    })
+}
+
+/// The prelude from `--prelude`, falling back to `QUICHE_PRELUDE`; exits on
+/// an unknown part or unreadable file.
+fn prelude_mode(args: &[String]) -> quiche::Prelude {
+    let prelude = match prelude_flag(args) {
+        Some(spec) => quiche::Prelude::parse(&spec),
+        None => quiche::Prelude::from_env(),
+    };
+    prelude.unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        process::exit(2);
+    })
+}
 
+fn resolve_quiche_lib_source(lib_path: Option<&str>) -> Result<PathBuf, String> {
     let selected_path = match lib_path {
         Some(path) => PathBuf::from(path),
-        None => default_lib_dir,
+        None => default_lib_dir()?,
     };
 
     if selected_path.is_file() {
@@ -290,37 +612,19 @@ fn resolve_quiche_lib_source(lib_path: Option<&str>) -> Result<PathBuf, String>
         .map_err(|e| format!("failed to canonicalize '{}': {e}", lib_rs.display()))
 }
 
-fn inject_quiche_lib_module(rust_code: &str, lib_src: &Path) -> String {
-    if rust_code.contains("mod quiche_lib;") {
-        return rust_code.to_string();
+/// The quiche-lib used without `--lib`: the workspace's `lib/`, else a
+/// `./lib` holding its source, else the copy built into the compiler.
+fn default_lib_dir() -> Result<PathBuf, String> {
+    if let Some(root) = find_workspace_root() {
+        return Ok(root.join("lib"));
     }
-
-    let escaped_path = lib_src
-        .to_string_lossy()
-        .replace('\\', "\\\\")
-        .replace('"', "\\\"");
-    let module_decl = format!("#[path = \"{escaped_path}\"]\nmod quiche_lib;\n");
-
-    let mut insert_at = 0usize;
-    for line in rust_code.split_inclusive('\n') {
-        let trimmed = line.trim_end_matches(['\r', '\n']);
-        if trimmed.starts_with("#![") {
-            insert_at += line.len();
-        } else {
-            break;
-        }
-    }
-
-    if insert_at == 0 {
-        format!("{module_decl}\n{rust_code}")
-    } else {
-        let mut out = String::with_capacity(rust_code.len() + module_decl.len() + 1);
-        out.push_str(&rust_code[..insert_at]);
-        out.push_str(&module_decl);
-        out.push('\n');
-        out.push_str(&rust_code[insert_at..]);
-        out
+    let local = env::current_dir()
+        .map_err(|e| format!("failed to resolve current directory: {e}"))?
+        .join("lib");
+    if local.join("src").join("lib.rs").exists() {
+        return Ok(local);
     }
+    quiche::vendor::vendored_lib_dir()
 }
 
 fn has_any_flag(args: &[String], flag: &str, aliases: &[&str]) -> bool {
@@ -333,19 +637,31 @@ fn print_usage() {
         "quiche - Python-flavoured Rust compiler\n\
          \n\
          USAGE:\n\
-         \x20   quiche <file.q> [OPTIONS]\n\
+         \x20   quiche <file.q> [OPTIONS] [-- <script args>]\n\
          \x20   quiche init <path> [cargo init flags]\n\
-         \x20   quiche build <file.q> [-o <output.rs>]\n\
-         \x20   quiche test                             # run qtest suite\n\
+         \x20   quiche build <file.q> [-o <output.rs>] [--crate-type cdylib] [--overflow <mode>] [--prelude <spec>] [--opt-size] [--allow-unsafe] [--memory-profile] [--daemon]\n\
+         \x20   quiche test [--update-snapshots] [--coverage] [--format junit|tap] [--overflow <mode>] [--prelude <spec>] [--allow-unsafe]\n\
+         \x20   quiche bindgen <crate|rustdoc.json> [-o <output.qi>]\n\
+         \x20   quiche graph [src-dir] [--calls] [--format dot|mermaid]\n\
+         \x20   quiche fmt [paths...] [--check]\n\
+         \x20   quiche repl [--lib <path>]\n\
+         \x20   quiche completions <bash|zsh|fish>\n\
+         \x20   quiche daemon [start|stop|status] [--socket <path>]\n\
          \n\
-         By default, quiche compiles and runs the script.\n\
+         By default, quiche compiles and runs the script, passing it the\n\
+         arguments after `--`.\n\
          Core experiment flags are enabled by default.\n\
          \n\
          OPTIONS:\n\
          \x20   --emit-rust              Emit generated Rust code to stdout\n\
          \x20   --emit-elevate           Emit Elevate (.ers) source to stdout\n\
          \x20   --emit-ast               Dump raw AST with metadata (debug)\n\
-            \x20   --lib <path>             quiche-lib source path (dir or src/lib.rs; default ./lib)\n\
+         \x20   --test                   Run test_* functions with @fixture injection\n\
+         \x20   --coverage               With --test, write lcov line coverage to target/qcov\n\
+         \x20   --hook <name>            Run session hook <name> (e.g. setup_session) instead of main\n\
+         \x20   --hot                    Rebuild on save and swap changed functions into the running script\n\
+         \x20   --lib <path>             quiche-lib source path (dir or src/lib.rs; default ./lib,\n\
+         \x20                            else the copy built into quiche)\n\
          \x20   -h, --help               Show this help message"
     );
 
@@ -375,6 +691,8 @@ fn print_usage() {
          \x20   quiche hello.q                          # compile + run\n\
          \x20   quiche hello.q --emit-rust              # show generated Rust\n\
          \x20   quiche hello.q --emit-elevate           # show parsed AST\n\
+         \x20   quiche game.q --hot                     # run, reloading functions on save\n\
+         \x20   quiche fmt --check                      # list .q files needing formatting\n\
          \x20   quiche test                              # run all tests"
     );
 }
@@ -384,32 +702,220 @@ fn print_usage() {
 // ─────────────────────────────────────────────────────────────────────────────
 
 fn run_test(args: &[String]) {
-    // Find qtest.q relative to workspace root (look for Cargo.toml upwards)
-    let qtest_path = find_workspace_root()
-        .map(|root| root.join("lib").join("qtest.q"))
-        .unwrap_or_else(|| PathBuf::from("lib/qtest.q"));
+    // Find qtest.q in the workspace's lib/ (look for Cargo.toml upwards),
+    // else in the quiche-lib built into the compiler
+    let qtest_path = default_lib_dir()
+        .map(|lib| lib.join("qtest.q"))
+        .unwrap_or_else(|error| {
+            eprintln!("Error: {error}");
+            process::exit(1);
+        });
 
     if !qtest_path.exists() {
         eprintln!(
             "Error: Could not find qtest runner at {}",
             qtest_path.display()
         );
-        eprintln!("Make sure you're in a Quiche workspace with lib/qtest.q");
         process::exit(1);
     }
 
-    // Re-invoke ourselves on qtest.q, passing through any extra args
+    let format = flag_value(args, "--format");
+    if let Some(format) = &format
+        && format != "junit"
+        && format != "tap"
+    {
+        eprintln!("Error: unknown --format '{format}' (expected junit or tap)");
+        process::exit(1);
+    }
+
+    // Re-invoke ourselves on qtest.q, passing through any extra args.
+    // --update-snapshots, --coverage, --format, --overflow, --prelude and
+    // --allow-unsafe travel as env vars so they reach each test file's compile and binary.
     let exe = env::current_exe().unwrap_or_else(|_| PathBuf::from("quiche"));
     let mut cmd = Command::new(exe);
     cmd.arg(&qtest_path);
-    cmd.args(args);
+    let mut passthrough = args.iter();
+    while let Some(arg) = passthrough.next() {
+        match arg.as_str() {
+            "--update-snapshots" | "--coverage" | "--allow-unsafe" => {}
+            "--format" | "--overflow" | "--prelude" => {
+                passthrough.next();
+            }
+            _ if arg.starts_with("--overflow=") || arg.starts_with("--prelude=") => {}
+            _ => {
+                cmd.arg(arg);
+            }
+        }
+    }
+    let report_dir = format.as_ref().map(|format| {
+        let root = find_workspace_root().unwrap_or_else(|| PathBuf::from("."));
+        let dir = root.join("target").join("qtest");
+        let _ = fs::remove_dir_all(&dir);
+        cmd.env("QUICHE_TEST_FORMAT", format);
+        cmd.env("QUICHE_TEST_REPORT_DIR", &dir);
+        dir
+    });
+    if has_flag(args, "--update-snapshots") {
+        cmd.env("QUICHE_UPDATE_SNAPSHOTS", "1");
+    }
+    if let Some(mode) = overflow_flag(args) {
+        cmd.env(quiche::overflow::OVERFLOW_ENV, mode);
+    }
+    if let Some(spec) = prelude_flag(args) {
+        cmd.env(quiche::prelude::PRELUDE_ENV, spec);
+    }
+    if has_flag(args, "--allow-unsafe") {
+        cmd.env(quiche::ALLOW_UNSAFE_ENV, "1");
+    }
+    let coverage_dir = has_flag(args, "--coverage").then(|| {
+        let root = find_workspace_root().unwrap_or_else(|| PathBuf::from("."));
+        let dir = root.join("target").join("qcov");
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    });
+    if let Some(dir) = &coverage_dir {
+        cmd.env("QUICHE_COVERAGE", "1");
+        cmd.env("QUICHE_COVERAGE_DIR", dir);
+    }
     let status = cmd.status().unwrap_or_else(|e| {
         eprintln!("Error: Failed to run qtest: {}", e);
         process::exit(1);
     });
+    if let Some(dir) = &coverage_dir {
+        report_coverage(dir);
+    }
+    if let (Some(format), Some(dir)) = (&format, &report_dir) {
+        merge_test_reports(format, dir);
+    }
     process::exit(status.code().unwrap_or(1));
 }
 
+/// Files in `dir` with extension `ext`, excluding the merged report itself.
+fn report_fragments(dir: &Path, ext: &str, merged: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.extension().is_some_and(|e| e == ext)
+                        && p.file_name().is_some_and(|n| n != merged)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+/// Merge the per-file reports written by each test binary into
+/// `<dir>/junit.xml` or `<dir>/results.tap`.
+fn merge_test_reports(format: &str, dir: &Path) {
+    let (ext, merged_name) = if format == "junit" {
+        ("xml", "junit.xml")
+    } else {
+        ("tap", "results.tap")
+    };
+    let fragments: Vec<String> = report_fragments(dir, ext, merged_name)
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .collect();
+
+    let merged = if format == "junit" {
+        let body = fragments.concat();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n{}</testsuites>\n",
+            body.matches("<testcase ").count(),
+            body.matches("<failure ").count(),
+            body.matches("<skipped ").count(),
+            body
+        )
+    } else {
+        // Renumber each file's TAP points into a single plan
+        let mut points = Vec::new();
+        for fragment in &fragments {
+            for line in fragment.lines() {
+                if let Some(rest) = line.strip_prefix("not ok ") {
+                    let desc = rest.split_once(' ').map_or("", |(_, d)| d);
+                    points.push(format!("not ok {} {}", points.len() + 1, desc));
+                } else if let Some(rest) = line.strip_prefix("ok ") {
+                    let desc = rest.split_once(' ').map_or("", |(_, d)| d);
+                    points.push(format!("ok {} {}", points.len() + 1, desc));
+                } else if line.starts_with("  ") {
+                    points.push(line.to_string());
+                }
+            }
+        }
+        let count = points.iter().filter(|p| !p.starts_with("  ")).count();
+        format!("TAP version 13\n1..{count}\n{}\n", points.join("\n"))
+    };
+
+    let path = dir.join(merged_name);
+    match fs::write(&path, merged) {
+        Ok(()) => println!("\n{format} report: {}", path.display()),
+        Err(e) => eprintln!("Error: Failed to write {}: {}", path.display(), e),
+    }
+}
+
+/// Merge the per-file lcov records written by each test binary into
+/// `<dir>/lcov.info` and print a per-file line coverage table.
+fn report_coverage(dir: &Path) {
+    let records = report_fragments(dir, "info", "lcov.info");
+    if records.is_empty() {
+        eprintln!("No coverage data was written to {}", dir.display());
+        return;
+    }
+
+    let mut merged = String::new();
+    let (mut total_found, mut total_hit) = (0usize, 0usize);
+    println!("\nCoverage:");
+    for record in &records {
+        let Ok(text) = fs::read_to_string(record) else {
+            continue;
+        };
+        let field = |key: &str| -> (String, usize) {
+            let value = text
+                .lines()
+                .find_map(|line| line.strip_prefix(key))
+                .unwrap_or("");
+            (value.to_string(), value.parse().unwrap_or(0))
+        };
+        let (source, _) = field("SF:");
+        let (_, found) = field("LF:");
+        let (_, hit) = field("LH:");
+        total_found += found;
+        total_hit += hit;
+        println!(
+            "    {:<45}{:>5}/{:<5}{:>6}",
+            source,
+            hit,
+            found,
+            percent(hit, found)
+        );
+        merged.push_str(&text);
+    }
+    println!(
+        "    {:<45}{:>5}/{:<5}{:>6}",
+        "TOTAL",
+        total_hit,
+        total_found,
+        percent(total_hit, total_found)
+    );
+
+    let lcov_path = dir.join("lcov.info");
+    match fs::write(&lcov_path, merged) {
+        Ok(()) => println!("\nlcov report: {}", lcov_path.display()),
+        Err(e) => eprintln!("Error: Failed to write {}: {}", lcov_path.display(), e),
+    }
+}
+
+fn percent(hit: usize, found: usize) -> String {
+    if found == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", 100.0 * hit as f64 / found as f64)
+    }
+}
+
 fn find_workspace_root() -> Option<PathBuf> {
     let mut dir = env::current_dir().ok()?;
     loop {
@@ -428,7 +934,7 @@ fn find_workspace_root() -> Option<PathBuf> {
 
 fn run_build(args: &[String]) {
     if args.is_empty() {
-        eprintln!("usage: quiche build <file.q> [-o <output.rs>]");
+        eprintln!("usage: quiche build <file.q> [-o <output.rs>] [--crate-type cdylib]");
         process::exit(2);
     }
 
@@ -446,9 +952,73 @@ fn run_build(args: &[String]) {
         }
     };
 
+    let crate_type = flag_value(args, "--crate-type");
+    if let Some(crate_type) = &crate_type
+        && crate_type != "cdylib"
+    {
+        eprintln!("Error: unsupported --crate-type '{crate_type}' (expected cdylib)");
+        process::exit(2);
+    }
+
+    let allow_unsafe = has_flag(args, "--allow-unsafe");
     let options = quiche::default_options();
-    match quiche::compile_file(&source, filename, &options) {
+    let overflow = overflow_mode(args);
+    let prelude = prelude_mode(args);
+    let memory_profile = has_flag(args, "--memory-profile");
+    let on_daemon = if !use_daemon(args) || memory_profile || prelude != Default::default() {
+        None
+    } else {
+        compile_on_daemon(&source, filename, &options, overflow, allow_unsafe)
+    };
+    let compiled = match on_daemon {
+        Some(compiled) => compiled,
+        None => {
+            if !allow_unsafe && let Err(e) = quiche::reject_unsafe(&source, filename) {
+                eprintln!("Compile error:\n{}", e);
+                process::exit(1);
+            }
+            if memory_profile {
+                quiche::memprof::enable();
+            }
+            let compiled =
+                quiche::compile_file_with_prelude(&source, filename, &options, overflow, &prelude);
+            if memory_profile {
+                eprint!("{}", quiche::memprof::report());
+            }
+            compiled
+        }
+    };
+    match compiled {
+        Ok(rust_code) if crate_type.is_some() => {
+            let output_path = output_path.unwrap_or_else(|| {
+                let stem = Path::new(filename)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "quiche".to_string());
+                PathBuf::from(cdylib_file_name(&stem))
+            });
+            let crates = crate_names(quiche::extern_crates(&source, filename));
+            if !crates.is_empty() {
+                eprintln!(
+                    "Error: '{}' uses crates via @extern ({}); add `crate-type = [\"cdylib\"]` to a Cargo project (see `quiche init`) instead",
+                    filename,
+                    crates.join(", ")
+                );
+                process::exit(1);
+            }
+            build_cdylib(
+                &rust_code,
+                &output_path,
+                flag_value(args, "--lib").as_deref(),
+                has_flag(args, "--opt-size"),
+            );
+        }
         Ok(rust_code) => {
+            let crates = quiche::extern_crates(&source, filename).unwrap_or_default();
+            if let Err(e) = record_dependencies(Path::new(filename), &crates) {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
             if let Some(path) = output_path {
                 if let Some(parent) = path.parent() {
                     fs::create_dir_all(parent).unwrap_or_else(|e| {
@@ -471,6 +1041,561 @@ fn run_build(args: &[String]) {
     }
 }
 
+/// Build generated code into a shared library at `output_path` with bare
+/// `rustc`, for loading from C, Python (ctypes), and other FFI hosts.
+fn build_cdylib(rust_code: &str, output_path: &Path, lib_path: Option<&str>, opt_size: bool) {
+    let quiche_lib_src = resolve_quiche_lib_source(lib_path).unwrap_or_else(|error| {
+        eprintln!("Error: {error}");
+        process::exit(1);
+    });
+    let rs_path = unique_temp_path("quiche-cdylib", "rs");
+    let rust_code = if opt_size {
+        rust_code.to_string()
+    } else {
+        inject_quiche_lib_module(rust_code, &quiche_lib_src)
+    };
+    fs::write(&rs_path, rust_code).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to write temp file: {}", e);
+        process::exit(1);
+    });
+    let built = if opt_size {
+        with_quiche_lib_rlib(&quiche_lib_src, |rlib| {
+            compile_rust_to_small_cdylib(&rs_path, output_path, rlib)
+        })
+    } else {
+        compile_rust_to_cdylib(&rs_path, output_path)
+    };
+    let _ = fs::remove_file(&rs_path);
+    if let Err(error) = built {
+        eprintln!("{error}");
+        process::exit(1);
+    }
+    println!("built {}", output_path.display());
+}
+
+/// The names of the crates from [`quiche::extern_crates`], for messages.
+fn crate_names(crates: Result<Vec<(String, Option<String>)>, String>) -> Vec<String> {
+    crates
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(krate, _)| krate)
+        .collect()
+}
+
+/// Add crates named by `@extern` declarations to the `[dependencies]` of the
+/// Cargo.toml enclosing `source`, so `cargo build` can resolve them.
+/// Crates that are already listed are left alone. A missing crate with no
+/// `version=` fails the build, leaving the manifest untouched, with the
+/// line to add.
+fn record_dependencies(source: &Path, crates: &[(String, Option<String>)]) -> Result<(), String> {
+    if crates.is_empty() {
+        return Ok(());
+    }
+    let start = fs::canonicalize(source)
+        .map_err(|e| format!("cannot resolve {}: {e}", source.display()))?;
+    let Some(manifest_path) = start
+        .ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|p| p.is_file())
+    else {
+        let names: Vec<&str> = crates.iter().map(|(krate, _)| krate.as_str()).collect();
+        eprintln!(
+            "warning: {} uses crates via @extern ({}) but is not inside a Cargo project",
+            source.display(),
+            names.join(", ")
+        );
+        return Ok(());
+    };
+    let manifest = fs::read_to_string(&manifest_path)
+        .map_err(|e| format!("failed to read {}: {e}", manifest_path.display()))?;
+
+    let listed = |name: &str| {
+        let dashed = name.replace('_', "-");
+        manifest.lines().any(|line| {
+            let key = line.split(['=', '.']).next().unwrap_or("").trim();
+            key == name || key == dashed
+        })
+    };
+    let missing: Vec<_> = crates.iter().filter(|(krate, _)| !listed(krate)).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let unversioned: Vec<String> = missing
+        .iter()
+        .filter(|(krate, version)| dependency_entry(krate, version.as_deref()).is_none())
+        .map(|(krate, _)| format!("\n    {krate} = \"<version>\""))
+        .collect();
+    if !unversioned.is_empty() {
+        return Err(format!(
+            "{} uses crates that {} does not list; add them under [dependencies] (or give their @extern a version=\"...\"):{}",
+            source.display(),
+            manifest_path.display(),
+            unversioned.concat()
+        ));
+    }
+
+    let entries: Vec<String> = missing
+        .iter()
+        .filter_map(|(krate, version)| dependency_entry(krate, version.as_deref()))
+        .collect();
+    let lines: String = entries.iter().map(|entry| format!("\n{entry}")).collect();
+    let updated = if manifest.contains("[dependencies]") {
+        manifest.replacen("[dependencies]", &format!("[dependencies]{lines}"), 1)
+    } else {
+        format!("{manifest}\n[dependencies]{lines}\n")
+    };
+    fs::write(&manifest_path, updated.as_bytes())
+        .map_err(|e| format!("failed to write {}: {e}", manifest_path.display()))?;
+    for entry in entries {
+        eprintln!("quiche: added `{entry}` to {}", manifest_path.display());
+    }
+    Ok(())
+}
+
+/// The `[dependencies]` line for a crate used by generated code: the
+/// `version=` its `@extern` gives, else a pin for the crates generated code
+/// brings in itself. PyO3 (from `@py_import`) is pinned to the API the glue
+/// is written against and starts its own interpreter; wasm-bindgen (from
+/// `@wasm_export`) is published under its dashed name; serde (from
+/// `@derive(Serialize)`) needs its derive macros. `None` for any other crate
+/// without a version.
+fn dependency_entry(krate: &str, version: Option<&str>) -> Option<String> {
+    Some(match (krate, version) {
+        (_, Some(version)) => format!("{krate} = \"{version}\""),
+        ("serde", None) => "serde = { version = \"1\", features = [\"derive\"] }".to_string(),
+        ("pyo3", None) => {
+            "pyo3 = { version = \"0.22\", features = [\"auto-initialize\"] }".to_string()
+        }
+        ("wasm_bindgen", None) => "wasm-bindgen = \"0.2\"".to_string(),
+        _ => return None,
+    })
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// quiche bindgen — .qi interface stubs from rustdoc JSON
+// ─────────────────────────────────────────────────────────────────────────────
+
+fn run_bindgen(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("usage: quiche bindgen <crate|rustdoc.json> [-o <output.qi>]");
+        process::exit(2);
+    }
+    let target = &args[0];
+    let output_path = flag_value(args, "-o").map(PathBuf::from);
+
+    let json_path = if target.ends_with(".json") {
+        PathBuf::from(target)
+    } else {
+        // rustdoc's JSON backend is nightly-only
+        let status = Command::new("cargo")
+            .args(["+nightly", "rustdoc", "-q", "-p", target, "--lib", "--"])
+            .args(["-Z", "unstable-options", "--output-format", "json"])
+            .status()
+            .unwrap_or_else(|e| {
+                eprintln!("Error: failed to run cargo rustdoc: {e}");
+                process::exit(1);
+            });
+        if !status.success() {
+            eprintln!(
+                "Error: cargo rustdoc failed for '{target}' (needs a nightly toolchain and '{target}' as a dependency of the current project)"
+            );
+            process::exit(status.code().unwrap_or(1));
+        }
+        let target_dir = env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| "target".to_string());
+        Path::new(&target_dir)
+            .join("doc")
+            .join(format!("{}.json", target.replace('-', "_")))
+    };
+
+    let json = fs::read_to_string(&json_path).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to read '{}': {}", json_path.display(), e);
+        process::exit(1);
+    });
+    let interface = quiche::bindgen::generate_interface(&json).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {e}", json_path.display());
+        process::exit(1);
+    });
+    match output_path {
+        Some(path) => fs::write(&path, interface).unwrap_or_else(|e| {
+            eprintln!("Error: Failed to write '{}': {}", path.display(), e);
+            process::exit(1);
+        }),
+        None => print!("{interface}"),
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// quiche daemon — keep a compiler running for scripts and builds
+// ─────────────────────────────────────────────────────────────────────────────
+
+fn run_daemon(args: &[String]) {
+    let socket = flag_value(args, "--socket")
+        .map(PathBuf::from)
+        .unwrap_or_else(quiche::daemon::socket_path);
+    let action = args
+        .iter()
+        .enumerate()
+        .find(|(i, arg)| !arg.starts_with('-') && (*i == 0 || args[i - 1] != "--socket"))
+        .map_or("start", |(_, arg)| arg.as_str());
+    let result = match action {
+        "start" => {
+            eprintln!("quiche daemon listening on {}", socket.display());
+            quiche::daemon::serve(&socket)
+        }
+        "stop" => quiche::daemon::stop(&socket),
+        "status" => quiche::daemon::ping(&socket).map(|status| println!("{status}")),
+        other => {
+            eprintln!("Error: unknown daemon action '{other}' (expected start, stop or status)");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// quiche graph — module and call graphs
+// ─────────────────────────────────────────────────────────────────────────────
+
+fn run_graph(args: &[String]) {
+    let format = flag_value(args, "--format")
+        .map(|format| quiche::graph::Format::parse(&format))
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            process::exit(2);
+        })
+        .unwrap_or_default();
+    let src_dir = args
+        .iter()
+        .enumerate()
+        .find(|(i, arg)| !arg.starts_with('-') && (*i == 0 || args[i - 1] != "--format"))
+        .map(|(_, arg)| PathBuf::from(arg))
+        .unwrap_or_else(|| {
+            if Path::new("src").is_dir() {
+                PathBuf::from("src")
+            } else {
+                PathBuf::from(".")
+            }
+        });
+    let graph = quiche::graph::project_modules(&src_dir).and_then(|modules| {
+        if has_flag(args, "--calls") {
+            quiche::graph::call_graph(&modules)
+        } else {
+            quiche::graph::module_graph(&modules)
+        }
+    });
+    match graph {
+        Ok(graph) => print!("{}", graph.render(format)),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// quiche fmt — canonical layout for .q sources
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Format the `.q` files named (directories searched recursively; `src`, or
+/// else the current directory, by default) in place, or with `--check` list
+/// those that would change and fail if any would.
+fn run_fmt(args: &[String]) {
+    let check = has_flag(args, "--check");
+    let mut roots: Vec<PathBuf> = args
+        .iter()
+        .filter(|arg| !arg.starts_with('-'))
+        .map(PathBuf::from)
+        .collect();
+    if roots.is_empty() {
+        roots.push(if Path::new("src").is_dir() {
+            PathBuf::from("src")
+        } else {
+            PathBuf::from(".")
+        });
+    }
+    let mut files = Vec::new();
+    for root in roots {
+        if root.is_dir() {
+            match quiche::build::find_sources(&root, "q") {
+                Ok(found) => files.extend(found.into_iter().map(|file| root.join(file))),
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                }
+            }
+        } else {
+            files.push(root);
+        }
+    }
+
+    let mut failed = false;
+    let mut unformatted = 0;
+    for file in &files {
+        let formatted = fs::read_to_string(file)
+            .map_err(|e| format!("failed to read: {e}"))
+            .and_then(|source| Ok((quiche::fmt::format_source(&source)?, source)));
+        match formatted {
+            Ok((formatted, source)) if formatted != source => {
+                if check {
+                    println!("would reformat {}", file.display());
+                    unformatted += 1;
+                } else if let Err(e) = fs::write(file, formatted) {
+                    eprintln!("Error: {}: failed to write: {e}", file.display());
+                    failed = true;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error: {}: {e}", file.display());
+                failed = true;
+            }
+        }
+    }
+    if unformatted > 0 {
+        eprintln!(
+            "{unformatted} of {} file(s) would be reformatted",
+            files.len()
+        );
+    }
+    if failed || unformatted > 0 {
+        process::exit(1);
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// quiche repl — interactive session
+// ─────────────────────────────────────────────────────────────────────────────
+
+fn run_repl(args: &[String]) {
+    let lib_src = match resolve_quiche_lib_source(flag_value(args, "--lib").as_deref()) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+    };
+    let mut session = quiche::repl::Session::new().with_lib(lib_src);
+    eprintln!("quiche repl: each input recompiles the session (:reset clears it, Ctrl-D quits)");
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+    loop {
+        eprint!("{}", if input.is_empty() { ">>> " } else { "... " });
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                eprintln!();
+                return;
+            }
+            Ok(_) => {}
+        }
+        if input.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":quit" | "exit()" | "quit()" => return,
+                ":reset" => {
+                    session.reset();
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        input.push_str(&line);
+        if quiche::repl::needs_more(&input) {
+            continue;
+        }
+        match session.eval(&input) {
+            Ok(output) => print!("{output}"),
+            Err(e) => eprintln!("{e}"),
+        }
+        input.clear();
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// quiche completions — shell completion scripts
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Subcommands and their flags, as offered by `quiche completions` (which
+/// completes shell names itself).
+const SUBCOMMANDS: &[(&str, &[&str])] = &[
+    ("init", &[]),
+    (
+        "build",
+        &[
+            "-o",
+            "--crate-type",
+            "--lib",
+            "--overflow",
+            "--prelude",
+            "--opt-size",
+            "--allow-unsafe",
+            "--memory-profile",
+            "--daemon",
+        ],
+    ),
+    (
+        "test",
+        &[
+            "--update-snapshots",
+            "--coverage",
+            "--format",
+            "--overflow",
+            "--prelude",
+            "--allow-unsafe",
+        ],
+    ),
+    ("bindgen", &["-o"]),
+    ("graph", &["--calls", "--format"]),
+    ("fmt", &["--check"]),
+    ("repl", &["--lib"]),
+    ("daemon", &["--socket"]),
+];
+
+/// Flags for running a script, besides the `FlagDef` tables.
+const SCRIPT_FLAGS: &[&str] = &[
+    "--emit-rust",
+    "--emit-elevate",
+    "--emit-ast",
+    "--test",
+    "--coverage",
+    "--hook",
+    "--lib",
+    "--hot",
+    "--help",
+];
+
+fn run_completions(args: &[String]) {
+    let shell = args.first().map(String::as_str).unwrap_or("");
+    match completion_script(shell) {
+        Some(script) => print!("{script}"),
+        None => {
+            eprintln!("usage: quiche completions <bash|zsh|fish>");
+            process::exit(2);
+        }
+    }
+}
+
+/// Every flag accepted when running a script.
+fn script_flags() -> Vec<&'static str> {
+    let defs = DEFAULT_EXPERIMENTS
+        .iter()
+        .chain(OPTIN_EXPERIMENTS)
+        .chain(COMPILER_OPTIONS);
+    SCRIPT_FLAGS
+        .iter()
+        .copied()
+        .chain(defs.map(|def| def.flag))
+        .collect()
+}
+
+fn completion_script(shell: &str) -> Option<String> {
+    let commands: Vec<&str> = SUBCOMMANDS
+        .iter()
+        .map(|(name, _)| *name)
+        .chain(["completions"])
+        .collect();
+    let flags = script_flags();
+    let script = match shell {
+        "bash" => {
+            let mut cases = String::new();
+            for (name, flags) in SUBCOMMANDS {
+                cases.push_str(&format!(
+                    "        {name}) opts=\"{}\" ;;\n",
+                    flags.join(" ")
+                ));
+            }
+            format!(
+                "# bash completion for quiche\n\
+                 _quiche() {{\n    \
+                 local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" opts\n    \
+                 if [[ $COMP_CWORD -eq 1 ]]; then\n        \
+                 COMPREPLY=($(compgen -W \"{commands}\" -- \"$cur\") $(compgen -f -X \"!*.q\" -- \"$cur\"))\n        \
+                 return\n    \
+                 fi\n    \
+                 case \"${{COMP_WORDS[1]}}\" in\n{cases}        \
+                 completions) COMPREPLY=($(compgen -W \"bash zsh fish\" -- \"$cur\")); return ;;\n        \
+                 *) opts=\"{flags}\" ;;\n    \
+                 esac\n    \
+                 if [[ $cur == -* ]]; then\n        \
+                 COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n    \
+                 else\n        \
+                 COMPREPLY=($(compgen -f -- \"$cur\"))\n    \
+                 fi\n\
+                 }}\n\
+                 complete -o filenames -F _quiche quiche\n",
+                commands = commands.join(" "),
+                flags = flags.join(" "),
+            )
+        }
+        "zsh" => {
+            let mut cases = String::new();
+            for (name, flags) in SUBCOMMANDS {
+                let specs: String = flags.iter().map(|f| format!(" '{f}'")).collect();
+                cases.push_str(&format!(
+                    "        {name}) _arguments{specs} '*:file:_files' ;;\n"
+                ));
+            }
+            let specs: String = flags.iter().map(|f| format!(" '{f}'")).collect();
+            format!(
+                "#compdef quiche\n\
+                 # zsh completion for quiche\n\
+                 if (( CURRENT == 2 )); then\n    \
+                 _alternative 'commands:command:({commands})' 'files:script:_files -g \"*.q\"'\n    \
+                 return\n\
+                 fi\n\
+                 case $words[2] in\n{cases}    \
+                 completions) _values shell bash zsh fish ;;\n    \
+                 *) _arguments{specs} '*:file:_files' ;;\n\
+                 esac\n",
+                commands = commands.join(" "),
+                cases = cases.replace("        ", "    "),
+            )
+        }
+        "fish" => {
+            let mut out = String::from(
+                "# fish completion for quiche\n\
+                 complete -c quiche -f -n __fish_use_subcommand -a '(__fish_complete_suffix .q)'\n",
+            );
+            out.push_str(&format!(
+                "complete -c quiche -f -n __fish_use_subcommand -a '{}'\n",
+                commands.join(" ")
+            ));
+            for (name, flags) in SUBCOMMANDS {
+                for flag in *flags {
+                    out.push_str(&fish_flag(
+                        &format!("__fish_seen_subcommand_from {name}"),
+                        flag,
+                    ));
+                }
+            }
+            out.push_str(
+                "complete -c quiche -f -n '__fish_seen_subcommand_from completions' -a 'bash zsh fish'\n",
+            );
+            let not_subcommand = format!("not __fish_seen_subcommand_from {}", commands.join(" "));
+            for flag in &flags {
+                out.push_str(&fish_flag(&not_subcommand, flag));
+            }
+            out
+        }
+        _ => return None,
+    };
+    Some(script)
+}
+
+/// A fish `complete` line offering `flag` when `condition` holds.
+fn fish_flag(condition: &str, flag: &str) -> String {
+    let option = match flag.strip_prefix("--") {
+        Some(long) => format!("-l {long}"),
+        None => format!("-s {}", flag.trim_start_matches('-')),
+    };
+    format!("complete -c quiche -n '{condition}' {option}\n")
+}
+
 // ─────────────────────────────────────────────────────────────────────────────
 // quiche init — scaffolds a Quiche crate (modeled on Elevate's init)
 // ─────────────────────────────────────────────────────────────────────────────
@@ -503,6 +1628,15 @@ fn run_init(args: &[String]) {
     println!("initialized quiche crate at {}", crate_root.display());
 }
 
+/// The `Cargo.toml` line adding quiche-lib: the local `lib_dir` by path if
+/// given, else the release matching this compiler.
+fn quiche_lib_dependency(lib_dir: Option<PathBuf>) -> String {
+    match lib_dir {
+        Some(dir) => format!("quiche-lib = {{ path = {:?} }}", dir.display().to_string()),
+        None => format!("quiche-lib = \"{}\"", quiche::vendor::QUICHE_LIB_VERSION),
+    }
+}
+
 fn apply_quiche_templates(crate_root: &Path) -> Result<(), String> {
     let src_dir = crate_root.join("src");
     fs::create_dir_all(&src_dir)
@@ -531,18 +1665,17 @@ fn apply_quiche_templates(crate_root: &Path) -> Result<(), String> {
     )
     .map_err(|e| format!("failed to write main.rs: {e}"))?;
 
-    // Add quiche-lib dependency to Cargo.toml
+    // Add quiche-lib dependency to Cargo.toml: the published crate, or this
+    // workspace's lib/ when initializing inside a Quiche checkout
     let manifest_path = crate_root.join("Cargo.toml");
     let manifest = fs::read_to_string(&manifest_path)
         .map_err(|e| format!("failed to read {}: {e}", manifest_path.display()))?;
     if !manifest.contains("quiche-lib") {
+        let dependency = quiche_lib_dependency(find_workspace_root().map(|root| root.join("lib")));
         let updated = if manifest.contains("[dependencies]") {
-            manifest.replace(
-                "[dependencies]",
-                "[dependencies]\nquiche-lib = { path = \"../lib\" }",
-            )
+            manifest.replace("[dependencies]", &format!("[dependencies]\n{dependency}"))
         } else {
-            format!("{manifest}\n[dependencies]\nquiche-lib = {{ path = \"../lib\" }}\n")
+            format!("{manifest}\n[dependencies]\n{dependency}\n")
         };
         fs::write(&manifest_path, updated.as_bytes())
             .map_err(|e| format!("failed to write {}: {e}", manifest_path.display()))?;
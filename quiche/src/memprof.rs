@@ -0,0 +1,232 @@
+//! `--memory-profile`: the compiler's own memory use, per pass.
+//!
+//! The compile of one file is split into passes — parsing, numeric
+//! coercion, overflow lowering, Elevate's codegen and the Rust rewrites —
+//! each wrapped in [`pass`]. Once [`enable`]d, every pass records how many
+//! allocations it made, how many bytes they came to, and how far live heap
+//! rose above where the pass started; [`report`] renders them with the
+//! process's peak RSS:
+//!
+//! ```text
+//! memory profile:
+//!   pass              allocs       bytes   peak live
+//!   parse             12,408     1.9 MiB   412.0 KiB
+//!   elevate           88,115    14.2 MiB     3.1 MiB
+//!   peak RSS: 38.4 MiB
+//! ```
+//!
+//! Allocations are counted by [`CountingAllocator`], installed as the global
+//! allocator only in builds with the `memory-profile` feature, so normal
+//! builds pay nothing for it; without the feature the report has the peak
+//! RSS alone.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK_LIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting allocations and live bytes.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn record(size: usize) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_LIVE.fetch_max(live, Ordering::Relaxed);
+    }
+}
+
+// SAFETY: every call is forwarded unchanged to `System`; only the counters
+// are added.
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::record(layout.size());
+        // SAFETY: the caller upholds `alloc`'s contract, passed on as is.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::record(layout.size());
+        // SAFETY: as for `alloc`.
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        // SAFETY: as for `alloc`.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        Self::record(new_size);
+        // SAFETY: as for `alloc`.
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[cfg(feature = "memory-profile")]
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// What one pass allocated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStats {
+    pub name: &'static str,
+    pub allocations: usize,
+    pub bytes: usize,
+    /// How far live heap rose above its level when the pass started.
+    pub peak_live: usize,
+}
+
+thread_local! {
+    /// The passes recorded on this thread, while profiling is enabled.
+    static PASSES: RefCell<Option<Vec<PassStats>>> = const { RefCell::new(None) };
+}
+
+/// Whether allocations are counted in this build.
+pub const COUNTING: bool = cfg!(feature = "memory-profile");
+
+/// Start recording the passes compiled on this thread.
+pub fn enable() {
+    PASSES.with(|passes| *passes.borrow_mut() = Some(Vec::new()));
+}
+
+/// Run `run` as the pass `name`, recording its allocations when profiling
+/// is enabled.
+pub fn pass<T>(name: &'static str, run: impl FnOnce() -> T) -> T {
+    if PASSES.with(|passes| passes.borrow().is_none()) {
+        return run();
+    }
+    let (allocations, bytes) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED.load(Ordering::Relaxed),
+    );
+    let live = LIVE.load(Ordering::Relaxed);
+    PEAK_LIVE.store(live, Ordering::Relaxed);
+    let result = run();
+    let stats = PassStats {
+        name,
+        allocations: ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        bytes: ALLOCATED.load(Ordering::Relaxed) - bytes,
+        peak_live: PEAK_LIVE.load(Ordering::Relaxed).saturating_sub(live),
+    };
+    PASSES.with(|passes| {
+        if let Some(passes) = passes.borrow_mut().as_mut() {
+            passes.push(stats);
+        }
+    });
+    result
+}
+
+/// The passes recorded since [`enable`], which stops recording.
+pub fn take() -> Vec<PassStats> {
+    PASSES
+        .with(|passes| passes.borrow_mut().take())
+        .unwrap_or_default()
+}
+
+/// The recorded passes and peak RSS as a table; stops recording.
+pub fn report() -> String {
+    let passes = take();
+    let mut out = String::from("memory profile:\n");
+    if COUNTING {
+        out.push_str(&format!(
+            "  {:<12}{:>12}{:>12}{:>12}\n",
+            "pass", "allocs", "bytes", "peak live"
+        ));
+        for pass in &passes {
+            out.push_str(&format!(
+                "  {:<12}{:>12}{:>12}{:>12}\n",
+                pass.name,
+                thousands(pass.allocations),
+                bytes(pass.bytes),
+                bytes(pass.peak_live)
+            ));
+        }
+    } else {
+        out.push_str("  allocation counts need a quiche built with `--features memory-profile`\n");
+    }
+    match peak_rss() {
+        Some(rss) => out.push_str(&format!("  peak RSS: {}\n", bytes(rss))),
+        None => out.push_str("  peak RSS: not available on this platform\n"),
+    }
+    out
+}
+
+/// The process's peak resident set size, from `/proc/self/status`.
+fn peak_rss() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// `12345` → `12,345`.
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// `1536` → `1.5 KiB`.
+fn bytes(n: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{n} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes_are_recorded_once_enabled() {
+        pass("ignored", || ());
+        enable();
+        let kept = pass("build", || vec![0u8; 4096]);
+        assert_eq!(kept.len(), 4096);
+        let passes = take();
+        assert_eq!(passes.len(), 1);
+        assert_eq!(passes[0].name, "build");
+        if COUNTING {
+            assert!(passes[0].allocations >= 1);
+            assert!(passes[0].bytes >= 4096);
+        }
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn test_report_formatting() {
+        assert_eq!(thousands(1234567), "1,234,567");
+        assert_eq!(thousands(12), "12");
+        assert_eq!(bytes(512), "512 B");
+        assert_eq!(bytes(1536), "1.5 KiB");
+        assert_eq!(bytes(3 * 1024 * 1024), "3.0 MiB");
+        enable();
+        let report = report();
+        assert!(report.starts_with("memory profile:\n"), "{report}");
+        assert!(report.contains("peak RSS: "), "{report}");
+    }
+}
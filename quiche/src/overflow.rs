@@ -0,0 +1,506 @@
+//! Integer overflow modes: `--overflow=checked|wrapping|saturating`.
+//!
+//! By default `+ - *` are Rust's operators, which panic on overflow in
+//! debug builds and silently wrap in release builds. With a mode, [`lower`]
+//! rewrites an integer `a + b` (and `a += b`) into a call to quiche-lib's
+//! `OverflowAdd::checked(a, b)` (or `wrapping` / `saturating`), and likewise
+//! `-`, `*` and negation, so the behaviour is the same in every build
+//! profile.
+//!
+//! The AST carries no inferred types, so an operation counts as integer
+//! only when an operand is known to be one — a parameter, local, `self`
+//! field or function result declared with an integer type, a cast to one,
+//! or an integer operation — and the other is too or is a literal. Floats,
+//! `Str`, newtypes and user operator types keep their own operators, as do
+//! operations on two literals.
+//!
+//! `quiche` takes the mode from `--overflow`; builds going through
+//! [`crate::compile_file`] (such as `build.rs` and `quiche test`) read it
+//! from the `QUICHE_OVERFLOW` environment variable.
+
+use std::collections::{HashMap, HashSet};
+
+use elevate::ast as e;
+
+/// Environment variable carrying the overflow mode to `compile_file`.
+pub const OVERFLOW_ENV: &str = "QUICHE_OVERFLOW";
+
+/// How integer `+ - *` behave on overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Rust's operators, left as they are.
+    #[default]
+    Native,
+    /// Panic with an `OverflowError`.
+    Checked,
+    /// Wrap around at the type's bounds.
+    Wrapping,
+    /// Clamp to the type's bounds.
+    Saturating,
+}
+
+impl Overflow {
+    pub fn parse(mode: &str) -> Result<Overflow, String> {
+        match mode {
+            "checked" => Ok(Overflow::Checked),
+            "wrapping" => Ok(Overflow::Wrapping),
+            "saturating" => Ok(Overflow::Saturating),
+            other => Err(format!(
+                "unknown overflow mode '{other}' (expected checked, wrapping or saturating)"
+            )),
+        }
+    }
+
+    /// The mode named by [`OVERFLOW_ENV`], or `Native` when it is unset.
+    pub fn from_env() -> Result<Overflow, String> {
+        match std::env::var(OVERFLOW_ENV) {
+            Ok(mode) => Overflow::parse(&mode),
+            Err(_) => Ok(Overflow::Native),
+        }
+    }
+
+    fn method(self) -> Option<&'static str> {
+        match self {
+            Overflow::Native => None,
+            Overflow::Checked => Some("checked"),
+            Overflow::Wrapping => Some("wrapping"),
+            Overflow::Saturating => Some("saturating"),
+        }
+    }
+}
+
+/// Rewrite the integer arithmetic in every function of `module` for `mode`.
+pub fn lower(module: &mut e::Module, mode: Overflow) {
+    let Some(method) = mode.method() else {
+        return;
+    };
+    let int_fns = int_functions(module);
+    let int_fields: HashMap<String, HashSet<String>> = module
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            e::Item::Struct(def) => Some((
+                def.name.clone(),
+                def.fields
+                    .iter()
+                    .filter(|field| is_int_type(&field.ty))
+                    .map(|field| field.name.clone())
+                    .collect(),
+            )),
+            _ => None,
+        })
+        .collect();
+    let no_fields = HashSet::new();
+    for item in &mut module.items {
+        match item {
+            e::Item::Function(func) => {
+                Scope::new(method, &int_fns, &no_fields).lower_function(func);
+            }
+            e::Item::Impl(block) => {
+                let fields = int_fields.get(&block.target).unwrap_or(&no_fields);
+                for func in &mut block.methods {
+                    Scope::new(method, &int_fns, fields).lower_function(func);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The functions and methods (`Type::method`) of `module` returning an
+/// integer type.
+fn int_functions(module: &e::Module) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for item in &module.items {
+        match item {
+            e::Item::Function(func) if func.return_type.as_ref().is_some_and(is_int_type) => {
+                names.insert(func.name.clone());
+            }
+            e::Item::Impl(block) => {
+                for func in &block.methods {
+                    if func.return_type.as_ref().is_some_and(is_int_type) {
+                        names.insert(format!("{}::{}", block.target, func.name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+fn is_int_type(ty: &e::Type) -> bool {
+    const INTS: &[&str] = &[
+        "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+    ];
+    ty.args.is_empty() && ty.path.len() == 1 && INTS.contains(&ty.path[0].as_str())
+}
+
+/// An integer literal, which takes the type of the operand beside it.
+fn is_literal(expr: &e::Expr) -> bool {
+    match expr {
+        e::Expr::Int(_) => true,
+        e::Expr::Unary {
+            op: e::UnaryOp::Neg,
+            expr,
+        } => matches!(**expr, e::Expr::Int(_)),
+        _ => false,
+    }
+}
+
+/// What the rewrite knows of one function: which names hold integers.
+/// Only operators with an operand known to be an integer are rewritten, so
+/// floats, strings, newtypes and user types keep their own operators.
+struct Scope<'a> {
+    method: &'a str,
+    int_fns: &'a HashSet<String>,
+    /// Integer fields of `self`'s type
+    self_fields: &'a HashSet<String>,
+    /// Parameters and locals known to hold integers
+    ints: HashSet<String>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(
+        method: &'a str,
+        int_fns: &'a HashSet<String>,
+        self_fields: &'a HashSet<String>,
+    ) -> Self {
+        Scope {
+            method,
+            int_fns,
+            self_fields,
+            ints: HashSet::new(),
+        }
+    }
+
+    fn lower_function(mut self, func: &mut e::FunctionDef) {
+        for param in &func.params {
+            if is_int_type(&param.ty) {
+                self.ints.insert(param.name.clone());
+            }
+        }
+        self.lower_block(&mut func.body);
+    }
+
+    /// Whether `expr` is known to be an integer.
+    fn is_int(&self, expr: &e::Expr) -> bool {
+        match expr {
+            e::Expr::Path(path) => path.len() == 1 && self.ints.contains(&path[0]),
+            e::Expr::Field { base, field } => {
+                matches!(&**base, e::Expr::Path(path) if path == &["self"])
+                    && self.self_fields.contains(field)
+            }
+            e::Expr::Call { callee, .. } => match &**callee {
+                e::Expr::Path(path) if path.len() == 1 => self.int_fns.contains(&path[0]),
+                e::Expr::Path(path) if path.len() == 2 => {
+                    OPERATORS.contains(&path[0].as_str()) || self.int_fns.contains(&path.join("::"))
+                }
+                _ => false,
+            },
+            e::Expr::Cast { target_type, .. } => is_int_type(target_type),
+            e::Expr::Unary {
+                op: e::UnaryOp::Neg,
+                expr,
+            } => self.is_int(expr),
+            e::Expr::Binary {
+                op:
+                    e::BinaryOp::Add
+                    | e::BinaryOp::Sub
+                    | e::BinaryOp::Mul
+                    | e::BinaryOp::Div
+                    | e::BinaryOp::Rem,
+                left,
+                right,
+            } => self.is_int_pair(left, right),
+            _ => false,
+        }
+    }
+
+    /// Whether the operands of a binary operator are integers: one is known
+    /// to be, and the other is too or is a literal.
+    fn is_int_pair(&self, left: &e::Expr, right: &e::Expr) -> bool {
+        (self.is_int(left) && (self.is_int(right) || is_literal(right)))
+            || (is_literal(left) && self.is_int(right))
+    }
+
+    /// Record what assigning `value` to `name` says of its type.
+    fn assign(&mut self, name: &str, value: &e::Expr) {
+        if self.is_int(value) || is_literal(value) {
+            self.ints.insert(name.to_string());
+        } else {
+            self.ints.remove(name);
+        }
+    }
+
+    fn lower_block(&mut self, block: &mut e::Block) {
+        for stmt in &mut block.statements {
+            match stmt {
+                e::Stmt::Const(def) => {
+                    self.lower_expr(&mut def.value);
+                    if def.ty.as_ref().is_some_and(is_int_type) {
+                        self.ints.insert(def.name.clone());
+                    } else {
+                        self.assign(&def.name, &def.value);
+                    }
+                }
+                e::Stmt::DestructureConst { value, .. } => self.lower_expr(value),
+                e::Stmt::Assign { target, op, value } => {
+                    self.lower_expr(value);
+                    let current = match target {
+                        e::AssignTarget::Path(name) => Some(e::Expr::Path(vec![name.clone()])),
+                        e::AssignTarget::Field { base, field } => Some(e::Expr::Field {
+                            base: base.clone(),
+                            field: field.clone(),
+                        }),
+                        e::AssignTarget::Index { base, index } => Some(e::Expr::Index {
+                            base: base.clone(),
+                            index: index.clone(),
+                        }),
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    };
+                    if *op == e::AssignOp::AddAssign
+                        && let Some(current) = current
+                        && self.is_int_pair(&current, value)
+                    {
+                        let sum = std::mem::replace(value, e::Expr::Tuple(vec![]));
+                        *value = call("OverflowAdd", self.method, vec![current, sum]);
+                        *op = e::AssignOp::Assign;
+                    } else if *op == e::AssignOp::Assign
+                        && let e::AssignTarget::Path(name) = target
+                    {
+                        self.assign(name, value);
+                    }
+                }
+                e::Stmt::Return(Some(value)) | e::Stmt::Expr(value) | e::Stmt::TailExpr(value) => {
+                    self.lower_expr(value)
+                }
+                e::Stmt::If {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    self.lower_expr(condition);
+                    self.lower_block(then_block);
+                    if let Some(block) = else_block {
+                        self.lower_block(block);
+                    }
+                }
+                e::Stmt::While { condition, body } => {
+                    self.lower_expr(condition);
+                    self.lower_block(body);
+                }
+                e::Stmt::For {
+                    binding,
+                    iter,
+                    body,
+                } => {
+                    self.lower_expr(iter);
+                    if let (e::DestructurePattern::Name(name), e::Expr::Range { start, end, .. }) =
+                        (&*binding, &*iter)
+                    {
+                        let int_bound = |bound: &Option<Box<e::Expr>>| {
+                            bound
+                                .as_deref()
+                                .is_none_or(|b| self.is_int(b) || is_literal(b))
+                        };
+                        if int_bound(start) && int_bound(end) {
+                            self.ints.insert(name.clone());
+                        } else {
+                            self.ints.remove(name);
+                        }
+                    }
+                    self.lower_block(body);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn lower_expr(&mut self, expr: &mut e::Expr) {
+        match expr {
+            e::Expr::Binary { op, left, right } => {
+                self.lower_expr(left);
+                self.lower_expr(right);
+                let name = match op {
+                    e::BinaryOp::Add => "OverflowAdd",
+                    e::BinaryOp::Sub => "OverflowSub",
+                    e::BinaryOp::Mul => "OverflowMul",
+                    _ => return,
+                };
+                if !self.is_int_pair(left, right) {
+                    return;
+                }
+                let (left, right) = (
+                    std::mem::replace(&mut **left, e::Expr::Tuple(vec![])),
+                    std::mem::replace(&mut **right, e::Expr::Tuple(vec![])),
+                );
+                *expr = call(name, self.method, vec![left, right]);
+            }
+            e::Expr::Unary {
+                op: e::UnaryOp::Neg,
+                expr: operand,
+            } => {
+                self.lower_expr(operand);
+                if self.is_int(operand) {
+                    let operand = std::mem::replace(&mut **operand, e::Expr::Tuple(vec![]));
+                    *expr = call("OverflowNeg", self.method, vec![operand]);
+                }
+            }
+            e::Expr::Call { callee, args } => {
+                self.lower_expr(callee);
+                args.iter_mut().for_each(|arg| self.lower_expr(arg));
+            }
+            e::Expr::MacroCall { args, .. } | e::Expr::Array(args) | e::Expr::Tuple(args) => {
+                args.iter_mut().for_each(|arg| self.lower_expr(arg));
+            }
+            e::Expr::Field { base, .. } => self.lower_expr(base),
+            e::Expr::Index { base, index } => {
+                self.lower_expr(base);
+                self.lower_expr(index);
+            }
+            e::Expr::Unary { expr, .. } | e::Expr::Cast { expr, .. } => self.lower_expr(expr),
+            e::Expr::Range { start, end, .. } => {
+                for bound in [start, end].into_iter().flatten() {
+                    self.lower_expr(bound);
+                }
+            }
+            e::Expr::StructLiteral { fields, .. } => {
+                for field in fields {
+                    self.lower_expr(&mut field.value);
+                }
+            }
+            e::Expr::Closure { params, body, .. } => {
+                let mut inner = Scope {
+                    method: self.method,
+                    int_fns: self.int_fns,
+                    self_fields: self.self_fields,
+                    ints: self.ints.clone(),
+                };
+                for param in params.iter() {
+                    if is_int_type(&param.ty) {
+                        inner.ints.insert(param.name.clone());
+                    } else {
+                        inner.ints.remove(&param.name);
+                    }
+                }
+                inner.lower_block(body);
+            }
+            e::Expr::Match { scrutinee, arms } => {
+                self.lower_expr(scrutinee);
+                for arm in arms {
+                    self.lower_expr(&mut arm.value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The quiche-lib traits the rewrite calls, all on integers.
+const OPERATORS: &[&str] = &["OverflowAdd", "OverflowSub", "OverflowMul", "OverflowNeg"];
+
+/// `Trait::method(args...)`.
+fn call(name: &str, method: &str, args: Vec<e::Expr>) -> e::Expr {
+    e::Expr::Call {
+        callee: Box::new(e::Expr::Path(vec![name.to_string(), method.to_string()])),
+        args,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lower_rewrites_integer_operators() {
+        let mut module = crate::parser::parse(
+            "def main(a: i64, b: i64):\n    c = a * b - 1\n    c += 2\n    d = 1 + 2\n",
+        )
+        .unwrap();
+        lower(&mut module, Overflow::Saturating);
+        let e::Item::Function(func) = &module.items[0] else {
+            panic!("expected main");
+        };
+        let callee = |expr: &e::Expr| match expr {
+            e::Expr::Call { callee, args } => match &**callee {
+                e::Expr::Path(path) => (path.join("::"), args.clone()),
+                _ => (String::new(), vec![]),
+            },
+            _ => (String::new(), vec![]),
+        };
+        let values: Vec<&e::Expr> = func
+            .body
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                e::Stmt::Assign { value, op, .. } => {
+                    assert_eq!(*op, e::AssignOp::Assign);
+                    Some(value)
+                }
+                _ => None,
+            })
+            .collect();
+        let (outer, args) = callee(values[0]);
+        assert_eq!(outer, "OverflowSub::saturating");
+        assert_eq!(callee(&args[0]).0, "OverflowMul::saturating");
+        assert_eq!(callee(values[1]).0, "OverflowAdd::saturating");
+        assert!(matches!(values[2], e::Expr::Binary { .. }));
+
+        assert!(Overflow::parse("clamp").is_err());
+    }
+
+    #[test]
+    fn test_lower_leaves_non_integer_operators_alone() {
+        let mut module = crate::parser::parse(
+            "type Meters = new f64\n\n\
+             def scale(a: Meters, b: Meters) -> Meters:\n    return a + b * 2.0\n\n\
+             def shout(s: Str) -> Str:\n    return s + \"!\"\n\n\
+             def count(n: i64) -> i64:\n    x = n\n    x -= 1\n    x *= 2\n    return -x\n",
+        )
+        .unwrap();
+        lower(&mut module, Overflow::Checked);
+        let returned = |name: &str| {
+            let func = module
+                .items
+                .iter()
+                .find_map(|item| match item {
+                    e::Item::Function(func) if func.name == name => Some(func),
+                    _ => None,
+                })
+                .unwrap();
+            let assigned: Vec<String> = func
+                .body
+                .statements
+                .iter()
+                .filter_map(|stmt| match stmt {
+                    e::Stmt::Assign { value, .. } => Some(format!("{value:?}")),
+                    _ => None,
+                })
+                .collect();
+            match func.body.statements.last() {
+                Some(e::Stmt::Return(Some(value))) => (format!("{value:?}"), assigned),
+                other => panic!("expected a return, got {other:?}"),
+            }
+        };
+        let (scaled, _) = returned("scale");
+        assert!(!scaled.contains("Overflow"), "{scaled}");
+        let (shouted, _) = returned("shout");
+        assert!(!shouted.contains("Overflow"), "{shouted}");
+        let (negated, assigned) = returned("count");
+        assert!(
+            negated.contains("\"OverflowNeg\", \"checked\""),
+            "{negated}"
+        );
+        assert!(
+            assigned[1].contains("\"OverflowSub\", \"checked\""),
+            "{assigned:?}"
+        );
+        assert!(
+            assigned[2].contains("\"OverflowMul\", \"checked\""),
+            "{assigned:?}"
+        );
+    }
+}
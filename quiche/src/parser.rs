@@ -4,10 +4,13 @@
 //! lexer and produces `elevate::ast::Module` with zero intermediate AST.
 #![allow(clippy::unwrap_used)]
 
+use crate::format::{self, FieldName, Piece};
 use crate::lexer::{Keyword, LexError, Lexer, Token, TokenKind};
+use crate::qmeta::ModuleMeta;
+use crate::unsupported::{self, Unsupported};
 use elevate::ast as e;
 use elevate::diag::Span;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
 // ─────────────────────────────────────────────────────────────────────────────
 // Parser Error
@@ -52,14 +55,141 @@ enum CallArg {
     Keyword(String, e::Expr),
 }
 
+/// A decorator on a top-level `def` or `type`: `@name` or `@name(args)`.
+#[derive(Debug, Clone)]
+pub struct Decorator {
+    pub name: String,
+    pub args: Vec<e::Expr>,
+    pub kwargs: Vec<(String, e::Expr)>,
+}
+
+/// A class-level attribute in a type body: `PI: f64 = 3.14159`, or
+/// `mut count: i64 = 0` for mutable class state.
+struct ClassAttr {
+    name: String,
+    ty: e::Type,
+    value: e::Expr,
+    mutable: bool,
+    span: Option<Span>,
+}
+
+/// A `def`'s parameter list.
+struct Signature {
+    params: Vec<e::Param>,
+    /// Parameter names other than `self`, with a `*` where keyword-only
+    /// parameters start; `*args` and `**kwargs` keep their stars
+    names: Vec<String>,
+    /// Default values, as source text, by parameter name
+    defaults: Vec<(String, String)>,
+}
+
+/// The fields, methods and class attributes of a `type` body.
+type TypeBody = (Vec<e::Field>, Vec<e::FunctionDef>, Vec<ClassAttr>);
+
+impl Decorator {
+    /// Look up a keyword argument by name.
+    pub fn kwarg(&self, name: &str) -> Option<&e::Expr> {
+        self.kwargs.iter().find(|(k, _)| k == name).map(|(_, v)| v)
+    }
+}
+
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
+    source: &'a str,
     current: Token,
     peeked: Option<Token>,
+    /// Byte offset just past the last consumed token, layout tokens
+    /// (newlines, indentation, comments) aside
+    prev_end: usize,
     /// Maps struct names to their ordered field names (for positional construction)
     struct_fields: HashMap<String, Vec<String>>,
-    /// Maps function names to their ordered parameter names (for kwarg reordering)
+    /// Class attributes by `(class, attribute)`: their type and whether they
+    /// are mutable class state, for rewriting `Circle.PI` and `Circle.count`
+    class_attrs: HashMap<(String, String), (e::Type, bool)>,
+    /// Maps function names (and `Type::method`) to their ordered parameter
+    /// names, with a `*` where keyword-only ones start and `*args` /
+    /// `**kwargs` starred (for kwarg reordering and argument packing)
     fn_params: HashMap<String, Vec<String>>,
+    /// Default parameter values by `(function, parameter)`, as source text
+    /// parsed again at each call that omits them
+    fn_defaults: HashMap<(String, String), String>,
+    /// `@trait` types by name, with their method signatures
+    traits: HashMap<String, Vec<e::FunctionDef>>,
+    /// Field types holding a trait object, keyed by `(type, field)`, for
+    /// boxing the values a constructor is given
+    boxed_fields: HashMap<(String, String), e::Type>,
+    /// Maps decorated item names to their decorators (in source order)
+    decorators: HashMap<String, Vec<Decorator>>,
+    /// The module's `import` and `from ... import` statements, in order
+    imports: Vec<Import>,
+    /// True while parsing the body of an `@fixture` function (enables `yield`)
+    in_fixture: bool,
+    /// Insert a `Qtest.hit(line)` coverage probe before every statement
+    coverage: bool,
+    /// Probed lines not yet claimed by an enclosing function
+    probes: Vec<usize>,
+    /// Maps function names to the lines probed inside them
+    fn_probes: HashMap<String, Vec<usize>>,
+    /// Maps `@extern` function names to their parameter count
+    externs: HashMap<String, usize>,
+    /// Crates referenced by `@extern` declarations, plus `pyo3` for
+    /// `@py_import` and `wasm_bindgen` for `@wasm_export`
+    extern_crates: BTreeSet<String>,
+    /// The `version=` each `@extern` crate was declared with
+    crate_versions: BTreeMap<String, String>,
+    /// `std::collections` types named without their path, for the module's
+    /// `use` (see [`collections_use`])
+    collections: BTreeSet<&'static str>,
+    /// Whether `quiche_free_string` has been emitted for an `@export`
+    /// returning `Str`
+    exports_strings: bool,
+    /// Whether the `__quiche_py` glue module has been emitted for a
+    /// `@py_import`
+    py_glue: bool,
+    /// Classes exported with `@wasm_export`, usable in exported signatures
+    wasm_classes: HashSet<String>,
+    /// Maps top-level function names to their `help()` text and docstring
+    docs: HashMap<String, (String, String)>,
+    /// Functions whose docs are read by `help(f)` or `f.__doc__`
+    doc_requests: BTreeSet<String>,
+    /// Maps functions returning `A | B` to their synthesized result enum
+    union_returns: HashMap<String, String>,
+    /// Maps synthesized result enums to their member types
+    union_enums: HashMap<String, Vec<e::Type>>,
+    /// Result enums (and their `From` impls) not yet placed in the module
+    pending_unions: Vec<e::Item>,
+    /// Explicit discriminants (`Ok = 0`) of the variants being parsed
+    discriminants: Vec<(String, i64)>,
+    /// Field types of named-field enum variants, keyed by `(enum, variant)`
+    variant_fields: HashMap<(String, String), Vec<e::Field>>,
+    /// Top-level statements, in order, for the implicit `main()`
+    top_level: Vec<e::Stmt>,
+    /// Byte offsets of the first top-level statement's start and the last
+    /// one's end
+    top_level_start: Option<usize>,
+    top_level_end: usize,
+    /// Span of each name's first plain assignment in the function being
+    /// parsed, for the declarations `infer_empty_collections` makes of them
+    first_assigns: HashMap<String, Span>,
+    /// `field=binding` pairs of the keyword variant pattern just parsed
+    /// (`case Shape.Rect(width=w)`), for `parse_match` to bind
+    field_bindings: Vec<(String, String)>,
+    /// Enums with explicit discriminants, constructible as `Status(404)`
+    discriminant_enums: HashSet<String>,
+    /// Enums whose variants all carry no data, readable from a string
+    unit_enums: HashSet<String>,
+    /// `(line, column)` of every `unsafe:` block
+    unsafe_blocks: Vec<(usize, usize)>,
+    /// Whether `raise` returns its exception as an `Err` (in a function
+    /// returning `Result`, or a `try` body) rather than ending the program
+    raise_into_result: bool,
+    /// `try` statements parsed so far, numbering their temporaries
+    try_count: usize,
+    /// The caught exception of each enclosing `except` block, innermost
+    /// last, for a bare `raise`
+    handling: Vec<String>,
+    /// `with` managers bound to no name so far, numbering their guards
+    with_count: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -72,10 +202,48 @@ impl<'a> Parser<'a> {
         let current = lexer.next_token()?;
         Ok(Parser {
             lexer,
+            source,
             current: current,
             peeked: None,
+            prev_end: 0,
             struct_fields: HashMap::new(),
+            class_attrs: HashMap::new(),
             fn_params: HashMap::new(),
+            fn_defaults: HashMap::new(),
+            traits: HashMap::new(),
+            boxed_fields: HashMap::new(),
+            decorators: HashMap::new(),
+            imports: Vec::new(),
+            in_fixture: false,
+            coverage: false,
+            probes: Vec::new(),
+            fn_probes: HashMap::new(),
+            externs: HashMap::new(),
+            extern_crates: BTreeSet::new(),
+            crate_versions: BTreeMap::new(),
+            collections: BTreeSet::new(),
+            exports_strings: false,
+            py_glue: false,
+            wasm_classes: HashSet::new(),
+            docs: HashMap::new(),
+            doc_requests: BTreeSet::new(),
+            union_returns: HashMap::new(),
+            union_enums: HashMap::new(),
+            pending_unions: Vec::new(),
+            discriminants: Vec::new(),
+            variant_fields: HashMap::new(),
+            field_bindings: Vec::new(),
+            top_level: Vec::new(),
+            top_level_start: None,
+            top_level_end: 0,
+            first_assigns: HashMap::new(),
+            discriminant_enums: HashSet::new(),
+            unit_enums: HashSet::new(),
+            unsafe_blocks: Vec::new(),
+            raise_into_result: false,
+            try_count: 0,
+            handling: Vec::new(),
+            with_count: 0,
         })
     }
 
@@ -100,6 +268,16 @@ impl<'a> Parser<'a> {
                 self.lexer.next_token()?
             },
         );
+        if !matches!(
+            cur.kind,
+            TokenKind::Newline
+                | TokenKind::Indent
+                | TokenKind::Dedent
+                | TokenKind::Comment(_)
+                | TokenKind::Eof
+        ) {
+            self.prev_end = cur.end;
+        }
         Ok(cur)
     }
 
@@ -143,9 +321,28 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Build an Elevate Span from a start byte offset to the current token.
+    /// `(line, column)` where the current string token's contents begin,
+    /// after its prefix and opening quote(s).
+    fn string_contents_start(&self) -> (usize, usize) {
+        let text = &self.source[self.current.start..];
+        let prefix = text.find(['"', '\'']).unwrap_or(0);
+        let quotes = if text[prefix..].starts_with("\"\"\"") || text[prefix..].starts_with("'''") {
+            3
+        } else {
+            1
+        };
+        (self.current.line, self.current.column + prefix + quotes)
+    }
+
+    /// The diagnostic for a construct in the [`unsupported`] catalog.
+    fn unsupported(&self, construct: Unsupported) -> ParseError {
+        self.error(construct.message())
+    }
+
+    /// Build an Elevate Span from a start byte offset to the end of the last
+    /// token consumed, so it covers exactly the construct's source text.
     fn span_from(&self, start: usize) -> Option<Span> {
-        Some(Span::new(start, self.current.start))
+        Some(Span::new(start, self.prev_end.max(start)))
     }
 
     fn skip_newlines(&mut self) -> Result<(), ParseError> {
@@ -175,14 +372,191 @@ impl<'a> Parser<'a> {
 
         while !matches!(self.kind(), TokenKind::Eof) {
             let parsed = self.parse_item()?;
+            for item in &parsed {
+                match item {
+                    e::Item::Function(func) => {
+                        self.docs.insert(func.name.clone(), function_doc(func));
+                    }
+                    e::Item::Enum(def) => self.register_named_variants(def),
+                    _ => {}
+                }
+            }
+            items.append(&mut self.pending_unions);
             items.extend(parsed);
             self.skip_newlines()?;
         }
+        if let Some(main) = self.implicit_main(&items)? {
+            items.push(main);
+        }
+        items.extend(self.doc_constants()?);
+        self.narrow_unions(&mut items)?;
 
         Ok(e::Module { items })
     }
 
+    /// A `main()` running the module's top-level statements in order, as a
+    /// Python script would. The `if __name__ == "__main__":` guard runs its
+    /// body unconditionally. When the module defines `def main`, the only
+    /// top-level statement allowed is a call to it, which is dropped.
+    fn implicit_main(&mut self, items: &[e::Item]) -> Result<Option<e::Item>, ParseError> {
+        let mut statements = Vec::new();
+        for stmt in std::mem::take(&mut self.top_level) {
+            match stmt {
+                e::Stmt::If {
+                    condition:
+                        e::Expr::Binary {
+                            op: e::BinaryOp::Eq,
+                            left,
+                            right,
+                        },
+                    then_block,
+                    else_block: None,
+                } if matches!(&*left, e::Expr::Path(p) if p == &["__name__"])
+                    && string_literal(&right).as_deref() == Some("__main__") =>
+                {
+                    statements.extend(then_block.statements);
+                }
+                stmt => statements.push(stmt),
+            }
+        }
+        if statements.is_empty() {
+            return Ok(None);
+        }
+        let has_main = items
+            .iter()
+            .any(|item| matches!(item, e::Item::Function(f) if f.name == "main"));
+        if has_main {
+            let calls_main = |stmt: &e::Stmt| {
+                matches!(stmt, e::Stmt::Expr(e::Expr::Call { callee, args })
+                    if args.is_empty() && matches!(&**callee, e::Expr::Path(p) if p == &["main"]))
+            };
+            if statements.iter().all(calls_main) {
+                return Ok(None);
+            }
+            return Err(self.error(
+                "top-level statements run as an implicit main(), but this module already defines `def main`; move them into main()".into(),
+            ));
+        }
+        let start = self.top_level_start.unwrap_or_default();
+        Ok(Some(e::Item::Function(e::FunctionDef {
+            visibility: e::Visibility::Public,
+            name: "main".into(),
+            type_params: vec![],
+            params: vec![],
+            return_type: None,
+            effect_row: None,
+            body: e::Block { statements },
+            span: Some(Span::new(start, self.top_level_end.max(start))),
+        })))
+    }
+
+    /// Note a `std::collections` type named without its path. Dict literals
+    /// don't count: their `HashMap` becomes quiche-lib's `Dict` (see
+    /// `wrap_collections`).
+    fn note_collection(&mut self, name: &str) {
+        if let Some(name) = COLLECTIONS.iter().find(|c| **c == name) {
+            self.collections.insert(name);
+        }
+    }
+
+    /// A top-level statement declaring a module constant, as a `pub const`
+    /// item: a `SCREAMING_CASE` name or a `Const[T]` annotation, with a
+    /// number, bool or string literal. Strings become `&str` constants.
+    /// Anything else is left for the implicit main().
+    ///
+    /// ```text
+    /// MAX_SIZE: i32 = 100            pub const MAX_SIZE: i32 = 100;
+    /// GREETING = "hello"             pub const GREETING: &str = "hello";
+    /// limit: Const[i64] = 5          pub const limit: i64 = 5;
+    /// ```
+    fn module_constant(&self, stmt: &e::Stmt, start: usize) -> Result<Option<e::Item>, ParseError> {
+        let (name, ty, value) = match stmt {
+            e::Stmt::Const(def) => (&def.name, def.ty.as_ref(), &def.value),
+            e::Stmt::Assign {
+                target: e::AssignTarget::Path(name),
+                op: e::AssignOp::Assign,
+                value,
+            } => (name, None, value),
+            _ => return Ok(None),
+        };
+        let explicit = ty
+            .filter(|ty| ty.path == ["Const"] && ty.args.len() == 1)
+            .map(|ty| &ty.args[0]);
+        let screaming = name.chars().any(|c| c.is_ascii_uppercase())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if explicit.is_none() && !screaming {
+            return Ok(None);
+        }
+        if matches!(stmt, e::Stmt::Const(_))
+            && matches!(value, e::Expr::Tuple(items) if items.is_empty())
+        {
+            return Err(self.error(format!("constant '{name}' needs a value")));
+        }
+        let ty = explicit
+            .or(ty)
+            .cloned()
+            .or_else(|| literal_type(value, &HashMap::new()));
+        let (ty, value) = match (ty, string_literal(value)) {
+            (Some(ty), Some(text))
+                if ty.args.is_empty()
+                    && matches!(ty.path.as_slice(), [s] if s == "Str" || s == "String") =>
+            {
+                let str_ty = e::Type {
+                    path: vec!["str".into()],
+                    args: vec![],
+                    trait_bounds: vec![],
+                };
+                (wrap_type("Ref", str_ty), e::Expr::String(text))
+            }
+            (Some(ty), _) if const_attr_type(&ty).is_some() && is_const_value(value) => {
+                (ty, value.clone())
+            }
+            _ if explicit.is_some() => {
+                return Err(self.error(format!(
+                    "Const '{name}' must be a number, bool or string literal"
+                )));
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(e::Item::Const(e::ConstDef {
+            visibility: e::Visibility::Public,
+            name: name.clone(),
+            ty: Some(ty),
+            value,
+            is_const: true,
+            span: self.span_from(start),
+        })))
+    }
+
+    /// `__QUICHE_HELP_<f>` and `__QUICHE_DOC_<f>` for each function whose
+    /// docs are used. Emitted after parsing, so `help()` can name functions
+    /// defined further down.
+    fn doc_constants(&self) -> Result<Vec<e::Item>, ParseError> {
+        let mut items = Vec::new();
+        for name in &self.doc_requests {
+            let Some((help, doc)) = self.docs.get(name) else {
+                return Err(self.error(format!(
+                    "help()/__doc__: '{name}' is not a function defined in this module"
+                )));
+            };
+            items.push(e::Item::RustBlock(format!(
+                "#[allow(non_upper_case_globals)]\n\
+                 const __QUICHE_HELP_{name}: &str = {help:?};\n\
+                 #[allow(non_upper_case_globals)]\n\
+                 const __QUICHE_DOC_{name}: &str = {doc:?};"
+            )));
+        }
+        Ok(items)
+    }
+
     fn parse_item(&mut self) -> Result<Vec<e::Item>, ParseError> {
+        if matches!(self.kind(), TokenKind::Ident(word) if word == "class")
+            && matches!(self.peek()?.kind, TokenKind::Ident(_))
+        {
+            return Err(self.unsupported(unsupported::CLASS));
+        }
         match self.kind() {
             TokenKind::Keyword(Keyword::Def) => {
                 Ok(vec![e::Item::Function(self.parse_function_def()?)])
@@ -193,2508 +567,9121 @@ impl<'a> Parser<'a> {
                 self.parse_bare_import()?;
                 Ok(vec![])
             }
+            TokenKind::At => self.parse_decorated_item(),
             _ => {
-                // Top-level expression or assignment — skip for now
-                self.parse_stmt()?;
-                Ok(vec![])
+                // Top-level statement: a module constant, or collected for
+                // the implicit main()
+                let start = self.current.start;
+                let mut statements = Vec::new();
+                self.parse_stmt_into(&mut statements)?;
+                let mut constants = Vec::new();
+                for stmt in statements {
+                    match self.module_constant(&stmt, start)? {
+                        Some(constant) => constants.push(constant),
+                        None => {
+                            self.top_level_start.get_or_insert(start);
+                            self.top_level_end = self.prev_end;
+                            self.top_level.push(stmt);
+                        }
+                    }
+                }
+                Ok(constants)
             }
         }
     }
 
     // ─────────────────────────────────────────────────────────────────────────
-    // Imports: from X.Y import Z → RustUse { tree: UseTree }
+    // Decorators: @name / @name(args) before `def` or `type`
     // ─────────────────────────────────────────────────────────────────────────
 
-    fn path_to_use_tree(path: Vec<String>) -> e::UseTree {
-        let mut iter = path.into_iter().rev();
-        let leaf = e::UseTree::Name(iter.next().unwrap());
-        iter.fold(leaf, |next, segment| e::UseTree::Path {
-            segment,
-            next: Box::new(next),
-        })
-    }
-
-    fn parse_from_import(&mut self) -> Result<Vec<e::Item>, ParseError> {
-        self.expect_kw(Keyword::From)?;
-        let mut module_path = vec![self.expect_ident()?];
+    fn parse_decorator(&mut self) -> Result<Decorator, ParseError> {
+        let at = self.error(String::new());
+        self.expect(&TokenKind::At)?;
+        let mut name = self.expect_ident()?;
         while self.eat(&TokenKind::Dot)? {
-            module_path.push(self.expect_ident()?);
+            name.push('.');
+            name.push_str(&self.expect_ident()?);
         }
-        self.expect_kw(Keyword::Import)?;
-
-        // Parse comma-separated names: from X.Y import A, B, C
-        let mut items = Vec::new();
-        loop {
-            let name = self.expect_ident()?;
-            let mut path = module_path.clone();
-            path.push(name);
-
-            // Consume optional "as alias" — we ignore aliases for now
-            if self.check_kw(Keyword::As) {
-                self.advance()?;
-                self.expect_ident()?;
-            }
-            let tree = Self::path_to_use_tree(path);
-            items.push(e::Item::RustUse(e::RustUse { tree, span: None }));
-
-            if !self.eat(&TokenKind::Comma)? {
-                break;
+        // Allocation strategies need the perceus-mem runtime, which is not
+        // part of this workspace; fail loudly rather than ignore the request.
+        if name == "mem" {
+            return Err(ParseError {
+                message: "@mem allocation strategies (region, inline, store, shared, pool) are not supported: the perceus-mem runtime is not available to this compiler".into(),
+                ..at
+            });
+        }
+        // `@cfg(...)` holds a Rust cfg predicate, kept as its source text
+        if name == "cfg" {
+            self.expect(&TokenKind::LParen)?;
+            let predicate = self.parse_cfg_predicate()?;
+            self.expect(&TokenKind::RParen)?;
+            return Ok(Decorator {
+                name,
+                args: vec![e::Expr::String(predicate)],
+                kwargs: vec![],
+            });
+        }
+        let mut args = Vec::new();
+        let mut kwargs = Vec::new();
+        if self.eat(&TokenKind::LParen)? {
+            for arg in self.parse_call_args_with_kwargs()? {
+                match arg {
+                    CallArg::Positional(value) => args.push(value),
+                    CallArg::Keyword(key, value) => kwargs.push((key, value)),
+                }
             }
+            self.expect(&TokenKind::RParen)?;
         }
-        Ok(items)
+        Ok(Decorator { name, args, kwargs })
     }
 
-    fn parse_bare_import(&mut self) -> Result<(), ParseError> {
-        self.expect_kw(Keyword::Import)?;
-        self.expect_ident()?;
-        while self.eat(&TokenKind::Dot)? {
-            self.expect_ident()?;
+    /// A cfg predicate in Rust's syntax: `windows`, `target_os="linux"`,
+    /// `not(...)`, `any(...)` and `all(...)`.
+    fn parse_cfg_predicate(&mut self) -> Result<String, ParseError> {
+        let combinator = match self.kind().clone() {
+            TokenKind::Keyword(Keyword::Not) => Some("not".to_string()),
+            TokenKind::Ident(id)
+                if matches!(id.as_str(), "any" | "all")
+                    && matches!(self.peek()?.kind, TokenKind::LParen) =>
+            {
+                Some(id)
+            }
+            _ => None,
+        };
+        if let Some(combinator) = combinator {
+            self.advance()?;
+            self.expect(&TokenKind::LParen)?;
+            let mut inner = vec![self.parse_cfg_predicate()?];
+            while self.eat(&TokenKind::Comma)? && !self.check(&TokenKind::RParen) {
+                inner.push(self.parse_cfg_predicate()?);
+            }
+            self.expect(&TokenKind::RParen)?;
+            if combinator == "not" && inner.len() != 1 {
+                return Err(self.error("@cfg not(...) takes a single predicate".into()));
+            }
+            return Ok(format!("{combinator}({})", inner.join(", ")));
         }
-        Ok(())
-    }
-
-    // ─────────────────────────────────────────────────────────────────────────
-    // Functions
-    // ─────────────────────────────────────────────────────────────────────────
-
-    fn parse_function_def(&mut self) -> Result<e::FunctionDef, ParseError> {
-        let start_pos = self.current.start;
-        self.expect_kw(Keyword::Def)?;
-        let name = self.expect_ident()?;
-
-        // Type params [T, U]
-        let type_params = self.parse_type_params()?;
-
-        // Params
-        self.expect(&TokenKind::LParen)?;
-        let params = self.parse_params()?;
-        self.expect(&TokenKind::RParen)?;
-
-        // Register parameter names for kwarg reordering
-        let param_names: Vec<String> = params
-            .iter()
-            .filter(|p| p.name != "self")
-            .map(|p| p.name.clone())
-            .collect();
-        self.fn_params.insert(name.clone(), param_names);
-
-        // Return type
-        let return_type = if self.eat(&TokenKind::Arrow)? {
-            Some(self.parse_type()?)
-        } else {
-            None
+        let Ok(key) = self.expect_ident() else {
+            return Err(self.error(format!(
+                "expected a cfg predicate such as `windows` or `target_os=\"linux\"`, got {}",
+                self.kind()
+            )));
         };
-
-        self.expect(&TokenKind::Colon)?;
-        let body = self.parse_block()?;
-
-        Ok(e::FunctionDef {
-            visibility: e::Visibility::Public,
-            name,
-            type_params,
-            params,
-            return_type,
-            effect_row: None,
-            body,
-            span: self.span_from(start_pos),
-        })
+        if !self.eat(&TokenKind::Eq)? {
+            return Ok(key);
+        }
+        match self.kind().clone() {
+            TokenKind::String(value) => {
+                self.advance()?;
+                Ok(format!("{key} = {value:?}"))
+            }
+            other => Err(self.error(format!(
+                "@cfg {key}= expects a string, e.g. {key}=\"...\", got {other}"
+            ))),
+        }
     }
 
-    fn parse_type_params(&mut self) -> Result<Vec<e::GenericParam>, ParseError> {
-        if !self.eat(&TokenKind::LBracket)? {
-            return Ok(vec![]);
+    fn parse_decorated_item(&mut self) -> Result<Vec<e::Item>, ParseError> {
+        let mut decorators = Vec::new();
+        while self.check(&TokenKind::At) {
+            decorators.push(self.parse_decorator()?);
+            self.skip_newlines()?;
         }
-        let mut params = Vec::new();
-        loop {
-            if self.check(&TokenKind::RBracket) {
-                break;
+
+        match self.kind() {
+            TokenKind::Keyword(Keyword::Def) if decorators.iter().any(|d| d.name == "extern") => {
+                let func = self.parse_function_def()?;
+                let items = self.lower_extern(&func, &decorators)?;
+                self.decorators.insert(func.name.clone(), decorators);
+                Ok(items)
             }
-            let name = self.expect_ident()?;
-            // Parse optional trait bounds: T: Display or T: Display + Debug
-            let bounds = if self.eat(&TokenKind::Colon)? {
-                let mut bounds = Vec::new();
-                bounds.push(self.parse_type()?);
-                while self.eat(&TokenKind::Plus)? {
-                    bounds.push(self.parse_type()?);
+            TokenKind::Keyword(Keyword::Def) if decorators.iter().any(|d| d.name == "export") => {
+                let func = self.parse_function_def()?;
+                let items = self.lower_export(func, &decorators)?;
+                Ok(items)
+            }
+            TokenKind::Keyword(Keyword::Def)
+                if decorators.iter().any(|d| d.name == "wasm_export") =>
+            {
+                let func = self.parse_function_def()?;
+                let wrapper = self.lower_wasm_export(&func)?;
+                self.decorators.insert(func.name.clone(), decorators);
+                Ok(vec![e::Item::Function(func), wrapper])
+            }
+            TokenKind::Keyword(Keyword::Def)
+                if decorators.iter().any(|d| d.name == "py_import") =>
+            {
+                let func = self.parse_function_def()?;
+                let items = self.lower_py_import(&func, &decorators)?;
+                self.decorators.insert(func.name.clone(), decorators);
+                Ok(items)
+            }
+            TokenKind::Keyword(Keyword::Def) if decorators.iter().any(|d| d.name == "cli") => {
+                if decorators.len() > 1 {
+                    return Err(self.error("@cli takes no other decorators".into()));
                 }
-                bounds
-            } else {
-                vec![]
-            };
-            params.push(e::GenericParam { name, bounds });
-            if !self.eat(&TokenKind::Comma)? {
-                break;
+                let func = self.parse_function_def()?;
+                self.lower_cli(func)
             }
-        }
-        self.expect(&TokenKind::RBracket)?;
-        Ok(params)
-    }
-
-    fn parse_params(&mut self) -> Result<Vec<e::Param>, ParseError> {
-        let mut params = Vec::new();
-        while !self.check(&TokenKind::RParen) {
-            let name = self.expect_ident()?;
-            // Emit `self` as a param with type `Self` — Elevate's
-            // type_from_ast_with_impl_self resolves Self → impl target type.
-            if name == "self" {
-                let ty = if self.eat(&TokenKind::Colon)? {
-                    self.parse_type()?
+            TokenKind::Keyword(Keyword::Def) => {
+                let is_fixture = decorators.iter().any(|d| d.name == "fixture");
+                self.in_fixture = is_fixture;
+                let func = self.parse_function_def();
+                self.in_fixture = false;
+                let func = func?;
+                let items = if is_fixture {
+                    self.split_fixture(func.clone())?
                 } else {
-                    e::Type {
-                        path: vec!["Self".into()],
-                        args: vec![],
-                        trait_bounds: vec![],
-                    }
+                    self.lower_fn_decorators(func.clone(), &decorators)?
                 };
-                params.push(e::Param { name, ty });
-                if !self.eat(&TokenKind::Comma)? {
-                    break;
+                self.decorators.insert(func.name.clone(), decorators);
+                Ok(items)
+            }
+            TokenKind::Keyword(Keyword::Type)
+                if let Some(deco) = decorators
+                    .iter()
+                    .find(|d| matches!(d.name.as_str(), "cfg" | "test")) =>
+            {
+                Err(self.error(format!("@{} applies to functions (`def`)", deco.name)))
+            }
+            TokenKind::Keyword(Keyword::Type) if decorators.iter().any(|d| d.name == "trait") => {
+                if decorators.len() > 1 {
+                    return Err(self.error("@trait takes no other decorators".into()));
                 }
-                continue;
+                Ok(vec![self.parse_trait_def()?])
             }
-            let ty = if self.eat(&TokenKind::Colon)? {
-                self.parse_type()?
-            } else {
-                // No type annotation — inferred
-                e::Type {
-                    path: vec!["_".into()],
-                    args: vec![],
-                    trait_bounds: vec![],
+            TokenKind::Keyword(Keyword::Type) => {
+                let mut items = self.parse_type_def()?;
+                let impls = self.lower_trait_impls(&items, &decorators)?;
+                items.extend(impls);
+                let derives = self.lower_derives(&items, &decorators)?;
+                items.extend(derives);
+                if decorators.iter().any(|d| d.name == "wasm_export") {
+                    let class = self.lower_wasm_class(&items)?;
+                    items.push(class);
                 }
-            };
-            params.push(e::Param { name, ty });
-            if !self.eat(&TokenKind::Comma)? {
-                break;
+                let name = items.iter().find_map(|item| match item {
+                    e::Item::Struct(s) => Some(s.name.clone()),
+                    e::Item::Enum(en) => Some(en.name.clone()),
+                    _ => None,
+                });
+                if let Some(name) = name {
+                    let markers = self
+                        .rust_attributes(&decorators)?
+                        .into_iter()
+                        .map(|attribute| {
+                            e::Item::RustBlock(format!("// quiche:attribute {name} {attribute}"))
+                        });
+                    items.splice(0..0, markers);
+                    self.decorators.insert(name, decorators);
+                }
+                Ok(items)
             }
+            _ => Err(self.error(format!(
+                "expected 'def' or 'type' after decorator, got {}",
+                self.kind()
+            ))),
         }
-        Ok(params)
     }
 
-    // ─────────────────────────────────────────────────────────────────────────
-    // Types
-    // ─────────────────────────────────────────────────────────────────────────
-
-    fn parse_type(&mut self) -> Result<e::Type, ParseError> {
-        let name = self.expect_ident()?;
-        // Rewrite Quiche primitive type names to newtype names
-        let name = match name.as_str() {
-            "str" => "Str".into(),
-            "list" => "List".into(),
-            "dict" => "Dict".into(),
-            _ => name,
-        };
-        let mut path = vec![name];
-
-        // Dotted path: std.collections.HashMap → ["std", "collections", "HashMap"]
-        while self.eat(&TokenKind::Dot)? {
-            path.push(self.expect_ident()?);
-        }
-
-        // Generic args: Vec[i32] or HashMap[String, i32]
-        let args = if self.eat(&TokenKind::LBracket)? {
-            let mut args = Vec::new();
-            loop {
-                if self.check(&TokenKind::RBracket) {
-                    break;
-                }
-                args.push(self.parse_type()?);
-                if !self.eat(&TokenKind::Comma)? {
-                    break;
-                }
+    /// Compiler-recognized decorators on a plain `def`:
+    ///
+    /// - `@inline` → `#[inline(always)]`, `@cold` → `#[cold]`, and `@hot` →
+    ///   `#[inline]` (Rust has no hot attribute; inlining is the hint it
+    ///   takes), applied through `// quiche:attribute` marker lines;
+    /// - `@cfg(target_os="windows")` → `#[cfg(target_os = "windows")]`, the
+    ///   same way (on both functions of a `@memoize`);
+    /// - `@rust_attr(...)` → the attributes it names (see `rust_attributes`);
+    /// - `@memoize` keeps the body as `__memoized_<name>` and makes `<name>`
+    ///   a wrapper that caches results by argument with quiche-lib's `Memo`.
+    ///
+    /// ```text
+    /// @memoize                         def fib(n: u64) -> u64:
+    /// def fib(n: u64) -> u64:      →       Memo.cached("fib", n, || __memoized_fib(n))
+    ///     ...                          def __memoized_fib(n: u64) -> u64:
+    ///                                      ...
+    /// ```
+    fn lower_fn_decorators(
+        &self,
+        mut func: e::FunctionDef,
+        decorators: &[Decorator],
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let mut items = Vec::new();
+        for deco in decorators {
+            let attribute = match (deco.name.as_str(), deco.args.as_slice()) {
+                ("inline", _) => "#[inline(always)]".to_string(),
+                ("cold", _) => "#[cold]".to_string(),
+                ("hot", _) => "#[inline]".to_string(),
+                ("cfg", [e::Expr::String(predicate)]) => format!("#[cfg({predicate})]"),
+                _ => continue,
+            };
+            items.push(e::Item::RustBlock(format!(
+                "// quiche:attribute {} {attribute}",
+                func.name
+            )));
+        }
+        for attribute in self.rust_attributes(decorators)? {
+            items.push(e::Item::RustBlock(format!(
+                "// quiche:attribute {} {attribute}",
+                func.name
+            )));
+        }
+        let memoize = decorators.iter().any(|d| d.name == "memoize");
+        for deco in decorators.iter().filter(|_| memoize) {
+            if let ("cfg", [e::Expr::String(predicate)]) =
+                (deco.name.as_str(), deco.args.as_slice())
+            {
+                items.push(e::Item::RustBlock(format!(
+                    "// quiche:attribute __memoized_{} #[cfg({predicate})]",
+                    func.name
+                )));
             }
-            self.expect(&TokenKind::RBracket)?;
-            args
-        } else {
-            vec![]
+        }
+        if memoize {
+            if func.return_type.is_none() {
+                return Err(self.error(format!(
+                    "@memoize '{}' needs a return type annotation",
+                    func.name
+                )));
+            }
+            let path = |name: &str| e::Expr::Path(vec![name.to_string()]);
+            let mut args: Vec<e::Expr> = func.params.iter().map(|p| path(&p.name)).collect();
+            let inner = format!("__memoized_{}", func.name);
+            let compute = e::Expr::Closure {
+                params: vec![],
+                return_type: None,
+                body: e::Block {
+                    statements: vec![e::Stmt::TailExpr(e::Expr::Call {
+                        callee: Box::new(path(&inner)),
+                        args: args.clone(),
+                    })],
+                },
+            };
+            let key = if args.len() == 1 {
+                args.remove(0)
+            } else {
+                e::Expr::Tuple(args)
+            };
+            let wrapper = e::FunctionDef {
+                body: e::Block {
+                    statements: vec![e::Stmt::TailExpr(e::Expr::Call {
+                        callee: Box::new(e::Expr::Path(vec!["Memo".into(), "cached".into()])),
+                        args: vec![e::Expr::String(func.name.clone()), key, compute],
+                    })],
+                },
+                ..func.clone()
+            };
+            func.name = inner;
+            items.push(e::Item::Function(wrapper));
+        }
+        items.push(e::Item::Function(func));
+        Ok(items)
+    }
+
+    /// `@cli def main(...)`: `main`'s body becomes `__cli_main`, and a new
+    /// `main` reads its arguments from the command line with quiche-lib's
+    /// `Cli`, which takes the signature as a spec (see `cli_module.rs`) and
+    /// the docstring as help: its first part describes the program, and an
+    /// `Args:` section describes parameters.
+    ///
+    /// ```text
+    /// @cli                                       def main():
+    /// def main(path: Str, count: i32 = 1):   →       Cli.parse("arg\tpath\tStr\t\t\nopt\tcount\ti32\t1\t")
+    ///     ...                                        __cli_main(Cli.get("path"), Cli.get_or("count", 1))
+    ///                                            def __cli_main(path: Str, count: i32 = 1):
+    ///                                                ...
+    /// ```
+    fn lower_cli(&self, mut func: e::FunctionDef) -> Result<Vec<e::Item>, ParseError> {
+        if func.name != "main" {
+            return Err(self.error(format!("@cli applies to `def main`, not '{}'", func.name)));
+        }
+        let (_, doc) = function_doc(&func);
+        let (about, param_docs) = cli_docs(&doc);
+        let names = self.fn_params.get("main").cloned().unwrap_or_default();
+        let path = |name: &str| e::Expr::Path(vec![name.to_string()]);
+        let getter = |name: &str, args: Vec<e::Expr>| e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["Cli".into(), name.into()])),
+            args,
         };
+        let mut spec = String::new();
+        let mut args = Vec::new();
+        let mut keyword_only = false;
+        for name in &names {
+            if name == "*" {
+                keyword_only = true;
+                continue;
+            }
+            if name.starts_with("**") {
+                return Err(self.error(format!("@cli main can't take '{name}'")));
+            }
+            let variadic = name.starts_with('*');
+            let name = name.trim_start_matches('*');
+            let Some(param) = func.params.iter().find(|p| p.name == name) else {
+                continue;
+            };
+            let item = if variadic {
+                param.ty.args.first()
+            } else {
+                Some(&param.ty)
+            };
+            let optional = item.is_some_and(|ty| ty.path == ["Option"] && ty.args.len() == 1);
+            let leaf = item.and_then(|ty| if optional { ty.args.first() } else { Some(ty) });
+            let Some(leaf) = leaf.filter(|ty| {
+                ty.args.is_empty()
+                    && ty.path.len() == 1
+                    && (CLI_TYPES.contains(&ty.path[0].as_str())
+                        || self.unit_enums.contains(&ty.path[0]))
+            }) else {
+                return Err(self.error(format!(
+                    "@cli parameter '{name}': a {} can't be read from the command line; use Str, bool, a number, an enum without data, or Option of one",
+                    quiche_type(&param.ty)
+                )));
+            };
+            let ty = quiche_type(leaf);
+            let default = self
+                .fn_defaults
+                .get(&("main".to_string(), name.to_string()))
+                .cloned();
+            let default_expr = match &default {
+                Some(text) => Some(Parser::new(text).and_then(|mut p| p.parse_expr())?),
+                None => None,
+            };
+            let key = e::Expr::String(name.to_string());
+            let (kind, arg) = if variadic {
+                ("args", getter("get_all", vec![key]))
+            } else if optional {
+                ("opt", getter("get_optional", vec![key]))
+            } else if ty == "bool" {
+                let default = default_expr.unwrap_or(e::Expr::Bool(false));
+                ("flag", getter("get_or", vec![key, default]))
+            } else if let Some(default) = default_expr {
+                ("opt", getter("get_or", vec![key, default]))
+            } else if keyword_only {
+                ("opt", getter("get", vec![key]))
+            } else {
+                ("arg", getter("get", vec![key]))
+            };
+            let help = param_docs.get(name).map(String::as_str).unwrap_or("");
+            spec.push_str(&format!(
+                "{kind}\t{name}\t{ty}\t{}\t{}\n",
+                default.as_deref().unwrap_or(""),
+                help.replace(['\t', '\n'], " ")
+            ));
+            args.push(arg);
+        }
+        if !about.is_empty() {
+            spec.push_str(&format!("\n{about}"));
+        }
 
-        Ok(e::Type {
-            path,
+        let call = e::Expr::Call {
+            callee: Box::new(path("__cli_main")),
             args,
-            trait_bounds: vec![],
-        })
+        };
+        let wrapper = e::FunctionDef {
+            name: "main".into(),
+            params: vec![],
+            body: e::Block {
+                statements: vec![
+                    e::Stmt::Expr(getter("parse", vec![e::Expr::String(spec)])),
+                    if func.return_type.is_some() {
+                        e::Stmt::TailExpr(call)
+                    } else {
+                        e::Stmt::Expr(call)
+                    },
+                ],
+            },
+            ..func.clone()
+        };
+        func.name = "__cli_main".into();
+        Ok(vec![e::Item::Function(wrapper), e::Item::Function(func)])
     }
 
-    /// Parse a single variant: `Name`, `Name(T1, T2)`, or `Name(x: T1, y: T2)`.
-    fn parse_variant(&mut self) -> Result<e::EnumVariant, ParseError> {
-        let name = self.expect_ident()?;
-
-        if !self.eat(&TokenKind::LParen)? {
-            return Ok(e::EnumVariant {
-                name,
-                fields: e::EnumVariantFields::Unit,
-            });
+    /// The Rust attributes of an item's `@rust_attr` decorators, passed
+    /// through verbatim: each string argument is an attribute (the `#[...]`
+    /// may be left off), and `name="args"` is the structured form of
+    /// `#[name(args)]`.
+    ///
+    /// ```text
+    /// @rust_attr('#[serde(rename_all = "camelCase")]')   →  #[serde(rename_all = "camelCase")]
+    /// @rust_attr("must_use")                             →  #[must_use]
+    /// @rust_attr(allow="dead_code")                      →  #[allow(dead_code)]
+    /// ```
+    fn rust_attributes(&self, decorators: &[Decorator]) -> Result<Vec<String>, ParseError> {
+        let mut attributes = Vec::new();
+        for deco in decorators.iter().filter(|d| d.name == "rust_attr") {
+            let malformed = || {
+                self.error(
+                    "@rust_attr takes attributes as strings: @rust_attr('#[must_use]') or @rust_attr(allow=\"dead_code\")"
+                        .into(),
+                )
+            };
+            if deco.args.is_empty() && deco.kwargs.is_empty() {
+                return Err(malformed());
+            }
+            for arg in &deco.args {
+                let text = string_literal(arg).ok_or_else(malformed)?;
+                let text = text.trim();
+                match text.strip_prefix("#[") {
+                    Some(inner) if inner.ends_with(']') => attributes.push(text.to_string()),
+                    Some(_) => return Err(malformed()),
+                    None => attributes.push(format!("#[{text}]")),
+                }
+            }
+            for (name, value) in &deco.kwargs {
+                let args = string_literal(value).ok_or_else(malformed)?;
+                attributes.push(format!("#[{name}({})]", args.trim()));
+            }
+        }
+        if attributes.iter().any(|attribute| attribute.contains('\n')) {
+            return Err(self.error("@rust_attr attributes must fit on one line".into()));
         }
+        Ok(attributes)
+    }
 
-        // Empty parens: Name() → unit variant
-        if self.eat(&TokenKind::RParen)? {
-            return Ok(e::EnumVariant {
-                name,
-                fields: e::EnumVariantFields::Unit,
-            });
+    /// Split a generator-style fixture at its `yield` into a setup function
+    /// (same name, returns the yielded value) and `__teardown_<name>`, which
+    /// receives the yielded value and runs the statements after `yield`.
+    ///
+    /// ```text
+    /// @fixture                      def db() -> Db:
+    /// def db() -> Db:                   conn = Db.open()
+    ///     conn = Db.open()      →       return conn
+    ///     yield conn                def __teardown_db(conn: Db):
+    ///     conn.close()                  conn.close()
+    /// ```
+    ///
+    /// The `yield` must be a statement of the fixture body itself: one inside
+    /// an `if`, `with` or loop has no single place to split at.
+    fn split_fixture(&self, mut func: e::FunctionDef) -> Result<Vec<e::Item>, ParseError> {
+        let mut yields = 0;
+        visit_exprs(&func.body, &mut |expr| {
+            if matches!(expr, e::Expr::MacroCall { path, .. } if path.len() == 1 && path[0] == "__yield__")
+            {
+                yields += 1;
+            }
+        });
+        let top_level = func
+            .body
+            .statements
+            .iter()
+            .filter(|s| Self::is_yield(s))
+            .count();
+        if yields > top_level {
+            return Err(self.error(format!(
+                "@fixture '{}' yields inside a nested block; `yield` must be a top-level statement of the fixture",
+                func.name
+            )));
         }
+        let Some(idx) = func.body.statements.iter().position(Self::is_yield) else {
+            return Ok(vec![e::Item::Function(func)]);
+        };
+        let Some(ty) = func.return_type.clone() else {
+            return Err(self.error(format!(
+                "@fixture '{}' uses yield and needs a return type annotation",
+                func.name
+            )));
+        };
 
-        // Peek ahead to distinguish named fields (ident : type) vs tuple (type).
-        let is_named = matches!(self.kind(), TokenKind::Ident(_))
-            && matches!(self.peek()?.kind, TokenKind::Colon);
+        let teardown_body = func.body.statements.split_off(idx + 1);
+        let value = match func.body.statements.pop() {
+            Some(e::Stmt::Expr(e::Expr::MacroCall { mut args, .. })) => args.pop(),
+            _ => None,
+        };
+        let param_name = match &value {
+            Some(e::Expr::Path(p)) if p.len() == 1 => p[0].clone(),
+            _ => "_value".to_string(),
+        };
+        func.body.statements.push(e::Stmt::Return(value));
+
+        let teardown = e::FunctionDef {
+            visibility: func.visibility,
+            name: format!("__teardown_{}", func.name),
+            type_params: func.type_params.clone(),
+            params: vec![e::Param {
+                name: param_name,
+                ty,
+            }],
+            return_type: None,
+            effect_row: None,
+            body: e::Block {
+                statements: teardown_body,
+            },
+            span: func.span,
+        };
+        Ok(vec![e::Item::Function(func), e::Item::Function(teardown)])
+    }
 
-        if is_named {
-            let mut fields = Vec::new();
-            loop {
-                let field_name = self.expect_ident()?;
-                self.expect(&TokenKind::Colon)?;
-                let ty = self.parse_type()?;
-                fields.push(e::Field {
-                    name: field_name,
-                    ty,
-                });
-                if !self.eat(&TokenKind::Comma)? {
-                    break;
+    /// Lower an `@extern` declaration to a `use` of the Rust item it names.
+    /// The body is ignored; the signature is kept for call-site checks.
+    ///
+    /// ```text
+    /// @extern(crate="serde_json", version="1") use serde_json::from_str;
+    /// def from_str[T](s: Str) -> Result[T, Error]:
+    ///     pass
+    ///
+    /// @extern(path="std::fs::read_dir")        use std::fs::read_dir;
+    /// ```
+    fn lower_extern(
+        &mut self,
+        func: &e::FunctionDef,
+        decorators: &[Decorator],
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let Some(deco) = decorators.iter().find(|d| d.name == "extern") else {
+            return Ok(vec![e::Item::Function(func.clone())]);
+        };
+        let kwarg = |key: &str| deco.kwarg(key).and_then(string_literal);
+        if let [abi] = deco.args.as_slice()
+            && string_literal(abi).as_deref() == Some("C")
+        {
+            return self.lower_c_extern(func, kwarg("lib"), kwarg("symbol"));
+        }
+        if !deco.args.is_empty() {
+            return Err(self.error(format!(
+                "@extern on '{}' takes keyword arguments: path=\"...\" and/or crate=\"...\" with version=\"...\", or \"C\" for a C function",
+                func.name
+            )));
+        }
+        let krate = kwarg("crate");
+        let path = match (kwarg("path"), &krate) {
+            (Some(path), _) => path,
+            (None, Some(krate)) => format!("{krate}::{}", func.name),
+            (None, None) => {
+                return Err(self.error(format!(
+                    "@extern on '{}' needs path=\"...\" or crate=\"...\"",
+                    func.name
+                )));
+            }
+        };
+        let segments: Vec<String> = path.split("::").map(str::to_string).collect();
+        if segments.iter().any(|s| s.is_empty()) {
+            return Err(self.error(format!("@extern path '{path}' is not a valid Rust path")));
+        }
+        let krate = krate.or_else(|| {
+            let root = &segments[0];
+            (!matches!(
+                root.as_str(),
+                "std" | "core" | "alloc" | "crate" | "self" | "super" | "quiche_lib"
+            ))
+            .then(|| root.clone())
+        });
+        let version = kwarg("version");
+        if kwarg("crate").is_some() && version.is_none() {
+            return Err(self.error(format!(
+                "@extern(crate=...) on '{}' needs the crate's version, e.g. version=\"1\"",
+                func.name
+            )));
+        }
+        match (krate, version) {
+            (Some(krate), version) => {
+                let krate = krate.replace('-', "_");
+                if let Some(version) = version {
+                    match self.crate_versions.get(&krate) {
+                        Some(other) if *other != version => {
+                            return Err(self.error(format!(
+                                "@extern on '{}' asks for {krate} version \"{version}\", but an earlier @extern asks for \"{other}\"",
+                                func.name
+                            )));
+                        }
+                        _ => {
+                            self.crate_versions.insert(krate.clone(), version);
+                        }
+                    }
                 }
+                self.extern_crates.insert(krate);
             }
-            self.expect(&TokenKind::RParen)?;
-            Ok(e::EnumVariant {
-                name,
-                fields: e::EnumVariantFields::Named(fields),
-            })
+            (None, Some(_)) => {
+                return Err(self.error(format!(
+                    "@extern on '{}' names no crates.io crate, so it takes no version=",
+                    func.name
+                )));
+            }
+            (None, None) => {}
+        }
+
+        let arity = func.params.iter().filter(|p| p.name != "self").count();
+        self.externs.insert(func.name.clone(), arity);
+        if segments.last() == Some(&func.name) {
+            Ok(vec![e::Item::RustUse(e::RustUse {
+                tree: Self::path_to_use_tree(segments),
+                span: func.span,
+            })])
         } else {
-            let mut types = Vec::new();
-            loop {
-                types.push(self.parse_type()?);
-                if !self.eat(&TokenKind::Comma)? {
-                    break;
-                }
+            Ok(vec![e::Item::RustBlock(format!(
+                "use {path} as {};",
+                func.name
+            ))])
+        }
+    }
+
+    /// `@extern("C")`: declare the C symbol and wrap it in a safe Rust
+    /// function that marshals Quiche values to their C equivalents.
+    fn lower_c_extern(
+        &mut self,
+        func: &e::FunctionDef,
+        lib: Option<String>,
+        symbol: Option<String>,
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let symbol = symbol.unwrap_or_else(|| func.name.clone());
+        let raw = format!("__quiche_c_{}", func.name);
+        let mut c_params = Vec::new();
+        let mut params = Vec::new();
+        let mut setup = String::new();
+        let mut args = Vec::new();
+        for param in &func.params {
+            let Some(ty) = c_type(&param.ty) else {
+                return Err(self.error(format!(
+                    "@extern(\"C\") on '{}': parameter '{}' has type '{}', which has no C equivalent",
+                    func.name,
+                    param.name,
+                    param.ty.path.join(".")
+                )));
+            };
+            params.push(format!("{}: {}", param.name, ty.quiche));
+            c_params.push(format!("{}: {}", param.name, ty.c));
+            if ty.quiche == "Str" {
+                setup.push_str(&format!(
+                    "    let {0} = CLib::c_string(&{0});\n",
+                    param.name
+                ));
+                args.push(format!("{}.as_ptr()", param.name));
+            } else {
+                args.push(param.name.clone());
             }
-            self.expect(&TokenKind::RParen)?;
-            Ok(e::EnumVariant {
-                name,
-                fields: e::EnumVariantFields::Tuple(types),
-            })
         }
+        let ret = match &func.return_type {
+            None => None,
+            Some(ty) if ty.path == ["None"] => None,
+            Some(ty) => Some(c_type(ty).ok_or_else(|| {
+                self.error(format!(
+                    "@extern(\"C\") on '{}': return type '{}' has no C equivalent",
+                    func.name,
+                    ty.path.join(".")
+                ))
+            })?),
+        };
+        let call = format!("{raw}({})", args.join(", "));
+        let (c_ret, ret, body) = match &ret {
+            None => (String::new(), String::new(), format!("unsafe {{ {call} }}")),
+            Some(ty) if ty.quiche == "Str" => (
+                format!(" -> {}", ty.c),
+                " -> Str".to_string(),
+                format!("unsafe {{ CLib::from_c_str({call}) }}"),
+            ),
+            Some(ty) => (
+                format!(" -> {}", ty.c),
+                format!(" -> {}", ty.quiche),
+                format!("unsafe {{ {call} }}"),
+            ),
+        };
+        let link = lib
+            .map(|lib| format!("#[link(name = \"{lib}\")]\n"))
+            .unwrap_or_default();
+
+        self.externs.insert(func.name.clone(), func.params.len());
+        Ok(vec![e::Item::RustBlock(format!(
+            "{link}unsafe extern \"C\" {{\n    #[link_name = \"{symbol}\"]\n    fn {raw}({}){c_ret};\n}}\n\n\
+             pub fn {}({}){ret} {{\n{setup}    {body}\n}}",
+            c_params.join(", "),
+            func.name,
+            params.join(", "),
+        ))])
     }
 
-    /// Parse a `|`-separated variant list. Supports:
+    /// `@export`: keep the function and add a C ABI wrapper around it, so
+    /// the compiled library can be loaded from C, ctypes, and the like.
     ///
-    ///   Inline:    `A | B(i32) | C`          (pipe as separator)
-    ///   Inline:    `| A | B(i32) | C`        (optional leading pipe)
-    ///   Multiline: `\n    | A\n    | B\n    | C`
-    fn parse_variant_list(&mut self) -> Result<Vec<e::EnumVariant>, ParseError> {
-        let mut variants = Vec::new();
-
-        // Skip newlines and optional indent (multiline form)
-        self.skip_newlines()?;
-        let in_block = self.eat(&TokenKind::Indent)?;
+    /// The wrapper uses `#[export_name]` rather than `#[no_mangle]` so it
+    /// can sit next to the Quiche function under the same C-visible name.
+    /// `Str` crosses as `char*` (returned strings are freed with the
+    /// generated `quiche_free_string`), `List[T]` of numbers as a
+    /// pointer/length pair.
+    fn lower_export(
+        &mut self,
+        func: e::FunctionDef,
+        decorators: &[Decorator],
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let deco = decorators.iter().find(|d| d.name == "export");
+        let symbol = deco
+            .and_then(|d| d.kwarg("name"))
+            .and_then(string_literal)
+            .unwrap_or_else(|| func.name.clone());
+        let mut c_params = Vec::new();
+        let mut args = Vec::new();
+        for param in &func.params {
+            let name = &param.name;
+            if param.ty.path == ["List"]
+                && let [elem] = param.ty.args.as_slice()
+                && let Some(elem) = c_type(elem).filter(|t| t.quiche == t.c)
+            {
+                c_params.push(format!("{name}: *const {}, {name}_len: usize", elem.c));
+                args.push(format!(
+                    "List::from(if {name}.is_null() {{ Vec::new() }} else {{ unsafe {{ std::slice::from_raw_parts({name}, {name}_len) }}.to_vec() }})"
+                ));
+                continue;
+            }
+            let Some(ty) = c_type(&param.ty) else {
+                return Err(self.error(format!(
+                    "@export on '{}': parameter '{name}' has type '{}', which has no C equivalent",
+                    func.name,
+                    param.ty.path.join(".")
+                )));
+            };
+            c_params.push(format!("{name}: {}", ty.c));
+            if ty.quiche == "Str" {
+                args.push(format!("unsafe {{ CLib::from_c_str({name}) }}"));
+            } else {
+                args.push(name.clone());
+            }
+        }
+        let call = format!("{}({})", func.name, args.join(", "));
+        let (ret, body) = match &func.return_type {
+            None => (String::new(), format!("{call};")),
+            Some(ty) if ty.path == ["None"] => (String::new(), format!("{call};")),
+            Some(ty) => match c_type(ty) {
+                Some(ty) if ty.quiche == "Str" => (
+                    " -> *mut std::ffi::c_char".to_string(),
+                    format!("CLib::into_c_str({call})"),
+                ),
+                Some(ty) => (format!(" -> {}", ty.c), call),
+                None => {
+                    return Err(self.error(format!(
+                        "@export on '{}': return type '{}' has no C equivalent",
+                        func.name,
+                        ty.path.join(".")
+                    )));
+                }
+            },
+        };
 
-        // Eat optional leading pipe, then parse first variant
-        self.eat(&TokenKind::Pipe)?;
-        variants.push(self.parse_variant()?);
-        self.skip_newlines()?;
+        let mut wrapper = format!(
+            "#[unsafe(export_name = \"{symbol}\")]\npub extern \"C\" fn __quiche_export_{}({}){ret} {{\n    {body}\n}}",
+            func.name,
+            c_params.join(", "),
+        );
+        if ret.contains("c_char") && !self.exports_strings {
+            self.exports_strings = true;
+            wrapper.push_str(
+                "\n\n/// Free a string returned by an exported Quiche function.\n\
+                 #[unsafe(no_mangle)]\n\
+                 pub extern \"C\" fn quiche_free_string(s: *mut std::ffi::c_char) {\n    \
+                 unsafe { CLib::free_c_str(s) }\n}",
+            );
+        }
+        self.decorators
+            .insert(func.name.clone(), decorators.to_vec());
+        Ok(vec![e::Item::Function(func), e::Item::RustBlock(wrapper)])
+    }
 
-        // Continue with remaining | Variant pairs
-        while self.eat(&TokenKind::Pipe)? {
-            variants.push(self.parse_variant()?);
-            self.skip_newlines()?;
+    /// `@py_import("module")`: replace the stub with a function that calls
+    /// the Python function of the same name (or `name="..."`) in an embedded
+    /// CPython through PyO3. Arguments and the result cross as quiche-lib
+    /// `Value`s.
+    ///
+    /// ```text
+    /// @py_import("numpy")                   pub fn mean(xs: List<f64>) -> f64 {
+    /// def mean(xs: List[f64]) -> f64:   →       FromValue::from_value(__quiche_py::call(
+    ///     pass                                      "numpy", "mean", vec![Value::from(xs)]))
+    ///                                       }
+    /// ```
+    fn lower_py_import(
+        &mut self,
+        func: &e::FunctionDef,
+        decorators: &[Decorator],
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let deco = decorators.iter().find(|d| d.name == "py_import");
+        let module = match deco.map(|d| d.args.as_slice()) {
+            Some([module]) => string_literal(module),
+            _ => None,
+        };
+        let Some(module) = module else {
+            return Err(self.error(format!(
+                "@py_import on '{}' takes the Python module name as a string, e.g. @py_import(\"numpy\")",
+                func.name
+            )));
+        };
+        let attr = deco
+            .and_then(|d| d.kwarg("name"))
+            .and_then(string_literal)
+            .unwrap_or_else(|| func.name.clone());
+        if !func.type_params.is_empty() {
+            return Err(self.error(format!(
+                "@py_import on '{}': generic functions are not supported",
+                func.name
+            )));
         }
 
-        if in_block && self.check(&TokenKind::Dedent) {
-            self.advance()?;
+        let params: Vec<String> = func
+            .params
+            .iter()
+            .map(|p| format!("{}: {}", p.name, rust_type(&p.ty)))
+            .collect();
+        let args: Vec<String> = func
+            .params
+            .iter()
+            .map(|p| format!("Value::from({})", p.name))
+            .collect();
+        let call = format!(
+            "__quiche_py::call({module:?}, {attr:?}, vec![{}])",
+            args.join(", ")
+        );
+        let (ret, body) = match &func.return_type {
+            Some(ty) if ty.path != ["None"] => (
+                format!(" -> {}", rust_type(ty)),
+                format!("FromValue::from_value({call})"),
+            ),
+            _ => (String::new(), format!("{call};")),
+        };
+        let mut code = format!(
+            "pub fn {}({}){ret} {{\n    {body}\n}}",
+            func.name,
+            params.join(", ")
+        );
+        if !self.py_glue {
+            self.py_glue = true;
+            code.push_str("\n\n");
+            code.push_str(PY_GLUE);
         }
+        self.extern_crates.insert("pyo3".to_string());
+        self.externs.insert(func.name.clone(), func.params.len());
+        Ok(vec![e::Item::RustBlock(code)])
+    }
 
-        Ok(Self::disambiguate_variants(variants))
+    /// `@wasm_export`: keep the function and add a `#[wasm_bindgen]` wrapper
+    /// for it, compiled only for `wasm32`, so JavaScript can call it under
+    /// the same name. `Str` crosses as a JS string, `List[T]` of numbers as a
+    /// typed array, and `@wasm_export` classes as JS objects.
+    fn lower_wasm_export(&mut self, func: &e::FunctionDef) -> Result<e::Item, ParseError> {
+        if !func.type_params.is_empty() {
+            return Err(self.error(format!(
+                "@wasm_export on '{}': generic functions cannot be exported",
+                func.name
+            )));
+        }
+        let mut params = Vec::new();
+        let mut args = Vec::new();
+        for param in &func.params {
+            let ty = self.wasm_type(&param.ty).ok_or_else(|| {
+                self.error(format!(
+                    "@wasm_export on '{}': parameter '{}' has type '{}', which cannot cross to JavaScript",
+                    func.name,
+                    param.name,
+                    rust_type(&param.ty)
+                ))
+            })?;
+            params.push(format!("{}: {}", param.name, ty.js()));
+            args.push(ty.to_quiche(&param.name));
+        }
+        self.extern_crates.insert("wasm_bindgen".to_string());
+        let call = format!("{}({})", func.name, args.join(", "));
+        let (ret, body) = match &func.return_type {
+            Some(ty) if ty.path != ["None"] => {
+                let wasm = self.wasm_type(ty).ok_or_else(|| {
+                    self.error(format!(
+                        "@wasm_export on '{}': return type '{}' cannot cross to JavaScript",
+                        func.name,
+                        rust_type(ty)
+                    ))
+                })?;
+                (format!(" -> {}", wasm.js()), wasm.to_js(&call))
+            }
+            _ => (String::new(), format!("{call};")),
+        };
+        Ok(e::Item::RustBlock(format!(
+            "#[cfg(target_arch = \"wasm32\")]\n\
+             #[wasm_bindgen::prelude::wasm_bindgen(js_name = {0})]\n\
+             pub fn __quiche_wasm_{0}({1}){ret} {{\n    {body}\n}}",
+            func.name,
+            params.join(", "),
+        )))
     }
 
-    /// If any variants share the same name, append the payload arity as a
-    /// suffix to each duplicate. Unique names are left untouched.
-    fn disambiguate_variants(variants: Vec<e::EnumVariant>) -> Vec<e::EnumVariant> {
-        // Count occurrences of each name
-        let mut counts: HashMap<String, usize> = HashMap::new();
-        for v in &variants {
-            *counts.entry(v.name.clone()).or_insert(0) += 1;
+    /// `@wasm_export` on a class: a `#[wasm_bindgen]` wrapper class with the
+    /// same JS name, a constructor taking the fields, and a getter per field.
+    fn lower_wasm_class(&mut self, items: &[e::Item]) -> Result<e::Item, ParseError> {
+        let Some(e::Item::Struct(def)) = items.first() else {
+            return Err(self.error("@wasm_export applies to functions and classes".into()));
+        };
+        if !def.type_params.is_empty() {
+            return Err(self.error(format!(
+                "@wasm_export on '{}': generic classes cannot be exported",
+                def.name
+            )));
         }
+        let name = &def.name;
+        let wrapper = format!("__QuicheWasm{name}");
+        let mut params = Vec::new();
+        let mut inits = Vec::new();
+        let mut getters = String::new();
+        for field in &def.fields {
+            let ty = self.wasm_type(&field.ty).ok_or_else(|| {
+                self.error(format!(
+                    "@wasm_export on '{name}': field '{}' has type '{}', which cannot cross to JavaScript",
+                    field.name,
+                    rust_type(&field.ty)
+                ))
+            })?;
+            params.push(format!("{}: {}", field.name, ty.js()));
+            inits.push(format!("{}: {}", field.name, ty.to_quiche(&field.name)));
+            getters.push_str(&format!(
+                "\n    #[wasm_bindgen(getter)]\n    pub fn {0}(&self) -> {1} {{\n        {2}\n    }}\n",
+                field.name,
+                ty.js(),
+                ty.to_js(&format!("self.0.{}.clone()", field.name)),
+            ));
+        }
+        self.wasm_classes.insert(name.clone());
+        self.extern_crates.insert("wasm_bindgen".to_string());
+        Ok(e::Item::RustBlock(format!(
+            "#[cfg(target_arch = \"wasm32\")]\n\
+             #[wasm_bindgen::prelude::wasm_bindgen(js_name = {name})]\n\
+             pub struct {wrapper}({name});\n\n\
+             #[cfg(target_arch = \"wasm32\")]\n\
+             #[wasm_bindgen::prelude::wasm_bindgen(js_class = {name})]\n\
+             impl {wrapper} {{\n    \
+             #[wasm_bindgen(constructor)]\n    \
+             pub fn new({}) -> {wrapper} {{\n        {wrapper}({name} {{ {} }})\n    }}\n{getters}}}",
+            params.join(", "),
+            inits.join(", "),
+        )))
+    }
 
-        // Only rename if a name appears more than once
-        variants
-            .into_iter()
-            .map(|mut v| {
-                if counts.get(&v.name).copied().unwrap_or(0) > 1 {
-                    let arity = match &v.fields {
-                        e::EnumVariantFields::Unit => 0,
-                        e::EnumVariantFields::Tuple(types) => types.len(),
-                        e::EnumVariantFields::Named(fields) => fields.len(),
-                    };
-                    v.name = format!("{}__a{}", v.name, arity);
-                }
-                v
-            })
-            .collect()
+    /// How `ty` crosses the wasm-bindgen boundary, if it can.
+    fn wasm_type(&self, ty: &e::Type) -> Option<WasmType> {
+        if let Some(c) = c_type(ty).filter(|t| t.quiche == t.c) {
+            return Some(WasmType::Plain(c.c));
+        }
+        match (ty.path.as_slice(), ty.args.as_slice()) {
+            ([name], []) if name == "Str" => Some(WasmType::Str),
+            ([name], []) if self.wasm_classes.contains(name) => Some(WasmType::Class(name.clone())),
+            ([name], [elem]) if name == "List" => c_type(elem)
+                .filter(|t| t.quiche == t.c && t.c != "bool")
+                .map(|t| WasmType::List(t.c)),
+            _ => None,
+        }
     }
 
-    // ─────────────────────────────────────────────────────────────────────────
-    // type Keyword → Struct / Enum / Union
-    // ─────────────────────────────────────────────────────────────────────────
-
-    /// Parse a `type` definition:
+    /// `@impl(Trait)` on a class: implement a standard Rust trait by
+    /// delegating to the class's own methods.
     ///
-    ///   Struct: `type Point:\n    x: i32\n    y: i32`
-    ///   Enum:   `type Color = | Red | Green | Blue(i32)`
-    fn parse_type_def(&mut self) -> Result<Vec<e::Item>, ParseError> {
-        let type_start = self.current.start;
-        self.expect_kw(Keyword::Type)?;
-        let name = self.expect_ident()?;
-        let type_params = self.parse_type_params()?;
+    /// - `Iterator` — `next(self) -> Option[T]`; `Item` is `T`.
+    /// - `Default` — a static `default()` if defined, else each field's default.
+    /// - `From[T]` — the static method taking a single `T` (`from` itself is
+    ///   a keyword, so its name is free).
+    /// - a `@trait` of this module — each of its methods.
+    fn lower_trait_impls(
+        &self,
+        items: &[e::Item],
+        decorators: &[Decorator],
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let traits: Vec<&e::Expr> = decorators
+            .iter()
+            .filter(|d| d.name == "impl")
+            .flat_map(|d| &d.args)
+            .collect();
+        if traits.is_empty() {
+            return Ok(vec![]);
+        }
+        let Some(e::Item::Struct(def)) = items.first() else {
+            return Err(self.error("@impl applies to classes (types with fields)".into()));
+        };
+        let methods: &[e::FunctionDef] = items
+            .iter()
+            .find_map(|item| match item {
+                e::Item::Impl(block) => Some(block.methods.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let is_static = |f: &&e::FunctionDef| f.params.first().is_none_or(|p| p.name != "self");
+        let name = &def.name;
+        let (generics, target) = if def.type_params.is_empty() {
+            (String::new(), name.clone())
+        } else {
+            let params: Vec<String> = def
+                .type_params
+                .iter()
+                .map(|p| {
+                    let bounds: Vec<String> = p.bounds.iter().map(rust_type).collect();
+                    if bounds.is_empty() {
+                        p.name.clone()
+                    } else {
+                        format!("{}: {}", p.name, bounds.join(" + "))
+                    }
+                })
+                .collect();
+            let args: Vec<&str> = def.type_params.iter().map(|p| p.name.as_str()).collect();
+            (
+                format!("<{}>", params.join(", ")),
+                format!("{name}<{}>", args.join(", ")),
+            )
+        };
 
-        if self.eat(&TokenKind::Eq)? {
-            // Union shorthand: `type Number = i64 | f64`
-            // If first token is a lowercase identifier, treat as union types
-            let is_union = match self.kind() {
-                TokenKind::Ident(id) => id.starts_with(char::is_lowercase),
-                _ => false,
+        let mut impls = Vec::new();
+        for expr in traits {
+            let Some(trait_ty) = decorator_type(expr) else {
+                return Err(self.error(format!(
+                    "@impl on '{name}' expects a trait, e.g. @impl(Default)"
+                )));
             };
-
-            if is_union {
-                let mut union_types = vec![self.parse_type()?];
-                while self.eat(&TokenKind::Pipe)? {
-                    union_types.push(self.parse_type()?);
+            let code = match (trait_ty.path.join("::").as_str(), trait_ty.args.as_slice()) {
+                ("Iterator", []) => {
+                    let next = methods.iter().find(|f| f.name == "next" && !is_static(f));
+                    let item = next
+                        .and_then(|f| f.return_type.as_ref())
+                        .filter(|ty| ty.path == ["Option"])
+                        .and_then(|ty| ty.args.first());
+                    let Some(item) = item else {
+                        return Err(self.error(format!(
+                            "@impl(Iterator) on '{name}' needs a method `def next(self) -> Option[T]`"
+                        )));
+                    };
+                    let item = rust_type(item);
+                    format!(
+                        "impl{generics} Iterator for {target} {{\n    type Item = {item};\n    \
+                         fn next(&mut self) -> Option<{item}> {{\n        {name}::next(self)\n    }}\n}}"
+                    )
                 }
-                let variants = union_types
-                    .into_iter()
-                    .map(|ty| {
-                        let variant_name = Self::type_to_variant_name(&ty);
-                        e::EnumVariant {
-                            name: variant_name,
-                            fields: e::EnumVariantFields::Tuple(vec![ty]),
+                ("Default", []) => {
+                    let body = if methods.iter().any(|f| f.name == "default" && is_static(&f)) {
+                        format!("{name}::default()")
+                    } else {
+                        let fields: Vec<String> = def
+                            .fields
+                            .iter()
+                            .map(|f| format!("{}: Default::default()", f.name))
+                            .collect();
+                        format!("{name} {{ {} }}", fields.join(", "))
+                    };
+                    format!(
+                        "impl{generics} Default for {target} {{\n    fn default() -> Self {{\n        {body}\n    }}\n}}"
+                    )
+                }
+                ("From", [source]) => {
+                    let source_ty = rust_type(source);
+                    let ctor = methods
+                        .iter()
+                        .filter(is_static)
+                        .find(|f| f.params.len() == 1 && rust_type(&f.params[0].ty) == source_ty);
+                    let Some(ctor) = ctor else {
+                        return Err(self.error(format!(
+                            "@impl(From[{source_ty}]) on '{name}' needs a static method taking one {source_ty}"
+                        )));
+                    };
+                    format!(
+                        "impl{generics} From<{source_ty}> for {target} {{\n    fn from(value: {source_ty}) -> Self {{\n        {name}::{}(value)\n    }}\n}}",
+                        ctor.name
+                    )
+                }
+                ("ContextManager", []) => {
+                    let steps: Vec<String> = ["enter", "exit"]
+                        .into_iter()
+                        .filter(|step| methods.iter().any(|f| f.name == *step && !is_static(&f)))
+                        .map(|step| {
+                            format!("    fn {step}(&mut self) {{\n        {name}::{step}(self)\n    }}\n")
+                        })
+                        .collect();
+                    if steps.is_empty() {
+                        return Err(self.error(format!(
+                            "@impl(ContextManager) on '{name}' needs a method `def enter(self)` or `def exit(self)`"
+                        )));
+                    }
+                    format!(
+                        "impl{generics} ContextManager for {target} {{\n{}}}",
+                        steps.concat()
+                    )
+                }
+                (trait_name, []) if let Some(signatures) = self.traits.get(trait_name) => {
+                    let mut delegates = Vec::new();
+                    for signature in signatures {
+                        let method = &signature.name;
+                        if !methods.iter().any(|f| f.name == *method && !is_static(&f)) {
+                            return Err(self.error(format!(
+                                "@impl({trait_name}) on '{name}' needs a method `def {method}(self, ...)`"
+                            )));
                         }
-                    })
-                    .collect();
-                return Ok(vec![e::Item::Enum(e::EnumDef {
-                    visibility: e::Visibility::Public,
-                    name,
-                    type_params,
-                    variants,
-                    span: self.span_from(type_start),
-                })]);
-            }
-
-            // Enum variant list: `type Color = Red | Green | Blue(i32)`
-            let variants = self.parse_variant_list()?;
-            return Ok(vec![e::Item::Enum(e::EnumDef {
-                visibility: e::Visibility::Public,
-                name,
-                type_params,
-                variants,
-                span: self.span_from(type_start),
-            })]);
-        }
-
-        // ── Struct form: `type Name:\n    field: Type` ───────────────
-        self.expect(&TokenKind::Colon)?;
-        let (fields, methods) = self.parse_type_struct_body()?;
-
-        // Register field names for positional struct construction
-        self.struct_fields.insert(
-            name.clone(),
-            fields.iter().map(|f| f.name.clone()).collect(),
-        );
-
-        let mut items = vec![e::Item::Struct(e::StructDef {
-            visibility: e::Visibility::Public,
-            name,
-            type_params,
-            fields,
-            span: self.span_from(type_start),
-        })];
-
-        if !methods.is_empty() {
-            let (name, type_params) = match &items[0] {
-                e::Item::Struct(s) => (s.name.clone(), s.type_params.clone()),
-                _ => unreachable!(),
+                        let args: Vec<&str> =
+                            signature.params.iter().map(|p| p.name.as_str()).collect();
+                        delegates.push(format!(
+                            "    {} {{\n        {name}::{method}({})\n    }}\n",
+                            trait_method_signature(signature),
+                            args.join(", ")
+                        ));
+                    }
+                    format!(
+                        "impl{generics} {trait_name} for {target} {{\n{}}}",
+                        delegates.concat()
+                    )
+                }
+                _ => {
+                    return Err(self.error(format!(
+                        "@impl({}) on '{name}' is not supported; supported traits are Iterator, Default, From[T], ContextManager and @trait types",
+                        rust_type(&trait_ty)
+                    )));
+                }
             };
-            let target_args = type_params
-                .iter()
-                .map(|param| e::Type {
-                    path: vec![param.name.clone()],
-                    args: vec![],
-                    trait_bounds: vec![],
-                })
-                .collect();
-            items.push(e::Item::Impl(e::ImplBlock {
-                type_params,
-                target: name,
-                target_args,
-                trait_target: None,
-                methods,
-                span: self.span_from(type_start),
-            }));
+            impls.push(e::Item::RustBlock(code));
         }
-
-        Ok(items)
+        Ok(impls)
     }
 
-    fn parse_type_struct_body(&mut self) -> Result<(Vec<e::Field>, Vec<e::FunctionDef>), ParseError> {
-        self.skip_newlines()?;
-        self.expect(&TokenKind::Indent)?;
-
-        let mut fields = Vec::new();
-        let mut methods = Vec::new();
-
-        while !self.check(&TokenKind::Dedent) && !self.check(&TokenKind::Eof) {
-            self.skip_newlines()?;
-            if self.check(&TokenKind::Dedent) || self.check(&TokenKind::Eof) {
-                break;
-            }
-
-            if self.check_kw(Keyword::Def) {
-                methods.push(self.parse_function_def()?);
-                self.skip_newlines()?;
-                continue;
+    /// `@derive(Hash, Default, Serialize)` on a type: extra traits for its
+    /// `#[derive(...)]` list, left as a `// quiche:derive Point Hash, Default`
+    /// marker line. The traits a derive builds on come along (`Eq` brings
+    /// `PartialEq`, `Copy` brings `Clone`), and the serde traits record the
+    /// `serde` dependency.
+    fn lower_derives(
+        &mut self,
+        items: &[e::Item],
+        decorators: &[Decorator],
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let args: Vec<&e::Expr> = decorators
+            .iter()
+            .filter(|d| d.name == "derive")
+            .flat_map(|d| &d.args)
+            .collect();
+        if args.is_empty() {
+            return Ok(vec![]);
+        }
+        let (name, is_enum) = match items.first() {
+            Some(e::Item::Struct(def)) => (def.name.clone(), false),
+            Some(e::Item::Enum(def)) => (def.name.clone(), true),
+            _ => return Err(self.error("@derive applies to classes and enums".into())),
+        };
+        let mut traits: Vec<&str> = Vec::new();
+        for arg in args {
+            let derive = match arg {
+                e::Expr::Path(path) if path.len() == 1 => DERIVABLE
+                    .iter()
+                    .find(|(trait_name, _)| *trait_name == path[0]),
+                _ => None,
+            };
+            let Some(&(trait_name, implied)) = derive else {
+                let known: Vec<&str> = DERIVABLE.iter().map(|(t, _)| *t).collect();
+                return Err(self.error(format!(
+                    "@derive on '{name}' expects derivable traits ({})",
+                    known.join(", ")
+                )));
+            };
+            if is_enum && trait_name == "Default" {
+                return Err(self.error(format!(
+                    "@derive(Default) on enum '{name}' is not supported; use @impl(Default) on a class or a static default() method"
+                )));
             }
-
-            if matches!(self.kind(), TokenKind::Ident(_)) {
-                let name = self.expect_ident()?;
-                self.expect(&TokenKind::Colon)?;
-                let ty = self.parse_type()?;
-                fields.push(e::Field { name, ty });
-                self.skip_newlines()?;
-                continue;
+            for t in implied.iter().chain([&trait_name]) {
+                if !traits.contains(t) {
+                    traits.push(t);
+                }
             }
-
-            return Err(self.error(
-                "expected field declaration (`name: Type`) or method (`def ...`) in type body"
-                    .into(),
-            ));
         }
-
-        if self.check(&TokenKind::Dedent) {
-            self.advance()?;
-        }
-
-        Ok((fields, methods))
+        let traits: Vec<&str> = traits
+            .into_iter()
+            .map(|t| match t {
+                "Serialize" | "Deserialize" => {
+                    self.extern_crates.insert("serde".to_string());
+                    if t == "Serialize" {
+                        "serde::Serialize"
+                    } else {
+                        "serde::Deserialize"
+                    }
+                }
+                _ => t,
+            })
+            .collect();
+        Ok(vec![e::Item::RustBlock(format!(
+            "// quiche:derive {name} {}",
+            traits.join(", ")
+        ))])
     }
 
-    /// Convert a Type to a PascalCase variant name for union enum generation.
-    /// Examples: `i64` → `I64`, `String` → `String`, `Vec[i32]` → `VecI32`
-    fn type_to_variant_name(ty: &e::Type) -> String {
-        let base = ty.path.last().unwrap_or(&String::new()).clone();
-        let mut name = String::new();
-        // Capitalize first letter
-        let mut chars = base.chars();
-        if let Some(first) = chars.next() {
-            name.push(first.to_ascii_uppercase());
-            name.extend(chars);
-        }
-        // Append generic args
-        for arg in &ty.args {
-            let arg_name = Self::type_to_variant_name(arg);
-            name.push_str(&arg_name);
-        }
-        name
+    fn is_yield(stmt: &e::Stmt) -> bool {
+        matches!(stmt, e::Stmt::Expr(e::Expr::MacroCall { path, .. }) if path.len() == 1 && path[0] == "__yield__")
     }
 
+    // ─────────────────────────────────────────────────────────────────────────
+    // Imports: from X.Y import Z → RustUse { tree: UseTree }
     // ─────────────────────────────────────────────────────────────────────────
 
-    fn parse_block(&mut self) -> Result<e::Block, ParseError> {
-        let mut statements = Vec::new();
+    fn path_to_use_tree(path: Vec<String>) -> e::UseTree {
+        let mut iter = path.into_iter().rev();
+        let leaf = e::UseTree::Name(iter.next().unwrap());
+        iter.fold(leaf, |next, segment| e::UseTree::Path {
+            segment,
+            next: Box::new(next),
+        })
+    }
 
-        // Inline single-statement (e.g., `if x: return 1`)
-        if !self.check(&TokenKind::Newline) && !self.check(&TokenKind::Indent) {
-            statements.push(self.parse_stmt()?);
-            return Ok(e::Block { statements });
+    fn parse_from_import(&mut self) -> Result<Vec<e::Item>, ParseError> {
+        self.expect_kw(Keyword::From)?;
+        let mut module_path = vec![self.expect_ident()?];
+        while self.eat(&TokenKind::Dot)? {
+            module_path.push(self.expect_ident()?);
         }
+        self.expect_kw(Keyword::Import)?;
 
-        self.skip_newlines()?;
-        self.expect(&TokenKind::Indent)?;
+        // Parse comma-separated names: from X.Y import A, B, C
+        let mut items = Vec::new();
+        let mut names = Vec::new();
+        loop {
+            let name_start = self.current.start;
+            let name = self.expect_ident()?;
+            let mut path = module_path.clone();
+            path.push(name.clone());
 
-        while !self.check(&TokenKind::Dedent) && !self.check(&TokenKind::Eof) {
-            self.skip_newlines()?;
-            if self.check(&TokenKind::Dedent) || self.check(&TokenKind::Eof) {
+            // Consume optional "as alias" — the generated `use` ignores
+            // aliases for now
+            let alias = if self.check_kw(Keyword::As) {
+                self.advance()?;
+                self.expect_ident()?
+            } else {
+                name.clone()
+            };
+            names.push((name, alias));
+            let tree = Self::path_to_use_tree(path);
+            items.push(e::Item::RustUse(e::RustUse {
+                tree,
+                span: self.span_from(name_start),
+            }));
+
+            if !self.eat(&TokenKind::Comma)? {
                 break;
             }
-            statements.push(self.parse_stmt()?);
-            self.skip_newlines()?;
         }
+        self.imports.push(Import {
+            module: module_path.join("."),
+            names,
+        });
+        Ok(items)
+    }
 
-        if self.check(&TokenKind::Dedent) {
+    fn parse_bare_import(&mut self) -> Result<(), ParseError> {
+        self.expect_kw(Keyword::Import)?;
+        let mut module_path = vec![self.expect_ident()?];
+        while self.eat(&TokenKind::Dot)? {
+            module_path.push(self.expect_ident()?);
+        }
+        // `import a.b as c`: as with `from`, the alias is ignored for now
+        if self.check_kw(Keyword::As) {
             self.advance()?;
+            self.expect_ident()?;
         }
-
-        Ok(e::Block { statements })
+        self.imports.push(Import {
+            module: module_path.join("."),
+            names: Vec::new(),
+        });
+        Ok(())
     }
 
     // ─────────────────────────────────────────────────────────────────────────
-    // Statements
+    // Functions
     // ─────────────────────────────────────────────────────────────────────────
 
-    fn parse_stmt(&mut self) -> Result<e::Stmt, ParseError> {
-        match self.kind() {
-            TokenKind::Keyword(Keyword::Return) => self.parse_return(),
-            TokenKind::Keyword(Keyword::If) => self.parse_if_or_elif(),
-            TokenKind::Keyword(Keyword::While) => self.parse_while(),
-            TokenKind::Keyword(Keyword::For) => self.parse_for(),
-            TokenKind::Keyword(Keyword::Match) => self.parse_match(),
-            TokenKind::Keyword(Keyword::Pass) => {
-                self.advance()?;
-                Ok(e::Stmt::Expr(e::Expr::Tuple(vec![])))
-            }
-            TokenKind::Keyword(Keyword::Break) => {
-                self.advance()?;
-                Ok(e::Stmt::Break)
-            }
-            TokenKind::Keyword(Keyword::Continue) => {
-                self.advance()?;
-                Ok(e::Stmt::Continue)
-            }
-            TokenKind::Keyword(Keyword::Def) => {
-                // Nested function def — parse but skip (not supported in Elevate stmt pos)
-                let _func = self.parse_function_def()?;
-                Ok(e::Stmt::Expr(e::Expr::Tuple(vec![])))
-            }
-            TokenKind::Keyword(Keyword::Assert) => self.parse_assert(),
-            _ => self.parse_expr_or_assign(),
-        }
-    }
-
-    fn parse_return(&mut self) -> Result<e::Stmt, ParseError> {
-        self.expect_kw(Keyword::Return)?;
-        if matches!(
-            self.kind(),
-            TokenKind::Newline | TokenKind::Dedent | TokenKind::Eof
-        ) {
-            Ok(e::Stmt::Return(None))
-        } else {
-            Ok(e::Stmt::Return(Some(self.parse_expr()?)))
-        }
-    }
-
-    /// `assert expr` → `assert!(expr)`
-    /// `assert expr, "msg"` → `assert!(expr, "{}", msg)`
-    fn parse_assert(&mut self) -> Result<e::Stmt, ParseError> {
-        self.expect_kw(Keyword::Assert)?;
-        let condition = self.parse_expr()?;
+    fn parse_function_def(&mut self) -> Result<e::FunctionDef, ParseError> {
+        let start_pos = self.current.start;
+        self.expect_kw(Keyword::Def)?;
+        let name = self.expect_ident()?;
 
-        let mut args = vec![condition];
+        // Type params [T, U]
+        let mut type_params = self.parse_type_params()?;
 
-        // Check for optional message: `assert expr, "message"`
-        if matches!(self.kind(), TokenKind::Comma) {
-            self.advance()?; // consume comma
-            let msg = self.parse_expr()?;
-            args.push(e::Expr::String("{}".to_string()));
-            args.push(msg);
+        // Params
+        self.expect(&TokenKind::LParen)?;
+        let signature = self.parse_signature()?;
+        self.expect(&TokenKind::RParen)?;
+        let mut params = signature.params;
+        for param in &mut params {
+            self.trait_param(&mut param.ty);
         }
 
-        Ok(e::Stmt::Expr(e::Expr::MacroCall {
-            path: vec!["assert".to_string()],
-            args,
-        }))
-    }
-
-    fn parse_if_or_elif(&mut self) -> Result<e::Stmt, ParseError> {
-        // Consume either `if` or `elif`
-        self.advance()?;
-        let condition = self.parse_expr()?;
-        self.expect(&TokenKind::Colon)?;
-        let then_block = self.parse_block()?;
-
-        // elif chains
-        let mut else_block = None;
-
-        if self.check_kw(Keyword::Elif) {
-            // elif becomes nested if in else
-            let elif_stmt = self.parse_if_or_elif()?;
-            else_block = Some(e::Block {
-                statements: vec![elif_stmt],
-            });
-        } else if self.check_kw(Keyword::Else) {
-            self.advance()?;
-            self.expect(&TokenKind::Colon)?;
-            else_block = Some(self.parse_block()?);
+        // Register parameter names and defaults for call-site binding
+        self.fn_defaults
+            .retain(|(function, _), _| *function != name);
+        for (param, value) in signature.defaults {
+            self.fn_defaults.insert((name.clone(), param), value);
         }
+        self.fn_params.insert(name.clone(), signature.names);
 
-        Ok(e::Stmt::If {
-            condition,
-            then_block,
-            else_block,
-        })
-    }
+        // Return type
+        let return_type = if self.eat(&TokenKind::Arrow)? {
+            let mut ty = self.parse_type()?;
+            if self.check(&TokenKind::Pipe) {
+                Some(self.parse_union_return(&name, ty)?)
+            } else {
+                self.box_traits(&mut ty);
+                Some(ty)
+            }
+        } else {
+            None
+        };
+        self.parse_where_clause(&mut type_params)?;
 
-    fn parse_while(&mut self) -> Result<e::Stmt, ParseError> {
-        self.expect_kw(Keyword::While)?;
-        let condition = self.parse_expr()?;
         self.expect(&TokenKind::Colon)?;
-        let body = self.parse_block()?;
-        Ok(e::Stmt::While { condition, body })
-    }
+        let first_probe = self.probes.len();
+        let outer_assigns = std::mem::take(&mut self.first_assigns);
+        let returns_result = return_type.as_ref().is_some_and(|ty| ty.path == ["Result"]);
+        let outer_raise = std::mem::replace(&mut self.raise_into_result, returns_result);
+        let body = self.parse_block();
+        self.raise_into_result = outer_raise;
+        let mut body = body?;
+        let mut known: HashMap<String, e::Type> = params
+            .iter()
+            .map(|p| (p.name.clone(), p.ty.clone()))
+            .collect();
+        let first_assigns = std::mem::replace(&mut self.first_assigns, outer_assigns);
+        infer_empty_collections(&mut body, &mut known, &first_assigns);
+        for param in &params {
+            let mutable = match param.ty.path.as_slice() {
+                [mode] if mode == "Ref" => false,
+                [mode] if mode == "MutRef" => true,
+                _ => continue,
+            };
+            if let Some(problem) = borrow_violation(&body.statements, &param.name, mutable) {
+                return Err(self.error(format!("in '{name}': {problem}")));
+            }
+        }
+        if let Some(problem) = thread_violation(&body, &known) {
+            return Err(self.error(format!("in '{name}': {problem}")));
+        }
+        let mut assigned = params.iter().map(|p| p.name.clone()).collect();
+        if let Some(problem) = try_scope_violation(&body.statements, &mut assigned, HashSet::new())
+        {
+            return Err(self.error(format!("in '{name}': {problem}")));
+        }
+        if let Some(union) = self.union_returns.get(&name) {
+            wrap_union_returns(&mut body, union, &self.union_enums[union], &known);
+        }
+        self.box_trait_values(&mut body, &known, return_type.as_ref());
+        if self.probes.len() > first_probe {
+            let lines = self.probes.split_off(first_probe);
+            self.fn_probes
+                .entry(name.clone())
+                .or_default()
+                .extend(lines);
+        }
 
-    fn parse_for(&mut self) -> Result<e::Stmt, ParseError> {
-        self.expect_kw(Keyword::For)?;
-        let binding_name = self.expect_ident()?;
-        let binding = e::DestructurePattern::Name(binding_name);
-        self.expect_kw(Keyword::In)?;
-        let iter = self.parse_expr()?;
-        self.expect(&TokenKind::Colon)?;
-        let body = self.parse_block()?;
-        Ok(e::Stmt::For {
-            binding,
-            iter,
+        Ok(e::FunctionDef {
+            visibility: e::Visibility::Public,
+            name,
+            type_params,
+            params,
+            return_type,
+            effect_row: None,
             body,
+            span: self.span_from(start_pos),
         })
     }
 
-    fn parse_match(&mut self) -> Result<e::Stmt, ParseError> {
-        self.expect_kw(Keyword::Match)?;
-        let scrutinee = self.parse_expr()?;
-        self.expect(&TokenKind::Colon)?;
-
-        self.skip_newlines()?;
-        self.expect(&TokenKind::Indent)?;
-
-        let mut arms = Vec::new();
-        while !self.check(&TokenKind::Dedent) && !self.check(&TokenKind::Eof) {
-            self.skip_newlines()?;
-            if self.check(&TokenKind::Dedent) {
+    fn parse_type_params(&mut self) -> Result<Vec<e::GenericParam>, ParseError> {
+        if !self.eat(&TokenKind::LBracket)? {
+            return Ok(vec![]);
+        }
+        let mut params = Vec::new();
+        loop {
+            if self.check(&TokenKind::RBracket) {
                 break;
             }
-            self.expect_kw(Keyword::Case)?;
-            let pattern = self.parse_pattern()?;
-            let guard = if self.check_kw(Keyword::If) {
-                self.advance()?;
-                Some(self.parse_expr()?)
+            let name = self.expect_ident()?;
+            // Parse optional trait bounds: T: Display or T: Display + Debug
+            let bounds = if self.eat(&TokenKind::Colon)? {
+                let mut bounds = Vec::new();
+                bounds.extend(expand_bound(self.parse_type()?));
+                while self.eat(&TokenKind::Plus)? {
+                    bounds.extend(expand_bound(self.parse_type()?));
+                }
+                bounds
             } else {
-                None
+                vec![]
             };
-            self.expect(&TokenKind::Colon)?;
-            let body = self.parse_block()?;
-            // Match arm value: wrap body in IIFE if multiple stmts
-            let value = self.block_to_expr(body);
-            arms.push(e::MatchArm {
-                pattern,
-                guard,
-                value,
-            });
-            self.skip_newlines()?;
-        }
-
-        if self.check(&TokenKind::Dedent) {
-            self.advance()?;
+            params.push(e::GenericParam { name, bounds });
+            if !self.eat(&TokenKind::Comma)? {
+                break;
+            }
         }
-
-        Ok(e::Stmt::Expr(e::Expr::Match {
-            scrutinee: Box::new(scrutinee),
-            arms,
-        }))
+        self.expect(&TokenKind::RBracket)?;
+        Ok(params)
     }
 
-    fn block_to_expr(&self, block: e::Block) -> e::Expr {
-        let stmts = block.statements;
-        if stmts.is_empty() {
-            return e::Expr::Tuple(vec![]);
+    /// An optional `where T: Display, U: Into[T]` clause. Each constraint is
+    /// added to its type parameter's bounds, which Rust treats the same as a
+    /// where-clause; constraints on anything but a type parameter are an
+    /// error.
+    fn parse_where_clause(
+        &mut self,
+        type_params: &mut [e::GenericParam],
+    ) -> Result<(), ParseError> {
+        if !matches!(self.kind(), TokenKind::Ident(word) if word == "where") {
+            return Ok(());
         }
-        if stmts.len() == 1 {
-            return match stmts.into_iter().next().unwrap() {
-                e::Stmt::Return(Some(val)) => val,
-                e::Stmt::Expr(val) => val,
-                other => e::Expr::Closure {
-                    params: vec![],
-                    return_type: None,
-                    body: e::Block {
-                        statements: vec![other],
-                    },
-                },
+        self.advance()?;
+        loop {
+            let name = self.expect_ident()?;
+            let Some(param) = type_params.iter_mut().find(|param| param.name == name) else {
+                return Err(self.error(format!(
+                    "where-clause constrains '{name}', which is not a type parameter"
+                )));
             };
+            self.expect(&TokenKind::Colon)?;
+            param.bounds.extend(expand_bound(self.parse_type()?));
+            while self.eat(&TokenKind::Plus)? {
+                param.bounds.extend(expand_bound(self.parse_type()?));
+            }
+            if !self.eat(&TokenKind::Comma)? {
+                return Ok(());
+            }
         }
-        // Multiple statements → IIFE: (|| { stmts })()
-        e::Expr::Call {
-            callee: Box::new(e::Expr::Closure {
-                params: vec![],
-                return_type: None,
-                body: e::Block { statements: stmts },
-            }),
-            args: vec![],
+    }
+
+    /// A closure's parameters, which take no defaults.
+    fn parse_params(&mut self) -> Result<Vec<e::Param>, ParseError> {
+        let signature = self.parse_signature()?;
+        if !signature.defaults.is_empty() || signature.names.iter().any(|n| n.starts_with('*')) {
+            return Err(self.error(
+                "default, keyword-only and variadic parameters are only supported on a `def`"
+                    .into(),
+            ));
         }
+        Ok(signature.params)
     }
 
-    fn parse_pattern(&mut self) -> Result<e::Pattern, ParseError> {
-        match self.kind().clone() {
-            TokenKind::Keyword(Keyword::True) => {
-                self.advance()?;
-                Ok(e::Pattern::Bool(true))
-            }
-            TokenKind::Keyword(Keyword::False) => {
-                self.advance()?;
-                Ok(e::Pattern::Bool(false))
-            }
-            TokenKind::Keyword(Keyword::None) => {
-                self.advance()?;
-                Ok(e::Pattern::Variant {
-                    path: vec!["None".to_string()],
-                    payload: None,
-                })
+    /// A `def`'s parameters: `name: T`, `name: T = default`, a bare `*`
+    /// after which parameters can only be passed by keyword, and the
+    /// variadic `*args: T` and `**kwargs: T`, which take the extra positional
+    /// arguments as a `List[T]` and the extra keyword ones as a
+    /// `Dict[Str, T]` (see [`Self::bind_call_args`]).
+    fn parse_signature(&mut self) -> Result<Signature, ParseError> {
+        let mut params = Vec::new();
+        let mut names: Vec<String> = Vec::new();
+        let mut defaults: Vec<(String, String)> = Vec::new();
+        let mut keyword_only = false;
+        while !self.check(&TokenKind::RParen) {
+            if let Some(kwargs) = names.iter().find(|n| n.starts_with("**")) {
+                return Err(self.error(format!("'{kwargs}' must be the last parameter")));
             }
-            TokenKind::Int(n) => {
-                let v = n;
+            if self.check(&TokenKind::Star)
+                && matches!(self.peek()?.kind, TokenKind::Comma | TokenKind::RParen)
+            {
+                if keyword_only {
+                    return Err(self.error("'*' may appear only once in a parameter list".into()));
+                }
                 self.advance()?;
-                Ok(e::Pattern::Int(v))
+                if !self.eat(&TokenKind::Comma)? || self.check(&TokenKind::RParen) {
+                    return Err(self.error("named parameters must follow a bare '*'".into()));
+                }
+                keyword_only = true;
+                names.push("*".to_string());
+                continue;
             }
-            TokenKind::String(s) => {
-                let v = s;
+            if self.check(&TokenKind::Star) || self.check(&TokenKind::DoubleStar) {
+                let stars = if self.check(&TokenKind::Star) {
+                    "*"
+                } else {
+                    "**"
+                };
                 self.advance()?;
-                Ok(e::Pattern::String(v))
+                let name = self.expect_ident()?;
+                if stars == "*" && keyword_only {
+                    return Err(self.error(format!(
+                        "'*{name}' can't follow a bare '*' or another '*args'"
+                    )));
+                }
+                let item = if self.eat(&TokenKind::Colon)? {
+                    self.parse_type()?
+                } else {
+                    e::Type {
+                        path: vec!["_".into()],
+                        args: vec![],
+                        trait_bounds: vec![],
+                    }
+                };
+                if self.check(&TokenKind::Eq) {
+                    return Err(self.error(format!("'{stars}{name}' can't have a default")));
+                }
+                let ty = if stars == "*" {
+                    keyword_only = true;
+                    wrap_type("List", item)
+                } else {
+                    let key = e::Type {
+                        path: vec!["Str".into()],
+                        args: vec![],
+                        trait_bounds: vec![],
+                    };
+                    e::Type {
+                        path: vec!["Dict".into()],
+                        args: vec![key, item],
+                        trait_bounds: vec![],
+                    }
+                };
+                names.push(format!("{stars}{name}"));
+                params.push(e::Param { name, ty });
+                if !self.eat(&TokenKind::Comma)? {
+                    break;
+                }
+                continue;
             }
-            TokenKind::Ident(name) if name == "_" => {
-                self.advance()?;
-                Ok(e::Pattern::Wildcard)
-            }
-            TokenKind::Ident(name) => {
-                self.advance()?;
-                // Check for Enum variant: Name.Variant(payload) or Name(payload)
-                if self.eat(&TokenKind::Dot)? {
-                    let variant = self.expect_ident()?;
-                    let payload = if self.eat(&TokenKind::LParen)? {
-                        let inner = self.parse_pattern()?;
-                        self.expect(&TokenKind::RParen)?;
-                        Some(Box::new(inner))
-                    } else {
-                        None
-                    };
-                    Ok(e::Pattern::Variant {
-                        path: vec![name, variant],
-                        payload,
-                    })
-                } else if self.check(&TokenKind::LParen) {
-                    self.advance()?;
-                    let inner = if self.check(&TokenKind::RParen) {
-                        None
-                    } else {
-                        Some(Box::new(self.parse_pattern()?))
-                    };
-                    self.expect(&TokenKind::RParen)?;
-                    Ok(e::Pattern::Variant {
-                        path: vec![name],
-                        payload: inner,
-                    })
+            let name = self.expect_ident()?;
+            // Emit `self` as a param with type `Self` — Elevate's
+            // type_from_ast_with_impl_self resolves Self → impl target type.
+            if name == "self" {
+                let ty = if self.eat(&TokenKind::Colon)? {
+                    self.parse_type()?
                 } else {
-                    Ok(e::Pattern::Binding(name))
+                    e::Type {
+                        path: vec!["Self".into()],
+                        args: vec![],
+                        trait_bounds: vec![],
+                    }
+                };
+                params.push(e::Param { name, ty });
+                if !self.eat(&TokenKind::Comma)? {
+                    break;
                 }
+                continue;
             }
-            _ => Err(self.error(format!("expected pattern, got {}", self.kind()))),
-        }
-    }
-
-    fn parse_expr_or_assign(&mut self) -> Result<e::Stmt, ParseError> {
-        let stmt_start = self.current.start;
-        let mut expr = self.parse_expr()?;
-
-        // Handle bare comma-separated expressions: `a, *rest, b = items`
-        // Creates an implicit Tuple for destructuring
-        if self.check(&TokenKind::Comma) && !self.check(&TokenKind::Newline) {
-            let mut elems = vec![expr];
-            while self.eat(&TokenKind::Comma)? {
-                if self.check(&TokenKind::Eq)
-                    || self.check(&TokenKind::Newline)
-                    || self.check(&TokenKind::Eof)
-                {
-                    break; // trailing comma
+            let ty = if self.eat(&TokenKind::Colon)? {
+                self.parse_param_type()?
+            } else {
+                // No type annotation — inferred
+                e::Type {
+                    path: vec!["_".into()],
+                    args: vec![],
+                    trait_bounds: vec![],
                 }
-                elems.push(self.parse_expr()?);
+            };
+            if self.eat(&TokenKind::Eq)? {
+                let start = self.current.start;
+                self.parse_expr()?;
+                let text = self.source[start..self.prev_end].replace('\n', " ");
+                defaults.push((name.clone(), text));
+            } else if !keyword_only && !defaults.is_empty() {
+                return Err(self.error(format!(
+                    "parameter '{name}' without a default follows one with a default"
+                )));
+            }
+            names.push(name.clone());
+            params.push(e::Param { name, ty });
+            if !self.eat(&TokenKind::Comma)? {
+                break;
             }
-            expr = e::Expr::Tuple(elems);
         }
+        Ok(Signature {
+            params,
+            names,
+            defaults,
+        })
+    }
 
-        // Check for type annotation: `name: Type = value`
-        if self.check(&TokenKind::Colon) {
-            if let e::Expr::Path(ref path) = expr {
-                if path.len() == 1 {
-                    let name = path[0].clone();
-                    self.advance()?; // consume ':'
-                    let ty = self.parse_type()?;
-                    if self.eat(&TokenKind::Eq)? {
-                        let value = self.parse_expr()?;
-                        return Ok(e::Stmt::Const(e::ConstDef {
-                            visibility: e::Visibility::Private,
-                            name,
-                            ty: Some(ty),
-                            value,
-                            is_const: false,
-                            span: self.span_from(stmt_start),
-                        }));
-                    }
-                    // Bare annotation without value (e.g., field decl in class body)
-                    return Ok(e::Stmt::Const(e::ConstDef {
-                        visibility: e::Visibility::Public,
-                        name,
-                        ty: Some(ty),
-                        value: e::Expr::Tuple(vec![]),
-                        is_const: false,
-                        span: self.span_from(stmt_start),
-                    }));
-                }
+    /// A parameter type, optionally prefixed with a borrow mode: `ref T`
+    /// becomes `Ref[T]` and `mut ref T` becomes `MutRef[T]`, overriding the
+    /// ownership planner's move/clone choice for that parameter.
+    fn parse_param_type(&mut self) -> Result<e::Type, ParseError> {
+        let word = match self.kind() {
+            TokenKind::Ident(word) if word == "mut" || word == "ref" => word.clone(),
+            _ => return self.parse_type(),
+        };
+        let mode = match (word.as_str(), &self.peek()?.kind) {
+            ("mut", TokenKind::Ident(next)) if next == "ref" => {
+                self.advance()?;
+                Some("MutRef")
             }
-        }
+            ("ref", TokenKind::Ident(_)) => Some("Ref"),
+            _ => None,
+        };
+        let Some(mode) = mode else {
+            return self.parse_type();
+        };
+        self.advance()?;
+        Ok(e::Type {
+            path: vec![mode.into()],
+            args: vec![self.parse_type()?],
+            trait_bounds: vec![],
+        })
+    }
 
-        // Assignment: `expr = value` or `expr += value`
-        if self.check(&TokenKind::Eq) {
-            self.advance()?;
-            let value = self.parse_expr()?;
+    // ─────────────────────────────────────────────────────────────────────────
+    // Types
+    // ─────────────────────────────────────────────────────────────────────────
 
-            // Check if LHS is a tuple containing *splat → destructure
-            if let Some(pattern) = self.try_expr_to_destructure(&expr) {
-                return Ok(e::Stmt::DestructureConst {
-                    pattern,
-                    value,
-                    is_const: false,
-                });
-            }
+    pub(crate) fn parse_type(&mut self) -> Result<e::Type, ParseError> {
+        let name = self.expect_ident()?;
+        // Rewrite Quiche primitive type names to newtype names
+        let name = match name.as_str() {
+            "str" => "Str".into(),
+            "list" => "List".into(),
+            "dict" => "Dict".into(),
+            // Gradual typing: `Any` is checked at runtime by quiche-lib's Value
+            "Any" => "Value".into(),
+            _ => name,
+        };
+        let mut path = vec![name];
 
-            let target = self.expr_to_assign_target(expr)?;
-            return Ok(e::Stmt::Assign {
-                target,
-                op: e::AssignOp::Assign,
-                value,
-            });
+        // Dotted path: std.collections.HashMap → ["std", "collections", "HashMap"]
+        while self.eat(&TokenKind::Dot)? {
+            path.push(self.expect_ident()?);
         }
-        if self.check(&TokenKind::PlusEq) {
-            self.advance()?;
-            let value = self.parse_expr()?;
-            let target = self.expr_to_assign_target(expr)?;
-            return Ok(e::Stmt::Assign {
-                target,
-                op: e::AssignOp::AddAssign,
-                value,
-            });
+        if let [name] = path.as_slice() {
+            self.note_collection(name);
         }
-        // Built-in call transformations
-        if let e::Expr::Call {
-            ref callee,
-            ref args,
-        } = expr
-        {
-            if let e::Expr::Path(ref path) = **callee {
-                if path.len() == 1 {
-                    let name = path[0].as_str();
-                    match name {
-                        // rust("code") escape hatch — emit verbatim Rust
-                        "rust" if args.len() == 1 => {
-                            // Unwrap str() wrapping if present (smart strings wrap all literals)
-                            let inner = match &args[0] {
-                                e::Expr::String(code) => Some(code.clone()),
-                                e::Expr::Call {
-                                    callee,
-                                    args: inner_args,
-                                } if matches!(**callee, e::Expr::Path(ref p) if p.len() == 1 && p[0] == "str")
-                                    && inner_args.len() == 1 =>
-                                {
-                                    if let e::Expr::String(code) = &inner_args[0] {
-                                        Some(code.clone())
-                                    } else {
-                                        None
-                                    }
-                                }
-                                _ => None,
-                            };
-                            if let Some(code) = inner {
-                                return Ok(e::Stmt::RustBlock(code));
-                            }
-                        }
-                        // print(a, b, c) → println!("{} {:?} {:?}", a, b, c)
-                        // String literals use {} (no quotes), everything else uses {:?} (Debug)
-                        "print" | "eprint" => {
-                            let macro_name = if name == "print" {
-                                "println"
-                            } else {
-                                "eprintln"
-                            };
-                            let fmt = args.iter().map(|_| "{}").collect::<Vec<_>>().join(" ");
-                            let mut macro_args = vec![e::Expr::String(fmt)];
-                            macro_args.extend(args.iter().cloned());
-                            return Ok(e::Stmt::Expr(e::Expr::MacroCall {
-                                path: vec![macro_name.into()],
-                                args: macro_args,
-                            }));
-                        }
-                        _ => {}
-                    }
+
+        // Generic args: Vec[i32] or HashMap[String, i32]
+        let args = if self.eat(&TokenKind::LBracket)? {
+            let mut args = Vec::new();
+            loop {
+                if self.check(&TokenKind::RBracket) {
+                    break;
+                }
+                args.push(self.parse_type()?);
+                if !self.eat(&TokenKind::Comma)? {
+                    break;
                 }
             }
-        }
+            self.expect(&TokenKind::RBracket)?;
+            args
+        } else {
+            vec![]
+        };
 
-        Ok(e::Stmt::Expr(expr))
+        Ok(e::Type {
+            path,
+            args,
+            trait_bounds: vec![],
+        })
     }
 
-    fn expr_to_assign_target(&self, expr: e::Expr) -> Result<e::AssignTarget, ParseError> {
-        match expr {
-            e::Expr::Path(p) if p.len() == 1 => {
-                Ok(e::AssignTarget::Path(p.into_iter().next().unwrap()))
-            }
-            e::Expr::Field { base, field } => Ok(e::AssignTarget::Field { base, field }),
-            e::Expr::Index { base, index } => Ok(e::AssignTarget::Index { base, index }),
-            _ => Err(self.error("invalid assignment target".into())),
-        }
-    }
+    /// Parse a single variant: `Name`, `Name(T1, T2)`, or `Name(x: T1, y: T2)`.
+    /// A unit variant may carry a discriminant, `Name = 404`, recorded in
+    /// `self.discriminants`.
+    fn parse_variant(&mut self) -> Result<e::EnumVariant, ParseError> {
+        let name = self.expect_ident()?;
 
-    /// Check if an expression is a tuple containing at least one *splat,
-    /// and convert it to a DestructurePattern::Slice.
-    fn try_expr_to_destructure(&self, expr: &e::Expr) -> Option<e::DestructurePattern> {
-        // Single starred expression: *rest = items
-        if Self::is_starred(expr) {
-            let name = Self::starred_name(expr).unwrap();
-            return Some(e::DestructurePattern::Slice {
-                prefix: vec![],
-                rest: Some(name),
-                suffix: vec![],
+        if self.eat(&TokenKind::Eq)? {
+            let negative = self.eat(&TokenKind::Minus)?;
+            let TokenKind::Int(value) = *self.kind() else {
+                return Err(self.error(format!(
+                    "expected an integer discriminant for variant '{name}'"
+                )));
+            };
+            self.advance()?;
+            self.discriminants
+                .push((name.clone(), if negative { -value } else { value }));
+            return Ok(e::EnumVariant {
+                name,
+                fields: e::EnumVariantFields::Unit,
             });
         }
 
-        // Tuple containing starred: a, *rest, b = items
-        let e::Expr::Tuple(elems) = expr else {
-            return None;
-        };
+        if !self.eat(&TokenKind::LParen)? {
+            return Ok(e::EnumVariant {
+                name,
+                fields: e::EnumVariantFields::Unit,
+            });
+        }
 
-        // If no starred element, try plain tuple destructure: a, b = expr
-        if !elems.iter().any(|e| Self::is_starred(e)) {
-            let mut items = Vec::new();
-            for elem in elems {
-                items.push(self.expr_to_destructure_name(elem)?);
-            }
-            return Some(e::DestructurePattern::Tuple(items));
+        // Empty parens: Name() → unit variant
+        if self.eat(&TokenKind::RParen)? {
+            return Ok(e::EnumVariant {
+                name,
+                fields: e::EnumVariantFields::Unit,
+            });
         }
 
-        let mut prefix = Vec::new();
-        let mut rest: Option<String> = None;
-        let mut suffix = Vec::new();
-        let mut after_star = false;
+        // Peek ahead to distinguish named fields (ident : type) vs tuple (type).
+        let is_named = matches!(self.kind(), TokenKind::Ident(_))
+            && matches!(self.peek()?.kind, TokenKind::Colon);
 
-        for elem in elems {
-            if Self::is_starred(elem) {
-                if rest.is_some() {
-                    return None; // multiple stars not allowed
+        if is_named {
+            let mut fields = Vec::new();
+            loop {
+                let field_name = self.expect_ident()?;
+                self.expect(&TokenKind::Colon)?;
+                let ty = self.parse_type()?;
+                fields.push(e::Field {
+                    name: field_name,
+                    ty,
+                });
+                if !self.eat(&TokenKind::Comma)? {
+                    break;
                 }
-                rest = Some(Self::starred_name(elem).unwrap());
-                after_star = true;
-            } else {
-                let pat = self.expr_to_destructure_name(elem)?;
-                if after_star {
-                    suffix.push(pat);
-                } else {
-                    prefix.push(pat);
+            }
+            self.expect(&TokenKind::RParen)?;
+            Ok(e::EnumVariant {
+                name,
+                fields: e::EnumVariantFields::Named(fields),
+            })
+        } else {
+            let mut types = Vec::new();
+            loop {
+                types.push(self.parse_type()?);
+                if !self.eat(&TokenKind::Comma)? {
+                    break;
                 }
             }
+            self.expect(&TokenKind::RParen)?;
+            Ok(e::EnumVariant {
+                name,
+                fields: e::EnumVariantFields::Tuple(types),
+            })
         }
-
-        Some(e::DestructurePattern::Slice {
-            prefix,
-            rest,
-            suffix,
-        })
     }
 
-    fn is_starred(expr: &e::Expr) -> bool {
-        matches!(expr, e::Expr::MacroCall { path, .. } if path.len() == 1 && path[0] == "__star__")
+    /// Parse a `|`-separated variant list. Supports:
+    ///
+    ///   Inline:    `A | B(i32) | C`          (pipe as separator)
+    ///   Inline:    `| A | B(i32) | C`        (optional leading pipe)
+    ///   Multiline: `\n    | A\n    | B\n    | C`
+    fn parse_variant_list(&mut self) -> Result<Vec<e::EnumVariant>, ParseError> {
+        let mut variants = Vec::new();
+
+        // Skip newlines and optional indent (multiline form)
+        self.skip_newlines()?;
+        let in_block = self.eat(&TokenKind::Indent)?;
+
+        // Eat optional leading pipe, then parse first variant
+        self.eat(&TokenKind::Pipe)?;
+        variants.push(self.parse_variant()?);
+        self.skip_newlines()?;
+
+        // Continue with remaining | Variant pairs
+        while self.eat(&TokenKind::Pipe)? {
+            variants.push(self.parse_variant()?);
+            self.skip_newlines()?;
+        }
+
+        if in_block && self.check(&TokenKind::Dedent) {
+            self.advance()?;
+        }
+
+        Ok(Self::disambiguate_variants(variants))
     }
 
-    fn starred_name(expr: &e::Expr) -> Option<String> {
-        if let e::Expr::MacroCall { path, args } = expr {
-            if path.len() == 1 && path[0] == "__star__" {
-                if let Some(e::Expr::Path(p)) = args.first() {
-                    return p.first().cloned();
-                }
+    /// Record an enum's named-field variants, so `Shape.Rect(width=3.0)`
+    /// builds a struct literal and `case Shape.Rect(width=w)` can bind by
+    /// field name.
+    fn register_named_variants(&mut self, def: &e::EnumDef) {
+        for variant in &def.variants {
+            if let e::EnumVariantFields::Named(fields) = &variant.fields {
+                self.struct_fields.insert(
+                    format!("{}::{}", def.name, variant.name),
+                    fields.iter().map(|f| f.name.clone()).collect(),
+                );
+                self.variant_fields
+                    .insert((def.name.clone(), variant.name.clone()), fields.clone());
             }
         }
-        None
     }
 
-    fn expr_to_destructure_name(&self, expr: &e::Expr) -> Option<e::DestructurePattern> {
-        match expr {
-            e::Expr::Path(p) if p.len() == 1 => {
-                if p[0] == "_" {
-                    Some(e::DestructurePattern::Ignore)
-                } else {
-                    Some(e::DestructurePattern::Name(p[0].clone()))
-                }
-            }
-            e::Expr::Tuple(elems) => {
-                let mut items = Vec::new();
-                for elem in elems {
-                    items.push(self.expr_to_destructure_name(elem)?);
-                }
-                Some(e::DestructurePattern::Tuple(items))
-            }
-            _ => None,
+    /// If any variants share the same name, append the payload arity as a
+    /// suffix to each duplicate. Unique names are left untouched.
+    fn disambiguate_variants(variants: Vec<e::EnumVariant>) -> Vec<e::EnumVariant> {
+        // Count occurrences of each name
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for v in &variants {
+            *counts.entry(v.name.clone()).or_insert(0) += 1;
         }
+
+        // Only rename if a name appears more than once
+        variants
+            .into_iter()
+            .map(|mut v| {
+                if counts.get(&v.name).copied().unwrap_or(0) > 1 {
+                    let arity = match &v.fields {
+                        e::EnumVariantFields::Unit => 0,
+                        e::EnumVariantFields::Tuple(types) => types.len(),
+                        e::EnumVariantFields::Named(fields) => fields.len(),
+                    };
+                    v.name = format!("{}__a{}", v.name, arity);
+                }
+                v
+            })
+            .collect()
     }
 
     // ─────────────────────────────────────────────────────────────────────────
-    // Expressions
+    // @trait → Rust trait, and trait objects
     // ─────────────────────────────────────────────────────────────────────────
 
-    fn parse_expr(&mut self) -> Result<e::Expr, ParseError> {
-        let body = self.parse_pipe_expr()?;
-        // Python-style ternary: body if condition else orelse
-        // Desugars to: match condition { true => body, _ => orelse }
-        if self.check_kw(Keyword::If) {
-            self.advance()?;
-            let condition = self.parse_or_expr()?;
-            self.expect_kw(Keyword::Else)?;
-            let orelse = self.parse_expr()?; // right-associative: a if c1 else b if c2 else d
-            return Ok(e::Expr::Match {
-                scrutinee: Box::new(condition),
-                arms: vec![
-                    e::MatchArm {
-                        pattern: e::Pattern::Bool(true),
-                        guard: None,
-                        value: body,
-                    },
-                    e::MatchArm {
-                        pattern: e::Pattern::Wildcard,
-                        guard: None,
-                        value: orelse,
-                    },
-                ],
-            });
+    /// `@trait` on a type of method signatures declares a Rust trait, which
+    /// classes implement with `@impl(Name)`:
+    ///
+    /// ```text
+    /// @trait                            pub trait Drawable {
+    /// type Drawable:                →       fn draw(&self) -> Str;
+    ///     def draw(self) -> str: ...        fn area(&self, scale: f64) -> f64;
+    ///     def area(self, scale: f64) -> f64: ...  }
+    /// ```
+    ///
+    /// Written as a type elsewhere, the trait stands for any implementor: a
+    /// parameter becomes `&dyn Drawable` and anything else `Box<dyn Drawable>`
+    /// (see [`Self::box_traits`]).
+    fn parse_trait_def(&mut self) -> Result<e::Item, ParseError> {
+        self.expect_kw(Keyword::Type)?;
+        let name = self.expect_ident()?;
+        if self.check(&TokenKind::LBracket) {
+            return Err(self.error(format!("@trait '{name}' can't take type parameters")));
         }
-        Ok(body)
+        self.expect(&TokenKind::Colon)?;
+        self.skip_newlines()?;
+        self.expect(&TokenKind::Indent)?;
+        // Known while its own signatures are parsed, which may take or
+        // return another implementor
+        self.traits.insert(name.clone(), Vec::new());
+        // Signatures have no statements to probe
+        let coverage = std::mem::replace(&mut self.coverage, false);
+        let methods = self.parse_trait_methods(&name);
+        self.coverage = coverage;
+        let methods = methods?;
+        self.eat(&TokenKind::Dedent)?;
+
+        let signatures: Vec<String> = methods
+            .iter()
+            .map(|method| format!("    {};\n", trait_method_signature(method)))
+            .collect();
+        let code = format!("pub trait {name} {{\n{}}}", signatures.concat());
+        self.traits.insert(name, methods);
+        Ok(e::Item::RustBlock(code))
     }
 
-    /// Pipe operator: `lhs |> f(args)` desugars to `f(lhs, args)`.
-    /// Left-associative, lowest precedence among binary ops.
-    ///
-    /// Note: `|>` has lower precedence than `==`, so `x |> f() == 3` parses
-    /// as `x |> (f() == 3)` — which is almost never what you want.
-    /// We detect this and emit a helpful error.
-    fn parse_pipe_expr(&mut self) -> Result<e::Expr, ParseError> {
-        let mut left = self.parse_or_expr()?;
-        while self.check(&TokenKind::PipeRight) {
-            self.advance()?;
-            let rhs = self.parse_or_expr()?;
-            // Detect `x |> f() == 3` — the RHS parsed a comparison, which
-            // means the user forgot parens around the pipe chain.
-            if Self::is_comparison(&rhs) {
+    /// The method signatures in the body of `@trait` `name`.
+    fn parse_trait_methods(&mut self, name: &str) -> Result<Vec<e::FunctionDef>, ParseError> {
+        let mut methods = Vec::new();
+        while !self.check(&TokenKind::Dedent) && !self.check(&TokenKind::Eof) {
+            self.skip_newlines()?;
+            match self.kind() {
+                TokenKind::Dedent | TokenKind::Eof => break,
+                // A docstring, or `pass` in a trait without methods
+                TokenKind::String(_) | TokenKind::Keyword(Keyword::Pass) => {
+                    self.advance()?;
+                    continue;
+                }
+                TokenKind::Keyword(Keyword::Def) => {}
+                _ => {
+                    return Err(self.error(format!(
+                        "@trait '{name}' holds method signatures (`def ...`) only"
+                    )));
+                }
+            }
+            let method = self.parse_function_def()?;
+            if method.params.first().is_none_or(|p| p.name != "self") {
                 return Err(self.error(format!(
-                    "|> has lower precedence than comparison operators (==, !=, <, >, etc.).\n\
-                     Wrap the pipe chain in parentheses: (... |> f()) == value"
+                    "method '{name}.{}' of a @trait takes `self`",
+                    method.name
                 )));
             }
-            left = Self::desugar_pipe(left, rhs)?;
+            let is_signature = method.body.statements.iter().all(|stmt| match stmt {
+                e::Stmt::Expr(e::Expr::Tuple(items)) => items.is_empty(),
+                e::Stmt::Expr(expr) => string_literal(expr).is_some(),
+                _ => false,
+            });
+            if !is_signature {
+                return Err(self.error(format!(
+                    "method '{name}.{}' of a @trait is a signature only; end it with `...`",
+                    method.name
+                )));
+            }
+            if let Some(param) = method.params.iter().find(|p| p.ty.path == ["_"]) {
+                return Err(self.error(format!(
+                    "parameter '{}' of '{name}.{}' needs a type",
+                    param.name, method.name
+                )));
+            }
+            methods.push(method);
         }
-        Ok(left)
+        Ok(methods)
     }
 
-    /// Check if an expression is a comparison binary op.
-    fn is_comparison(expr: &e::Expr) -> bool {
-        matches!(
-            expr,
-            e::Expr::Binary {
-                op: e::BinaryOp::Eq
-                    | e::BinaryOp::Ne
-                    | e::BinaryOp::Lt
-                    | e::BinaryOp::Le
-                    | e::BinaryOp::Gt
-                    | e::BinaryOp::Ge,
-                ..
-            }
-        )
+    /// Make each `@trait` name in `ty` a boxed trait object, `Box[Dyn[T]]`,
+    /// so a field, return value or `List[Drawable]` element can hold any
+    /// implementor.
+    fn box_traits(&self, ty: &mut e::Type) {
+        if ty.path == ["Dyn"] {
+            return;
+        }
+        if let [name] = ty.path.as_slice()
+            && ty.args.is_empty()
+            && self.traits.contains_key(name)
+        {
+            *ty = wrap_type("Box", wrap_type("Dyn", ty.clone()));
+            return;
+        }
+        for arg in &mut ty.args {
+            self.box_traits(arg);
+        }
     }
 
-    /// Transform `lhs |> rhs` into a function call:
-    ///   - `Call { callee, args }` → `Call { callee, args: [lhs] + args }`
-    ///   - `Path(f)` or `Field { .. }` → `Call { callee: rhs, args: [lhs] }`
-    fn desugar_pipe(lhs: e::Expr, rhs: e::Expr) -> Result<e::Expr, ParseError> {
-        match rhs {
-            e::Expr::Call { callee, mut args } => {
-                if matches!(callee.as_ref(), e::Expr::Path(path) if path.len() == 1 && path[0] == "len")
-                    && args.is_empty()
-                {
-                    return Ok(Self::coerce_len_call_result(e::Expr::Call {
-                        callee: Box::new(e::Expr::Field {
-                            base: Box::new(lhs),
-                            field: "len".into(),
-                        }),
-                        args: vec![],
-                    }));
-                }
-                args.insert(0, lhs);
-                Ok(Self::coerce_len_call_result(e::Expr::Call { callee, args }))
-            }
-            e::Expr::Path(path) if path.len() == 1 && path[0] == "len" => {
-                Ok(Self::coerce_len_call_result(e::Expr::Call {
-                    callee: Box::new(e::Expr::Field {
-                        base: Box::new(lhs),
-                        field: "len".into(),
-                    }),
-                    args: vec![],
-                }))
+    /// A parameter typed as a `@trait` borrows its argument, `&dyn T` —
+    /// `mut ref T` makes it `&mut dyn T`; anything else is
+    /// [boxed](Self::box_traits).
+    fn trait_param(&self, ty: &mut e::Type) {
+        let (mode, inner) = match (ty.path.as_slice(), ty.args.as_slice()) {
+            ([mode], [inner]) if mode == "Ref" || mode == "MutRef" => (mode.clone(), inner),
+            _ => ("Ref".to_string(), &*ty),
+        };
+        if let [name] = inner.path.as_slice()
+            && inner.args.is_empty()
+            && self.traits.contains_key(name)
+        {
+            *ty = wrap_type(&mode, wrap_type("Dyn", inner.clone()));
+        } else {
+            self.box_traits(ty);
+        }
+    }
+
+    /// `value`, stored where `ty` is expected: boxed when `ty` is a trait
+    /// object (`Box[Dyn[T]]`) and `value` is built in place — `Circle(1.0)`,
+    /// `Circle.new(...)` — also inside a list, dict or `Some(...)` literal.
+    /// Anything else is already a trait object, or left to the type checker.
+    fn box_value(&self, value: e::Expr, ty: &e::Type) -> e::Expr {
+        match (ty.path.as_slice(), ty.args.as_slice(), value) {
+            ([boxed], [inner], value) if boxed == "Box" && inner.path == ["Dyn"] => {
+                if self.is_construction(&value) {
+                    e::Expr::Call {
+                        callee: Box::new(e::Expr::Path(vec!["Box".into(), "new".into()])),
+                        args: vec![value],
+                    }
+                } else {
+                    value
+                }
             }
-            e::Expr::Path(_) | e::Expr::Field { .. } => Ok(e::Expr::Call {
-                callee: Box::new(rhs),
-                args: vec![lhs],
-            }),
-            // Allow piping into macro calls too
-            e::Expr::MacroCall { path, mut args } => {
-                args.insert(0, lhs);
-                Ok(e::Expr::MacroCall { path, args })
+            ([list], [item], e::Expr::Array(items)) if list == "List" => e::Expr::Array(
+                items
+                    .into_iter()
+                    .map(|value| self.box_value(value, item))
+                    .collect(),
+            ),
+            ([option], [inner], e::Expr::Call { callee, mut args })
+                if option == "Option"
+                    && args.len() == 1
+                    && matches!(&*callee, e::Expr::Path(p) if p == &["Some"]) =>
+            {
+                let value = self.box_value(args.remove(0), inner);
+                e::Expr::Call {
+                    callee,
+                    args: vec![value],
+                }
             }
-            _ => {
-                // For any other expression, treat as a call with lhs as arg
-                Ok(e::Expr::Call {
-                    callee: Box::new(rhs),
-                    args: vec![lhs],
-                })
+            ([dict], [_, item], e::Expr::Call { callee, args })
+                if dict == "Dict"
+                    && matches!(&*callee, e::Expr::Path(p) if p == &["HashMap", "from"]) =>
+            {
+                let args = args
+                    .into_iter()
+                    .map(|arg| match arg {
+                        e::Expr::Array(pairs) => e::Expr::Array(
+                            pairs
+                                .into_iter()
+                                .map(|pair| match pair {
+                                    e::Expr::Tuple(mut kv) if kv.len() == 2 => {
+                                        let value = self.box_value(kv.remove(1), item);
+                                        kv.push(value);
+                                        e::Expr::Tuple(kv)
+                                    }
+                                    other => other,
+                                })
+                                .collect(),
+                        ),
+                        other => other,
+                    })
+                    .collect();
+                e::Expr::Call { callee, args }
             }
+            (_, _, value) => value,
         }
     }
 
-    fn parse_or_expr(&mut self) -> Result<e::Expr, ParseError> {
-        let mut left = self.parse_and_expr()?;
-        while self.check_kw(Keyword::Or) {
-            self.advance()?;
-            let right = self.parse_and_expr()?;
-            left = e::Expr::Binary {
-                op: e::BinaryOp::Or,
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+    /// Whether `value` builds a class value: `Circle(1.0)`, lowered to a
+    /// struct literal, or a static method such as `Circle.new(...)`.
+    fn is_construction(&self, value: &e::Expr) -> bool {
+        match value {
+            e::Expr::StructLiteral { .. } => true,
+            e::Expr::Call { callee, .. } => match &**callee {
+                e::Expr::Path(path) => path.len() == 2 && self.struct_fields.contains_key(&path[0]),
+                e::Expr::Field { base, .. } => {
+                    matches!(&**base, e::Expr::Path(p) if p.len() == 1 && self.struct_fields.contains_key(&p[0]))
+                }
+                _ => false,
+            },
+            _ => false,
         }
-        Ok(left)
     }
 
-    fn parse_and_expr(&mut self) -> Result<e::Expr, ParseError> {
-        let mut left = self.parse_not_expr()?;
-        while self.check_kw(Keyword::And) {
-            self.advance()?;
-            let right = self.parse_not_expr()?;
-            left = e::Expr::Binary {
-                op: e::BinaryOp::And,
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+    /// Box the values a function body stores as trait objects (see
+    /// [`Self::box_value`]): those it returns as one, and those added to a
+    /// local collection of them — `shapes.append(Circle(1.0))`,
+    /// `shapes[0] = Square(2.0)`.
+    fn box_trait_values(
+        &self,
+        block: &mut e::Block,
+        known: &HashMap<String, e::Type>,
+        return_type: Option<&e::Type>,
+    ) {
+        // The element type of a local `List` or `Dict`
+        let item_type = |base: &e::Expr| match base {
+            e::Expr::Path(path) if path.len() == 1 => known
+                .get(&path[0])
+                .filter(|ty| ty.path == ["List"] || ty.path == ["Dict"])
+                .and_then(|ty| ty.args.last()),
+            _ => None,
+        };
+        for stmt in &mut block.statements {
+            match stmt {
+                e::Stmt::Return(Some(value)) | e::Stmt::TailExpr(value) => {
+                    if let Some(ty) = return_type {
+                        let taken = std::mem::replace(value, e::Expr::Tuple(vec![]));
+                        *value = self.box_value(taken, ty);
+                    }
+                }
+                e::Stmt::Expr(e::Expr::Call { callee, args }) => {
+                    if let e::Expr::Field { base, field, .. } = &**callee
+                        && matches!(field.as_str(), "append" | "push" | "insert")
+                        && let Some(ty) = item_type(base)
+                        && let Some(value) = args.last_mut()
+                    {
+                        let taken = std::mem::replace(value, e::Expr::Tuple(vec![]));
+                        *value = self.box_value(taken, ty);
+                    }
+                }
+                e::Stmt::Assign {
+                    target: e::AssignTarget::Index { base, .. },
+                    value,
+                    ..
+                } => {
+                    if let Some(ty) = item_type(base) {
+                        let taken = std::mem::replace(value, e::Expr::Tuple(vec![]));
+                        *value = self.box_value(taken, ty);
+                    }
+                }
+                e::Stmt::If {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    self.box_trait_values(then_block, known, return_type);
+                    if let Some(block) = else_block {
+                        self.box_trait_values(block, known, return_type);
+                    }
+                }
+                e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+                    self.box_trait_values(body, known, return_type);
+                }
+                _ => {}
+            }
         }
-        Ok(left)
     }
 
-    fn parse_not_expr(&mut self) -> Result<e::Expr, ParseError> {
-        if self.check_kw(Keyword::Not) {
-            self.advance()?;
-            let expr = self.parse_not_expr()?;
-            return Ok(e::Expr::Unary {
-                op: e::UnaryOp::Not,
-                expr: Box::new(expr),
-            });
-        }
-        self.parse_comparison()
-    }
+    // ─────────────────────────────────────────────────────────────────────────
+    // type Keyword → Struct / Enum / Union
+    // ─────────────────────────────────────────────────────────────────────────
 
-    fn parse_comparison(&mut self) -> Result<e::Expr, ParseError> {
-        let mut left = self.parse_addition()?;
-        loop {
-            let op = match self.kind() {
-                TokenKind::EqEq => e::BinaryOp::Eq,
-                TokenKind::NotEq => e::BinaryOp::Ne,
-                TokenKind::Lt => e::BinaryOp::Lt,
-                TokenKind::LtEq => e::BinaryOp::Le,
-                TokenKind::Gt => e::BinaryOp::Gt,
-                TokenKind::GtEq => e::BinaryOp::Ge,
-                _ => break,
-            };
-            self.advance()?;
-            let right = self.parse_addition()?;
-            let (left_coerced, right_coerced) = Self::rewrite_len_comparison_operands(left, right);
-            left = e::Expr::Binary {
-                op,
-                left: Box::new(left_coerced),
-                right: Box::new(right_coerced),
+    /// Parse a `type` definition:
+    ///
+    ///   Struct: `type Point:\n    x: i32\n    y: i32`
+    ///   Enum:   `type Color = | Red | Green | Blue(i32)`
+    fn parse_type_def(&mut self) -> Result<Vec<e::Item>, ParseError> {
+        let type_start = self.current.start;
+        self.expect_kw(Keyword::Type)?;
+        let name = self.expect_ident()?;
+        let mut type_params = self.parse_type_params()?;
+        self.parse_where_clause(&mut type_params)?;
+
+        if self.eat(&TokenKind::Eq)? {
+            // Newtype: `type Meters = new f64`
+            if matches!(self.kind(), TokenKind::Ident(id) if id == "new")
+                && matches!(self.peek()?.kind, TokenKind::Ident(_))
+            {
+                self.advance()?;
+                let inner = self.parse_type()?;
+                return self.newtype(name, type_params, inner, type_start);
+            }
+
+            // Named tuple: `type Point = (x: i64, y: i64)`
+            if self.check(&TokenKind::LParen) {
+                return self.named_tuple(name, type_params, type_start);
+            }
+
+            // Union shorthand: `type Number = i64 | f64`
+            // If first token is a lowercase identifier, treat as union types
+            let is_union = match self.kind() {
+                TokenKind::Ident(id) => id.starts_with(char::is_lowercase),
+                _ => false,
             };
-        }
-        Ok(left)
-    }
 
-    fn rewrite_len_comparison_operands(left: e::Expr, right: e::Expr) -> (e::Expr, e::Expr) {
-        let left_is_len = Self::is_len_call_expr(&left);
-        let right_is_len = Self::is_len_call_expr(&right);
+            if is_union {
+                let mut union_types = vec![self.parse_type()?];
+                while self.eat(&TokenKind::Pipe)? {
+                    union_types.push(self.parse_type()?);
+                }
+                let variants = Self::union_variants(union_types);
+                return Ok(vec![e::Item::Enum(e::EnumDef {
+                    visibility: e::Visibility::Public,
+                    name,
+                    type_params,
+                    variants,
+                    span: self.span_from(type_start),
+                })]);
+            }
 
-        match (left_is_len, right_is_len) {
-            (true, false) => (Self::cast_expr_to_i64(left), right),
-            (false, true) => (left, Self::cast_expr_to_i64(right)),
-            _ => (left, right),
+            // Enum variant list: `type Color = Red | Green | Blue(i32)`
+            let variants = self.parse_variant_list()?;
+            let discriminants = std::mem::take(&mut self.discriminants);
+            let mut items = Vec::new();
+            if !discriminants.is_empty() {
+                items.push(self.discriminant_impl(&name, &variants, &discriminants)?);
+            }
+            if type_params.is_empty()
+                && variants
+                    .iter()
+                    .all(|variant| matches!(variant.fields, e::EnumVariantFields::Unit))
+            {
+                items.push(self.unit_variants_impl(&name, &variants));
+            }
+            items.insert(
+                0,
+                e::Item::Enum(e::EnumDef {
+                    visibility: e::Visibility::Public,
+                    name,
+                    type_params,
+                    variants,
+                    span: self.span_from(type_start),
+                }),
+            );
+            return Ok(items);
         }
-    }
 
-    fn is_len_call_expr(expr: &e::Expr) -> bool {
-        matches!(
-            expr,
-            e::Expr::Call {
-                callee,
-                args,
-            } if (
-                args.is_empty() && matches!(
-                    callee.as_ref(),
-                    e::Expr::Field { field, .. } if field == "len"
-                )
-            ) || (
-                args.len() == 1 && matches!(
-                    callee.as_ref(),
-                    e::Expr::Path(path) if path.last().is_some_and(|segment| segment == "len")
-                )
-            )
-        )
-    }
+        // ── Struct form: `type Name:\n    field: Type` ───────────────
+        self.expect(&TokenKind::Colon)?;
+        let (fields, methods, attrs) = self.parse_type_struct_body(&name)?;
+        if !attrs.is_empty() && !type_params.is_empty() {
+            return Err(self.error(format!(
+                "class attributes on generic type '{name}' are not supported"
+            )));
+        }
 
-    fn coerce_len_call_result(expr: e::Expr) -> e::Expr {
-        if Self::is_len_call_expr(&expr) {
-            return Self::cast_expr_to_i64(expr);
+        // Register field names for positional struct construction
+        self.struct_fields.insert(
+            name.clone(),
+            fields.iter().map(|f| f.name.clone()).collect(),
+        );
+
+        let mut items = vec![e::Item::Struct(e::StructDef {
+            visibility: e::Visibility::Public,
+            name,
+            type_params,
+            fields,
+            span: self.span_from(type_start),
+        })];
+
+        if !methods.is_empty() {
+            let (name, type_params) = match &items[0] {
+                e::Item::Struct(s) => (s.name.clone(), s.type_params.clone()),
+                _ => unreachable!(),
+            };
+            let target_args = type_params
+                .iter()
+                .map(|param| e::Type {
+                    path: vec![param.name.clone()],
+                    args: vec![],
+                    trait_bounds: vec![],
+                })
+                .collect();
+            items.push(e::Item::Impl(e::ImplBlock {
+                type_params,
+                target: name,
+                target_args,
+                trait_target: None,
+                methods,
+                span: self.span_from(type_start),
+            }));
         }
-        expr
+        let class = match &items[0] {
+            e::Item::Struct(s) => s.name.clone(),
+            _ => unreachable!(),
+        };
+        items.extend(self.class_attr_items(&class, attrs)?);
+
+        Ok(items)
     }
 
-    fn cast_expr_to_i64(expr: e::Expr) -> e::Expr {
-        e::Expr::Cast {
-            expr: Box::new(expr),
-            target_type: e::Type {
-                path: vec!["i64".into()],
-                args: vec![],
-                trait_bounds: vec![],
-            },
+    /// Register a method's parameters under `Type::method` too, so
+    /// `Type.method(...)` calls bind their arguments.
+    fn register_method(&mut self, class: &str, method: &str) {
+        let key = format!("{class}::{method}");
+        if let Some(names) = self.fn_params.get(method).cloned() {
+            self.fn_params.insert(key.clone(), names);
         }
+        self.fn_defaults.retain(|(function, _), _| *function != key);
+        let defaults: Vec<_> = self
+            .fn_defaults
+            .iter()
+            .filter(|((function, _), _)| function == method)
+            .map(|((_, param), value)| ((key.clone(), param.clone()), value.clone()))
+            .collect();
+        self.fn_defaults.extend(defaults);
     }
 
-    fn parse_addition(&mut self) -> Result<e::Expr, ParseError> {
-        let mut left = self.parse_multiplication()?;
-        loop {
-            let op = match self.kind() {
-                TokenKind::Plus => e::BinaryOp::Add,
-                TokenKind::Minus => e::BinaryOp::Sub,
-                _ => break,
-            };
-            self.advance()?;
-            let right = self.parse_multiplication()?;
-            left = e::Expr::Binary {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+    fn parse_type_struct_body(&mut self, class: &str) -> Result<TypeBody, ParseError> {
+        self.skip_newlines()?;
+        self.expect(&TokenKind::Indent)?;
+
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        let mut attrs: Vec<ClassAttr> = Vec::new();
+
+        while !self.check(&TokenKind::Dedent) && !self.check(&TokenKind::Eof) {
+            self.skip_newlines()?;
+            if self.check(&TokenKind::Dedent) || self.check(&TokenKind::Eof) {
+                break;
+            }
+
+            if self.check_kw(Keyword::Def) {
+                let method = self.parse_function_def()?;
+                self.register_method(class, &method.name);
+                methods.push(method);
+                self.skip_newlines()?;
+                continue;
+            }
+
+            if matches!(self.kind(), TokenKind::Ident(_)) {
+                let member_start = self.current.start;
+                let mutable = matches!(self.kind(), TokenKind::Ident(id) if id == "mut")
+                    && matches!(self.peek()?.kind, TokenKind::Ident(_));
+                if mutable {
+                    self.advance()?;
+                }
+                let name = self.expect_ident()?;
+                self.expect(&TokenKind::Colon)?;
+                let ty = self.parse_type()?;
+                // `name: Type = value` declares a class attribute
+                if self.eat(&TokenKind::Eq)? {
+                    let value = self.parse_expr()?;
+                    if attrs.iter().any(|a| a.name == name) {
+                        return Err(self.error(format!(
+                            "class attribute '{class}.{name}' is declared twice"
+                        )));
+                    }
+                    self.class_attrs
+                        .insert((class.to_string(), name.clone()), (ty.clone(), mutable));
+                    attrs.push(ClassAttr {
+                        name,
+                        ty,
+                        value,
+                        mutable,
+                        span: self.span_from(member_start),
+                    });
+                } else if mutable {
+                    return Err(self.error(format!(
+                        "mutable class attribute '{class}.{name}' needs an initial value"
+                    )));
+                } else {
+                    let mut ty = ty;
+                    self.box_traits(&mut ty);
+                    if holds_trait_object(&ty) {
+                        self.boxed_fields
+                            .insert((class.to_string(), name.clone()), ty.clone());
+                    }
+                    fields.push(e::Field { name, ty });
+                }
+                self.skip_newlines()?;
+                continue;
+            }
+
+            return Err(self.error(
+                "expected field declaration (`name: Type`) or method (`def ...`) in type body"
+                    .into(),
+            ));
         }
-        // Rewrite string + chains to str(format!("{}{}", a, b))
-        Ok(Self::rewrite_string_concat(left))
-    }
 
-    /// If `expr` is a chain of `Add` ops containing any string-like operand,
-    /// flatten it into `str(format!("{}{}{}", a, b, c))`.
-    fn rewrite_string_concat(expr: e::Expr) -> e::Expr {
-        let mut operands = Vec::new();
-        if !Self::flatten_add_chain(&expr, &mut operands) {
-            return expr; // contains Sub — not a pure + chain
+        if self.check(&TokenKind::Dedent) {
+            self.advance()?;
         }
-        // Only rewrite actual + chains (2+ operands), not single expressions
-        if operands.len() < 2 {
-            return expr;
+
+        for method in &methods {
+            let clash = attrs.iter().find(|a| {
+                a.mutable && (method.name == a.name || method.name == format!("set_{}", a.name))
+            });
+            if let Some(attr) = clash {
+                return Err(self.error(format!(
+                    "method '{class}.{}' clashes with the accessors of class attribute '{}'",
+                    method.name, attr.name
+                )));
+            }
         }
-        // Check if any operand looks string-like
-        if !operands.iter().any(Self::is_string_expr) {
-            return expr; // pure numeric, leave for Elevate
+
+        Ok((fields, methods, attrs))
+    }
+
+    /// The items behind a class's attributes.
+    ///
+    /// A constant of a primitive type becomes an associated const; other
+    /// constants and `mut` attributes live in a lazily initialized global,
+    /// read through `Circle::name()` (and written through
+    /// `Circle::set_name(value)`), so they can hold any value:
+    ///
+    /// ```text
+    /// type Circle:                 const __CIRCLE_PI: f64 = 3.14159;
+    ///     PI: f64 = 3.14159   →    impl Circle { pub const PI: f64 = __CIRCLE_PI; }
+    ///     mut count: i64 = 0       static __CIRCLE_COUNT: OnceLock<Mutex<i64>> = ...;
+    ///                              impl Circle { pub fn count() -> i64 ...
+    ///                                            pub fn set_count(value: i64) ... }
+    /// ```
+    fn class_attr_items(
+        &self,
+        class: &str,
+        attrs: Vec<ClassAttr>,
+    ) -> Result<Vec<e::Item>, ParseError> {
+        let mut items = Vec::new();
+        let mut accessors = Vec::new();
+        for attr in attrs {
+            let global = format!("__{}_{}", class.to_uppercase(), attr.name.to_uppercase());
+            if let Some(ty) = const_attr_type(&attr.ty).filter(|_| !attr.mutable) {
+                items.push(e::Item::Const(e::ConstDef {
+                    visibility: e::Visibility::Private,
+                    name: global.clone(),
+                    ty: Some(attr.ty),
+                    value: attr.value,
+                    is_const: true,
+                    span: attr.span,
+                }));
+                accessors.push(format!("    pub const {}: {ty} = {global};", attr.name));
+                continue;
+            }
+            let ty = rust_type(&attr.ty);
+            let init = format!(
+                "__{}_{}_init",
+                class.to_lowercase(),
+                attr.name.to_lowercase()
+            );
+            items.push(e::Item::Function(e::FunctionDef {
+                visibility: e::Visibility::Private,
+                name: init.clone(),
+                type_params: vec![],
+                params: vec![],
+                return_type: Some(attr.ty),
+                effect_row: None,
+                body: e::Block {
+                    statements: vec![e::Stmt::Return(Some(attr.value))],
+                },
+                span: attr.span,
+            }));
+            items.push(e::Item::RustBlock(format!(
+                "static {global}: std::sync::OnceLock<std::sync::Mutex<{ty}>> = std::sync::OnceLock::new();"
+            )));
+            let cell = format!(
+                "{global}\n            .get_or_init(|| std::sync::Mutex::new({init}()))\n            \
+                 .lock()\n            .unwrap_or_else(std::sync::PoisonError::into_inner)"
+            );
+            accessors.push(format!(
+                "    #[allow(non_snake_case)]\n    pub fn {}() -> {ty} {{\n        {cell}\n            .clone()\n    }}",
+                attr.name
+            ));
+            if attr.mutable {
+                accessors.push(format!(
+                    "    #[allow(non_snake_case)]\n    pub fn set_{}(value: {ty}) {{\n        *{cell} = value;\n    }}",
+                    attr.name
+                ));
+            }
         }
-        // Build format!("{}{}{}", a, b, c) wrapped in str()
-        let fmt = operands.iter().map(|_| "{}").collect::<Vec<_>>().join("");
-        let mut macro_args = vec![e::Expr::String(fmt)];
-        macro_args.extend(operands);
-        let format_call = e::Expr::MacroCall {
-            path: vec!["format".into()],
-            args: macro_args,
-        };
-        // Wrap in str() constructor
-        e::Expr::Call {
-            callee: Box::new(e::Expr::Path(vec!["str".into()])),
-            args: vec![format_call],
+        if !accessors.is_empty() {
+            items.push(e::Item::RustBlock(format!(
+                "impl {class} {{\n{}\n}}",
+                accessors.join("\n\n")
+            )));
         }
+        Ok(items)
     }
 
-    /// Flatten a left-associative Add chain into a vec of operands.
-    /// Returns false if any Sub is encountered (not a pure + chain).
-    fn flatten_add_chain(expr: &e::Expr, out: &mut Vec<e::Expr>) -> bool {
-        if let e::Expr::Binary { op, left, right } = expr {
-            match op {
-                e::BinaryOp::Add => {
-                    if !Self::flatten_add_chain(left, out) {
-                        return false;
-                    }
-                    out.push(*right.clone());
-                    true
-                }
-                e::BinaryOp::Sub => false,
-                _ => {
-                    out.push(expr.clone());
-                    true
-                }
-            }
+    /// `Circle.PI` / `Circle.count` for a class attribute: the value, typed
+    /// for the checker through a closure around the Rust access.
+    fn class_attr_read(&self, class: &str, name: &str) -> Option<e::Expr> {
+        let (ty, mutable) = self
+            .class_attrs
+            .get(&(class.to_string(), name.to_string()))?;
+        let access = if *mutable || const_attr_type(ty).is_none() {
+            format!("{class}::{name}()")
         } else {
-            out.push(expr.clone());
-            true
+            format!("{class}::{name}")
+        };
+        Some(e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![],
+                return_type: Some(ty.clone()),
+                body: e::Block {
+                    statements: vec![e::Stmt::RustBlock(access)],
+                },
+            }),
+            args: vec![],
+        })
+    }
+
+    /// The `(class, attribute)` an expression built by `class_attr_read`
+    /// reads, if it is one.
+    fn class_attr_of(&self, expr: &e::Expr) -> Option<(String, String)> {
+        let e::Expr::Call { callee, args } = expr else {
+            return None;
+        };
+        let e::Expr::Closure { params, body, .. } = &**callee else {
+            return None;
+        };
+        let [e::Stmt::RustBlock(access)] = body.statements.as_slice() else {
+            return None;
+        };
+        if !args.is_empty() || !params.is_empty() {
+            return None;
         }
+        let (class, name) = access.trim_end_matches("()").split_once("::")?;
+        let key = (class.to_string(), name.to_string());
+        self.class_attrs.contains_key(&key).then_some(key)
     }
 
-    /// Check if an expression is string-like (str() call, string literal, or format! macro).
-    fn is_string_expr(expr: &e::Expr) -> bool {
-        match expr {
-            // Raw string literals
-            e::Expr::String(_) => true,
-            // str("literal") or str(expr)
-            e::Expr::Call { callee, .. } => {
-                matches!(**callee, e::Expr::Path(ref p) if p.len() == 1 && p[0] == "str")
+    /// `Circle.count = value` (or `+= value`) for mutable class state: a call
+    /// of its setter. `read` is the rewritten read of the attribute.
+    fn class_attr_write(
+        &self,
+        (class, name): (String, String),
+        read: e::Expr,
+        op: e::AssignOp,
+        value: e::Expr,
+    ) -> Result<e::Stmt, ParseError> {
+        let (ty, mutable) = &self.class_attrs[&(class.clone(), name.clone())];
+        if !mutable {
+            return Err(self.error(format!(
+                "cannot assign to class constant '{class}.{name}'; declare it `mut {name}: ...` for mutable class state"
+            )));
+        }
+        let value = match op {
+            e::AssignOp::Assign => value,
+            e::AssignOp::AddAssign => e::Expr::Binary {
+                op: e::BinaryOp::Add,
+                left: Box::new(read),
+                right: Box::new(value),
+            },
+        };
+        Ok(e::Stmt::Expr(e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![e::Param {
+                    name: "value".into(),
+                    ty: ty.clone(),
+                }],
+                return_type: None,
+                body: e::Block {
+                    statements: vec![e::Stmt::RustBlock(format!("{class}::set_{name}(value);"))],
+                },
+            }),
+            args: vec![value],
+        }))
+    }
+
+    /// `Status::from_int` for an enum with explicit discriminants, headed by
+    /// the marker that has the generated enum made `#[repr(i32)]` with those
+    /// values (see `apply_enum_discriminants` in lib.rs). Variants without a
+    /// value follow the previous one, as in Rust.
+    fn discriminant_impl(
+        &mut self,
+        name: &str,
+        variants: &[e::EnumVariant],
+        explicit: &[(String, i64)],
+    ) -> Result<e::Item, ParseError> {
+        let mut values = Vec::new();
+        let mut next = 0;
+        for variant in variants {
+            if !matches!(variant.fields, e::EnumVariantFields::Unit) {
+                return Err(self.error(format!(
+                    "enum '{name}' has discriminants, so variant '{}' cannot carry data",
+                    variant.name
+                )));
             }
-            // format!(...) — f-strings
-            e::Expr::MacroCall { path, .. } => path.len() == 1 && path[0] == "format",
-            _ => false,
+            let value = explicit
+                .iter()
+                .find(|(v, _)| v == &variant.name)
+                .map_or(next, |(_, value)| *value);
+            if let Some((other, _)) = values.iter().find(|(_, v)| *v == value) {
+                return Err(self.error(format!(
+                    "variants '{other}' and '{}' of '{name}' share the discriminant {value}",
+                    variant.name
+                )));
+            }
+            values.push((variant.name.clone(), value));
+            next = value + 1;
         }
+        self.discriminant_enums.insert(name.to_string());
+
+        let marker: Vec<String> = values.iter().map(|(v, n)| format!("{v}={n}")).collect();
+        let arms: Vec<String> = values
+            .iter()
+            .map(|(v, n)| format!("            {n} => {name}::{v},\n"))
+            .collect();
+        Ok(e::Item::RustBlock(format!(
+            "// quiche:discriminants {name} {}\n\
+             impl {name} {{\n    \
+             pub fn from_int(value: i64) -> {name} {{\n        \
+             match value {{\n{}            \
+             _ => panic!(\"{{value}} is not a valid {name}\"),\n        \
+             }}\n    \
+             }}\n\
+             }}",
+            marker.join(" "),
+            arms.concat(),
+        )))
     }
 
-    fn parse_multiplication(&mut self) -> Result<e::Expr, ParseError> {
-        let mut left = self.parse_unary()?;
-        loop {
-            let op = match self.kind() {
-                TokenKind::Star => e::BinaryOp::Mul,
-                TokenKind::Slash => e::BinaryOp::Div,
-                TokenKind::Percent => e::BinaryOp::Rem,
-                _ => break,
-            };
-            self.advance()?;
-            let right = self.parse_unary()?;
-            left = e::Expr::Binary {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+    /// `Color.variants()` and `Color.from_str(name)` for an enum whose
+    /// variants carry no data, plus `FromStr` (so `@cli` can read one).
+    /// Names match exactly: `Color.from_str("red")` is `None`.
+    fn unit_variants_impl(&mut self, name: &str, variants: &[e::EnumVariant]) -> e::Item {
+        self.unit_enums.insert(name.to_string());
+        let names: Vec<String> = variants
+            .iter()
+            .map(|variant| format!("Str::from(\"{}\")", variant.name))
+            .collect();
+        let arms: Vec<String> = variants
+            .iter()
+            .map(|variant| format!("            \"{0}\" => Some({name}::{0}),\n", variant.name))
+            .collect();
+        let expected: Vec<&str> = variants
+            .iter()
+            .map(|variant| variant.name.as_str())
+            .collect();
+        e::Item::RustBlock(format!(
+            "impl {name} {{\n    \
+             pub fn variants() -> List<Str> {{\n        \
+             List::from(vec![{}])\n    \
+             }}\n\n    \
+             pub fn from_str(name: impl AsRef<str>) -> Option<{name}> {{\n        \
+             match name.as_ref() {{\n{}            \
+             _ => None,\n        \
+             }}\n    \
+             }}\n\
+             }}\n\n\
+             impl std::str::FromStr for {name} {{\n    \
+             type Err = Str;\n\n    \
+             fn from_str(name: &str) -> Result<{name}, Str> {{\n        \
+             {name}::from_str(name).ok_or_else(|| {{\n            \
+             Str::from(format!(\"'{{name}}' is not a {name} (expected one of {})\"))\n        \
+             }})\n    \
+             }}\n\
+             }}",
+            names.join(", "),
+            arms.concat(),
+            expected.join(", "),
+        ))
+    }
+
+    /// One tuple variant per member of a union, named after its type.
+    fn union_variants(types: Vec<e::Type>) -> Vec<e::EnumVariant> {
+        types
+            .into_iter()
+            .map(|ty| {
+                let variant_name = Self::type_to_variant_name(&ty);
+                e::EnumVariant {
+                    name: variant_name,
+                    fields: e::EnumVariantFields::Tuple(vec![ty]),
+                }
+            })
+            .collect()
+    }
+
+    /// Parse the rest of a union return type, `-> i64 | Str`, after its
+    /// first member. The union becomes an enum named after the function
+    /// (`parse` → `ParseResult`) with a variant and a `From` impl per
+    /// member; it is placed before the function in the module.
+    fn parse_union_return(&mut self, func: &str, first: e::Type) -> Result<e::Type, ParseError> {
+        let start = self.current.start;
+        let mut types = vec![first];
+        while self.eat(&TokenKind::Pipe)? {
+            types.push(self.parse_type()?);
         }
-        Ok(left)
+        let mut seen = HashSet::new();
+        for ty in &types {
+            if !seen.insert(Self::type_to_variant_name(ty)) {
+                return Err(self.error(format!(
+                    "return type of '{func}' lists `{}` twice",
+                    quiche_type(ty)
+                )));
+            }
+        }
+
+        let name: String = func
+            .split('_')
+            .flat_map(|word| {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|c| c.to_ascii_uppercase())
+                    .into_iter()
+                    .chain(chars)
+            })
+            .chain("Result".chars())
+            .collect();
+        let from_impls: Vec<String> = types
+            .iter()
+            .map(|ty| {
+                format!(
+                    "impl From<{ty}> for {name} {{\n    fn from(value: {ty}) -> Self {{\n        {name}::{variant}(value)\n    }}\n}}",
+                    ty = rust_type(ty),
+                    variant = Self::type_to_variant_name(ty),
+                )
+            })
+            .collect();
+        self.pending_unions.push(e::Item::Enum(e::EnumDef {
+            visibility: e::Visibility::Public,
+            name: name.clone(),
+            type_params: vec![],
+            variants: Self::union_variants(types.clone()),
+            span: self.span_from(start),
+        }));
+        self.pending_unions
+            .push(e::Item::RustBlock(from_impls.join("\n\n")));
+        self.union_returns.insert(func.to_string(), name.clone());
+        self.union_enums.insert(name.clone(), types);
+        Ok(e::Type {
+            path: vec![name],
+            args: vec![],
+            trait_bounds: vec![],
+        })
     }
 
-    fn parse_unary(&mut self) -> Result<e::Expr, ParseError> {
-        if self.check(&TokenKind::Minus) {
-            self.advance()?;
-            let expr = self.parse_unary()?;
-            return Ok(e::Expr::Unary {
-                op: e::UnaryOp::Neg,
-                expr: Box::new(expr),
-            });
+    /// Narrow values of union return types at their use sites:
+    ///
+    /// - `if isinstance(r, i64):` tests the variant and rebinds `r` as the
+    ///   `i64` inside the branch;
+    /// - `case i64(n):` in a `match` on such a value matches the variant.
+    ///
+    /// A value is known to be a union when it is the result of calling a
+    /// union-returning function, directly or through a local.
+    fn narrow_unions(&self, items: &mut [e::Item]) -> Result<(), ParseError> {
+        if self.union_returns.is_empty() {
+            return Ok(());
         }
-        // *name → starred expression (for destructuring: a, *rest = items)
-        if self.check(&TokenKind::Star) {
-            self.advance()?;
-            let name = self.expect_ident()?;
-            return Ok(e::Expr::MacroCall {
-                path: vec!["__star__".into()],
-                args: vec![e::Expr::Path(vec![name])],
-            });
+        for item in items {
+            let functions: Vec<&mut e::FunctionDef> = match item {
+                e::Item::Function(func) => vec![func],
+                e::Item::Impl(block) => block.methods.iter_mut().collect(),
+                _ => continue,
+            };
+            for func in functions {
+                let mut locals: HashMap<String, String> = func
+                    .params
+                    .iter()
+                    .filter_map(|p| match p.ty.path.as_slice() {
+                        [name] if self.union_enums.contains_key(name) => {
+                            Some((p.name.clone(), name.clone()))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let mut calls = self.isinstance_calls(func.span);
+                self.narrow_block(&mut func.body, &mut locals, &mut calls)?;
+            }
         }
-        self.parse_postfix()
+        Ok(())
     }
 
-    fn parse_postfix(&mut self) -> Result<e::Expr, ParseError> {
-        let mut expr = self.parse_primary()?;
-        loop {
-            if self.eat(&TokenKind::Dot)? {
-                let field = self.expect_ident()?;
-                // Static method heuristic: if base is a capitalized Path (type name)
-                // and this is followed by '(' (a call), merge into path for `Type::method`.
-                // e.g. Student.new(...) → Path(["Student", "new"]) → Student::new(...)
-                if let e::Expr::Path(ref segments) = expr {
-                    if let Some(first) = segments.first() {
-                        if first.starts_with(|c: char| c.is_uppercase())
-                            && self.check(&TokenKind::LParen)
-                        {
-                            let mut path = segments.clone();
-                            path.push(field);
-                            expr = e::Expr::Path(path);
-                            continue;
+    fn narrow_block(
+        &self,
+        block: &mut e::Block,
+        locals: &mut HashMap<String, String>,
+        calls: &mut HashMap<String, VecDeque<Span>>,
+    ) -> Result<(), ParseError> {
+        for stmt in &mut block.statements {
+            match stmt {
+                e::Stmt::Assign {
+                    target: e::AssignTarget::Path(name),
+                    value,
+                    ..
+                } => match self.union_of(value, locals) {
+                    Some(union) => {
+                        locals.insert(name.clone(), union);
+                    }
+                    None => {
+                        locals.remove(name.as_str());
+                    }
+                },
+                e::Stmt::Const(def) => {
+                    let declared = def.ty.as_ref().and_then(|ty| match ty.path.as_slice() {
+                        [name] if self.union_enums.contains_key(name) => Some(name.clone()),
+                        _ => None,
+                    });
+                    match declared.or_else(|| self.union_of(&def.value, locals)) {
+                        Some(union) => {
+                            locals.insert(def.name.clone(), union);
+                        }
+                        None => {
+                            locals.remove(def.name.as_str());
                         }
                     }
                 }
-                // Quiche value semantics: .iter() → .into_iter()
-                // so iterator chains yield T instead of &T, avoiding
-                // double-reference issues in filter/any/all closures.
-                let field = if field == "iter" {
-                    "into_iter".into()
-                } else {
-                    field
-                };
-                expr = e::Expr::Field {
-                    base: Box::new(expr),
-                    field,
-                };
-            } else if self.check(&TokenKind::LParen) {
-                self.advance()?;
-                let call_args = self.parse_call_args_with_kwargs()?;
-                self.expect(&TokenKind::RParen)?;
-
-                // Check if this is a struct constructor call
-                let is_struct_call = if let e::Expr::Path(ref path) = expr {
-                    path.len() == 1 && self.struct_fields.contains_key(&path[0])
-                } else {
-                    false
-                };
-
-                if is_struct_call {
-                    // Convert to StructLiteral
-                    let path = if let e::Expr::Path(ref p) = expr {
-                        p.clone()
-                    } else {
-                        unreachable!()
-                    };
-                    let field_names = self.struct_fields.get(&path[0]).unwrap().clone();
-
-                    // Check if any args are keyword args
-                    let has_kwargs = call_args
-                        .iter()
-                        .any(|a| matches!(a, CallArg::Keyword(_, _)));
-
-                    let fields = if has_kwargs {
-                        // Keyword construction: Point(x=5, y=5)
-                        call_args
-                            .into_iter()
-                            .map(|arg| {
-                                match arg {
-                                    CallArg::Keyword(name, value) => {
-                                        e::StructLiteralField { name, value }
-                                    }
-                                    CallArg::Positional(_) => {
-                                        // Mixed positional+keyword not supported yet — treat as error
-                                        // For now, skip positional in kwargs mode
-                                        e::StructLiteralField {
-                                            name: String::new(),
-                                            value: e::Expr::Int(0),
-                                        }
-                                    }
-                                }
-                            })
-                            .filter(|f| !f.name.is_empty())
-                            .collect()
-                    } else {
-                        // Positional construction: Point(5, 5) → zip with field names
-                        field_names
-                            .iter()
-                            .zip(call_args.into_iter())
-                            .map(|(name, arg): (&String, CallArg)| {
-                                let value = match arg {
-                                    CallArg::Positional(expr) => expr,
-                                    CallArg::Keyword(_, expr) => expr,
-                                };
-                                e::StructLiteralField {
-                                    name: name.clone(),
-                                    value,
-                                }
-                            })
-                            .collect()
-                    };
-
-                    expr = e::Expr::StructLiteral { path, fields };
-                    continue;
-                }
-
-                // Reorder kwargs for non-struct calls using fn_params
-                let has_kwargs = call_args
-                    .iter()
-                    .any(|a| matches!(a, CallArg::Keyword(_, _)));
-                let args: Vec<e::Expr> = if has_kwargs {
-                    // Determine the function key for fn_params lookup
-                    let fn_key = match &expr {
-                        e::Expr::Path(path) if path.len() == 1 => Some(path[0].clone()),
-                        e::Expr::Path(path) if path.len() == 2 => {
-                            // Point.new(...) → Path(["Point", "new"]) → key "Point::new"
-                            Some(format!("{}::{}", path[0], path[1]))
-                        }
-                        e::Expr::Field { base, field, .. } => {
-                            if let e::Expr::Path(ref base_path) = **base {
-                                if base_path.len() == 1 {
-                                    Some(format!("{}::{}", base_path[0], field))
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        }
-                        _ => None,
-                    };
-
-                    if let Some(ref key) = fn_key {
-                        if let Some(param_names) = self.fn_params.get(key) {
-                            // Build a map of kwarg name → value
-                            let mut kwarg_map: HashMap<String, e::Expr> = HashMap::new();
-                            let mut positional = Vec::new();
-                            for arg in call_args {
-                                match arg {
-                                    CallArg::Keyword(name, val) => {
-                                        kwarg_map.insert(name, val);
-                                    }
-                                    CallArg::Positional(val) => {
-                                        positional.push(val);
-                                    }
-                                }
-                            }
-                            // Reorder: fill positional first, then kwargs by param name order
-                            let mut result = Vec::new();
-                            let mut pos_idx = 0;
-                            for pname in param_names {
-                                if let Some(val) = kwarg_map.remove(pname) {
-                                    result.push(val);
-                                } else if pos_idx < positional.len() {
-                                    result.push(positional[pos_idx].clone());
-                                    pos_idx += 1;
-                                }
-                            }
-                            result
-                        } else {
-                            // Function not known — pass in order
-                            call_args
-                                .into_iter()
-                                .map(|a| match a {
-                                    CallArg::Positional(e) => e,
-                                    CallArg::Keyword(_, e) => e,
-                                })
-                                .collect()
-                        }
+                e::Stmt::If {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    if let Some((name, union, variant, ty)) =
+                        self.isinstance_test(condition, locals)?
+                    {
+                        *condition = e::Expr::Match {
+                            scrutinee: Box::new(e::Expr::Path(vec![name.clone()])),
+                            arms: vec![
+                                e::MatchArm {
+                                    pattern: e::Pattern::Variant {
+                                        path: vec![union.clone(), variant.clone()],
+                                        payload: Some(Box::new(e::Pattern::Wildcard)),
+                                    },
+                                    guard: None,
+                                    value: e::Expr::Bool(true),
+                                },
+                                e::MatchArm {
+                                    pattern: e::Pattern::Wildcard,
+                                    guard: None,
+                                    value: e::Expr::Bool(false),
+                                },
+                            ],
+                        };
+                        let rebind = e::Stmt::Const(e::ConstDef {
+                            visibility: e::Visibility::Private,
+                            name: name.clone(),
+                            ty: Some(ty),
+                            value: e::Expr::Match {
+                                scrutinee: Box::new(e::Expr::Path(vec![name.clone()])),
+                                arms: vec![
+                                    e::MatchArm {
+                                        pattern: e::Pattern::Variant {
+                                            path: vec![union, variant],
+                                            payload: Some(Box::new(e::Pattern::Binding(
+                                                name.clone(),
+                                            ))),
+                                        },
+                                        guard: None,
+                                        value: e::Expr::Path(vec![name.clone()]),
+                                    },
+                                    e::MatchArm {
+                                        pattern: e::Pattern::Wildcard,
+                                        guard: None,
+                                        value: e::Expr::MacroCall {
+                                            path: vec!["unreachable".into()],
+                                            args: vec![],
+                                        },
+                                    },
+                                ],
+                            },
+                            is_const: false,
+                            span: calls.get_mut(name.as_str()).and_then(VecDeque::pop_front),
+                        });
+                        then_block.statements.insert(0, rebind);
+                        let mut inner = locals.clone();
+                        inner.remove(name.as_str());
+                        self.narrow_block(then_block, &mut inner, calls)?;
                     } else {
-                        call_args
-                            .into_iter()
-                            .map(|a| match a {
-                                CallArg::Positional(e) => e,
-                                CallArg::Keyword(_, e) => e,
-                            })
-                            .collect()
+                        self.narrow_block(then_block, &mut locals.clone(), calls)?;
                     }
-                } else {
-                    // All positional — pass through
-                    call_args
-                        .into_iter()
-                        .map(|a| match a {
-                            CallArg::Positional(e) => e,
-                            CallArg::Keyword(_, e) => e,
-                        })
-                        .collect()
-                };
-
-                // Convert len(x) → x.len()
-                if let e::Expr::Path(ref path) = expr {
-                    if path.len() == 1 && path[0] == "len" && args.len() == 1 {
-                        let receiver = args.into_iter().next().unwrap();
-                        expr = e::Expr::Call {
-                            callee: Box::new(e::Expr::Field {
-                                base: Box::new(receiver),
-                                field: "len".into(),
-                            }),
-                            args: vec![],
-                        };
-                        continue;
+                    if let Some(block) = else_block {
+                        self.narrow_block(block, &mut locals.clone(), calls)?;
                     }
                 }
-
-                // Convert range(end) → 0..end, range(start, end) → start..end
-                if let e::Expr::Path(ref path) = expr {
-                    if path.len() == 1 && path[0] == "range" {
-                        match args.len() {
-                            1 => {
-                                expr = e::Expr::Range {
-                                    start: Some(Box::new(e::Expr::Int(0))),
-                                    end: Some(Box::new(args.into_iter().next().unwrap())),
-                                    inclusive: false,
-                                };
-                                continue;
-                            }
-                            2 => {
-                                let mut it = args.into_iter();
-                                let start = it.next().unwrap();
-                                let end = it.next().unwrap();
-                                expr = e::Expr::Range {
-                                    start: Some(Box::new(start)),
-                                    end: Some(Box::new(end)),
-                                    inclusive: false,
-                                };
-                                continue;
-                            }
-                            _ => {} // fall through to normal Call
+                e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+                    self.narrow_block(body, &mut locals.clone(), calls)?;
+                }
+                e::Stmt::Expr(e::Expr::Match { scrutinee, arms }) => {
+                    if let Some(union) = self.union_of(scrutinee, locals) {
+                        for arm in arms.iter_mut() {
+                            self.narrow_pattern(&mut arm.pattern, &union)?;
                         }
                     }
                 }
-                expr = e::Expr::Call {
-                    callee: Box::new(expr),
-                    args,
-                };
-                expr = Self::coerce_len_call_result(expr);
-            } else if self.check(&TokenKind::LBracket) {
-                self.advance()?;
-                let index = self.parse_expr()?;
-                // Check for range: [start..end]
-                if self.eat(&TokenKind::DotDot)? {
-                    let end = self.parse_expr()?;
-                    self.expect(&TokenKind::RBracket)?;
-                    expr = e::Expr::Index {
-                        base: Box::new(expr),
-                        index: Box::new(e::Expr::Range {
-                            start: Some(Box::new(index)),
-                            end: Some(Box::new(end)),
-                            inclusive: false,
-                        }),
-                    };
-                } else {
-                    self.expect(&TokenKind::RBracket)?;
-                    expr = e::Expr::Index {
-                        base: Box::new(expr),
-                        index: Box::new(index),
-                    };
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The `isinstance(name, T)` calls in `span`'s source, by name in source
+    /// order, for the rebinding each narrowed `if` gets.
+    fn isinstance_calls(&self, span: Option<Span>) -> HashMap<String, VecDeque<Span>> {
+        let mut calls: HashMap<String, VecDeque<Span>> = HashMap::new();
+        let Some(span) = span else {
+            return calls;
+        };
+        let text = &self.source[span.start..span.end];
+        for (at, call) in text.match_indices("isinstance(") {
+            let args = &text[at + call.len()..];
+            let name: String = args
+                .trim_start()
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect();
+            let mut depth = 1;
+            let close = args.find(|c| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
                 }
-            } else if self.check_kw(Keyword::As) {
-                self.advance()?; // consume 'as'
-                let target_type = self.parse_type()?;
-                expr = e::Expr::Cast {
-                    expr: Box::new(expr),
-                    target_type,
-                };
-            } else {
-                break;
+                depth == 0
+            });
+            if let (false, Some(close)) = (name.is_empty(), close) {
+                let start = span.start + at;
+                let end = start + call.len() + close + 1;
+                calls
+                    .entry(name)
+                    .or_default()
+                    .push_back(Span::new(start, end));
             }
         }
-        Ok(expr)
+        calls
     }
 
-    fn parse_primary(&mut self) -> Result<e::Expr, ParseError> {
-        match self.kind().clone() {
-            TokenKind::Int(n) => {
-                self.advance()?;
-                Ok(e::Expr::Int(n))
+    /// The result enum of `expr` if it is a union value.
+    fn union_of(&self, expr: &e::Expr, locals: &HashMap<String, String>) -> Option<String> {
+        match expr {
+            e::Expr::Path(path) if path.len() == 1 => locals.get(&path[0]).cloned(),
+            e::Expr::Call { callee, .. } => match &**callee {
+                e::Expr::Path(path) if path.len() == 1 => self.union_returns.get(&path[0]).cloned(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The variant of `union` holding a value of type `ty`.
+    fn union_variant(&self, union: &str, ty: &e::Type) -> Result<String, ParseError> {
+        let variant = Self::type_to_variant_name(ty);
+        let members = &self.union_enums[union];
+        if members
+            .iter()
+            .any(|member| Self::type_to_variant_name(member) == variant)
+        {
+            return Ok(variant);
+        }
+        let names: Vec<String> = members.iter().map(quiche_type).collect();
+        Err(self.error(format!(
+            "`{}` is not one of the types in `{}`",
+            quiche_type(ty),
+            names.join(" | ")
+        )))
+    }
+
+    /// `isinstance(name, T)` on a union local: the local, its enum, the
+    /// variant for `T`, and `T`.
+    fn isinstance_test(
+        &self,
+        condition: &e::Expr,
+        locals: &HashMap<String, String>,
+    ) -> Result<Option<(String, String, String, e::Type)>, ParseError> {
+        let e::Expr::Call { callee, args } = condition else {
+            return Ok(None);
+        };
+        if !matches!(&**callee, e::Expr::Path(p) if p == &["isinstance"]) {
+            return Ok(None);
+        }
+        let [e::Expr::Path(path), ty] = args.as_slice() else {
+            return Ok(None);
+        };
+        let [name] = path.as_slice() else {
+            return Ok(None);
+        };
+        let Some(union) = locals.get(name) else {
+            return Ok(None);
+        };
+        let Some(ty) = decorator_type(ty) else {
+            return Err(self.error("isinstance: expected a type as the second argument".into()));
+        };
+        let variant = self.union_variant(union, &ty)?;
+        Ok(Some((name.clone(), union.clone(), variant, ty)))
+    }
+
+    /// Rewrite `case i64(n)` against a union to `case ParseResult.I64(n)`.
+    fn narrow_pattern(&self, pattern: &mut e::Pattern, union: &str) -> Result<(), ParseError> {
+        if let e::Pattern::Variant { path, .. } = pattern
+            && let [name] = path.as_slice()
+            && name != "None"
+        {
+            let ty =
+                decorator_type(&e::Expr::Path(vec![name.clone()])).unwrap_or_else(|| e::Type {
+                    path: vec![name.clone()],
+                    args: vec![],
+                    trait_bounds: vec![],
+                });
+            let variant = self.union_variant(union, &ty)?;
+            *path = vec![union.to_string(), variant];
+        }
+        Ok(())
+    }
+
+    /// Convert a Type to a PascalCase variant name for union enum generation.
+    /// Examples: `i64` → `I64`, `String` → `String`, `Vec[i32]` → `VecI32`
+    fn type_to_variant_name(ty: &e::Type) -> String {
+        let base = ty.path.last().unwrap_or(&String::new()).clone();
+        let mut name = String::new();
+        // Capitalize first letter
+        let mut chars = base.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+        // Append generic args
+        for arg in &ty.args {
+            let arg_name = Self::type_to_variant_name(arg);
+            name.push_str(&arg_name);
+        }
+        name
+    }
+
+    /// `type Meters = new f64`: a `#[repr(transparent)]` struct over one
+    /// `value` field, constructed as `Meters(3.5)` and converted back with
+    /// `f64.from(m)` or `m.value`. It displays as its value and, over a
+    /// number, supports `+`/`-` between values of the newtype and `*`/`/` by
+    /// a plain number.
+    fn newtype(
+        &mut self,
+        name: String,
+        type_params: Vec<e::GenericParam>,
+        inner: e::Type,
+        start: usize,
+    ) -> Result<Vec<e::Item>, ParseError> {
+        if !type_params.is_empty() {
+            return Err(self.error(format!("newtype '{name}' cannot be generic")));
+        }
+        self.struct_fields
+            .insert(name.clone(), vec!["value".to_string()]);
+        let ty = rust_type(&inner);
+        let mut code = format!(
+            "// quiche:transparent {name}\n\
+             impl From<{ty}> for {name} {{\n    \
+             fn from(value: {ty}) -> Self {{\n        {name} {{ value }}\n    }}\n}}\n\n\
+             impl From<{name}> for {ty} {{\n    \
+             fn from(value: {name}) -> Self {{\n        value.value\n    }}\n}}\n\n\
+             impl std::fmt::Display for {name} {{\n    \
+             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{\n        \
+             std::fmt::Display::fmt(&self.value, f)\n    }}\n}}\n"
+        );
+        let numeric = c_type(&inner).is_some_and(|c| !matches!(c.quiche, "bool" | "Str" | "Ptr"));
+        if numeric {
+            for (op, method) in [("Add", "add"), ("Sub", "sub")] {
+                code.push_str(&format!(
+                    "\nimpl std::ops::{op} for {name} {{\n    type Output = {name};\n    \
+                     fn {method}(self, rhs: {name}) -> {name} {{\n        \
+                     {name} {{ value: std::ops::{op}::{method}(self.value, rhs.value) }}\n    }}\n}}\n\n\
+                     impl std::ops::{op}Assign for {name} {{\n    \
+                     fn {method}_assign(&mut self, rhs: {name}) {{\n        \
+                     self.value = std::ops::{op}::{method}(self.value, rhs.value);\n    }}\n}}\n"
+                ));
             }
-            TokenKind::Float(_f) => {
-                // Elevate doesn't have Float in AST — emit as Int for now
-                self.advance()?;
-                Ok(e::Expr::Int(0)) // TODO: float support
+            for (op, method) in [("Mul", "mul"), ("Div", "div")] {
+                code.push_str(&format!(
+                    "\nimpl std::ops::{op}<{ty}> for {name} {{\n    type Output = {name};\n    \
+                     fn {method}(self, rhs: {ty}) -> {name} {{\n        \
+                     {name} {{ value: std::ops::{op}::{method}(self.value, rhs) }}\n    }}\n}}\n"
+                ));
             }
-            TokenKind::String(s) => {
-                self.advance()?;
-                // Wrap in str() constructor for Smart String (Arc<str>)
-                Ok(e::Expr::Call {
-                    callee: Box::new(e::Expr::Path(vec!["str".into()])),
-                    args: vec![e::Expr::String(s)],
-                })
+            if !ty.starts_with('u') {
+                code.push_str(&format!(
+                    "\nimpl std::ops::Neg for {name} {{\n    type Output = {name};\n    \
+                     fn neg(self) -> {name} {{\n        {name} {{ value: -self.value }}\n    }}\n}}\n"
+                ));
             }
-            TokenKind::Keyword(Keyword::True) => {
-                self.advance()?;
-                Ok(e::Expr::Bool(true))
+        }
+        Ok(vec![
+            e::Item::Struct(e::StructDef {
+                visibility: e::Visibility::Public,
+                name,
+                type_params,
+                fields: vec![e::Field {
+                    name: "value".into(),
+                    ty: inner,
+                }],
+                span: self.span_from(start),
+            }),
+            e::Item::RustBlock(code.trim_end().to_string()),
+        ])
+    }
+
+    /// `type Point = (x: i64, y: i64)`: a struct that also behaves like a
+    /// tuple. It is built positionally or by keyword like any class, shows
+    /// as `Point(x=1, y=2)`, unpacks with `x, y = p` (through quiche-lib's
+    /// `QuicheUnpack`) and converts from a plain `(i64, i64)`.
+    fn named_tuple(
+        &mut self,
+        name: String,
+        type_params: Vec<e::GenericParam>,
+        start: usize,
+    ) -> Result<Vec<e::Item>, ParseError> {
+        if !type_params.is_empty() {
+            return Err(self.error(format!("named tuple '{name}' cannot be generic")));
+        }
+        self.expect(&TokenKind::LParen)?;
+        let mut fields = Vec::new();
+        while !self.check(&TokenKind::RParen) {
+            let field = self.expect_ident()?;
+            self.expect(&TokenKind::Colon)?;
+            let ty = self.parse_type()?;
+            if fields.iter().any(|f: &e::Field| f.name == field) {
+                return Err(self.error(format!(
+                    "named tuple '{name}' has two fields named '{field}'"
+                )));
             }
-            TokenKind::Keyword(Keyword::False) => {
-                self.advance()?;
-                Ok(e::Expr::Bool(false))
+            fields.push(e::Field { name: field, ty });
+            if !self.eat(&TokenKind::Comma)? {
+                break;
             }
-            TokenKind::Keyword(Keyword::None) => {
-                self.advance()?;
-                Ok(e::Expr::Path(vec!["None".into()]))
+        }
+        self.expect(&TokenKind::RParen)?;
+        if fields.is_empty() {
+            return Err(self.error(format!("named tuple '{name}' needs at least one field")));
+        }
+        self.struct_fields.insert(
+            name.clone(),
+            fields.iter().map(|f| f.name.clone()).collect(),
+        );
+
+        let names: Vec<&str> = fields.iter().map(|f| f.name.as_str()).collect();
+        let types: Vec<String> = fields.iter().map(|f| rust_type(&f.ty)).collect();
+        let tuple = format!("({},)", types.join(", "));
+        let shown: Vec<String> = names.iter().map(|n| format!("{n}={{}}")).collect();
+        let shown_args: Vec<String> = names
+            .iter()
+            .map(|n| format!("quiche_show!(self.{n})"))
+            .collect();
+        let owned: Vec<String> = names.iter().map(|n| format!("self.{n}")).collect();
+        let borrowed: Vec<String> = names.iter().map(|n| format!("&self.{n}")).collect();
+        let borrowed_types: Vec<String> = types.iter().map(|t| format!("&'a {t}")).collect();
+        let code = format!(
+            "impl std::fmt::Display for {name} {{\n    \
+             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{\n        \
+             write!(f, \"{name}({})\", {})\n    }}\n}}\n\n\
+             impl QuicheUnpack for {name} {{\n    type Tuple = {tuple};\n    \
+             fn unpack(self) -> {tuple} {{\n        ({},)\n    }}\n}}\n\n\
+             impl<'a> QuicheUnpack for &'a {name} {{\n    type Tuple = ({},);\n    \
+             fn unpack(self) -> Self::Tuple {{\n        ({},)\n    }}\n}}\n\n\
+             impl From<{tuple}> for {name} {{\n    \
+             fn from(({},): {tuple}) -> Self {{\n        {name} {{ {} }}\n    }}\n}}",
+            shown.join(", "),
+            shown_args.join(", "),
+            owned.join(", "),
+            borrowed_types.join(", "),
+            borrowed.join(", "),
+            names.join(", "),
+            names.join(", "),
+        );
+        Ok(vec![
+            e::Item::Struct(e::StructDef {
+                visibility: e::Visibility::Public,
+                name,
+                type_params,
+                fields,
+                span: self.span_from(start),
+            }),
+            e::Item::RustBlock(code),
+        ])
+    }
+
+    // ─────────────────────────────────────────────────────────────────────────
+
+    fn parse_block(&mut self) -> Result<e::Block, ParseError> {
+        let mut statements = Vec::new();
+
+        // Inline single-statement (e.g., `if x: return 1`)
+        if !self.check(&TokenKind::Newline) && !self.check(&TokenKind::Indent) {
+            self.push_probe(&mut statements);
+            self.parse_stmt_into(&mut statements)?;
+            return Ok(e::Block { statements });
+        }
+
+        self.skip_newlines()?;
+        self.expect(&TokenKind::Indent)?;
+
+        while !self.check(&TokenKind::Dedent) && !self.check(&TokenKind::Eof) {
+            self.skip_newlines()?;
+            if self.check(&TokenKind::Dedent) || self.check(&TokenKind::Eof) {
+                break;
             }
-            TokenKind::Ident(name) => {
+            self.push_probe(&mut statements);
+            self.parse_stmt_into(&mut statements)?;
+            self.skip_newlines()?;
+        }
+
+        if self.check(&TokenKind::Dedent) {
+            self.advance()?;
+        }
+
+        Ok(e::Block { statements })
+    }
+
+    /// In coverage mode, emit `Qtest.hit(line)` ahead of the statement about
+    /// to be parsed and remember the line as instrumented.
+    fn is_probe(stmt: &e::Stmt) -> bool {
+        matches!(stmt, e::Stmt::Expr(e::Expr::Call { callee, .. })
+            if matches!(callee.as_ref(), e::Expr::Path(p) if p.len() == 2 && p[0] == "Qtest" && p[1] == "hit"))
+    }
+
+    fn push_probe(&mut self, statements: &mut Vec<e::Stmt>) {
+        if !self.coverage {
+            return;
+        }
+        let line = self.current.line;
+        self.probes.push(line);
+        statements.push(e::Stmt::Expr(e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["Qtest".into(), "hit".into()])),
+            args: vec![e::Expr::Int(line as i64)],
+        }));
+    }
+
+    // ─────────────────────────────────────────────────────────────────────────
+    // Statements
+    // ─────────────────────────────────────────────────────────────────────────
+
+    /// Parse one statement onto `statements`; a `try` lowers to several.
+    fn parse_stmt_into(&mut self, statements: &mut Vec<e::Stmt>) -> Result<(), ParseError> {
+        if self.check_kw(Keyword::Try) {
+            statements.extend(self.parse_try()?);
+        } else {
+            statements.push(self.parse_stmt()?);
+        }
+        Ok(())
+    }
+
+    fn parse_stmt(&mut self) -> Result<e::Stmt, ParseError> {
+        if matches!(self.kind(), TokenKind::Ident(word) if word == "unsafe")
+            && matches!(self.peek()?.kind, TokenKind::Colon)
+        {
+            return self.parse_unsafe();
+        }
+        match self.kind() {
+            TokenKind::Keyword(Keyword::Return) => self.parse_return(),
+            TokenKind::Keyword(Keyword::If) => self.parse_if_or_elif(),
+            TokenKind::Keyword(Keyword::While) => self.parse_while(),
+            TokenKind::Keyword(Keyword::For) => self.parse_for(),
+            TokenKind::Keyword(Keyword::Match) => self.parse_match(),
+            // `...` is `pass`, as in a `@trait` method's signature
+            TokenKind::Keyword(Keyword::Pass) | TokenKind::Ellipsis => {
                 self.advance()?;
-                Ok(e::Expr::Path(vec![name]))
+                Ok(e::Stmt::Expr(e::Expr::Tuple(vec![])))
             }
-            TokenKind::LParen => {
+            TokenKind::Keyword(Keyword::Break) => {
                 self.advance()?;
-                if self.check(&TokenKind::RParen) {
-                    self.advance()?;
-                    return Ok(e::Expr::Tuple(vec![]));
-                }
-                let expr = self.parse_expr()?;
-                // Check for tuple: (a, b, ...)
-                if self.eat(&TokenKind::Comma)? {
-                    let mut elems = vec![expr];
-                    while !self.check(&TokenKind::RParen) {
-                        elems.push(self.parse_expr()?);
-                        if !self.eat(&TokenKind::Comma)? {
-                            break;
-                        }
-                    }
-                    self.expect(&TokenKind::RParen)?;
-                    return Ok(e::Expr::Tuple(elems));
-                }
-                self.expect(&TokenKind::RParen)?;
-                Ok(expr) // parenthesized expression
+                Ok(e::Stmt::Break)
             }
-            TokenKind::LBracket => {
+            TokenKind::Keyword(Keyword::Continue) => {
                 self.advance()?;
-                if self.check(&TokenKind::RBracket) {
-                    self.advance()?;
-                    return Ok(e::Expr::Array(vec![]));
-                }
-                let first = self.parse_expr()?;
-                // List comprehension: [expr for var in iter] or [expr for var in iter if cond]
-                if self.check(&TokenKind::Keyword(Keyword::For)) {
-                    self.advance()?; // consume 'for'
-                    let var = self.expect_ident()?;
-                    self.expect(&TokenKind::Keyword(Keyword::In))?;
-                    let iter_expr = self.parse_expr()?;
-                    // Optional filter: if cond
-                    let filter = if self.eat(&TokenKind::Keyword(Keyword::If))? {
-                        Some(self.parse_expr()?)
-                    } else {
-                        None
-                    };
-                    self.expect(&TokenKind::RBracket)?;
-                    return Ok(Self::build_list_comprehension(
-                        var, iter_expr, first, filter,
-                    ));
+                Ok(e::Stmt::Continue)
+            }
+            TokenKind::Keyword(Keyword::Def) => Err(self.unsupported(unsupported::NESTED_DEF)),
+            TokenKind::Keyword(Keyword::Assert) => self.parse_assert(),
+            TokenKind::Keyword(Keyword::Yield) => self.parse_yield(),
+            TokenKind::Keyword(Keyword::Raise) => self.parse_raise(),
+            TokenKind::Keyword(keyword @ (Keyword::Except | Keyword::Finally)) => {
+                let keyword = format!("{keyword:?}").to_lowercase();
+                Err(self.error(format!("`{keyword}` without a matching `try:`")))
+            }
+            TokenKind::Keyword(Keyword::With) => self.parse_with(),
+            TokenKind::Keyword(Keyword::Global | Keyword::Nonlocal) => {
+                Err(self.unsupported(unsupported::GLOBAL))
+            }
+            TokenKind::Keyword(Keyword::Del) => Err(self.unsupported(unsupported::DEL)),
+            TokenKind::Keyword(Keyword::Async) => Err(self.unsupported(unsupported::ASYNC)),
+            _ => self.parse_expr_or_assign(),
+        }
+    }
+
+    /// `unsafe:` block → `if __unsafe__!() { ... }`, which `apply_unsafe_blocks`
+    /// turns into a Rust `unsafe { ... }` block. Whether it may be compiled at
+    /// all is up to the caller (`--allow-unsafe`), which reads the positions
+    /// recorded here.
+    fn parse_unsafe(&mut self) -> Result<e::Stmt, ParseError> {
+        self.unsafe_blocks
+            .push((self.current.line, self.current.column));
+        self.advance()?;
+        self.expect(&TokenKind::Colon)?;
+        let then_block = self.parse_block()?;
+        Ok(e::Stmt::If {
+            condition: e::Expr::MacroCall {
+                path: vec!["__unsafe__".into()],
+                args: vec![],
+            },
+            then_block,
+            else_block: None,
+        })
+    }
+
+    /// `with` block → `if __with__!() { ... }`, which `apply_with_blocks`
+    /// turns into a plain Rust block, each manager entered through
+    /// quiche-lib's `With` guard:
+    ///
+    /// ```text
+    /// with open(path) as f:        if __with__!():
+    ///     print(f.read())    →         f = With::enter(open(path))
+    ///                                  print(f.read())
+    /// ```
+    ///
+    /// The guards are dropped where the block ends, on `return`, `break`, a
+    /// raise or a panic too, exiting their managers in reverse order.
+    fn parse_with(&mut self) -> Result<e::Stmt, ParseError> {
+        self.expect_kw(Keyword::With)?;
+        let mut statements = Vec::new();
+        loop {
+            // `as` parses as a cast: `open(path) as f` is `f`'s binding
+            let (manager, name) = match self.parse_expr()? {
+                e::Expr::Cast { expr, target_type }
+                    if target_type.args.is_empty() && target_type.path.len() == 1 =>
+                {
+                    (*expr, target_type.path.concat())
                 }
-                // Regular array literal
-                let mut elems = vec![first];
-                if self.eat(&TokenKind::Comma)? {
-                    while !self.check(&TokenKind::RBracket) {
-                        elems.push(self.parse_expr()?);
-                        if !self.eat(&TokenKind::Comma)? {
-                            break;
-                        }
-                    }
+                manager => {
+                    self.with_count += 1;
+                    (manager, format!("__with_{}", self.with_count))
                 }
-                self.expect(&TokenKind::RBracket)?;
-                Ok(e::Expr::Array(elems))
+            };
+            statements.push(e::Stmt::Assign {
+                target: e::AssignTarget::Path(name),
+                op: e::AssignOp::Assign,
+                value: e::Expr::Call {
+                    callee: Box::new(e::Expr::Path(vec!["With".into(), "enter".into()])),
+                    args: vec![manager],
+                },
+            });
+            if !self.eat(&TokenKind::Comma)? {
+                break;
             }
-            TokenKind::FString { content, .. } => {
-                self.advance()?;
-                // f-string → emit as format!() with ALL expressions as positional args.
-                // Rust's format!() only accepts identifiers inside {}, not expressions,
-                // so we always extract to positional args:
-                //   f"{name} is {age * 2}" → format!("{} is {}", name, age * 2)
-                //   f"literal {{braces}}"  → format!("literal {{braces}}")
-                let mut format_str = String::new();
-                let mut args: Vec<e::Expr> = Vec::new();
-                let mut chars = content.chars().peekable();
-                while let Some(c) = chars.next() {
-                    if c == '{' {
-                        // Check for escaped brace: {{ → literal {
-                        if chars.peek() == Some(&'{') {
-                            chars.next();
-                            format_str.push_str("{{");
-                            continue;
-                        }
-                        // Collect the expression inside { ... }
-                        let mut expr_str = String::new();
-                        let mut brace_depth = 1;
-                        while let Some(&nc) = chars.peek() {
-                            if nc == '{' {
-                                brace_depth += 1;
-                                expr_str.push(nc);
-                                chars.next();
-                            } else if nc == '}' {
-                                brace_depth -= 1;
-                                if brace_depth == 0 {
-                                    chars.next();
-                                    break;
-                                }
-                                expr_str.push(nc);
-                                chars.next();
-                            } else {
-                                expr_str.push(nc);
-                                chars.next();
-                            }
-                        }
-                        // ALL expressions become positional args (safe for Rust format!)
-                        format_str.push_str("{}");
-                        let mut sub = Parser::new(&expr_str)?;
-                        let parsed_expr = sub.parse_expr()?;
-                        args.push(parsed_expr);
-                    } else if c == '}' {
-                        // Check for escaped close brace: }} → literal }
-                        if chars.peek() == Some(&'}') {
-                            chars.next();
-                            format_str.push_str("}}");
-                        } else {
-                            format_str.push(c);
-                        }
-                    } else {
-                        format_str.push(c);
+        }
+        self.expect(&TokenKind::Colon)?;
+        statements.extend(self.parse_block()?.statements);
+        Ok(e::Stmt::If {
+            condition: e::Expr::MacroCall {
+                path: vec!["__with__".into()],
+                args: vec![],
+            },
+            then_block: e::Block { statements },
+            else_block: None,
+        })
+    }
+
+    /// `try:` with `except`, `else:` and `finally:` clauses, lowered onto
+    /// quiche-lib's `Exception`. The body runs as a closure under
+    /// `Exception::attempt`, so a `raise` (or `?`) in it returns the exception
+    /// to the clauses:
+    ///
+    /// ```text
+    /// try:                          __try_1 = Exception::attempt(|| { body; Ok(()) })
+    ///     body                      if __try_1.is_err():
+    /// except KeyError as e:    →        __error_1 = __try_1.unwrap_err()
+    ///     handle(e)                     if quiche_except!(__error_1, KeyError):
+    /// else:                                 e = quiche_caught!(__error_1, KeyError)
+    ///     done()                            handle(e)
+    ///                                   else:
+    ///                                       <raise __error_1 again>
+    ///                               else:
+    ///                                   done()
+    /// ```
+    ///
+    /// With `finally:`, all of that runs under a second `Exception::attempt`,
+    /// and the finally block runs before an exception left over is raised
+    /// again. Code run in a closure can't `return`, or `break` / `continue`
+    /// a loop outside it.
+    fn parse_try(&mut self) -> Result<Vec<e::Stmt>, ParseError> {
+        self.expect_kw(Keyword::Try)?;
+        self.expect(&TokenKind::Colon)?;
+        self.try_count += 1;
+        let try_var = format!("__try_{}", self.try_count);
+        let error_var = format!("__error_{}", self.try_count);
+        let outer_raise = std::mem::replace(&mut self.raise_into_result, true);
+        let body = self.parse_block();
+        self.raise_into_result = outer_raise;
+        let body = body?;
+        if let Some(escape) = block_escape(&body, false) {
+            return Err(self.error(format!(
+                "`{escape}` inside a `try` body is not supported; set a variable and {escape} after the `try`"
+            )));
+        }
+
+        // Each clause as (condition, statements); `None` catches everything
+        let mut handlers: Vec<(Option<e::Expr>, Vec<e::Stmt>)> = Vec::new();
+        self.skip_newlines()?;
+        while self.check_kw(Keyword::Except) {
+            if handlers
+                .last()
+                .is_some_and(|(condition, _)| condition.is_none())
+            {
+                return Err(self.error("a bare `except:` must be the last except clause".into()));
+            }
+            self.advance()?;
+            let mut types = Vec::new();
+            if self.eat(&TokenKind::LParen)? {
+                while !self.check(&TokenKind::RParen) {
+                    types.push(self.parse_exception_type()?);
+                    if !self.eat(&TokenKind::Comma)? {
+                        break;
                     }
                 }
-                let mut macro_args = vec![e::Expr::String(format_str)];
-                macro_args.extend(args);
-                let macro_call = e::Expr::MacroCall {
-                    path: vec!["format".into()],
-                    args: macro_args,
+                self.expect(&TokenKind::RParen)?;
+            } else if !self.check(&TokenKind::Colon) {
+                types.push(self.parse_exception_type()?);
+            }
+            let binding = if self.eat(&TokenKind::Keyword(Keyword::As))? {
+                Some(self.expect_ident()?)
+            } else {
+                None
+            };
+            self.expect(&TokenKind::Colon)?;
+            self.handling.push(error_var.clone());
+            let block = self.parse_block();
+            self.handling.pop();
+            let mut statements = Vec::new();
+            // `except Exception` catches everything, like a bare `except`
+            let catch_all = types.iter().all(|ty| ty == &["Exception"]);
+            if let Some(name) = binding {
+                let ty = match types.as_slice() {
+                    [ty] => ty.clone(),
+                    _ => vec!["Exception".to_string()],
                 };
-                // Wrap in str() to produce Str instead of String
-                Ok(e::Expr::Call {
-                    callee: Box::new(e::Expr::Path(vec!["str".into()])),
-                    args: vec![macro_call],
-                })
+                statements.push(e::Stmt::Assign {
+                    target: e::AssignTarget::Path(name),
+                    op: e::AssignOp::Assign,
+                    value: e::Expr::MacroCall {
+                        path: vec!["quiche_caught".into()],
+                        args: vec![e::Expr::Path(vec![error_var.clone()]), e::Expr::Path(ty)],
+                    },
+                });
             }
-            TokenKind::LBrace => {
+            statements.extend(block?.statements);
+            let condition = (!catch_all).then(|| e::Expr::MacroCall {
+                path: vec!["quiche_except".into()],
+                args: std::iter::once(e::Expr::Path(vec![error_var.clone()]))
+                    .chain(types.into_iter().map(e::Expr::Path))
+                    .collect(),
+            });
+            handlers.push((condition, statements));
+            self.skip_newlines()?;
+        }
+        let else_block = if !handlers.is_empty() && self.check_kw(Keyword::Else) {
+            self.advance()?;
+            self.expect(&TokenKind::Colon)?;
+            let block = self.parse_block()?;
+            self.skip_newlines()?;
+            Some(block)
+        } else {
+            None
+        };
+        let finally_block = if self.check_kw(Keyword::Finally) {
+            self.advance()?;
+            self.expect(&TokenKind::Colon)?;
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+        if handlers.is_empty() && finally_block.is_none() {
+            return Err(self.error("expected `except` or `finally:` after the `try` body".into()));
+        }
+
+        let attempt = |var: &str, mut statements: Vec<e::Stmt>| {
+            statements.push(e::Stmt::TailExpr(e::Expr::Call {
+                callee: Box::new(e::Expr::Path(vec!["Ok".into()])),
+                args: vec![e::Expr::Tuple(vec![])],
+            }));
+            e::Stmt::Assign {
+                target: e::AssignTarget::Path(var.to_string()),
+                op: e::AssignOp::Assign,
+                value: e::Expr::Call {
+                    callee: Box::new(e::Expr::Path(vec!["Exception".into(), "attempt".into()])),
+                    args: vec![e::Expr::Closure {
+                        params: vec![],
+                        return_type: None,
+                        body: e::Block { statements },
+                    }],
+                },
+            }
+        };
+        let method = |var: &str, name: &str| e::Expr::Call {
+            callee: Box::new(e::Expr::Field {
+                base: Box::new(e::Expr::Path(vec![var.to_string()])),
+                field: name.into(),
+            }),
+            args: vec![],
+        };
+
+        // Inside a second attempt when there is a finally block
+        let in_closure = finally_block.is_some();
+        let mut lowered = if handlers.is_empty() {
+            body.statements
+        } else {
+            let mut chain = vec![raise_stmt(
+                e::Expr::Path(vec![error_var.clone()]),
+                in_closure || self.raise_into_result,
+            )];
+            for (condition, mut statements) in handlers.into_iter().rev() {
+                if in_closure {
+                    raise_into_result(&mut statements);
+                }
+                chain = match condition {
+                    Some(condition) => vec![e::Stmt::If {
+                        condition,
+                        then_block: e::Block { statements },
+                        else_block: Some(e::Block { statements: chain }),
+                    }],
+                    None => statements,
+                };
+            }
+            let mut then_statements = vec![e::Stmt::Assign {
+                target: e::AssignTarget::Path(error_var.clone()),
+                op: e::AssignOp::Assign,
+                value: method(&try_var, "unwrap_err"),
+            }];
+            then_statements.extend(chain);
+            let else_block = else_block.map(|mut block| {
+                if in_closure {
+                    raise_into_result(&mut block.statements);
+                }
+                block
+            });
+            vec![
+                attempt(&try_var, body.statements),
+                e::Stmt::If {
+                    condition: method(&try_var, "is_err"),
+                    then_block: e::Block {
+                        statements: then_statements,
+                    },
+                    else_block,
+                },
+            ]
+        };
+        let Some(finally_block) = finally_block else {
+            return Ok(lowered);
+        };
+        let lowered_block = e::Block {
+            statements: lowered,
+        };
+        if let Some(escape) = block_escape(&lowered_block, false) {
+            return Err(self.error(format!(
+                "`{escape}` inside `except` / `else` is not supported with a `finally:` block"
+            )));
+        }
+        let outcome_var = format!("__outcome_{}", self.try_count);
+        lowered = vec![attempt(&outcome_var, lowered_block.statements)];
+        lowered.extend(finally_block.statements);
+        let outcome = e::Expr::Path(vec![outcome_var]);
+        lowered.push(e::Stmt::Expr(if self.raise_into_result {
+            e::Expr::MacroCall {
+                path: vec!["quiche_try".into()],
+                args: vec![outcome],
+            }
+        } else {
+            e::Expr::Call {
+                callee: Box::new(e::Expr::Path(vec!["Exception".into(), "check".into()])),
+                args: vec![outcome],
+            }
+        }));
+        Ok(lowered)
+    }
+
+    /// The exception class named in an `except` clause: `ValueError`,
+    /// `errors.NotFound`.
+    fn parse_exception_type(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut path = vec![self.expect_ident()?];
+        while self.eat(&TokenKind::Dot)? {
+            path.push(self.expect_ident()?);
+        }
+        Ok(path)
+    }
+
+    /// `raise value`, or a bare `raise` of the exception an `except` block
+    /// caught (see `parse_try`).
+    fn parse_raise(&mut self) -> Result<e::Stmt, ParseError> {
+        self.expect_kw(Keyword::Raise)?;
+        if matches!(
+            self.kind(),
+            TokenKind::Newline | TokenKind::Dedent | TokenKind::Eof
+        ) {
+            let Some(error) = self.handling.last() else {
+                return Err(self.error("a bare `raise` must be inside an `except` block".into()));
+            };
+            return Ok(raise_stmt(
+                e::Expr::Path(vec![error.clone()]),
+                self.raise_into_result,
+            ));
+        }
+        let value = self.parse_expr()?;
+        if self.check_kw(Keyword::From) {
+            return Err(self.error(
+                "`raise ... from ...` is not supported; raise the new exception alone".into(),
+            ));
+        }
+        Ok(raise_stmt(
+            e::Expr::MacroCall {
+                path: vec!["quiche_raise".into()],
+                args: vec![value],
+            },
+            self.raise_into_result,
+        ))
+    }
+
+    fn parse_return(&mut self) -> Result<e::Stmt, ParseError> {
+        self.expect_kw(Keyword::Return)?;
+        if matches!(
+            self.kind(),
+            TokenKind::Newline | TokenKind::Dedent | TokenKind::Eof
+        ) {
+            Ok(e::Stmt::Return(None))
+        } else {
+            Ok(e::Stmt::Return(Some(self.parse_expr()?)))
+        }
+    }
+
+    /// `yield expr` inside an `@fixture` → `__yield__!(expr)`, later split by
+    /// `split_fixture` into setup and teardown halves.
+    fn parse_yield(&mut self) -> Result<e::Stmt, ParseError> {
+        if !self.in_fixture {
+            return Err(self.unsupported(unsupported::GENERATOR));
+        }
+        self.expect_kw(Keyword::Yield)?;
+        let value = self.parse_expr()?;
+        Ok(e::Stmt::Expr(e::Expr::MacroCall {
+            path: vec!["__yield__".into()],
+            args: vec![value],
+        }))
+    }
+
+    /// `assert cond` / `assert cond, message`. A failing assert prints the
+    /// values its condition read (see [`Self::assert_captures`]):
+    ///
+    /// ```text
+    /// assertion failed: a + b == expected
+    ///   a + b = 5
+    ///   a = 2
+    ///   b = 3
+    ///   expected = 6
+    /// ```
+    fn parse_assert(&mut self) -> Result<e::Stmt, ParseError> {
+        self.expect_kw(Keyword::Assert)?;
+        let start = self.current.start;
+        let condition = self.parse_expr()?;
+        let text = self.source[start..self.prev_end].to_string();
+        let captures = Self::assert_captures(&condition);
+
+        let mut args = vec![condition];
+
+        // Check for optional message: `assert expr, "message"`
+        let message = if matches!(self.kind(), TokenKind::Comma) {
+            self.advance()?; // consume comma
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        if message.is_some() || !captures.is_empty() {
+            let escape = |text: &str| text.replace('{', "{{").replace('}', "}}");
+            let mut format = match message {
+                Some(_) => "{}".to_string(),
+                None => format!("assertion failed: {}", escape(&text)),
+            };
+            for (text, _) in &captures {
+                format.push_str(&format!("\n  {} = {{}}", escape(text)));
+            }
+            args.push(e::Expr::String(format));
+            args.extend(message);
+            args.extend(captures.into_iter().map(|(_, value)| e::Expr::MacroCall {
+                path: vec!["quiche_show".into()],
+                args: vec![value],
+            }));
+        }
+
+        Ok(e::Stmt::Expr(e::Expr::MacroCall {
+            path: vec!["assert".to_string()],
+            args,
+        }))
+    }
+
+    /// The sub-expressions of an assert's condition to show when it fails,
+    /// with their source text: the operands of a top-level comparison, then
+    /// every variable and field it reads, each once. Only expressions that
+    /// can be evaluated again without side effects or panics qualify (no
+    /// calls, and nothing but plain reads below an `and` / `or`), since the
+    /// failure message re-evaluates them.
+    fn assert_captures(condition: &e::Expr) -> Vec<(String, e::Expr)> {
+        let mut captures: Vec<(String, e::Expr)> = Vec::new();
+        let comparison = match condition {
+            e::Expr::Unary {
+                op: e::UnaryOp::Not,
+                expr,
+            } => expr,
+            other => other,
+        };
+        if Self::is_comparison(comparison)
+            && let e::Expr::Binary { left, right, .. } = comparison
+        {
+            for operand in [left, right] {
+                if matches!(
+                    **operand,
+                    e::Expr::Binary { .. } | e::Expr::Unary { .. } | e::Expr::Index { .. }
+                ) && let Some(text) = Self::assert_text(operand)
+                {
+                    captures.push((text, (**operand).clone()));
+                }
+            }
+        }
+        Self::collect_assert_reads(condition, &mut captures);
+        captures
+    }
+
+    fn collect_assert_reads(expr: &e::Expr, captures: &mut Vec<(String, e::Expr)>) {
+        let is_variable = |expr: &e::Expr| {
+            matches!(expr, e::Expr::Path(p)
+                if p.len() == 1 && p[0].starts_with(|c: char| c.is_lowercase() || c == '_'))
+        };
+        let read = match expr {
+            e::Expr::Path(_) if is_variable(expr) => Self::assert_text(expr),
+            e::Expr::Field { base, .. } if is_variable(Self::field_root(base)) => {
+                Self::assert_text(expr)
+            }
+            _ => None,
+        };
+        if let Some(text) = read {
+            if !captures.iter().any(|(seen, _)| *seen == text) {
+                captures.push((text, expr.clone()));
+            }
+            return;
+        }
+        match expr {
+            e::Expr::Field { base, .. } => Self::collect_assert_reads(base, captures),
+            e::Expr::Call { callee, args } => {
+                if let e::Expr::Field { base, .. } = &**callee {
+                    Self::collect_assert_reads(base, captures);
+                }
+                for arg in args {
+                    Self::collect_assert_reads(arg, captures);
+                }
+            }
+            e::Expr::Index { base, index } => {
+                Self::collect_assert_reads(base, captures);
+                Self::collect_assert_reads(index, captures);
+            }
+            e::Expr::Unary { expr, .. } | e::Expr::Cast { expr, .. } => {
+                Self::collect_assert_reads(expr, captures);
+            }
+            e::Expr::Binary { left, right, .. } => {
+                Self::collect_assert_reads(left, captures);
+                Self::collect_assert_reads(right, captures);
+            }
+            e::Expr::Tuple(items) | e::Expr::Array(items) => {
+                for item in items {
+                    Self::collect_assert_reads(item, captures);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The innermost base of a field chain (`p` in `p.pos.x`).
+    fn field_root(expr: &e::Expr) -> &e::Expr {
+        match expr {
+            e::Expr::Field { base, .. } => Self::field_root(base),
+            other => other,
+        }
+    }
+
+    /// Quiche source text for a call-free expression, or `None` if it has
+    /// calls (or anything else an assert shouldn't re-evaluate).
+    fn assert_text(expr: &e::Expr) -> Option<String> {
+        if let Some(s) = string_literal(expr) {
+            return Some(format!("{s:?}"));
+        }
+        let nested = |expr: &e::Expr| {
+            let text = Self::assert_text(expr)?;
+            Some(if matches!(expr, e::Expr::Binary { .. }) {
+                format!("({text})")
+            } else {
+                text
+            })
+        };
+        match expr {
+            e::Expr::Int(n) => Some(n.to_string()),
+            e::Expr::Bool(b) => Some(if *b { "True" } else { "False" }.to_string()),
+            e::Expr::Path(path) => Some(path.join(".")),
+            e::Expr::Field { base, field } => Some(format!("{}.{field}", nested(base)?)),
+            e::Expr::Index { base, index } => {
+                Some(format!("{}[{}]", nested(base)?, Self::assert_text(index)?))
+            }
+            e::Expr::Unary { op, expr } => Some(match op {
+                e::UnaryOp::Not => format!("not {}", nested(expr)?),
+                e::UnaryOp::Neg => format!("-{}", nested(expr)?),
+            }),
+            e::Expr::Binary { op, left, right } => {
+                let op = match op {
+                    e::BinaryOp::Add => "+",
+                    e::BinaryOp::Sub => "-",
+                    e::BinaryOp::Mul => "*",
+                    e::BinaryOp::Div => "/",
+                    e::BinaryOp::Rem => "%",
+                    e::BinaryOp::And => "and",
+                    e::BinaryOp::Or => "or",
+                    e::BinaryOp::Eq => "==",
+                    e::BinaryOp::Ne => "!=",
+                    e::BinaryOp::Lt => "<",
+                    e::BinaryOp::Le => "<=",
+                    e::BinaryOp::Gt => ">",
+                    e::BinaryOp::Ge => ">=",
+                };
+                Some(format!("{} {op} {}", nested(left)?, nested(right)?))
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_if_or_elif(&mut self) -> Result<e::Stmt, ParseError> {
+        // Consume either `if` or `elif`
+        self.advance()?;
+        let condition = self.parse_expr()?;
+        self.expect(&TokenKind::Colon)?;
+        let then_block = self.parse_block()?;
+
+        // elif chains
+        let mut else_block = None;
+
+        if self.check_kw(Keyword::Elif) {
+            // elif becomes nested if in else
+            let elif_stmt = self.parse_if_or_elif()?;
+            else_block = Some(e::Block {
+                statements: vec![elif_stmt],
+            });
+        } else if self.check_kw(Keyword::Else) {
+            self.advance()?;
+            self.expect(&TokenKind::Colon)?;
+            else_block = Some(self.parse_block()?);
+        }
+
+        Ok(e::Stmt::If {
+            condition,
+            then_block,
+            else_block,
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<e::Stmt, ParseError> {
+        self.expect_kw(Keyword::While)?;
+        let condition = self.parse_expr()?;
+        self.expect(&TokenKind::Colon)?;
+        let body = self.parse_block()?;
+        Ok(e::Stmt::While { condition, body })
+    }
+
+    fn parse_for(&mut self) -> Result<e::Stmt, ParseError> {
+        self.expect_kw(Keyword::For)?;
+        let binding_name = self.expect_ident()?;
+        let binding = e::DestructurePattern::Name(binding_name);
+        self.expect_kw(Keyword::In)?;
+        let mut iter = self.parse_expr()?;
+        // A collection held in a name or field iterates through quiche-lib's
+        // QuicheIterable (a `Str` yields chars, an `Option` zero or one
+        // item, ...). Ranges, literals and method chains such as `.keys()`
+        // already produce iterators.
+        if matches!(
+            iter,
+            e::Expr::Path(_) | e::Expr::Field { .. } | e::Expr::Index { .. }
+        ) {
+            iter = e::Expr::Call {
+                callee: Box::new(e::Expr::Path(vec![
+                    "QuicheIterable".into(),
+                    "quiche_iter".into(),
+                ])),
+                args: vec![iter],
+            };
+        }
+        self.expect(&TokenKind::Colon)?;
+        let body = self.parse_block()?;
+        Ok(e::Stmt::For {
+            binding,
+            iter,
+            body,
+        })
+    }
+
+    fn parse_match(&mut self) -> Result<e::Stmt, ParseError> {
+        self.expect_kw(Keyword::Match)?;
+        let scrutinee = self.parse_expr()?;
+        self.expect(&TokenKind::Colon)?;
+
+        self.skip_newlines()?;
+        self.expect(&TokenKind::Indent)?;
+
+        let mut arms = Vec::new();
+        let mut needs_catch_all = false;
+        while !self.check(&TokenKind::Dedent) && !self.check(&TokenKind::Eof) {
+            self.skip_newlines()?;
+            if self.check(&TokenKind::Dedent) {
+                break;
+            }
+            self.expect_kw(Keyword::Case)?;
+            let mut pattern = self.parse_pattern()?;
+            let bindings = std::mem::take(&mut self.field_bindings);
+            let mut guard = if self.check_kw(Keyword::If) {
                 self.advance()?;
-                self.parse_dict_literal()
+                Some(self.parse_expr()?)
+            } else {
+                None
+            };
+            self.expect(&TokenKind::Colon)?;
+            let mut body = self.parse_block()?;
+            if let e::Pattern::Variant { path, .. } = &pattern
+                && let [enum_name, variant] = path.as_slice()
+                && let Some(fields) = self
+                    .variant_fields
+                    .get(&(enum_name.clone(), variant.clone()))
+            {
+                let (arm_guard, lets) =
+                    Self::named_variant_arm(enum_name, variant, fields, &bindings, guard);
+                pattern = e::Pattern::Binding("__variant".into());
+                guard = Some(arm_guard);
+                body.statements.splice(0..0, lets);
+                needs_catch_all = true;
+            }
+            // Match arm value: wrap body in IIFE if multiple stmts
+            let value = self.block_to_expr(body);
+            arms.push(e::MatchArm {
+                pattern,
+                guard,
+                value,
+            });
+            self.skip_newlines()?;
+        }
+
+        if self.check(&TokenKind::Dedent) {
+            self.advance()?;
+        }
+        // Guarded arms don't count towards exhaustiveness in Rust
+        let has_catch_all = arms.iter().any(|arm| {
+            arm.guard.is_none()
+                && matches!(arm.pattern, e::Pattern::Wildcard | e::Pattern::Binding(_))
+        });
+        if needs_catch_all && !has_catch_all {
+            arms.push(e::MatchArm {
+                pattern: e::Pattern::Wildcard,
+                guard: None,
+                value: e::Expr::MacroCall {
+                    path: vec!["unreachable".into()],
+                    args: vec![],
+                },
+            });
+        }
+
+        Ok(e::Stmt::Expr(e::Expr::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+        }))
+    }
+
+    /// The guard and field bindings of an arm matching a named-field variant,
+    /// `case Shape.Rect(width=w)`. Elevate patterns have no named fields,
+    /// so the arm binds the whole value as `__variant`, checks the variant
+    /// in its guard, and reads each bound field out of it:
+    ///
+    /// ```text
+    /// __variant if matches!(__variant, Shape::Rect { .. }) => {
+    ///     w = (|| -> f64 { let Shape::Rect { width: __field, .. } = &__variant ...; __field.clone() })()
+    ///     ...
+    /// ```
+    ///
+    /// A user guard runs after the check, with the fields bound.
+    fn named_variant_arm(
+        enum_name: &str,
+        variant: &str,
+        fields: &[e::Field],
+        bindings: &[(String, String)],
+        guard: Option<e::Expr>,
+    ) -> (e::Expr, Vec<e::Stmt>) {
+        let rust_value = |ty: Option<e::Type>, code: String| e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![],
+                return_type: ty,
+                body: e::Block {
+                    statements: vec![e::Stmt::RustBlock(code)],
+                },
+            }),
+            args: vec![],
+        };
+        let lets: Vec<e::Stmt> = bindings
+            .iter()
+            .filter_map(|(field, binding)| {
+                let ty = fields.iter().find(|f| &f.name == field)?.ty.clone();
+                Some(e::Stmt::Assign {
+                    target: e::AssignTarget::Path(binding.clone()),
+                    op: e::AssignOp::Assign,
+                    value: rust_value(
+                        Some(ty),
+                        format!(
+                            "let {enum_name}::{variant} {{ {field}: __field, .. }} = &__variant else {{ unreachable!() }};\n__field.clone()"
+                        ),
+                    ),
+                })
+            })
+            .collect();
+        let bool_ty = e::Type {
+            path: vec!["bool".into()],
+            args: vec![],
+            trait_bounds: vec![],
+        };
+        let is_variant = rust_value(
+            Some(bool_ty.clone()),
+            format!("matches!(__variant, {enum_name}::{variant} {{ .. }})"),
+        );
+        let guard = match guard {
+            None => is_variant,
+            Some(user) => {
+                let mut statements = lets.clone();
+                statements.push(e::Stmt::TailExpr(user));
+                e::Expr::Binary {
+                    op: e::BinaryOp::And,
+                    left: Box::new(is_variant),
+                    right: Box::new(e::Expr::Call {
+                        callee: Box::new(e::Expr::Closure {
+                            params: vec![],
+                            return_type: Some(bool_ty),
+                            body: e::Block { statements },
+                        }),
+                        args: vec![],
+                    }),
+                }
+            }
+        };
+        (guard, lets)
+    }
+
+    fn block_to_expr(&self, block: e::Block) -> e::Expr {
+        let stmts = block.statements;
+        if stmts.is_empty() {
+            return e::Expr::Tuple(vec![]);
+        }
+        if stmts.len() == 1 {
+            return match stmts.into_iter().next().unwrap() {
+                e::Stmt::Return(Some(val)) => val,
+                e::Stmt::Expr(val) => val,
+                other => e::Expr::Closure {
+                    params: vec![],
+                    return_type: None,
+                    body: e::Block {
+                        statements: vec![other],
+                    },
+                },
+            };
+        }
+        // A coverage probe turns a one-value arm into two statements; return
+        // the value explicitly so the IIFE still yields it.
+        let mut stmts = stmts;
+        if stmts.len() == 2
+            && Self::is_probe(&stmts[0])
+            && let Some(e::Stmt::Expr(val)) = stmts.pop_if(|s| matches!(s, e::Stmt::Expr(_)))
+        {
+            stmts.push(e::Stmt::Return(Some(val)));
+        }
+        // Multiple statements → IIFE: (|| { stmts })()
+        e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![],
+                return_type: None,
+                body: e::Block { statements: stmts },
+            }),
+            args: vec![],
+        }
+    }
+
+    fn parse_pattern(&mut self) -> Result<e::Pattern, ParseError> {
+        match self.kind().clone() {
+            TokenKind::Keyword(Keyword::True) => {
+                self.advance()?;
+                Ok(e::Pattern::Bool(true))
+            }
+            TokenKind::Keyword(Keyword::False) => {
+                self.advance()?;
+                Ok(e::Pattern::Bool(false))
+            }
+            TokenKind::Keyword(Keyword::None) => {
+                self.advance()?;
+                Ok(e::Pattern::Variant {
+                    path: vec!["None".to_string()],
+                    payload: None,
+                })
+            }
+            TokenKind::Int(n) => {
+                let v = n;
+                self.advance()?;
+                Ok(e::Pattern::Int(v))
+            }
+            TokenKind::String(s) => {
+                let v = s;
+                self.advance()?;
+                Ok(e::Pattern::String(v))
+            }
+            TokenKind::Ident(name) if name == "_" => {
+                self.advance()?;
+                Ok(e::Pattern::Wildcard)
+            }
+            TokenKind::Ident(name) => {
+                self.advance()?;
+                // Check for Enum variant: Name.Variant(payload) or Name(payload)
+                if self.eat(&TokenKind::Dot)? {
+                    let variant = self.expect_ident()?;
+                    // Named fields by keyword: Shape.Rect(width=w, height=_)
+                    if self.check(&TokenKind::LParen)
+                        && let Some(fields) = self
+                            .variant_fields
+                            .get(&(name.clone(), variant.clone()))
+                            .cloned()
+                    {
+                        self.advance()?;
+                        let mut bindings = Vec::new();
+                        while !self.check(&TokenKind::RParen) {
+                            let field = self.expect_ident()?;
+                            if !fields.iter().any(|f| f.name == field) {
+                                return Err(self
+                                    .error(format!("'{name}.{variant}' has no field '{field}'")));
+                            }
+                            self.expect(&TokenKind::Eq)?;
+                            let binding = self.expect_ident()?;
+                            if binding != "_" {
+                                bindings.push((field, binding));
+                            }
+                            if !self.eat(&TokenKind::Comma)? {
+                                break;
+                            }
+                        }
+                        self.expect(&TokenKind::RParen)?;
+                        self.field_bindings = bindings;
+                        return Ok(e::Pattern::Variant {
+                            path: vec![name, variant],
+                            payload: None,
+                        });
+                    }
+                    let payload = if self.eat(&TokenKind::LParen)? {
+                        let inner = self.parse_pattern()?;
+                        self.expect(&TokenKind::RParen)?;
+                        Some(Box::new(inner))
+                    } else {
+                        None
+                    };
+                    Ok(e::Pattern::Variant {
+                        path: vec![name, variant],
+                        payload,
+                    })
+                } else if self.check(&TokenKind::LParen) {
+                    self.advance()?;
+                    let inner = if self.check(&TokenKind::RParen) {
+                        None
+                    } else {
+                        Some(Box::new(self.parse_pattern()?))
+                    };
+                    self.expect(&TokenKind::RParen)?;
+                    Ok(e::Pattern::Variant {
+                        path: vec![name],
+                        payload: inner,
+                    })
+                } else {
+                    Ok(e::Pattern::Binding(name))
+                }
+            }
+            _ => Err(self.error(format!("expected pattern, got {}", self.kind()))),
+        }
+    }
+
+    fn parse_expr_or_assign(&mut self) -> Result<e::Stmt, ParseError> {
+        let stmt_start = self.current.start;
+        let mut expr = self.parse_expr()?;
+
+        // Handle bare comma-separated expressions: `a, *rest, b = items`
+        // Creates an implicit Tuple for destructuring
+        if self.check(&TokenKind::Comma) && !self.check(&TokenKind::Newline) {
+            let mut elems = vec![expr];
+            while self.eat(&TokenKind::Comma)? {
+                if self.check(&TokenKind::Eq)
+                    || self.check(&TokenKind::Newline)
+                    || self.check(&TokenKind::Eof)
+                {
+                    break; // trailing comma
+                }
+                elems.push(self.parse_expr()?);
+            }
+            expr = e::Expr::Tuple(elems);
+        }
+
+        // Check for type annotation: `name: Type = value`
+        if self.check(&TokenKind::Colon) {
+            if let e::Expr::Path(ref path) = expr {
+                if path.len() == 1 {
+                    let name = path[0].clone();
+                    self.advance()?; // consume ':'
+                    let mut ty = self.parse_type()?;
+                    self.box_traits(&mut ty);
+                    if self.eat(&TokenKind::Eq)? {
+                        let mut value = self.parse_expr()?;
+                        value = self.box_value(value, &ty);
+                        // `x: Any = 3` boxes the value into a dynamic Value
+                        if ty.path == ["Value"] && ty.args.is_empty() {
+                            value = e::Expr::Call {
+                                callee: Box::new(e::Expr::Path(vec![
+                                    "Value".into(),
+                                    "from".into(),
+                                ])),
+                                args: vec![value],
+                            };
+                        }
+                        return Ok(e::Stmt::Const(e::ConstDef {
+                            visibility: e::Visibility::Private,
+                            name,
+                            ty: Some(ty),
+                            value,
+                            is_const: false,
+                            span: self.span_from(stmt_start),
+                        }));
+                    }
+                    // Bare annotation without value (e.g., field decl in class body)
+                    return Ok(e::Stmt::Const(e::ConstDef {
+                        visibility: e::Visibility::Public,
+                        name,
+                        ty: Some(ty),
+                        value: e::Expr::Tuple(vec![]),
+                        is_const: false,
+                        span: self.span_from(stmt_start),
+                    }));
+                }
+            }
+        }
+
+        // Assignment: `expr = value` or `expr += value`
+        if self.check(&TokenKind::Eq) {
+            self.advance()?;
+            let value = self.parse_expr()?;
+
+            // Check if LHS is a tuple containing *splat → destructure
+            if let Some(pattern) = self.try_expr_to_destructure(&expr) {
+                // `a, b = value` also unpacks named tuples and borrowed
+                // tuples; literal tuples are already in shape.
+                let value = match (&pattern, value) {
+                    (e::DestructurePattern::Tuple(_), value)
+                        if !matches!(value, e::Expr::Tuple(_)) =>
+                    {
+                        e::Expr::Call {
+                            callee: Box::new(e::Expr::Path(vec![
+                                "QuicheUnpack".into(),
+                                "unpack".into(),
+                            ])),
+                            args: vec![value],
+                        }
+                    }
+                    (_, value) => value,
+                };
+                return Ok(e::Stmt::DestructureConst {
+                    pattern,
+                    value,
+                    is_const: false,
+                });
+            }
+
+            if let Some(attr) = self.class_attr_of(&expr) {
+                return self.class_attr_write(attr, expr, e::AssignOp::Assign, value);
+            }
+            let target = self.expr_to_assign_target(expr)?;
+            if let (e::AssignTarget::Path(name), Some(span)) = (&target, self.span_from(stmt_start))
+            {
+                self.first_assigns.entry(name.clone()).or_insert(span);
+            }
+            return Ok(e::Stmt::Assign {
+                target,
+                op: e::AssignOp::Assign,
+                value,
+            });
+        }
+        if self.check(&TokenKind::PlusEq) {
+            self.advance()?;
+            let value = self.parse_expr()?;
+            if let Some(attr) = self.class_attr_of(&expr) {
+                return self.class_attr_write(attr, expr, e::AssignOp::AddAssign, value);
+            }
+            let target = self.expr_to_assign_target(expr)?;
+            return Ok(e::Stmt::Assign {
+                target,
+                op: e::AssignOp::AddAssign,
+                value,
+            });
+        }
+        // `x -= v` / `x *= v` → `x = x - v`
+        for (token, op) in [
+            (TokenKind::MinusEq, e::BinaryOp::Sub),
+            (TokenKind::StarEq, e::BinaryOp::Mul),
+        ] {
+            if self.check(&token) {
+                self.advance()?;
+                let value = e::Expr::Binary {
+                    op,
+                    left: Box::new(expr.clone()),
+                    right: Box::new(self.parse_expr()?),
+                };
+                if let Some(attr) = self.class_attr_of(&expr) {
+                    return self.class_attr_write(attr, expr, e::AssignOp::Assign, value);
+                }
+                let target = self.expr_to_assign_target(expr)?;
+                return Ok(e::Stmt::Assign {
+                    target,
+                    op: e::AssignOp::Assign,
+                    value,
+                });
+            }
+        }
+        // Built-in call transformations
+        if let e::Expr::Call {
+            ref callee,
+            ref args,
+        } = expr
+        {
+            if let e::Expr::Path(ref path) = **callee {
+                if path.len() == 1 {
+                    let name = path[0].as_str();
+                    match name {
+                        // rust("code") escape hatch — emit verbatim Rust
+                        "rust" if args.len() == 1 => {
+                            if let Some(code) = string_literal(&args[0]) {
+                                return Ok(e::Stmt::RustBlock(code));
+                            }
+                        }
+                        // help(f) → print f's signature and docstring
+                        "help" => {
+                            let [e::Expr::Path(target)] = args.as_slice() else {
+                                return Err(
+                                    self.error("help() takes the name of a function".into())
+                                );
+                            };
+                            let [target] = target.as_slice() else {
+                                return Err(
+                                    self.error("help() takes the name of a function".into())
+                                );
+                            };
+                            self.doc_requests.insert(target.clone());
+                            return Ok(e::Stmt::RustBlock(format!(
+                                "println!(\"{{}}\", __QUICHE_HELP_{target});"
+                            )));
+                        }
+                        // mock(f, replacement) → __mock__!(f, replacement), resolved
+                        // by the qtest harness into a registry call + call-site shim
+                        "mock" if args.len() == 2 => {
+                            let is_name =
+                                |a: &e::Expr| matches!(a, e::Expr::Path(p) if p.len() == 1);
+                            if !is_name(&args[0]) || !is_name(&args[1]) {
+                                return Err(self.error(
+                                    "mock() takes a function name and a replacement function name"
+                                        .into(),
+                                ));
+                            }
+                            return Ok(e::Stmt::Expr(e::Expr::MacroCall {
+                                path: vec!["__mock__".into()],
+                                args: args.clone(),
+                            }));
+                        }
+                        // pprint(x) → quiche_pprint!(x): x's Debug output,
+                        // indented across lines when it's too wide for one
+                        "pprint" if args.len() == 1 => {
+                            return Ok(e::Stmt::Expr(e::Expr::MacroCall {
+                                path: vec!["quiche_pprint".into()],
+                                args: args.clone(),
+                            }));
+                        }
+                        "pprint" => {
+                            return Err(self.error("pprint() takes one value".into()));
+                        }
+                        // print(a, b, c) → println!("{} {:?} {:?}", a, b, c)
+                        // String literals use {} (no quotes), everything else uses {:?} (Debug)
+                        "print" | "eprint" => {
+                            let macro_name = if name == "print" {
+                                "println"
+                            } else {
+                                "eprintln"
+                            };
+                            let fmt = args.iter().map(|_| "{}").collect::<Vec<_>>().join(" ");
+                            let mut macro_args = vec![e::Expr::String(fmt)];
+                            macro_args.extend(args.iter().cloned());
+                            return Ok(e::Stmt::Expr(e::Expr::MacroCall {
+                                path: vec![macro_name.into()],
+                                args: macro_args,
+                            }));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Ok(e::Stmt::Expr(expr))
+    }
+
+    fn expr_to_assign_target(&self, expr: e::Expr) -> Result<e::AssignTarget, ParseError> {
+        match expr {
+            e::Expr::Path(p) if p.len() == 1 => {
+                Ok(e::AssignTarget::Path(p.into_iter().next().unwrap()))
+            }
+            e::Expr::Field { base, field } => Ok(e::AssignTarget::Field { base, field }),
+            e::Expr::Index { base, index } => Ok(e::AssignTarget::Index { base, index }),
+            _ => Err(self.error("invalid assignment target".into())),
+        }
+    }
+
+    /// `rust("code", returns="T")` — a Rust expression with a declared type.
+    ///
+    /// Lowered to an immediately-called closure `(|| -> T { code })()`, so the
+    /// type checker sees a `T` where the escape is used, while the code itself
+    /// stays opaque and can still refer to surrounding locals.
+    fn typed_rust_expr(&mut self, call_args: Vec<CallArg>) -> Result<e::Expr, ParseError> {
+        let mut code = None;
+        let mut returns = None;
+        for arg in call_args {
+            match arg {
+                CallArg::Positional(value) if code.is_none() => code = Some(value),
+                CallArg::Keyword(key, value) if key == "returns" => returns = Some(value),
+                CallArg::Positional(_) | CallArg::Keyword(..) => {
+                    return Err(
+                        self.error("rust() takes one code string and returns=\"<type>\"".into())
+                    );
+                }
+            }
+        }
+        let Some(code) = code.as_ref().and_then(string_literal) else {
+            return Err(self.error("rust() code must be a string literal".into()));
+        };
+        let Some(returns) = returns.as_ref().and_then(string_literal) else {
+            return Err(self.error("rust() returns= must be a string naming a type".into()));
+        };
+        let return_type = Parser::new(&returns)
+            .and_then(|mut p| {
+                let ty = p.parse_type()?;
+                p.skip_newlines()?;
+                if p.check(&TokenKind::Eof) {
+                    Ok(ty)
+                } else {
+                    Err(p.error(String::new()))
+                }
+            })
+            .map_err(|_| self.error(format!("rust() returns=\"{returns}\" is not a type")))?;
+
+        Ok(e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![],
+                return_type: Some(return_type),
+                body: e::Block {
+                    statements: vec![e::Stmt::RustBlock(format!("({})", code.trim()))],
+                },
+            }),
+            args: vec![],
+        })
+    }
+
+    /// Check if an expression is a tuple containing at least one *splat,
+    /// and convert it to a DestructurePattern::Slice.
+    fn try_expr_to_destructure(&self, expr: &e::Expr) -> Option<e::DestructurePattern> {
+        // Single starred expression: *rest = items
+        if Self::is_starred(expr) {
+            let name = Self::starred_name(expr).unwrap();
+            return Some(e::DestructurePattern::Slice {
+                prefix: vec![],
+                rest: Some(name),
+                suffix: vec![],
+            });
+        }
+
+        // Tuple containing starred: a, *rest, b = items
+        let e::Expr::Tuple(elems) = expr else {
+            return None;
+        };
+
+        // If no starred element, try plain tuple destructure: a, b = expr
+        if !elems.iter().any(|e| Self::is_starred(e)) {
+            let mut items = Vec::new();
+            for elem in elems {
+                items.push(self.expr_to_destructure_name(elem)?);
+            }
+            return Some(e::DestructurePattern::Tuple(items));
+        }
+
+        let mut prefix = Vec::new();
+        let mut rest: Option<String> = None;
+        let mut suffix = Vec::new();
+        let mut after_star = false;
+
+        for elem in elems {
+            if Self::is_starred(elem) {
+                if rest.is_some() {
+                    return None; // multiple stars not allowed
+                }
+                rest = Some(Self::starred_name(elem).unwrap());
+                after_star = true;
+            } else {
+                let pat = self.expr_to_destructure_name(elem)?;
+                if after_star {
+                    suffix.push(pat);
+                } else {
+                    prefix.push(pat);
+                }
+            }
+        }
+
+        Some(e::DestructurePattern::Slice {
+            prefix,
+            rest,
+            suffix,
+        })
+    }
+
+    fn is_starred(expr: &e::Expr) -> bool {
+        matches!(expr, e::Expr::MacroCall { path, .. } if path.len() == 1 && path[0] == "__star__")
+    }
+
+    fn starred_name(expr: &e::Expr) -> Option<String> {
+        if let e::Expr::MacroCall { path, args } = expr {
+            if path.len() == 1 && path[0] == "__star__" {
+                if let Some(e::Expr::Path(p)) = args.first() {
+                    return p.first().cloned();
+                }
+            }
+        }
+        None
+    }
+
+    fn expr_to_destructure_name(&self, expr: &e::Expr) -> Option<e::DestructurePattern> {
+        match expr {
+            e::Expr::Path(p) if p.len() == 1 => {
+                if p[0] == "_" {
+                    Some(e::DestructurePattern::Ignore)
+                } else {
+                    Some(e::DestructurePattern::Name(p[0].clone()))
+                }
+            }
+            e::Expr::Tuple(elems) => {
+                let mut items = Vec::new();
+                for elem in elems {
+                    items.push(self.expr_to_destructure_name(elem)?);
+                }
+                Some(e::DestructurePattern::Tuple(items))
+            }
+            _ => None,
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────────────────────
+    // Expressions
+    // ─────────────────────────────────────────────────────────────────────────
+
+    fn parse_expr(&mut self) -> Result<e::Expr, ParseError> {
+        let body = self.parse_pipe_expr()?;
+        // Python-style ternary: body if condition else orelse
+        // Desugars to: match condition { true => body, _ => orelse }
+        if self.check_kw(Keyword::If) {
+            self.advance()?;
+            let condition = self.parse_or_expr()?;
+            self.expect_kw(Keyword::Else)?;
+            let orelse = self.parse_expr()?; // right-associative: a if c1 else b if c2 else d
+            return Ok(e::Expr::Match {
+                scrutinee: Box::new(condition),
+                arms: vec![
+                    e::MatchArm {
+                        pattern: e::Pattern::Bool(true),
+                        guard: None,
+                        value: body,
+                    },
+                    e::MatchArm {
+                        pattern: e::Pattern::Wildcard,
+                        guard: None,
+                        value: orelse,
+                    },
+                ],
+            });
+        }
+        Ok(body)
+    }
+
+    /// Pipe operator: `lhs |> f(args)` desugars to `f(lhs, args)`.
+    /// Left-associative, lowest precedence among binary ops.
+    ///
+    /// Note: `|>` has lower precedence than `==`, so `x |> f() == 3` parses
+    /// as `x |> (f() == 3)` — which is almost never what you want.
+    /// We detect this and emit a helpful error.
+    fn parse_pipe_expr(&mut self) -> Result<e::Expr, ParseError> {
+        let mut left = self.parse_or_expr()?;
+        while self.check(&TokenKind::PipeRight) {
+            self.advance()?;
+            let rhs = self.parse_or_expr()?;
+            // Detect `x |> f() == 3` — the RHS parsed a comparison, which
+            // means the user forgot parens around the pipe chain.
+            if Self::is_comparison(&rhs) {
+                return Err(self.error(format!(
+                    "|> has lower precedence than comparison operators (==, !=, <, >, etc.).\n\
+                     Wrap the pipe chain in parentheses: (... |> f()) == value"
+                )));
+            }
+            left = Self::desugar_pipe(left, rhs)?;
+        }
+        Ok(left)
+    }
+
+    /// Check if an expression is a comparison binary op.
+    fn is_comparison(expr: &e::Expr) -> bool {
+        matches!(
+            expr,
+            e::Expr::Binary {
+                op: e::BinaryOp::Eq
+                    | e::BinaryOp::Ne
+                    | e::BinaryOp::Lt
+                    | e::BinaryOp::Le
+                    | e::BinaryOp::Gt
+                    | e::BinaryOp::Ge,
+                ..
+            }
+        )
+    }
+
+    /// Transform `lhs |> rhs` into a function call:
+    ///   - `Call { callee, args }` → `Call { callee, args: [lhs] + args }`
+    ///   - `Path(f)` or `Field { .. }` → `Call { callee: rhs, args: [lhs] }`
+    fn desugar_pipe(lhs: e::Expr, rhs: e::Expr) -> Result<e::Expr, ParseError> {
+        match rhs {
+            e::Expr::Call { callee, mut args } => {
+                if matches!(callee.as_ref(), e::Expr::Path(path) if path.len() == 1 && path[0] == "len")
+                    && args.is_empty()
+                {
+                    return Ok(Self::coerce_len_call_result(e::Expr::Call {
+                        callee: Box::new(e::Expr::Field {
+                            base: Box::new(lhs),
+                            field: "len".into(),
+                        }),
+                        args: vec![],
+                    }));
+                }
+                args.insert(0, lhs);
+                Ok(Self::coerce_len_call_result(e::Expr::Call { callee, args }))
+            }
+            e::Expr::Path(path) if path.len() == 1 && path[0] == "len" => {
+                Ok(Self::coerce_len_call_result(e::Expr::Call {
+                    callee: Box::new(e::Expr::Field {
+                        base: Box::new(lhs),
+                        field: "len".into(),
+                    }),
+                    args: vec![],
+                }))
+            }
+            e::Expr::Path(_) | e::Expr::Field { .. } => Ok(e::Expr::Call {
+                callee: Box::new(rhs),
+                args: vec![lhs],
+            }),
+            // Allow piping into macro calls too
+            e::Expr::MacroCall { path, mut args } => {
+                args.insert(0, lhs);
+                Ok(e::Expr::MacroCall { path, args })
+            }
+            _ => {
+                // For any other expression, treat as a call with lhs as arg
+                Ok(e::Expr::Call {
+                    callee: Box::new(rhs),
+                    args: vec![lhs],
+                })
+            }
+        }
+    }
+
+    fn parse_or_expr(&mut self) -> Result<e::Expr, ParseError> {
+        let mut left = self.parse_and_expr()?;
+        while self.check_kw(Keyword::Or) {
+            self.advance()?;
+            let right = self.parse_and_expr()?;
+            left = e::Expr::Binary {
+                op: e::BinaryOp::Or,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_and_expr(&mut self) -> Result<e::Expr, ParseError> {
+        let mut left = self.parse_not_expr()?;
+        while self.check_kw(Keyword::And) {
+            self.advance()?;
+            let right = self.parse_not_expr()?;
+            left = e::Expr::Binary {
+                op: e::BinaryOp::And,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_not_expr(&mut self) -> Result<e::Expr, ParseError> {
+        if self.check_kw(Keyword::Not) {
+            self.advance()?;
+            let expr = self.parse_not_expr()?;
+            return Ok(e::Expr::Unary {
+                op: e::UnaryOp::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<e::Expr, ParseError> {
+        let mut left = self.parse_addition()?;
+        loop {
+            let op = match self.kind() {
+                TokenKind::EqEq => e::BinaryOp::Eq,
+                TokenKind::NotEq => e::BinaryOp::Ne,
+                TokenKind::Lt => e::BinaryOp::Lt,
+                TokenKind::LtEq => e::BinaryOp::Le,
+                TokenKind::Gt => e::BinaryOp::Gt,
+                TokenKind::GtEq => e::BinaryOp::Ge,
+                _ => break,
+            };
+            self.advance()?;
+            let right = self.parse_addition()?;
+            let (left_coerced, right_coerced) = Self::rewrite_len_comparison_operands(left, right);
+            left = match op {
+                e::BinaryOp::Eq | e::BinaryOp::Ne
+                    if Self::is_collection_literal(&left_coerced)
+                        || Self::is_collection_literal(&right_coerced) =>
+                {
+                    Self::collection_eq(op, left_coerced, right_coerced)
+                }
+                _ => e::Expr::Binary {
+                    op,
+                    left: Box::new(left_coerced),
+                    right: Box::new(right_coerced),
+                },
+            };
+        }
+        Ok(left)
+    }
+
+    /// A list or dict literal (`[1, 2]`, `{"a": 1}`, `{}`).
+    fn is_collection_literal(expr: &e::Expr) -> bool {
+        match expr {
+            e::Expr::Array(_) => true,
+            e::Expr::Call { callee, .. } => matches!(
+                callee.as_ref(),
+                e::Expr::Path(path) if path.len() == 2 && path[0] == "HashMap" && (path[1] == "from" || path[1] == "new")
+            ),
+            _ => false,
+        }
+    }
+
+    /// `xs == [1, 2, 3]` → `quiche_eq!(xs, [1, 2, 3])` (see the prelude),
+    /// which compares through `PartialEq` with the value side as the method
+    /// receiver, so a borrowed `xs` is dereferenced and a `Vec` compares
+    /// equal to the `List` the literal builds. `!=` negates it.
+    fn collection_eq(op: e::BinaryOp, left: e::Expr, right: e::Expr) -> e::Expr {
+        let (value, literal) = if Self::is_collection_literal(&left) {
+            (right, left)
+        } else {
+            (left, right)
+        };
+        let eq = e::Expr::MacroCall {
+            path: vec!["quiche_eq".into()],
+            args: vec![value, literal],
+        };
+        if matches!(op, e::BinaryOp::Ne) {
+            e::Expr::Unary {
+                op: e::UnaryOp::Not,
+                expr: Box::new(eq),
+            }
+        } else {
+            eq
+        }
+    }
+
+    fn rewrite_len_comparison_operands(left: e::Expr, right: e::Expr) -> (e::Expr, e::Expr) {
+        let left_is_len = Self::is_len_call_expr(&left);
+        let right_is_len = Self::is_len_call_expr(&right);
+
+        match (left_is_len, right_is_len) {
+            (true, false) => (Self::cast_expr_to_i64(left), right),
+            (false, true) => (left, Self::cast_expr_to_i64(right)),
+            _ => (left, right),
+        }
+    }
+
+    fn is_len_call_expr(expr: &e::Expr) -> bool {
+        matches!(
+            expr,
+            e::Expr::Call {
+                callee,
+                args,
+            } if (
+                args.is_empty() && matches!(
+                    callee.as_ref(),
+                    e::Expr::Field { field, .. } if field == "len"
+                )
+            ) || (
+                args.len() == 1 && matches!(
+                    callee.as_ref(),
+                    e::Expr::Path(path) if path.last().is_some_and(|segment| segment == "len")
+                )
+            )
+        )
+    }
+
+    fn coerce_len_call_result(expr: e::Expr) -> e::Expr {
+        if Self::is_len_call_expr(&expr) {
+            return Self::cast_expr_to_i64(expr);
+        }
+        expr
+    }
+
+    fn cast_expr_to_i64(expr: e::Expr) -> e::Expr {
+        e::Expr::Cast {
+            expr: Box::new(expr),
+            target_type: e::Type {
+                path: vec!["i64".into()],
+                args: vec![],
+                trait_bounds: vec![],
+            },
+        }
+    }
+
+    fn parse_addition(&mut self) -> Result<e::Expr, ParseError> {
+        let mut left = self.parse_multiplication()?;
+        loop {
+            let op = match self.kind() {
+                TokenKind::Plus => e::BinaryOp::Add,
+                TokenKind::Minus => e::BinaryOp::Sub,
+                _ => break,
+            };
+            self.advance()?;
+            let right = self.parse_multiplication()?;
+            left = e::Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        // Rewrite string + chains to str(format!("{}{}", a, b))
+        Ok(Self::rewrite_string_concat(left))
+    }
+
+    /// If `expr` is a chain of `Add` ops containing any string-like operand,
+    /// flatten it into `str(format!("{}{}{}", a, b, c))`.
+    fn rewrite_string_concat(expr: e::Expr) -> e::Expr {
+        let mut operands = Vec::new();
+        if !Self::flatten_add_chain(&expr, &mut operands) {
+            return expr; // contains Sub — not a pure + chain
+        }
+        // Only rewrite actual + chains (2+ operands), not single expressions
+        if operands.len() < 2 {
+            return expr;
+        }
+        // Check if any operand looks string-like
+        if !operands.iter().any(Self::is_string_expr) {
+            return expr; // pure numeric, leave for Elevate
+        }
+        // Build format!("{}{}{}", a, b, c) wrapped in str()
+        let fmt = operands.iter().map(|_| "{}").collect::<Vec<_>>().join("");
+        let mut macro_args = vec![e::Expr::String(fmt)];
+        macro_args.extend(operands);
+        let format_call = e::Expr::MacroCall {
+            path: vec!["format".into()],
+            args: macro_args,
+        };
+        // Wrap in str() constructor
+        e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["str".into()])),
+            args: vec![format_call],
+        }
+    }
+
+    /// Flatten a left-associative Add chain into a vec of operands.
+    /// Returns false if any Sub is encountered (not a pure + chain).
+    fn flatten_add_chain(expr: &e::Expr, out: &mut Vec<e::Expr>) -> bool {
+        if let e::Expr::Binary { op, left, right } = expr {
+            match op {
+                e::BinaryOp::Add => {
+                    if !Self::flatten_add_chain(left, out) {
+                        return false;
+                    }
+                    out.push(*right.clone());
+                    true
+                }
+                e::BinaryOp::Sub => false,
+                _ => {
+                    out.push(expr.clone());
+                    true
+                }
+            }
+        } else {
+            out.push(expr.clone());
+            true
+        }
+    }
+
+    /// Check if an expression is string-like (str() call, string literal, or format! macro).
+    fn is_string_expr(expr: &e::Expr) -> bool {
+        match expr {
+            // Raw string literals
+            e::Expr::String(_) => true,
+            // str("literal") or str(expr)
+            e::Expr::Call { callee, .. } => {
+                matches!(**callee, e::Expr::Path(ref p) if p.len() == 1 && p[0] == "str")
+            }
+            // format!(...) — f-strings
+            e::Expr::MacroCall { path, .. } => path.len() == 1 && path[0] == "format",
+            _ => false,
+        }
+    }
+
+    fn parse_multiplication(&mut self) -> Result<e::Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.kind() {
+                TokenKind::Star => e::BinaryOp::Mul,
+                TokenKind::Slash => e::BinaryOp::Div,
+                TokenKind::Percent => e::BinaryOp::Rem,
+                _ => break,
+            };
+            self.advance()?;
+            let right = self.parse_unary()?;
+            left = e::Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<e::Expr, ParseError> {
+        if self.check(&TokenKind::Minus) {
+            self.advance()?;
+            let expr = self.parse_unary()?;
+            return Ok(e::Expr::Unary {
+                op: e::UnaryOp::Neg,
+                expr: Box::new(expr),
+            });
+        }
+        // *name → starred expression (for destructuring: a, *rest = items)
+        if self.check(&TokenKind::Star) {
+            self.advance()?;
+            let name = self.expect_ident()?;
+            return Ok(e::Expr::MacroCall {
+                path: vec!["__star__".into()],
+                args: vec![e::Expr::Path(vec![name])],
+            });
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<e::Expr, ParseError> {
+        let literal_start =
+            matches!(self.kind(), TokenKind::String(_)).then(|| self.string_contents_start());
+        let mut expr = self.parse_primary()?;
+        loop {
+            // `expr?` → `quiche_try!(expr)` (see the prelude): Rust's `?`,
+            // returning an `Err`/`None` from the enclosing function
+            if self.eat(&TokenKind::Question)? {
+                expr = e::Expr::MacroCall {
+                    path: vec!["quiche_try".into()],
+                    args: vec![expr],
+                };
+                continue;
+            }
+            if self.eat(&TokenKind::Dot)? {
+                let field = self.expect_ident()?;
+                // "...".format(args) → format!() of the checked template
+                if field == "format"
+                    && self.check(&TokenKind::LParen)
+                    && let Some(start) = literal_start
+                    && let Some(template) = string_literal(&expr)
+                {
+                    expr = self.parse_format_call(&template, start)?;
+                    continue;
+                }
+                // Static method heuristic: if base is a capitalized Path (type name)
+                // and this is followed by '(' (a call), merge into path for `Type::method`.
+                // e.g. Student.new(...) → Path(["Student", "new"]) → Student::new(...)
+                if let e::Expr::Path(ref segments) = expr {
+                    if let Some(first) = segments.first() {
+                        if first.starts_with(|c: char| c.is_uppercase())
+                            && self.check(&TokenKind::LParen)
+                        {
+                            let mut path = segments.clone();
+                            path.push(field);
+                            expr = e::Expr::Path(path);
+                            continue;
+                        }
+                    }
+                }
+                // Circle.PI → the class attribute's value
+                if let e::Expr::Path(ref segments) = expr
+                    && let [class] = segments.as_slice()
+                    && let Some(read) = self.class_attr_read(class, &field)
+                {
+                    expr = read;
+                    continue;
+                }
+                // f.__doc__ → the docstring of function f, as a Str
+                if field == "__doc__"
+                    && let e::Expr::Path(ref segments) = expr
+                    && let [name] = segments.as_slice()
+                {
+                    self.doc_requests.insert(name.clone());
+                    expr = e::Expr::Call {
+                        callee: Box::new(e::Expr::Closure {
+                            params: vec![],
+                            return_type: Some(e::Type {
+                                path: vec!["Str".into()],
+                                args: vec![],
+                                trait_bounds: vec![],
+                            }),
+                            body: e::Block {
+                                statements: vec![e::Stmt::RustBlock(format!(
+                                    "Str::from(__QUICHE_DOC_{name})"
+                                ))],
+                            },
+                        }),
+                        args: vec![],
+                    };
+                    continue;
+                }
+                // Quiche value semantics: .iter() → .into_iter()
+                // so iterator chains yield T instead of &T, avoiding
+                // double-reference issues in filter/any/all closures.
+                let field = if field == "iter" {
+                    "into_iter".into()
+                } else {
+                    field
+                };
+                expr = e::Expr::Field {
+                    base: Box::new(expr),
+                    field,
+                };
+            } else if self.check(&TokenKind::LParen) {
+                self.advance()?;
+                if self.check(&TokenKind::DoubleStar)
+                    && let e::Expr::Path(ref path) = expr
+                    && let [name] = path.as_slice()
+                    && self.struct_fields.contains_key(name)
+                {
+                    expr = self.parse_struct_update(name.clone())?;
+                    continue;
+                }
+                let call_args = self.parse_call_args_with_kwargs()?;
+                self.expect(&TokenKind::RParen)?;
+
+                if matches!(&expr, e::Expr::Path(p) if p.len() == 1 && p[0] == "rust")
+                    && call_args
+                        .iter()
+                        .any(|a| matches!(a, CallArg::Keyword(k, _) if k == "returns"))
+                {
+                    expr = self.typed_rust_expr(call_args)?;
+                    continue;
+                }
+
+                // Check if this is a struct constructor call
+                // Classes, and named-field enum variants (`Shape.Rect(...)`)
+                let is_struct_call = if let e::Expr::Path(ref path) = expr {
+                    matches!(path.len(), 1 | 2) && self.struct_fields.contains_key(&path.join("::"))
+                } else {
+                    false
+                };
+
+                if is_struct_call {
+                    // Convert to StructLiteral
+                    let path = if let e::Expr::Path(ref p) = expr {
+                        p.clone()
+                    } else {
+                        unreachable!()
+                    };
+                    let field_names = self.struct_fields.get(&path.join("::")).unwrap().clone();
+
+                    // Check if any args are keyword args
+                    let has_kwargs = call_args
+                        .iter()
+                        .any(|a| matches!(a, CallArg::Keyword(_, _)));
+
+                    let fields: Vec<e::StructLiteralField> = if has_kwargs {
+                        // Keyword construction: Point(x=5, y=5)
+                        call_args
+                            .into_iter()
+                            .map(|arg| {
+                                match arg {
+                                    CallArg::Keyword(name, value) => {
+                                        e::StructLiteralField { name, value }
+                                    }
+                                    CallArg::Positional(_) => {
+                                        // Mixed positional+keyword not supported yet — treat as error
+                                        // For now, skip positional in kwargs mode
+                                        e::StructLiteralField {
+                                            name: String::new(),
+                                            value: e::Expr::Int(0),
+                                        }
+                                    }
+                                }
+                            })
+                            .filter(|f| !f.name.is_empty())
+                            .collect()
+                    } else {
+                        // Positional construction: Point(5, 5) → zip with field names
+                        field_names
+                            .iter()
+                            .zip(call_args.into_iter())
+                            .map(|(name, arg): (&String, CallArg)| {
+                                let value = match arg {
+                                    CallArg::Positional(expr) => expr,
+                                    CallArg::Keyword(_, expr) => expr,
+                                };
+                                e::StructLiteralField {
+                                    name: name.clone(),
+                                    value,
+                                }
+                            })
+                            .collect()
+                    };
+
+                    let type_name = path.join("::");
+                    let fields = fields
+                        .into_iter()
+                        .map(|mut field| {
+                            let key = (type_name.clone(), field.name.clone());
+                            if let Some(ty) = self.boxed_fields.get(&key) {
+                                field.value = self.box_value(field.value, ty);
+                            }
+                            field
+                        })
+                        .collect();
+                    expr = e::Expr::StructLiteral { path, fields };
+                    continue;
+                }
+
+                // Bind kwargs, keyword-only parameters and defaults for calls
+                // to known functions using fn_params
+                let has_kwargs = call_args
+                    .iter()
+                    .any(|a| matches!(a, CallArg::Keyword(_, _)));
+                let fn_key = match &expr {
+                    e::Expr::Path(path) if path.len() == 1 => Some(path[0].clone()),
+                    e::Expr::Path(path) if path.len() == 2 => {
+                        // Point.new(...) → Path(["Point", "new"]) → key "Point::new"
+                        Some(format!("{}::{}", path[0], path[1]))
+                    }
+                    e::Expr::Field { base, field, .. } => match &**base {
+                        e::Expr::Path(base_path) if base_path.len() == 1 => {
+                            Some(format!("{}::{}", base_path[0], field))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let signature = fn_key
+                    .as_ref()
+                    .and_then(|key| Some((key, self.fn_params.get(key)?.clone())))
+                    .filter(|(key, names)| {
+                        has_kwargs
+                            || names.iter().any(|n| n.starts_with('*'))
+                            || self
+                                .fn_defaults
+                                .keys()
+                                .any(|(function, _)| function == *key)
+                    });
+                let args: Vec<e::Expr> = if let Some((key, names)) = signature {
+                    self.bind_call_args(key, &names, call_args)?
+                } else {
+                    // Function not known — pass in order
+                    call_args
+                        .into_iter()
+                        .map(|a| match a {
+                            CallArg::Positional(e) => e,
+                            CallArg::Keyword(_, e) => e,
+                        })
+                        .collect()
+                };
+
+                // Check calls to @extern declarations against their signature
+                if let e::Expr::Path(path) = &expr
+                    && path.len() == 1
+                    && let Some(&arity) = self.externs.get(&path[0])
+                    && args.len() != arity
+                {
+                    return Err(self.error(format!(
+                        "extern '{}' takes {} argument(s) but {} were given",
+                        path[0],
+                        arity,
+                        args.len()
+                    )));
+                }
+
+                // int(x) → x as i64; Status(404) → Status::from_int(404)
+                if let e::Expr::Path(ref path) = expr
+                    && path.len() == 1
+                    && args.len() == 1
+                {
+                    if path[0] == "int" {
+                        expr = e::Expr::Cast {
+                            expr: Box::new(args.into_iter().next().unwrap()),
+                            target_type: e::Type {
+                                path: vec!["i64".into()],
+                                args: vec![],
+                                trait_bounds: vec![],
+                            },
+                        };
+                        continue;
+                    }
+                    if self.discriminant_enums.contains(&path[0]) {
+                        expr = e::Expr::Path(vec![path[0].clone(), "from_int".into()]);
+                    }
+                }
+
+                // env("NAME") → env!("NAME"), include_str("f") → include_str!("f"),
+                // both read when the program is compiled
+                if let e::Expr::Path(ref path) = expr
+                    && path.len() == 1
+                    && matches!(path[0].as_str(), "env" | "include_str")
+                {
+                    expr = self.compile_time_builtin(&path[0], args)?;
+                    continue;
+                }
+
+                // Convert len(x) → x.len()
+                if let e::Expr::Path(ref path) = expr {
+                    if path.len() == 1 && path[0] == "len" && args.len() == 1 {
+                        let receiver = args.into_iter().next().unwrap();
+                        expr = e::Expr::Call {
+                            callee: Box::new(e::Expr::Field {
+                                base: Box::new(receiver),
+                                field: "len".into(),
+                            }),
+                            args: vec![],
+                        };
+                        continue;
+                    }
+                }
+
+                // qtest helpers: assert_snapshot(...) / patch_env(...) → Qtest::name(...)
+                if let e::Expr::Path(ref path) = expr
+                    && path.len() == 1
+                    && matches!(path[0].as_str(), "assert_snapshot" | "patch_env")
+                {
+                    expr = e::Expr::Path(vec!["Qtest".into(), path[0].clone()]);
+                }
+
+                // Convert range(end) → 0..end, range(start, end) → start..end
+                if let e::Expr::Path(ref path) = expr {
+                    if path.len() == 1 && path[0] == "range" {
+                        match args.len() {
+                            1 => {
+                                expr = e::Expr::Range {
+                                    start: Some(Box::new(e::Expr::Int(0))),
+                                    end: Some(Box::new(args.into_iter().next().unwrap())),
+                                    inclusive: false,
+                                };
+                                continue;
+                            }
+                            2 => {
+                                let mut it = args.into_iter();
+                                let start = it.next().unwrap();
+                                let end = it.next().unwrap();
+                                expr = e::Expr::Range {
+                                    start: Some(Box::new(start)),
+                                    end: Some(Box::new(end)),
+                                    inclusive: false,
+                                };
+                                continue;
+                            }
+                            _ => {} // fall through to normal Call
+                        }
+                    }
+                }
+                expr = e::Expr::Call {
+                    callee: Box::new(expr),
+                    args,
+                };
+                expr = Self::coerce_len_call_result(expr);
+            } else if self.check(&TokenKind::LBracket) {
+                self.advance()?;
+                let index = self.parse_expr()?;
+                // Check for range: [start..end]
+                if self.eat(&TokenKind::DotDot)? {
+                    let end = self.parse_expr()?;
+                    self.expect(&TokenKind::RBracket)?;
+                    expr = e::Expr::Index {
+                        base: Box::new(expr),
+                        index: Box::new(e::Expr::Range {
+                            start: Some(Box::new(index)),
+                            end: Some(Box::new(end)),
+                            inclusive: false,
+                        }),
+                    };
+                } else {
+                    self.expect(&TokenKind::RBracket)?;
+                    expr = e::Expr::Index {
+                        base: Box::new(expr),
+                        index: Box::new(index),
+                    };
+                }
+            } else if self.check_kw(Keyword::As) {
+                self.advance()?; // consume 'as'
+                let target_type = self.parse_type()?;
+                expr = e::Expr::Cast {
+                    expr: Box::new(expr),
+                    target_type,
+                };
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<e::Expr, ParseError> {
+        if matches!(self.kind(), TokenKind::Ident(name) if name == "fn")
+            && matches!(self.peek()?.kind, TokenKind::LParen)
+        {
+            return self.parse_fn_expr();
+        }
+        match self.kind().clone() {
+            TokenKind::Int(n) => {
+                self.advance()?;
+                Ok(e::Expr::Int(n))
+            }
+            TokenKind::Float(_f) => {
+                // Elevate doesn't have Float in AST — emit as Int for now
+                self.advance()?;
+                Ok(e::Expr::Int(0)) // TODO: float support
+            }
+            TokenKind::String(s) => {
+                self.advance()?;
+                // Wrap in str() constructor for Smart String (inline or Arc<str>)
+                Ok(e::Expr::Call {
+                    callee: Box::new(e::Expr::Path(vec!["str".into()])),
+                    args: vec![e::Expr::String(s)],
+                })
+            }
+            TokenKind::Keyword(Keyword::True) => {
+                self.advance()?;
+                Ok(e::Expr::Bool(true))
+            }
+            TokenKind::Keyword(Keyword::False) => {
+                self.advance()?;
+                Ok(e::Expr::Bool(false))
+            }
+            TokenKind::Keyword(Keyword::None) => {
+                self.advance()?;
+                Ok(e::Expr::Path(vec!["None".into()]))
+            }
+            TokenKind::Ident(name) => {
+                self.advance()?;
+                self.note_collection(&name);
+                Ok(e::Expr::Path(vec![name]))
+            }
+            TokenKind::LParen => {
+                self.advance()?;
+                if self.check(&TokenKind::RParen) {
+                    self.advance()?;
+                    return Ok(e::Expr::Tuple(vec![]));
+                }
+                let expr = self.parse_expr()?;
+                // Check for tuple: (a, b, ...)
+                if self.eat(&TokenKind::Comma)? {
+                    let mut elems = vec![expr];
+                    while !self.check(&TokenKind::RParen) {
+                        elems.push(self.parse_expr()?);
+                        if !self.eat(&TokenKind::Comma)? {
+                            break;
+                        }
+                    }
+                    self.expect(&TokenKind::RParen)?;
+                    return Ok(e::Expr::Tuple(elems));
+                }
+                self.expect(&TokenKind::RParen)?;
+                Ok(expr) // parenthesized expression
+            }
+            TokenKind::LBracket => {
+                let start = self.current.start;
+                self.advance()?;
+                if self.check(&TokenKind::RBracket) {
+                    self.advance()?;
+                    return Ok(e::Expr::Array(vec![]));
+                }
+                let first = self.parse_expr()?;
+                // List comprehension: [expr for var in iter] or [expr for var in iter if cond]
+                if self.check(&TokenKind::Keyword(Keyword::For)) {
+                    self.advance()?; // consume 'for'
+                    let var = self.expect_ident()?;
+                    self.expect(&TokenKind::Keyword(Keyword::In))?;
+                    let iter_expr = self.parse_expr()?;
+                    // Optional filter: if cond
+                    let filter = if self.eat(&TokenKind::Keyword(Keyword::If))? {
+                        Some(self.parse_expr()?)
+                    } else {
+                        None
+                    };
+                    self.expect(&TokenKind::RBracket)?;
+                    return Ok(Self::build_list_comprehension(
+                        var,
+                        iter_expr,
+                        first,
+                        filter,
+                        self.span_from(start),
+                    ));
+                }
+                // Regular array literal
+                let mut elems = vec![first];
+                if self.eat(&TokenKind::Comma)? {
+                    while !self.check(&TokenKind::RBracket) {
+                        elems.push(self.parse_expr()?);
+                        if !self.eat(&TokenKind::Comma)? {
+                            break;
+                        }
+                    }
+                }
+                self.expect(&TokenKind::RBracket)?;
+                Ok(e::Expr::Array(elems))
+            }
+            TokenKind::FString { content, .. } => {
+                let start = self.string_contents_start();
+                self.advance()?;
+                // f-string → emit as format!() with ALL expressions as positional args.
+                // Rust's format!() only accepts identifiers inside {}, not expressions,
+                // so we always extract to positional args:
+                //   f"{name} is {age * 2}" → format!("{} is {}", name, age * 2)
+                //   f"literal {{braces}}"  → format!("literal {{braces}}")
+                //   f"{price:>8.2f}"       → format!("{:>8.2}", price)
+                let mut format_str = String::new();
+                let mut args: Vec<e::Expr> = Vec::new();
+                let mut chars = content.chars().peekable();
+                let mut offset = 0;
+                while let Some(c) = chars.next() {
+                    let at = offset;
+                    offset += c.len_utf8();
+                    let located = |message: String| {
+                        let (line, column) = format::locate(&content, at, start);
+                        ParseError {
+                            message,
+                            line,
+                            column,
+                        }
+                    };
+                    if c == '{' {
+                        // Check for escaped brace: {{ → literal {
+                        if chars.peek() == Some(&'{') {
+                            chars.next();
+                            offset += 1;
+                            format_str.push_str("{{");
+                            continue;
+                        }
+                        let placeholder = fstring_placeholder(&mut chars);
+                        offset += placeholder.len() + 1;
+                        let field = format::split_field(&placeholder).map_err(located)?;
+                        let spec = match field.spec {
+                            Some(spec) => format::rust_spec(spec).map_err(located)?,
+                            None => String::new(),
+                        };
+                        // `{expr=}` (Python 3.8): the expression's text as
+                        // written, then its `repr` (the prelude's
+                        // `quiche_repr!`) unless a conversion or spec is given.
+                        let (expr_str, conversion) = match fstring_debug_expr(field.name) {
+                            Some(debug_expr) => {
+                                format_str
+                                    .push_str(&field.name.replace('{', "{{").replace('}', "}}"));
+                                let conversion = match (field.conversion, field.spec) {
+                                    (None, None) => Some('r'),
+                                    (conversion, _) => conversion,
+                                };
+                                (debug_expr, conversion)
+                            }
+                            None => (field.name, field.conversion),
+                        };
+                        // ALL expressions become positional args (safe for Rust format!)
+                        if spec.is_empty() {
+                            format_str.push_str("{}");
+                        } else {
+                            format_str.push_str(&format!("{{:{spec}}}"));
+                        }
+                        let parsed_expr =
+                            self.fstring_expr(expr_str, format::locate(&content, at, start))?;
+                        // `!r` is the value's `repr`; otherwise Display when
+                        // the type has it, Debug otherwise (the prelude's
+                        // `quiche_show!`).
+                        let show = if conversion == Some('r') {
+                            "quiche_repr"
+                        } else {
+                            "quiche_show"
+                        };
+                        args.push(e::Expr::MacroCall {
+                            path: vec![show.into()],
+                            args: vec![parsed_expr],
+                        });
+                    } else if c == '}' {
+                        // Check for escaped close brace: }} → literal }
+                        if chars.peek() != Some(&'}') {
+                            return Err(located("single '}' in f-string (write '}}')".into()));
+                        }
+                        chars.next();
+                        offset += 1;
+                        format_str.push_str("}}");
+                    } else {
+                        format_str.push(c);
+                    }
+                }
+                let mut macro_args = vec![e::Expr::String(format_str)];
+                macro_args.extend(args);
+                let macro_call = e::Expr::MacroCall {
+                    path: vec!["format".into()],
+                    args: macro_args,
+                };
+                // Wrap in str() to produce Str instead of String
+                Ok(e::Expr::Call {
+                    callee: Box::new(e::Expr::Path(vec!["str".into()])),
+                    args: vec![macro_call],
+                })
+            }
+            TokenKind::LBrace => {
+                let start = self.current.start;
+                self.advance()?;
+                self.parse_dict_literal(start)
+            }
+            TokenKind::Pipe => {
+                self.advance()?; // consume opening |
+                // Parse params: |x, y: i32, z| or || (empty)
+                let mut params = Vec::new();
+                while !self.check(&TokenKind::Pipe) {
+                    let name = self.expect_ident()?;
+                    let ty = if self.eat(&TokenKind::Colon)? {
+                        self.parse_type()?
+                    } else {
+                        e::Type {
+                            path: vec!["_".into()],
+                            args: vec![],
+                            trait_bounds: vec![],
+                        }
+                    };
+                    params.push(e::Param { name, ty });
+                    if !self.eat(&TokenKind::Comma)? {
+                        break;
+                    }
+                }
+                self.expect(&TokenKind::Pipe)?; // consume closing |
+                let body_expr = self.parse_expr()?;
+                Ok(e::Expr::Closure {
+                    params,
+                    return_type: None,
+                    body: e::Block {
+                        statements: vec![e::Stmt::TailExpr(body_expr)],
+                    },
+                })
+            }
+            // Python-style lambda: `lambda x, y: expr`
+            TokenKind::Keyword(Keyword::Lambda) => {
+                self.advance()?; // consume 'lambda'
+                let mut params = Vec::new();
+                // Parse params until we hit ':'
+                while !self.check(&TokenKind::Colon) {
+                    let name = self.expect_ident()?;
+                    let ty = e::Type {
+                        path: vec!["_".into()],
+                        args: vec![],
+                        trait_bounds: vec![],
+                    };
+                    params.push(e::Param { name, ty });
+                    if !self.eat(&TokenKind::Comma)? {
+                        break;
+                    }
+                }
+                self.expect(&TokenKind::Colon)?; // consume ':'
+                let body_expr = self.parse_expr()?;
+                Ok(e::Expr::Closure {
+                    params,
+                    return_type: None,
+                    body: e::Block {
+                        statements: vec![e::Stmt::TailExpr(body_expr)],
+                    },
+                })
+            }
+            TokenKind::Keyword(Keyword::Await) => Err(self.unsupported(unsupported::ASYNC)),
+            _ => Err(self.error(format!("expected expression, got {}", self.kind()))),
+        }
+    }
+
+    /// The arguments of `"...".format(...)` (at the `(`), as a `format!` of
+    /// the template. Each field must name an argument and each argument must
+    /// be used; `start` locates the template's contents for errors.
+    fn parse_format_call(
+        &mut self,
+        template: &str,
+        start: (usize, usize),
+    ) -> Result<e::Expr, ParseError> {
+        let located = |offset: usize, message: String| {
+            let (line, column) = format::locate(template, offset, start);
+            ParseError {
+                message,
+                line,
+                column,
+            }
+        };
+        let pieces = format::template_pieces(template)
+            .map_err(|(offset, message)| located(offset, message))?;
+        self.expect(&TokenKind::LParen)?;
+        let call_args = self.parse_call_args_with_kwargs()?;
+        self.expect(&TokenKind::RParen)?;
+        let mut values = Vec::new();
+        let mut keywords = Vec::new();
+        for arg in call_args {
+            match arg {
+                CallArg::Positional(value) => values.push(value),
+                CallArg::Keyword(name, value) => keywords.push((name, value)),
+            }
+        }
+        let positional = values.len();
+        let mut used = vec![false; positional + keywords.len()];
+        // `{}` numbers fields automatically, `{0}` manually; not both
+        let mut automatic: Option<bool> = None;
+        let mut next = 0;
+        // One format!() argument per (argument, `!r`) pair used
+        let mut slots: Vec<(usize, bool)> = Vec::new();
+        let mut format_str = String::new();
+        for piece in pieces {
+            let (offset, name, conversion, spec) = match piece {
+                Piece::Text(text) => {
+                    format_str.push_str(&text);
+                    continue;
+                }
+                Piece::Field {
+                    offset,
+                    name,
+                    conversion,
+                    spec,
+                } => (offset, name, conversion, spec),
+            };
+            let index = match name {
+                FieldName::Keyword(name) => {
+                    let index = keywords
+                        .iter()
+                        .position(|(k, _)| *k == name)
+                        .ok_or_else(|| {
+                            located(
+                                offset,
+                                format!("no argument named '{name}' for field '{{{name}}}'"),
+                            )
+                        })?;
+                    positional + index
+                }
+                FieldName::Auto | FieldName::Index(_) => {
+                    let is_auto = name == FieldName::Auto;
+                    if automatic.is_some_and(|auto| auto != is_auto) {
+                        return Err(located(
+                            offset,
+                            "cannot mix automatic ('{}') and manual ('{0}') field numbering".into(),
+                        ));
+                    }
+                    automatic = Some(is_auto);
+                    let index = match name {
+                        FieldName::Index(index) => index,
+                        _ => {
+                            next += 1;
+                            next - 1
+                        }
+                    };
+                    if index >= positional {
+                        return Err(located(
+                            offset,
+                            format!(
+                                "format string needs positional argument {index}, but only {positional} given"
+                            ),
+                        ));
+                    }
+                    index
+                }
+            };
+            used[index] = true;
+            let slot = (index, conversion == Some('r'));
+            let slot = slots.iter().position(|s| *s == slot).unwrap_or_else(|| {
+                slots.push(slot);
+                slots.len() - 1
+            });
+            if spec.is_empty() {
+                format_str.push_str(&format!("{{{slot}}}"));
+            } else {
+                format_str.push_str(&format!("{{{slot}:{spec}}}"));
+            }
+        }
+        values.extend(keywords.iter().map(|(_, value)| value.clone()));
+        if let Some(unused) = used.iter().position(|used| !used) {
+            let argument = if unused < positional {
+                format!("positional argument {unused}")
+            } else {
+                format!("argument '{}'", keywords[unused - positional].0)
+            };
+            return Err(located(
+                0,
+                format!("format() {argument} is not used by the format string"),
+            ));
+        }
+        let mut macro_args = vec![e::Expr::String(format_str)];
+        macro_args.extend(slots.into_iter().map(|(index, repr)| e::Expr::MacroCall {
+            path: vec![if repr { "quiche_repr" } else { "quiche_show" }.into()],
+            args: vec![values[index].clone()],
+        }));
+        Ok(e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["str".into()])),
+            args: vec![e::Expr::MacroCall {
+                path: vec!["format".into()],
+                args: macro_args,
+            }],
+        })
+    }
+
+    /// Parse an f-string placeholder's expression. As in Python, it is read
+    /// as if parenthesized, so it may span lines (`{a +\n b}`) and `{a, b}`
+    /// is a tuple.
+    /// Errors are reported at `(line, column)`, the placeholder's position.
+    fn fstring_expr(
+        &self,
+        source: &str,
+        (line, column): (usize, usize),
+    ) -> Result<e::Expr, ParseError> {
+        let located = |err: ParseError| ParseError {
+            message: format!(
+                "in f-string expression `{}`: {}",
+                source.trim(),
+                err.message
+            ),
+            line,
+            column,
+        };
+        let parenthesized = format!("({source})");
+        let mut sub = Parser::new(&parenthesized).map_err(located)?;
+        let expr = sub.parse_expr().map_err(located)?;
+        sub.skip_newlines().map_err(located)?;
+        if !sub.check(&TokenKind::Eof) {
+            return Err(located(sub.error(format!("unexpected {}", sub.kind()))));
+        }
+        Ok(expr)
+    }
+
+    /// An anonymous function, `fn(x, y: i64) -> T: body`. The body is an
+    /// expression on the same line or an indented block — also inside a call's
+    /// parentheses, where the lexer re-enables indentation for it. A block's
+    /// final expression statement is its value.
+    fn parse_fn_expr(&mut self) -> Result<e::Expr, ParseError> {
+        self.advance()?; // consume 'fn'
+        self.expect(&TokenKind::LParen)?;
+        let params = self.parse_params()?;
+        self.expect(&TokenKind::RParen)?;
+        let return_type = if self.eat(&TokenKind::Arrow)? {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        self.expect(&TokenKind::Colon)?;
+        if !self.check(&TokenKind::Newline) {
+            let body_expr = self.parse_expr()?;
+            return Ok(e::Expr::Closure {
+                params,
+                return_type,
+                body: e::Block {
+                    statements: vec![e::Stmt::TailExpr(body_expr)],
+                },
+            });
+        }
+        let mut body = self.parse_block()?;
+        if let Some(e::Stmt::Expr(_)) = body.statements.last()
+            && let Some(e::Stmt::Expr(value)) = body.statements.pop()
+        {
+            body.statements.push(e::Stmt::TailExpr(value));
+        }
+        Ok(e::Expr::Closure {
+            params,
+            return_type,
+            body,
+        })
+    }
+
+    /// Parse a dict literal: `{key: val, ...}` or `{**spread, key: val}`
+    /// Called after `{` (at `start`) has been consumed.
+    fn parse_dict_literal(&mut self, start: usize) -> Result<e::Expr, ParseError> {
+        // Empty dict: {}
+        if self.check(&TokenKind::RBrace) {
+            self.advance()?;
+            return Ok(e::Expr::Call {
+                callee: Box::new(e::Expr::Path(vec!["HashMap".into(), "new".into()])),
+                args: vec![],
+            });
+        }
+
+        // Collect entries: either (key, val) pairs or **spread
+        enum DictEntry {
+            Pair(e::Expr, e::Expr),
+            Spread(e::Expr),
+        }
+        let mut entries = Vec::new();
+
+        loop {
+            if self.check(&TokenKind::RBrace) {
+                break;
+            }
+
+            // Check for **spread
+            if self.check(&TokenKind::DoubleStar) {
+                self.advance()?;
+                let spread_expr = self.parse_expr()?;
+                entries.push(DictEntry::Spread(spread_expr));
+            } else {
+                // key: value
+                let key = self.parse_expr()?;
+                self.expect(&TokenKind::Colon)?;
+                let value = self.parse_expr()?;
+
+                // Dict comprehension: {key: val for var in iter}
+                if self.check(&TokenKind::Keyword(Keyword::For)) {
+                    self.advance()?; // consume 'for'
+                    let var = self.expect_ident()?;
+                    self.expect(&TokenKind::Keyword(Keyword::In))?;
+                    let iter_expr = self.parse_expr()?;
+                    // Optional filter: if cond
+                    let filter = if self.eat(&TokenKind::Keyword(Keyword::If))? {
+                        Some(self.parse_expr()?)
+                    } else {
+                        None
+                    };
+                    self.expect(&TokenKind::RBrace)?;
+                    return Ok(Self::build_dict_comprehension(
+                        var,
+                        iter_expr,
+                        key,
+                        value,
+                        filter,
+                        self.span_from(start),
+                    ));
+                }
+
+                entries.push(DictEntry::Pair(key, value));
+            }
+
+            if !self.eat(&TokenKind::Comma)? {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RBrace)?;
+
+        // Check if there are any spreads
+        let has_spread = entries.iter().any(|e| matches!(e, DictEntry::Spread(_)));
+
+        if !has_spread {
+            // Simple dict: HashMap::from([(k1, v1), (k2, v2)])
+            let pairs: Vec<e::Expr> = entries
+                .into_iter()
+                .map(|e| match e {
+                    DictEntry::Pair(k, v) => e::Expr::Tuple(vec![k, v]),
+                    _ => unreachable!(),
+                })
+                .collect();
+            Ok(e::Expr::Call {
+                callee: Box::new(e::Expr::Path(vec!["HashMap".into(), "from".into()])),
+                args: vec![e::Expr::Array(pairs)],
+            })
+        } else {
+            // Dict with spread: emit as RustBlock
+            // { let mut __m = HashMap::new(); __m.extend(spread); __m.insert(k, v); __m }
+            let mut code = String::from("{ let mut __m = std::collections::HashMap::new(); ");
+            for entry in entries {
+                match entry {
+                    DictEntry::Spread(expr) => {
+                        // We need to emit the spread expr as Rust — for simple paths
+                        if let e::Expr::Path(ref p) = expr {
+                            code.push_str(&format!(
+                                "__m.extend({}.iter().map(|(k, v)| (k.clone(), v.clone()))); ",
+                                p.join("::")
+                            ));
+                        }
+                    }
+                    DictEntry::Pair(key, value) => {
+                        // For string keys and simple values
+                        let key_str = Self::expr_to_rust_string(&key);
+                        let val_str = Self::expr_to_rust_string(&value);
+                        code.push_str(&format!("__m.insert({}, {}); ", key_str, val_str));
+                    }
+                }
+            }
+            code.push_str("__m }");
+            Ok(e::Expr::MacroCall {
+                path: vec!["__rust_expr__".into()],
+                args: vec![e::Expr::String(code)],
+            })
+        }
+    }
+
+    /// `env("NAME")`, `env("NAME", "default")` or `include_str("path")`, as a
+    /// `Str` fixed at compile time. Relative `include_str` paths are resolved
+    /// against the source file once it is known (see `resolve_include_paths`).
+    fn compile_time_builtin(&self, name: &str, args: Vec<e::Expr>) -> Result<e::Expr, ParseError> {
+        let literals: Option<Vec<String>> = args.iter().map(string_literal).collect();
+        let value = match (name, literals.as_deref()) {
+            ("env", Some([var])) => e::Expr::MacroCall {
+                path: vec!["env".into()],
+                args: vec![e::Expr::String(var.clone())],
+            },
+            ("env", Some([var, default])) => e::Expr::Call {
+                callee: Box::new(e::Expr::Field {
+                    base: Box::new(e::Expr::MacroCall {
+                        path: vec!["option_env".into()],
+                        args: vec![e::Expr::String(var.clone())],
+                    }),
+                    field: "unwrap_or".into(),
+                }),
+                args: vec![e::Expr::String(default.clone())],
+            },
+            ("include_str", Some([file])) => e::Expr::MacroCall {
+                path: vec!["include_str".into()],
+                args: vec![e::Expr::String(file.clone())],
+            },
+            ("env", _) => {
+                return Err(self.error(
+                    "env() takes a variable name and an optional default, as string literals"
+                        .into(),
+                ));
+            }
+            _ => return Err(self.error("include_str() takes a path as a string literal".into())),
+        };
+        Ok(e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["str".into()])),
+            args: vec![value],
+        })
+    }
+
+    /// Best-effort conversion of an Expr to a Rust string for RustBlock emission.
+    fn expr_to_rust_string(expr: &e::Expr) -> String {
+        match expr {
+            e::Expr::String(s) => format!("String::from(\"{}\")", s),
+            e::Expr::Int(n) => n.to_string(),
+            e::Expr::Bool(b) => b.to_string(),
+            e::Expr::Path(p) => p.join("::"),
+            e::Expr::Binary { op, left, right } => {
+                let op_str = match op {
+                    e::BinaryOp::Add => "+",
+                    e::BinaryOp::Sub => "-",
+                    e::BinaryOp::Mul => "*",
+                    e::BinaryOp::Div => "/",
+                    e::BinaryOp::Rem => "%",
+                    e::BinaryOp::And => "&&",
+                    e::BinaryOp::Or => "||",
+                    e::BinaryOp::Eq => "==",
+                    e::BinaryOp::Ne => "!=",
+                    e::BinaryOp::Lt => "<",
+                    e::BinaryOp::Le => "<=",
+                    e::BinaryOp::Gt => ">",
+                    e::BinaryOp::Ge => ">=",
+                };
+                format!(
+                    "({} {} {})",
+                    Self::expr_to_rust_string(left),
+                    op_str,
+                    Self::expr_to_rust_string(right)
+                )
+            }
+            e::Expr::Unary { op, expr: inner } => {
+                let op_str = match op {
+                    e::UnaryOp::Not => "!",
+                    e::UnaryOp::Neg => "-",
+                };
+                format!("({}{})", op_str, Self::expr_to_rust_string(inner))
+            }
+            e::Expr::Field { base, field } => {
+                format!("{}.{}", Self::expr_to_rust_string(base), field)
+            }
+            e::Expr::Tuple(elems) => {
+                let parts: Vec<String> = elems.iter().map(Self::expr_to_rust_string).collect();
+                format!("({})", parts.join(", "))
+            }
+            e::Expr::Call { callee, args } => {
+                let args_str: Vec<String> = args.iter().map(Self::expr_to_rust_string).collect();
+                format!(
+                    "{}({})",
+                    Self::expr_to_rust_string(callee),
+                    args_str.join(", ")
+                )
+            }
+            e::Expr::Index { base, index } => {
+                format!(
+                    "{}[{}]",
+                    Self::expr_to_rust_string(base),
+                    Self::expr_to_rust_string(index)
+                )
+            }
+            _ => "todo!()".into(),
+        }
+    }
+
+    /// Build a list comprehension: [expr for var in iter if cond]
+    /// Desugars to IIFE: (|| { let __v = Vec::new(); for var in iter { [if cond {] __v.push(expr); [}] } __v })()
+    fn build_list_comprehension(
+        var: String,
+        iter_expr: e::Expr,
+        map_expr: e::Expr,
+        filter: Option<e::Expr>,
+        span: Option<Span>,
+    ) -> e::Expr {
+        // Build the push statement: __v.push(map_expr)
+        let push_stmt = e::Stmt::Expr(e::Expr::Call {
+            callee: Box::new(e::Expr::Field {
+                base: Box::new(e::Expr::Path(vec!["__v".into()])),
+                field: "push".into(),
+            }),
+            args: vec![map_expr],
+        });
+
+        // For body: either just push, or if cond { push }
+        let for_body = if let Some(cond) = filter {
+            e::Block {
+                statements: vec![e::Stmt::If {
+                    condition: cond,
+                    then_block: e::Block {
+                        statements: vec![push_stmt],
+                    },
+                    else_block: None,
+                }],
+            }
+        } else {
+            e::Block {
+                statements: vec![push_stmt],
+            }
+        };
+
+        // Build IIFE body: let __v = Vec::new(); for var in iter { ... }; __v
+        let iife_body = e::Block {
+            statements: vec![
+                // let __v: Vec<_> = Vec::new()
+                e::Stmt::Const(e::ConstDef {
+                    visibility: e::Visibility::Private,
+                    name: "__v".into(),
+                    ty: Some(e::Type {
+                        path: vec!["Vec".into()],
+                        args: vec![e::Type {
+                            path: vec!["_".into()],
+                            args: vec![],
+                            trait_bounds: vec![],
+                        }],
+                        trait_bounds: vec![],
+                    }),
+                    value: e::Expr::Call {
+                        callee: Box::new(e::Expr::Path(vec!["Vec".into(), "new".into()])),
+                        args: vec![],
+                    },
+                    is_const: false,
+                    span,
+                }),
+                // for var in iter { ... }
+                e::Stmt::For {
+                    binding: e::DestructurePattern::Name(var),
+                    iter: iter_expr,
+                    body: for_body,
+                },
+                // __v (tail expression — return the vec)
+                e::Stmt::TailExpr(e::Expr::Path(vec!["__v".into()])),
+            ],
+        };
+
+        // Wrap in IIFE: (|| { ... })()
+        e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![],
+                return_type: None,
+                body: iife_body,
+            }),
+            args: vec![],
+        }
+    }
+
+    /// Build a dict comprehension: {key: val for var in iter if cond}
+    /// Desugars to IIFE: build Vec<(K,V)> with push, then .into_iter().collect()
+    fn build_dict_comprehension(
+        var: String,
+        iter_expr: e::Expr,
+        key_expr: e::Expr,
+        val_expr: e::Expr,
+        filter: Option<e::Expr>,
+        span: Option<Span>,
+    ) -> e::Expr {
+        // Build: __v.push((key, val))
+        let push_stmt = e::Stmt::Expr(e::Expr::Call {
+            callee: Box::new(e::Expr::Field {
+                base: Box::new(e::Expr::Path(vec!["__v".into()])),
+                field: "push".into(),
+            }),
+            args: vec![e::Expr::Tuple(vec![key_expr, val_expr])],
+        });
+
+        let for_body = if let Some(cond) = filter {
+            e::Block {
+                statements: vec![e::Stmt::If {
+                    condition: cond,
+                    then_block: e::Block {
+                        statements: vec![push_stmt],
+                    },
+                    else_block: None,
+                }],
+            }
+        } else {
+            e::Block {
+                statements: vec![push_stmt],
+            }
+        };
+
+        // __v.into_iter().collect()
+        let collect_expr = e::Expr::Call {
+            callee: Box::new(e::Expr::Field {
+                base: Box::new(e::Expr::Call {
+                    callee: Box::new(e::Expr::Field {
+                        base: Box::new(e::Expr::Path(vec!["__v".into()])),
+                        field: "into_iter".into(),
+                    }),
+                    args: vec![],
+                }),
+                field: "collect".into(),
+            }),
+            args: vec![],
+        };
+
+        let iife_body = e::Block {
+            statements: vec![
+                // let __v: Vec<_> = Vec::new()
+                e::Stmt::Const(e::ConstDef {
+                    visibility: e::Visibility::Private,
+                    name: "__v".into(),
+                    ty: Some(e::Type {
+                        path: vec!["Vec".into()],
+                        args: vec![e::Type {
+                            path: vec!["_".into()],
+                            args: vec![],
+                            trait_bounds: vec![],
+                        }],
+                        trait_bounds: vec![],
+                    }),
+                    value: e::Expr::Call {
+                        callee: Box::new(e::Expr::Path(vec!["Vec".into(), "new".into()])),
+                        args: vec![],
+                    },
+                    is_const: false,
+                    span,
+                }),
+                // for var in iter { __v.push((key, val)); }
+                e::Stmt::For {
+                    binding: e::DestructurePattern::Name(var),
+                    iter: iter_expr,
+                    body: for_body,
+                },
+                // __v.into_iter().collect()
+                e::Stmt::TailExpr(collect_expr),
+            ],
+        };
+
+        e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![],
+                return_type: None,
+                body: iife_body,
+            }),
+            args: vec![],
+        }
+    }
+
+    /// Parse call arguments, detecting keyword args (name=expr).
+    /// Struct update, after the `(`: `Config(**base, retries=5)` copies
+    /// `base` with the named fields replaced, like Rust's
+    /// `Config { retries: 5, ..base }`. Elevate's struct literals have no
+    /// base, so it is built as a closure called in place:
+    ///
+    /// ```text
+    /// (|| { __spread = base; __spread.retries = 5; __spread })()
+    /// ```
+    fn parse_struct_update(&mut self, name: String) -> Result<e::Expr, ParseError> {
+        self.expect(&TokenKind::DoubleStar)?;
+        let base = self.parse_expr()?;
+        let spread = || e::Expr::Path(vec!["__spread".to_string()]);
+        let mut statements = vec![e::Stmt::Assign {
+            target: e::AssignTarget::Path("__spread".to_string()),
+            op: e::AssignOp::Assign,
+            value: base,
+        }];
+        while self.eat(&TokenKind::Comma)? && !self.check(&TokenKind::RParen) {
+            let CallArg::Keyword(field, value) = self.parse_call_arg()? else {
+                return Err(self.error(format!(
+                    "'{name}(**base, ...)' takes keyword fields after the base, e.g. {name}(**base, x=1)"
+                )));
+            };
+            if !self.struct_fields[&name].contains(&field) {
+                return Err(self.error(format!("'{name}' has no field '{field}'")));
+            }
+            statements.push(e::Stmt::Assign {
+                target: e::AssignTarget::Field {
+                    base: Box::new(spread()),
+                    field,
+                },
+                op: e::AssignOp::Assign,
+                value,
+            });
+        }
+        self.expect(&TokenKind::RParen)?;
+        statements.push(e::Stmt::TailExpr(spread()));
+        Ok(e::Expr::Call {
+            callee: Box::new(e::Expr::Closure {
+                params: vec![],
+                return_type: None,
+                body: e::Block { statements },
+            }),
+            args: vec![],
+        })
+    }
+
+    fn parse_call_args_with_kwargs(&mut self) -> Result<Vec<CallArg>, ParseError> {
+        let mut args = Vec::new();
+        while !self.check(&TokenKind::RParen) {
+            args.push(self.parse_call_arg()?);
+            if !self.eat(&TokenKind::Comma)? {
+                break;
+            }
+        }
+        Ok(args)
+    }
+
+    fn parse_call_arg(&mut self) -> Result<CallArg, ParseError> {
+        // Check for keyword arg: Ident followed by '='
+        let is_kwarg = if let TokenKind::Ident(_) = self.kind() {
+            matches!(self.peek()?.kind, TokenKind::Eq)
+        } else {
+            false
+        };
+        if is_kwarg {
+            let name = self.expect_ident()?;
+            self.expect(&TokenKind::Eq)?;
+            let value = self.parse_expr()?;
+            Ok(CallArg::Keyword(name, value))
+        } else {
+            Ok(CallArg::Positional(self.parse_expr()?))
+        }
+    }
+
+    /// A call's arguments in the parameter order of the function `key`
+    /// (see [`Self::fn_params`]): positional ones first, then keyword ones
+    /// by name, then the defaults of the parameters left out. Extra
+    /// positional arguments are packed into a list for `*args`, and extra
+    /// keyword ones into a dict for `**kwargs`.
+    fn bind_call_args(
+        &self,
+        key: &str,
+        names: &[String],
+        call_args: Vec<CallArg>,
+    ) -> Result<Vec<e::Expr>, ParseError> {
+        let function = key.replace("::", ".");
+        let positional = names
+            .iter()
+            .position(|n| n.starts_with('*'))
+            .unwrap_or(names.len());
+        let varargs = names
+            .iter()
+            .position(|n| n.starts_with('*') && !n.starts_with("**") && n.len() > 1);
+        let kwargs = names.iter().position(|n| n.starts_with("**"));
+        let mut slots: Vec<Option<e::Expr>> = vec![None; names.len()];
+        let mut extra_positional = Vec::new();
+        let mut extra_keywords: Vec<(String, e::Expr)> = Vec::new();
+        let mut next = 0;
+        for arg in call_args {
+            match arg {
+                CallArg::Positional(value) => {
+                    if next < positional {
+                        slots[next] = Some(value);
+                        next += 1;
+                    } else if varargs.is_some() {
+                        extra_positional.push(value);
+                    } else {
+                        return Err(self.error(format!(
+                            "'{function}' takes {positional} positional argument(s) but more were given"
+                        )));
+                    }
+                }
+                CallArg::Keyword(name, value) => {
+                    let Some(i) = names.iter().position(|n| *n == name) else {
+                        if kwargs.is_none() {
+                            return Err(
+                                self.error(format!("'{function}' has no parameter named '{name}'"))
+                            );
+                        }
+                        if extra_keywords.iter().any(|(k, _)| *k == name) {
+                            return Err(self.error(format!(
+                                "'{function}' got more than one value for '{name}'"
+                            )));
+                        }
+                        extra_keywords.push((name, value));
+                        continue;
+                    };
+                    if slots[i].replace(value).is_some() {
+                        return Err(self
+                            .error(format!("'{function}' got more than one value for '{name}'")));
+                    }
+                }
+            }
+        }
+        let mut args = Vec::new();
+        for (i, (name, slot)) in names.iter().zip(slots).enumerate() {
+            if name == "*" {
+                continue;
+            }
+            let value = if Some(i) == varargs {
+                e::Expr::Array(std::mem::take(&mut extra_positional))
+            } else if Some(i) == kwargs {
+                dict_literal(std::mem::take(&mut extra_keywords))
+            } else {
+                match slot {
+                    Some(value) => value,
+                    None => match self.fn_defaults.get(&(key.to_string(), name.clone())) {
+                        // Evaluated afresh at every call, unlike Python's defaults
+                        Some(text) => Parser::new(text)
+                            .and_then(|mut parser| parser.parse_expr())
+                            .map_err(|e| {
+                                self.error(format!(
+                                    "default of '{function}' '{name}': {}",
+                                    e.message
+                                ))
+                            })?,
+                        None => {
+                            return Err(self.error(format!(
+                                "call to '{function}' is missing argument '{name}'"
+                            )));
+                        }
+                    },
+                }
+            };
+            args.push(value);
+        }
+        Ok(args)
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Public API
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Extract the text of a string literal, unwrapping the `str()` call that
+/// smart strings wrap around every literal.
+pub(crate) fn string_literal(expr: &e::Expr) -> Option<String> {
+    match expr {
+        e::Expr::String(s) => Some(s.clone()),
+        e::Expr::Call { callee, args }
+            if matches!(**callee, e::Expr::Path(ref p) if p.len() == 1 && p[0] == "str")
+                && args.len() == 1 =>
+        {
+            match &args[0] {
+                e::Expr::String(s) => Some(s.clone()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The `help()` text and docstring of a function: its signature in Quiche
+/// syntax, then the docstring (from a leading string statement) indented.
+fn function_doc(func: &e::FunctionDef) -> (String, String) {
+    let params: Vec<String> = func
+        .params
+        .iter()
+        .map(|p| {
+            if p.name == "self" {
+                p.name.clone()
+            } else {
+                format!("{}: {}", p.name, quiche_type(&p.ty))
+            }
+        })
+        .collect();
+    let mut help = format!("{}({})", func.name, params.join(", "));
+    if let Some(ty) = &func.return_type {
+        help.push_str(&format!(" -> {}", quiche_type(ty)));
+    }
+    let doc = match func.body.statements.first() {
+        Some(e::Stmt::Expr(expr)) => string_literal(expr).map(|d| clean_docstring(&d)),
+        _ => None,
+    }
+    .unwrap_or_default();
+    for line in doc.lines() {
+        help.push('\n');
+        if !line.is_empty() {
+            help.push_str("    ");
+            help.push_str(line);
+        }
+    }
+    (help, doc)
+}
+
+/// Wrap each value `return`ed from a function with a union return type in
+/// its variant: directly when the value's type is evident, through the
+/// enum's `From` impls otherwise.
+fn wrap_union_returns(
+    block: &mut e::Block,
+    union: &str,
+    members: &[e::Type],
+    known: &HashMap<String, e::Type>,
+) {
+    for stmt in &mut block.statements {
+        match stmt {
+            e::Stmt::Return(Some(value)) | e::Stmt::TailExpr(value) => {
+                let variant = literal_type(value, known)
+                    .map(|ty| Parser::type_to_variant_name(&ty))
+                    .filter(|variant| {
+                        members
+                            .iter()
+                            .any(|member| &Parser::type_to_variant_name(member) == variant)
+                    })
+                    .unwrap_or_else(|| "from".to_string());
+                let value_expr = std::mem::replace(value, e::Expr::Tuple(vec![]));
+                *value = e::Expr::Call {
+                    callee: Box::new(e::Expr::Path(vec![union.to_string(), variant])),
+                    args: vec![value_expr],
+                };
+            }
+            e::Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                wrap_union_returns(then_block, union, members, known);
+                if let Some(block) = else_block {
+                    wrap_union_returns(block, union, members, known);
+                }
+            }
+            e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+                wrap_union_returns(body, union, members, known);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Methods that change a collection in place.
+const MUTATING_METHODS: &[&str] = &[
+    "append", "push", "insert", "extend", "pop", "remove", "clear", "sort", "reverse", "update",
+    "truncate", "retain",
+];
+
+/// The first thing in `stmts` that breaks parameter `name`'s borrow mode:
+/// any change to a `ref` parameter, or rebinding a `mut ref` one (changes
+/// must go through it). Returning either would move out of a borrow.
+fn borrow_violation(stmts: &[e::Stmt], name: &str, mutable: bool) -> Option<String> {
+    let is_param =
+        |expr: &e::Expr| matches!(expr, e::Expr::Path(path) if path.len() == 1 && path[0] == name);
+    let mode = if mutable { "mut ref" } else { "ref" };
+    let fix = if mutable {
+        "assign through it instead"
+    } else {
+        "declare it `mut ref` to allow changes"
+    };
+    stmts.iter().find_map(|stmt| match stmt {
+        e::Stmt::Assign { target, .. } => match target {
+            e::AssignTarget::Path(target) if target == name => Some(format!(
+                "cannot assign to '{name}': parameter is `{mode}`; {fix}"
+            )),
+            e::AssignTarget::Field { base, .. } | e::AssignTarget::Index { base, .. }
+                if !mutable && is_param(base) =>
+            {
+                Some(format!("cannot modify '{name}': parameter is `ref`; {fix}"))
+            }
+            _ => None,
+        },
+        e::Stmt::Expr(e::Expr::Call { callee, .. }) if !mutable => match &**callee {
+            e::Expr::Field { base, field }
+                if is_param(base) && MUTATING_METHODS.contains(&field.as_str()) =>
+            {
+                Some(format!(
+                    "cannot call '{field}' on '{name}': parameter is `ref`; {fix}"
+                ))
+            }
+            _ => None,
+        },
+        e::Stmt::Return(Some(value)) | e::Stmt::TailExpr(value) if is_param(value) => Some(
+            format!("cannot return '{name}': parameter is `{mode}`, not owned"),
+        ),
+        e::Stmt::If {
+            then_block,
+            else_block,
+            ..
+        } => borrow_violation(&then_block.statements, name, mutable).or_else(|| {
+            else_block
+                .as_ref()
+                .and_then(|block| borrow_violation(&block.statements, name, mutable))
+        }),
+        e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+            borrow_violation(&body.statements, name, mutable)
+        }
+        _ => None,
+    })
+}
+
+/// The first read, after a `try`, of a variable the `try` body introduced.
+/// The body runs as a closure (see `Parser::parse_try`), so what it assigns
+/// is local to it; `assigned` holds the names bound so far, and `hidden`
+/// those bound only inside an earlier `try` body.
+fn try_scope_violation(
+    stmts: &[e::Stmt],
+    assigned: &mut HashSet<String>,
+    mut hidden: HashSet<String>,
+) -> Option<String> {
+    for stmt in stmts {
+        if !hidden.is_empty() {
+            let mut read = None;
+            let block = e::Block {
+                statements: vec![stmt.clone()],
+            };
+            visit_exprs(&block, &mut |expr| {
+                if let e::Expr::Path(path) = expr
+                    && let [name] = path.as_slice()
+                    && hidden.contains(name)
+                {
+                    read.get_or_insert_with(|| name.clone());
+                }
+            });
+            if let Some(name) = read {
+                return Some(format!(
+                    "'{name}' is assigned inside a `try` body, so it isn't defined after the `try`; assign it before the `try` and update it inside"
+                ));
+            }
+        }
+        if let Some(body) = try_attempt_body(stmt) {
+            let mut inside = HashSet::new();
+            bound_names(&body.statements, &mut inside);
+            hidden.extend(inside.into_iter().filter(|name| !assigned.contains(name)));
+            continue;
+        }
+        let nested = match stmt {
+            e::Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => std::iter::once(then_block).chain(else_block).collect(),
+            e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => vec![body],
+            _ => vec![],
+        };
+        for block in nested {
+            let problem =
+                try_scope_violation(&block.statements, &mut assigned.clone(), hidden.clone());
+            if problem.is_some() {
+                return problem;
+            }
+        }
+        let mut bound = HashSet::new();
+        bound_names(std::slice::from_ref(stmt), &mut bound);
+        for name in bound {
+            hidden.remove(&name);
+            assigned.insert(name);
+        }
+    }
+    None
+}
+
+/// The closure body of a lowered `try`: `__try_1 = Exception::attempt(|| ...)`.
+fn try_attempt_body(stmt: &e::Stmt) -> Option<&e::Block> {
+    let e::Stmt::Assign {
+        value: e::Expr::Call { callee, args },
+        ..
+    } = stmt
+    else {
+        return None;
+    };
+    match (&**callee, args.as_slice()) {
+        (e::Expr::Path(path), [e::Expr::Closure { body, .. }])
+            if path == &["Exception", "attempt"] =>
+        {
+            Some(body)
+        }
+        _ => None,
+    }
+}
+
+/// The variable names `stmts` bind, in nested blocks too but not inside
+/// closures. Compiler temporaries (`__try_1`, ...) are left out.
+fn bound_names(stmts: &[e::Stmt], names: &mut HashSet<String>) {
+    fn pattern_names(pattern: &e::DestructurePattern, names: &mut HashSet<String>) {
+        match pattern {
+            e::DestructurePattern::Name(name) => {
+                names.insert(name.clone());
+            }
+            e::DestructurePattern::Ignore => {}
+            e::DestructurePattern::Tuple(parts) => {
+                parts.iter().for_each(|part| pattern_names(part, names));
+            }
+            e::DestructurePattern::Slice {
+                prefix,
+                rest,
+                suffix,
+            } => {
+                prefix
+                    .iter()
+                    .chain(suffix)
+                    .for_each(|part| pattern_names(part, names));
+                names.extend(rest.clone());
+            }
+        }
+    }
+    for stmt in stmts {
+        match stmt {
+            e::Stmt::Assign {
+                target: e::AssignTarget::Path(name),
+                ..
+            } if !name.starts_with("__") => {
+                names.insert(name.clone());
+            }
+            e::Stmt::Const(def) => {
+                names.insert(def.name.clone());
+            }
+            e::Stmt::DestructureConst { pattern, .. } => pattern_names(pattern, names),
+            e::Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                bound_names(&then_block.statements, names);
+                if let Some(block) = else_block {
+                    bound_names(&block.statements, names);
+                }
+            }
+            e::Stmt::While { body, .. } => bound_names(&body.statements, names),
+            e::Stmt::For { binding, body, .. } => {
+                pattern_names(binding, names);
+                bound_names(&body.statements, names);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Static-module calls whose function argument runs on other threads:
+/// `(module, function, index of the function argument)`.
+const THREADED_CALLS: &[(&str, &str, usize)] = &[
+    ("Parallel", "map", 0),
+    ("Parallel", "for_each", 0),
+    ("Server", "route", 1),
+    ("Signal", "on", 1),
+];
+
+/// The first call in `block` handing work to other threads that can't go:
+/// the function of a [`THREADED_CALLS`] call a lambda capturing something
+/// [`thread_unsafe`], or the items of a `Parallel` call a list of such.
+fn thread_violation(block: &e::Block, known: &HashMap<String, e::Type>) -> Option<String> {
+    let mut problem = None;
+    visit_exprs(block, &mut |expr| {
+        let e::Expr::Call { callee, args } = expr else {
+            return;
+        };
+        let e::Expr::Path(path) = &**callee else {
+            return;
+        };
+        let [module, function] = path.as_slice() else {
+            return;
+        };
+        let Some(&(_, _, at)) = THREADED_CALLS
+            .iter()
+            .find(|(m, f, _)| m == module && f == function)
+        else {
+            return;
+        };
+        if problem.is_some() {
+            return;
+        }
+        if let Some(e::Expr::Closure { params, body, .. }) = args.get(at) {
+            let mut captured = None;
+            visit_exprs(body, &mut |expr| {
+                if let e::Expr::Path(p) = expr
+                    && let [name] = p.as_slice()
+                    && !params.iter().any(|param| &param.name == name)
+                    && let Some(what) = known.get(name).and_then(thread_unsafe)
+                {
+                    captured.get_or_insert((name.clone(), what));
+                }
+            });
+            if let Some((name, what)) = captured {
+                problem = Some(format!(
+                    "{module}.{function}'s function captures '{name}', {what}, which can't be shared between threads"
+                ));
+                return;
+            }
+        }
+        if module == "Parallel"
+            && let Some(e::Expr::Path(p)) = args.get(1)
+            && let [name] = p.as_slice()
+            && let Some(what) = known.get(name).and_then(thread_unsafe)
+        {
+            problem = Some(format!(
+                "{module}.{function}'s items '{name}' hold {what}, which can't be sent between threads"
+            ));
+        }
+    });
+    problem
+}
+
+/// What makes a value of type `ty` unusable from another thread, if
+/// anything: a loaded C library, a raw C pointer, or a trait object (which
+/// promises nothing about its concrete type), directly or inside a
+/// collection.
+fn thread_unsafe(ty: &e::Type) -> Option<&'static str> {
+    match ty.path.as_slice() {
+        [name] if name == "CLib" => Some("a CLib"),
+        [name] if name == "Ptr" => Some("a C pointer"),
+        [name] if name == "Dyn" => Some("a trait object"),
+        _ => ty.args.iter().find_map(thread_unsafe),
+    }
+}
+
+/// Call `visit` on every expression in `block`, outermost first, including
+/// those in nested blocks and lambda bodies.
+fn visit_exprs(block: &e::Block, visit: &mut dyn FnMut(&e::Expr)) {
+    for stmt in &block.statements {
+        match stmt {
+            e::Stmt::Const(def) => visit_expr(&def.value, visit),
+            e::Stmt::DestructureConst { value, .. }
+            | e::Stmt::Assign { value, .. }
+            | e::Stmt::Return(Some(value))
+            | e::Stmt::Expr(value)
+            | e::Stmt::TailExpr(value) => visit_expr(value, visit),
+            e::Stmt::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                visit_expr(condition, visit);
+                visit_exprs(then_block, visit);
+                if let Some(block) = else_block {
+                    visit_exprs(block, visit);
+                }
+            }
+            e::Stmt::While { condition, body } => {
+                visit_expr(condition, visit);
+                visit_exprs(body, visit);
+            }
+            e::Stmt::For { iter, body, .. } => {
+                visit_expr(iter, visit);
+                visit_exprs(body, visit);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// [`visit_exprs`] for `expr` and the expressions inside it.
+fn visit_expr(expr: &e::Expr, visit: &mut dyn FnMut(&e::Expr)) {
+    visit(expr);
+    match expr {
+        e::Expr::Call { callee, args } => {
+            visit_expr(callee, visit);
+            args.iter().for_each(|arg| visit_expr(arg, visit));
+        }
+        e::Expr::MacroCall { args, .. } | e::Expr::Array(args) | e::Expr::Tuple(args) => {
+            args.iter().for_each(|arg| visit_expr(arg, visit));
+        }
+        e::Expr::Field { base, .. } => visit_expr(base, visit),
+        e::Expr::Index { base, index } => {
+            visit_expr(base, visit);
+            visit_expr(index, visit);
+        }
+        e::Expr::Unary { expr, .. } | e::Expr::Cast { expr, .. } => visit_expr(expr, visit),
+        e::Expr::Binary { left, right, .. } => {
+            visit_expr(left, visit);
+            visit_expr(right, visit);
+        }
+        e::Expr::Range { start, end, .. } => {
+            for bound in [start, end].into_iter().flatten() {
+                visit_expr(bound, visit);
+            }
+        }
+        e::Expr::StructLiteral { fields, .. } => {
+            for field in fields {
+                visit_expr(&field.value, visit);
+            }
+        }
+        e::Expr::Closure { body, .. } => visit_exprs(body, visit),
+        e::Expr::Match { scrutinee, arms } => {
+            visit_expr(scrutinee, visit);
+            for arm in arms {
+                visit_expr(&arm.value, visit);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Raise `exception`: `return Err(exception)` where the enclosing function
+/// or `try` body returns a `Result`, otherwise `Exception::raise`, which ends
+/// the program.
+fn raise_stmt(exception: e::Expr, into_result: bool) -> e::Stmt {
+    if into_result {
+        e::Stmt::Return(Some(e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["Err".into()])),
+            args: vec![exception],
+        }))
+    } else {
+        e::Stmt::Expr(e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["Exception".into(), "raise".into()])),
+            args: vec![exception],
+        })
+    }
+}
+
+/// Turn the program-ending raises in `statements` (parsed outside a
+/// `Result` context) into `Err` returns, for clauses moved into the closure
+/// of a `try` with a `finally:` block.
+fn raise_into_result(statements: &mut [e::Stmt]) {
+    for stmt in statements {
+        match stmt {
+            e::Stmt::Expr(e::Expr::Call { callee, args }) if matches!(&**callee, e::Expr::Path(p) if p == &["Exception", "raise"]) =>
+            {
+                *stmt = raise_stmt(args.remove(0), true);
+            }
+            e::Stmt::Expr(e::Expr::Call { callee, args }) if matches!(&**callee, e::Expr::Path(p) if p == &["Exception", "check"]) =>
+            {
+                *stmt = e::Stmt::Expr(e::Expr::MacroCall {
+                    path: vec!["quiche_try".into()],
+                    args: std::mem::take(args),
+                });
+            }
+            e::Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                raise_into_result(&mut then_block.statements);
+                if let Some(block) = else_block {
+                    raise_into_result(&mut block.statements);
+                }
+            }
+            e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+                raise_into_result(&mut body.statements);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The first `return`, or `break` / `continue` of a loop outside `block`,
+/// that code moved into a closure couldn't perform. A `raise`, lowered to
+/// `return Err(...)`, is what the closure is for.
+fn block_escape(block: &e::Block, in_loop: bool) -> Option<&'static str> {
+    let is_raise = |value: &Option<e::Expr>| {
+        let Some(e::Expr::Call { callee, args }) = value else {
+            return false;
+        };
+        let raised = match &args[..] {
+            [e::Expr::MacroCall { path, .. }] => path == &["quiche_raise"],
+            [e::Expr::Path(p)] => p.len() == 1 && p[0].starts_with("__error_"),
+            _ => false,
+        };
+        raised && matches!(&**callee, e::Expr::Path(p) if p == &["Err"])
+    };
+    block.statements.iter().find_map(|stmt| match stmt {
+        e::Stmt::Return(value) if !is_raise(value) => Some("return"),
+        e::Stmt::Break if !in_loop => Some("break"),
+        e::Stmt::Continue if !in_loop => Some("continue"),
+        e::Stmt::If {
+            then_block,
+            else_block,
+            ..
+        } => block_escape(then_block, in_loop)
+            .or_else(|| else_block.as_ref().and_then(|b| block_escape(b, in_loop))),
+        e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => block_escape(body, true),
+        _ => None,
+    })
+}
+
+/// Give `xs = []` and `d = {}` a type from how the collection is filled
+/// later in the function, as an annotation would: `xs.push(3)` or
+/// `xs.append(name)` makes a `List[i64]` / `List[<type of name>]`, and
+/// `d[k] = v` or `d.insert(k, v)` a `Dict`. Only first assignments are
+/// rewritten, and only when an element type can be read off a literal or a
+/// typed name; anything else is left to the type checker.
+fn infer_empty_collections(
+    block: &mut e::Block,
+    known: &mut HashMap<String, e::Type>,
+    first_assigns: &HashMap<String, Span>,
+) {
+    for i in 0..block.statements.len() {
+        let (rest_start, rest) = block.statements.split_at_mut(i + 1);
+        let stmt = &mut rest_start[i];
+        match stmt {
+            e::Stmt::Assign {
+                target: e::AssignTarget::Path(name),
+                op: e::AssignOp::Assign,
+                value,
+            } if !known.contains_key(name.as_str()) => {
+                let ty = if matches!(value, e::Expr::Array(items) if items.is_empty()) {
+                    find_fill(rest, name, known, false)
+                } else if is_empty_dict(value) {
+                    find_fill(rest, name, known, true)
+                } else {
+                    None
+                };
+                if let Some(ty) = ty {
+                    known.insert(name.clone(), ty.clone());
+                    let span = first_assigns.get(name.as_str()).copied();
+                    *stmt = e::Stmt::Const(e::ConstDef {
+                        visibility: e::Visibility::Private,
+                        name: std::mem::take(name),
+                        ty: Some(ty),
+                        value: std::mem::replace(value, e::Expr::Tuple(vec![])),
+                        is_const: false,
+                        span,
+                    });
+                } else if let Some(ty) = literal_type(value, known) {
+                    known.insert(name.clone(), ty);
+                }
+            }
+            e::Stmt::Const(def) => {
+                if let Some(ty) = def.ty.clone().or_else(|| literal_type(&def.value, known)) {
+                    known.insert(def.name.clone(), ty);
+                }
+            }
+            e::Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                infer_empty_collections(then_block, known, first_assigns);
+                if let Some(block) = else_block {
+                    infer_empty_collections(block, known, first_assigns);
+                }
+            }
+            e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+                infer_empty_collections(body, known, first_assigns);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `{}`, which the parser builds as `HashMap::new()`.
+fn is_empty_dict(expr: &e::Expr) -> bool {
+    matches!(expr, e::Expr::Call { callee, args }
+        if args.is_empty() && matches!(&**callee, e::Expr::Path(p) if p == &["HashMap", "new"]))
+}
+
+/// The type of the collection `name` implied by the first statement in
+/// `stmts` (searching nested blocks) that adds to it.
+fn find_fill(
+    stmts: &[e::Stmt],
+    name: &str,
+    known: &HashMap<String, e::Type>,
+    dict: bool,
+) -> Option<e::Type> {
+    let is_name = |expr: &e::Expr| matches!(expr, e::Expr::Path(p) if p.len() == 1 && p[0] == name);
+    let generic = |base: &str, args: Vec<e::Type>| e::Type {
+        path: vec![base.to_string()],
+        args,
+        trait_bounds: vec![],
+    };
+    for stmt in stmts {
+        let found = match stmt {
+            e::Stmt::Expr(e::Expr::Call { callee, args }) => match (&**callee, args.as_slice()) {
+                (e::Expr::Field { base, field }, [item])
+                    if !dict && is_name(base) && (field == "push" || field == "append") =>
+                {
+                    literal_type(item, known).map(|t| generic("List", vec![t]))
+                }
+                (e::Expr::Field { base, field }, [key, value])
+                    if dict && is_name(base) && field == "insert" =>
+                {
+                    literal_type(key, known)
+                        .zip(literal_type(value, known))
+                        .map(|(k, v)| generic("Dict", vec![k, v]))
+                }
+                _ => None,
+            },
+            e::Stmt::Assign {
+                target: e::AssignTarget::Index { base, index },
+                value,
+                ..
+            } if dict && is_name(base) => literal_type(index, known)
+                .zip(literal_type(value, known))
+                .map(|(k, v)| generic("Dict", vec![k, v])),
+            e::Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => find_fill(&then_block.statements, name, known, dict).or_else(|| {
+                else_block
+                    .as_ref()
+                    .and_then(|b| find_fill(&b.statements, name, known, dict))
+            }),
+            e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+                find_fill(&body.statements, name, known, dict)
+            }
+            _ => None,
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+/// Whether `expr` is built from number and bool literals alone, so it can
+/// initialize a Rust `const`.
+fn is_const_value(expr: &e::Expr) -> bool {
+    match expr {
+        e::Expr::Int(_) | e::Expr::Bool(_) => true,
+        e::Expr::Unary { expr, .. } => is_const_value(expr),
+        e::Expr::Binary { left, right, .. } => is_const_value(left) && is_const_value(right),
+        _ => false,
+    }
+}
+
+/// The type of a literal, of a name whose type is known, or of a
+/// `CLib.load(...)`.
+fn literal_type(expr: &e::Expr, known: &HashMap<String, e::Type>) -> Option<e::Type> {
+    let simple = |name: &str| e::Type {
+        path: vec![name.to_string()],
+        args: vec![],
+        trait_bounds: vec![],
+    };
+    match expr {
+        e::Expr::Int(_) => Some(simple("i64")),
+        e::Expr::Bool(_) => Some(simple("bool")),
+        e::Expr::String(_) => Some(simple("Str")),
+        e::Expr::Path(p) if p.len() == 1 => known.get(&p[0]).cloned(),
+        e::Expr::Call { callee, .. } if matches!(&**callee, e::Expr::Path(p) if p == &["CLib", "load"]) => {
+            Some(simple("CLib"))
+        }
+        e::Expr::Unary {
+            op: e::UnaryOp::Neg,
+            expr,
+        } => literal_type(expr, known),
+        _ => string_literal(expr).map(|_| simple("Str")),
+    }
+}
+
+/// Quiche spelling of a parsed type: `Dict[Str, List[i64]]`.
+pub(crate) fn quiche_type(ty: &e::Type) -> String {
+    let path = ty.path.join(".");
+    if ty.args.is_empty() {
+        path
+    } else {
+        let args: Vec<String> = ty.args.iter().map(quiche_type).collect();
+        format!("{path}[{}]", args.join(", "))
+    }
+}
+
+/// Parameter types `@cli` converts command-line strings to.
+const CLI_TYPES: &[&str] = &[
+    "Str", "String", "bool", "char", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+    "u32", "u64", "u128", "usize", "f32", "f64",
+];
+
+/// A cleaned docstring split for `@cli`'s help: the description, and each
+/// parameter's text from an `Args:` section of `name: text` lines.
+fn cli_docs(doc: &str) -> (String, HashMap<String, String>) {
+    let mut about = Vec::new();
+    let mut params: HashMap<String, String> = HashMap::new();
+    let mut section: Option<usize> = None;
+    let mut current: Option<String> = None;
+    for line in doc.lines() {
+        let indent = line.len() - line.trim_start().len();
+        let text = line.trim();
+        if matches!(text, "Args:" | "Arguments:") {
+            section = Some(indent);
+            continue;
+        }
+        match section {
+            Some(outer) if indent > outer || text.is_empty() => {
+                let entry = text
+                    .split_once(':')
+                    .filter(|(name, _)| !name.contains(' ') || name.contains('('));
+                match entry {
+                    Some((name, help)) if !text.is_empty() => {
+                        let name = name.split('(').next().unwrap_or(name).trim().to_string();
+                        params.insert(name.clone(), help.trim().to_string());
+                        current = Some(name);
+                    }
+                    _ => {
+                        if let Some(help) = current.as_ref().and_then(|name| params.get_mut(name))
+                            && !text.is_empty()
+                        {
+                            help.push(' ');
+                            help.push_str(text);
+                        }
+                    }
+                }
+            }
+            Some(_) => {
+                section = None;
+                current = None;
+                about.push(line);
+            }
+            None => about.push(line),
+        }
+    }
+    (about.join("\n").trim().to_string(), params)
+}
+
+/// A docstring with surrounding blank lines and the common indentation of
+/// its continuation lines removed, like Python's `inspect.cleandoc`.
+fn clean_docstring(doc: &str) -> String {
+    let mut lines = doc.lines();
+    let first = lines.next().unwrap_or("").trim().to_string();
+    let rest: Vec<&str> = lines.collect();
+    let indent = rest
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut out = vec![first];
+    out.extend(
+        rest.iter()
+            .map(|l| l.get(indent..).unwrap_or("").trim_end().to_string()),
+    );
+    out.join("\n").trim_matches('\n').to_string()
+}
+
+/// The text of an f-string placeholder, after its `{`, up to the matching
+/// `}` (consumed). String literals inside it may hold braces.
+fn fstring_placeholder(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut expr = String::new();
+    let mut depth = 1;
+    let mut quote: Option<char> = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                expr.push(c);
+                expr.extend(chars.next());
+                continue;
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            (None, _) => {}
+        }
+        expr.push(c);
+    }
+    expr
+}
+
+/// The expression of an f-string placeholder in the `{expr=}` debug form,
+/// as opposed to one ending in a comparison (`{a == b}`, `{a <= b}`).
+fn fstring_debug_expr(placeholder: &str) -> Option<&str> {
+    let expr = placeholder.trim_end().strip_suffix('=')?;
+    if expr.ends_with(['=', '!', '<', '>']) || expr.trim().is_empty() {
+        return None;
+    }
+    Some(expr)
+}
+
+/// A type-parameter bound, with Quiche's trait aliases spelled out:
+/// `Hashable` (usable as a `Dict` key or `Set` member) is `Hash + Eq`, and
+/// `Comparable` (totally ordered) is `Ord`.
+fn expand_bound(bound: e::Type) -> Vec<e::Type> {
+    let named = |path: &[&str]| e::Type {
+        path: path.iter().map(|s| s.to_string()).collect(),
+        args: vec![],
+        trait_bounds: vec![],
+    };
+    match bound.path.as_slice() {
+        [name] if name == "Hashable" && bound.args.is_empty() => {
+            vec![named(&["std", "hash", "Hash"]), named(&["Eq"])]
+        }
+        [name] if name == "Comparable" && bound.args.is_empty() => vec![named(&["Ord"])],
+        _ => vec![bound],
+    }
+}
+
+/// Rust spelling of a parsed type: `Dict[Str, List[i64]]` → `Dict<Str, List<i64>>`.
+fn rust_type(ty: &e::Type) -> String {
+    match (ty.path.as_slice(), ty.args.as_slice()) {
+        ([mode], [inner]) if mode == "Ref" => return format!("&{}", rust_type(inner)),
+        ([mode], [inner]) if mode == "MutRef" => return format!("&mut {}", rust_type(inner)),
+        ([mode], [inner]) if mode == "Dyn" => return format!("dyn {}", rust_type(inner)),
+        _ => {}
+    }
+    let path = ty.path.join("::");
+    if ty.args.is_empty() {
+        path
+    } else {
+        let args: Vec<String> = ty.args.iter().map(rust_type).collect();
+        format!("{path}<{}>", args.join(", "))
+    }
+}
+
+/// A dict literal of `Str` keys, as `{"sep": ", "}` is parsed: `HashMap::new()`
+/// when empty.
+fn dict_literal(entries: Vec<(String, e::Expr)>) -> e::Expr {
+    if entries.is_empty() {
+        return e::Expr::Call {
+            callee: Box::new(e::Expr::Path(vec!["HashMap".into(), "new".into()])),
+            args: vec![],
+        };
+    }
+    let pairs = entries
+        .into_iter()
+        .map(|(key, value)| {
+            let key = e::Expr::Call {
+                callee: Box::new(e::Expr::Path(vec!["str".into()])),
+                args: vec![e::Expr::String(key)],
+            };
+            e::Expr::Tuple(vec![key, value])
+        })
+        .collect();
+    e::Expr::Call {
+        callee: Box::new(e::Expr::Path(vec!["HashMap".into(), "from".into()])),
+        args: vec![e::Expr::Array(pairs)],
+    }
+}
+
+/// `std::collections` types, which are not in Rust's prelude.
+const COLLECTIONS: &[&str] = &[
+    "BTreeMap",
+    "BTreeSet",
+    "BinaryHeap",
+    "HashMap",
+    "HashSet",
+    "VecDeque",
+];
+
+/// One `use std::collections::{...};` for the collection types a module
+/// names, except those it imports or declares itself.
+fn collections_use(names: &BTreeSet<&str>, items: &[e::Item]) -> Option<e::Item> {
+    let names: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|name| {
+            !items.iter().any(|item| match item {
+                e::Item::Struct(def) => def.name == *name,
+                e::Item::Enum(def) => def.name == *name,
+                e::Item::RustUse(import) => use_tree_names(&import.tree, name),
+                _ => false,
+            })
+        })
+        .collect();
+    (!names.is_empty())
+        .then(|| e::Item::RustBlock(format!("use std::collections::{{{}}};", names.join(", "))))
+}
+
+/// Whether a `use` brings `name` into scope.
+fn use_tree_names(tree: &e::UseTree, name: &str) -> bool {
+    match tree {
+        e::UseTree::Name(imported) => imported == name,
+        e::UseTree::Path { next, .. } => use_tree_names(next, name),
+        #[allow(unreachable_patterns)]
+        _ => false,
+    }
+}
+
+/// `outer[inner]`, e.g. `Box[Dyn[T]]`.
+fn wrap_type(outer: &str, inner: e::Type) -> e::Type {
+    e::Type {
+        path: vec![outer.into()],
+        args: vec![inner],
+        trait_bounds: vec![],
+    }
+}
+
+/// Whether `ty` holds a trait object anywhere, as `List[Box[Dyn[T]]]` does.
+fn holds_trait_object(ty: &e::Type) -> bool {
+    ty.path == ["Dyn"] || ty.args.iter().any(holds_trait_object)
+}
+
+/// A `@trait` method's Rust signature, `fn area(&self, scale: f64) -> f64`.
+fn trait_method_signature(method: &e::FunctionDef) -> String {
+    let mut params = vec!["&self".to_string()];
+    params.extend(
+        method
+            .params
+            .iter()
+            .skip(1)
+            .map(|p| format!("{}: {}", p.name, rust_type(&p.ty))),
+    );
+    let ret = method
+        .return_type
+        .as_ref()
+        .map(|ty| format!(" -> {}", rust_type(ty)))
+        .unwrap_or_default();
+    format!("fn {}({}){ret}", method.name, params.join(", "))
+}
+
+/// Traits `@derive` accepts, with the traits each derive needs alongside it.
+const DERIVABLE: &[(&str, &[&str])] = &[
+    ("Debug", &[]),
+    ("Clone", &[]),
+    ("Copy", &["Clone"]),
+    ("PartialEq", &[]),
+    ("Eq", &["PartialEq"]),
+    ("PartialOrd", &["PartialEq"]),
+    ("Ord", &["PartialEq", "Eq", "PartialOrd"]),
+    ("Hash", &[]),
+    ("Default", &[]),
+    ("Serialize", &[]),
+    ("Deserialize", &[]),
+];
+
+/// A type written as a decorator argument: `Default` or `From[Str]`.
+fn decorator_type(expr: &e::Expr) -> Option<e::Type> {
+    match expr {
+        e::Expr::Path(path) => {
+            let path = match path.as_slice() {
+                [name] if name == "str" => vec!["Str".to_string()],
+                [name] if name == "list" => vec!["List".to_string()],
+                [name] if name == "dict" => vec!["Dict".to_string()],
+                _ => path.clone(),
+            };
+            Some(e::Type {
+                path,
+                args: vec![],
+                trait_bounds: vec![],
+            })
+        }
+        e::Expr::Index { base, index } => {
+            let mut ty = decorator_type(base)?;
+            ty.args = match &**index {
+                e::Expr::Tuple(items) => items.iter().map(decorator_type).collect::<Option<_>>()?,
+                single => vec![decorator_type(single)?],
+            };
+            Some(ty)
+        }
+        _ => None,
+    }
+}
+
+/// A Quiche type as it appears in an `@extern("C")` wrapper signature and
+/// in the underlying C declaration.
+/// A Quiche type as seen from JavaScript through wasm-bindgen.
+enum WasmType {
+    /// Numbers and `bool`, passed as-is.
+    Plain(&'static str),
+    /// `Str`, as a JS string (`String` on the Rust side).
+    Str,
+    /// `List[T]` of numbers, as a typed array (`Vec<T>`).
+    List(&'static str),
+    /// An `@wasm_export` class, as its wrapper.
+    Class(String),
+}
+
+impl WasmType {
+    /// The Rust type in the `#[wasm_bindgen]` signature.
+    fn js(&self) -> String {
+        match self {
+            WasmType::Plain(ty) => ty.to_string(),
+            WasmType::Str => "String".to_string(),
+            WasmType::List(elem) => format!("Vec<{elem}>"),
+            WasmType::Class(name) => format!("__QuicheWasm{name}"),
+        }
+    }
+
+    /// Convert `value` from its JS-facing type to the Quiche type.
+    fn to_quiche(&self, value: &str) -> String {
+        match self {
+            WasmType::Plain(_) => value.to_string(),
+            WasmType::Str => format!("Str::from({value})"),
+            WasmType::List(_) => format!("List::from({value})"),
+            WasmType::Class(_) => format!("{value}.0"),
+        }
+    }
+
+    /// Convert `value` from the Quiche type to its JS-facing type.
+    fn to_js(&self, value: &str) -> String {
+        match self {
+            WasmType::Plain(_) => value.to_string(),
+            WasmType::Str => format!("{value}.to_string()"),
+            WasmType::List(_) => format!("{value}.0"),
+            WasmType::Class(name) => format!("__QuicheWasm{name}({value})"),
+        }
+    }
+}
+
+/// Runtime side of `@py_import`, emitted once per module: calls into an
+/// embedded CPython and converts between quiche-lib `Value`s and Python
+/// objects. Written against PyO3 0.22 with the `auto-initialize` feature.
+const PY_GLUE: &str = r#"mod __quiche_py {
+    use super::{List, Str, Value};
+    use pyo3::prelude::*;
+    use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple};
+
+    pub fn call(module: &str, function: &str, args: Vec<Value>) -> Value {
+        Python::with_gil(|py| {
+            let args: Vec<PyObject> = args.iter().map(|arg| to_py(py, arg)).collect();
+            PyModule::import_bound(py, module)
+                .and_then(|m| m.getattr(function))
+                .and_then(|f| f.call1(PyTuple::new_bound(py, args)))
+                .and_then(|result| from_py(&result))
+                .unwrap_or_else(|e| panic!("Python call {module}.{function} failed: {e}"))
+        })
+    }
+
+    fn to_py(py: Python<'_>, value: &Value) -> PyObject {
+        match value {
+            Value::None => py.None(),
+            Value::Bool(b) => b.to_object(py),
+            Value::Int(i) => i.to_object(py),
+            Value::Float(f) => f.to_object(py),
+            Value::Str(s) => (**s).to_object(py),
+            Value::List(items) => {
+                PyList::new_bound(py, items.iter().map(|item| to_py(py, item))).into_py(py)
+            }
+            Value::Dict(entries) => {
+                let dict = PyDict::new_bound(py);
+                for (k, v) in entries.iter() {
+                    let _ = dict.set_item(to_py(py, k), to_py(py, v));
+                }
+                dict.into_py(py)
+            }
+        }
+    }
+
+    fn from_py(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+        if obj.is_none() {
+            Ok(Value::None)
+        } else if obj.is_instance_of::<PyBool>() {
+            Ok(Value::Bool(obj.extract()?))
+        } else if obj.is_instance_of::<PyLong>() {
+            Ok(Value::Int(obj.extract()?))
+        } else if obj.is_instance_of::<PyFloat>() {
+            Ok(Value::Float(obj.extract()?))
+        } else if let Ok(s) = obj.downcast::<PyString>() {
+            Ok(Value::Str(Str::from(s.to_str()?)))
+        } else if let Ok(dict) = obj.downcast::<PyDict>() {
+            dict.iter()
+                .map(|(k, v)| Ok((from_py(&k)?, from_py(&v)?)))
+                .collect::<PyResult<List<_>>>()
+                .map(Value::Dict)
+        } else if obj.hasattr("tolist")? {
+            // numpy arrays and scalars
+            from_py(&obj.call_method0("tolist")?)
+        } else {
+            obj.iter()?
+                .map(|item| from_py(&item?))
+                .collect::<PyResult<List<_>>>()
+                .map(Value::List)
+        }
+    }
+}"#;
+
+struct CType {
+    quiche: &'static str,
+    c: &'static str,
+}
+
+/// The Rust type of a class constant that can be an associated `const`:
+/// numbers and `bool`. Other constants are initialized lazily.
+fn const_attr_type(ty: &e::Type) -> Option<&'static str> {
+    c_type(ty)
+        .map(|c| c.quiche)
+        .filter(|ty| !matches!(*ty, "Str" | "Ptr"))
+}
+
+/// Map a Quiche type to its C equivalent: numbers pass through, `Str`
+/// crosses as a NUL-terminated `char*`, and `Ptr` as `void*`.
+fn c_type(ty: &e::Type) -> Option<CType> {
+    if !ty.args.is_empty() {
+        return None;
+    }
+    let [name] = ty.path.as_slice() else {
+        return None;
+    };
+    let same = |name: &'static str| {
+        Some(CType {
+            quiche: name,
+            c: name,
+        })
+    };
+    match name.as_str() {
+        "int" => same("i64"),
+        "float" => same("f64"),
+        "i8" => same("i8"),
+        "i16" => same("i16"),
+        "i32" => same("i32"),
+        "i64" => same("i64"),
+        "isize" => same("isize"),
+        "u8" => same("u8"),
+        "u16" => same("u16"),
+        "u32" => same("u32"),
+        "u64" => same("u64"),
+        "usize" => same("usize"),
+        "f32" => same("f32"),
+        "f64" => same("f64"),
+        "bool" => same("bool"),
+        "Str" => Some(CType {
+            quiche: "Str",
+            c: "*const std::ffi::c_char",
+        }),
+        "Ptr" => Some(CType {
+            quiche: "Ptr",
+            c: "*mut std::ffi::c_void",
+        }),
+        _ => None,
+    }
+}
+
+pub fn parse(source: &str) -> Result<e::Module, ParseError> {
+    parse_decorated(source).map(ParsedModule::without_inline_tests)
+}
+
+/// A top-level `import module` or `from module import name as alias`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// The dotted module path, e.g. `net.http`
+    pub module: String,
+    /// `(name, alias)` for each imported name; empty for a bare `import`
+    pub names: Vec<(String, String)>,
+}
+
+/// The imports of `source`, read with the parser's own import rules but
+/// without parsing the rest of the module — for callers that need them to
+/// set up the full parse (`.qi` interfaces, `.qmeta` symbol tables).
+/// Strings, comments and indented code never count as imports.
+pub fn parse_imports(source: &str) -> Result<Vec<Import>, ParseError> {
+    let mut parser = Parser::new(source)?;
+    let mut depth = 0usize;
+    let mut line_start = true;
+    loop {
+        let top_level = depth == 0 && line_start;
+        match parser.kind() {
+            TokenKind::Eof => return Ok(parser.imports),
+            TokenKind::Keyword(Keyword::From) if top_level => {
+                parser.parse_from_import()?;
+            }
+            TokenKind::Keyword(Keyword::Import) if top_level => parser.parse_bare_import()?,
+            kind => {
+                match kind {
+                    TokenKind::Indent => depth += 1,
+                    TokenKind::Dedent => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                line_start = matches!(kind, TokenKind::Newline | TokenKind::Dedent);
+                parser.advance()?;
+            }
+        }
+    }
+}
+
+/// A parsed module plus the side tables the parser collected along the way.
+#[derive(Debug)]
+pub struct ParsedModule {
+    pub module: e::Module,
+    /// Decorators recorded for each top-level item, keyed by item name.
+    pub decorators: HashMap<String, Vec<Decorator>>,
+    /// Source lines that received a `Qtest.hit` coverage probe, keyed by the
+    /// enclosing function name. Empty unless parsed with `parse_instrumented`.
+    pub coverage: HashMap<String, Vec<usize>>,
+    /// crates.io crates the generated code uses (from `@extern`,
+    /// `@py_import`, `@derive(Serialize)`, ...), sorted.
+    pub extern_crates: Vec<String>,
+    /// The version of each `extern_crates` entry its `@extern` declares.
+    /// Crates without one (named by `path=`, or added for `@py_import` and
+    /// the like) are missing.
+    pub crate_versions: BTreeMap<String, String>,
+    /// `(line, column)` of every `unsafe:` block, in source order.
+    pub unsafe_blocks: Vec<(usize, usize)>,
+    /// The symbol tables of the module's own definitions, for modules that
+    /// import it.
+    pub meta: ModuleMeta,
+    /// The module's imports, in source order.
+    pub imports: Vec<Import>,
+}
+
+impl ParsedModule {
+    /// The module for a normal build, with its inline `@test` functions
+    /// stripped (as Rust strips `#[cfg(test)]` items).
+    pub fn without_inline_tests(self) -> e::Module {
+        let mut module = self.module;
+        module.items.retain(
+            |item| !matches!(item, e::Item::Function(f) if is_inline_test(&self.decorators, &f.name)),
+        );
+        module
+    }
+}
+
+/// Whether `name` is an inline test: a function marked `@test`, which the
+/// qtest harness runs alongside the module's `test_*` functions.
+pub(crate) fn is_inline_test(decorators: &HashMap<String, Vec<Decorator>>, name: &str) -> bool {
+    decorators
+        .get(name)
+        .is_some_and(|decos| decos.iter().any(|d| d.name == "test"))
+}
+
+/// Parse a module, keeping the decorators recorded for each top-level item.
+pub fn parse_decorated(source: &str) -> Result<ParsedModule, ParseError> {
+    parse_with(source, false)
+}
+
+/// Parse a module with a `Qtest.hit(line)` coverage probe before every
+/// statement (used by `quiche --test --coverage`).
+pub fn parse_instrumented(source: &str) -> Result<ParsedModule, ParseError> {
+    parse_with(source, true)
+}
+
+fn parse_with(source: &str, coverage: bool) -> Result<ParsedModule, ParseError> {
+    parse_with_interfaces(source, &[], coverage)
+}
+
+/// Parse a module together with the `.qi` interface files it imports, given
+/// as `(path, source)` pairs. Interface items come first, so calls in
+/// `source` are checked against their `@extern` signatures.
+pub fn parse_with_interfaces(
+    source: &str,
+    interfaces: &[(String, String)],
+    coverage: bool,
+) -> Result<ParsedModule, ParseError> {
+    parse_with_imports(source, interfaces, &[], coverage)
+}
+
+/// [`parse_with_interfaces`], also knowing the symbol tables of the project
+/// modules the source imports (see [`crate::qmeta`]).
+pub fn parse_with_imports(
+    source: &str,
+    interfaces: &[(String, String)],
+    imports: &[ModuleMeta],
+    coverage: bool,
+) -> Result<ParsedModule, ParseError> {
+    let mut parser = Parser::new(source)?;
+    parser.coverage = coverage;
+    let mut interface_items = Vec::new();
+    for (path, text) in interfaces {
+        let in_interface = |e: ParseError| ParseError {
+            message: format!("in {path}: {}", e.message),
+            ..e
+        };
+        let mut sub = Parser::new(text).map_err(in_interface)?;
+        interface_items.extend(sub.parse_module().map_err(in_interface)?.items);
+        parser.struct_fields.extend(sub.struct_fields);
+        parser.fn_params.extend(sub.fn_params);
+        parser.fn_defaults.extend(sub.fn_defaults);
+        parser.externs.extend(sub.externs);
+        parser.extern_crates.extend(sub.extern_crates);
+        parser.crate_versions.extend(sub.crate_versions);
+        parser.collections.extend(sub.collections);
+    }
+    for meta in imports {
+        parser.struct_fields.extend(meta.struct_fields.clone());
+        parser.fn_params.extend(meta.fn_params.clone());
+        parser.fn_defaults.extend(meta.fn_defaults.clone());
+        parser.class_attrs.extend(meta.class_attrs.clone());
+    }
+    let known_fields = parser.struct_fields.clone();
+    let known_params = parser.fn_params.clone();
+    let known_defaults = parser.fn_defaults.clone();
+    let known_attrs = parser.class_attrs.clone();
+    let mut module = parser.parse_module()?;
+    let meta = ModuleMeta {
+        struct_fields: parser
+            .struct_fields
+            .iter()
+            .filter(|(name, _)| !known_fields.contains_key(*name))
+            .map(|(name, fields)| (name.clone(), fields.clone()))
+            .collect(),
+        fn_params: parser
+            .fn_params
+            .iter()
+            .filter(|(name, _)| !known_params.contains_key(*name))
+            .map(|(name, params)| (name.clone(), params.clone()))
+            .collect(),
+        fn_defaults: parser
+            .fn_defaults
+            .iter()
+            .filter(|(key, _)| !known_defaults.contains_key(*key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        class_attrs: parser
+            .class_attrs
+            .iter()
+            .filter(|(key, _)| !known_attrs.contains_key(*key))
+            .map(|(key, attr)| (key.clone(), attr.clone()))
+            .collect(),
+    };
+
+    // Interface items first; the prelude goes ahead of both when the module
+    // is compiled (see `crate::prelude`)
+    let mut new_items = interface_items;
+    new_items.extend(module.items);
+    module.items = new_items;
+    if let Some(import) = collections_use(&parser.collections, &module.items) {
+        module.items.insert(0, import);
+    }
+
+    Ok(ParsedModule {
+        module,
+        decorators: parser.decorators,
+        coverage: parser.fn_probes,
+        extern_crates: parser.extern_crates.into_iter().collect(),
+        crate_versions: parser.crate_versions,
+        unsafe_blocks: parser.unsafe_blocks,
+        meta,
+        imports: parser.imports,
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{parse, string_literal};
+    use elevate::ast::*;
+
+    fn parse_body(source: &str) -> Vec<Stmt> {
+        let module = parse(source).unwrap();
+        match &module.items[0] {
+            Item::Function(f) => f.body.statements.clone(),
+            other => panic!("Expected Function, got {:?}", other),
+        }
+    }
+
+    // ─── Star Destructuring ─────────────────────────────────────────────────
+
+    #[test]
+    fn test_star_prefix_rest() {
+        let stmts = parse_body("def test():\n    first, *rest = items\n");
+        match &stmts[0] {
+            Stmt::DestructureConst { pattern, .. } => match pattern {
+                DestructurePattern::Slice {
+                    prefix,
+                    rest,
+                    suffix,
+                } => {
+                    assert_eq!(prefix.len(), 1);
+                    assert!(matches!(&prefix[0], DestructurePattern::Name(n) if n == "first"));
+                    assert_eq!(rest.as_deref(), Some("rest"));
+                    assert!(suffix.is_empty());
+                }
+                other => panic!("Expected Slice pattern, got {:?}", other),
+            },
+            other => panic!("Expected DestructureConst, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_star_prefix_rest_suffix() {
+        let stmts = parse_body("def test():\n    a, *middle, last = items\n");
+        match &stmts[0] {
+            Stmt::DestructureConst { pattern, .. } => match pattern {
+                DestructurePattern::Slice {
+                    prefix,
+                    rest,
+                    suffix,
+                } => {
+                    assert_eq!(prefix.len(), 1);
+                    assert!(matches!(&prefix[0], DestructurePattern::Name(n) if n == "a"));
+                    assert_eq!(rest.as_deref(), Some("middle"));
+                    assert_eq!(suffix.len(), 1);
+                    assert!(matches!(&suffix[0], DestructurePattern::Name(n) if n == "last"));
+                }
+                other => panic!("Expected Slice pattern, got {:?}", other),
+            },
+            other => panic!("Expected DestructureConst, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_star_only() {
+        let stmts = parse_body("def test():\n    *everything = items\n");
+        match &stmts[0] {
+            Stmt::DestructureConst { pattern, .. } => match pattern {
+                DestructurePattern::Slice {
+                    prefix,
+                    rest,
+                    suffix,
+                } => {
+                    assert!(prefix.is_empty());
+                    assert_eq!(rest.as_deref(), Some("everything"));
+                    assert!(suffix.is_empty());
+                }
+                other => panic!("Expected Slice pattern, got {:?}", other),
+            },
+            other => panic!("Expected DestructureConst, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_star_with_ignore() {
+        let stmts = parse_body("def test():\n    _, *rest, _ = items\n");
+        match &stmts[0] {
+            Stmt::DestructureConst { pattern, .. } => match pattern {
+                DestructurePattern::Slice {
+                    prefix,
+                    rest,
+                    suffix,
+                } => {
+                    assert_eq!(prefix.len(), 1);
+                    assert!(matches!(&prefix[0], DestructurePattern::Ignore));
+                    assert_eq!(rest.as_deref(), Some("rest"));
+                    assert_eq!(suffix.len(), 1);
+                    assert!(matches!(&suffix[0], DestructurePattern::Ignore));
+                }
+                other => panic!("Expected Slice pattern, got {:?}", other),
+            },
+            other => panic!("Expected DestructureConst, got {:?}", other),
+        }
+    }
+
+    // ─── Print Built-in ─────────────────────────────────────────────────────
+
+    #[test]
+    fn test_print_no_args() {
+        let stmts = parse_body("def test():\n    print()\n");
+        match &stmts[0] {
+            Stmt::Expr(Expr::MacroCall { path, args }) => {
+                assert_eq!(path, &["println"]);
+                assert_eq!(args.len(), 1); // just the format string
+                assert!(matches!(&args[0], Expr::String(s) if s.is_empty()));
             }
-            TokenKind::Pipe => {
-                self.advance()?; // consume opening |
-                // Parse params: |x, y: i32, z| or || (empty)
-                let mut params = Vec::new();
-                while !self.check(&TokenKind::Pipe) {
-                    let name = self.expect_ident()?;
-                    let ty = if self.eat(&TokenKind::Colon)? {
-                        self.parse_type()?
-                    } else {
-                        e::Type {
-                            path: vec!["_".into()],
-                            args: vec![],
-                            trait_bounds: vec![],
-                        }
-                    };
-                    params.push(e::Param { name, ty });
-                    if !self.eat(&TokenKind::Comma)? {
-                        break;
+            other => panic!("Expected MacroCall, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_print_multiple_args() {
+        let stmts = parse_body("def test():\n    print(a, b, c)\n");
+        match &stmts[0] {
+            Stmt::Expr(Expr::MacroCall { path, args }) => {
+                assert_eq!(path, &["println"]);
+                assert_eq!(args.len(), 4); // format string + 3 args
+                assert!(matches!(&args[0], Expr::String(s) if s == "{} {} {}"));
+            }
+            other => panic!("Expected MacroCall, got {:?}", other),
+        }
+    }
+
+    // ─── Rust Escape Hatch ──────────────────────────────────────────────────
+
+    #[test]
+    fn test_rust_escape_hatch() {
+        let stmts = parse_body("def test():\n    rust(\"println!(42);\")\n");
+        match &stmts[0] {
+            Stmt::RustBlock(code) => {
+                assert_eq!(code, "println!(42);");
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_typed_rust_expression() {
+        let stmts = parse_body(
+            "def count(xs: List[i64]) -> i64:\n    return rust(\"xs.len() as i64\", returns=\"i64\")\n",
+        );
+        match &stmts[0] {
+            Stmt::Return(Some(Expr::Call { callee, args })) => {
+                assert!(args.is_empty());
+                match &**callee {
+                    Expr::Closure {
+                        params,
+                        return_type: Some(ty),
+                        body,
+                    } => {
+                        assert!(params.is_empty());
+                        assert_eq!(ty.path, vec!["i64"]);
+                        assert!(matches!(
+                            &body.statements[..],
+                            [Stmt::RustBlock(code)] if code == "(xs.len() as i64)"
+                        ));
                     }
+                    other => panic!("Expected typed closure, got {:?}", other),
                 }
-                self.expect(&TokenKind::Pipe)?; // consume closing |
-                let body_expr = self.parse_expr()?;
-                Ok(e::Expr::Closure {
-                    params,
-                    return_type: None,
-                    body: e::Block {
-                        statements: vec![e::Stmt::TailExpr(body_expr)],
-                    },
-                })
             }
-            // Python-style lambda: `lambda x, y: expr`
-            TokenKind::Keyword(Keyword::Lambda) => {
-                self.advance()?; // consume 'lambda'
-                let mut params = Vec::new();
-                // Parse params until we hit ':'
-                while !self.check(&TokenKind::Colon) {
-                    let name = self.expect_ident()?;
-                    let ty = e::Type {
-                        path: vec!["_".into()],
-                        args: vec![],
-                        trait_bounds: vec![],
-                    };
-                    params.push(e::Param { name, ty });
-                    if !self.eat(&TokenKind::Comma)? {
-                        break;
+            other => panic!("Expected return of call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_typed_rust_expression_errors() {
+        let err = parse("def f():\n    x = rust(\"1\", returns=\"List[\")\n").unwrap_err();
+        assert!(err.message.contains("is not a type"), "{}", err.message);
+        let err = parse("def f():\n    x = rust(code, returns=\"i64\")\n").unwrap_err();
+        assert!(err.message.contains("string literal"), "{}", err.message);
+    }
+
+    // ─── Static Method Calls ─────────────────────────────────────────────────
+
+    #[test]
+    fn test_static_method_call_path() {
+        // Student.new("Alice", 16) should generate Path(["Student", "new"]) as callee
+        let stmts = parse_body("def test():\n    x = Student.new()\n");
+        match &stmts[0] {
+            Stmt::Assign { value, .. } => match value {
+                Expr::Call { callee, .. } => match callee.as_ref() {
+                    Expr::Path(segments) => {
+                        assert_eq!(segments, &["Student", "new"]);
                     }
-                }
-                self.expect(&TokenKind::Colon)?; // consume ':'
-                let body_expr = self.parse_expr()?;
-                Ok(e::Expr::Closure {
-                    params,
-                    return_type: None,
-                    body: e::Block {
-                        statements: vec![e::Stmt::TailExpr(body_expr)],
-                    },
+                    other => panic!("Expected Path callee, got {:?}", other),
+                },
+                other => panic!("Expected Call, got {:?}", other),
+            },
+            other => panic!("Expected Assign, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_power_assert_captures_operands() {
+        let stmts = parse_body(
+            "def test():\n    assert a + b == expected\n    assert p.x > 0 and ok, \"bad\"\n    assert check(items)\n",
+        );
+        let assert_args = |stmt: &Stmt| match stmt {
+            Stmt::Expr(Expr::MacroCall { path, args }) if path == &["assert"] => args.clone(),
+            other => panic!("Expected assert!, got {:?}", other),
+        };
+        let shown = |args: &[Expr]| -> Vec<String> {
+            args.iter()
+                .filter_map(|arg| match arg {
+                    Expr::MacroCall { path, args } if path == &["quiche_show"] => {
+                        Some(format!("{:?}", args[0]))
+                    }
+                    _ => None,
                 })
+                .collect()
+        };
+
+        let args = assert_args(&stmts[0]);
+        assert!(matches!(
+            &args[1],
+            Expr::String(f) if f == "assertion failed: a + b == expected\n  a + b = {}\n  a = {}\n  b = {}\n  expected = {}"
+        ));
+        assert_eq!(shown(&args).len(), 4);
+
+        let args = assert_args(&stmts[1]);
+        assert!(matches!(&args[1], Expr::String(f) if f == "{}\n  p.x = {}\n  ok = {}"));
+        assert!(string_literal(&args[2]).is_some_and(|m| m == "bad"));
+        assert_eq!(shown(&args).len(), 2);
+
+        // Nothing worth showing but the call's argument
+        let args = assert_args(&stmts[2]);
+        assert!(
+            matches!(&args[1], Expr::String(f) if f == "assertion failed: check(items)\n  items = {}")
+        );
+    }
+
+    #[test]
+    fn test_assert_snapshot_calls_qtest() {
+        let stmts = parse_body("def test():\n    assert_snapshot(\"out\", x)\n");
+        match &stmts[0] {
+            Stmt::Expr(Expr::Call { callee, args }) => {
+                assert!(
+                    matches!(callee.as_ref(), Expr::Path(p) if p == &["Qtest", "assert_snapshot"])
+                );
+                assert_eq!(args.len(), 2);
             }
-            _ => Err(self.error(format!("expected expression, got {}", self.kind()))),
+            other => panic!("Expected Call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_instance_method_remains_field() {
+        // obj.method() where obj is lowercase should remain as Field access
+        let stmts = parse_body("def test():\n    x = obj.method()\n");
+        match &stmts[0] {
+            Stmt::Assign { value, .. } => match value {
+                Expr::Call { callee, .. } => match callee.as_ref() {
+                    Expr::Field { base, field } => {
+                        assert!(matches!(base.as_ref(), Expr::Path(p) if p == &["obj"]));
+                        assert_eq!(field, "method");
+                    }
+                    other => panic!("Expected Field callee, got {:?}", other),
+                },
+                other => panic!("Expected Call, got {:?}", other),
+            },
+            other => panic!("Expected Assign, got {:?}", other),
         }
     }
 
-    /// Parse a dict literal: `{key: val, ...}` or `{**spread, key: val}`
-    /// Called after `{` has been consumed.
-    fn parse_dict_literal(&mut self) -> Result<e::Expr, ParseError> {
-        // Empty dict: {}
-        if self.check(&TokenKind::RBrace) {
-            self.advance()?;
-            return Ok(e::Expr::Call {
-                callee: Box::new(e::Expr::Path(vec!["HashMap".into(), "new".into()])),
-                args: vec![],
-            });
+    #[test]
+    fn test_static_field_access_no_call() {
+        // Type.field without () should remain as Field (not merged into path)
+        let stmts = parse_body("def test():\n    x = Type.CONST\n");
+        match &stmts[0] {
+            Stmt::Assign { value, .. } => match value {
+                Expr::Field { base, field } => {
+                    assert!(matches!(base.as_ref(), Expr::Path(p) if p == &["Type"]));
+                    assert_eq!(field, "CONST");
+                }
+                other => panic!("Expected Field, got {:?}", other),
+            },
+            other => panic!("Expected Assign, got {:?}", other),
         }
+    }
 
-        // Collect entries: either (key, val) pairs or **spread
-        enum DictEntry {
-            Pair(e::Expr, e::Expr),
-            Spread(e::Expr),
-        }
-        let mut entries = Vec::new();
+    #[test]
+    fn test_type_body_methods_emit_impl_block() {
+        let module = parse(
+            "type Session:\n    name: str\n\n    def advance(self: Session, event: str):\n        return\n",
+        )
+        .unwrap();
 
-        loop {
-            if self.check(&TokenKind::RBrace) {
-                break;
+        let user_items = &module.items;
+        assert!(matches!(&user_items[0], Item::Struct(_)));
+        match &user_items[1] {
+            Item::Impl(imp) => {
+                assert_eq!(imp.target, "Session");
+                assert_eq!(imp.methods.len(), 1);
+                assert_eq!(imp.methods[0].name, "advance");
             }
+            other => panic!("Expected Impl item, got {:?}", other),
+        }
+    }
 
-            // Check for **spread
-            if self.check(&TokenKind::DoubleStar) {
-                self.advance()?;
-                let spread_expr = self.parse_expr()?;
-                entries.push(DictEntry::Spread(spread_expr));
-            } else {
-                // key: value
-                let key = self.parse_expr()?;
-                self.expect(&TokenKind::Colon)?;
-                let value = self.parse_expr()?;
+    // ─── Enum Definitions ────────────────────────────────────────────────────
 
-                // Dict comprehension: {key: val for var in iter}
-                if self.check(&TokenKind::Keyword(Keyword::For)) {
-                    self.advance()?; // consume 'for'
-                    let var = self.expect_ident()?;
-                    self.expect(&TokenKind::Keyword(Keyword::In))?;
-                    let iter_expr = self.parse_expr()?;
-                    // Optional filter: if cond
-                    let filter = if self.eat(&TokenKind::Keyword(Keyword::If))? {
-                        Some(self.parse_expr()?)
-                    } else {
-                        None
-                    };
-                    self.expect(&TokenKind::RBrace)?;
-                    return Ok(Self::build_dict_comprehension(
-                        var, iter_expr, key, value, filter,
-                    ));
-                }
+    // ─── Decorators ──────────────────────────────────────────────────────────
 
-                entries.push(DictEntry::Pair(key, value));
-            }
+    #[test]
+    fn test_decorators_recorded_by_item_name() {
+        let parsed =
+            super::parse_decorated("@fixture(scope=\"module\")\ndef db() -> int:\n    return 1\n")
+                .unwrap();
+        assert!(matches!(&parsed.module.items[0], Item::Function(f) if f.name == "db"));
+        let decos = &parsed.decorators["db"];
+        assert_eq!(decos.len(), 1);
+        assert_eq!(decos[0].name, "fixture");
+        assert_eq!(
+            decos[0]
+                .kwarg("scope")
+                .and_then(super::string_literal)
+                .as_deref(),
+            Some("module")
+        );
+    }
 
-            if !self.eat(&TokenKind::Comma)? {
-                break;
+    #[test]
+    fn test_fixture_yield_splits_teardown() {
+        let module =
+            parse("@fixture\ndef num() -> int:\n    n = 1\n    yield n\n    print(n)\n").unwrap();
+        let user_items = &module.items;
+        match (&user_items[0], &user_items[1]) {
+            (Item::Function(setup), Item::Function(teardown)) => {
+                assert_eq!(setup.name, "num");
+                assert!(matches!(
+                    setup.body.statements.last(),
+                    Some(Stmt::Return(Some(Expr::Path(p)))) if p == &["n"]
+                ));
+                assert_eq!(teardown.name, "__teardown_num");
+                assert_eq!(teardown.params[0].name, "n");
+                assert_eq!(teardown.body.statements.len(), 1);
             }
+            other => panic!("Expected setup + teardown functions, got {:?}", other),
         }
-        self.expect(&TokenKind::RBrace)?;
-
-        // Check if there are any spreads
-        let has_spread = entries.iter().any(|e| matches!(e, DictEntry::Spread(_)));
+    }
 
-        if !has_spread {
-            // Simple dict: HashMap::from([(k1, v1), (k2, v2)])
-            let pairs: Vec<e::Expr> = entries
-                .into_iter()
-                .map(|e| match e {
-                    DictEntry::Pair(k, v) => e::Expr::Tuple(vec![k, v]),
-                    _ => unreachable!(),
-                })
-                .collect();
-            Ok(e::Expr::Call {
-                callee: Box::new(e::Expr::Path(vec!["HashMap".into(), "from".into()])),
-                args: vec![e::Expr::Array(pairs)],
-            })
-        } else {
-            // Dict with spread: emit as RustBlock
-            // { let mut __m = HashMap::new(); __m.extend(spread); __m.insert(k, v); __m }
-            let mut code = String::from("{ let mut __m = std::collections::HashMap::new(); ");
-            for entry in entries {
-                match entry {
-                    DictEntry::Spread(expr) => {
-                        // We need to emit the spread expr as Rust — for simple paths
-                        if let e::Expr::Path(ref p) = expr {
-                            code.push_str(&format!(
-                                "__m.extend({}.iter().map(|(k, v)| (k.clone(), v.clone()))); ",
-                                p.join("::")
-                            ));
-                        }
-                    }
-                    DictEntry::Pair(key, value) => {
-                        // For string keys and simple values
-                        let key_str = Self::expr_to_rust_string(&key);
-                        let val_str = Self::expr_to_rust_string(&value);
-                        code.push_str(&format!("__m.insert({}, {}); ", key_str, val_str));
-                    }
-                }
+    #[test]
+    fn test_instrumented_probes_each_statement() {
+        let parsed = super::parse_instrumented("def f():\n    x = 1\n    print(x)\n").unwrap();
+        let body = match &parsed.module.items[0] {
+            Item::Function(f) => &f.body.statements,
+            other => panic!("Expected Function, got {:?}", other),
+        };
+        assert_eq!(body.len(), 4);
+        match &body[0] {
+            Stmt::Expr(Expr::Call { callee, args }) => {
+                assert!(matches!(callee.as_ref(), Expr::Path(p) if p == &["Qtest", "hit"]));
+                assert!(matches!(args[0], Expr::Int(2)));
             }
-            code.push_str("__m }");
-            Ok(e::Expr::MacroCall {
-                path: vec!["__rust_expr__".into()],
-                args: vec![e::Expr::String(code)],
-            })
+            other => panic!("Expected probe call, got {:?}", other),
         }
+        assert_eq!(parsed.coverage["f"], vec![2, 3]);
     }
 
-    /// Best-effort conversion of an Expr to a Rust string for RustBlock emission.
-    fn expr_to_rust_string(expr: &e::Expr) -> String {
-        match expr {
-            e::Expr::String(s) => format!("String::from(\"{}\")", s),
-            e::Expr::Int(n) => n.to_string(),
-            e::Expr::Bool(b) => b.to_string(),
-            e::Expr::Path(p) => p.join("::"),
-            e::Expr::Binary { op, left, right } => {
-                let op_str = match op {
-                    e::BinaryOp::Add => "+",
-                    e::BinaryOp::Sub => "-",
-                    e::BinaryOp::Mul => "*",
-                    e::BinaryOp::Div => "/",
-                    e::BinaryOp::Rem => "%",
-                    e::BinaryOp::And => "&&",
-                    e::BinaryOp::Or => "||",
-                    e::BinaryOp::Eq => "==",
-                    e::BinaryOp::Ne => "!=",
-                    e::BinaryOp::Lt => "<",
-                    e::BinaryOp::Le => "<=",
-                    e::BinaryOp::Gt => ">",
-                    e::BinaryOp::Ge => ">=",
-                };
-                format!(
-                    "({} {} {})",
-                    Self::expr_to_rust_string(left),
-                    op_str,
-                    Self::expr_to_rust_string(right)
-                )
-            }
-            e::Expr::Unary { op, expr: inner } => {
-                let op_str = match op {
-                    e::UnaryOp::Not => "!",
-                    e::UnaryOp::Neg => "-",
-                };
-                format!("({}{})", op_str, Self::expr_to_rust_string(inner))
-            }
-            e::Expr::Field { base, field } => {
-                format!("{}.{}", Self::expr_to_rust_string(base), field)
-            }
-            e::Expr::Tuple(elems) => {
-                let parts: Vec<String> = elems.iter().map(Self::expr_to_rust_string).collect();
-                format!("({})", parts.join(", "))
-            }
-            e::Expr::Call { callee, args } => {
-                let args_str: Vec<String> = args.iter().map(Self::expr_to_rust_string).collect();
-                format!(
-                    "{}({})",
-                    Self::expr_to_rust_string(callee),
-                    args_str.join(", ")
-                )
-            }
-            e::Expr::Index { base, index } => {
-                format!(
-                    "{}[{}]",
-                    Self::expr_to_rust_string(base),
-                    Self::expr_to_rust_string(index)
-                )
-            }
-            _ => "todo!()".into(),
+    #[test]
+    fn test_fixture_nested_yield_is_error() {
+        for body in [
+            "    if True:\n        yield 1\n",
+            "    with open(\"f\") as f:\n        yield 1\n",
+        ] {
+            let err = parse(&format!("@fixture\ndef num() -> int:\n{body}")).unwrap_err();
+            assert!(
+                err.message.contains("yields inside a nested block"),
+                "{}",
+                err.message
+            );
         }
     }
 
-    /// Build a list comprehension: [expr for var in iter if cond]
-    /// Desugars to IIFE: (|| { let __v = Vec::new(); for var in iter { [if cond {] __v.push(expr); [}] } __v })()
-    fn build_list_comprehension(
-        var: String,
-        iter_expr: e::Expr,
-        map_expr: e::Expr,
-        filter: Option<e::Expr>,
-    ) -> e::Expr {
-        // Build the push statement: __v.push(map_expr)
-        let push_stmt = e::Stmt::Expr(e::Expr::Call {
-            callee: Box::new(e::Expr::Field {
-                base: Box::new(e::Expr::Path(vec!["__v".into()])),
-                field: "push".into(),
-            }),
-            args: vec![map_expr],
-        });
-
-        // For body: either just push, or if cond { push }
-        let for_body = if let Some(cond) = filter {
-            e::Block {
-                statements: vec![e::Stmt::If {
-                    condition: cond,
-                    then_block: e::Block {
-                        statements: vec![push_stmt],
-                    },
-                    else_block: None,
-                }],
-            }
-        } else {
-            e::Block {
-                statements: vec![push_stmt],
-            }
-        };
-
-        // Build IIFE body: let __v = Vec::new(); for var in iter { ... }; __v
-        let iife_body = e::Block {
-            statements: vec![
-                // let __v: Vec<_> = Vec::new()
-                e::Stmt::Const(e::ConstDef {
-                    visibility: e::Visibility::Private,
-                    name: "__v".into(),
-                    ty: Some(e::Type {
-                        path: vec!["Vec".into()],
-                        args: vec![e::Type {
-                            path: vec!["_".into()],
-                            args: vec![],
-                            trait_bounds: vec![],
-                        }],
-                        trait_bounds: vec![],
-                    }),
-                    value: e::Expr::Call {
-                        callee: Box::new(e::Expr::Path(vec!["Vec".into(), "new".into()])),
-                        args: vec![],
-                    },
-                    is_const: false,
-                    span: None,
-                }),
-                // for var in iter { ... }
-                e::Stmt::For {
-                    binding: e::DestructurePattern::Name(var),
-                    iter: iter_expr,
-                    body: for_body,
+    #[test]
+    fn test_parse_imports_reads_statements_only() {
+        let imports = super::parse_imports(
+            "import net.http as http\nfrom util import clamp as limit, helper\n\ndef f():\n    s = \"import fake\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            imports,
+            vec![
+                super::Import {
+                    module: "net.http".into(),
+                    names: vec![],
                 },
-                // __v (tail expression — return the vec)
-                e::Stmt::TailExpr(e::Expr::Path(vec!["__v".into()])),
-            ],
-        };
+                super::Import {
+                    module: "util".into(),
+                    names: vec![
+                        ("clamp".into(), "limit".into()),
+                        ("helper".into(), "helper".into())
+                    ],
+                },
+            ]
+        );
+    }
 
-        // Wrap in IIFE: (|| { ... })()
-        e::Expr::Call {
-            callee: Box::new(e::Expr::Closure {
-                params: vec![],
-                return_type: None,
-                body: iife_body,
-            }),
-            args: vec![],
-        }
+    #[test]
+    fn test_yield_outside_fixture_is_error() {
+        let err = parse("def gen() -> int:\n    yield 1\n").unwrap_err();
+        assert!(err.message.contains("@fixture"), "{}", err.message);
     }
 
-    /// Build a dict comprehension: {key: val for var in iter if cond}
-    /// Desugars to IIFE: build Vec<(K,V)> with push, then .into_iter().collect()
-    fn build_dict_comprehension(
-        var: String,
-        iter_expr: e::Expr,
-        key_expr: e::Expr,
-        val_expr: e::Expr,
-        filter: Option<e::Expr>,
-    ) -> e::Expr {
-        // Build: __v.push((key, val))
-        let push_stmt = e::Stmt::Expr(e::Expr::Call {
-            callee: Box::new(e::Expr::Field {
-                base: Box::new(e::Expr::Path(vec!["__v".into()])),
-                field: "push".into(),
-            }),
-            args: vec![e::Expr::Tuple(vec![key_expr, val_expr])],
-        });
+    #[test]
+    fn test_mem_decorator_is_rejected() {
+        let err = parse(
+            "type Node:\n    value: i64\n\n@mem(region)\ndef build() -> i64:\n    return 1\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.message.contains("@mem allocation strategies"),
+            "{}",
+            err.message
+        );
+        assert_eq!((err.line, err.column), (4, 1));
+    }
 
-        let for_body = if let Some(cond) = filter {
-            e::Block {
-                statements: vec![e::Stmt::If {
-                    condition: cond,
-                    then_block: e::Block {
-                        statements: vec![push_stmt],
-                    },
-                    else_block: None,
-                }],
-            }
-        } else {
-            e::Block {
-                statements: vec![push_stmt],
+    // ─── @extern ────────────────────────────────────────────────────────────
+
+    #[test]
+    fn test_extern_crate_emits_use_and_records_dependency() {
+        let parsed = super::parse_decorated(
+            "@extern(crate=\"serde_json\", version=\"1.0\")\ndef from_str[T](s: Str) -> Result[T, Error]:\n    pass\n",
+        )
+        .unwrap();
+        assert_eq!(parsed.extern_crates, vec!["serde_json"]);
+        assert_eq!(parsed.crate_versions["serde_json"], "1.0");
+        match &parsed.module.items[0] {
+            Item::RustUse(RustUse {
+                tree: UseTree::Path { segment, next },
+                ..
+            }) => {
+                assert_eq!(segment, "serde_json");
+                assert!(matches!(&**next, UseTree::Name(n) if n == "from_str"));
             }
-        };
+            other => panic!("Expected RustUse, got {:?}", other),
+        }
+    }
 
-        // __v.into_iter().collect()
-        let collect_expr = e::Expr::Call {
-            callee: Box::new(e::Expr::Field {
-                base: Box::new(e::Expr::Call {
-                    callee: Box::new(e::Expr::Field {
-                        base: Box::new(e::Expr::Path(vec!["__v".into()])),
-                        field: "into_iter".into(),
-                    }),
-                    args: vec![],
-                }),
-                field: "collect".into(),
-            }),
-            args: vec![],
-        };
+    #[test]
+    fn test_extern_crate_needs_one_version() {
+        let err = parse("@extern(crate=\"rand\")\ndef random() -> f64:\n    pass\n").unwrap_err();
+        assert!(err.message.contains("needs the crate's version"), "{err}");
+        let err = parse(
+            "@extern(crate=\"rand\", version=\"0.8\")\ndef random() -> f64:\n    pass\n\n@extern(path=\"rand::thread_rng\", version=\"0.9\")\ndef thread_rng() -> ThreadRng:\n    pass\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.message.contains("earlier @extern asks for \"0.8\""),
+            "{err}"
+        );
+        let err = parse(
+            "@extern(path=\"std::fs::read_dir\", version=\"1\")\ndef read_dir(path: Str) -> ReadDir:\n    pass\n",
+        )
+        .unwrap_err();
+        assert!(err.message.contains("takes no version="), "{err}");
 
-        let iife_body = e::Block {
-            statements: vec![
-                // let __v: Vec<_> = Vec::new()
-                e::Stmt::Const(e::ConstDef {
-                    visibility: e::Visibility::Private,
-                    name: "__v".into(),
-                    ty: Some(e::Type {
-                        path: vec!["Vec".into()],
-                        args: vec![e::Type {
-                            path: vec!["_".into()],
-                            args: vec![],
-                            trait_bounds: vec![],
-                        }],
-                        trait_bounds: vec![],
-                    }),
-                    value: e::Expr::Call {
-                        callee: Box::new(e::Expr::Path(vec!["Vec".into(), "new".into()])),
-                        args: vec![],
-                    },
-                    is_const: false,
-                    span: None,
-                }),
-                // for var in iter { __v.push((key, val)); }
-                e::Stmt::For {
-                    binding: e::DestructurePattern::Name(var),
-                    iter: iter_expr,
-                    body: for_body,
-                },
-                // __v.into_iter().collect()
-                e::Stmt::TailExpr(collect_expr),
-            ],
-        };
+        let parsed = super::parse_decorated(
+            "@extern(path=\"rand::random\")\ndef random() -> f64:\n    pass\n",
+        )
+        .unwrap();
+        assert_eq!(parsed.extern_crates, vec!["rand"]);
+        assert!(parsed.crate_versions.is_empty());
+    }
 
-        e::Expr::Call {
-            callee: Box::new(e::Expr::Closure {
-                params: vec![],
-                return_type: None,
-                body: iife_body,
-            }),
-            args: vec![],
-        }
+    #[test]
+    fn test_extern_path_alias_and_std_not_recorded() {
+        let parsed = super::parse_decorated(
+            "@extern(path=\"std::fs::read_to_string\")\ndef slurp(path: Str) -> Str:\n    pass\n",
+        )
+        .unwrap();
+        assert!(parsed.extern_crates.is_empty());
+        assert!(matches!(
+            &parsed.module.items[0],
+            Item::RustBlock(code) if code == "use std::fs::read_to_string as slurp;"
+        ));
     }
 
-    /// Parse call arguments, detecting keyword args (name=expr).
-    fn parse_call_args_with_kwargs(&mut self) -> Result<Vec<CallArg>, ParseError> {
-        let mut args = Vec::new();
-        while !self.check(&TokenKind::RParen) {
-            // Check for keyword arg: Ident followed by '='
-            let is_kwarg = if let TokenKind::Ident(_) = self.kind() {
-                matches!(self.peek()?.kind, TokenKind::Eq)
-            } else {
-                false
-            };
-            if is_kwarg {
-                let name = self.expect_ident()?;
-                self.expect(&TokenKind::Eq)?;
-                let value = self.parse_expr()?;
-                args.push(CallArg::Keyword(name, value));
-            } else {
-                let expr = self.parse_expr()?;
-                args.push(CallArg::Positional(expr));
+    #[test]
+    fn test_extern_call_arity_checked() {
+        let err = parse(
+            "@extern(crate=\"rand\", version=\"0.8\")\ndef random() -> f64:\n    pass\n\ndef main():\n    x = random(1)\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.message
+                .contains("extern 'random' takes 0 argument(s) but 1 were given"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_extern_c_emits_declaration_and_safe_wrapper() {
+        let parsed = super::parse_decorated(
+            "@extern(\"C\", lib=\"m\")\ndef cos(x: f64) -> f64:\n    pass\n\n@extern(\"C\", symbol=\"strlen\")\ndef c_len(s: Str) -> usize:\n    pass\n",
+        )
+        .unwrap();
+        assert!(parsed.extern_crates.is_empty());
+        match &parsed.module.items[0] {
+            Item::RustBlock(code) => {
+                assert!(code.starts_with("#[link(name = \"m\")]\nunsafe extern \"C\" {"));
+                assert!(code.contains("fn __quiche_c_cos(x: f64) -> f64;"));
+                assert!(code.contains("pub fn cos(x: f64) -> f64 {"));
             }
-            if !self.eat(&TokenKind::Comma)? {
-                break;
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
+        match &parsed.module.items[1] {
+            Item::RustBlock(code) => {
+                assert!(code.contains("#[link_name = \"strlen\"]"));
+                assert!(code.contains("fn __quiche_c_c_len(s: *const std::ffi::c_char) -> usize;"));
+                assert!(code.contains("let s = CLib::c_string(&s);"));
+                assert!(code.contains("unsafe { __quiche_c_c_len(s.as_ptr()) }"));
             }
+            other => panic!("Expected RustBlock, got {:?}", other),
         }
-        Ok(args)
     }
-}
-
-// ─────────────────────────────────────────────────────────────────────────────
-// Public API
-// ─────────────────────────────────────────────────────────────────────────────
 
-/// Quiche primitive type prelude — imports types from `quiche-lib` crate.
-///
-/// Two RustBlocks:
-/// 1. `use quiche_lib::*;` — actual import
-/// 2. Stub fn `str()` — so Elevate's `extract_rust_block_function_names` resolves it
-fn quiche_prelude() -> Vec<e::Item> {
-    vec![
-        e::Item::RustBlock("use quiche_lib::*;".into()),
-        e::Item::RustBlock(
-            "pub fn str<T: std::fmt::Display>(x: T) -> Str { quiche_lib::str(x) }".into(),
-        ),
-    ]
-}
+    #[test]
+    fn test_extern_c_rejects_unmappable_types() {
+        let err = parse("@extern(\"C\")\ndef f(xs: List[i64]) -> i64:\n    pass\n").unwrap_err();
+        assert!(
+            err.message.contains("parameter 'xs' has type 'List'"),
+            "{}",
+            err.message
+        );
+    }
 
-pub fn parse(source: &str) -> Result<e::Module, ParseError> {
-    let mut parser = Parser::new(source)?;
-    let mut module = parser.parse_module()?;
+    #[test]
+    fn test_export_keeps_function_and_adds_c_wrapper() {
+        let parsed = super::parse_decorated(
+            "@export\ndef total(xs: List[f64], scale: f64) -> f64:\n    return 0.0\n\n@export(name=\"q_greet\")\ndef greet(name: Str) -> Str:\n    return name\n",
+        )
+        .unwrap();
+        let items = &parsed.module.items;
+        assert!(matches!(&items[0], Item::Function(f) if f.name == "total"));
+        match &items[1] {
+            Item::RustBlock(code) => {
+                assert!(code.starts_with("#[unsafe(export_name = \"total\")]"));
+                assert!(code.contains(
+                    "fn __quiche_export_total(xs: *const f64, xs_len: usize, scale: f64) -> f64"
+                ));
+                assert!(code.contains("std::slice::from_raw_parts(xs, xs_len)"));
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
+        match &items[3] {
+            Item::RustBlock(code) => {
+                assert!(code.starts_with("#[unsafe(export_name = \"q_greet\")]"));
+                assert!(
+                    code.contains("CLib::into_c_str(greet(unsafe { CLib::from_c_str(name) }))")
+                );
+                assert!(code.contains("pub extern \"C\" fn quiche_free_string"));
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
+        assert!(parsed.decorators.contains_key("greet"));
+    }
 
-    // Inject Quiche primitive type prelude at the top
-    let mut new_items = quiche_prelude();
-    new_items.extend(module.items);
-    module.items = new_items;
+    #[test]
+    fn test_py_import_calls_python_through_values() {
+        let parsed = super::parse_decorated(
+            "@py_import(\"numpy\")\ndef mean(xs: List[f64]) -> f64:\n    pass\n\n@py_import(\"os\", name=\"getcwd\")\ndef cwd() -> Str:\n    pass\n",
+        )
+        .unwrap();
+        let items = &parsed.module.items;
+        match &items[0] {
+            Item::RustBlock(code) => {
+                assert!(code.starts_with(
+                    "pub fn mean(xs: List<f64>) -> f64 {\n    FromValue::from_value(__quiche_py::call(\"numpy\", \"mean\", vec![Value::from(xs)]))\n}"
+                ));
+                assert!(code.contains("mod __quiche_py {"));
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
+        match &items[1] {
+            Item::RustBlock(code) => {
+                assert!(code.contains("__quiche_py::call(\"os\", \"getcwd\", vec![])"));
+                assert!(!code.contains("mod __quiche_py"));
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
+        assert_eq!(parsed.extern_crates, vec!["pyo3"]);
 
-    Ok(module)
-}
+        let err = parse("@py_import\ndef f() -> i64:\n    pass\n").unwrap_err();
+        assert!(
+            err.message.contains("takes the Python module name"),
+            "{}",
+            err.message
+        );
+    }
 
-#[cfg(test)]
-#[allow(clippy::unwrap_used)]
-mod tests {
-    use super::parse;
-    use elevate::ast::*;
+    #[test]
+    fn test_wasm_export_wraps_functions_and_classes() {
+        let parsed = super::parse_decorated(
+            "@wasm_export\ntype Point:\n    x: f64\n    label: Str\n\n@wasm_export\ndef scale(p: Point, xs: List[f64], name: Str) -> Str:\n    return name\n",
+        )
+        .unwrap();
+        let blocks: Vec<&String> = parsed
+            .module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::RustBlock(code) => Some(code),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].contains("pub struct __QuicheWasmPoint(Point);"));
+        assert!(blocks[0].contains(
+            "pub fn new(x: f64, label: String) -> __QuicheWasmPoint {\n        __QuicheWasmPoint(Point { x: x, label: Str::from(label) })"
+        ));
+        assert!(
+            blocks[0].contains(
+                "pub fn label(&self) -> String {\n        self.0.label.clone().to_string()"
+            )
+        );
+        assert!(blocks[1].starts_with(
+            "#[cfg(target_arch = \"wasm32\")]\n#[wasm_bindgen::prelude::wasm_bindgen(js_name = scale)]"
+        ));
+        assert!(blocks[1].contains(
+            "pub fn __quiche_wasm_scale(p: __QuicheWasmPoint, xs: Vec<f64>, name: String) -> String {\n    scale(p.0, List::from(xs), Str::from(name)).to_string()"
+        ));
+        assert_eq!(parsed.extern_crates, vec!["wasm_bindgen"]);
+
+        let err = parse("@wasm_export\ndef f(d: Dict[Str, i64]):\n    pass\n").unwrap_err();
+        assert!(
+            err.message.contains("cannot cross to JavaScript"),
+            "{}",
+            err.message
+        );
+    }
 
-    fn parse_body(source: &str) -> Vec<Stmt> {
+    #[test]
+    fn test_help_and_doc_read_function_docstrings() {
+        let source = "def main():\n    help(area)\n    d = area.__doc__\n\ndef area(w: f64, h: f64) -> f64:\n    \"\"\"Area of a rectangle.\n\n    Both sides in metres.\n    \"\"\"\n    return w * h\n";
         let module = parse(source).unwrap();
-        // Skip Quiche prelude items (2 RustBlocks: use + str stub)
-        let user_items = &module.items[2..];
-        match &user_items[0] {
-            Item::Function(f) => f.body.statements.clone(),
+        let items = &module.items;
+        match &items[0] {
+            Item::Function(f) => {
+                assert!(matches!(
+                    &f.body.statements[0],
+                    Stmt::RustBlock(code) if code == "println!(\"{}\", __QUICHE_HELP_area);"
+                ));
+            }
             other => panic!("Expected Function, got {:?}", other),
         }
-    }
+        match items.last() {
+            Some(Item::RustBlock(code)) => {
+                assert!(code.contains(
+                    "const __QUICHE_HELP_area: &str = \"area(w: f64, h: f64) -> f64\\n    Area of a rectangle.\\n\\n    Both sides in metres.\";"
+                ));
+                assert!(code.contains(
+                    "const __QUICHE_DOC_area: &str = \"Area of a rectangle.\\n\\nBoth sides in metres.\";"
+                ));
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
+        }
 
-    // ─── Star Destructuring ─────────────────────────────────────────────────
+        let err = parse("def main():\n    help(missing)\n").unwrap_err();
+        assert!(
+            err.message.contains("'missing' is not a function"),
+            "{}",
+            err.message
+        );
+    }
 
     #[test]
-    fn test_star_prefix_rest() {
-        let stmts = parse_body("def test():\n    first, *rest = items\n");
+    fn test_any_annotation_boxes_into_value() {
+        let stmts = parse_body("def main():\n    x: Any = 3\n");
         match &stmts[0] {
-            Stmt::DestructureConst { pattern, .. } => match pattern {
-                DestructurePattern::Slice {
-                    prefix,
-                    rest,
-                    suffix,
-                } => {
-                    assert_eq!(prefix.len(), 1);
-                    assert!(matches!(&prefix[0], DestructurePattern::Name(n) if n == "first"));
-                    assert_eq!(rest.as_deref(), Some("rest"));
-                    assert!(suffix.is_empty());
-                }
-                other => panic!("Expected Slice pattern, got {:?}", other),
-            },
-            other => panic!("Expected DestructureConst, got {:?}", other),
+            Stmt::Const(c) => {
+                assert_eq!(c.ty.as_ref().unwrap().path, vec!["Value"]);
+                assert!(matches!(
+                    &c.value,
+                    Expr::Call { callee, args }
+                        if matches!(&**callee, Expr::Path(p) if p == &["Value", "from"])
+                            && matches!(args.as_slice(), [Expr::Int(3)])
+                ));
+            }
+            other => panic!("Expected Const, got {:?}", other),
         }
     }
 
     #[test]
-    fn test_star_prefix_rest_suffix() {
-        let stmts = parse_body("def test():\n    a, *middle, last = items\n");
-        match &stmts[0] {
-            Stmt::DestructureConst { pattern, .. } => match pattern {
-                DestructurePattern::Slice {
-                    prefix,
-                    rest,
-                    suffix,
-                } => {
-                    assert_eq!(prefix.len(), 1);
-                    assert!(matches!(&prefix[0], DestructurePattern::Name(n) if n == "a"));
-                    assert_eq!(rest.as_deref(), Some("middle"));
-                    assert_eq!(suffix.len(), 1);
-                    assert!(matches!(&suffix[0], DestructurePattern::Name(n) if n == "last"));
+    fn test_empty_collections_take_type_from_later_use() {
+        let stmts = parse_body(
+            "def main(name: Str):\n    xs = []\n    if True:\n        xs.push(3)\n    d = {}\n    d[name] = True\n    ys = []\n    ys.push(f())\n",
+        );
+        let annotation = |stmt: &Stmt| match stmt {
+            Stmt::Const(c) => Some(super::quiche_type(c.ty.as_ref().unwrap())),
+            _ => None,
+        };
+        assert_eq!(annotation(&stmts[0]).as_deref(), Some("List[i64]"));
+        assert_eq!(annotation(&stmts[2]).as_deref(), Some("Dict[Str, bool]"));
+        // Nothing to read a type from: left for the type checker.
+        assert!(matches!(&stmts[4], Stmt::Assign { .. }));
+    }
+
+    /// The named nodes of `module` that carry a span, with the source text
+    /// each span covers: items, and declarations in function bodies and in
+    /// the closures lowered constructs (comprehensions) expand to.
+    fn spanned_texts<'a>(module: &Module, source: &'a str) -> Vec<(String, &'a str)> {
+        fn visit_block<'a>(block: &Block, source: &'a str, out: &mut Vec<(String, &'a str)>) {
+            for stmt in &block.statements {
+                match stmt {
+                    Stmt::Const(def) => {
+                        let span = def
+                            .span
+                            .unwrap_or_else(|| panic!("{} has no span", def.name));
+                        out.push((def.name.clone(), &source[span.start..span.end]));
+                        visit_expr(&def.value, source, out);
+                    }
+                    Stmt::Assign { value, .. } | Stmt::Expr(value) => {
+                        visit_expr(value, source, out)
+                    }
+                    Stmt::If {
+                        then_block,
+                        else_block,
+                        ..
+                    } => {
+                        visit_block(then_block, source, out);
+                        if let Some(else_block) = else_block {
+                            visit_block(else_block, source, out);
+                        }
+                    }
+                    Stmt::While { body, .. } | Stmt::For { body, .. } => {
+                        visit_block(body, source, out)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        fn visit_expr<'a>(expr: &Expr, source: &'a str, out: &mut Vec<(String, &'a str)>) {
+            match expr {
+                Expr::Call { callee, args } => {
+                    visit_expr(callee, source, out);
+                    for arg in args {
+                        visit_expr(arg, source, out);
+                    }
+                }
+                Expr::Closure { body, .. } => visit_block(body, source, out),
+                _ => {}
+            }
+        }
+        let mut out = Vec::new();
+        let function = |f: &FunctionDef, out: &mut Vec<(String, &'a str)>| {
+            let span = f.span.unwrap_or_else(|| panic!("{} has no span", f.name));
+            out.push((f.name.clone(), &source[span.start..span.end]));
+            visit_block(&f.body, source, out);
+        };
+        for item in &module.items {
+            match item {
+                Item::Function(f) => function(f, &mut out),
+                Item::Impl(imp) => {
+                    for method in &imp.methods {
+                        function(method, &mut out);
+                    }
                 }
-                other => panic!("Expected Slice pattern, got {:?}", other),
-            },
-            other => panic!("Expected DestructureConst, got {:?}", other),
+                Item::Const(def) => {
+                    let span = def
+                        .span
+                        .unwrap_or_else(|| panic!("{} has no span", def.name));
+                    out.push((def.name.clone(), &source[span.start..span.end]));
+                }
+                Item::RustUse(import) => {
+                    let span = import.span.expect("import has no span");
+                    out.push(("use".to_string(), &source[span.start..span.end]));
+                }
+                _ => {}
+            }
         }
+        out
     }
 
     #[test]
-    fn test_star_only() {
-        let stmts = parse_body("def test():\n    *everything = items\n");
-        match &stmts[0] {
-            Stmt::DestructureConst { pattern, .. } => match pattern {
-                DestructurePattern::Slice {
-                    prefix,
-                    rest,
-                    suffix,
-                } => {
-                    assert!(prefix.is_empty());
-                    assert_eq!(rest.as_deref(), Some("everything"));
-                    assert!(suffix.is_empty());
-                }
-                other => panic!("Expected Slice pattern, got {:?}", other),
-            },
-            other => panic!("Expected DestructureConst, got {:?}", other),
-        }
+    fn test_desugared_nodes_keep_source_spans() {
+        let source = "from std.collections import HashMap, HashSet\n\n\
+                      type Circle:\n    radius: f64\n    PI: f64 = 3.14\n    mut count: i64 = 0\n\n\
+                      def parse(s: Str) -> i64 | Str:\n    if s == \"\":\n        return \"empty\"\n    return s.len()  # length\n\n\
+                      def main():\n    \
+                      xs = []\n    \
+                      xs.push(3)\n    \
+                      squares = [x * x for x in xs]\n    \
+                      index = {x: x * 2 for x in xs}\n    \
+                      r = parse(\"x\")\n    \
+                      if isinstance( r, i64 ):\n        print(r)\n";
+        let module = parse(source).unwrap();
+        let texts = spanned_texts(&module, source);
+        let text = |name: &str, nth: usize| {
+            texts
+                .iter()
+                .filter(|(n, _)| n == name)
+                .nth(nth)
+                .map(|(_, text)| *text)
+                .unwrap_or_else(|| panic!("no span #{nth} for {name}: {texts:?}"))
+        };
+        assert_eq!(text("use", 0), "HashMap");
+        assert_eq!(text("use", 1), "HashSet");
+        assert_eq!(text("__CIRCLE_PI", 0), "PI: f64 = 3.14");
+        assert_eq!(text("__circle_count_init", 0), "mut count: i64 = 0");
+        assert!(text("parse", 0).starts_with("def parse(s: Str)"));
+        assert!(text("parse", 0).ends_with("return s.len()"));
+        assert!(text("main", 0).ends_with("print(r)"));
+        assert_eq!(text("xs", 0), "xs = []");
+        assert_eq!(text("__v", 0), "[x * x for x in xs]");
+        assert_eq!(text("__v", 1), "{x: x * 2 for x in xs}");
+        assert_eq!(text("r", 0), "isinstance( r, i64 )");
+
+        let source = "x = 1\nprint(x)  # done\n";
+        let module = parse(source).unwrap();
+        assert_eq!(
+            spanned_texts(&module, source),
+            [("main".to_string(), "x = 1\nprint(x)")]
+        );
     }
 
     #[test]
-    fn test_star_with_ignore() {
-        let stmts = parse_body("def test():\n    _, *rest, _ = items\n");
-        match &stmts[0] {
-            Stmt::DestructureConst { pattern, .. } => match pattern {
-                DestructurePattern::Slice {
-                    prefix,
-                    rest,
-                    suffix,
-                } => {
-                    assert_eq!(prefix.len(), 1);
-                    assert!(matches!(&prefix[0], DestructurePattern::Ignore));
-                    assert_eq!(rest.as_deref(), Some("rest"));
-                    assert_eq!(suffix.len(), 1);
-                    assert!(matches!(&suffix[0], DestructurePattern::Ignore));
+    fn test_block_bodied_fn_expressions() {
+        let stmts = parse_body(
+            "def main(items: List[i64]):\n    ys = items.map(fn(x):\n        y = x * 2\n        y + 1\n    )\n    key = fn(p: i64) -> i64: -p\n    print(ys)\n",
+        );
+        assert_eq!(stmts.len(), 3);
+        let Stmt::Assign { value, .. } = &stmts[0] else {
+            panic!("Expected Assign, got {:?}", stmts[0]);
+        };
+        match value {
+            Expr::Call { args, .. } => match &args[0] {
+                Expr::Closure { params, body, .. } => {
+                    assert_eq!(params[0].name, "x");
+                    assert_eq!(body.statements.len(), 2);
+                    assert!(matches!(body.statements[1], Stmt::TailExpr(_)));
                 }
-                other => panic!("Expected Slice pattern, got {:?}", other),
+                other => panic!("Expected Closure, got {:?}", other),
             },
-            other => panic!("Expected DestructureConst, got {:?}", other),
+            other => panic!("Expected Call, got {:?}", other),
         }
+        assert!(matches!(
+            &stmts[1],
+            Stmt::Assign {
+                value: Expr::Closure {
+                    return_type: Some(_),
+                    ..
+                },
+                ..
+            }
+        ));
     }
 
-    // ─── Print Built-in ─────────────────────────────────────────────────────
+    #[test]
+    fn test_try_except_lowers_to_attempt_and_clauses() {
+        let stmts = parse_body(
+            "def main():\n    try:\n        n = parse(text)?\n        raise ValueError(\"x\")\n    except (KeyError, ValueError):\n        raise\n    except Problem as e:\n        print(e)\n    else:\n        print(1)\n",
+        );
+        assert_eq!(stmts.len(), 2);
+        let Stmt::Assign {
+            target: AssignTarget::Path(name),
+            value: Expr::Call { callee, args },
+            ..
+        } = &stmts[0]
+        else {
+            panic!("Expected the attempt, got {:?}", stmts[0]);
+        };
+        assert_eq!(name, "__try_1");
+        assert!(matches!(callee.as_ref(), Expr::Path(p) if p == &["Exception", "attempt"]));
+        let Expr::Closure { body, .. } = &args[0] else {
+            panic!("Expected the try body closure, got {:?}", args[0]);
+        };
+        // The raise returns from the closure; `Ok(())` ends it
+        assert!(matches!(
+            &body.statements[1],
+            Stmt::Return(Some(Expr::Call { args, .. }))
+                if matches!(&args[0], Expr::MacroCall { path, .. } if path == &["quiche_raise"])
+        ));
+        assert!(matches!(body.statements.last(), Some(Stmt::TailExpr(_))));
+
+        let Stmt::If {
+            then_block,
+            else_block: Some(else_block),
+            ..
+        } = &stmts[1]
+        else {
+            panic!("Expected the except clauses, got {:?}", stmts[1]);
+        };
+        assert_eq!(else_block.statements.len(), 1);
+        let Stmt::If {
+            condition: Expr::MacroCall { path, args },
+            then_block: first,
+            else_block: Some(rest),
+        } = &then_block.statements[1]
+        else {
+            panic!(
+                "Expected the first clause, got {:?}",
+                then_block.statements[1]
+            );
+        };
+        assert_eq!(path, &["quiche_except"]);
+        assert_eq!(args.len(), 3);
+        // In `main`, raising again ends the program
+        assert!(matches!(
+            &first.statements[0],
+            Stmt::Expr(Expr::Call { callee, .. })
+                if matches!(callee.as_ref(), Expr::Path(p) if p == &["Exception", "raise"])
+        ));
+        let Stmt::If {
+            then_block: second,
+            else_block: Some(unmatched),
+            ..
+        } = &rest.statements[0]
+        else {
+            panic!("Expected the second clause, got {:?}", rest.statements[0]);
+        };
+        assert!(matches!(
+            &second.statements[0],
+            Stmt::Assign { target: AssignTarget::Path(name), value: Expr::MacroCall { path, .. }, .. }
+                if name == "e" && path == &["quiche_caught"]
+        ));
+        assert_eq!(unmatched.statements.len(), 1);
+    }
 
     #[test]
-    fn test_print_no_args() {
-        let stmts = parse_body("def test():\n    print()\n");
-        match &stmts[0] {
-            Stmt::Expr(Expr::MacroCall { path, args }) => {
-                assert_eq!(path, &["println"]);
-                assert_eq!(args.len(), 1); // just the format string
-                assert!(matches!(&args[0], Expr::String(s) if s.is_empty()));
-            }
-            other => panic!("Expected MacroCall, got {:?}", other),
+    fn test_try_body_variables_are_not_visible_after_it() {
+        for source in [
+            "def f():\n    try:\n        n = int(\"1\")\n    except ValueError:\n        pass\n    print(n)\n",
+            "def f():\n    try:\n        n = 1\n    finally:\n        pass\n    if True:\n        print(n + 1)\n",
+            "def f():\n    try:\n        n = 1\n    except:\n        print(n)\n",
+        ] {
+            let err = parse(source).unwrap_err();
+            assert!(
+                err.message
+                    .contains("'n' is assigned inside a `try` body, so it isn't defined after"),
+                "{source}: {}",
+                err.message
+            );
+        }
+        for source in [
+            "def f():\n    n = 0\n    try:\n        n = int(\"1\")\n    except ValueError:\n        pass\n    print(n)\n",
+            "def f(n: i64):\n    try:\n        n = 1\n    except:\n        pass\n    print(n)\n",
+            "def f():\n    try:\n        n = 1\n        print(n)\n    except:\n        n = 2\n    print(n)\n",
+        ] {
+            assert!(parse(source).is_ok(), "{source}");
         }
     }
 
     #[test]
-    fn test_print_multiple_args() {
-        let stmts = parse_body("def test():\n    print(a, b, c)\n");
-        match &stmts[0] {
-            Stmt::Expr(Expr::MacroCall { path, args }) => {
-                assert_eq!(path, &["println"]);
-                assert_eq!(args.len(), 4); // format string + 3 args
-                assert!(matches!(&args[0], Expr::String(s) if s == "{} {} {}"));
-            }
-            other => panic!("Expected MacroCall, got {:?}", other),
+    fn test_try_finally_reraises_after_the_finally_block() {
+        let stmts = parse_body(
+            "def load() -> Result[i64, Exception]:\n    try:\n        work()\n    except:\n        raise\n    finally:\n        print(1)\n    return Ok(1)\n",
+        );
+        assert_eq!(stmts.len(), 4);
+        assert!(matches!(
+            &stmts[0],
+            Stmt::Assign { target: AssignTarget::Path(name), .. } if name == "__outcome_1"
+        ));
+        assert!(matches!(
+            &stmts[2],
+            Stmt::Expr(Expr::MacroCall { path, .. }) if path == &["quiche_try"]
+        ));
+
+        for (source, expected) in [
+            ("def f():\n    raise\n", "bare `raise`"),
+            (
+                "def f() -> i64:\n    try:\n        return 1\n    except:\n        pass\n    return 0\n",
+                "`return` inside a `try` body",
+            ),
+            (
+                "def f():\n    for x in xs:\n        try:\n            break\n        finally:\n            pass\n",
+                "`break` inside a `try` body",
+            ),
+            (
+                "def f():\n    try:\n        g()\n    h()\n",
+                "expected `except`",
+            ),
+            (
+                "def f():\n    try:\n        g()\n    except:\n        pass\n    except KeyError:\n        pass\n",
+                "must be the last",
+            ),
+            (
+                "def f():\n    except:\n        pass\n",
+                "without a matching `try:`",
+            ),
+        ] {
+            let err = parse(source).unwrap_err();
+            assert!(err.message.contains(expected), "{source}: {}", err.message);
         }
     }
 
-    // ─── Rust Escape Hatch ──────────────────────────────────────────────────
+    #[test]
+    fn test_for_over_collection_uses_quiche_iterable() {
+        let stmts = parse_body(
+            "def main(name: Str):\n    for c in name:\n        print(c)\n    for i in range(3):\n        print(i)\n",
+        );
+        let iter = |stmt: &Stmt| match stmt {
+            Stmt::For { iter, .. } => iter.clone(),
+            other => panic!("Expected For, got {:?}", other),
+        };
+        assert!(matches!(
+            iter(&stmts[0]),
+            Expr::Call { callee, args }
+                if matches!(&*callee, Expr::Path(p) if p == &["QuicheIterable", "quiche_iter"])
+                    && matches!(args.as_slice(), [Expr::Path(p)] if p == &["name"])
+        ));
+        assert!(!matches!(iter(&stmts[1]), Expr::Call { .. }));
+    }
 
     #[test]
-    fn test_rust_escape_hatch() {
-        let stmts = parse_body("def test():\n    rust(\"println!(42);\")\n");
-        match &stmts[0] {
-            Stmt::RustBlock(code) => {
-                assert_eq!(code, "println!(42);");
-            }
-            other => panic!("Expected RustBlock, got {:?}", other),
-        }
+    fn test_where_clause_adds_bounds() {
+        let module = parse(
+            "def show[T, U](x: T, y: U) -> Str where T: Display, U: Into[T] + Clone:\n    return f\"{x}\"\n\ntype Pair[K, V] where K: Hashable:\n    key: K\n    value: V\n",
+        )
+        .unwrap();
+        let bounds = |params: &[GenericParam]| -> Vec<Vec<String>> {
+            params
+                .iter()
+                .map(|p| p.bounds.iter().map(super::quiche_type).collect())
+                .collect()
+        };
+        let Item::Function(f) = &module.items[0] else {
+            panic!("expected function");
+        };
+        assert_eq!(
+            bounds(&f.type_params),
+            [vec!["Display"], vec!["Into[T]", "Clone"]]
+        );
+        let Item::Struct(s) = &module.items[1] else {
+            panic!("expected struct");
+        };
+        assert_eq!(bounds(&s.type_params)[0], ["std.hash.Hash", "Eq"]);
+
+        let err = parse("def f[T](x: T) where U: Display:\n    pass\n").unwrap_err();
+        assert!(err.message.contains("'U', which is not a type parameter"));
     }
 
-    // ─── Static Method Calls ─────────────────────────────────────────────────
+    #[test]
+    fn test_borrow_annotations_on_params() {
+        let module = parse(
+            "def update(items: mut ref List[i32], limit: ref i32, ref: i32):\n    items.push(limit)\n",
+        )
+        .unwrap();
+        let Item::Function(f) = &module.items[0] else {
+            panic!("expected function");
+        };
+        let types: Vec<String> = f.params.iter().map(|p| super::quiche_type(&p.ty)).collect();
+        assert_eq!(types, ["MutRef[List[i32]]", "Ref[i32]", "i32"]);
+
+        let err =
+            parse("def f(items: ref List[i32]):\n    for i in items:\n        items.append(i)\n")
+                .unwrap_err();
+        assert!(
+            err.message
+                .contains("cannot call 'append' on 'items': parameter is `ref`")
+        );
+        let err = parse("def f(items: mut ref List[i32]):\n    items = []\n").unwrap_err();
+        assert!(
+            err.message
+                .contains("cannot assign to 'items': parameter is `mut ref`")
+        );
+        let err = parse("def f(p: ref Point) -> Point:\n    return p\n").unwrap_err();
+        assert!(err.message.contains("cannot return 'p'"));
+    }
 
     #[test]
-    fn test_static_method_call_path() {
-        // Student.new("Alice", 16) should generate Path(["Student", "new"]) as callee
-        let stmts = parse_body("def test():\n    x = Student.new()\n");
-        match &stmts[0] {
-            Stmt::Assign { value, .. } => match value {
-                Expr::Call { callee, .. } => match callee.as_ref() {
-                    Expr::Path(segments) => {
-                        assert_eq!(segments, &["Student", "new"]);
-                    }
-                    other => panic!("Expected Path callee, got {:?}", other),
+    fn test_cli_main_reads_its_parameters_from_the_command_line() {
+        let source = "@cli\ndef main(path: Str, verbose: bool = False, count: i32 = 1, *files: Str, name: Option[Str] = None):\n    \"\"\"Count lines.\n\n    Args:\n        path: the file\n            to read\n        count (i32): how many times\n    \"\"\"\n    print(path)\n";
+        let module = parse(source).unwrap();
+        let functions: Vec<&FunctionDef> = module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Function(f) => Some(f),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(functions.len(), 2);
+        let (main, body) = (functions[0], functions[1]);
+        assert_eq!((main.name.as_str(), main.params.len()), ("main", 0));
+        assert_eq!((body.name.as_str(), body.params.len()), ("__cli_main", 5));
+        let [
+            Stmt::Expr(Expr::Call { args: spec, .. }),
+            Stmt::Expr(Expr::Call { args, .. }),
+        ] = main.body.statements.as_slice()
+        else {
+            panic!("{:?}", main.body.statements);
+        };
+        let [Expr::String(spec)] = spec.as_slice() else {
+            panic!("{spec:?}");
+        };
+        assert_eq!(
+            spec,
+            "arg\tpath\tStr\t\tthe file to read\n\
+             flag\tverbose\tbool\tFalse\t\n\
+             opt\tcount\ti32\t1\thow many times\n\
+             args\tfiles\tStr\t\t\n\
+             opt\tname\tStr\tNone\t\n\
+             \nCount lines."
+        );
+        let getters: Vec<String> = args
+            .iter()
+            .map(|arg| match arg {
+                Expr::Call { callee, .. } => match &**callee {
+                    Expr::Path(path) => path.join("."),
+                    other => format!("{other:?}"),
                 },
-                other => panic!("Expected Call, got {:?}", other),
-            },
-            other => panic!("Expected Assign, got {:?}", other),
-        }
+                other => format!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            getters,
+            vec![
+                "Cli.get",
+                "Cli.get_or",
+                "Cli.get_or",
+                "Cli.get_all",
+                "Cli.get_optional"
+            ]
+        );
+
+        let err = parse("@cli\ndef run(path: Str):\n    pass\n").unwrap_err();
+        assert!(
+            err.message.contains("@cli applies to `def main`"),
+            "{}",
+            err.message
+        );
+        let err = parse("@cli\ndef main(xs: List[i64]):\n    pass\n").unwrap_err();
+        assert!(
+            err.message
+                .contains("@cli parameter 'xs': a List[i64] can't be read from the command line"),
+            "{}",
+            err.message
+        );
     }
 
     #[test]
-    fn test_instance_method_remains_field() {
-        // obj.method() where obj is lowercase should remain as Field access
-        let stmts = parse_body("def test():\n    x = obj.method()\n");
-        match &stmts[0] {
-            Stmt::Assign { value, .. } => match value {
-                Expr::Call { callee, .. } => match callee.as_ref() {
-                    Expr::Field { base, field } => {
-                        assert!(matches!(base.as_ref(), Expr::Path(p) if p == &["obj"]));
-                        assert_eq!(field, "method");
-                    }
-                    other => panic!("Expected Field callee, got {:?}", other),
-                },
-                other => panic!("Expected Call, got {:?}", other),
-            },
-            other => panic!("Expected Assign, got {:?}", other),
+    fn test_unit_enums_list_and_parse_their_variants() {
+        let module = parse(
+            "type Level = | Low | High
+",
+        )
+        .unwrap();
+        match &module.items[1] {
+            Item::RustBlock(code) => {
+                assert!(
+                    code.contains("List::from(vec![Str::from(\"Low\"), Str::from(\"High\")])"),
+                    "{code}"
+                );
+                assert!(code.contains("\"High\" => Some(Level::High),"), "{code}");
+                assert!(code.contains("impl std::str::FromStr for Level"), "{code}");
+                assert!(code.contains("(expected one of Low, High)"), "{code}");
+            }
+            other => panic!("Expected RustBlock, got {:?}", other),
         }
+
+        let module = parse(
+            "type Shape = | Dot | Circle(f64)
+",
+        )
+        .unwrap();
+        assert_eq!(module.items.len(), 1);
+        let module = parse(
+            "type Maybe[T] = | Nothing | Unknown
+",
+        )
+        .unwrap();
+        assert_eq!(module.items.len(), 1);
+
+        let source = "type Level = | Low | High
+\n@cli\ndef main(level: Level = Level.Low):\n    pass\n";
+        assert!(parse(source).is_ok());
     }
 
     #[test]
-    fn test_static_field_access_no_call() {
-        // Type.field without () should remain as Field (not merged into path)
-        let stmts = parse_body("def test():\n    x = Type.CONST\n");
-        match &stmts[0] {
-            Stmt::Assign { value, .. } => match value {
-                Expr::Field { base, field } => {
-                    assert!(matches!(base.as_ref(), Expr::Path(p) if p == &["Type"]));
-                    assert_eq!(field, "CONST");
-                }
-                other => panic!("Expected Field, got {:?}", other),
+    fn test_threaded_work_must_be_thread_safe() {
+        let ok = "def main():\n    factor = 3\n    xs = Parallel.map(lambda x: x * factor, [1, 2, 3])\n    Parallel.for_each(lambda x: print(x), xs)\n";
+        assert!(parse(ok).is_ok());
+
+        let err = parse(
+            "def main():\n    lib = CLib.load(\"libm.so.6\")\n    xs = Parallel.map(lambda x: lib.has(x), [\"cos\"])\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.message.contains(
+                "in 'main': Parallel.map's function captures 'lib', a CLib, which can't be shared between threads"
+            ),
+            "{}",
+            err.message
+        );
+        let err = parse(
+            "@trait\ntype Shape:\n    def area(self) -> f64: ...\n\ndef total(shapes: List[Shape]):\n    Parallel.for_each(lambda s: print(s.area()), shapes)\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.message
+                .contains("Parallel.for_each's items 'shapes' hold a trait object"),
+            "{}",
+            err.message
+        );
+        let err = parse(
+            "def main():\n    lib = CLib.load(\"libm.so.6\")\n    Server.route(\"/cos\", lambda req: str(lib.has(\"cos\")))\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.message
+                .contains("Server.route's function captures 'lib', a CLib"),
+            "{}",
+            err.message
+        );
+        let err = parse(
+            "def main():\n    lib = CLib.load(\"libm.so.6\")\n    Signal.on(\"SIGINT\", lambda: print(lib.has(\"cos\")))\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.message
+                .contains("Signal.on's function captures 'lib', a CLib"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_union_return_synthesizes_enum_and_narrows() {
+        let source = "def parse(s: Str) -> i64 | Str:\n    if s == \"\":\n        return \"empty\"\n    return s.len()\n\ndef main():\n    r = parse(\"x\")\n    if isinstance(r, i64):\n        print(r)\n    match r:\n        case Str(msg):\n            print(msg)\n        case _:\n            pass\n";
+        let module = parse(source).unwrap();
+        let items = &module.items;
+        match &items[0] {
+            Item::Enum(en) => {
+                assert_eq!(en.name, "ParseResult");
+                let names: Vec<&str> = en.variants.iter().map(|v| v.name.as_str()).collect();
+                assert_eq!(names, vec!["I64", "Str"]);
+            }
+            other => panic!("Expected Enum, got {:?}", other),
+        }
+        assert!(
+            matches!(&items[1], Item::RustBlock(code) if code.contains("impl From<Str> for ParseResult"))
+        );
+        let callee_path = |expr: &Expr| match expr {
+            Expr::Call { callee, .. } => match &**callee {
+                Expr::Path(p) => p.join("::"),
+                _ => String::new(),
             },
-            other => panic!("Expected Assign, got {:?}", other),
+            _ => String::new(),
+        };
+        match &items[2] {
+            Item::Function(f) => {
+                assert_eq!(f.return_type.as_ref().unwrap().path, vec!["ParseResult"]);
+                match &f.body.statements[0] {
+                    Stmt::If { then_block, .. } => assert!(matches!(
+                        &then_block.statements[0],
+                        Stmt::Return(Some(v)) if callee_path(v) == "ParseResult::Str"
+                    )),
+                    other => panic!("Expected If, got {:?}", other),
+                }
+                assert!(matches!(
+                    &f.body.statements[1],
+                    Stmt::Return(Some(v)) if callee_path(v) == "ParseResult::from"
+                ));
+            }
+            other => panic!("Expected Function, got {:?}", other),
+        }
+        match &items[3] {
+            Item::Function(f) => {
+                match &f.body.statements[1] {
+                    Stmt::If {
+                        condition,
+                        then_block,
+                        ..
+                    } => {
+                        assert!(matches!(condition, Expr::Match { .. }));
+                        assert!(matches!(
+                            &then_block.statements[0],
+                            Stmt::Const(c) if c.name == "r" && c.ty.as_ref().unwrap().path == vec!["i64"]
+                        ));
+                    }
+                    other => panic!("Expected If, got {:?}", other),
+                }
+                match &f.body.statements[2] {
+                    Stmt::Expr(Expr::Match { arms, .. }) => assert!(matches!(
+                        &arms[0].pattern,
+                        Pattern::Variant { path, .. } if path == &["ParseResult", "Str"]
+                    )),
+                    other => panic!("Expected Match, got {:?}", other),
+                }
+            }
+            other => panic!("Expected Function, got {:?}", other),
         }
+
+        let err = parse(&source.replace("isinstance(r, i64)", "isinstance(r, bool)")).unwrap_err();
+        assert!(
+            err.message
+                .contains("`bool` is not one of the types in `i64 | Str`"),
+            "{}",
+            err.message
+        );
     }
 
     #[test]
-    fn test_type_body_methods_emit_impl_block() {
-        let module = parse(
-            "type Session:\n    name: str\n\n    def advance(self: Session, event: str):\n        return\n",
+    fn test_impl_generates_delegating_trait_impls() {
+        let parsed = super::parse_decorated(
+            "@impl(Iterator)\n@impl(Default)\n@impl(From[str])\n@impl(ContextManager)\ntype Counter:\n    n: i64\n\n    def next(self) -> Option[i64]:\n        return None\n\n    def parse(text: Str) -> Counter:\n        return Counter(0)\n\n    def exit(self):\n        pass\n",
         )
         .unwrap();
+        let blocks: Vec<&String> = parsed
+            .module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::RustBlock(code) => Some(code),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[0].starts_with("impl Iterator for Counter {\n    type Item = i64;"));
+        assert!(blocks[0].contains("Counter::next(self)"));
+        assert!(blocks[1].contains("Counter { n: Default::default() }"));
+        assert!(blocks[2].starts_with("impl From<Str> for Counter {"));
+        assert!(blocks[2].contains("Counter::parse(value)"));
+        assert_eq!(
+            blocks[3],
+            "impl ContextManager for Counter {\n    fn exit(&mut self) {\n        Counter::exit(self)\n    }\n}"
+        );
+    }
 
-        let user_items = &module.items[2..];
-        assert!(matches!(&user_items[0], Item::Struct(_)));
-        match &user_items[1] {
-            Item::Impl(imp) => {
-                assert_eq!(imp.target, "Session");
-                assert_eq!(imp.methods.len(), 1);
-                assert_eq!(imp.methods[0].name, "advance");
-            }
-            other => panic!("Expected Impl item, got {:?}", other),
-        }
+    #[test]
+    fn test_impl_requires_matching_method() {
+        let err = parse("@impl(Iterator)\ntype Empty:\n    n: i64\n").unwrap_err();
+        assert!(
+            err.message
+                .contains("needs a method `def next(self) -> Option[T]`"),
+            "{}",
+            err.message
+        );
+        let err = parse("@impl(Hash)\ntype Empty:\n    n: i64\n").unwrap_err();
+        assert!(err.message.contains("not supported"), "{}", err.message);
     }
 
-    // ─── Enum Definitions ────────────────────────────────────────────────────
+    #[test]
+    fn test_trait_objects_box_implementors() {
+        let source = "\
+@trait
+type Drawable:
+    \"\"\"Anything that can be drawn.\"\"\"
+    def draw(self) -> str: ...
+    def overlaps(self, other: Drawable) -> bool: ...
+
+@impl(Drawable)
+type Circle:
+    r: f64
+
+    def draw(self) -> str:
+        return \"circle\"
+
+    def overlaps(self, other: Drawable) -> bool:
+        return False
+
+type Scene:
+    shapes: List[Drawable]
+
+def render(shape: Drawable) -> str:
+    return shape.draw()
+
+def biggest() -> Drawable:
+    return Circle(2.0)
+
+def main():
+    shapes: List[Drawable] = [Circle(1.0), biggest()]
+    shapes.append(Circle(3.0))
+    scene = Scene([Circle(4.0)])
+";
+        let module = parse(source).unwrap();
+        let blocks: Vec<&String> = module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::RustBlock(code) => Some(code),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            blocks[0],
+            "pub trait Drawable {\n    fn draw(&self) -> Str;\n    fn overlaps(&self, other: &dyn Drawable) -> bool;\n}"
+        );
+        assert!(
+            blocks[1].starts_with("impl Drawable for Circle {\n    fn draw(&self) -> Str {\n        Circle::draw(self)\n    }"),
+            "{}",
+            blocks[1]
+        );
+        assert!(blocks[1].contains("Circle::overlaps(self, other)"));
+
+        let function = |name: &str| {
+            module
+                .items
+                .iter()
+                .find_map(|item| match item {
+                    Item::Function(f) if f.name == name => Some(f.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let is_boxed = |expr: &Expr| matches!(expr, Expr::Call { callee, .. } if matches!(&**callee, Expr::Path(p) if p == &["Box", "new"]));
+        let render = function("render");
+        assert_eq!(super::rust_type(&render.params[0].ty), "&dyn Drawable");
+        let biggest = function("biggest");
+        assert_eq!(
+            super::rust_type(biggest.return_type.as_ref().unwrap()),
+            "Box<dyn Drawable>"
+        );
+        assert!(
+            matches!(&biggest.body.statements[0], Stmt::Return(Some(value)) if is_boxed(value))
+        );
+
+        let main = function("main");
+        let Stmt::Const(shapes) = &main.body.statements[0] else {
+            panic!("expected an annotated assignment");
+        };
+        assert_eq!(
+            super::rust_type(shapes.ty.as_ref().unwrap()),
+            "List<Box<dyn Drawable>>"
+        );
+        let Expr::Array(items) = &shapes.value else {
+            panic!("expected a list literal");
+        };
+        // A value already returned as a trait object isn't boxed again
+        assert!(is_boxed(&items[0]) && !is_boxed(&items[1]));
+        assert!(
+            matches!(&main.body.statements[1], Stmt::Expr(Expr::Call { args, .. }) if is_boxed(&args[0]))
+        );
+        let Stmt::Assign {
+            value: Expr::StructLiteral { fields, .. },
+            ..
+        } = &main.body.statements[2]
+        else {
+            panic!("expected a Scene");
+        };
+        assert!(matches!(&fields[0].value, Expr::Array(items) if is_boxed(&items[0])));
+
+        let err = parse("@trait\ntype Shape:\n    def area(self) -> f64:\n        return 1.0\n")
+            .unwrap_err();
+        assert!(
+            err.message.contains("is a signature only"),
+            "{}",
+            err.message
+        );
+        let err = parse("@trait\ntype Shape:\n    def area(self) -> f64: ...\n\n@impl(Shape)\ntype Square:\n    side: f64\n").unwrap_err();
+        assert!(
+            err.message.contains("needs a method `def area(self, ...)`"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_interface_externs_checked_in_importing_module() {
+        let interface = (
+            "rand.qi".to_string(),
+            "@extern(crate=\"rand\", version=\"0.8\")\ndef random() -> f64:\n    pass\n"
+                .to_string(),
+        );
+        let parsed = super::parse_with_interfaces(
+            "import rand\n\ndef main():\n    x = random()\n",
+            std::slice::from_ref(&interface),
+            false,
+        )
+        .unwrap();
+        assert_eq!(parsed.extern_crates, vec!["rand"]);
+        assert!(matches!(parsed.module.items[0], Item::RustUse(_)));
+
+        let err =
+            super::parse_with_interfaces("def main():\n    x = random(2)\n", &[interface], false)
+                .unwrap_err();
+        assert!(
+            err.message.contains("takes 0 argument(s)"),
+            "{}",
+            err.message
+        );
+    }
 }
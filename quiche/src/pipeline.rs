@@ -0,0 +1,308 @@
+//! `CompilePipeline` — compiling `.q` files with AST passes of your own.
+//!
+//! A file is parsed (which desugars Quiche into the Elevate AST), its
+//! numeric coercions and overflow mode are applied, and the result goes to
+//! Elevate for type inference and code generation. A pipeline runs extra
+//! passes over the module in between, so a build script can rewrite it:
+//!
+//! ```ignore
+//! // build.rs
+//! use quiche::CompilePipeline;
+//! use quiche::ast as e;
+//!
+//! fn main() {
+//!     let out_dir = std::env::var("OUT_DIR").unwrap();
+//!     let pipeline = CompilePipeline::new()
+//!         .pass("version", |module, _| {
+//!             module.items.push(e::Item::RustBlock(format!(
+//!                 "pub const VERSION: &str = {:?};",
+//!                 env!("CARGO_PKG_VERSION")
+//!             )));
+//!             Ok(())
+//!         })
+//!         .pass_before("version", "strip-debug", |module, context| {
+//!             module.items.retain(|item| {
+//!                 !matches!(item, e::Item::Function(f) if context.has_decorator(&f.name, "debug_only"))
+//!             });
+//!             Ok(())
+//!         });
+//!     if let Err(e) = pipeline.compile_dir("src".as_ref(), out_dir.as_ref()) {
+//!         panic!("{e}");
+//!     }
+//! }
+//! ```
+//!
+//! Passes run in the order they were added, or where `pass_before` /
+//! `pass_after` put them. A pass that fails stops the file's compile with
+//! an error naming the pass; a pipeline whose ordering can't be met (an
+//! unknown anchor, a name used twice) fails every compile with that error.
+
+use crate::CompileOptions;
+use crate::overflow::Overflow;
+use crate::parser::Decorator;
+use crate::prelude::Prelude;
+use crate::qmeta::ModuleMeta;
+use elevate::ast as e;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A pass over a parsed module, before Elevate sees it.
+pub type Pass = Box<dyn Fn(&mut e::Module, &PassContext) -> Result<(), String>>;
+
+/// What a pass knows of the file it runs on, besides its module.
+pub struct PassContext<'a> {
+    /// The `.q` file's name, as given to the compile.
+    pub filename: &'a str,
+    /// The decorators of each top-level `def` and `type`, by name, in
+    /// source order — including ones the compiler itself ignores, such as a
+    /// project's own `@debug_only`.
+    pub decorators: &'a HashMap<String, Vec<Decorator>>,
+}
+
+impl PassContext<'_> {
+    /// Whether the top-level item `name` carries `@decorator`.
+    pub fn has_decorator(&self, name: &str, decorator: &str) -> bool {
+        self.decorators
+            .get(name)
+            .is_some_and(|decorators| decorators.iter().any(|d| d.name == decorator))
+    }
+}
+
+/// Compile settings and extra passes, for `.q` files or a whole tree of
+/// them.
+pub struct CompilePipeline {
+    options: CompileOptions,
+    overflow: Option<Overflow>,
+    prelude: Option<Prelude>,
+    passes: Vec<(String, Pass)>,
+    /// The first ordering problem found while building, reported by every
+    /// compile
+    error: Option<String>,
+}
+
+impl Default for CompilePipeline {
+    fn default() -> Self {
+        CompilePipeline::new()
+    }
+}
+
+impl CompilePipeline {
+    /// A pipeline with [`crate::default_options`], no passes, and the
+    /// overflow mode and prelude of the environment (`QUICHE_OVERFLOW`,
+    /// `QUICHE_PRELUDE`).
+    pub fn new() -> CompilePipeline {
+        CompilePipeline {
+            options: crate::default_options(),
+            overflow: None,
+            prelude: None,
+            passes: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Compile with `options`.
+    pub fn options(mut self, options: CompileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Lower integer `+ - *` for `overflow` (see [`crate::overflow`]).
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = Some(overflow);
+        self
+    }
+
+    /// Put `prelude` ahead of each module (see [`crate::prelude`]).
+    pub fn prelude(mut self, prelude: Prelude) -> Self {
+        self.prelude = Some(prelude);
+        self
+    }
+
+    /// Run `pass` after the passes added so far.
+    pub fn pass(
+        self,
+        name: &str,
+        pass: impl Fn(&mut e::Module, &PassContext) -> Result<(), String> + 'static,
+    ) -> Self {
+        let at = self.passes.len();
+        self.insert(at, name, Box::new(pass))
+    }
+
+    /// Run `pass` just before the pass named `before`.
+    pub fn pass_before(
+        self,
+        before: &str,
+        name: &str,
+        pass: impl Fn(&mut e::Module, &PassContext) -> Result<(), String> + 'static,
+    ) -> Self {
+        match self.position(before) {
+            Some(at) => self.insert(at, name, Box::new(pass)),
+            None => self.fail(format!(
+                "pass '{name}' is ordered before unknown pass '{before}'"
+            )),
+        }
+    }
+
+    /// Run `pass` just after the pass named `after`.
+    pub fn pass_after(
+        self,
+        after: &str,
+        name: &str,
+        pass: impl Fn(&mut e::Module, &PassContext) -> Result<(), String> + 'static,
+    ) -> Self {
+        match self.position(after) {
+            Some(at) => self.insert(at + 1, name, Box::new(pass)),
+            None => self.fail(format!(
+                "pass '{name}' is ordered after unknown pass '{after}'"
+            )),
+        }
+    }
+
+    /// The passes' names, in the order they run.
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Compile one `.q` file to Rust.
+    pub fn compile_file(&self, source: &str, filename: &str) -> Result<String, String> {
+        self.compile_file_with_imports(source, filename, &[])
+    }
+
+    /// [`Self::compile_file`] for a module of a project, given the symbol
+    /// tables of the project modules it imports (see [`crate::qmeta`]).
+    pub fn compile_file_with_imports(
+        &self,
+        source: &str,
+        filename: &str,
+        imports: &[ModuleMeta],
+    ) -> Result<String, String> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        if std::env::var_os(crate::ALLOW_UNSAFE_ENV).is_none() {
+            crate::reject_unsafe(source, filename)?;
+        }
+        let overflow = match self.overflow {
+            Some(overflow) => overflow,
+            None => Overflow::from_env()?,
+        };
+        let prelude = match &self.prelude {
+            Some(prelude) => prelude.clone(),
+            None => Prelude::from_env()?,
+        };
+        crate::compile_parsed_file(
+            source,
+            filename,
+            &self.options,
+            overflow,
+            &prelude,
+            imports,
+            &self.passes,
+        )
+    }
+
+    /// Compile every `.q` file under `src_dir` into `out_dir`, as
+    /// [`crate::compile_dir`] does.
+    pub fn compile_dir(&self, src_dir: &Path, out_dir: &Path) -> Result<Vec<PathBuf>, String> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        crate::build::compile_dir_with(self, src_dir, out_dir)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|(pass, _)| pass == name)
+    }
+
+    fn insert(mut self, at: usize, name: &str, pass: Pass) -> Self {
+        if self.position(name).is_some() {
+            return self.fail(format!("pass '{name}' is added twice"));
+        }
+        self.passes.insert(at, (name.to_string(), pass));
+        self
+    }
+
+    fn fail(mut self, error: String) -> Self {
+        self.error.get_or_insert(error);
+        self
+    }
+}
+
+/// Run `passes` over `module` in order, stopping at the first that fails.
+pub(crate) fn run_passes(
+    passes: &[(String, Pass)],
+    module: &mut e::Module,
+    context: &PassContext,
+) -> Result<(), String> {
+    for (name, pass) in passes {
+        pass(module, context)
+            .map_err(|e| format!("{}: pass '{name}' failed: {e}", context.filename))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn add_const(
+        name: &'static str,
+    ) -> impl Fn(&mut e::Module, &PassContext) -> Result<(), String> {
+        move |module, _| {
+            module.items.push(e::Item::RustBlock(format!("// {name}")));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_passes_are_ordered_and_checked() {
+        let pipeline = CompilePipeline::new()
+            .pass("b", add_const("b"))
+            .pass_before("b", "a", add_const("a"))
+            .pass_after("b", "c", add_const("c"));
+        assert_eq!(pipeline.pass_names(), ["a", "b", "c"]);
+
+        let decorators = HashMap::from([(
+            "trace".to_string(),
+            vec![Decorator {
+                name: "debug_only".into(),
+                args: vec![],
+                kwargs: vec![],
+            }],
+        )]);
+        let context = PassContext {
+            filename: "app.q",
+            decorators: &decorators,
+        };
+        assert!(context.has_decorator("trace", "debug_only"));
+        assert!(!context.has_decorator("main", "debug_only"));
+        let mut module = e::Module::default();
+        run_passes(&pipeline.passes, &mut module, &context).unwrap();
+        let order: Vec<String> = module
+            .items
+            .iter()
+            .map(|item| match item {
+                e::Item::RustBlock(code) => code.clone(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(order, ["// a", "// b", "// c"]);
+
+        let failing = CompilePipeline::new().pass("check", |_, _| Err("no main".into()));
+        let err = run_passes(&failing.passes, &mut module, &context).unwrap_err();
+        assert_eq!(err, "app.q: pass 'check' failed: no main");
+
+        let err = CompilePipeline::new()
+            .pass("a", add_const("a"))
+            .pass("a", add_const("a"))
+            .compile_file("def main():\n    pass\n", "app.q")
+            .unwrap_err();
+        assert_eq!(err, "pass 'a' is added twice");
+        let err = CompilePipeline::new()
+            .pass_after("missing", "a", add_const("a"))
+            .compile_file("", "app.q")
+            .unwrap_err();
+        assert!(err.contains("after unknown pass 'missing'"), "{err}");
+    }
+}
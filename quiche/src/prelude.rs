@@ -0,0 +1,171 @@
+//! The Rust prelude put ahead of every compiled module.
+//!
+//! By default that is quiche-lib — `use quiche_lib::*;` and the helper
+//! macros the parser's lowerings expand to. [`Prelude::parse`] reads a
+//! comma-separated spec of parts, from `--prelude` or the `QUICHE_PRELUDE`
+//! environment variable:
+//!
+//! - `quiche` — quiche-lib (on unless `none` is given)
+//! - `none` — no quiche-lib, for targets it doesn't build for; Quiche code
+//!   using `Str`, `List`, f-strings, `raise`, ... won't compile without it
+//! - a path to a `.rs` file — Rust added after quiche-lib, e.g. a project's
+//!   own `use` lines or helpers
+//!
+//! so `QUICHE_PRELUDE=src/prelude.rs` extends the default and
+//! `QUICHE_PRELUDE=none,embedded.rs` replaces it. Set in a project's
+//! `.cargo/config.toml` `[env]` table, it applies to every `.q` file
+//! `build.rs` compiles.
+//!
+//! The prelude is added when the module is handed to Elevate, not by the
+//! parser, so parsed modules (and tooling indexing their items) hold only
+//! the source's own items.
+
+use elevate::ast as e;
+
+/// Environment variable carrying the prelude spec to `compile_file`.
+pub const PRELUDE_ENV: &str = "QUICHE_PRELUDE";
+
+/// What goes ahead of a compiled module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prelude {
+    /// Whether quiche-lib is imported, with its helper macros.
+    pub quiche_lib: bool,
+    /// Rust source added after quiche-lib, in order.
+    pub extra: Vec<String>,
+}
+
+impl Default for Prelude {
+    fn default() -> Self {
+        Prelude {
+            quiche_lib: true,
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl Prelude {
+    /// The prelude named by a spec such as `none,src/prelude.rs`, reading
+    /// its files.
+    pub fn parse(spec: &str) -> Result<Prelude, String> {
+        let mut prelude = Prelude::default();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part {
+                "quiche" => prelude.quiche_lib = true,
+                "none" => prelude.quiche_lib = false,
+                path if path.ends_with(".rs") => {
+                    let code = std::fs::read_to_string(path)
+                        .map_err(|e| format!("failed to read prelude file {path}: {e}"))?;
+                    prelude.extra.push(code);
+                }
+                other => {
+                    return Err(format!(
+                        "unknown prelude part '{other}' (expected quiche, none or a .rs file)"
+                    ));
+                }
+            }
+        }
+        Ok(prelude)
+    }
+
+    /// The prelude named by [`PRELUDE_ENV`], or the default when it is unset.
+    pub fn from_env() -> Result<Prelude, String> {
+        match std::env::var(PRELUDE_ENV) {
+            Ok(spec) => Prelude::parse(&spec),
+            Err(_) => Ok(Prelude::default()),
+        }
+    }
+
+    /// The prelude's items, in order.
+    pub fn items(&self) -> Vec<e::Item> {
+        let mut items = if self.quiche_lib {
+            quiche_lib_items()
+        } else {
+            Vec::new()
+        };
+        items.extend(self.extra.iter().cloned().map(e::Item::RustBlock));
+        items
+    }
+
+    /// `module` with the prelude ahead of its items.
+    pub fn apply(&self, module: &e::Module) -> e::Module {
+        let mut items = self.items();
+        items.extend(module.items.iter().cloned());
+        e::Module { items }
+    }
+}
+
+/// Quiche primitive type prelude — imports types from `quiche-lib` crate.
+///
+/// Two RustBlocks:
+/// 1. `use quiche_lib::*;` — actual import
+/// 2. Stub fns `str()` and `open()` — so Elevate's
+///    `extract_rust_block_function_names` resolves them — `quiche_show!`,
+///    which f-string placeholders go through (see quiche-lib's `Show`),
+///    `quiche_eq!`, which `==` against a list or dict literal goes through,
+///    `quiche_repr!` / `quiche_pprint!`, behind `f"{expr=}"` and `pprint(x)`,
+///    `quiche_try!`, behind `expr?`, and `quiche_raise!` / `quiche_except!` /
+///    `quiche_caught!`, behind `raise` and `try` / `except` (see quiche-lib's
+///    `Exception`)
+fn quiche_lib_items() -> Vec<e::Item> {
+    vec![
+        e::Item::RustBlock("use quiche_lib::*;".into()),
+        e::Item::RustBlock(
+            "pub fn str<T: std::fmt::Display>(x: T) -> Str { quiche_lib::str(x) }\n\
+             pub fn open(path: Str) -> FileHandle { quiche_lib::open(path) }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_show { ($value:expr) => { (&quiche_lib::Show(&$value)).quiche_show() }; }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_eq { ($value:expr, $literal:expr) => { ($value).eq(&$literal) }; }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_repr { ($value:expr) => { quiche_lib::repr(&$value) }; }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_pprint { ($value:expr) => { quiche_lib::pprint(&$value) }; }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_try { ($value:expr) => { $value? }; }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_raise { ($value:expr) => {{ let __raised = $value; \
+             let __message = (&&quiche_lib::Raised(&__raised)).exception_message(); \
+             quiche_lib::Exception::new(__raised, __message) }}; }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_except { ($error:expr, $($ty:ty),+) => { $($error.is::<$ty>())||+ }; }\n\
+             #[allow(unused_macros)]\n\
+             macro_rules! quiche_caught { ($error:expr, $ty:ty) => { $error.get::<$ty>().expect(\"checked by its except clause\") }; }"
+                .into(),
+        ),
+    ]
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prelude_spec_parts() {
+        assert_eq!(Prelude::parse("").unwrap(), Prelude::default());
+        assert_eq!(Prelude::default().items().len(), 2);
+        assert!(Prelude::parse("none").unwrap().items().is_empty());
+
+        let dir = std::env::temp_dir().join(format!("quiche-prelude-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("extra.rs");
+        std::fs::write(&file, "use std::rc::Rc;").unwrap();
+        let prelude = Prelude::parse(&format!("none, {}", file.display())).unwrap();
+        assert!(matches!(
+            prelude.items().as_slice(),
+            [e::Item::RustBlock(code)] if code == "use std::rc::Rc;"
+        ));
+        let module = e::Module {
+            items: vec![e::Item::RustBlock("// user".into())],
+        };
+        let applied = Prelude::parse(&file.display().to_string())
+            .unwrap()
+            .apply(&module);
+        assert_eq!(applied.items.len(), 4);
+        assert!(matches!(&applied.items[3], e::Item::RustBlock(code) if code == "// user"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let err = Prelude::parse("std").unwrap_err();
+        assert!(err.contains("unknown prelude part 'std'"), "{err}");
+    }
+}
@@ -0,0 +1,233 @@
+//! `.qmeta` files — the symbol tables of a compiled module, for the modules
+//! that import it.
+//!
+//! The parser rewrites some code using what it knows about the names
+//! involved: keyword arguments are reordered into parameter order (and
+//! omitted ones filled from their defaults), types are
+//! built from positional arguments in field order, and `Circle.PI` becomes a
+//! constant read. Each parser only knows the module it parses, so
+//! [`crate::compile_dir`] writes a [`ModuleMeta`] per module (`net/http.q` →
+//! `net/http.qmeta` in the output directory) and loads those of a module's
+//! imports when compiling it.
+//!
+//! The format is one entry per line, names separated by spaces:
+//!
+//! ```text
+//! # quiche module metadata
+//! fields Point x y
+//! params area width height
+//! params greet name * punct
+//! params log level *parts **options
+//! default greet punct "!"
+//! attr Circle PI f64
+//! attr Circle count mut i64
+//! ```
+//!
+//! A `*` among the parameters marks where keyword-only ones start, `*args`
+//! and `**kwargs` keep their stars, and a default is the rest of its line,
+//! as Quiche source.
+//!
+//! Trait impls are not recorded: they are declared on the type, and the
+//! generated Rust resolves them across modules by itself.
+
+use crate::parser::Parser;
+use elevate::ast as e;
+use std::collections::BTreeMap;
+
+const HEADER: &str = "# quiche module metadata";
+
+/// The symbol tables a module defines.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleMeta {
+    /// Field names of each type (and named-field enum variant, as
+    /// `Shape::Rect`), in declaration order.
+    pub struct_fields: BTreeMap<String, Vec<String>>,
+    /// Parameter names of each function and method, in order, with a `*`
+    /// where keyword-only ones start and `*args` / `**kwargs` starred.
+    pub fn_params: BTreeMap<String, Vec<String>>,
+    /// Default parameter values by `(function, parameter)`, as source text.
+    pub fn_defaults: BTreeMap<(String, String), String>,
+    /// Class attributes by `(class, attribute)`: their type and whether
+    /// they are mutable class state.
+    pub class_attrs: BTreeMap<(String, String), (e::Type, bool)>,
+}
+
+impl ModuleMeta {
+    /// Whether the module defines nothing worth recording.
+    pub fn is_empty(&self) -> bool {
+        self.struct_fields.is_empty()
+            && self.fn_params.is_empty()
+            && self.fn_defaults.is_empty()
+            && self.class_attrs.is_empty()
+    }
+
+    /// The `.qmeta` text of these tables.
+    pub fn render(&self) -> String {
+        let mut out = format!("{HEADER}\n");
+        for (name, fields) in &self.struct_fields {
+            out.push_str(&entry("fields", name, fields));
+        }
+        for (name, params) in &self.fn_params {
+            out.push_str(&entry("params", name, params));
+        }
+        for ((function, param), value) in &self.fn_defaults {
+            out.push_str(&format!("default {function} {param} {value}\n"));
+        }
+        for ((class, attr), (ty, mutable)) in &self.class_attrs {
+            let mutable = if *mutable { " mut" } else { "" };
+            out.push_str(&format!(
+                "attr {class} {attr}{mutable} {}\n",
+                crate::parser::quiche_type(ty)
+            ));
+        }
+        out
+    }
+
+    /// Read `.qmeta` text back into tables.
+    pub fn parse(text: &str) -> Result<ModuleMeta, String> {
+        let mut meta = ModuleMeta::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let malformed = || format!("line {}: malformed entry '{line}'", i + 1);
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("fields"), Some(name)) => {
+                    meta.struct_fields
+                        .insert(name.to_string(), words.map(str::to_string).collect());
+                }
+                (Some("params"), Some(name)) => {
+                    meta.fn_params
+                        .insert(name.to_string(), words.map(str::to_string).collect());
+                }
+                (Some("default"), Some(function)) => {
+                    let param = words.next().ok_or_else(malformed)?;
+                    // The value is the rest of the line, spaces and all
+                    let value = line
+                        .splitn(4, ' ')
+                        .nth(3)
+                        .filter(|value| !value.trim().is_empty())
+                        .ok_or_else(malformed)?;
+                    meta.fn_defaults
+                        .insert((function.to_string(), param.to_string()), value.to_string());
+                }
+                (Some("attr"), Some(class)) => {
+                    let attr = words.next().ok_or_else(malformed)?;
+                    let rest: Vec<&str> = words.collect();
+                    let (mutable, ty) = match rest.split_first() {
+                        Some((&"mut", ty)) => (true, ty.join(" ")),
+                        _ => (false, rest.join(" ")),
+                    };
+                    let ty = Parser::new(&ty)
+                        .and_then(|mut parser| parser.parse_type())
+                        .map_err(|e| format!("{}: {}", malformed(), e.message))?;
+                    meta.class_attrs
+                        .insert((class.to_string(), attr.to_string()), (ty, mutable));
+                }
+                _ => return Err(malformed()),
+            }
+        }
+        Ok(meta)
+    }
+}
+
+fn entry(kind: &str, name: &str, names: &[String]) -> String {
+    if names.is_empty() {
+        format!("{kind} {name}\n")
+    } else {
+        format!("{kind} {name} {}\n", names.join(" "))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_round_trips() {
+        let source = "type Point:\n    x: i64\n    y: i64\n\ntype Circle:\n    PI: f64 = 3.14159\n    mut count: i64 = 0\n    r: f64\n\ndef area(width: f64, height: f64) -> f64:\n    return width * height\n";
+        let meta = crate::parser::parse_with_interfaces(source, &[], false)
+            .unwrap()
+            .meta;
+        assert_eq!(meta.struct_fields["Point"], ["x", "y"]);
+        assert_eq!(meta.fn_params["area"], ["width", "height"]);
+
+        let text = meta.render();
+        assert!(text.contains("fields Point x y\n"), "{text}");
+        assert!(text.contains("attr Circle count mut i64\n"), "{text}");
+        let read = ModuleMeta::parse(&text).unwrap();
+        assert_eq!(read.render(), text);
+        let (ty, mutable) = &read.class_attrs[&("Circle".into(), "PI".into())];
+        assert_eq!(
+            (ty.path.as_slice(), *mutable),
+            (["f64".to_string()].as_slice(), false)
+        );
+
+        assert!(ModuleMeta::parse("attr Circle\n").is_err());
+    }
+
+    #[test]
+    fn test_imported_meta_fills_defaults() {
+        let text = "def greet(name: str, *, punct: str = \"! \") -> str:\n    return name\n";
+        let meta = crate::parser::parse_with_interfaces(text, &[], false)
+            .unwrap()
+            .meta;
+        assert_eq!(meta.fn_params["greet"], ["name", "*", "punct"]);
+        let rendered = meta.render();
+        assert!(
+            rendered.contains("default greet punct \"! \"\n"),
+            "{rendered}"
+        );
+        let read = ModuleMeta::parse(&rendered).unwrap();
+        assert_eq!(read.render(), rendered);
+        assert!(ModuleMeta::parse("default greet punct\n").is_err());
+
+        let app = "from greeting import greet\n\ndef main():\n    greet(\"Ada\")\n";
+        let parsed =
+            crate::parser::parse_with_imports(app, &[], std::slice::from_ref(&read), false)
+                .unwrap();
+        assert!(parsed.meta.fn_defaults.is_empty());
+        let Some(e::Item::Function(main)) = parsed.module.items.last() else {
+            panic!("expected main");
+        };
+        let e::Stmt::Expr(e::Expr::Call { args, .. }) = &main.body.statements[0] else {
+            panic!("expected a call");
+        };
+        assert_eq!(args.len(), 2, "{args:?}");
+    }
+
+    #[test]
+    fn test_imported_meta_reorders_kwargs() {
+        let shapes = "type Point:\n    x: i64\n    y: i64\n\ndef area(width: f64, height: f64) -> f64:\n    return width * height\n";
+        let meta = crate::parser::parse_with_interfaces(shapes, &[], false)
+            .unwrap()
+            .meta;
+        let app = "from shapes import area, Point\n\ndef main():\n    area(height=2, width=3)\n    Point(1, 2)\n";
+        let parsed =
+            crate::parser::parse_with_imports(app, &[], std::slice::from_ref(&meta), false)
+                .unwrap();
+        // Only the module's own definitions are recorded for its importers
+        assert_eq!(parsed.meta.fn_params.keys().collect::<Vec<_>>(), ["main"]);
+        assert!(parsed.meta.struct_fields.is_empty());
+        let main = parsed
+            .module
+            .items
+            .iter()
+            .find_map(|item| match item {
+                e::Item::Function(f) if f.name == "main" => Some(f),
+                _ => None,
+            })
+            .unwrap();
+        let e::Stmt::Expr(e::Expr::Call { args, .. }) = &main.body.statements[0] else {
+            panic!("expected a call");
+        };
+        assert!(matches!(&args[0], e::Expr::Int(3)), "{args:?}");
+        assert!(matches!(
+            &main.body.statements[1],
+            e::Stmt::Expr(e::Expr::StructLiteral { .. })
+        ));
+    }
+}
@@ -0,0 +1,1227 @@
+//! qtest harness synthesis — turns a test module into a runnable test binary.
+//!
+//! `quiche <file.q> --test` parses the file, then replaces its `main` with a
+//! generated one that runs every top-level `test_*` function, and every
+//! function marked `@test`, through `Qtest.run`. `@test` functions can sit
+//! next to the code they test: normal builds strip them. Test parameters
+//! are resolved by name against `@fixture` functions, which may themselves
+//! request other fixtures:
+//!
+//! ```text
+//! @fixture(scope="module")
+//! def db() -> Db:
+//!     conn = Db.open()
+//!     yield conn           # statements after yield run as teardown
+//!     conn.close()
+//!
+//! def test_insert(db: Db):
+//!     ...
+//! ```
+//!
+//! Function-scoped fixtures (the default) are built fresh for each test and
+//! torn down right after it; module-scoped fixtures are built once before the
+//! first test and torn down after the last one. A fixture that panics is
+//! reported as an error: against its test for a function-scoped fixture, or
+//! against every test for a module-scoped one, which then don't run.
+//!
+//! Tests can be marked with `@skip("reason")`, `@skipif(cond, "reason")`
+//! (`cond` is evaluated when the harness runs) and `@xfail("reason")` /
+//! `@xfail("reason", strict=True)`; outcomes and reasons show up in the
+//! summary.
+//!
+//! `@given(int, Str)` turns a test into a property: its parameters are
+//! generated rather than injected, and failing inputs are shrunk (see the
+//! `Qtest.given` runtime).
+//!
+//! `mock(f, replacement)` inside a test swaps out a function defined in the
+//! module for the rest of that test: every mocked function gets a shim that
+//! consults `Qtest.mocked` before falling through to the original body.
+//!
+//! A module may define `setup_module()` and `teardown_module()`, which run
+//! once before the first test (and before module fixtures) and once after
+//! the last. A panic in either is reported as an error against the whole
+//! module; if setup fails, no tests run and each is reported as an error.
+//!
+//! Session-wide `setup_session()` / `teardown_session()` hooks live in
+//! `tests/conftest.q`; `quiche test` runs them once around all test files
+//! via [`build_hook_main`].
+//!
+//! When the module was parsed with coverage probes, the harness registers
+//! the probed lines so `Qtest.finish` can write an lcov record for the file.
+
+use crate::parser::{Decorator, ParsedModule, is_inline_test, string_literal};
+use elevate::ast as e;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Function,
+    Module,
+}
+
+/// Markers on a test function, from `@skip` / `@skipif` / `@xfail`.
+#[derive(Debug, Clone, Default)]
+struct Markers {
+    skip: Option<e::Expr>,
+    /// `(condition, reason)` pairs, checked in order.
+    skipif: Vec<(e::Expr, e::Expr)>,
+    /// `(reason, strict)`
+    xfail: Option<(e::Expr, e::Expr)>,
+    /// Rust types of the `@given(...)` arguments, one per test parameter.
+    given: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+struct Fixture {
+    scope: Scope,
+    /// Fixtures this fixture requests, by parameter name.
+    deps: Vec<String>,
+    has_teardown: bool,
+}
+
+/// Replace the module's `main` with a generated test harness.
+///
+/// Leaves the module untouched if it defines no tests, so
+/// script-style test files that drive themselves from `main` keep working.
+pub fn build_harness(parsed: &mut ParsedModule, source_name: &str) -> Result<(), String> {
+    apply_mocks(&mut parsed.module)?;
+    let module = &mut parsed.module;
+    let decorators = &parsed.decorators;
+    let functions: HashMap<&str, &e::FunctionDef> = module
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            e::Item::Function(f) => Some((f.name.as_str(), f)),
+            _ => None,
+        })
+        .collect();
+
+    let mut fixtures = HashMap::new();
+    for (name, decos) in decorators {
+        let Some(deco) = decos.iter().find(|d| d.name == "fixture") else {
+            continue;
+        };
+        let Some(func) = functions.get(name.as_str()) else {
+            continue;
+        };
+        fixtures.insert(
+            name.clone(),
+            Fixture {
+                scope: fixture_scope(name, deco)?,
+                deps: func.params.iter().map(|p| p.name.clone()).collect(),
+                has_teardown: functions.contains_key(teardown_name(name).as_str()),
+            },
+        );
+    }
+
+    let tests: Vec<(String, Vec<String>)> = module
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            e::Item::Function(f)
+                if (f.name.starts_with("test_") || is_inline_test(decorators, &f.name))
+                    && !fixtures.contains_key(&f.name) =>
+            {
+                Some((
+                    f.name.clone(),
+                    f.params.iter().map(|p| p.name.clone()).collect(),
+                ))
+            }
+            _ => None,
+        })
+        .collect();
+    if tests.is_empty() {
+        return Ok(());
+    }
+
+    for (name, fixture) in &fixtures {
+        for dep in &fixture.deps {
+            match fixtures.get(dep) {
+                None => return Err(format!("fixture '{name}' requests unknown fixture '{dep}'")),
+                Some(d) if fixture.scope == Scope::Module && d.scope == Scope::Function => {
+                    return Err(format!(
+                        "module-scoped fixture '{name}' cannot request function-scoped fixture '{dep}'"
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    // Resolve every test's fixtures up front so errors surface before codegen.
+    let mut plans = Vec::new();
+    let mut module_order = Vec::new();
+    for (test, params) in &tests {
+        let markers = test_markers(test, decorators.get(test))?;
+        if let Some(types) = &markers.given
+            && types.len() != params.len()
+        {
+            return Err(format!(
+                "@given on '{test}' supplies {} value(s) but the test takes {} parameter(s)",
+                types.len(),
+                params.len()
+            ));
+        }
+        if markers.skip.is_some() || markers.given.is_some() {
+            plans.push((test.clone(), params.clone(), vec![], markers));
+            continue;
+        }
+        let mut order = Vec::new();
+        let mut visiting = Vec::new();
+        for param in params {
+            if !fixtures.contains_key(param) {
+                return Err(format!("test '{test}' requests unknown fixture '{param}'"));
+            }
+            resolve(param, &fixtures, &mut visiting, &mut order)?;
+        }
+        let (module_scoped, function_scoped): (Vec<String>, Vec<String>) = order
+            .into_iter()
+            .partition(|name| fixtures[name].scope == Scope::Module);
+        for name in module_scoped {
+            if !module_order.contains(&name) {
+                module_order.push(name);
+            }
+        }
+        plans.push((test.clone(), params.clone(), function_scoped, markers));
+    }
+    // Later tests may pull in module fixtures that earlier ones depend on;
+    // re-resolve to get a single dependency-respecting order.
+    let mut ordered = Vec::new();
+    for name in &module_order {
+        resolve(name, &fixtures, &mut Vec::new(), &mut ordered)?;
+    }
+    let module_order = ordered;
+
+    let var = |name: &str| match fixtures[name].scope {
+        Scope::Module => format!("__mfx_{name}"),
+        Scope::Function => format!("__fx_{name}"),
+    };
+    let call_with = |callee: &str, params: &[String]| {
+        call(
+            vec![callee.to_string()],
+            params.iter().map(|p| path(&var(p))).collect(),
+        )
+    };
+    // `|| name(deps...)`, for the `Qtest` call that builds fixture `name`
+    let build = |name: &str| e::Expr::Closure {
+        params: vec![],
+        return_type: None,
+        body: e::Block {
+            statements: vec![e::Stmt::Expr(call_with(name, &fixtures[name].deps))],
+        },
+    };
+    let runnable: Vec<&str> = plans
+        .iter()
+        .filter(|(_, _, _, markers)| markers.skip.is_none())
+        .map(|(test, ..)| test.as_str())
+        .collect();
+    let runnable = runnable.join(",");
+
+    let mut body = vec![e::Stmt::Expr(call(
+        vec!["Qtest".into(), "set_source".into()],
+        vec![string(source_name)],
+    ))];
+    // The user's `main` is replaced below, so its lines are not coverable.
+    let mut lines: Vec<usize> = parsed
+        .coverage
+        .iter()
+        .filter(|(func, _)| func.as_str() != "main")
+        .flat_map(|(_, lines)| lines.iter().copied())
+        .collect();
+    if !lines.is_empty() {
+        lines.sort_unstable();
+        lines.dedup();
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        body.push(e::Stmt::Expr(call(
+            vec!["Qtest".into(), "track_lines".into()],
+            vec![string(&lines.join(","))],
+        )));
+    }
+    let header = body;
+    let mut body = Vec::new();
+    // A panicking module fixture is reported against every test it would
+    // have run; a function fixture's panic is its test's error.
+    for name in &module_order {
+        body.push(assign(
+            &var(name),
+            call(
+                vec!["Qtest".into(), "module_fixture".into()],
+                vec![string(name), string(&runnable), build(name)],
+            ),
+        ));
+    }
+    for (test, params, function_scoped, markers) in &plans {
+        let skip = |reason: &e::Expr| {
+            e::Stmt::Expr(call(
+                vec!["Qtest".into(), "skip".into()],
+                vec![string(test), reason.clone()],
+            ))
+        };
+        if let Some(reason) = &markers.skip {
+            body.push(skip(reason));
+            continue;
+        }
+
+        let mut statements = Vec::new();
+        for name in function_scoped {
+            statements.push(assign(
+                &var(name),
+                call(
+                    vec!["Qtest".into(), "fixture".into()],
+                    vec![string(name), build(name)],
+                ),
+            ));
+        }
+        let statement = match &markers.given {
+            // Generated values arrive as one tuple: `|args| test(args.0, ...)`
+            Some(types) => {
+                let args: Vec<String> = (0..types.len()).map(|i| format!("args.{i}")).collect();
+                e::Stmt::RustBlock(format!(
+                    "Qtest::given::<({},)>(|args| {test}({}));",
+                    types.join(", "),
+                    args.join(", ")
+                ))
+            }
+            None => e::Stmt::Expr(call_with(test, params)),
+        };
+        // Teardown runs even when the test panics, then the panic goes on
+        // to `Qtest.run`.
+        let teardowns: Vec<e::Stmt> = function_scoped
+            .iter()
+            .rev()
+            .filter(|name| fixtures[*name].has_teardown)
+            .map(|name| e::Stmt::Expr(call(vec![teardown_name(name)], vec![path(&var(name))])))
+            .collect();
+        if teardowns.is_empty() {
+            statements.push(statement);
+        } else {
+            let attempt = e::Expr::Closure {
+                params: vec![],
+                return_type: None,
+                body: e::Block {
+                    statements: vec![statement],
+                },
+            };
+            statements.push(assign(
+                "__outcome",
+                call(vec!["Qtest".into(), "attempt".into()], vec![attempt]),
+            ));
+            statements.extend(teardowns);
+            statements.push(e::Stmt::Expr(call(
+                vec!["Qtest".into(), "resume".into()],
+                vec![path("__outcome")],
+            )));
+        }
+        let test_body = e::Expr::Closure {
+            params: vec![],
+            return_type: None,
+            body: e::Block { statements },
+        };
+        let mut run = vec![e::Stmt::Expr(match &markers.xfail {
+            Some((reason, strict)) => call(
+                vec!["Qtest".into(), "run_xfail".into()],
+                vec![string(test), reason.clone(), strict.clone(), test_body],
+            ),
+            None => call(
+                vec!["Qtest".into(), "run".into()],
+                vec![string(test), test_body],
+            ),
+        })];
+
+        // @skipif conditions wrap the run, first decorator outermost
+        for (condition, reason) in markers.skipif.iter().rev() {
+            run = vec![e::Stmt::If {
+                condition: condition.clone(),
+                then_block: e::Block {
+                    statements: vec![skip(reason)],
+                },
+                else_block: Some(e::Block { statements: run }),
+            }];
+        }
+        body.extend(run);
+    }
+    for name in module_order.iter().rev() {
+        if fixtures[name].has_teardown {
+            body.push(e::Stmt::Expr(call(
+                vec![teardown_name(name)],
+                vec![path(&var(name))],
+            )));
+        }
+    }
+
+    // setup_module()/teardown_module() bracket the whole module; if setup
+    // fails, every test is reported as an error instead of being run.
+    let hook = |name: &str| {
+        functions.contains_key(name).then(|| e::Expr::Closure {
+            params: vec![],
+            return_type: None,
+            body: e::Block {
+                statements: vec![e::Stmt::Expr(call(vec![name.to_string()], vec![]))],
+            },
+        })
+    };
+    if let Some(teardown) = hook("teardown_module") {
+        body.push(e::Stmt::Expr(call(
+            vec!["Qtest".into(), "teardown_module".into()],
+            vec![teardown],
+        )));
+    }
+    if let Some(setup) = hook("setup_module") {
+        let failed = tests
+            .iter()
+            .map(|(test, _)| {
+                e::Stmt::Expr(call(
+                    vec!["Qtest".into(), "setup_failed".into()],
+                    vec![string(test)],
+                ))
+            })
+            .collect();
+        body = vec![e::Stmt::If {
+            condition: call(vec!["Qtest".into(), "setup_module".into()], vec![setup]),
+            then_block: e::Block { statements: body },
+            else_block: Some(e::Block { statements: failed }),
+        }];
+    }
+    let mut body = [header, body].concat();
+    body.push(e::Stmt::Expr(call(
+        vec!["Qtest".into(), "finish".into()],
+        vec![],
+    )));
+
+    replace_main(module, body);
+    Ok(())
+}
+
+/// Replace the module's `main` with a call to the session hook `hook`
+/// (`setup_session` / `teardown_session` in `tests/conftest.q`). A module
+/// without that hook gets an empty `main`, so the runner can call both
+/// unconditionally.
+pub fn build_hook_main(module: &mut e::Module, hook: &str) {
+    let defined = module
+        .items
+        .iter()
+        .any(|item| matches!(item, e::Item::Function(f) if f.name == hook));
+    let statements = if defined {
+        vec![e::Stmt::Expr(call(vec![hook.to_string()], vec![]))]
+    } else {
+        vec![]
+    };
+    replace_main(module, statements);
+}
+
+fn replace_main(module: &mut e::Module, statements: Vec<e::Stmt>) {
+    module
+        .items
+        .retain(|item| !matches!(item, e::Item::Function(f) if f.name == "main"));
+    module.items.push(e::Item::Function(e::FunctionDef {
+        visibility: e::Visibility::Public,
+        name: "main".into(),
+        type_params: vec![],
+        params: vec![],
+        return_type: None,
+        effect_row: None,
+        body: e::Block { statements },
+        span: None,
+    }));
+}
+
+/// Resolve `__mock__!(target, replacement)` statements into `Qtest::mock`
+/// registrations and give every mocked function an indirection shim:
+///
+/// ```text
+/// def load(path: Str) -> Str        fn load(path: Str) -> Str {
+///     ...                    →          if let Some(m) = Qtest::mocked::<fn(_) -> _>("load") {
+///                                           return m(path);
+///                                       }
+///                                       return __unmocked_load(path);
+///                                   }
+/// ```
+fn apply_mocks(module: &mut e::Module) -> Result<(), String> {
+    let arities: HashMap<String, usize> = module
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            e::Item::Function(f) => Some((f.name.clone(), f.params.len())),
+            _ => None,
+        })
+        .collect();
+
+    let mut targets = Vec::new();
+    for item in &mut module.items {
+        if let e::Item::Function(f) = item {
+            rewrite_mock_calls(&mut f.body, &arities, &mut targets)?;
+        }
+    }
+    if targets.is_empty() {
+        return Ok(());
+    }
+
+    let mut shims = Vec::new();
+    for item in &mut module.items {
+        let e::Item::Function(f) = item else {
+            continue;
+        };
+        if !targets.contains(&f.name) {
+            continue;
+        }
+        let name = f.name.clone();
+        let args: Vec<String> = f.params.iter().map(|p| p.name.clone()).collect();
+        let mut shim = f.clone();
+        f.name = format!("__unmocked_{name}");
+        shim.body = e::Block {
+            statements: vec![
+                e::Stmt::RustBlock(format!(
+                    "if let Some(mocked) = Qtest::mocked::<{}>(\"{name}\") {{ return mocked({}); }}",
+                    fn_pointer_type(args.len()),
+                    args.join(", ")
+                )),
+                e::Stmt::Return(Some(call(
+                    vec![f.name.clone()],
+                    args.iter().map(|a| path(a)).collect(),
+                ))),
+            ],
+        };
+        shims.push(e::Item::Function(shim));
+    }
+    module.items.extend(shims);
+    Ok(())
+}
+
+/// Rewrite the `mock(...)` calls in `block`, including those in nested
+/// blocks and in closures such as the one a `try` body is lowered to.
+fn rewrite_mock_calls(
+    block: &mut e::Block,
+    arities: &HashMap<String, usize>,
+    targets: &mut Vec<String>,
+) -> Result<(), String> {
+    for stmt in &mut block.statements {
+        match stmt {
+            e::Stmt::Expr(e::Expr::MacroCall {
+                path: macro_path,
+                args,
+            }) if macro_path.len() == 1 && macro_path[0] == "__mock__" => {
+                let name = |expr: &e::Expr| match expr {
+                    e::Expr::Path(p) => p.join("::"),
+                    _ => String::new(),
+                };
+                let (target, replacement) = (name(&args[0]), name(&args[1]));
+                let Some(arity) = arities.get(&target) else {
+                    return Err(format!(
+                        "mock target '{target}' is not a function defined in this module"
+                    ));
+                };
+                *stmt = e::Stmt::RustBlock(format!(
+                    "Qtest::mock(\"{target}\", {replacement} as {});",
+                    fn_pointer_type(*arity)
+                ));
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+            e::Stmt::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                rewrite_mock_calls(then_block, arities, targets)?;
+                if let Some(else_block) = else_block {
+                    rewrite_mock_calls(else_block, arities, targets)?;
+                }
+            }
+            e::Stmt::While { body, .. } | e::Stmt::For { body, .. } => {
+                rewrite_mock_calls(body, arities, targets)?;
+            }
+            e::Stmt::Const(e::ConstDef { value, .. })
+            | e::Stmt::DestructureConst { value, .. }
+            | e::Stmt::Assign { value, .. }
+            | e::Stmt::Return(Some(value))
+            | e::Stmt::Expr(value)
+            | e::Stmt::TailExpr(value) => rewrite_closure_mocks(value, arities, targets)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// [`rewrite_mock_calls`] for the bodies of the closures in `expr`.
+fn rewrite_closure_mocks(
+    expr: &mut e::Expr,
+    arities: &HashMap<String, usize>,
+    targets: &mut Vec<String>,
+) -> Result<(), String> {
+    match expr {
+        e::Expr::Closure { body, .. } => rewrite_mock_calls(body, arities, targets),
+        e::Expr::Call { callee, args } => {
+            rewrite_closure_mocks(callee, arities, targets)?;
+            args.iter_mut()
+                .try_for_each(|arg| rewrite_closure_mocks(arg, arities, targets))
+        }
+        e::Expr::MacroCall { args, .. } | e::Expr::Array(args) | e::Expr::Tuple(args) => args
+            .iter_mut()
+            .try_for_each(|arg| rewrite_closure_mocks(arg, arities, targets)),
+        e::Expr::Match { scrutinee, arms } => {
+            rewrite_closure_mocks(scrutinee, arities, targets)?;
+            arms.iter_mut()
+                .try_for_each(|arm| rewrite_closure_mocks(&mut arm.value, arities, targets))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `fn(_, _) -> _` for the given arity — lets rustc infer the concrete
+/// pointer type from the mocked function's signature.
+fn fn_pointer_type(arity: usize) -> String {
+    format!("fn({}) -> _", vec!["_"; arity].join(", "))
+}
+
+fn test_markers(test: &str, decorators: Option<&Vec<Decorator>>) -> Result<Markers, String> {
+    let mut markers = Markers::default();
+    for deco in decorators.into_iter().flatten() {
+        let reason = |index: usize| {
+            deco.args
+                .get(index)
+                .or_else(|| deco.kwarg("reason"))
+                .cloned()
+                .unwrap_or_else(|| string(""))
+        };
+        match deco.name.as_str() {
+            "skip" => markers.skip = Some(reason(0)),
+            "skipif" => {
+                let Some(condition) = deco.args.first().cloned() else {
+                    return Err(format!("@skipif on '{test}' needs a condition"));
+                };
+                markers.skipif.push((condition, reason(1)));
+            }
+            "xfail" => {
+                let strict = deco
+                    .kwarg("strict")
+                    .cloned()
+                    .unwrap_or(e::Expr::Bool(false));
+                markers.xfail = Some((reason(0), strict));
+            }
+            "given" => {
+                let types = deco
+                    .args
+                    .iter()
+                    .map(|arg| {
+                        given_type(arg).ok_or_else(|| {
+                            format!("@given on '{test}': unsupported type argument {arg:?}")
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if types.is_empty() {
+                    return Err(format!("@given on '{test}' needs at least one type"));
+                }
+                markers.given = Some(types);
+            }
+            _ => {}
+        }
+    }
+    Ok(markers)
+}
+
+/// Rust type for a `@given` argument: a type name (`int`, `Str`, ...)
+/// optionally applied as `Vec[int]` / `Option[Str]`.
+fn given_type(expr: &e::Expr) -> Option<String> {
+    match expr {
+        e::Expr::Path(path) => Some(
+            match path.join("::").as_str() {
+                "int" => "i64",
+                "float" => "f64",
+                "str" => "Str",
+                "list" => "List",
+                other => other,
+            }
+            .to_string(),
+        ),
+        e::Expr::Index { base, index } => {
+            let args = match &**index {
+                e::Expr::Tuple(items) => {
+                    items.iter().map(given_type).collect::<Option<Vec<_>>>()?
+                }
+                single => vec![given_type(single)?],
+            };
+            Some(format!("{}<{}>", given_type(base)?, args.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+fn fixture_scope(name: &str, deco: &Decorator) -> Result<Scope, String> {
+    let Some(scope) = deco.kwarg("scope") else {
+        return Ok(Scope::Function);
+    };
+    match string_literal(scope).as_deref() {
+        Some("function") => Ok(Scope::Function),
+        Some("module") => Ok(Scope::Module),
+        _ => Err(format!(
+            "fixture '{name}': scope must be \"function\" or \"module\""
+        )),
+    }
+}
+
+fn teardown_name(fixture: &str) -> String {
+    format!("__teardown_{fixture}")
+}
+
+/// Depth-first dependency resolution: appends `name` to `order` after all of
+/// the fixtures it requests, reporting cycles.
+fn resolve(
+    name: &str,
+    fixtures: &HashMap<String, Fixture>,
+    visiting: &mut Vec<String>,
+    order: &mut Vec<String>,
+) -> Result<(), String> {
+    if order.iter().any(|n| n == name) {
+        return Ok(());
+    }
+    if visiting.iter().any(|n| n == name) {
+        visiting.push(name.to_string());
+        return Err(format!("fixture cycle: {}", visiting.join(" -> ")));
+    }
+    visiting.push(name.to_string());
+    for dep in &fixtures[name].deps {
+        resolve(dep, fixtures, visiting, order)?;
+    }
+    visiting.pop();
+    order.push(name.to_string());
+    Ok(())
+}
+
+fn path(name: &str) -> e::Expr {
+    e::Expr::Path(vec![name.to_string()])
+}
+
+fn call(callee: Vec<String>, args: Vec<e::Expr>) -> e::Expr {
+    e::Expr::Call {
+        callee: Box::new(e::Expr::Path(callee)),
+        args,
+    }
+}
+
+fn string(s: &str) -> e::Expr {
+    call(vec!["str".into()], vec![e::Expr::String(s.to_string())])
+}
+
+fn assign(name: &str, value: e::Expr) -> e::Stmt {
+    e::Stmt::Assign {
+        target: e::AssignTarget::Path(name.to_string()),
+        op: e::AssignOp::Assign,
+        value,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{build_harness, build_hook_main};
+    use crate::parser::{parse_decorated, parse_instrumented, string_literal};
+    use elevate::ast::*;
+
+    fn harness(source: &str) -> Result<Module, String> {
+        let mut parsed = parse_decorated(source).map_err(|e| e.to_string())?;
+        build_harness(&mut parsed, "tests/test_x.q")?;
+        Ok(parsed.module)
+    }
+
+    fn main_body(module: &Module) -> Vec<Stmt> {
+        module
+            .items
+            .iter()
+            .find_map(|item| match item {
+                Item::Function(f) if f.name == "main" => Some(f.body.statements.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn callee_names(stmts: &[Stmt]) -> Vec<String> {
+        stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Expr(Expr::Call { callee, .. })
+                | Stmt::Assign {
+                    value: Expr::Call { callee, .. },
+                    ..
+                } => match &**callee {
+                    Expr::Path(p) => Some(p.join("::")),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The statements of the test closure passed to `Qtest.run`.
+    fn run_body(stmt: &Stmt) -> Vec<Stmt> {
+        match stmt {
+            Stmt::Expr(Expr::Call { args, .. }) => match args.last() {
+                Some(Expr::Closure { body, .. }) => body.statements.clone(),
+                other => panic!("Expected test closure, got {:?}", other),
+            },
+            other => panic!("Expected Qtest.run, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_harness_replaces_main() {
+        let module =
+            harness("def test_a():\n    assert 1 == 1\n\ndef main():\n    test_a()\n").unwrap();
+        let mains = module
+            .items
+            .iter()
+            .filter(|i| matches!(i, Item::Function(f) if f.name == "main"))
+            .count();
+        assert_eq!(mains, 1);
+        assert_eq!(
+            callee_names(&main_body(&module)),
+            vec!["Qtest::set_source", "Qtest::run", "Qtest::finish"]
+        );
+    }
+
+    #[test]
+    fn test_harness_without_tests_keeps_main() {
+        let module = harness("def main():\n    print(1)\n").unwrap();
+        assert!(callee_names(&main_body(&module)).is_empty());
+    }
+
+    #[test]
+    fn test_inline_test_functions() {
+        let source = "\
+def add(a: int, b: int) -> int:
+    return a + b
+
+@test
+def adds_small_numbers():
+    assert add(1, 2) == 3
+
+def main():
+    print(add(1, 2))
+";
+        let module = harness(source).unwrap();
+        let run_names: Vec<String> = main_body(&module)
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Expr(Expr::Call { callee, args })
+                    if matches!(&**callee, Expr::Path(p) if p == &["Qtest", "run"]) =>
+                {
+                    string_literal(&args[0])
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(run_names, vec!["adds_small_numbers"]);
+
+        let build = crate::parser::parse(source).unwrap();
+        assert!(
+            !build
+                .items
+                .iter()
+                .any(|i| matches!(i, Item::Function(f) if f.name == "adds_small_numbers"))
+        );
+
+        let err = parse_decorated("@test\ntype T:\n    x: int\n").unwrap_err();
+        assert!(err.message.contains("@test applies to functions"), "{err}");
+    }
+
+    #[test]
+    fn test_function_fixture_per_test_with_teardown() {
+        let source = "\
+@fixture
+def num() -> int:
+    n = 41
+    yield n
+    print(n)
+
+def test_a(num: int):
+    assert num == 41
+
+def test_b(num: int):
+    assert num > 0
+";
+        let module = harness(source).unwrap();
+        let body = main_body(&module);
+        assert_eq!(
+            callee_names(&body),
+            vec![
+                "Qtest::set_source",
+                "Qtest::run",
+                "Qtest::run",
+                "Qtest::finish"
+            ]
+        );
+        assert_eq!(
+            callee_names(&run_body(&body[1])),
+            vec![
+                "Qtest::fixture",
+                "Qtest::attempt",
+                "__teardown_num",
+                "Qtest::resume"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_module_fixture_built_once() {
+        let source = "\
+@fixture(scope=\"module\")
+def base() -> int:
+    return 1
+
+@fixture
+def derived(base: int) -> int:
+    return base + 1
+
+def test_a(derived: int):
+    pass
+
+def test_b(base: int):
+    pass
+";
+        let module = harness(source).unwrap();
+        let body = main_body(&module);
+        assert_eq!(
+            callee_names(&body),
+            vec![
+                "Qtest::set_source",
+                "Qtest::module_fixture",
+                "Qtest::run",
+                "Qtest::run",
+                "Qtest::finish"
+            ]
+        );
+        match &body[1] {
+            Stmt::Assign {
+                value: Expr::Call { args, .. },
+                ..
+            } => assert_eq!(string_literal(&args[1]).as_deref(), Some("test_a,test_b")),
+            other => panic!("Expected module fixture, got {:?}", other),
+        }
+        assert_eq!(
+            callee_names(&run_body(&body[2])),
+            vec!["Qtest::fixture", "test_a"]
+        );
+        assert_eq!(callee_names(&run_body(&body[3])), vec!["test_b"]);
+    }
+
+    #[test]
+    fn test_unknown_fixture_is_error() {
+        let err = harness("def test_a(missing: int):\n    pass\n").unwrap_err();
+        assert!(err.contains("unknown fixture 'missing'"), "{err}");
+    }
+
+    #[test]
+    fn test_fixture_cycle_is_error() {
+        let source = "\
+@fixture
+def a(b: int) -> int:
+    return b
+
+@fixture
+def b(a: int) -> int:
+    return a
+
+def test_x(a: int):
+    pass
+";
+        let err = harness(source).unwrap_err();
+        assert!(err.contains("fixture cycle"), "{err}");
+    }
+
+    #[test]
+    fn test_module_fixture_cannot_use_function_fixture() {
+        let source = "\
+@fixture
+def a() -> int:
+    return 1
+
+@fixture(scope=\"module\")
+def b(a: int) -> int:
+    return a
+
+def test_x(b: int):
+    pass
+";
+        let err = harness(source).unwrap_err();
+        assert!(err.contains("cannot request function-scoped"), "{err}");
+    }
+
+    #[test]
+    fn test_coverage_lines_registered_without_main() {
+        let source = "def test_a():\n    x = 1\n    assert x == 1\n\ndef main():\n    test_a()\n";
+        let mut parsed = parse_instrumented(source).unwrap();
+        build_harness(&mut parsed, "tests/test_x.q").unwrap();
+        let body = main_body(&parsed.module);
+        assert_eq!(
+            callee_names(&body)[..2],
+            ["Qtest::set_source", "Qtest::track_lines"]
+        );
+        match &body[1] {
+            Stmt::Expr(Expr::Call { args, .. }) => match &args[0] {
+                Expr::Call { args, .. } => {
+                    assert!(matches!(&args[0], Expr::String(s) if s == "2,3"))
+                }
+                other => panic!("Expected str() call, got {:?}", other),
+            },
+            other => panic!("Expected Call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mock_rewrites_registration_and_shims_target() {
+        let source = "\
+def load(path: Str) -> Str:
+    return File.read(path)
+
+def fake_load(path: Str) -> Str:
+    return \"fake\"
+
+def test_load():
+    mock(load, fake_load)
+    assert load(\"x\") == \"fake\"
+";
+        let module = harness(source).unwrap();
+        let function = |name: &str| {
+            module
+                .items
+                .iter()
+                .find_map(|item| match item {
+                    Item::Function(f) if f.name == name => Some(f.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert!(matches!(
+            &function("test_load").body.statements[0],
+            Stmt::RustBlock(code) if code == "Qtest::mock(\"load\", fake_load as fn(_) -> _);"
+        ));
+        let shim = function("load");
+        assert!(matches!(
+            &shim.body.statements[0],
+            Stmt::RustBlock(code) if code.contains("Qtest::mocked::<fn(_) -> _>(\"load\")")
+        ));
+        assert_eq!(function("__unmocked_load").params.len(), 1);
+    }
+
+    #[test]
+    fn test_mock_inside_try_and_with_is_rewritten() {
+        let source = "\
+def load(path: Str) -> Str:
+    return File.read(path)
+
+def fake_load(path: Str) -> Str:
+    return \"fake\"
+
+def test_load():
+    try:
+        mock(load, fake_load)
+    except ValueError:
+        pass
+    with open(\"x\") as f:
+        mock(load, fake_load)
+";
+        let module = harness(source).unwrap();
+        let test = module
+            .items
+            .iter()
+            .find_map(|item| match item {
+                Item::Function(f) if f.name == "test_load" => Some(f),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(mock_registrations(&test.body.statements), 2);
+        assert!(
+            module
+                .items
+                .iter()
+                .any(|item| matches!(item, Item::Function(f) if f.name == "__unmocked_load"))
+        );
+    }
+
+    /// `Qtest::mock("load", ...)` registrations in `stmts`, counting those in
+    /// `if` blocks and in closures passed to calls.
+    fn mock_registrations(stmts: &[Stmt]) -> usize {
+        stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::RustBlock(code) if code.starts_with("Qtest::mock(\"load\"") => 1,
+                Stmt::If {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    mock_registrations(&then_block.statements)
+                        + else_block
+                            .as_ref()
+                            .map_or(0, |block| mock_registrations(&block.statements))
+                }
+                Stmt::Assign {
+                    value: Expr::Call { args, .. },
+                    ..
+                }
+                | Stmt::Expr(Expr::Call { args, .. }) => args
+                    .iter()
+                    .map(|arg| match arg {
+                        Expr::Closure { body, .. } => mock_registrations(&body.statements),
+                        _ => 0,
+                    })
+                    .sum(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_mock_unknown_target_is_error() {
+        let err = harness("def test_a():\n    mock(nope, other)\n").unwrap_err();
+        assert!(err.contains("mock target 'nope'"), "{err}");
+    }
+
+    #[test]
+    fn test_skip_and_xfail_markers() {
+        let source = "\
+@fixture
+def num() -> int:
+    return 1
+
+@skip(\"not ready\")
+def test_skipped(num: int):
+    pass
+
+@xfail(\"known bug\", strict=True)
+def test_broken():
+    assert False
+
+@skipif(1 > 0, reason=\"always\")
+def test_conditional(num: int):
+    pass
+";
+        let module = harness(source).unwrap();
+        let body = main_body(&module);
+        assert_eq!(
+            callee_names(&body),
+            vec![
+                "Qtest::set_source",
+                "Qtest::skip",
+                "Qtest::run_xfail",
+                "Qtest::finish"
+            ]
+        );
+        match &body[3] {
+            Stmt::If {
+                then_block,
+                else_block: Some(else_block),
+                ..
+            } => {
+                assert_eq!(callee_names(&then_block.statements), vec!["Qtest::skip"]);
+                assert_eq!(callee_names(&else_block.statements), vec!["Qtest::run"]);
+                assert_eq!(
+                    callee_names(&run_body(&else_block.statements[0])),
+                    vec!["Qtest::fixture", "test_conditional"]
+                );
+            }
+            other => panic!("Expected skipif guard, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_skipif_without_condition_is_error() {
+        let err = harness("@skipif\ndef test_a():\n    pass\n").unwrap_err();
+        assert!(err.contains("needs a condition"), "{err}");
+    }
+
+    #[test]
+    fn test_module_hooks_wrap_tests() {
+        let source = "\
+def setup_module():
+    pass
+
+def teardown_module():
+    pass
+
+def test_a():
+    pass
+
+def test_b():
+    pass
+";
+        let module = harness(source).unwrap();
+        let body = main_body(&module);
+        assert_eq!(
+            callee_names(&body),
+            vec!["Qtest::set_source", "Qtest::finish"]
+        );
+        match &body[1] {
+            Stmt::If {
+                condition: Expr::Call { callee, .. },
+                then_block,
+                else_block: Some(else_block),
+            } => {
+                assert!(
+                    matches!(&**callee, Expr::Path(p) if p.join("::") == "Qtest::setup_module")
+                );
+                assert_eq!(
+                    callee_names(&then_block.statements),
+                    vec!["Qtest::run", "Qtest::run", "Qtest::teardown_module"]
+                );
+                assert_eq!(
+                    callee_names(&else_block.statements),
+                    vec!["Qtest::setup_failed", "Qtest::setup_failed"]
+                );
+            }
+            other => panic!("Expected setup_module guard, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_teardown_module_without_setup() {
+        let source = "def teardown_module():\n    pass\n\ndef test_a():\n    pass\n";
+        let module = harness(source).unwrap();
+        assert_eq!(
+            callee_names(&main_body(&module)),
+            vec![
+                "Qtest::set_source",
+                "Qtest::run",
+                "Qtest::teardown_module",
+                "Qtest::finish"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_session_hook_main() {
+        let source = "def setup_session():\n    pass\n\ndef main():\n    print(1)\n";
+        let mut module = parse_decorated(source).unwrap().module;
+        build_hook_main(&mut module, "setup_session");
+        assert_eq!(callee_names(&main_body(&module)), vec!["setup_session"]);
+
+        build_hook_main(&mut module, "teardown_session");
+        assert!(main_body(&module).is_empty());
+    }
+
+    #[test]
+    fn test_given_runs_property() {
+        let source = "@given(int, Vec[str])\ndef test_prop(n: int, xs: Vec[Str]):\n    pass\n";
+        let module = harness(source).unwrap();
+        let body = main_body(&module);
+        let Stmt::Expr(Expr::Call { args, .. }) = &body[1] else {
+            panic!("Expected Qtest::run, got {:?}", body[1]);
+        };
+        match &args[1] {
+            Expr::Closure { body, .. } => match &body.statements[..] {
+                [Stmt::RustBlock(code)] => assert_eq!(
+                    code,
+                    "Qtest::given::<(i64, Vec<Str>,)>(|args| test_prop(args.0, args.1));"
+                ),
+                other => panic!("Expected given call, got {:?}", other),
+            },
+            other => panic!("Expected closure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_given_arity_mismatch_is_error() {
+        let err = harness("@given(int)\ndef test_a(a: int, b: int):\n    pass\n").unwrap_err();
+        assert!(err.contains("supplies 1 value(s)"), "{err}");
+    }
+}
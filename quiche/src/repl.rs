@@ -0,0 +1,318 @@
+//! `quiche repl` — an interactive session, recompiled whole for each input.
+//!
+//! Like [`crate::Engine`], the session has no interpreter behind it: every
+//! input compiles, together with everything entered before it, into one
+//! program that `rustc` builds and runs. Definitions (`def`, `type`,
+//! imports) are kept, a new one replacing any earlier definition of the
+//! same name. Statements are replayed ahead of the input with their output
+//! hidden, so variables carry over. An input that ends in an expression
+//! shows its value, as Python's REPL does.
+//!
+//! An input that fails to compile or panics is dropped from the session.
+//! Replaying repeats side effects other than printing (writing a file,
+//! reading stdin, which is closed), so those are better kept inside
+//! functions.
+
+use std::path::PathBuf;
+
+use crate::engine::{is_definition, run_snippet, top_level_chunks};
+use crate::lexer::{Keyword, TokenKind, tokenize};
+use crate::{CompileOptions, default_options, parser};
+
+/// Printed between the replayed statements and the new input, which is all
+/// that is shown.
+const REPLAYED_MARKER: &str = "\u{1e}quiche-replayed";
+
+/// Printed before the value of an input ending in an expression.
+const ECHO_MARKER: &str = "\u{1e}quiche-echo";
+
+/// The definitions and statements entered so far.
+pub struct Session {
+    definitions: Vec<String>,
+    statements: Vec<String>,
+    options: CompileOptions,
+    /// quiche-lib's `lib.rs`, or `None` for the vendored copy.
+    lib_src: Option<PathBuf>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            definitions: Vec::new(),
+            statements: Vec::new(),
+            options: default_options(),
+            lib_src: None,
+        }
+    }
+
+    /// Use the quiche-lib source at `lib_rs` (a `lib.rs` path).
+    pub fn with_lib(mut self, lib_rs: impl Into<PathBuf>) -> Self {
+        self.lib_src = Some(lib_rs.into());
+        self
+    }
+
+    pub fn with_options(mut self, options: CompileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Forget everything entered so far.
+    pub fn reset(&mut self) {
+        self.definitions.clear();
+        self.statements.clear();
+    }
+
+    /// Run `input` after the session so far, returning what it printed
+    /// (and its value, if it ends in an expression). It joins the session
+    /// only if it compiles and runs.
+    pub fn eval(&mut self, input: &str) -> Result<String, String> {
+        let (definitions, statements) = self.with_input(input);
+        let echo = statements
+            .last()
+            .filter(|last| is_expression(last))
+            .cloned();
+        let compiled = match &echo {
+            Some(expr) => {
+                let shown = &statements[..statements.len() - 1];
+                self.compile(&definitions, shown, Some(expr))
+                    .or_else(|_| self.compile(&definitions, &statements, None))?
+            }
+            None => self.compile(&definitions, &statements, None)?,
+        };
+        let stdout = run_snippet(&compiled, self.lib_src.as_deref())?;
+        self.definitions = definitions;
+        self.statements = statements;
+        Ok(new_output(&stdout))
+    }
+
+    /// The session's definitions and statements with `input`'s added.
+    fn with_input(&self, input: &str) -> (Vec<String>, Vec<String>) {
+        let mut definitions = self.definitions.clone();
+        let mut statements = self.statements.clone();
+        let mut decorators = String::new();
+        for chunk in top_level_chunks(input) {
+            if chunk.starts_with('@') {
+                decorators.push_str(&chunk);
+                decorators.push('\n');
+            } else if is_definition(&chunk) {
+                let chunk = format!("{}{chunk}", std::mem::take(&mut decorators));
+                let name = defined_name(&chunk);
+                definitions
+                    .retain(|old| old != &chunk && (name.is_none() || defined_name(old) != name));
+                definitions.push(chunk);
+            } else {
+                statements.push(chunk);
+            }
+        }
+        (definitions, statements)
+    }
+
+    /// Compile `definitions` with a `main` replaying the session's
+    /// statements, then running the new ones of `statements`, then showing
+    /// `echo` (if any).
+    fn compile(
+        &self,
+        definitions: &[String],
+        statements: &[String],
+        echo: Option<&str>,
+    ) -> Result<String, String> {
+        let mut source = definitions.join("\n");
+        source.push_str("\ndef main():\n");
+        let mut body: Vec<String> = statements.to_vec();
+        body.insert(
+            self.statements.len().min(body.len()),
+            format!("print(\"{REPLAYED_MARKER}\")"),
+        );
+        if let Some(expr) = echo {
+            body.push(format!("print(\"{ECHO_MARKER}\")"));
+            body.push(format!("pprint({expr})"));
+        }
+        for statement in &body {
+            for line in statement.lines() {
+                source.push_str(&format!("    {line}\n"));
+            }
+        }
+        let module = parser::parse(&source).map_err(|e| format!("{e}"))?;
+        crate::compile_module_file(
+            &module,
+            &source,
+            "<repl>",
+            &self.options,
+            &crate::Prelude::default(),
+        )
+    }
+}
+
+/// Whether `input` is incomplete: it opens a block or a decorator that no
+/// blank line has closed yet, or leaves a bracket open.
+pub fn needs_more(input: &str) -> bool {
+    let first = input.lines().next().unwrap_or("").trim_end();
+    if first.ends_with(':') || first.starts_with('@') {
+        return !input.ends_with("\n\n");
+    }
+    let Ok(tokens) = tokenize(input) else {
+        return false;
+    };
+    let mut depth = 0i32;
+    for token in &tokens {
+        match token.kind {
+            TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => depth += 1,
+            TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+/// Whether the statement `chunk` is a lone expression worth showing the
+/// value of: not an assignment, a keyword statement, or a `print`.
+fn is_expression(chunk: &str) -> bool {
+    if chunk.contains('\n') {
+        return false;
+    }
+    let Ok(tokens) = tokenize(chunk) else {
+        return false;
+    };
+    match tokens.first().map(|token| &token.kind) {
+        Some(TokenKind::Keyword(keyword))
+            if !matches!(
+                keyword,
+                Keyword::True | Keyword::False | Keyword::None | Keyword::Not
+            ) =>
+        {
+            return false;
+        }
+        Some(TokenKind::Ident(name)) if ["print", "eprint", "pprint"].contains(&name.as_str()) => {
+            return false;
+        }
+        None => return false,
+        _ => {}
+    }
+    let mut depth = 0i32;
+    for token in &tokens {
+        match token.kind {
+            TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => depth += 1,
+            TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => depth -= 1,
+            TokenKind::Eq
+            | TokenKind::ColonEq
+            | TokenKind::PlusEq
+            | TokenKind::MinusEq
+            | TokenKind::StarEq
+            | TokenKind::SlashEq
+            | TokenKind::PercentEq
+            | TokenKind::DoubleStarEq
+            | TokenKind::DoubleSlashEq
+            | TokenKind::PipeEq
+            | TokenKind::AmpEq
+            | TokenKind::CaretEq
+            | TokenKind::LShiftEq
+            | TokenKind::RShiftEq
+            | TokenKind::AtEq
+                if depth == 0 =>
+            {
+                return false;
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+/// The name a `def` or `type` chunk (after its decorators) defines.
+fn defined_name(chunk: &str) -> Option<&str> {
+    let line = chunk.lines().find(|line| !line.starts_with('@'))?;
+    let rest = line
+        .strip_prefix("def ")
+        .or_else(|| line.strip_prefix("type "))?;
+    let end = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+/// What the new input printed, from a run's `stdout`: everything after the
+/// replayed statements' output, with an echoed value of `()` (a call
+/// returning nothing) left out.
+fn new_output(stdout: &str) -> String {
+    let mut shown = String::new();
+    let mut echo = None;
+    let mut replayed = true;
+    for line in stdout.split_inclusive('\n') {
+        match line.trim_end_matches('\n') {
+            REPLAYED_MARKER => replayed = false,
+            ECHO_MARKER => echo = Some(String::new()),
+            _ if replayed => {}
+            _ => match &mut echo {
+                Some(echo) => echo.push_str(line),
+                None => shown.push_str(line),
+            },
+        }
+    }
+    if let Some(echo) = echo
+        && echo.trim_end() != "()"
+    {
+        shown.push_str(&echo);
+    }
+    shown
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inputs_replace_definitions_and_keep_statements() {
+        let mut session = Session::new();
+        session.definitions = vec!["def f() -> i64:\n    return 1".into(), "import math".into()];
+        session.statements = vec!["x = f()".into()];
+        let (definitions, statements) =
+            session.with_input("@inline\ndef f() -> i64:\n    return 2\n\nimport math\nx + 1\n");
+        assert_eq!(
+            definitions,
+            vec!["@inline\ndef f() -> i64:\n    return 2", "import math"]
+        );
+        assert_eq!(statements, vec!["x = f()", "x + 1"]);
+        assert_eq!(defined_name("type Point:\n    x: i64"), Some("Point"));
+        assert_eq!(defined_name("from a import b"), None);
+    }
+
+    #[test]
+    fn test_only_lone_expressions_are_echoed() {
+        assert!(is_expression("x + 1"));
+        assert!(is_expression("f(x=1)[0]"));
+        assert!(is_expression("not done"));
+        assert!(!is_expression("lambda x: x"));
+        assert!(!is_expression("x = 1"));
+        assert!(!is_expression("x: i64 = 1"));
+        assert!(!is_expression("x += 1"));
+        assert!(!is_expression("print(x)"));
+        assert!(!is_expression("assert x"));
+        assert!(!is_expression("for x in xs:\n    pass"));
+    }
+
+    #[test]
+    fn test_blocks_and_open_brackets_need_more_input() {
+        assert!(needs_more("def f():"));
+        assert!(needs_more("def f():\n    return 1\n"));
+        assert!(!needs_more("def f():\n    return 1\n\n"));
+        assert!(needs_more("@inline"));
+        assert!(needs_more("xs = [1,\n"));
+        assert!(!needs_more("xs = [1,\n  2]\n"));
+        assert!(!needs_more("x = 1\n"));
+    }
+
+    #[test]
+    fn test_new_output_hides_replayed_statements_and_unit_values() {
+        let stdout = format!("old\n{REPLAYED_MARKER}\nnew\n{ECHO_MARKER}\n[1, 2]\n");
+        assert_eq!(new_output(&stdout), "new\n[1, 2]\n");
+        let stdout = format!("old\n{REPLAYED_MARKER}\n{ECHO_MARKER}\n()\n");
+        assert_eq!(new_output(&stdout), "");
+    }
+}
@@ -0,0 +1,203 @@
+//! Building generated Rust into standalone executables.
+//!
+//! Scripts and embedded evaluation compile with bare `rustc`, pulling in
+//! quiche-lib as a `#[path]` module instead of a Cargo dependency (or, for
+//! `--opt-size`, as a precompiled rlib).
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Compile a standalone Rust source file to an executable with `rustc`.
+pub fn compile_rust_to_binary(rust_path: &Path, output_path: &Path) -> Result<(), String> {
+    rustc(rust_path, output_path, &[])
+}
+
+/// Compile a standalone Rust source file to a C-loadable shared library.
+pub fn compile_rust_to_cdylib(rust_path: &Path, output_path: &Path) -> Result<(), String> {
+    rustc(
+        rust_path,
+        output_path,
+        &["--crate-type", "cdylib", "-C", "opt-level=2"],
+    )
+}
+
+/// `rustc` flags of `--opt-size` builds: smallest code, one codegen unit and
+/// whole-program LTO (dropping the parts of quiche-lib a program never
+/// calls), and no symbol table.
+const SIZE_FLAGS: &[&str] = &[
+    "-C",
+    "opt-level=z",
+    "-C",
+    "codegen-units=1",
+    "-C",
+    "lto=fat",
+    "-C",
+    "strip=symbols",
+];
+
+/// Compile the quiche-lib source at `lib_src` into a size-optimized rlib in
+/// `out_dir`, which `--opt-size` builds link as a crate instead of including
+/// the library's source in every program.
+pub fn compile_quiche_lib_rlib(lib_src: &Path, out_dir: &Path) -> Result<PathBuf, String> {
+    let rlib = out_dir.join("libquiche_lib.rlib");
+    rustc(
+        lib_src,
+        &rlib,
+        &[
+            "--crate-type",
+            "rlib",
+            "--crate-name",
+            "quiche_lib",
+            "-C",
+            "opt-level=z",
+        ],
+    )?;
+    Ok(rlib)
+}
+
+/// Compile a standalone Rust source file to a size-optimized executable,
+/// linking quiche-lib from the rlib built by [`compile_quiche_lib_rlib`].
+pub fn compile_rust_to_small_binary(
+    rust_path: &Path,
+    output_path: &Path,
+    quiche_lib_rlib: &Path,
+) -> Result<(), String> {
+    rustc_small(rust_path, output_path, quiche_lib_rlib, &[])
+}
+
+/// Compile a standalone Rust source file to a size-optimized shared library,
+/// linking quiche-lib from the rlib built by [`compile_quiche_lib_rlib`].
+pub fn compile_rust_to_small_cdylib(
+    rust_path: &Path,
+    output_path: &Path,
+    quiche_lib_rlib: &Path,
+) -> Result<(), String> {
+    rustc_small(
+        rust_path,
+        output_path,
+        quiche_lib_rlib,
+        &["--crate-type", "cdylib"],
+    )
+}
+
+fn rustc_small(
+    rust_path: &Path,
+    output_path: &Path,
+    quiche_lib_rlib: &Path,
+    extra_args: &[&str],
+) -> Result<(), String> {
+    let extern_arg = format!("quiche_lib={}", quiche_lib_rlib.display());
+    let mut args = extra_args.to_vec();
+    args.extend(SIZE_FLAGS);
+    args.extend(["--extern", &extern_arg]);
+    rustc(rust_path, output_path, &args)
+}
+
+fn rustc(rust_path: &Path, output_path: &Path, extra_args: &[&str]) -> Result<(), String> {
+    let output = Command::new("rustc")
+        .arg("--edition=2021")
+        .args(extra_args)
+        .arg(rust_path)
+        .arg("-o")
+        .arg(output_path)
+        .output()
+        .map_err(|error| format!("failed to run rustc for {}: {error}", rust_path.display()))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "rustc failed for {} (status: {})\n{}",
+            rust_path.display(),
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// A fresh path in the system temp dir, keyed by process id and timestamp.
+pub fn unique_temp_path(prefix: &str, ext: &str) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let pid = process::id();
+    let suffix = if ext.is_empty() {
+        format!("{prefix}-{pid}-{nanos}")
+    } else {
+        format!("{prefix}-{pid}-{nanos}.{ext}")
+    };
+    env::temp_dir().join(suffix)
+}
+
+/// Platform file name of the shared library for `stem`: `libstem.so`,
+/// `libstem.dylib`, or `stem.dll`.
+pub fn cdylib_file_name(stem: &str) -> String {
+    if cfg!(windows) {
+        format!("{stem}.dll")
+    } else if cfg!(target_os = "macos") {
+        format!("lib{stem}.dylib")
+    } else {
+        format!("lib{stem}.so")
+    }
+}
+
+/// File extension of executables on this platform.
+pub fn binary_ext() -> &'static str {
+    if cfg!(windows) { "exe" } else { "" }
+}
+
+/// Make generated code self-contained for bare `rustc` by declaring
+/// `mod quiche_lib` from the quiche-lib source at `lib_src`.
+pub fn inject_quiche_lib_module(rust_code: &str, lib_src: &Path) -> String {
+    if rust_code.contains("mod quiche_lib;") {
+        return rust_code.to_string();
+    }
+
+    let escaped_path = lib_src
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    let module_decl = format!("#[path = \"{escaped_path}\"]\nmod quiche_lib;\n");
+
+    let mut insert_at = 0usize;
+    for line in rust_code.split_inclusive('\n') {
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+        if trimmed.starts_with("#![") {
+            insert_at += line.len();
+        } else {
+            break;
+        }
+    }
+
+    if insert_at == 0 {
+        format!("{module_decl}\n{rust_code}")
+    } else {
+        let mut out = String::with_capacity(rust_code.len() + module_decl.len() + 1);
+        out.push_str(&rust_code[..insert_at]);
+        out.push_str(&module_decl);
+        out.push('\n');
+        out.push_str(&rust_code[insert_at..]);
+        out
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Scripts, the engine and `--opt-size` builds compile quiche-lib as
+    /// edition 2021, so it must not use 2024-only syntax such as let chains.
+    #[test]
+    fn test_quiche_lib_compiles_as_edition_2021() {
+        let lib = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../lib/src/lib.rs"));
+        let out_dir = unique_temp_path("quiche-lib-2021", "");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let built = compile_quiche_lib_rlib(&lib, &out_dir);
+        let _ = std::fs::remove_dir_all(&out_dir);
+        assert!(built.is_ok(), "{}", built.unwrap_err());
+    }
+}
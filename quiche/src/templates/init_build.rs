@@ -23,6 +23,8 @@ fn main() {
 
     let out_dir = env::var("OUT_DIR").unwrap();
 
+    // Compile every file before failing, so all errors are reported together
+    let mut failures = Vec::new();
     for q_file in &q_files {
         let stem = q_file.file_stem().unwrap().to_string_lossy();
         let rs_file = Path::new(&out_dir).join(format!("{stem}.rs"));
@@ -40,12 +42,21 @@ fn main() {
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            panic!(
-                "quiche compilation failed for {}:\n{stderr}",
-                q_file.display()
-            );
+            failures.push(format!("{}:\n{}", q_file.display(), stderr.trim_end()));
         }
 
         println!("cargo::rerun-if-changed={}", q_file.display());
     }
+
+    // Each failure is one file's whole `quiche build` output, ordered by
+    // path; the diagnostics within it are left as quiche printed them
+    if !failures.is_empty() {
+        failures.sort();
+        panic!(
+            "{}\n\nerror: {} of {} Quiche file(s) failed to compile",
+            failures.join("\n\n"),
+            failures.len(),
+            q_files.len()
+        );
+    }
 }
@@ -0,0 +1,82 @@
+//! Source map for Python-style tracebacks in `quiche run` scripts.
+//!
+//! [`inject`] starts the generated `main` with a call to quiche-lib's
+//! `Traceback::install`, passing the `.q` line of every function and
+//! method, so a panic prints `File "app.q", line 2, in divide` frames
+//! instead of a Rust panic message pointing at the temporary `.rs` file.
+
+use elevate::ast as e;
+use regex::Regex;
+
+/// `rust_code` with a traceback hook installed at the start of `main`.
+/// Code without a `main` is returned unchanged.
+pub fn inject(rust_code: &str, module: &e::Module, source: &str, filename: &str) -> String {
+    let Ok(main) = Regex::new(r"fn main\(\)[^{;]*\{") else {
+        return rust_code.to_string();
+    };
+    let Some(found) = main.find(rust_code) else {
+        return rust_code.to_string();
+    };
+    let frames: Vec<String> = source_map(module, source)
+        .iter()
+        .map(|(path, name, line)| format!("({path:?}, {name:?}, {line})"))
+        .collect();
+    format!(
+        "{}\n    quiche_lib::Traceback::install({filename:?}, &[{}]);{}",
+        &rust_code[..found.end()],
+        frames.join(", "),
+        &rust_code[found.end()..]
+    )
+}
+
+/// `(rust_path, name, line)` for every function and method in `module`.
+fn source_map(module: &e::Module, source: &str) -> Vec<(String, String, usize)> {
+    let line = |func: &e::FunctionDef| {
+        func.span
+            .map(|span| source[..span.start.min(source.len())].matches('\n').count() + 1)
+    };
+    let mut frames = Vec::new();
+    for item in &module.items {
+        match item {
+            e::Item::Function(func) => {
+                if let Some(line) = line(func) {
+                    frames.push((func.name.clone(), func.name.clone(), line));
+                }
+            }
+            e::Item::Impl(block) if block.trait_target.is_none() => {
+                for method in &block.methods {
+                    if let Some(line) = line(method) {
+                        frames.push((
+                            format!("{}::{}", block.target, method.name),
+                            format!("{}.{}", block.target, method.name),
+                            line,
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_installs_hook_with_def_lines() {
+        let source = "type Point:\n    x: i64\n\n    def area(self) -> i64:\n        return self.x\n\ndef main():\n    print(1)\n";
+        let module = crate::parser::parse(source).unwrap();
+        let rust = "fn helper() {}\n\npub fn main() {\n    println!(\"1\");\n}\n";
+        let injected = inject(rust, &module, source, "app.q");
+        assert!(injected.contains(
+            "pub fn main() {\n    quiche_lib::Traceback::install(\"app.q\", &[(\"Point::area\", \"Point.area\", 4), (\"main\", \"main\", 7)]);\n    println!"
+        ));
+        assert_eq!(
+            inject("fn helper() {}\n", &module, source, "app.q"),
+            "fn helper() {}\n"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Catalog of Python constructs Quiche doesn't support yet.
+//!
+//! Rather than failing with a generic parse error (or, for a nested `def`,
+//! quietly dropping code), the parser reports each of these with a stable
+//! code, the feature's name and what to write instead:
+//!
+//! ```text
+//! Parse error at 3:5: `del` is not supported yet [Q0007]: values are dropped
+//! at the end of their scope; use `.remove(...)` for collection entries
+//! (tracked in docs/status.md)
+//! ```
+//!
+//! The catalog is mirrored by the "Not Yet Supported" table in
+//! `docs/status.md`, which tracks each construct. When one lands, its entry
+//! goes from both; codes are never reused.
+
+/// A construct the parser recognizes but can't compile yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported {
+    /// Stable diagnostic code, `Q0001`...
+    pub code: &'static str,
+    /// The construct, as users write it.
+    pub feature: &'static str,
+    /// What to write instead.
+    pub help: &'static str,
+}
+
+impl Unsupported {
+    /// The diagnostic message for this construct.
+    pub fn message(&self) -> String {
+        format!(
+            "{} is not supported yet [{}]: {} (tracked in docs/status.md)",
+            self.feature, self.code, self.help
+        )
+    }
+}
+
+pub const GENERATOR: Unsupported = Unsupported {
+    code: "Q0004",
+    feature: "`yield` outside an `@fixture`",
+    help: "build and return a `List`, or return an iterator",
+};
+
+pub const CLASS: Unsupported = Unsupported {
+    code: "Q0005",
+    feature: "`class`",
+    help: "declare the type with `type Name:` (fields, then methods)",
+};
+
+pub const GLOBAL: Unsupported = Unsupported {
+    code: "Q0006",
+    feature: "`global` / `nonlocal`",
+    help: "pass the state as a parameter, or keep it in a class attribute (`mut name: T = value`)",
+};
+
+pub const DEL: Unsupported = Unsupported {
+    code: "Q0007",
+    feature: "`del`",
+    help: "values are dropped at the end of their scope; use `.remove(...)` for collection entries",
+};
+
+pub const ASYNC: Unsupported = Unsupported {
+    code: "Q0008",
+    feature: "`async` / `await`",
+    help: "call the function synchronously, or run work in parallel with `Parallel.map` or `Parallel.for_each`",
+};
+
+pub const NESTED_DEF: Unsupported = Unsupported {
+    code: "Q0009",
+    feature: "a `def` inside a function",
+    help: "use a lambda (`lambda x: ...`) or closure (`|x| ...`), or move the function to the top level",
+};
+
+/// Every cataloged construct, in code order.
+pub const CATALOG: &[Unsupported] = &[GENERATOR, CLASS, GLOBAL, DEL, ASYNC, NESTED_DEF];
+
+/// The cataloged construct with diagnostic code `code`.
+pub fn lookup(code: &str) -> Option<&'static Unsupported> {
+    CATALOG.iter().find(|entry| entry.code == code)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_codes_are_unique_and_ordered() {
+        let codes: Vec<&str> = CATALOG.iter().map(|entry| entry.code).collect();
+        let mut sorted = codes.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(codes, sorted);
+        assert_eq!(lookup("Q0007").unwrap().feature, "`del`");
+    }
+
+    #[test]
+    fn test_constructs_report_their_code() {
+        let cases = [
+            ("def f() -> int:\n    yield 1\n", GENERATOR),
+            ("class Point:\n    x: int\n", CLASS),
+            ("def f():\n    global count\n", GLOBAL),
+            ("def f():\n    del items[0]\n", DEL),
+            ("async def f():\n    pass\n", ASYNC),
+            ("def f():\n    x = await g()\n", ASYNC),
+            ("def f():\n    def g():\n        pass\n", NESTED_DEF),
+        ];
+        for (source, expected) in cases {
+            let err = crate::parser::parse(source).unwrap_err();
+            assert_eq!(err.message, expected.message(), "{source}");
+        }
+    }
+}
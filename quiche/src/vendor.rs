@@ -0,0 +1,91 @@
+//! The quiche-lib package built into the compiler, for use outside a Quiche
+//! workspace.
+//!
+//! Projects made by `quiche init` depend on the published quiche-lib crate
+//! through Cargo ([`QUICHE_LIB_VERSION`]). Scripts are built with bare
+//! `rustc` instead, which includes quiche-lib's source as a `#[path]` module
+//! (see [`crate::script`]). Inside this workspace that source is `lib/`;
+//! anywhere else it is the copy the quiche-lib dependency embeds (its
+//! `sources` feature), written on first use to
+//! `$QUICHE_HOME/lib/quiche-lib-<version>` (`~/.quiche` by default), so a
+//! script always builds against the quiche-lib of the compiler running it.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// The quiche-lib release these sources are, and that `quiche init`
+/// projects depend on.
+pub const QUICHE_LIB_VERSION: &str = quiche_lib::sources::VERSION;
+
+/// Environment variable naming the directory the vendored copy is written
+/// under.
+pub const QUICHE_HOME_ENV: &str = "QUICHE_HOME";
+
+/// quiche-lib's package files, by path relative to its root.
+pub const LIB_FILES: &[(&str, &str)] = quiche_lib::sources::FILES;
+
+/// The root of the vendored quiche-lib package, written (or brought up to
+/// date) on first use.
+pub fn vendored_lib_dir() -> Result<PathBuf, String> {
+    let root = quiche_home()
+        .join("lib")
+        .join(format!("quiche-lib-{QUICHE_LIB_VERSION}"));
+    for (file, contents) in LIB_FILES {
+        let path = root.join(file);
+        if fs::read_to_string(&path).is_ok_and(|current| current == *contents) {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create {}: {e}", parent.display()))?;
+        }
+        // Written aside and renamed, so a concurrent build never reads half
+        // a file
+        let mut partial = path.clone().into_os_string();
+        partial.push(format!(".{}.partial", std::process::id()));
+        fs::write(&partial, contents)
+            .and_then(|()| fs::rename(&partial, &path))
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+    }
+    Ok(root)
+}
+
+/// `$QUICHE_HOME`, else `~/.quiche`, else a directory under the system
+/// temp dir.
+pub(crate) fn quiche_home() -> PathBuf {
+    if let Some(home) = env::var_os(QUICHE_HOME_ENV) {
+        return PathBuf::from(home);
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".quiche"))
+        .unwrap_or_else(|| env::temp_dir().join("quiche"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vendored_files_match_the_package() {
+        let lib = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../lib"));
+        let sources = crate::build::find_sources(&lib.join("src"), "rs").unwrap();
+        let vendored: Vec<&str> = LIB_FILES
+            .iter()
+            .filter_map(|(file, _)| file.strip_prefix("src/"))
+            .collect();
+        let sources: Vec<String> = sources
+            .iter()
+            .map(|s| s.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(vendored, sources, "update LIB_FILES");
+
+        let manifest = LIB_FILES[0].1;
+        assert!(
+            manifest.contains(&format!("\nversion = \"{QUICHE_LIB_VERSION}\"\n")),
+            "{manifest}"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Golden-file tests for the compiler, one directory per mode under
+//! `tests/golden/`:
+//!
+//! - `run-pass/<name>.q`     — compiles and runs; stdout must equal `<name>.stdout`
+//! - `compile-fail/<name>.q` — must fail to compile; stderr must equal `<name>.stderr`
+//! - `emit-rust/<name>.q`    — `--emit-rust --prelude=none` output must equal
+//!   `<name>.rs`, which so holds only the fixture's own code
+//!
+//! Every mode needs at least one fixture, so a missing directory fails
+//! rather than passing with nothing checked.
+//!
+//! Run with `UPDATE_EXPECT=1 cargo test -p quiche --test golden` to (re)write
+//! the expectation files from the current compiler output.
+#![allow(clippy::expect_used, clippy::panic)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// How a fixture is run and which output stream is checked.
+#[derive(Clone, Copy)]
+enum Mode {
+    RunPass,
+    CompileFail,
+    EmitRust,
+}
+
+impl Mode {
+    fn dir(self) -> &'static str {
+        match self {
+            Mode::RunPass => "run-pass",
+            Mode::CompileFail => "compile-fail",
+            Mode::EmitRust => "emit-rust",
+        }
+    }
+
+    fn expect_ext(self) -> &'static str {
+        match self {
+            Mode::RunPass => "stdout",
+            Mode::CompileFail => "stderr",
+            Mode::EmitRust => "rs",
+        }
+    }
+}
+
+fn golden_dir(mode: Mode) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(mode.dir())
+}
+
+fn update_mode() -> bool {
+    std::env::var("UPDATE_EXPECT").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+fn fixtures(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "q"))
+        .collect();
+    files.sort();
+    files
+}
+
+fn run_quiche(dir: &Path, file: &str, mode: Mode) -> Output {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_quiche"));
+    cmd.current_dir(dir).arg(file);
+    if matches!(mode, Mode::EmitRust | Mode::CompileFail) {
+        cmd.arg("--emit-rust");
+    }
+    if matches!(mode, Mode::EmitRust) {
+        cmd.arg("--prelude=none");
+    }
+    cmd.output().expect("failed to execute quiche binary")
+}
+
+/// Drop the experiment/option banner quiche prints on stderr, so
+/// expectations don't churn when the default experiments change.
+fn normalize_stderr(stderr: &str) -> String {
+    stderr
+        .lines()
+        .filter(|line| !line.starts_with("🧪 experiments:") && !line.starts_with("🔒 "))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// Check every fixture of `mode`, returning one message per mismatch.
+fn check_mode(mode: Mode) -> Vec<String> {
+    let dir = golden_dir(mode);
+    let update = update_mode();
+    let mut failures = Vec::new();
+
+    let fixtures = fixtures(&dir);
+    if fixtures.is_empty() {
+        failures.push(format!(
+            "{}: no .q fixtures in {}",
+            mode.dir(),
+            dir.display()
+        ));
+    }
+    for path in fixtures {
+        let file = path
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let output = run_quiche(&dir, &file, mode);
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+        let actual = match mode {
+            Mode::RunPass | Mode::EmitRust if !output.status.success() => {
+                failures.push(format!(
+                    "{}/{file}: expected success, got {}\nstderr:\n{stderr}",
+                    mode.dir(),
+                    output.status
+                ));
+                continue;
+            }
+            Mode::CompileFail if output.status.success() => {
+                failures.push(format!(
+                    "{}/{file}: expected a compile error, but it compiled",
+                    mode.dir()
+                ));
+                continue;
+            }
+            Mode::RunPass | Mode::EmitRust => stdout,
+            Mode::CompileFail => normalize_stderr(&stderr),
+        };
+
+        let expect_path = path.with_extension(mode.expect_ext());
+        let expected = fs::read_to_string(&expect_path).ok();
+        if expected.as_deref() == Some(actual.as_str()) {
+            continue;
+        }
+        if update {
+            fs::write(&expect_path, &actual).expect("failed to write expectation file");
+            continue;
+        }
+        match expected {
+            None => failures.push(format!(
+                "{}/{file}: missing {} (run with UPDATE_EXPECT=1 to create it)",
+                mode.dir(),
+                expect_path.display()
+            )),
+            Some(expected) => failures.push(format!(
+                "{}/{file}: output differs from {}\n{}",
+                mode.dir(),
+                expect_path.display(),
+                line_diff(&expected, &actual)
+            )),
+        }
+    }
+    failures
+}
+
+fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => out.push_str(&format!("  {e}\n")),
+            (e, a) => {
+                if let Some(e) = e {
+                    out.push_str(&format!("- {e}\n"));
+                }
+                if let Some(a) = a {
+                    out.push_str(&format!("+ {a}\n"));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn assert_golden(mode: Mode) {
+    let failures = check_mode(mode);
+    assert!(
+        failures.is_empty(),
+        "{} golden test(s) failed:\n\n{}\nRun with UPDATE_EXPECT=1 to accept the new output.",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn golden_run_pass() {
+    assert_golden(Mode::RunPass);
+}
+
+#[test]
+fn golden_compile_fail() {
+    assert_golden(Mode::CompileFail);
+}
+
+#[test]
+fn golden_emit_rust() {
+    assert_golden(Mode::EmitRust);
+}
@@ -0,0 +1,17 @@
+#![allow(unused_parens)]
+#![allow(dropping_copy_types)]
+#![allow(dropping_references)]
+
+// Generated by elevate compiler.
+// Do not edit generated output manually.
+
+// ownership-note: experimental flag enabled: exp_type_system
+
+pub fn area(width: i64, height: i64) -> i64 {
+    return (width * height);
+}
+
+pub fn main() -> () {
+    let total: i64 = (area(3, 4) + 1);
+    assert!((total == 13));
+}
@@ -164,32 +164,73 @@ mod quiche {
     // High Priority: Consumes Self (Result/Option)
     pub trait QuicheResult {
         type Output;
-        fn quiche_handle(self) -> Self::Output;
+        /// `at` names the calling function; `context` is the optional
+        /// message given as `check!(value, "context")`.
+        fn quiche_handle(self, at: &str, context: Option<&str>) -> Self::Output;
     }
     
     impl<T, E: std::fmt::Debug> QuicheResult for Result<T, E> {
         type Output = T;
-        fn quiche_handle(self) -> T {
-            self.expect("Quiche Exception")
+        fn quiche_handle(self, at: &str, context: Option<&str>) -> T {
+            match self {
+                Ok(value) => value,
+                Err(error) => fail(&format!("{error:?}"), at, context),
+            }
+        }
+    }
+    
+    impl<T> QuicheResult for Option<T> {
+        type Output = T;
+        fn quiche_handle(self, at: &str, context: Option<&str>) -> T {
+            match self {
+                Some(value) => value,
+                None => fail("expected a value but got None", at, context),
+            }
+        }
+    }
+    
+    fn fail(message: &str, at: &str, context: Option<&str>) -> ! {
+        match context {
+            Some(context) => panic!("{context}: {message} at {at}"),
+            None => panic!("{message} at {at}"),
         }
     }
     
     // Low Priority: Takes &Self (Clone fallback)
     pub trait QuicheGeneric {
-        fn quiche_handle(&self) -> Self;
+        fn quiche_handle(&self, at: &str, context: Option<&str>) -> Self;
     }
     
     impl<T: Clone> QuicheGeneric for T {
-        fn quiche_handle(&self) -> Self {
+        fn quiche_handle(&self, _at: &str, _context: Option<&str>) -> Self {
             self.clone()
         }
     }
     
+    // Path of the enclosing function, for error messages.
+    macro_rules! function_name {
+        () => {{
+            fn f() {}
+            fn type_name_of<T>(_: T) -> &'static str {
+                std::any::type_name::<T>()
+            }
+            let name = type_name_of(f);
+            name.strip_suffix("::f").unwrap_or(name).trim_end_matches("::{{closure}}")
+        }};
+    }
+    pub(crate) use function_name;
+    
     macro_rules! check {
         ($val:expr) => {
             {
                 use crate::quiche::{QuicheResult, QuicheGeneric};
-                ($val).quiche_handle()
+                ($val).quiche_handle(crate::quiche::function_name!(), None)
+            }
+        };
+        ($val:expr, $context:expr) => {
+            {
+                use crate::quiche::{QuicheResult, QuicheGeneric};
+                ($val).quiche_handle(crate::quiche::function_name!(), Some($context))
             }
         };
     }